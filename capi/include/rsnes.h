@@ -0,0 +1,81 @@
+/* C bindings of the rsnes SNES emulator, see capi/src/lib.rs */
+
+#ifndef RSNES_H
+#define RSNES_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RSNES_OK 0
+/* a pointer argument is null or no ROM is loaded */
+#define RSNES_INVALID_ARGUMENT (-1)
+#define RSNES_INVALID_ROM (-2)
+/* the state is corrupt, incompatible or refused in integrity mode */
+#define RSNES_INVALID_STATE (-3)
+
+/* the buttons of a standard controller, see rsnes_set_buttons */
+#define RSNES_BUTTON_B 0x001
+#define RSNES_BUTTON_Y 0x002
+#define RSNES_BUTTON_SELECT 0x004
+#define RSNES_BUTTON_START 0x008
+#define RSNES_BUTTON_UP 0x010
+#define RSNES_BUTTON_DOWN 0x020
+#define RSNES_BUTTON_LEFT 0x040
+#define RSNES_BUTTON_RIGHT 0x080
+#define RSNES_BUTTON_A 0x100
+#define RSNES_BUTTON_X 0x200
+#define RSNES_BUTTON_L 0x400
+#define RSNES_BUTTON_R 0x800
+
+typedef struct RsnesDevice RsnesDevice;
+
+/* Create a device without a cartridge, freed with rsnes_free. */
+RsnesDevice *rsnes_new(void);
+void rsnes_free(RsnesDevice *device);
+
+/* Load the ROM of a cartridge, replacing the console by a new one of the
+   region of the cartridge. */
+int rsnes_load_rom(RsnesDevice *device, const uint8_t *rom, size_t size);
+
+/* Whether the console runs at 50 Hz instead of 60 Hz */
+bool rsnes_is_pal(RsnesDevice *device);
+
+/* Emulate the console until the next frame begins. */
+int rsnes_run_frame(RsnesDevice *device);
+
+/* The picture of the last frame as rows of width RGBA pixels, the alpha
+   channel is undefined. The pointer stays valid, until a ROM is loaded or
+   the device is freed. Returns NULL, if no ROM is loaded. */
+const uint8_t *rsnes_frame_buffer(RsnesDevice *device, uint32_t *width, uint32_t *height);
+
+/* Move up to max_frames stereo samples at 32 kHz into samples, left and right
+   interleaved. Returns the count of samples moved. */
+size_t rsnes_pull_audio(RsnesDevice *device, int16_t *samples, size_t max_frames);
+
+/* The count of stereo samples output in the last frame, e.g. to pull exactly
+   these after every frame. The fraction of a sample left over is part of the
+   next frame. */
+uint32_t rsnes_frame_samples(RsnesDevice *device);
+
+/* Set the pressed RSNES_BUTTON_* of the standard controller in port 1 or 2. */
+int rsnes_set_buttons(RsnesDevice *device, uint32_t port, uint16_t buttons);
+
+/* Save the state of the console into a buffer of *size bytes, freed with
+   rsnes_free_state. Returns NULL, if no ROM is loaded. */
+uint8_t *rsnes_save_state(RsnesDevice *device, size_t *size);
+void rsnes_free_state(uint8_t *state, size_t size);
+
+/* Load a state of rsnes_save_state of the same version of rsnes. If it can't
+   be loaded, the console keeps its state. */
+int rsnes_load_state(RsnesDevice *device, const uint8_t *state, size_t size);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,320 @@
+//! C bindings of rsnes
+//!
+//! The functions are declared in `include/rsnes.h`. A device is created empty
+//! by [`rsnes_new`] and gets its cartridge by [`rsnes_load_rom`], which also
+//! selects the region of the console. Every call of [`rsnes_run_frame`]
+//! emulates a frame, afterwards the picture is read with
+//! [`rsnes_frame_buffer`] and the audio with [`rsnes_pull_audio`].
+//!
+//! The S-SMP isn't threaded, so a device may be used from any thread, but
+//! only from one at a time. Save states are the states of the core without
+//! the header of the emulator, so they are only compatible with the same
+//! version of rsnes.
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend, FrameBuffer, FRAME_BUFFER_SIZE},
+    cartridge::{Cartridge, CountryFrameRate},
+    controller::{Controller, StandardController},
+    device::Device,
+    ppu::{MAX_SCREEN_HEIGHT, MAX_SCREEN_HEIGHT_OVERSCAN, SCREEN_WIDTH},
+    spc700::StereoSample,
+};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use std::collections::VecDeque;
+
+/// The samples, which weren't pulled, are dropped beyond one second
+const MAX_QUEUED_SAMPLES: usize = 32000;
+
+/// The master cycles emulated at once, like the emulator does
+const MASTER_CYCLES_PER_TICK: u16 = 2;
+
+pub const RSNES_OK: i32 = 0;
+/// A pointer argument is null or no ROM is loaded
+pub const RSNES_INVALID_ARGUMENT: i32 = -1;
+pub const RSNES_INVALID_ROM: i32 = -2;
+/// The state is corrupt, incompatible or refused in integrity mode
+pub const RSNES_INVALID_STATE: i32 = -3;
+
+/// Keeps the samples of the S-DSP, until they are pulled
+#[derive(Default)]
+struct SampleQueue(VecDeque<StereoSample>);
+
+impl AudioBackend for SampleQueue {
+    fn push_sample(&mut self, sample: StereoSample) {
+        if self.0.len() >= MAX_QUEUED_SAMPLES {
+            self.0.pop_front();
+        }
+        self.0.push_back(sample)
+    }
+}
+
+type CDevice = Device<SampleQueue, ArrayFrameBuffer>;
+
+/// The handle of a device, opaque to C
+pub struct RsnesDevice {
+    device: Option<Box<CDevice>>,
+}
+
+/// The device of a handle, `None` if the handle is null or has no ROM loaded
+unsafe fn loaded<'a>(device: *mut RsnesDevice) -> Option<&'a mut CDevice> {
+    device.as_mut()?.device.as_deref_mut()
+}
+
+/// Create a device without a cartridge. It's freed with [`rsnes_free`].
+#[no_mangle]
+pub extern "C" fn rsnes_new() -> *mut RsnesDevice {
+    Box::into_raw(Box::new(RsnesDevice { device: None }))
+}
+
+/// Free a device created with [`rsnes_new`]
+///
+/// # Safety
+///
+/// `device` must be null or a device returned by [`rsnes_new`], which
+/// wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_free(device: *mut RsnesDevice) {
+    if !device.is_null() {
+        drop(Box::from_raw(device))
+    }
+}
+
+/// Load the ROM of a cartridge, replacing the console by a new one of the
+/// region of the cartridge. Returns [`RSNES_OK`] or an error code.
+///
+/// # Safety
+///
+/// `device` must be a valid device and `rom` must point to `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_load_rom(
+    device: *mut RsnesDevice,
+    rom: *const u8,
+    size: usize,
+) -> i32 {
+    let handle = match device.as_mut() {
+        Some(handle) if !rom.is_null() => handle,
+        _ => return RSNES_INVALID_ARGUMENT,
+    };
+    let cartridge = match Cartridge::from_bytes(std::slice::from_raw_parts(rom, size)) {
+        Ok(cartridge) => cartridge,
+        Err(_) => return RSNES_INVALID_ROM,
+    };
+    let is_pal = matches!(cartridge.get_country_frame_rate(), CountryFrameRate::Pal);
+    let frame_buffer = ArrayFrameBuffer([[0; 4]; FRAME_BUFFER_SIZE], false);
+    let mut device = Box::new(Device::new(
+        SampleQueue::default(),
+        frame_buffer,
+        is_pal,
+        false,
+    ));
+    device.load_cartridge(cartridge);
+    handle.device = Some(device);
+    RSNES_OK
+}
+
+/// Whether the loaded console runs at 50 Hz, the rate of PAL consoles
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_is_pal(device: *mut RsnesDevice) -> bool {
+    loaded(device).is_some_and(|device| device.is_pal())
+}
+
+/// Emulate the console until the next frame begins. Returns [`RSNES_OK`] or
+/// [`RSNES_INVALID_ARGUMENT`], if no ROM is loaded.
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_run_frame(device: *mut RsnesDevice) -> i32 {
+    let device = match loaded(device) {
+        Some(device) => device,
+        None => return RSNES_INVALID_ARGUMENT,
+    };
+    device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    while !device.new_frame {
+        device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    }
+    RSNES_OK
+}
+
+/// The picture of the last frame as rows of `width` RGBA pixels. The alpha
+/// channel is undefined. The pointer stays valid, until a ROM is loaded or
+/// the device is freed. Returns null, if no ROM is loaded.
+///
+/// # Safety
+///
+/// `device` must be a valid device, `width` and `height` must be null or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_frame_buffer(
+    device: *mut RsnesDevice,
+    width: *mut u32,
+    height: *mut u32,
+) -> *const u8 {
+    let device = match loaded(device) {
+        Some(device) => device,
+        None => return std::ptr::null(),
+    };
+    let rows = if device.ppu.is_overscan() {
+        MAX_SCREEN_HEIGHT_OVERSCAN
+    } else {
+        MAX_SCREEN_HEIGHT
+    };
+    if let Some(width) = width.as_mut() {
+        *width = SCREEN_WIDTH
+    }
+    if let Some(height) = height.as_mut() {
+        *height = rows
+    }
+    device.ppu.frame_buffer.pixels().as_ptr().cast()
+}
+
+/// Move up to `max_frames` stereo samples at 32 kHz into `samples`, left and
+/// right interleaved. Returns the count of samples moved, up to a second of
+/// samples is kept until it's pulled.
+///
+/// # Safety
+///
+/// `device` must be a valid device and `samples` must be valid for writes of
+/// `2 * max_frames` values.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_pull_audio(
+    device: *mut RsnesDevice,
+    samples: *mut i16,
+    max_frames: usize,
+) -> usize {
+    let queue = match loaded(device).and_then(|device| device.smp.backend.as_mut()) {
+        Some(queue) if !samples.is_null() => queue,
+        _ => return 0,
+    };
+    let count = queue.0.len().min(max_frames);
+    let out = std::slice::from_raw_parts_mut(samples, 2 * count);
+    for (frame, sample) in out.chunks_exact_mut(2).zip(queue.0.drain(..count)) {
+        frame.copy_from_slice(&[sample.l, sample.r])
+    }
+    count
+}
+
+/// The count of stereo samples output in the last frame, e.g. to pull
+/// exactly these after every frame. It only depends on the emulated cycles;
+/// the fraction of a sample left over is part of the next frame.
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_frame_samples(device: *mut RsnesDevice) -> u32 {
+    loaded(device).map_or(0, |device| device.samples_this_frame())
+}
+
+/// Set the pressed buttons of a standard controller in port 1 or 2, the
+/// controller is connected, if it isn't yet. The bits are the `RSNES_BUTTON_*`
+/// constants. Returns [`RSNES_OK`] or [`RSNES_INVALID_ARGUMENT`].
+///
+/// # Safety
+///
+/// `device` must be a valid device.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_set_buttons(
+    device: *mut RsnesDevice,
+    port: u32,
+    buttons: u16,
+) -> i32 {
+    let device = match loaded(device) {
+        Some(device) => device,
+        None => return RSNES_INVALID_ARGUMENT,
+    };
+    let controller = match port {
+        1 => &mut device.controllers.port1.controller,
+        2 => &mut device.controllers.port2.controller,
+        _ => return RSNES_INVALID_ARGUMENT,
+    };
+    match controller {
+        Controller::Standard(cntrl) => cntrl.pressed_buttons = buttons,
+        _ => {
+            *controller = Controller::Standard(StandardController {
+                pressed_buttons: buttons,
+            })
+        }
+    }
+    RSNES_OK
+}
+
+/// Save the state of the console. Returns a buffer of `*size` bytes, which
+/// is freed with [`rsnes_free_state`], or null, if no ROM is loaded.
+///
+/// # Safety
+///
+/// `device` must be a valid device and `size` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_save_state(device: *mut RsnesDevice, size: *mut usize) -> *mut u8 {
+    let (device, size) = match (loaded(device), size.as_mut()) {
+        (Some(device), Some(size)) => (device, size),
+        _ => return std::ptr::null_mut(),
+    };
+    let mut serializer = SaveStateSerializer { data: vec![] };
+    device.serialize(&mut serializer);
+    let state = serializer.data.into_boxed_slice();
+    *size = state.len();
+    Box::into_raw(state).cast()
+}
+
+/// Free a state returned by [`rsnes_save_state`]
+///
+/// # Safety
+///
+/// `state` must be null or a state returned by [`rsnes_save_state`] with its
+/// `size`, which wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_free_state(state: *mut u8, size: usize) {
+    if !state.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            state, size,
+        )))
+    }
+}
+
+/// Load a state saved by [`rsnes_save_state`]. If it can't be loaded, the
+/// console keeps its state and [`RSNES_INVALID_STATE`] is returned.
+///
+/// # Safety
+///
+/// `device` must be a valid device and `state` must point to `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rsnes_load_state(
+    device: *mut RsnesDevice,
+    state: *const u8,
+    size: usize,
+) -> i32 {
+    let device = match loaded(device) {
+        Some(device) if !state.is_null() => device,
+        _ => return RSNES_INVALID_ARGUMENT,
+    };
+    let state = std::slice::from_raw_parts(state, size);
+    let mut backup = SaveStateSerializer { data: vec![] };
+    device.serialize(&mut backup);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut deserializer = SaveStateDeserializer { data: state.iter() };
+        device
+            .load_state(&mut deserializer)
+            .map(|()| deserializer.data.len())
+    }));
+    match result {
+        Ok(Ok(0)) => RSNES_OK,
+        // refused in integrity mode, nothing was loaded
+        Ok(Err(_)) => RSNES_INVALID_STATE,
+        _ => {
+            let _ = device.load_state(&mut SaveStateDeserializer {
+                data: backup.data.iter(),
+            });
+            RSNES_INVALID_STATE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,81 @@
+use super::*;
+use rsnes::test_util::{create_looping_rom, with_stack};
+
+/// Run `f` with a device, which has the test ROM loaded
+fn with_device(f: impl FnOnce(*mut RsnesDevice) + Send + 'static) {
+    with_stack(move || unsafe {
+        let device = rsnes_new();
+        let rom = create_looping_rom();
+        assert_eq!(rsnes_load_rom(device, rom.as_ptr(), rom.len()), RSNES_OK);
+        f(device);
+        rsnes_free(device)
+    })
+}
+
+#[test]
+pub fn test_without_rom() {
+    with_stack(|| unsafe {
+        let device = rsnes_new();
+        assert_eq!(rsnes_run_frame(device), RSNES_INVALID_ARGUMENT);
+        let (mut width, mut height) = (0, 0);
+        assert!(rsnes_frame_buffer(device, &mut width, &mut height).is_null());
+        let rom = [0u8; 0x100];
+        assert_eq!(
+            rsnes_load_rom(device, rom.as_ptr(), rom.len()),
+            RSNES_INVALID_ROM
+        );
+        rsnes_free(device);
+        rsnes_free(std::ptr::null_mut())
+    })
+}
+
+#[test]
+pub fn test_run_frames() {
+    with_device(|device| unsafe {
+        for _ in 0..3 {
+            assert_eq!(rsnes_run_frame(device), RSNES_OK);
+        }
+        let (mut width, mut height) = (0, 0);
+        assert!(!rsnes_frame_buffer(device, &mut width, &mut height).is_null());
+        assert_eq!((width, height), (256, 224));
+        assert!(!rsnes_is_pal(device));
+        // about 533 samples per frame at 60 Hz
+        let mut samples = vec![0; 2 * 4000];
+        let count = rsnes_pull_audio(device, samples.as_mut_ptr(), 4000);
+        assert!((1400..=1700).contains(&count), "{count} samples");
+        assert_eq!(rsnes_pull_audio(device, samples.as_mut_ptr(), 4000), 0);
+        for _ in 0..3 {
+            assert_eq!(rsnes_run_frame(device), RSNES_OK);
+            let count = rsnes_pull_audio(device, samples.as_mut_ptr(), 4000);
+            assert_eq!(count, rsnes_frame_samples(device) as usize);
+        }
+        assert_eq!(rsnes_set_buttons(device, 2, 0x100), RSNES_OK);
+        assert_eq!(rsnes_set_buttons(device, 3, 0x100), RSNES_INVALID_ARGUMENT);
+    })
+}
+
+#[test]
+pub fn test_states() {
+    with_device(|device| unsafe {
+        rsnes_run_frame(device);
+        let mut size = 0;
+        let state = rsnes_save_state(device, &mut size);
+        assert!(!state.is_null());
+        rsnes_run_frame(device);
+        let corrupt = [0u8; 16];
+        assert_eq!(
+            rsnes_load_state(device, corrupt.as_ptr(), corrupt.len()),
+            RSNES_INVALID_STATE
+        );
+        assert_eq!(rsnes_load_state(device, state, size), RSNES_OK);
+        // the state loaded is saved again
+        let mut size_again = 0;
+        let state_again = rsnes_save_state(device, &mut size_again);
+        assert_eq!(
+            std::slice::from_raw_parts(state, size),
+            std::slice::from_raw_parts(state_again, size_again)
+        );
+        rsnes_free_state(state, size);
+        rsnes_free_state(state_again, size_again)
+    })
+}
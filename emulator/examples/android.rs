@@ -0,0 +1,6 @@
+//! The entry point of the Android app, see the `android` module of the library
+
+#[cfg_attr(target_os = "android", ndk_glue::main(backtrace = "on"))]
+fn main() {
+    rsnes_emulator::run()
+}
@@ -0,0 +1,40 @@
+//! Export of the resolved address map (debugger command `map`)
+//!
+//! Every line of the text format contains a range of addresses and the memory
+//! the first address is decoded to, the following addresses continue linearly
+//! from there. The JSON format contains the same ranges as an array of
+//! objects with the fields `start`, `end`, `memory` and, except for
+//! `coprocessor` and `unmapped`, `offset`.
+
+use rsnes::device::{MappedRange, MemoryRegion};
+
+pub fn to_text(ranges: &[MappedRange]) -> String {
+    ranges
+        .iter()
+        .map(|range| format!("{}-{}  {}\n", range.start, range.end, range.region))
+        .collect()
+}
+
+pub fn to_json(ranges: &[MappedRange]) -> String {
+    let entries: Vec<String> = ranges
+        .iter()
+        .map(|range| {
+            let (memory, offset) = match range.region {
+                MemoryRegion::Wram(offset) => ("wram", Some(offset as usize)),
+                MemoryRegion::Rom(offset) => ("rom", Some(offset)),
+                MemoryRegion::Sram(offset) => ("sram", Some(offset)),
+                MemoryRegion::BusB(reg) => ("bus-b", Some(0x2100 | usize::from(reg))),
+                MemoryRegion::CpuRegister(reg) => ("cpu-register", Some(usize::from(reg))),
+                MemoryRegion::Coprocessor => ("coprocessor", None),
+                MemoryRegion::Unmapped => ("unmapped", None),
+            };
+            let offset =
+                offset.map_or_else(String::new, |offset| format!(", \"offset\": {offset}"));
+            format!(
+                "  {{\"start\": \"{}\", \"end\": \"{}\", \"memory\": \"{memory}\"{offset}}}",
+                range.start, range.end
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
@@ -0,0 +1,119 @@
+//! The Android app (`cargo apk build --example android --features android`)
+//!
+//! The app has no command line. It's started by opening a ROM with it from a
+//! file manager or the system file picker, which passes a `content://` URI of
+//! the storage access framework in the intent. The ROM is read through the
+//! content resolver and copied to the internal storage of the app, from where
+//! it's loaded like any other file. The internal storage is the home directory
+//! as well, so the configuration, the save memory and the save states are kept
+//! there.
+
+use jni::{
+    objects::{JObject, JString, JValue},
+    JavaVM,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// The size of the chunks, in which the ROM is read from its stream
+const CHUNK_SIZE: i32 = 0x10000;
+
+/// Prepare the environment of the app and return its command line
+pub fn args() -> Vec<OsString> {
+    let activity = ndk_glue::native_activity();
+    let home = PathBuf::from(OsStr::from_bytes(activity.internal_data_path().to_bytes()));
+    std::env::set_var("HOME", &home);
+    wait_for_window();
+    let mut args = vec![OsString::from("rsnes")];
+    match read_intent_rom() {
+        Ok(Some((name, rom))) => {
+            let path = home.join(name);
+            match std::fs::write(&path, rom) {
+                Ok(()) => args.push(path.into()),
+                Err(err) => eprintln!(
+                    "[warning] could not copy the ROM to `{}` ({err})",
+                    path.display()
+                ),
+            }
+        }
+        Ok(None) => eprintln!("[warning] no ROM was opened with the app"),
+        Err(err) => eprintln!("[warning] could not read the opened ROM ({err})"),
+    }
+    if args.len() == 1 {
+        // without a ROM, the controller test screen is shown
+        args.push("--test-input".into())
+    }
+    args
+}
+
+/// The surface can't be created before the native window, which is created
+/// after the app was started
+fn wait_for_window() {
+    while ndk_glue::native_window().is_none() {
+        std::thread::sleep(Duration::from_millis(10))
+    }
+}
+
+/// Read the ROM, whose URI the activity was started with, and return its file
+/// name and content
+fn read_intent_rom() -> jni::errors::Result<Option<(String, Vec<u8>)>> {
+    let native_activity = ndk_glue::native_activity();
+    let vm = unsafe { JavaVM::from_raw(native_activity.vm())? };
+    let env = vm.attach_current_thread()?;
+    let activity = JObject::from(native_activity.activity());
+
+    let intent = env
+        .call_method(activity, "getIntent", "()Landroid/content/Intent;", &[])?
+        .l()?;
+    let uri = env
+        .call_method(intent, "getData", "()Landroid/net/Uri;", &[])?
+        .l()?;
+    if uri.is_null() {
+        return Ok(None);
+    }
+    // the last segment of a document URI is an ID like `primary:Games/rom.sfc`
+    let segment: JString = env
+        .call_method(uri, "getLastPathSegment", "()Ljava/lang/String;", &[])?
+        .l()?
+        .into();
+    let segment: String = env.get_string(segment)?.into();
+    let name = segment.rsplit(['/', ':']).next().unwrap_or_default();
+    let name = if name.is_empty() { "rom.sfc" } else { name }.to_string();
+
+    let resolver = env
+        .call_method(
+            activity,
+            "getContentResolver",
+            "()Landroid/content/ContentResolver;",
+            &[],
+        )?
+        .l()?;
+    let stream = env
+        .call_method(
+            resolver,
+            "openInputStream",
+            "(Landroid/net/Uri;)Ljava/io/InputStream;",
+            &[JValue::Object(uri)],
+        )?
+        .l()?;
+    let chunk = env.new_byte_array(CHUNK_SIZE)?;
+    let mut buffer = vec![0; CHUNK_SIZE as usize];
+    let mut rom = vec![];
+    loop {
+        let len = env
+            .call_method(stream, "read", "([B)I", &[JValue::Object(chunk.into())])?
+            .i()?;
+        if len < 0 {
+            break;
+        }
+        let buffer = &mut buffer[..len as usize];
+        env.get_byte_array_region(chunk, 0, buffer)?;
+        rom.extend(buffer.iter().map(|&byte| byte as u8));
+    }
+    env.call_method(stream, "close", "()V", &[])?;
+    Ok(Some((name, rom)))
+}
@@ -0,0 +1,138 @@
+//! ROMs in ZIP and gzip archives
+//!
+//! A ZIP archive may contain several files, the ROM is the first one with a
+//! ROM extension (see [`quick_resume::ROM_EXTENSIONS`]), unless another one
+//! is selected by its name (`--archive-entry`). A gzip file contains exactly
+//! one ROM.
+
+use crate::inflate::{self, inflate};
+use crate::quick_resume;
+use crate::zip::{Archive, ZipError};
+use rsnes::patch::crc32;
+use std::path::{Path, PathBuf};
+
+/// The extensions of the archives, which are unpacked when loaded
+pub static ARCHIVE_EXTENSIONS: &[&str] = &["zip", "gz"];
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Zip(ZipError),
+    Gzip(&'static str),
+    /// The ZIP archive has no file with a ROM extension
+    NoRom,
+}
+
+impl From<ZipError> for ArchiveError {
+    fn from(err: ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Zip(err) => write!(fmt, "{err}"),
+            Self::Gzip(msg) => write!(fmt, "invalid or unsupported gzip file ({msg})"),
+            Self::NoRom => write!(
+                fmt,
+                "the archive contains no file with the extension {}",
+                quick_resume::ROM_EXTENSIONS.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+/// Whether `path` is an archive by its extension
+pub fn is_archive(path: &Path) -> bool {
+    has_extension(path, ARCHIVE_EXTENSIONS)
+}
+
+/// The path of the ROM in the archive `path`, as if it was unpacked next to
+/// it. Files looked up next to the ROM, like patches, are named after this.
+pub fn rom_path(path: &Path) -> PathBuf {
+    if has_extension(path, &["gz"]) {
+        // `game.sfc.gz` contains `game.sfc`
+        path.with_extension("")
+    } else {
+        path.to_owned()
+    }
+}
+
+/// Unpack the ROM from the archive `data` read from `path`. `entry` selects
+/// the file of a ZIP archive by its name.
+pub fn unpack(path: &Path, data: &[u8], entry: Option<&str>) -> Result<Vec<u8>, ArchiveError> {
+    if has_extension(path, &["gz"]) {
+        return gunzip(data);
+    }
+    let archive = Archive::new(data)?;
+    let name = match entry {
+        Some(name) => name,
+        None => archive
+            .names()
+            .find(|name| quick_resume::is_rom(Path::new(name)))
+            .ok_or(ArchiveError::NoRom)?,
+    };
+    Ok(archive.read(name)?)
+}
+
+/// Decompress a gzip file with a single member
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let truncated = ArchiveError::Gzip("truncated file");
+    let header = data.get(..10).ok_or(truncated)?;
+    if header[..2] != GZIP_MAGIC {
+        return Err(ArchiveError::Gzip("not a gzip file"));
+    }
+    if header[2] != 8 {
+        return Err(ArchiveError::Gzip("unsupported compression method"));
+    }
+    let flags = header[3];
+    let mut offset = 10;
+    if flags & FEXTRA > 0 {
+        let len = data
+            .get(offset..offset + 2)
+            .ok_or(ArchiveError::Gzip("truncated file"))?;
+        offset += 2 + usize::from(u16::from_le_bytes([len[0], len[1]]));
+    }
+    // the name and the comment are zero-terminated
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag > 0 {
+            let len = (data.get(offset..).unwrap_or_default())
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or(ArchiveError::Gzip("truncated file"))?;
+            offset += len + 1;
+        }
+    }
+    if flags & FHCRC > 0 {
+        offset += 2;
+    }
+    let stream = data
+        .get(offset..)
+        .ok_or(ArchiveError::Gzip("truncated file"))?;
+    let (content, len) = inflate(stream, inflate::MAX_SIZE).map_err(ArchiveError::Gzip)?;
+    let trailer = stream
+        .get(len..len + 8)
+        .ok_or(ArchiveError::Gzip("truncated file"))?;
+    let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+    let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+    // the size is stored modulo 2^32
+    if crc32(&content) != crc || content.len() as u32 != size {
+        return Err(ArchiveError::Gzip("checksum mismatch"));
+    }
+    Ok(content)
+}
@@ -0,0 +1,104 @@
+//! Automatic state files (`--autosave`)
+//!
+//! Every few minutes of emulated time, the device is written to a state file
+//! named after the current time (UTC), e.g. `2024-05-01-183000.state`, in a
+//! directory of the ROM. Only the newest files are kept, so a crash costs at
+//! most one interval of progress. The files are written by a
+//! [`StateWorker`].
+
+use crate::state_worker::{StateWorker, WorkerStats};
+use crate::{states, stats};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The directory of the automatic state files, relative to `$HOME`
+static AUTOSAVE_DIR: &str = ".config/rsnes/autosave";
+
+/// The count of state files, which may wait to be written
+const QUEUE_SIZE: usize = 2;
+
+/// The directory of the automatic state files of the ROM with the id `rom_id`
+pub fn autosave_dir(rom_id: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(AUTOSAVE_DIR).join(rom_id))
+}
+
+pub struct AutoSave {
+    dir: PathBuf,
+    interval: Duration,
+    /// The count of state files to keep
+    count: usize,
+    elapsed: Duration,
+    worker: StateWorker,
+}
+
+impl AutoSave {
+    pub fn new(dir: PathBuf, interval: Duration, count: usize) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            interval,
+            count: count.max(1),
+            elapsed: Duration::ZERO,
+            worker: StateWorker::new("autosave", QUEUE_SIZE),
+        })
+    }
+
+    /// Must be called after every emulated frame
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &Device<B, FB>,
+        frame_time: Duration,
+    ) {
+        self.elapsed += frame_time;
+        if self.elapsed < self.interval {
+            return;
+        }
+        self.elapsed = Duration::ZERO;
+        let state = states::save(device);
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let name = format!(
+            "{}.{}",
+            stats::format_timestamp(timestamp),
+            states::STATE_FILE_EXTENSION
+        );
+        let (dir, count) = (self.dir.clone(), self.count);
+        // writing the file would delay the next frame
+        self.worker.submit(move || {
+            let path = dir.join(name);
+            if let Err(err) = states::write_file(&path, &state).and_then(|()| rotate(&dir, count)) {
+                eprintln!(
+                    "[warning] unable to write automatic state file \"{}\" ({err})",
+                    path.display()
+                );
+            }
+        });
+    }
+
+    pub fn worker_stats(&self) -> WorkerStats {
+        self.worker.stats()
+    }
+}
+
+/// Remove the oldest state files, until only `count` are left
+fn rotate(dir: &Path, count: usize) -> std::io::Result<()> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(states::STATE_FILE_EXTENSION.as_ref()) {
+            files.push(path)
+        }
+    }
+    // the names sort by time
+    files.sort();
+    for path in &files[..files.len().saturating_sub(count)] {
+        std::fs::remove_file(path)?
+    }
+    Ok(())
+}
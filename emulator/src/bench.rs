@@ -0,0 +1,152 @@
+//! Benchmark of the emulation core (`--bench`)
+//!
+//! The device runs uncapped for a fixed count of frames without any output.
+//! Afterwards the emulated frames per second, the host time spent in the
+//! components of the core (see [`rsnes::profile`]) and the CPU usage of the
+//! process are reported, followed by the time taken to save and load a state,
+//! which rollback netplay does for every predicted frame.
+
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    profile::Component,
+};
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// The count of states saved and loaded to measure their average time
+const STATE_ROUNDS: u32 = 100;
+
+/// The monotonic clock of the profiler
+fn clock() -> Duration {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    EPOCH.get_or_init(Instant::now).elapsed()
+}
+
+/// The CPU time used by this process in user and kernel mode
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut usage = core::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: `usage` is valid for writes and initialized on success
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let usage = unsafe { usage.assume_init() };
+    let duration =
+        |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+    Some(duration(usage.ru_utime) + duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+pub struct Report {
+    frames: u64,
+    /// The duration of the frames on the console
+    emulated: Duration,
+    elapsed: Duration,
+    /// The CPU time of the process while running, if available
+    cpu_time: Option<Duration>,
+    components: Vec<(Component, Duration)>,
+    threaded: bool,
+    /// The average time to save and to load a state
+    state_save: Duration,
+    state_load: Duration,
+}
+
+/// Emulate `frames` frames as fast as possible and measure the time taken
+pub fn run<B: AudioBackend, FB: FrameBuffer>(snes: &mut Device<B, FB>, frames: u64) -> Report {
+    snes.enable_profiling(clock);
+    let cpu_start = cpu_time();
+    let start = Instant::now();
+    let mut cycles = 0;
+    for _ in 0..frames {
+        cycles += crate::run_frame(snes);
+    }
+    let elapsed = start.elapsed();
+    let cpu_time = cpu_start.zip(cpu_time()).map(|(start, end)| end - start);
+    let profiler = snes.profiler().unwrap();
+    let components = Component::ALL
+        .iter()
+        .map(|&component| (component, profiler.time(component)))
+        .collect();
+    let start = Instant::now();
+    let mut state = vec![];
+    for _ in 0..STATE_ROUNDS {
+        state = crate::states::save(snes);
+    }
+    let state_save = start.elapsed() / STATE_ROUNDS;
+    let start = Instant::now();
+    for _ in 0..STATE_ROUNDS {
+        // the state was just saved by this device, so it always loads
+        let _ = crate::states::load(snes, &state);
+    }
+    let state_load = start.elapsed() / STATE_ROUNDS;
+    Report {
+        frames,
+        emulated: rsnes::runner::cycles_to_duration(cycles, snes.is_pal()),
+        elapsed,
+        cpu_time,
+        components,
+        threaded: snes.smp.is_threaded(),
+        state_save,
+        state_load,
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let elapsed = self.elapsed.as_secs_f64();
+        writeln!(
+            f,
+            "emulated {} frames in {:.2}s: {:.1} fps, {:.0}% of the original speed",
+            self.frames,
+            elapsed,
+            self.frames as f64 / elapsed,
+            100.0 * self.emulated.as_secs_f64() / elapsed,
+        )?;
+        match self.cpu_time {
+            Some(cpu_time) => writeln!(
+                f,
+                "host CPU usage: {:.2}s ({:.0}% of one core)",
+                cpu_time.as_secs_f64(),
+                100.0 * cpu_time.as_secs_f64() / elapsed,
+            )?,
+            None => writeln!(f, "host CPU usage: not available")?,
+        }
+        let total: Duration = self.components.iter().map(|(_, time)| *time).sum();
+        writeln!(f, "time per component:")?;
+        for (component, time) in &self.components {
+            writeln!(
+                f,
+                "  {:<12} {:>8.3}s {:>5.1}%",
+                component.name(),
+                time.as_secs_f64(),
+                100.0 * time.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE),
+            )?;
+        }
+        if self.threaded {
+            writeln!(
+                f,
+                "the SPC700 runs in its own thread, its time only includes waiting for it"
+            )?;
+        }
+        let millis = |time: Duration| time.as_secs_f64() * 1000.0;
+        writeln!(
+            f,
+            "save state: {:.3} ms, load state: {:.3} ms ({} the budget of rollback netplay, {:.0} ms)",
+            millis(self.state_save),
+            millis(self.state_load),
+            if self.state_save + self.state_load > crate::netplay::SNAPSHOT_BUDGET {
+                "over"
+            } else {
+                "within"
+            },
+            millis(crate::netplay::SNAPSHOT_BUDGET),
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+//! Upgrade state files to the current format
+//!
+//! The emulator upgrades old states whenever it reads them, but doesn't write
+//! them back. This utility rewrites the files, e.g. slot files, autosaves or
+//! shared states, so they don't depend on the migrations of older formats.
+//! State container files are upgraded by the emulator, when they are opened.
+
+#[path = "../state_version.rs"]
+mod state_version;
+
+use clap::Parser;
+use state_version::{CONTAINER_MAGIC, STATE_FILE_MAGIC, STATE_VERSION};
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[clap(version = clap::crate_version!())]
+struct Options {
+    /// State files to upgrade in place
+    #[clap(parse(from_os_str), required = true)]
+    files: Vec<PathBuf>,
+
+    /// Only print the format version of every file
+    #[clap(long)]
+    dry_run: bool,
+}
+
+enum Outcome {
+    UpToDate,
+    Upgraded(u32),
+    Outdated(u32),
+}
+
+fn migrate_file(path: &Path, dry_run: bool) -> Result<Outcome, String> {
+    let content = std::fs::read(path).map_err(|err| format!("unable to read the file ({err})"))?;
+    let (version, state) = match state_version::parse_header(&content, STATE_FILE_MAGIC) {
+        Some(header) => header,
+        None if state_version::parse_header(&content, CONTAINER_MAGIC).is_some() => {
+            return Err(String::from(
+                "state containers are upgraded by the emulator, when they are opened",
+            ))
+        }
+        None => return Err(String::from("not a rsnes state file")),
+    };
+    if version == STATE_VERSION {
+        return Ok(Outcome::UpToDate);
+    }
+    let state = state_version::migrate(state.to_vec(), version).map_err(|err| err.to_string())?;
+    if dry_run {
+        return Ok(Outcome::Outdated(version));
+    }
+    let mut content = state_version::header(STATE_FILE_MAGIC);
+    content.extend(state);
+    // replace the file atomically, so a crash doesn't lose the state
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, content)
+        .and_then(|()| std::fs::rename(&tmp_path, path))
+        .map_err(|err| format!("unable to write the file ({err})"))?;
+    Ok(Outcome::Upgraded(version))
+}
+
+fn main() {
+    let options = Options::parse();
+    let mut failed = false;
+    for path in &options.files {
+        match migrate_file(path, options.dry_run) {
+            Ok(Outcome::UpToDate) => {
+                println!("{}: up to date (version {STATE_VERSION})", path.display())
+            }
+            Ok(Outcome::Upgraded(version)) => println!(
+                "{}: upgraded from version {version} to {STATE_VERSION}",
+                path.display()
+            ),
+            Ok(Outcome::Outdated(version)) => println!(
+                "{}: version {version}, can be upgraded to {STATE_VERSION}",
+                path.display()
+            ),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                failed = true
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1)
+    }
+}
@@ -0,0 +1,84 @@
+//! Cheat files
+//!
+//! The cheats of a game are kept in a text file named after the ROM id, with
+//! one cheat per line: `+` or `-` for an enabled or disabled cheat, the code
+//! and an optional description, e.g. `+ 7E0DBE09 infinite lives`. Lines
+//! starting with `#` are ignored. The file is loaded at startup and written
+//! whenever the cheats are changed with the debugger or the pause menu.
+
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    cheat::Code,
+    device::Device,
+};
+use std::path::{Path, PathBuf};
+
+/// The directory of the cheat files, relative to `$HOME`
+static CHEAT_DIR: &str = ".config/rsnes/cheats";
+
+/// The cheat file of the ROM with the id `rom_id`
+pub fn cheat_path(rom_id: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(
+        Path::new(&home)
+            .join(CHEAT_DIR)
+            .join(format!("{rom_id}.cht")),
+    )
+}
+
+/// Add the cheats of the file to `device`. A missing file has no cheats.
+/// Invalid lines are skipped with a warning.
+pub fn load<B: AudioBackend, FB: FrameBuffer>(
+    device: &mut Device<B, FB>,
+    path: &Path,
+) -> std::io::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let warn = |msg: &dyn std::fmt::Display| {
+            eprintln!("[warning] {}:{}: {msg}", path.display(), i + 1)
+        };
+        let (enabled, rest) = if let Some(rest) = line.strip_prefix('+') {
+            (true, rest)
+        } else if let Some(rest) = line.strip_prefix('-') {
+            (false, rest)
+        } else {
+            warn(&"expected `+` or `-` in front of the code");
+            continue;
+        };
+        let (code, description) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .unwrap_or((rest.trim_start(), ""));
+        let added = Code::parse(code)
+            .and_then(|code| device.add_cheat(code, description.trim().to_string(), enabled));
+        if let Err(err) = added {
+            warn(&err)
+        }
+    }
+    Ok(())
+}
+
+/// Write the cheats of `device` to the file
+pub fn save<B: AudioBackend, FB: FrameBuffer>(
+    device: &Device<B, FB>,
+    path: &Path,
+) -> std::io::Result<()> {
+    let mut text = String::new();
+    for cheat in device.cheats() {
+        let state = if cheat.enabled { '+' } else { '-' };
+        text += format!("{state} {} {}", cheat.code, cheat.description).trim_end();
+        text.push('\n');
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, text)
+}
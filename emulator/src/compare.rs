@@ -0,0 +1,82 @@
+//! Comparing a ROM hack with its original (`--compare`)
+//!
+//! Both ROMs run in lockstep with the same input and their pictures are shown
+//! side by side, the original on the left. After every frame, the pictures
+//! are compared pixel by pixel. While they differ, the pixels, which are the
+//! same in both pictures, are dimmed, so the differences stand out.
+
+/// The divisor of the color of pixels, which are the same in both pictures
+const DIM: u8 = 4;
+
+#[derive(Debug, Default)]
+pub struct Comparison {
+    /// The count of compared frames
+    pub frames: u64,
+    /// The count of frames with different pictures
+    pub differing_frames: u64,
+    /// The first frame with different pictures, counted from 1
+    pub first_difference: Option<u64>,
+    /// The count of different pixels in the last frame
+    pub differing_pixels: usize,
+}
+
+impl Comparison {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compare the RGBA pictures of both consoles after a frame and return
+    /// them with the differences highlighted
+    pub fn on_frame(&mut self, original: &[u8], hack: &[u8]) -> [Vec<u8>; 2] {
+        let mut pictures = [original.to_vec(), hack.to_vec()];
+        let [left, right] = &mut pictures;
+        let same: Vec<bool> = original
+            .chunks_exact(4)
+            .zip(hack.chunks_exact(4))
+            .map(|(a, b)| a[..3] == b[..3])
+            .collect();
+        self.frames += 1;
+        self.differing_pixels = same.iter().filter(|same| !**same).count();
+        if self.differing_pixels > 0 {
+            self.differing_frames += 1;
+            self.first_difference.get_or_insert(self.frames);
+            for picture in [left, right] {
+                for (pixel, _) in picture
+                    .chunks_exact_mut(4)
+                    .zip(&same)
+                    .filter(|(_, same)| **same)
+                {
+                    for component in &mut pixel[..3] {
+                        *component /= DIM
+                    }
+                }
+            }
+        }
+        pictures
+    }
+
+    /// The title of the window showing the comparison
+    pub fn title(&self) -> String {
+        match self.differing_pixels {
+            0 => format!("rsnes - compare: frame {}", self.frames),
+            count => format!(
+                "rsnes - compare: frame {}, {count} pixel(s) differ",
+                self.frames
+            ),
+        }
+    }
+
+    /// Print the result of the comparison
+    pub fn print_summary(&self) {
+        match self.first_difference {
+            Some(frame) => println!(
+                "[info] compared {} frames, {} differ, the first one is frame {frame}",
+                self.frames, self.differing_frames
+            ),
+            None => println!(
+                "[info] compared {} frames, all pictures are the same",
+                self.frames
+            ),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+mod schema;
+
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use toml::value::{Table, Value};
@@ -16,6 +18,14 @@ pub enum ConfigLoadError {
         expected: &'static str,
         got: &'static str,
     },
+    /// A value of the wrong type found by the schema validation
+    InvalidType {
+        key: String,
+        expected: &'static str,
+        got: &'static str,
+        /// The line and column (starting at 1) of the key
+        position: Option<(usize, usize)>,
+    },
     UnknownField(String),
     RequiredAttr {
         location: &'static str,
@@ -45,6 +55,18 @@ impl std::fmt::Display for ConfigLoadError {
             Self::WrongType { expected, got } => {
                 write!(fmt, "expected type `{expected}`, got `{got}`")
             }
+            Self::InvalidType {
+                key,
+                expected,
+                got,
+                position,
+            } => {
+                write!(fmt, "expected type `{expected}` for `{key}`, got `{got}`")?;
+                if let Some((line, column)) = position {
+                    write!(fmt, " at line {line}, column {column}")?;
+                }
+                Ok(())
+            }
             Self::UnknownField(field) => {
                 write!(fmt, "unknown field `{field}`")
             }
@@ -164,6 +186,56 @@ impl ControllerProfile {
         }
     }
 
+    /// The options as they are written in the config file
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        match self {
+            Self::Standard { scancodes } => {
+                table.insert("type".into(), "standard".into());
+                let mut codes = Table::new();
+                let ControllerProfileStandardScancodes {
+                    a,
+                    b,
+                    x,
+                    y,
+                    up,
+                    left,
+                    down,
+                    right,
+                    l,
+                    r,
+                    start,
+                    select,
+                } = scancodes;
+                for (name, code) in [
+                    ("A", a),
+                    ("B", b),
+                    ("X", x),
+                    ("Y", y),
+                    ("Up", up),
+                    ("Down", down),
+                    ("Left", left),
+                    ("Right", right),
+                    ("L", l),
+                    ("R", r),
+                    ("Start", start),
+                    ("Select", select),
+                ] {
+                    if let Some(code) = code {
+                        codes.insert(name.into(), i64::from(*code).into());
+                    }
+                }
+                table.insert("scancodes".into(), codes.into());
+            }
+            Self::Mouse { xspeed, yspeed } => {
+                table.insert("type".into(), "mouse".into());
+                table.insert("xspeed".into(), (*xspeed).into());
+                table.insert("yspeed".into(), (*yspeed).into());
+            }
+        }
+        table
+    }
+
     fn default_standard() -> Self {
         Self::Standard {
             scancodes: ControllerProfileStandardScancodes {
@@ -183,12 +255,8 @@ impl ControllerProfile {
         }
     }
 
-    pub fn handle_scancode(
-        &self,
-        scancode: u32,
-        is_pressed: bool,
-        controller: &mut rsnes::controller::Controller,
-    ) -> bool {
+    /// The button of a standard controller mapped to `scancode`
+    pub fn button(&self, scancode: u32) -> Option<u16> {
         match self {
             Self::Standard {
                 scancodes:
@@ -208,8 +276,7 @@ impl ControllerProfile {
                     },
             } => {
                 use rsnes::controller::buttons::*;
-                let mut key = 0;
-                for (code, button) in [
+                [
                     (a, A),
                     (b, B),
                     (x, X),
@@ -224,32 +291,32 @@ impl ControllerProfile {
                     (select, SELECT),
                 ]
                 .into_iter()
-                .filter_map(|(c, b)| c.map(|c| (c, b)))
-                {
-                    if code == scancode {
-                        key = button;
-                        break;
-                    }
-                }
-                let handled = key > 0;
-                if handled {
-                    match controller {
-                        rsnes::controller::Controller::Standard(controller) => {
-                            if is_pressed {
-                                controller.pressed_buttons |= key
-                            } else {
-                                controller.pressed_buttons &= !key
-                            }
-                        }
-                        _ => (),
-                    }
-                }
-                handled
+                .find(|(code, _)| **code == Some(scancode))
+                .map(|(_, button)| button)
             }
-            _ => false,
+            _ => None,
         }
     }
 
+    pub fn handle_scancode(
+        &self,
+        scancode: u32,
+        is_pressed: bool,
+        controller: &mut rsnes::controller::Controller,
+    ) -> bool {
+        let Some(key) = self.button(scancode) else {
+            return false;
+        };
+        if let rsnes::controller::Controller::Standard(controller) = controller {
+            if is_pressed {
+                controller.pressed_buttons |= key
+            } else {
+                controller.pressed_buttons &= !key
+            }
+        }
+        true
+    }
+
     pub fn handle_mouse_button(
         &self,
         button: winit::event::MouseButton,
@@ -308,12 +375,270 @@ pub fn controller_profile_to_port(
     })
 }
 
+/// The key left of `1`, as all function keys are already in use
+const DEFAULT_FULLSCREEN_KEY: u32 = 0x29;
+/// The key `P`
+const DEFAULT_PAUSE_KEY: u32 = 0x19;
+/// The key `O`
+const DEFAULT_FRAME_ADVANCE_KEY: u32 = 0x18;
+/// The key `Print`
+const DEFAULT_SCREENSHOT_KEY: u32 = 0x63;
+/// The key `R`
+const DEFAULT_RECORD_KEY: u32 = 0x13;
+/// The key `F`
+const DEFAULT_FILTER_KEY: u32 = 0x21;
+/// The key `V`
+const DEFAULT_SCOPE_KEY: u32 = 0x2f;
+/// The key `N`
+const DEFAULT_NOTES_KEY: u32 = 0x31;
+/// The key `Escape`
+const DEFAULT_MENU_KEY: u32 = 0x01;
+/// Start and Select
+const DEFAULT_MENU_COMBO: u16 =
+    rsnes::controller::buttons::START | rsnes::controller::buttons::SELECT;
+/// The key `T`
+const DEFAULT_INPUT_TEST_KEY: u32 = 0x14;
+/// The key `G`
+const DEFAULT_TIMER_KEY: u32 = 0x22;
+/// The key `H`
+const DEFAULT_TIMER_RESET_KEY: u32 = 0x23;
+
+/// The names of the buttons of a standard controller in the config file
+static BUTTON_NAMES: [(&str, u16); 12] = {
+    use rsnes::controller::buttons::*;
+    [
+        ("A", A),
+        ("B", B),
+        ("X", X),
+        ("Y", Y),
+        ("Up", UP),
+        ("Down", DOWN),
+        ("Left", LEFT),
+        ("Right", RIGHT),
+        ("L", L),
+        ("R", R),
+        ("Start", START),
+        ("Select", SELECT),
+    ]
+};
+
+/// The names of the buttons pressed in `buttons`
+pub fn button_names(buttons: u16) -> Vec<&'static str> {
+    BUTTON_NAMES
+        .iter()
+        .filter(|(_, button)| buttons & button > 0)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// The buttons pressed in `buttons` as a TOML array of their names
+pub fn format_combo(buttons: u16) -> String {
+    let names: Vec<String> = button_names(buttons)
+        .into_iter()
+        .map(|name| format!("\"{name}\""))
+        .collect();
+    format!("[{}]", names.join(", "))
+}
+
+/// How the picture of the console is sampled, when it is scaled to the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Sharp pixels, uneven at non-integer scales
+    Nearest,
+    /// Even, but blurry pixels
+    Linear,
+}
+
+impl Filter {
+    pub fn toggled(self) -> Self {
+        match self {
+            Self::Nearest => Self::Linear,
+            Self::Linear => Self::Nearest,
+        }
+    }
+
+    pub fn filter_mode(self) -> wgpu::FilterMode {
+        match self {
+            Self::Nearest => wgpu::FilterMode::Nearest,
+            Self::Linear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Nearest => "nearest",
+            Self::Linear => "linear",
+        })
+    }
+}
+
+/// Whether the clock of the audio device or the system clock paces the
+/// emulation, they always drift apart a little
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Paced by the system clock, audio samples are dropped or silence is
+    /// inserted, when the queue of the audio device runs full or empty
+    Video,
+    /// Paced by the audio device, the emulation waits for the queue to drain,
+    /// so the audio is smooth, but frames are sometimes shown twice
+    Audio,
+    /// Paced by the system clock, the resampler follows the fill level of the
+    /// queue with inaudible changes of the pitch
+    Dynamic,
+}
+
+impl std::fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Dynamic => "dynamic",
+        })
+    }
+}
+
+/// The addresses, at which a game keeps its in-game time (`igt`). Every
+/// part is a byte and optional, the in-game time is their sum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IgtAddresses {
+    pub hours: Option<rsnes::device::Addr24>,
+    pub minutes: Option<rsnes::device::Addr24>,
+    pub seconds: Option<rsnes::device::Addr24>,
+    /// The frames of the current second
+    pub frames: Option<rsnes::device::Addr24>,
+    /// The bytes are binary-coded decimals, e.g. `0x59` for 59
+    pub bcd: bool,
+}
+
+impl IgtAddresses {
+    fn load(map: &Table) -> Result<Self, ConfigLoadError> {
+        let mut igt = Self::default();
+        for (key, val) in map.iter() {
+            let addr = || {
+                let addr = *getval!(val, Integer)?;
+                u32::try_from(addr)
+                    .ok()
+                    .filter(|&addr| addr <= 0xff_ffff)
+                    .map(|addr| rsnes::device::Addr24::new((addr >> 16) as u8, addr as u16))
+                    .ok_or_else(|| ConfigLoadError::UnknownValue {
+                        field: "igt",
+                        value: format!("{addr:#x}"),
+                    })
+            };
+            match key.as_str() {
+                "hours" => igt.hours = Some(addr()?),
+                "minutes" => igt.minutes = Some(addr()?),
+                "seconds" => igt.seconds = Some(addr()?),
+                "frames" => igt.frames = Some(addr()?),
+                "bcd" => igt.bcd = *getval!(val, Boolean)?,
+                _ => return Err(ConfigLoadError::UnknownField(key.clone())),
+            }
+        }
+        Ok(igt)
+    }
+
+    fn to_table(self) -> Table {
+        let mut table = Table::new();
+        for (key, addr) in [
+            ("hours", self.hours),
+            ("minutes", self.minutes),
+            ("seconds", self.seconds),
+            ("frames", self.frames),
+        ] {
+            if let Some(addr) = addr {
+                let addr = (i64::from(addr.bank) << 16) | i64::from(addr.addr);
+                table.insert(key.into(), addr.into());
+            }
+        }
+        table.insert("bcd".into(), self.bcd.into());
+        table
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Profile {
     pub port1: Option<String>,
     pub port2: Option<String>,
     pub region: rsnes::cartridge::CountryFrameRate,
     pub threaded: bool,
+    /// Output audio, disabled with `audio = false` or `--no-audio`
+    pub audio: bool,
+    /// The directory of the save state slot files (see `--state-dir`)
+    pub state_dir: Option<PathBuf>,
+    /// Start with the window in fullscreen mode
+    pub fullscreen: bool,
+    /// The scancode of the key, which toggles fullscreen mode
+    pub fullscreen_key: u32,
+    /// The scancode of the key, which pauses and resumes the emulation
+    pub pause_key: u32,
+    /// The scancode of the key, which emulates a single frame while paused
+    pub frame_advance_key: u32,
+    /// The scancode of the key, which saves a screenshot
+    pub screenshot_key: u32,
+    /// The directory of the screenshots, `$HOME/.config/rsnes/screenshots`
+    /// by default
+    pub screenshot_dir: Option<PathBuf>,
+    /// The scancode of the key, which starts and stops a video recording
+    pub record_key: u32,
+    /// The directory of the recordings, `$HOME/.config/rsnes/recordings`
+    /// by default
+    pub recording_dir: Option<PathBuf>,
+    /// Replace the icon of the window with a thumbnail of the game
+    pub game_icon: bool,
+    /// Render Mode 7 at this scale (see `--hd-mode7`)
+    pub hd_mode7: Option<u8>,
+    /// The initial scaling filter, nearest neighbor by default
+    pub filter: Filter,
+    /// The synchronization of audio and video, dynamic rate control by
+    /// default (see `--audio-sync`)
+    pub sync_mode: SyncMode,
+    /// Only present frames with a new picture and let the event loop sleep
+    /// until the next frame is due (see `--capped-present`)
+    pub capped_present: bool,
+    /// The scancode of the key, which toggles the scaling filter
+    pub filter_key: u32,
+    /// The scancode of the key, which shows and hides the oscilloscopes of
+    /// the DSP voices
+    pub scope_key: u32,
+    /// The scancode of the key, which shows and hides the notes played by
+    /// the DSP voices
+    pub notes_key: u32,
+    /// The scancode of the key, which opens and closes the pause menu
+    pub menu_key: u32,
+    /// The scancode of the key, which opens and closes the controller test
+    /// screen
+    pub input_test_key: u32,
+    /// Draw touch controls for port 1 over the picture, enabled by default
+    /// on Android
+    pub touch_overlay: bool,
+    /// Show the speedrun timer over the picture
+    pub timer: bool,
+    /// The scancode of the key, which starts and stops the real time of the
+    /// speedrun timer
+    pub timer_key: u32,
+    /// The scancode of the key, which resets the real time of the speedrun
+    /// timer
+    pub timer_reset_key: u32,
+    /// Where the game keeps its in-game time, which the speedrun timer shows
+    /// below the real time
+    pub igt: Option<IgtAddresses>,
+    /// The buttons of port 1, which open the pause menu, when they are held
+    /// together, or 0
+    pub menu_combo: u16,
+    /// The buttons of port 1, which save the state to the slot last chosen
+    /// in the pause menu, when they are held together, or 0
+    pub save_state_combo: u16,
+    /// The buttons of port 1, which load the state from the slot last chosen
+    /// in the pause menu, when they are held together, or 0
+    pub load_state_combo: u16,
+    /// Mappings of gamepads in the format of the SDL game controller
+    /// database, which take precedence over the built-in ones
+    pub gamepad_mappings: Vec<String>,
+    /// The directories searched for firmware dumps of coprocessors before
+    /// `$HOME/.config/rsnes/firmware`
+    pub firmware_dirs: Vec<PathBuf>,
 }
 
 impl Profile {
@@ -345,15 +670,291 @@ impl Profile {
             .transpose()?
             .copied()
             .unwrap_or(true);
+        let audio = map
+            .get("audio")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(true);
+        let state_dir = map
+            .get("state-dir")
+            .map(|v| getval!(v, String))
+            .transpose()?
+            .map(PathBuf::from);
+        let screenshot_dir = map
+            .get("screenshot-dir")
+            .map(|v| getval!(v, String))
+            .transpose()?
+            .map(PathBuf::from);
+        let recording_dir = map
+            .get("recording-dir")
+            .map(|v| getval!(v, String))
+            .transpose()?
+            .map(PathBuf::from);
+        let fullscreen = map
+            .get("fullscreen")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let hd_mode7 = map
+            .get("hd-mode7")
+            .map(|v| getval!(v, Integer))
+            .transpose()?
+            .map(|&scale| {
+                u8::try_from(scale)
+                    .ok()
+                    .filter(|scale| (1..=rsnes::ppu::HdFrame::MAX_SCALE).contains(scale))
+                    .ok_or_else(|| ConfigLoadError::UnknownValue {
+                        field: "hd-mode7",
+                        value: scale.to_string(),
+                    })
+            })
+            .transpose()?;
+        let game_icon = map
+            .get("game-icon")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let filter = match map.get("filter").map(|v| getval!(v, String)).transpose()? {
+            None => Filter::Nearest,
+            Some(filter) => match filter.as_str() {
+                "nearest" => Filter::Nearest,
+                "linear" => Filter::Linear,
+                _ => {
+                    return Err(ConfigLoadError::UnknownValue {
+                        field: "filter",
+                        value: filter.clone(),
+                    })
+                }
+            },
+        };
+        let sync_mode = match map
+            .get("sync-mode")
+            .map(|v| getval!(v, String))
+            .transpose()?
+        {
+            None => SyncMode::Dynamic,
+            Some(mode) => match mode.as_str() {
+                "video" => SyncMode::Video,
+                "audio" => SyncMode::Audio,
+                "dynamic" => SyncMode::Dynamic,
+                _ => {
+                    return Err(ConfigLoadError::UnknownValue {
+                        field: "sync-mode",
+                        value: mode.clone(),
+                    })
+                }
+            },
+        };
+        let capped_present = map
+            .get("capped-present")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let touch_overlay = map
+            .get("touch-overlay")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(cfg!(target_os = "android"));
+        let timer = map
+            .get("timer")
+            .map(|v| getval!(v, Boolean))
+            .transpose()?
+            .copied()
+            .unwrap_or(false);
+        let igt = map
+            .get("igt")
+            .map(|v| getval!(v, Table))
+            .transpose()?
+            .map(IgtAddresses::load)
+            .transpose()?;
+        macro_rules! get_key {
+            ($name:literal, $default:expr) => {
+                map.get($name)
+                    .map(|v| getval!(v, Integer))
+                    .transpose()?
+                    .map_or(Ok($default), |&key| {
+                        u32::try_from(key).map_err(|_| ConfigLoadError::UnknownValue {
+                            field: $name,
+                            value: key.to_string(),
+                        })
+                    })?
+            };
+        }
+        let fullscreen_key = get_key!("fullscreen-key", DEFAULT_FULLSCREEN_KEY);
+        let pause_key = get_key!("pause-key", DEFAULT_PAUSE_KEY);
+        let frame_advance_key = get_key!("frame-advance-key", DEFAULT_FRAME_ADVANCE_KEY);
+        let screenshot_key = get_key!("screenshot-key", DEFAULT_SCREENSHOT_KEY);
+        let record_key = get_key!("record-key", DEFAULT_RECORD_KEY);
+        let filter_key = get_key!("filter-key", DEFAULT_FILTER_KEY);
+        let scope_key = get_key!("scope-key", DEFAULT_SCOPE_KEY);
+        let notes_key = get_key!("notes-key", DEFAULT_NOTES_KEY);
+        let menu_key = get_key!("menu-key", DEFAULT_MENU_KEY);
+        let input_test_key = get_key!("input-test-key", DEFAULT_INPUT_TEST_KEY);
+        let timer_key = get_key!("timer-key", DEFAULT_TIMER_KEY);
+        let timer_reset_key = get_key!("timer-reset-key", DEFAULT_TIMER_RESET_KEY);
+        macro_rules! get_combo {
+            ($name:literal, $default:expr) => {
+                match map.get($name) {
+                    Some(val) => getval!(val, Array)?.iter().try_fold(0, |combo, name| {
+                        let name = getval!(name, String)?;
+                        BUTTON_NAMES
+                            .iter()
+                            .find(|(button_name, _)| button_name == name)
+                            .map(|(_, button)| combo | button)
+                            .ok_or_else(|| ConfigLoadError::UnknownValue {
+                                field: $name,
+                                value: name.clone(),
+                            })
+                    })?,
+                    None => $default,
+                }
+            };
+        }
+        let menu_combo = get_combo!("menu-combo", DEFAULT_MENU_COMBO);
+        let save_state_combo = get_combo!("save-state-combo", 0);
+        let load_state_combo = get_combo!("load-state-combo", 0);
+        let gamepad_mappings = match map.get("gamepad-mappings") {
+            Some(val) => getval!(val, Array)?
+                .iter()
+                .map(|mapping| {
+                    let mapping = getval!(mapping, String)?;
+                    match crate::gamepad::Mapping::parse(mapping) {
+                        Some(_) => Ok(mapping.clone()),
+                        None => Err(ConfigLoadError::UnknownValue {
+                            field: "gamepad-mappings",
+                            value: mapping.clone(),
+                        }),
+                    }
+                })
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        let firmware_dirs = match map.get("firmware-dirs") {
+            Some(val) => getval!(val, Array)?
+                .iter()
+                .map(|dir| getval!(dir, String).map(PathBuf::from))
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
         Ok(Self {
             port1,
             port2,
             region,
             threaded,
+            audio,
+            state_dir,
+            fullscreen,
+            fullscreen_key,
+            pause_key,
+            frame_advance_key,
+            screenshot_key,
+            screenshot_dir,
+            record_key,
+            recording_dir,
+            game_icon,
+            hd_mode7,
+            filter,
+            sync_mode,
+            capped_present,
+            filter_key,
+            scope_key,
+            notes_key,
+            menu_key,
+            input_test_key,
+            touch_overlay,
+            timer,
+            timer_key,
+            timer_reset_key,
+            igt,
+            menu_combo,
+            save_state_combo,
+            load_state_combo,
+            gamepad_mappings,
+            firmware_dirs,
         })
     }
 }
 
+impl Profile {
+    /// The options as they are written in the config file, options without
+    /// a value are left out
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        let mut insert = |key: &str, val: Value| table.insert(key.into(), val);
+        let path = |path: &PathBuf| Value::from(path.to_string_lossy().as_ref());
+        let combo = |buttons: u16| Value::from(button_names(buttons));
+        let scancode = |code: u32| Value::from(i64::from(code));
+        if let Some(port1) = &self.port1 {
+            insert("port1", port1.as_str().into());
+        }
+        if let Some(port2) = &self.port2 {
+            insert("port2", port2.as_str().into());
+        }
+        let region = match self.region {
+            rsnes::cartridge::CountryFrameRate::Any => "auto",
+            rsnes::cartridge::CountryFrameRate::Pal => "pal",
+            rsnes::cartridge::CountryFrameRate::Ntsc => "ntsc",
+        };
+        insert("region", region.into());
+        insert("threaded", self.threaded.into());
+        insert("audio", self.audio.into());
+        if let Some(dir) = &self.state_dir {
+            insert("state-dir", path(dir));
+        }
+        insert("fullscreen", self.fullscreen.into());
+        insert("fullscreen-key", scancode(self.fullscreen_key));
+        insert("pause-key", scancode(self.pause_key));
+        insert("frame-advance-key", scancode(self.frame_advance_key));
+        insert("screenshot-key", scancode(self.screenshot_key));
+        if let Some(dir) = &self.screenshot_dir {
+            insert("screenshot-dir", path(dir));
+        }
+        insert("record-key", scancode(self.record_key));
+        if let Some(dir) = &self.recording_dir {
+            insert("recording-dir", path(dir));
+        }
+        insert("game-icon", self.game_icon.into());
+        if let Some(scale) = self.hd_mode7 {
+            insert("hd-mode7", i64::from(scale).into());
+        }
+        insert("filter", self.filter.to_string().into());
+        insert("sync-mode", self.sync_mode.to_string().into());
+        insert("capped-present", self.capped_present.into());
+        insert("filter-key", scancode(self.filter_key));
+        insert("scope-key", scancode(self.scope_key));
+        insert("notes-key", scancode(self.notes_key));
+        insert("menu-key", scancode(self.menu_key));
+        insert("input-test-key", scancode(self.input_test_key));
+        insert("touch-overlay", self.touch_overlay.into());
+        insert("timer", self.timer.into());
+        insert("timer-key", scancode(self.timer_key));
+        insert("timer-reset-key", scancode(self.timer_reset_key));
+        if let Some(igt) = self.igt {
+            insert("igt", igt.to_table().into());
+        }
+        insert("menu-combo", combo(self.menu_combo));
+        if self.save_state_combo != 0 {
+            insert("save-state-combo", combo(self.save_state_combo));
+        }
+        if self.load_state_combo != 0 {
+            insert("load-state-combo", combo(self.load_state_combo));
+        }
+        if !self.gamepad_mappings.is_empty() {
+            insert("gamepad-mappings", self.gamepad_mappings.clone().into());
+        }
+        if !self.firmware_dirs.is_empty() {
+            let dirs = self.firmware_dirs.iter().map(path).collect::<Vec<_>>();
+            insert("firmware-dirs", dirs.into());
+        }
+        table
+    }
+}
+
 impl Default for Profile {
     fn default() -> Self {
         Self {
@@ -361,23 +962,336 @@ impl Default for Profile {
             port2: None,
             region: rsnes::cartridge::CountryFrameRate::Any,
             threaded: true,
+            audio: true,
+            state_dir: None,
+            fullscreen: false,
+            fullscreen_key: DEFAULT_FULLSCREEN_KEY,
+            pause_key: DEFAULT_PAUSE_KEY,
+            frame_advance_key: DEFAULT_FRAME_ADVANCE_KEY,
+            screenshot_key: DEFAULT_SCREENSHOT_KEY,
+            screenshot_dir: None,
+            record_key: DEFAULT_RECORD_KEY,
+            recording_dir: None,
+            game_icon: false,
+            hd_mode7: None,
+            filter: Filter::Nearest,
+            sync_mode: SyncMode::Dynamic,
+            capped_present: false,
+            filter_key: DEFAULT_FILTER_KEY,
+            scope_key: DEFAULT_SCOPE_KEY,
+            notes_key: DEFAULT_NOTES_KEY,
+            menu_key: DEFAULT_MENU_KEY,
+            input_test_key: DEFAULT_INPUT_TEST_KEY,
+            touch_overlay: cfg!(target_os = "android"),
+            timer: false,
+            timer_key: DEFAULT_TIMER_KEY,
+            timer_reset_key: DEFAULT_TIMER_RESET_KEY,
+            igt: None,
+            menu_combo: DEFAULT_MENU_COMBO,
+            save_state_combo: 0,
+            load_state_combo: 0,
+            gamepad_mappings: vec![],
+            firmware_dirs: vec![],
+        }
+    }
+}
+
+/// The games, for which a profile is selected automatically
+#[derive(Debug, Clone)]
+struct AutoProfile {
+    name: String,
+    /// The CRC-32s of the ROMs as lowercase hex strings (`match-crc32`)
+    crc32s: Vec<String>,
+    /// A regular expression matching the title in the header (`match-title`)
+    title: Option<regex::Regex>,
+}
+
+impl AutoProfile {
+    /// Returns `None`, if the profile isn't selected automatically
+    fn load(name: &str, map: &Table) -> Result<Option<Self>, ConfigLoadError> {
+        let crc32s = match map.get("match-crc32") {
+            Some(val) => getval!(val, Array)?
+                .iter()
+                .map(|crc32| getval!(crc32, String).map(|crc32| crc32.to_lowercase()))
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        let title = map
+            .get("match-title")
+            .map(|v| getval!(v, String))
+            .transpose()?
+            .map(|title| {
+                regex::Regex::new(title).map_err(|_| ConfigLoadError::UnknownValue {
+                    field: "match-title",
+                    value: title.clone(),
+                })
+            })
+            .transpose()?;
+        Ok((!crc32s.is_empty() || title.is_some()).then(|| Self {
+            name: name.to_owned(),
+            crc32s,
+            title,
+        }))
+    }
+}
+
+/// Options for a single game, which is identified by the CRC-32 of its ROM
+#[derive(Debug, Clone, Default)]
+pub struct GameConfig {
+    pub widescreen: Option<u8>,
+    pub widescreen_bgs: Option<[bool; 4]>,
+    pub music_pack: Option<PathBuf>,
+    /// The save data is kept in flash memory instead of SRAM
+    pub flash_save: bool,
+    /// The SRAM size is detected at runtime, although the header has none
+    pub detect_sram: bool,
+    /// Patch the region lockout of the game
+    pub region_patch: bool,
+}
+
+impl GameConfig {
+    fn load(map: &Table) -> Result<Self, ConfigLoadError> {
+        let mut game = Self::default();
+        for (key, val) in map.iter() {
+            match key.as_str() {
+                "widescreen" => {
+                    let pixels = *getval!(val, Integer)?;
+                    if !(0..=i64::from(rsnes::ppu::HdFrame::MAX_WIDESCREEN)).contains(&pixels) {
+                        return Err(ConfigLoadError::UnknownValue {
+                            field: "widescreen",
+                            value: pixels.to_string(),
+                        });
+                    }
+                    game.widescreen = Some(pixels as u8)
+                }
+                "widescreen-bgs" => {
+                    let mut bgs = [false; 4];
+                    for bg in getval!(val, Array)? {
+                        match *getval!(bg, Integer)? {
+                            nr @ 1..=4 => bgs[nr as usize - 1] = true,
+                            nr => {
+                                return Err(ConfigLoadError::UnknownValue {
+                                    field: "widescreen-bgs",
+                                    value: nr.to_string(),
+                                })
+                            }
+                        }
+                    }
+                    game.widescreen_bgs = Some(bgs)
+                }
+                "music-pack" => game.music_pack = Some(PathBuf::from(getval!(val, String)?)),
+                "save-memory" => {
+                    game.flash_save = match getval!(val, String)?.as_str() {
+                        "sram" => false,
+                        "flash" => true,
+                        memory => {
+                            return Err(ConfigLoadError::UnknownValue {
+                                field: "save-memory",
+                                value: memory.to_owned(),
+                            })
+                        }
+                    }
+                }
+                "detect-sram" => game.detect_sram = *getval!(val, Boolean)?,
+                "region-patch" => game.region_patch = *getval!(val, Boolean)?,
+                // reported as warnings by the schema validation
+                _ => (),
+            }
+        }
+        Ok(game)
+    }
+}
+
+impl GameConfig {
+    /// The options as they are written in the config file, options without
+    /// a value are left out
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        if let Some(pixels) = self.widescreen {
+            table.insert("widescreen".into(), i64::from(pixels).into());
+        }
+        if let Some(bgs) = self.widescreen_bgs {
+            let bgs: Vec<i64> = (1..=4).filter(|&nr| bgs[nr as usize - 1]).collect();
+            table.insert("widescreen-bgs".into(), bgs.into());
+        }
+        if let Some(dir) = &self.music_pack {
+            table.insert("music-pack".into(), dir.to_string_lossy().as_ref().into());
+        }
+        let memory = if self.flash_save { "flash" } else { "sram" };
+        table.insert("save-memory".into(), memory.into());
+        table.insert("detect-sram".into(), self.detect_sram.into());
+        table.insert("region-patch".into(), self.region_patch.into());
+        table
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SongDetection {
+    /// The song id is written by the CPU to an APU port
+    Port(u8),
+    /// The song id is stored in memory
+    Memory(rsnes::device::Addr24),
+}
+
+#[derive(Debug, Clone)]
+pub struct MusicTrack {
+    pub path: PathBuf,
+    /// The sample to jump to at the end of the track
+    pub loop_start: Option<u64>,
+}
+
+/// The `music.toml` file of a music pack, see [`crate::music`]
+#[derive(Debug, Clone)]
+pub struct MusicPack {
+    pub detection: SongDetection,
+    /// Song ids, which stop the replacement
+    pub stop: Vec<u8>,
+    /// The DSP voices muted while a replacement plays (bit n for voice n)
+    pub voices: u8,
+    pub volume: f32,
+    pub tracks: HashMap<u8, MusicTrack>,
+}
+
+impl MusicPack {
+    fn song_id(val: &Value, field: &'static str) -> Result<u8, ConfigLoadError> {
+        let id = match val {
+            Value::String(id) => parse_song_id(id),
+            val => u8::try_from(*getval!(val, Integer)?).ok(),
+        };
+        id.ok_or_else(|| ConfigLoadError::UnknownValue {
+            field,
+            value: val.to_string(),
+        })
+    }
+
+    fn load_tracks(dir: &Path, map: &Table) -> Result<HashMap<u8, MusicTrack>, ConfigLoadError> {
+        let mut tracks = HashMap::new();
+        for (key, val) in map.iter() {
+            let id = parse_song_id(key).ok_or_else(|| ConfigLoadError::UnknownValue {
+                field: "tracks",
+                value: key.clone(),
+            })?;
+            let track = match val {
+                Value::String(file) => MusicTrack {
+                    path: dir.join(file),
+                    loop_start: None,
+                },
+                val => {
+                    let map = getval!(val, Table)?;
+                    let mut file = None;
+                    let mut loop_start = None;
+                    for (key, val) in map.iter() {
+                        match key.as_str() {
+                            "file" => file = Some(dir.join(getval!(val, String)?)),
+                            "loop" => loop_start = Some(*getval!(val, Integer)? as u64),
+                            _ => return Err(ConfigLoadError::UnknownField(key.clone())),
+                        }
+                    }
+                    MusicTrack {
+                        path: file.ok_or(ConfigLoadError::RequiredAttr {
+                            location: "tracks",
+                            attr: "file",
+                        })?,
+                        loop_start,
+                    }
+                }
+            };
+            tracks.insert(id, track);
         }
+        Ok(tracks)
+    }
+
+    /// Load `music.toml` from a music pack directory
+    pub fn load_from_dir<P: AsRef<Path>>(dir: P) -> Result<Self, ConfigLoadError> {
+        let dir = dir.as_ref();
+        let main: Table = toml::de::from_str(&std::fs::read_to_string(dir.join("music.toml"))?)
+            .map_err(ConfigLoadError::De)?;
+        let mut pack = Self {
+            detection: SongDetection::Port(0),
+            stop: vec![],
+            voices: 0xff,
+            volume: 1.0,
+            tracks: HashMap::new(),
+        };
+        for (key, val) in main.iter() {
+            match key.as_str() {
+                "port" => match *getval!(val, Integer)? {
+                    port @ 0..=3 => pack.detection = SongDetection::Port(port as u8),
+                    port => {
+                        return Err(ConfigLoadError::UnknownValue {
+                            field: "port",
+                            value: port.to_string(),
+                        })
+                    }
+                },
+                "address" => {
+                    let addr = *getval!(val, Integer)? as u32;
+                    pack.detection = SongDetection::Memory(rsnes::device::Addr24::new(
+                        (addr >> 16) as u8,
+                        addr as u16,
+                    ))
+                }
+                "stop" => {
+                    pack.stop = getval!(val, Array)?
+                        .iter()
+                        .map(|id| Self::song_id(id, "stop"))
+                        .collect::<Result<_, _>>()?
+                }
+                "voices" => {
+                    pack.voices = 0;
+                    for voice in getval!(val, Array)? {
+                        match *getval!(voice, Integer)? {
+                            voice @ 0..=7 => pack.voices |= 1 << voice,
+                            voice => {
+                                return Err(ConfigLoadError::UnknownValue {
+                                    field: "voices",
+                                    value: voice.to_string(),
+                                })
+                            }
+                        }
+                    }
+                }
+                "volume" => pack.volume = *getval!(val, Float)? as f32,
+                "tracks" => pack.tracks = Self::load_tracks(dir, getval!(val, Table)?)?,
+                _ => return Err(ConfigLoadError::UnknownField(key.clone())),
+            }
+        }
+        Ok(pack)
+    }
+}
+
+/// Parse a song id like `12` or `0x0c`
+fn parse_song_id(id: &str) -> Option<u8> {
+    match id.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => id.parse().ok(),
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
+    /// The file the config was loaded from
+    path: Option<PathBuf>,
     default_profile: String,
     profiles: HashMap<String, Profile>,
+    /// Sorted by name
+    auto_profiles: Vec<AutoProfile>,
     controller_profiles: HashMap<String, ControllerProfile>,
+    games: HashMap<String, GameConfig>,
+    /// The keys of the file, which are ignored
+    unknown_keys: Vec<schema::UnknownKey>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            path: None,
             default_profile: String::from("default"),
             profiles: [(String::from("default"), Profile::default())].into(),
+            auto_profiles: vec![],
             controller_profiles: [(String::from("default"), ControllerProfile::default())].into(),
+            games: HashMap::new(),
+            unknown_keys: vec![],
         }
     }
 }
@@ -388,7 +1302,10 @@ impl Config {
             if verbose {
                 println!("[info] loading config file `{}`", path.display());
             }
-            Self::load_from_file(path)
+            Self::load_from_file(&path).map(|config| Self {
+                path: Some(path),
+                ..config
+            })
         } else {
             Ok(Self::default())
         }
@@ -406,42 +1323,87 @@ impl Config {
             .collect()
     }
 
-    fn load_profiles(map: &Table) -> Result<HashMap<String, Profile>, ConfigLoadError> {
+    /// Automatically selected profiles are merged with the default profile:
+    /// options, which they don't set, are taken from it
+    fn load_profiles(
+        map: &Table,
+        default_profile: &str,
+    ) -> Result<(HashMap<String, Profile>, Vec<AutoProfile>), ConfigLoadError> {
+        let default = map
+            .get(default_profile)
+            .map(|v| getval!(v, Table))
+            .transpose()?;
+        let mut profiles = HashMap::new();
+        let mut auto_profiles = vec![];
+        for (name, val) in map.iter() {
+            let map = getval!(val, Table)?;
+            let auto_profile = AutoProfile::load(name, map)?;
+            let profile = match default {
+                Some(default) if auto_profile.is_some() && name != default_profile => {
+                    let mut merged = default.clone();
+                    merged.extend(map.clone());
+                    Profile::load(&merged)?
+                }
+                _ => Profile::load(map)?,
+            };
+            profiles.insert(name.clone(), profile);
+            auto_profiles.extend(auto_profile);
+        }
+        auto_profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok((profiles, auto_profiles))
+    }
+
+    fn load_games(map: &Table) -> Result<HashMap<String, GameConfig>, ConfigLoadError> {
         map.into_iter()
             .map(|(key, val)| {
                 getval!(val, Table)
-                    .and_then(Profile::load)
-                    .map(|val| (key.clone(), val))
+                    .and_then(GameConfig::load)
+                    .map(|val| (key.to_lowercase(), val))
             })
             .collect()
     }
 
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigLoadError> {
-        let main: Table =
-            toml::de::from_str(&std::fs::read_to_string(path)?).map_err(ConfigLoadError::De)?;
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    fn parse(text: &str) -> Result<Self, ConfigLoadError> {
+        let main: Table = toml::de::from_str(text).map_err(ConfigLoadError::De)?;
+        let unknown_keys = schema::validate(text, &main)?;
         let mut controller_profiles = Default::default();
-        let mut profiles = Default::default();
+        let mut profiles = None;
+        let mut games = Default::default();
         let mut default_profile = None;
         for (key, val) in main.iter() {
             match key.as_str() {
                 "default-profile" => {
                     default_profile = Some(getval!(val, String)?.clone());
                 }
-                "profiles" => profiles = Self::load_profiles(getval!(val, Table)?)?,
+                "profiles" => profiles = Some(getval!(val, Table)?),
                 "controller-profiles" => {
                     controller_profiles = Self::load_controller_profiles(getval!(val, Table)?)?
                 }
-                _ => return Err(ConfigLoadError::UnknownField(key.clone())),
+                "games" => games = Self::load_games(getval!(val, Table)?)?,
+                // reported as warnings by the schema validation
+                _ => (),
             }
         }
         let default_profile = default_profile.ok_or_else(|| ConfigLoadError::RequiredAttr {
             location: "root",
             attr: "default-profile",
         })?;
+        let (profiles, auto_profiles) = match profiles {
+            Some(profiles) => Self::load_profiles(profiles, &default_profile)?,
+            None => Default::default(),
+        };
         let slf = Self {
+            path: None,
             default_profile,
             profiles,
+            auto_profiles,
             controller_profiles,
+            games,
+            unknown_keys,
         };
         slf.validate_names()?;
         Ok(slf)
@@ -480,10 +1442,94 @@ impl Config {
             .find(|path| path.is_file())
     }
 
+    /// The path, at which the config file is created by default
+    pub fn default_path() -> Option<PathBuf> {
+        let (_, path) = CONFIG_FILE_PATHS[0];
+        std::env::var_os("HOME").map(|home| Path::new(&home).join(path))
+    }
+
+    /// The text of a config file with the default configuration, which
+    /// documents all options. A mouse and a game are added as commented
+    /// out examples.
+    pub fn default_file() -> String {
+        let config = Self::default();
+        let mut root = Table::new();
+        root.insert("default-profile".into(), config.default_profile.into());
+        let mouse = ControllerProfile::Mouse {
+            xspeed: 1.0,
+            yspeed: 1.0,
+        };
+        let mut sections = vec![];
+        for (name, profile) in &config.profiles {
+            sections.push(schema::Section {
+                map: "profiles",
+                name,
+                values: profile.to_table(),
+                commented: false,
+            });
+        }
+        for (name, profile) in &config.controller_profiles {
+            sections.push(schema::Section {
+                map: "controller-profiles",
+                name,
+                values: profile.to_table(),
+                commented: false,
+            });
+        }
+        sections.push(schema::Section {
+            map: "controller-profiles",
+            name: "mouse",
+            values: mouse.to_table(),
+            commented: true,
+        });
+        sections.push(schema::Section {
+            map: "games",
+            name: "0123abcd",
+            values: GameConfig::default().to_table(),
+            commented: true,
+        });
+        schema::document(&root, &sections)
+    }
+
+    /// Get the options for a game by the CRC-32 of its ROM as hex string
+    pub fn get_game(&self, id: &str) -> Option<&GameConfig> {
+        self.games.get(id)
+    }
+
     pub fn get_profile(&self, name: &str) -> Option<&Profile> {
         self.profiles.get(name)
     }
 
+    /// The automatically selected profile for a game by the CRC-32 of its
+    /// ROM as hex string or by its title. Profiles matching the CRC-32 take
+    /// precedence, otherwise the first matching profile by name is selected.
+    pub fn select_profile(&self, id: &str, title: &str) -> Option<(&str, &Profile)> {
+        let auto_profile = self
+            .auto_profiles
+            .iter()
+            .find(|profile| profile.crc32s.iter().any(|crc32| crc32 == id))
+            .or_else(|| {
+                self.auto_profiles
+                    .iter()
+                    .find(|profile| profile.title.as_ref().is_some_and(|re| re.is_match(title)))
+            })?;
+        Some((&auto_profile.name, &self.profiles[&auto_profile.name]))
+    }
+
+    /// Warnings about the config file, e.g. unknown keys
+    pub fn warnings(&self) -> impl Iterator<Item = String> + '_ {
+        self.unknown_keys.iter().map(ToString::to_string)
+    }
+
+    /// The file the config was loaded from, `None` if no config file exists
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn default_profile_name(&self) -> &str {
+        &self.default_profile
+    }
+
     pub fn get_default_profile(&self) -> &Profile {
         self.profiles.get(&self.default_profile).unwrap()
     }
@@ -498,3 +1544,66 @@ impl Config {
         })
     }
 }
+
+/// Set the option `key` of the profile `profile` to the TOML `value` in the
+/// config file at `path`. The rest of the file is kept as it is, including
+/// its comments. The file is only written, if it still loads afterwards.
+pub fn write_profile_option(
+    path: &Path,
+    profile: &str,
+    key: &str,
+    value: &str,
+) -> Result<(), ConfigLoadError> {
+    let text = std::fs::read_to_string(path)?;
+    let text = set_profile_option(&text, profile, key, value);
+    Config::parse(&text)?;
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+fn set_profile_option(text: &str, profile: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = text.lines().map(str::to_owned).collect();
+    let option = format!("{key} = {value}");
+    let headers = [
+        format!("[profiles.{profile}]"),
+        format!("[profiles.\"{profile}\"]"),
+    ];
+    let indent = |line: &str| line[..line.len() - line.trim_start().len()].to_owned();
+    match lines
+        .iter()
+        .position(|line| headers.iter().any(|header| line.trim() == header))
+    {
+        Some(header) => {
+            let end = (header + 1..lines.len())
+                .find(|&i| lines[i].trim_start().starts_with('['))
+                .unwrap_or(lines.len());
+            let is_option = |line: &str| {
+                line.trim_start()
+                    .strip_prefix(key)
+                    .is_some_and(|rest| rest.trim_start().starts_with('='))
+            };
+            if let Some(i) = (header + 1..end).find(|&i| is_option(&lines[i])) {
+                lines[i] = indent(&lines[i]) + &option;
+            } else {
+                // after the last option of the table, with its indentation,
+                // comments at the end may belong to the next table
+                let is_code = |line: &str| !line.trim().is_empty() && !line.trim().starts_with('#');
+                let last = (header + 1..end)
+                    .rev()
+                    .find(|&i| is_code(&lines[i]))
+                    .unwrap_or(header);
+                let indent = (header + 1..end)
+                    .map(|i| &lines[i])
+                    .find(|line| is_code(line))
+                    .map_or_else(|| indent(&lines[header]), |line| indent(line));
+                lines.insert(last + 1, indent + &option);
+            }
+        }
+        None => {
+            lines.push(String::new());
+            lines.push(headers[0].clone());
+            lines.push(option);
+        }
+    }
+    lines.join("\n") + "\n"
+}
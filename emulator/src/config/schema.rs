@@ -0,0 +1,622 @@
+//! The structure of the config file
+//!
+//! The parsed file is checked against [`ROOT`] before it is loaded. Values of
+//! the wrong type are errors, unknown keys only produce warnings, suggesting
+//! the most similar known key. Both are located in the text of the file, as
+//! the parsed TOML values don't keep their positions.
+//!
+//! The default config file is generated from the schema as well, so it
+//! documents every known key.
+
+use super::ConfigLoadError;
+use std::fmt::Write;
+use toml::value::{Table, Value};
+
+/// The expected value of a key
+enum Schema {
+    /// A value with the name of its TOML type, e.g. `integer`
+    Value(&'static str),
+    /// An integer written in hexadecimal in the default config file
+    Scancode,
+    Array(&'static Schema),
+    /// A table with the given keys
+    Table(&'static [Key]),
+    /// A table with arbitrary keys, e.g. the names of the profiles
+    Map(&'static Schema),
+}
+
+/// A known key of a table
+struct Key {
+    name: &'static str,
+    schema: Schema,
+    /// The comment above the key in the default config file
+    doc: &'static str,
+    /// The value written to the default config file, if the key has no
+    /// default value
+    example: Option<&'static str>,
+}
+
+impl Key {
+    const fn new(name: &'static str, schema: Schema, doc: &'static str) -> Self {
+        Self {
+            name,
+            schema,
+            doc,
+            example: None,
+        }
+    }
+
+    const fn example(self, example: &'static str) -> Self {
+        Self {
+            example: Some(example),
+            ..self
+        }
+    }
+}
+
+const STRING: Schema = Schema::Value("string");
+const INTEGER: Schema = Schema::Value("integer");
+const FLOAT: Schema = Schema::Value("float");
+const BOOLEAN: Schema = Schema::Value("boolean");
+
+const PROFILE: Schema = Schema::Table(&[
+    Key::new(
+        "port1",
+        STRING,
+        "The controller profile connected to controller port 1",
+    ),
+    Key::new(
+        "port2",
+        STRING,
+        "The controller profile connected to controller port 2, it is left\n\
+         unconnected by default",
+    )
+    .example("\"mouse\""),
+    Key::new(
+        "region",
+        STRING,
+        "The region of the console: \"auto\" takes it from the header of the\n\
+         cartridge, \"pal\" or \"ntsc\" force it",
+    ),
+    Key::new(
+        "threaded",
+        BOOLEAN,
+        "Run the SPC700 in its own thread. This is intended to give a speedup\n\
+         on multi-core processors, but may sometimes lead to major slowdowns.",
+    ),
+    Key::new(
+        "audio",
+        BOOLEAN,
+        "Output audio, without audio the frames are paced by the system clock\n\
+         (like `--no-audio`)",
+    ),
+    Key::new(
+        "state-dir",
+        STRING,
+        "Keep every save state slot in a file in this directory, e.g.\n\
+         `game.state0` for `game.sfc` (like `--state-dir <DIR>`)",
+    )
+    .example("\"/home/user/.config/rsnes/slots\""),
+    Key::new(
+        "fullscreen",
+        BOOLEAN,
+        "Start with the window in fullscreen mode",
+    ),
+    Key::new(
+        "fullscreen-key",
+        Schema::Scancode,
+        "The scancode of the key, which switches between windowed and\n\
+         fullscreen mode",
+    ),
+    Key::new(
+        "pause-key",
+        Schema::Scancode,
+        "The scancode of the key, which pauses and resumes the emulation",
+    ),
+    Key::new(
+        "frame-advance-key",
+        Schema::Scancode,
+        "The scancode of the key, which emulates a single frame while paused",
+    ),
+    Key::new(
+        "screenshot-key",
+        Schema::Scancode,
+        "The scancode of the key, which saves a screenshot",
+    ),
+    Key::new(
+        "screenshot-dir",
+        STRING,
+        "The directory of the screenshots, `$HOME/.config/rsnes/screenshots`\n\
+         by default",
+    )
+    .example("\"/home/user/Pictures\""),
+    Key::new(
+        "record-key",
+        Schema::Scancode,
+        "The scancode of the key, which starts and stops recording a video\n\
+         (requires `ffmpeg`)",
+    ),
+    Key::new(
+        "recording-dir",
+        STRING,
+        "The directory of the recordings, `$HOME/.config/rsnes/recordings`\n\
+         by default",
+    )
+    .example("\"/home/user/Videos\""),
+    Key::new(
+        "game-icon",
+        BOOLEAN,
+        "Replace the icon of the window with a thumbnail of the game",
+    ),
+    Key::new(
+        "hd-mode7",
+        INTEGER,
+        "Render Mode 7 at this scale (1 to 4, like `--hd-mode7`)",
+    )
+    .example("2"),
+    Key::new(
+        "filter",
+        STRING,
+        "The filter scaling the picture to the window: \"nearest\" keeps the\n\
+         pixels sharp, \"linear\" keeps them even at non-integer scales",
+    ),
+    Key::new(
+        "sync-mode",
+        STRING,
+        "The clock pacing the emulation: \"video\" (the system clock), \"audio\"\n\
+         (the audio device, like `--audio-sync`) or \"dynamic\" (the system\n\
+         clock, with the audio resampled to keep its queue filled)",
+    ),
+    Key::new(
+        "capped-present",
+        BOOLEAN,
+        "Present only frames with a new picture and sleep until the next frame\n\
+         is due, instead of redrawing at 120 Hz (like `--capped-present`)",
+    ),
+    Key::new(
+        "filter-key",
+        Schema::Scancode,
+        "The scancode of the key, which switches between both filters",
+    ),
+    Key::new(
+        "scope-key",
+        Schema::Scancode,
+        "The scancode of the key, which shows and hides the oscilloscopes of\n\
+         the DSP voices",
+    ),
+    Key::new(
+        "menu-key",
+        Schema::Scancode,
+        "The scancode of the key, which opens and closes the pause menu",
+    ),
+    Key::new(
+        "input-test-key",
+        Schema::Scancode,
+        "The scancode of the key, which opens and closes the controller test\n\
+         screen",
+    ),
+    Key::new(
+        "touch-overlay",
+        BOOLEAN,
+        "Draw touch controls for the controller in port 1 over the picture\n\
+         (enabled by default on Android)",
+    ),
+    Key::new(
+        "menu-combo",
+        Schema::Array(&STRING),
+        "The buttons of the controller in port 1, which open the pause menu,\n\
+         when they are held together (Start and Select by default, `[]`\n\
+         disables it)",
+    )
+    .example("[\"Start\", \"Select\"]"),
+    Key::new(
+        "save-state-combo",
+        Schema::Array(&STRING),
+        "The buttons of the controller in port 1, which save the state of the\n\
+         slot last chosen in the pause menu, when they are held together",
+    )
+    .example("[\"L\", \"Select\"]"),
+    Key::new(
+        "load-state-combo",
+        Schema::Array(&STRING),
+        "The buttons of the controller in port 1, which load the state of the\n\
+         slot last chosen in the pause menu, when they are held together",
+    )
+    .example("[\"R\", \"Select\"]"),
+    Key::new(
+        "gamepad-mappings",
+        Schema::Array(&STRING),
+        "Mappings of gamepads in the format of the SDL game controller\n\
+         database, which take precedence over the built-in ones",
+    )
+    .example("[\"030000005e0400008e02000010010000,Xbox 360 Controller,a:b0,b:b1,x:b2,y:b3,platform:Linux,\"]"),
+    Key::new(
+        "firmware-dirs",
+        Schema::Array(&STRING),
+        "The directories searched for firmware dumps of coprocessors before\n\
+         `$HOME/.config/rsnes/firmware`, the built-in firmware is used\n\
+         without one",
+    )
+    .example("[\"/home/user/firmware\"]"),
+    Key::new(
+        "match-crc32",
+        Schema::Array(&STRING),
+        "Select this profile automatically for games, whose ROM has one of\n\
+         these CRC-32s. Options it doesn't set are taken from the default\n\
+         profile.",
+    )
+    .example("[\"0123abcd\"]"),
+    Key::new(
+        "match-title",
+        STRING,
+        "Select this profile automatically for games, whose title in the\n\
+         header matches this regular expression",
+    )
+    .example("\"^F-ZERO\""),
+]);
+
+const CONTROLLER_PROFILE: Schema = Schema::Table(&[
+    Key::new(
+        "type",
+        STRING,
+        "The type of the controller: \"standard\" or \"mouse\"",
+    ),
+    Key::new(
+        "scancodes",
+        Schema::Table(&[
+            Key::new("A", Schema::Scancode, ""),
+            Key::new("B", Schema::Scancode, ""),
+            Key::new("X", Schema::Scancode, ""),
+            Key::new("Y", Schema::Scancode, ""),
+            Key::new("Up", Schema::Scancode, ""),
+            Key::new("Down", Schema::Scancode, ""),
+            Key::new("Left", Schema::Scancode, ""),
+            Key::new("Right", Schema::Scancode, ""),
+            Key::new("L", Schema::Scancode, ""),
+            Key::new("R", Schema::Scancode, ""),
+            Key::new("Start", Schema::Scancode, ""),
+            Key::new("Select", Schema::Scancode, ""),
+        ]),
+        "The scancodes of the keys mapped to the buttons of a standard\n\
+         controller",
+    ),
+    Key::new(
+        "xspeed",
+        FLOAT,
+        "The multipliers of the movements of a mouse, negative values invert\n\
+         an axis",
+    ),
+    Key::new("yspeed", FLOAT, ""),
+]);
+
+const GAME: Schema = Schema::Table(&[
+    Key::new(
+        "widescreen",
+        INTEGER,
+        "Experimental: render this count of additional pixels left and right\n\
+         of the picture (at most 128, like `--widescreen <PIXELS>`)",
+    )
+    .example("64"),
+    Key::new(
+        "widescreen-bgs",
+        Schema::Array(&INTEGER),
+        "The backgrounds (1 to 4) extended in the widescreen area, all by\n\
+         default",
+    )
+    .example("[1, 2]"),
+    Key::new(
+        "music-pack",
+        STRING,
+        "A music pack directory with a `music.toml` file (like\n\
+         `--music-pack <DIR>`)",
+    )
+    .example("\"/home/user/music/my-game\""),
+    Key::new(
+        "save-memory",
+        STRING,
+        "The memory of the save data: \"sram\" or \"flash\" (like `--flash-save`)",
+    ),
+    Key::new(
+        "detect-sram",
+        BOOLEAN,
+        "Detect the SRAM size at runtime, although the header has no SRAM",
+    ),
+    Key::new(
+        "region-patch",
+        BOOLEAN,
+        "Patch the region lockout checks of the game at load time",
+    ),
+]);
+
+const ROOT: Schema = Schema::Table(&[
+    Key::new(
+        "default-profile",
+        STRING,
+        "The profile used, when none is selected with `--profile <PROFILE>`",
+    ),
+    Key::new(
+        "profiles",
+        Schema::Map(&PROFILE),
+        "Profiles are sets of options, of which one is selected",
+    ),
+    Key::new(
+        "controller-profiles",
+        Schema::Map(&CONTROLLER_PROFILE),
+        "Controller profiles configure a controller connected by a profile",
+    ),
+    Key::new(
+        "games",
+        Schema::Map(&GAME),
+        "Options for single games, which are identified by the CRC-32 of\n\
+         their ROM in hexadecimal (see `--stats`)",
+    ),
+]);
+
+/// A table of a map in the default config file, e.g. `[profiles.default]`
+pub struct Section<'a> {
+    /// The key of the map in the root table, e.g. `profiles`
+    pub map: &'static str,
+    pub name: &'a str,
+    pub values: Table,
+    /// Comment out the whole table, as it is only an example
+    pub commented: bool,
+}
+
+/// Write a config file with the values in `root` and `sections`. Every known
+/// key is documented, keys without a value are commented out with an example
+/// value, if they have one.
+pub fn document(root: &Table, sections: &[Section]) -> String {
+    let mut out = String::from(
+        "# rsnes configuration file, generated by `rsnes --write-default-config`\n\
+         #\n\
+         # All options are set to their defaults. Options without a default are\n\
+         # commented out with an example value.\n",
+    );
+    let Schema::Table(keys) = ROOT else {
+        unreachable!()
+    };
+    for key in keys {
+        let Schema::Map(schema) = &key.schema else {
+            write_key(&mut out, key, root.get(key.name), "", false);
+            continue;
+        };
+        let Schema::Table(keys) = schema else {
+            unreachable!()
+        };
+        write_doc(&mut out, key.doc);
+        for section in sections.iter().filter(|section| section.map == key.name) {
+            let comment = if section.commented { "# " } else { "" };
+            let _ = writeln!(out, "\n{comment}[{}.{}]", section.map, section.name);
+            for key in keys.iter() {
+                let val = section.values.get(key.name);
+                write_key(&mut out, key, val, "", section.commented);
+            }
+        }
+    }
+    out
+}
+
+fn write_doc(out: &mut String, doc: &str) {
+    if !doc.is_empty() {
+        out.push('\n');
+        for line in doc.lines() {
+            let _ = writeln!(out, "# {line}");
+        }
+    }
+}
+
+/// Write `key` with its value `val` or its example, keys with neither are
+/// left out together with their documentation
+fn write_key(out: &mut String, key: &Key, val: Option<&Value>, prefix: &str, commented: bool) {
+    let comment = if commented || val.is_none() { "# " } else { "" };
+    let name = key.name;
+    let line = match (&key.schema, val) {
+        (Schema::Table(keys), _) => {
+            let table = val.and_then(Value::as_table);
+            let prefix = format!("{prefix}{name}.");
+            let mut lines = String::new();
+            for key in keys.iter() {
+                let val = table.and_then(|table| table.get(key.name));
+                write_key(&mut lines, key, val, &prefix, commented);
+            }
+            lines
+        }
+        (Schema::Scancode, Some(Value::Integer(code))) => {
+            format!("{comment}{prefix}{name} = {code:#04x}\n")
+        }
+        (_, Some(val)) => format!("{comment}{prefix}{name} = {val}\n"),
+        (_, None) => match key.example {
+            Some(example) => format!("{comment}{prefix}{name} = {example}\n"),
+            None => String::new(),
+        },
+    };
+    if !line.is_empty() {
+        write_doc(out, key.doc);
+        out.push_str(&line);
+    }
+}
+
+/// A key of the config file, which isn't known
+#[derive(Debug, Clone)]
+pub struct UnknownKey {
+    /// The dotted path of the key, e.g. `profiles.default.fulscreen`
+    pub key: String,
+    /// The line and column (starting at 1) of the key
+    pub position: Option<(usize, usize)>,
+    /// The most similar known key
+    pub suggestion: Option<&'static str>,
+}
+
+impl std::fmt::Display for UnknownKey {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "unknown key `{}`", self.key)?;
+        if let Some((line, column)) = self.position {
+            write!(fmt, " at line {line}, column {column}")?;
+        }
+        if let Some(suggestion) = self.suggestion {
+            write!(fmt, ", did you mean `{suggestion}`?")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the config file `text`, which parsed to `table`. Returns the
+/// unknown keys or the first value of the wrong type.
+pub fn validate(text: &str, table: &Table) -> Result<Vec<UnknownKey>, ConfigLoadError> {
+    let mut unknown_keys = vec![];
+    let mut path = vec![];
+    check_table(text, &ROOT, table, &mut path, &mut unknown_keys)?;
+    Ok(unknown_keys)
+}
+
+fn check_table<'a>(
+    text: &str,
+    schema: &Schema,
+    table: &'a Table,
+    path: &mut Vec<&'a str>,
+    unknown_keys: &mut Vec<UnknownKey>,
+) -> Result<(), ConfigLoadError> {
+    for (key, val) in table.iter() {
+        path.push(key);
+        let result = match schema {
+            Schema::Table(keys) => match keys.iter().find(|known| known.name == key) {
+                Some(known) => check(text, &known.schema, val, path, unknown_keys),
+                None => {
+                    unknown_keys.push(UnknownKey {
+                        key: path.join("."),
+                        position: position(text, path),
+                        suggestion: suggest(key, keys.iter().map(|known| known.name)),
+                    });
+                    Ok(())
+                }
+            },
+            Schema::Map(schema) => check(text, schema, val, path, unknown_keys),
+            Schema::Value(_) | Schema::Scancode | Schema::Array(_) => unreachable!(),
+        };
+        path.pop();
+        result?
+    }
+    Ok(())
+}
+
+fn check<'a>(
+    text: &str,
+    schema: &Schema,
+    val: &'a Value,
+    path: &mut Vec<&'a str>,
+    unknown_keys: &mut Vec<UnknownKey>,
+) -> Result<(), ConfigLoadError> {
+    let expected = match *schema {
+        Schema::Value(ty) => ty,
+        Schema::Scancode => "integer",
+        Schema::Array(_) => "array",
+        Schema::Table(_) | Schema::Map(_) => "table",
+    };
+    let wrong_type = || ConfigLoadError::InvalidType {
+        key: path.join("."),
+        expected,
+        got: val.type_str(),
+        position: position(text, path),
+    };
+    if val.type_str() != expected {
+        return Err(wrong_type());
+    }
+    match (schema, val) {
+        (Schema::Array(schema), Value::Array(vals)) => {
+            for val in vals {
+                if let &Schema::Value(ty) = *schema {
+                    if val.type_str() != ty {
+                        return Err(ConfigLoadError::InvalidType {
+                            key: path.join("."),
+                            expected: ty,
+                            got: val.type_str(),
+                            position: position(text, path),
+                        });
+                    }
+                }
+            }
+        }
+        (Schema::Table(_) | Schema::Map(_), Value::Table(table)) => {
+            check_table(text, schema, table, path, unknown_keys)?
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// The most similar name to `key`, if any is similar enough
+fn suggest(key: &str, names: impl Iterator<Item = &'static str>) -> Option<&'static str> {
+    let key = key.to_lowercase();
+    names
+        .map(|name| (edit_distance(&key, &name.to_lowercase()), name))
+        .filter(|&(distance, name)| distance <= (name.len() / 3).max(1))
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, name)| name)
+}
+
+/// The Levenshtein distance of two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Split a dotted key or table header, e.g. `profiles."two players".port1`
+fn split_key(key: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quote = None;
+    for c in key.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '.') => parts.push(String::new()),
+            (None, c) if c.is_whitespace() => (),
+            (_, c) => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+/// Find the line and column (starting at 1) of the key `path` in `text`.
+/// Keys in inline tables are located at the key of the inline table.
+fn position(text: &str, path: &[&str]) -> Option<(usize, usize)> {
+    let mut table = vec![];
+    let mut best: Option<(usize, (usize, usize))> = None;
+    for (nr, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('#') {
+            continue;
+        }
+        let column = line.len() - trimmed.len() + 1;
+        let full = if let Some(header) = trimmed.strip_prefix('[') {
+            let header = header.trim_start_matches('[');
+            let Some(end) = header.find(']') else {
+                continue;
+            };
+            table = split_key(&header[..end]);
+            table.clone()
+        } else if let Some((key, _)) = trimmed.split_once('=') {
+            let mut full = table.clone();
+            full.extend(split_key(key));
+            full
+        } else {
+            continue;
+        };
+        let common = full.iter().zip(path).take_while(|(a, b)| a == *b).count();
+        if common == path.len() {
+            return Some((nr + 1, column));
+        } else if common == full.len() && best.is_none_or(|(len, _)| common > len) {
+            best = Some((common, (nr + 1, column)));
+        }
+    }
+    best.map(|(_, position)| position)
+}
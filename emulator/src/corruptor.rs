@@ -0,0 +1,162 @@
+//! Memory corruption for glitch hunting
+//!
+//! The corruptor modifies random bytes in a set of address ranges, either on
+//! request or every few frames. Every step remembers the original values,
+//! so corruptions can be undone.
+
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::{Addr24, Device},
+};
+use std::collections::VecDeque;
+
+/// The maximum count of steps that can be undone
+const MAX_UNDO_STEPS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Invert a single bit
+    Flip,
+    /// Replace the byte with a random value
+    Random,
+}
+
+pub struct Corruptor {
+    /// Start address and length of the corrupted ranges
+    pub ranges: Vec<(Addr24, u32)>,
+    pub kind: Kind,
+    /// Count of frames between two steps, zero means manual steps only
+    pub interval: u32,
+    /// Count of bytes corrupted in every step
+    pub count: usize,
+    pub active: bool,
+    frames: u32,
+    rng: u64,
+    /// The original values of the modified bytes, one entry per step
+    history: VecDeque<Vec<(Addr24, u8)>>,
+}
+
+impl Corruptor {
+    pub fn new(seed: u64) -> Self {
+        let mut corruptor = Self {
+            ranges: vec![],
+            kind: Kind::Flip,
+            interval: 60,
+            count: 1,
+            active: false,
+            frames: 0,
+            rng: 0,
+            history: VecDeque::new(),
+        };
+        corruptor.seed(seed);
+        corruptor
+    }
+
+    pub fn seed(&mut self, seed: u64) {
+        // xorshift must not be seeded with zero
+        self.rng = seed | 1;
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64*
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Pick a random address out of all ranges
+    fn random_addr(&mut self) -> Option<Addr24> {
+        let total: u64 = self.ranges.iter().map(|(_, len)| u64::from(*len)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut offset = self.next_random() % total;
+        for &(start, len) in &self.ranges {
+            if offset < u64::from(len) {
+                let addr = ((u32::from(start.bank) << 16 | u32::from(start.addr)) + offset as u32)
+                    & 0xffffff;
+                return Some(Addr24::new((addr >> 16) as u8, addr as u16));
+            }
+            offset -= u64::from(len);
+        }
+        unreachable!()
+    }
+
+    /// Corrupt `count` bytes. Registers and unmapped addresses are skipped.
+    /// Returns the modified addresses.
+    pub fn step<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+    ) -> Vec<Addr24> {
+        let mut changes = Vec::with_capacity(self.count);
+        for _ in 0..self.count {
+            let addr = match self.random_addr() {
+                Some(addr) => addr,
+                None => break,
+            };
+            let old = match device.peek(addr) {
+                Some(old) => old,
+                None => continue,
+            };
+            let random = self.next_random();
+            let new = match self.kind {
+                Kind::Flip => old ^ (1 << (random & 7)),
+                Kind::Random => random as u8,
+            };
+            if device.poke(addr, new) {
+                changes.push((addr, old));
+            }
+        }
+        let addrs = changes.iter().map(|(addr, _)| *addr).collect();
+        if !changes.is_empty() {
+            if self.history.len() >= MAX_UNDO_STEPS {
+                self.history.pop_front();
+            }
+            self.history.push_back(changes);
+        }
+        addrs
+    }
+
+    /// Must be called after every emulated frame
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+    ) -> Vec<Addr24> {
+        if !self.active || self.interval == 0 {
+            return vec![];
+        }
+        self.frames += 1;
+        if self.frames < self.interval {
+            return vec![];
+        }
+        self.frames = 0;
+        self.step(device)
+    }
+
+    /// Restore the bytes modified by the last `steps` steps.
+    /// Returns the count of undone steps.
+    pub fn undo<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        steps: usize,
+    ) -> usize {
+        let mut undone = 0;
+        while undone < steps {
+            let changes = match self.history.pop_back() {
+                Some(changes) => changes,
+                None => break,
+            };
+            // restore in reverse order, in case an address was hit twice
+            for (addr, old) in changes.into_iter().rev() {
+                device.poke(addr, old);
+            }
+            undone += 1;
+        }
+        undone
+    }
+
+    pub fn undo_steps(&self) -> usize {
+        self.history.len()
+    }
+}
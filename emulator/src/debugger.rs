@@ -0,0 +1,1711 @@
+//! Interactive debugger console
+//!
+//! The console reads commands from stdin on a separate thread (with
+//! readline-style line editing, history and tab completion) and hands
+//! them over to the main loop, which executes them between emulation steps.
+//!
+//! The labels, breakpoints, watchpoints, the bus log and CPU trace settings,
+//! the tinted layers and the PPU register panel are kept per ROM in a session
+//! file. It's a script of the commands restoring them, which is run when the
+//! debugger is opened for the ROM again, and it's written whenever one of them
+//! is changed.
+
+use crate::address_map;
+use crate::cheats;
+use crate::corruptor::{self, Corruptor};
+use crate::movie::{self, Movie};
+use crate::palette;
+use crate::png;
+use crate::project::{Project, BOOKMARK_COUNT};
+use crate::states::{self as state_files, SaveStates, SLOT_COUNT};
+use crate::tas::{Mode, Tas};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer, FRAME_BUFFER_SIZE},
+    bus_log::{AccessKind, BusLog, Filter, Origin},
+    device::{Addr24, Device, MemoryRegion},
+    disasm::{self, MAX_INSTRUCTION_SIZE},
+    ppu::{PixelSource, LAYER_NAMES},
+    trace::{CpuTrace, Format},
+};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+static HISTORY_FILE_PATH: &str = ".config/rsnes/debug_history";
+/// The directory of the session files, relative to `$HOME`
+static SESSION_DIR: &str = ".config/rsnes/debug";
+static SESSION_MAGIC: &str = "# rsnes debug session";
+/// The commands, which change what's kept in the session file
+static SESSION_COMMANDS: &[&str] = &["break", "watch", "buslog", "trace", "tint", "ppu", "label"];
+const PROMPT: &str = "(rsnes) ";
+const MAX_SHOWN_MIRRORS: usize = 16;
+/// The count of accesses kept by the bus log, if no size is given
+const DEFAULT_BUS_LOG_SIZE: usize = 0x10000;
+/// The count of instructions kept by the CPU trace, if no size is given.
+/// This is more than a frame has, so none are dropped, if written to a file.
+const DEFAULT_TRACE_SIZE: usize = 0x40000;
+/// The count of frames shown around the current frame in the piano roll
+const ROLL_CONTEXT: usize = 8;
+/// The maximum count of scripts, which may be running at the same time
+/// through nested `source` commands
+const MAX_SCRIPT_DEPTH: usize = 16;
+
+/// All commands understood by the console together with a short description
+static COMMANDS: &[(&str, &str, &str)] = &[
+    ("help", "", "print this help"),
+    ("continue", "", "resume the emulation"),
+    ("pause", "", "pause the emulation"),
+    ("advance", "[frames]", "emulate frames while paused"),
+    (
+        "break",
+        "[addr]",
+        "list the breakpoints or toggle one at the instruction at `addr`",
+    ),
+    (
+        "watch",
+        "<addr> [end] [r|w] [cpu|dma]",
+        "pause on matching accesses",
+    ),
+    ("watch", "[clear]", "list or remove all watchpoints"),
+    ("regs", "", "print the main CPU registers"),
+    (
+        "read",
+        "<addr> [len]",
+        "print `len` bytes of memory at `addr` (registers show as --)",
+    ),
+    (
+        "region",
+        "<addr>",
+        "show the memory `addr` is decoded to and all its mirrors",
+    ),
+    (
+        "map",
+        "[file]",
+        "print or export the address map (JSON for `.json` files)",
+    ),
+    (
+        "write",
+        "<addr> <byte>...",
+        "write bytes to memory at `addr`",
+    ),
+    (
+        "asm",
+        "<addr> <instr>",
+        "assemble an instruction and patch it at `addr`",
+    ),
+    (
+        "dis",
+        "[addr] [count]",
+        "disassemble `count` instructions at `addr` (default: the program counter)",
+    ),
+    (
+        "buslog",
+        "[on [size]|off|clear]",
+        "show, enable or disable the bus access log, or remove its filters",
+    ),
+    (
+        "buslog",
+        "include|exclude <addr> [end] [r|w] [cpu|dma]",
+        "only log or never log matching accesses",
+    ),
+    (
+        "buslog",
+        "dump [file]",
+        "print or write the logged accesses and empty the log",
+    ),
+    (
+        "trace",
+        "[on [size]|off]",
+        "show, enable or disable the trace of the instructions of the main CPU",
+    ),
+    (
+        "trace",
+        "format rsnes|bsnes|mesen",
+        "write the traced instructions like rsnes, bsnes or Mesen",
+    ),
+    (
+        "trace",
+        "dump [file]",
+        "print or write the traced instructions and empty the trace",
+    ),
+    (
+        "trace",
+        "file <file>|off",
+        "write every traced instruction to a file after each frame",
+    ),
+    ("patches", "", "list all patched ROM bytes"),
+    (
+        "hacks",
+        "[<name> on|off]",
+        "list the compatibility hacks of the game or toggle one",
+    ),
+    ("cheat", "", "list the cheats of the game"),
+    (
+        "cheat",
+        "add <code> [description]",
+        "add a Game Genie or Pro Action Replay code",
+    ),
+    (
+        "cheat",
+        "on|off|remove <index>",
+        "enable, disable or remove a cheat",
+    ),
+    ("ips", "<file>", "export the ROM patches as an IPS file"),
+    (
+        "ppu",
+        "[watch]",
+        "print the decoded PPU registers (`watch` toggles refreshing every frame)",
+    ),
+    (
+        "ppu",
+        "<register> [value]",
+        "print or set a single PPU register, e.g. `ppu BG1HOFS`",
+    ),
+    (
+        "tint",
+        "[<layer>...|off]",
+        "toggle tinting layers by their priority (bg1 to bg4, obj, backdrop)",
+    ),
+    (
+        "inspect",
+        "[on|off]",
+        "toggle recording the source of every pixel, then click a pixel to inspect it",
+    ),
+    (
+        "inspect",
+        "<x> <y>",
+        "print the layer, tile, color and color math of a pixel of the last frame",
+    ),
+    ("palette", "", "print the 256 colors of CGRAM (BGR555)"),
+    (
+        "palette",
+        "save <file>",
+        "export CGRAM to a .pal (JASC-PAL) or .act file",
+    ),
+    (
+        "palette",
+        "load <file> [first]",
+        "write the colors of a palette file to CGRAM from color `first` on",
+    ),
+    (
+        "movie",
+        "[new|play|record|stop]",
+        "show the movie status or start/stop a movie",
+    ),
+    (
+        "movie",
+        "new here",
+        "start a new movie, which starts from the current state",
+    ),
+    (
+        "movie",
+        "load|save <file>",
+        "load or save the movie, load imports .lsmv and .bk2 movies",
+    ),
+    (
+        "roll",
+        "[frame] [count]",
+        "show the per-frame input of the movie (piano roll)",
+    ),
+    (
+        "input",
+        "<frame> <port> <buttons>",
+        "set the pressed buttons, e.g. `UY` or `.`",
+    ),
+    (
+        "toggle",
+        "<frame> <port> <buttons>",
+        "toggle buttons in a frame",
+    ),
+    (
+        "insert",
+        "<frame> [count]",
+        "insert empty frames in front of `frame`",
+    ),
+    (
+        "delete",
+        "<frame> [count]",
+        "delete frames starting with `frame`",
+    ),
+    ("seek", "<frame>", "go to the start of a movie frame"),
+    (
+        "branch",
+        "[save|load|delete <name>]",
+        "list, save, load or delete movie branches",
+    ),
+    (
+        "bookmark",
+        "[slot] [goto]",
+        "list, set or go to frame bookmarks",
+    ),
+    (
+        "corrupt",
+        "[start|stop|now|clear]",
+        "show or control the corruptor (needs `--corruptor`)",
+    ),
+    (
+        "corrupt",
+        "range <addr> <len>",
+        "add an address range to corrupt",
+    ),
+    (
+        "corrupt",
+        "mode flip|random",
+        "flip single bits or randomize whole bytes",
+    ),
+    (
+        "corrupt",
+        "every <frames> [count]",
+        "corrupt `count` bytes every few frames",
+    ),
+    (
+        "corrupt",
+        "undo [steps]",
+        "restore the bytes of the last corruption steps",
+    ),
+    ("corrupt", "seed <n>", "seed the random number generator"),
+    (
+        "state",
+        "[save|load <slot>]",
+        "list, store or load save state slots",
+    ),
+    (
+        "state",
+        "export <slot> <file>",
+        "write a save state slot to a file",
+    ),
+    ("state", "undo", "undo loading a save state slot"),
+    (
+        "state",
+        "thumbnail <slot> <file>",
+        "write the thumbnail of a slot to a PNG file",
+    ),
+    (
+        "state",
+        "import <file> [slot]",
+        "load a state file, or read it into a slot",
+    ),
+    ("label", "<name> <addr>", "define a symbol for an address"),
+    ("labels", "", "list all defined symbols"),
+    ("source", "<file>", "execute the commands in a script file"),
+    ("history", "", "print the command history"),
+    ("quit", "", "exit the emulator"),
+];
+
+type Symbols = Arc<Mutex<BTreeMap<String, Addr24>>>;
+
+struct ConsoleHelper {
+    symbols: Symbols,
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+        let to_pair = |name: &str| Pair {
+            display: name.to_owned(),
+            replacement: name.to_owned(),
+        };
+        let candidates = if line[..start].trim().is_empty() {
+            COMMANDS
+                .iter()
+                .map(|(name, ..)| *name)
+                .filter(|name| name.starts_with(word))
+                .map(to_pair)
+                .collect()
+        } else {
+            self.symbols
+                .lock()
+                .unwrap()
+                .keys()
+                .filter(|name| name.starts_with(word))
+                .map(|name| to_pair(name))
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ConsoleHelper {}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
+
+fn history_file_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| Path::new(&home).join(HISTORY_FILE_PATH))
+}
+
+/// The session file of the ROM with the id `rom_id`
+pub fn session_path(rom_id: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(
+        Path::new(&home)
+            .join(SESSION_DIR)
+            .join(format!("{rom_id}.txt")),
+    )
+}
+
+/// The arguments of `watch`, `buslog include` or `buslog exclude`, which
+/// create `filter`
+fn filter_args(filter: &Filter) -> String {
+    let mut args = format!("{} {}", filter.start, filter.end);
+    match filter.kind {
+        Some(AccessKind::Read) => args.push_str(" r"),
+        Some(AccessKind::Write) => args.push_str(" w"),
+        None => (),
+    }
+    match filter.origin {
+        Some(Origin::Cpu) => args.push_str(" cpu"),
+        Some(Origin::Dma) => args.push_str(" dma"),
+        None => (),
+    }
+    args
+}
+
+/// What the main loop has to do after a command was executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Quit,
+}
+
+pub struct Debugger {
+    lines: mpsc::Receiver<String>,
+    symbols: Symbols,
+    history: Arc<Mutex<Vec<String>>>,
+    rom_patches: BTreeSet<usize>,
+    /// Only available when started with `--corruptor`
+    corruptor: Option<Corruptor>,
+    watch_ppu: bool,
+    /// The file, to which the CPU trace is written after every frame
+    trace_file: Option<BufWriter<File>>,
+    /// The count of scripts currently running
+    script_depth: usize,
+    /// The file, to which the cheats are written after every change
+    pub cheat_file: Option<PathBuf>,
+    /// The file, to which the session is written after every change
+    pub session_file: Option<PathBuf>,
+    pub paused: bool,
+    /// The count of frames to emulate while paused
+    pub advance: usize,
+}
+
+impl Debugger {
+    /// Spawn the console thread
+    pub fn new(corruptor: Option<Corruptor>) -> Self {
+        let (sender, lines) = mpsc::channel();
+        let symbols = Symbols::default();
+        let history = Arc::new(Mutex::new(Vec::new()));
+        let helper = ConsoleHelper {
+            symbols: Arc::clone(&symbols),
+        };
+        let thread_history = Arc::clone(&history);
+        std::thread::spawn(move || {
+            let mut editor = Editor::<ConsoleHelper>::new();
+            editor.set_helper(Some(helper));
+            let history_path = history_file_path();
+            if let Some(path) = &history_path {
+                let _ = editor.load_history(path);
+            }
+            loop {
+                match editor.readline(PROMPT) {
+                    Ok(line) => {
+                        if !line.trim().is_empty() {
+                            editor.add_history_entry(line.as_str());
+                            thread_history.lock().unwrap().push(line.clone());
+                            if let Some(path) = &history_path {
+                                if let Some(dir) = path.parent() {
+                                    let _ = std::fs::create_dir_all(dir);
+                                }
+                                let _ = editor.save_history(path);
+                            }
+                        }
+                        if sender.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(ReadlineError::Eof) => {
+                        let _ = sender.send(String::from("quit"));
+                        break;
+                    }
+                    Err(err) => {
+                        eprintln!("[warning] debugger console failed ({err})");
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            lines,
+            symbols,
+            history,
+            rom_patches: BTreeSet::new(),
+            corruptor,
+            watch_ppu: false,
+            trace_file: None,
+            script_depth: 0,
+            cheat_file: None,
+            session_file: None,
+            paused: false,
+            advance: 0,
+        }
+    }
+
+    /// Execute every command of a script file.
+    /// Empty lines and lines starting with `#` are ignored.
+    /// Scripts may `source` other scripts up to a depth of
+    /// [`MAX_SCRIPT_DEPTH`], so a script sourcing itself fails instead of
+    /// overflowing the stack.
+    pub fn run_script<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        path: &Path,
+        device: &mut Device<B, FB>,
+        tas: &mut Tas,
+        states: &mut SaveStates,
+    ) -> std::io::Result<Action> {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            return Err(std::io::Error::other(format!(
+                "scripts nested deeper than {MAX_SCRIPT_DEPTH} levels"
+            )));
+        }
+        let content = std::fs::read_to_string(path)?;
+        self.script_depth += 1;
+        let mut action = Action::None;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if self.execute(line, device, tas, states) == Action::Quit {
+                action = Action::Quit;
+                break;
+            }
+        }
+        self.script_depth -= 1;
+        Ok(action)
+    }
+
+    /// Execute all commands the user typed in since the last call
+    pub fn poll<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        tas: &mut Tas,
+        states: &mut SaveStates,
+    ) -> Action {
+        while let Ok(line) = self.lines.try_recv() {
+            if self.execute(&line, device, tas, states) == Action::Quit {
+                return Action::Quit;
+            }
+            let cmd = line.split_whitespace().next();
+            if cmd.is_some_and(|cmd| SESSION_COMMANDS.contains(&cmd)) {
+                self.save_session(device)
+            }
+        }
+        Action::None
+    }
+
+    /// Restore the session from the session file, if there is one
+    pub fn load_session<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        tas: &mut Tas,
+        states: &mut SaveStates,
+    ) -> std::io::Result<()> {
+        match &self.session_file {
+            Some(path) if path.exists() => {
+                self.run_script(&path.clone(), device, tas, states)?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The commands, which restore the session
+    fn session_script<B: AudioBackend, FB: FrameBuffer>(&self, device: &Device<B, FB>) -> String {
+        let mut script = vec![SESSION_MAGIC.to_string()];
+        for (name, addr) in self.symbols.lock().unwrap().iter() {
+            script.push(format!("label {name} {addr}"));
+        }
+        if let Some(debugger) = &device.debugger {
+            for addr in &debugger.breakpoints {
+                script.push(format!("break {addr}"));
+            }
+            for filter in &debugger.watchpoints {
+                script.push(format!("watch {}", filter_args(filter)));
+            }
+        }
+        if let Some(log) = &device.bus_log {
+            script.push(format!("buslog on {}", log.capacity()));
+            for filter in &log.include {
+                script.push(format!("buslog include {}", filter_args(filter)));
+            }
+            for filter in &log.exclude {
+                script.push(format!("buslog exclude {}", filter_args(filter)));
+            }
+        }
+        if let Some(trace) = &device.cpu_trace {
+            script.push(format!("trace on {}", trace.capacity()));
+            script.push(format!("trace format {}", trace.format));
+        }
+        let tinted: Vec<_> = LAYER_NAMES
+            .iter()
+            .zip(device.ppu.tinted_layers)
+            .filter(|(_, tinted)| *tinted)
+            .map(|(name, _)| *name)
+            .collect();
+        if !tinted.is_empty() {
+            script.push(format!("tint {}", tinted.join(" ")));
+        }
+        if self.watch_ppu {
+            script.push(String::from("ppu watch"));
+        }
+        script.push(String::new());
+        script.join("\n")
+    }
+
+    /// Write the session file, if there is one
+    fn save_session<B: AudioBackend, FB: FrameBuffer>(&self, device: &Device<B, FB>) {
+        let path = match &self.session_file {
+            Some(path) => path,
+            None => return,
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::fs::write(path, self.session_script(device)));
+        if let Err(err) = result {
+            println!(
+                "could not write the session file `{}` ({err})",
+                path.display()
+            )
+        }
+    }
+
+    /// Called by the main loop, when a breakpoint or watchpoint stopped the
+    /// emulation
+    pub fn on_break<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        self.write_trace(device);
+        if let Some(reason) = device.debugger.as_ref().and_then(|d| d.break_reason()) {
+            println!("stopped by {reason}");
+        }
+        self.paused = true;
+    }
+
+    /// Called by the main loop after a frame has been emulated
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        if let Some(corruptor) = &mut self.corruptor {
+            let changes = corruptor.on_frame(device);
+            self.track_rom_patches(device, &changes);
+        }
+        self.write_trace(device);
+        if self.watch_ppu {
+            // clear the terminal before redrawing the register panel
+            print!("\x1b[H\x1b[2J");
+            println!("{}", device.ppu.registers());
+        }
+    }
+
+    /// Print where the pixel `x`, `y` of the frame buffer comes from.
+    /// Called by the main loop, when a pixel is clicked, while pixels are
+    /// inspected.
+    pub fn inspect_pixel<B: AudioBackend, FB: FrameBuffer>(
+        &self,
+        device: &Device<B, FB>,
+        [x, y]: [u16; 2],
+    ) {
+        let Some(sources) = &device.ppu.pixel_sources else {
+            println!("pixels aren't inspected, enable it with `inspect on`");
+            return;
+        };
+        let source = sources[usize::from(y) * 256 + usize::from(x)];
+        let mut report = format!(
+            "pixel ({x}, {y}): {}",
+            LAYER_NAMES[usize::from(source.layer)]
+        );
+        if let Some(addr) = source.tile_addr {
+            report += &format!(
+                ", priority {}, tile ${addr:04x}, palette {} color {}",
+                source.priority, source.palette, source.color
+            );
+        }
+        match source.cgram_addr {
+            Some(addr) => report += &format!(" (CGRAM ${addr:02x})"),
+            None => report += " (direct color)",
+        }
+        if source.color_math {
+            report += ", color math"
+        }
+        println!("{report}")
+    }
+
+    /// Move the traced instructions to the trace file, if there is one
+    fn write_trace<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        let (file, trace) = match (&mut self.trace_file, &mut device.cpu_trace) {
+            (Some(file), Some(trace)) => (file, trace),
+            _ => return,
+        };
+        let dropped = trace.dropped();
+        let result = trace
+            .take_lines()
+            .iter()
+            .try_for_each(|line| writeln!(file, "{line}"))
+            .and_then(|()| file.flush());
+        if let Err(err) = result {
+            println!("[warning] stopped writing the CPU trace ({err})");
+            self.trace_file = None;
+        } else if dropped > 0 {
+            println!("[warning] {dropped} traced instruction(s) didn't fit into the trace");
+        }
+    }
+
+    fn parse_addr(&self, arg: &str) -> Option<Addr24> {
+        if let Some(addr) = self.symbols.lock().unwrap().get(arg) {
+            return Some(*addr);
+        }
+        let arg = arg.trim_start_matches('$');
+        let addr = if let Some((bank, addr)) = arg.split_once(':') {
+            (u32::from_str_radix(bank, 16).ok()? << 16) | u32::from_str_radix(addr, 16).ok()?
+        } else {
+            u32::from_str_radix(arg, 16).ok()?
+        };
+        (addr <= 0xffffff).then(|| Addr24::new((addr >> 16) as u8, addr as u16))
+    }
+
+    /// Parse the arguments of `buslog include`, `buslog exclude` and `watch`
+    fn parse_bus_filter(&self, args: &[&str]) -> Result<Filter, String> {
+        let start = match args.first() {
+            Some(arg) => self
+                .parse_addr(arg)
+                .ok_or_else(|| format!("invalid address `{arg}`"))?,
+            None => return Err(String::from("missing address argument")),
+        };
+        let mut filter = Filter::range(start, start);
+        for &arg in &args[1..] {
+            match arg {
+                "r" => filter.kind = Some(AccessKind::Read),
+                "w" => filter.kind = Some(AccessKind::Write),
+                "cpu" => filter.origin = Some(Origin::Cpu),
+                "dma" => filter.origin = Some(Origin::Dma),
+                _ => match self.parse_addr(arg) {
+                    Some(end) if end >= start => filter.end = end,
+                    _ => return Err(format!("invalid end address `{arg}`")),
+                },
+            }
+        }
+        Ok(filter)
+    }
+
+    fn parse_number(arg: &str) -> Option<u32> {
+        if let Some(hex) = arg.strip_prefix('$').or_else(|| arg.strip_prefix("0x")) {
+            u32::from_str_radix(hex, 16).ok()
+        } else {
+            arg.parse().ok()
+        }
+    }
+
+    /// Write a byte into the memory behind an address, so that every mirror sees the change.
+    /// Bytes mapped to ROM are remembered for the IPS export.
+    /// Registers are written through the bus.
+    fn patch_byte<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        addr: Addr24,
+        value: u8,
+    ) {
+        if let MemoryRegion::Rom(offset) = device.region(addr) {
+            self.rom_patches.insert(offset);
+        }
+        if !device.poke(addr, value) {
+            device.write_data::<u8>(addr, value);
+        }
+    }
+
+    /// Remember modified ROM bytes for IPS export
+    fn track_rom_patches<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &Device<B, FB>,
+        addrs: &[Addr24],
+    ) {
+        for &addr in addrs {
+            if let MemoryRegion::Rom(offset) = device.region(addr) {
+                self.rom_patches.insert(offset);
+            }
+        }
+    }
+
+    pub fn execute<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        line: &str,
+        device: &mut Device<B, FB>,
+        tas: &mut Tas,
+        states: &mut SaveStates,
+    ) -> Action {
+        let mut args = line.split_whitespace();
+        let cmd = match args.next() {
+            Some(cmd) => cmd,
+            None => return Action::None,
+        };
+        let args: Vec<&str> = args.collect();
+        macro_rules! num_arg {
+            ($i:expr, $default:expr) => {
+                match args.get($i).map(|arg| (arg, Self::parse_number(arg))) {
+                    Some((_, Some(n))) => n as usize,
+                    Some((arg, None)) => {
+                        println!("invalid number `{arg}`");
+                        return Action::None;
+                    }
+                    None => $default,
+                }
+            };
+        }
+        macro_rules! port_arg {
+            ($i:expr) => {
+                match args.get($i) {
+                    Some(&"1") => 0,
+                    Some(&"2") => 1,
+                    _ => {
+                        println!("expected controller port 1 or 2");
+                        return Action::None;
+                    }
+                }
+            };
+        }
+        macro_rules! addr_arg {
+            ($i:expr) => {
+                match args.get($i).map(|arg| (arg, self.parse_addr(arg))) {
+                    Some((_, Some(addr))) => addr,
+                    Some((arg, None)) => {
+                        println!("invalid address `{arg}`");
+                        return Action::None;
+                    }
+                    None => {
+                        println!("missing address argument");
+                        return Action::None;
+                    }
+                }
+            };
+        }
+        match cmd {
+            "help" | "h" => {
+                for (name, args, description) in COMMANDS {
+                    println!("  {:<26} {description}", format!("{name} {args}"));
+                }
+            }
+            "continue" | "c" => {
+                self.paused = false;
+                if let Some(debugger) = &mut device.debugger {
+                    debugger.resume();
+                }
+            }
+            "pause" | "p" => self.paused = true,
+            "advance" => {
+                if self.paused {
+                    self.advance += num_arg!(0, 1);
+                    if let Some(debugger) = &mut device.debugger {
+                        debugger.resume();
+                    }
+                } else {
+                    println!("the emulation is not paused")
+                }
+            }
+            "break" => {
+                let debugger = device.debugger.get_or_insert_with(Default::default);
+                if args.is_empty() {
+                    if debugger.breakpoints.is_empty() {
+                        println!("no breakpoints")
+                    }
+                    for addr in &debugger.breakpoints {
+                        println!("{addr}")
+                    }
+                } else {
+                    let addr = addr_arg!(0);
+                    if !debugger.breakpoints.remove(&addr) {
+                        debugger.breakpoints.insert(addr);
+                    }
+                }
+            }
+            "watch" => {
+                let debugger = device.debugger.get_or_insert_with(Default::default);
+                match args.first().copied() {
+                    None if debugger.watchpoints.is_empty() => println!("no watchpoints"),
+                    None => {
+                        for filter in &debugger.watchpoints {
+                            println!("{filter}")
+                        }
+                    }
+                    Some("clear") => debugger.watchpoints.clear(),
+                    Some(_) => match self.parse_bus_filter(&args) {
+                        Ok(filter) => debugger.watchpoints.push(filter),
+                        Err(err) => println!("{err}"),
+                    },
+                }
+            }
+            "regs" | "r" => {
+                let regs = device.with_main_cpu().cpu().regs.clone();
+                println!(
+                    "A={:04x} X={:04x} Y={:04x} SP={:04x} DP={:04x} DB={:02x} PC={} P={:08b}{}",
+                    regs.a,
+                    regs.x,
+                    regs.y,
+                    regs.sp,
+                    regs.dp,
+                    regs.db,
+                    regs.pc,
+                    regs.status.0,
+                    if regs.is_emulation { " E" } else { "" }
+                );
+            }
+            "read" => {
+                let addr = addr_arg!(0);
+                let len = match args.get(1) {
+                    Some(arg) => match Self::parse_number(arg) {
+                        Some(len) => len,
+                        None => {
+                            println!("invalid length `{arg}`");
+                            return Action::None;
+                        }
+                    },
+                    None => 1,
+                };
+                for row in 0..len.div_ceil(16) {
+                    let start = Addr24::new(addr.bank, addr.addr.wrapping_add(row as u16 * 16));
+                    let bytes: Vec<String> = (0..(len - row * 16).min(16))
+                        .map(|i| {
+                            let addr = Addr24::new(start.bank, start.addr.wrapping_add(i as u16));
+                            device
+                                .peek(addr)
+                                .map(|val| format!("{val:02x}"))
+                                .unwrap_or_else(|| String::from("--"))
+                        })
+                        .collect();
+                    let region = device.region(start).to_string();
+                    println!("{start}  {region:<16} {}", bytes.join(" "));
+                }
+            }
+            "write" => {
+                let addr = addr_arg!(0);
+                let bytes: Option<Vec<u8>> = args[1..]
+                    .iter()
+                    .map(|arg| u8::from_str_radix(arg.trim_start_matches('$'), 16).ok())
+                    .collect();
+                match bytes {
+                    Some(bytes) if !bytes.is_empty() => {
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            let addr = Addr24::new(addr.bank, addr.addr.wrapping_add(i as u16));
+                            self.patch_byte(device, addr, byte)
+                        }
+                    }
+                    _ => println!("expected one or more hexadecimal bytes"),
+                }
+            }
+            "asm" | "a" => {
+                let addr = addr_arg!(0);
+                let instr = args[1..].join(" ");
+                let cpu = device.with_main_cpu().cpu().clone();
+                match rsnes::asm::assemble(&instr, addr, cpu.is_reg8(), cpu.is_idx8()) {
+                    Ok(bytes) => {
+                        let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+                        println!("{addr}  {:<12} {instr}", hex.join(" "));
+                        for (i, byte) in bytes.into_iter().enumerate() {
+                            let addr = Addr24::new(addr.bank, addr.addr.wrapping_add(i as u16));
+                            self.patch_byte(device, addr, byte)
+                        }
+                    }
+                    Err(err) => println!("{err}"),
+                }
+            }
+            "dis" | "d" => {
+                let cpu = device.with_main_cpu().cpu().clone();
+                let mut addr = match args.first() {
+                    Some(_) => addr_arg!(0),
+                    None => cpu.regs.pc,
+                };
+                let mut flags = (cpu.is_reg8(), cpu.is_idx8());
+                for _ in 0..num_arg!(1, 10) {
+                    let bytes: Vec<u8> = (0..MAX_INSTRUCTION_SIZE as u16)
+                        .map_while(|i| {
+                            device.peek(Addr24::new(addr.bank, addr.addr.wrapping_add(i)))
+                        })
+                        .collect();
+                    let instr = match disasm::decode(&bytes, addr, flags.0, flags.1) {
+                        Some(instr) => instr,
+                        None => {
+                            println!("{addr}  cannot be read");
+                            break;
+                        }
+                    };
+                    let hex: Vec<String> = bytes[..instr.size]
+                        .iter()
+                        .map(|b| format!("{b:02x}"))
+                        .collect();
+                    println!("{addr}  {:<12} {instr}", hex.join(" "));
+                    flags = instr.flags_after(flags.0, flags.1);
+                    addr = instr.next_addr();
+                }
+            }
+            "buslog" => match args.first().copied() {
+                None => match &device.bus_log {
+                    Some(log) => {
+                        println!(
+                            "{}/{} access(es) logged, {} dropped",
+                            log.len(),
+                            log.capacity(),
+                            log.dropped()
+                        );
+                        for filter in &log.include {
+                            println!("  include {filter}");
+                        }
+                        for filter in &log.exclude {
+                            println!("  exclude {filter}");
+                        }
+                    }
+                    None => println!("the bus log is disabled"),
+                },
+                Some("on") => {
+                    let size = num_arg!(1, DEFAULT_BUS_LOG_SIZE);
+                    let mut log = BusLog::new(size);
+                    // keep the filters, when the size changes
+                    if let Some(old) = device.bus_log.take() {
+                        log.include = old.include;
+                        log.exclude = old.exclude;
+                    }
+                    device.bus_log = Some(log)
+                }
+                Some("off") => device.bus_log = None,
+                Some(cmd @ ("clear" | "include" | "exclude" | "dump")) => {
+                    let log = match &mut device.bus_log {
+                        Some(log) => log,
+                        None => {
+                            println!("the bus log is disabled, enable it with `buslog on`");
+                            return Action::None;
+                        }
+                    };
+                    match cmd {
+                        "clear" => {
+                            log.include.clear();
+                            log.exclude.clear();
+                        }
+                        "dump" => {
+                            let dropped = log.dropped();
+                            let lines: Vec<String> =
+                                log.take().iter().map(ToString::to_string).collect();
+                            match args.get(1) {
+                                Some(path) => {
+                                    let mut content = lines.join("\n");
+                                    content.push('\n');
+                                    if let Err(err) = std::fs::write(path, content) {
+                                        println!("could not write bus log `{path}` ({err})")
+                                    }
+                                }
+                                None => lines.iter().for_each(|line| println!("{line}")),
+                            }
+                            if dropped > 0 {
+                                println!("{dropped} older access(es) didn't fit into the log");
+                            }
+                        }
+                        _ => match self.parse_bus_filter(&args[1..]) {
+                            Ok(filter) if cmd == "include" => log.include.push(filter),
+                            Ok(filter) => log.exclude.push(filter),
+                            Err(err) => println!("{err}"),
+                        },
+                    }
+                }
+                Some(arg) => println!("unknown argument `{arg}`"),
+            },
+            "trace" => match args.first().copied() {
+                None => match &device.cpu_trace {
+                    Some(trace) => println!(
+                        "{}/{} instruction(s) traced in the {} format, {} dropped{}",
+                        trace.len(),
+                        trace.capacity(),
+                        trace.format,
+                        trace.dropped(),
+                        if self.trace_file.is_some() {
+                            ", written to a file"
+                        } else {
+                            ""
+                        }
+                    ),
+                    None => println!("the CPU trace is disabled"),
+                },
+                Some("on") => {
+                    let size = num_arg!(1, DEFAULT_TRACE_SIZE);
+                    self.write_trace(device);
+                    let mut trace = CpuTrace::new(size);
+                    if let Some(old) = &device.cpu_trace {
+                        trace.format = old.format;
+                    }
+                    device.cpu_trace = Some(trace)
+                }
+                Some("off") => {
+                    self.write_trace(device);
+                    self.trace_file = None;
+                    device.cpu_trace = None
+                }
+                Some("format") => match args.get(1).and_then(|arg| Format::from_name(arg)) {
+                    Some(format) => {
+                        self.write_trace(device);
+                        device
+                            .cpu_trace
+                            .get_or_insert_with(|| CpuTrace::new(DEFAULT_TRACE_SIZE))
+                            .format = format
+                    }
+                    None => println!("expected `trace format rsnes|bsnes|mesen`"),
+                },
+                Some("dump") => {
+                    let trace = match &mut device.cpu_trace {
+                        Some(trace) => trace,
+                        None => {
+                            println!("the CPU trace is disabled, enable it with `trace on`");
+                            return Action::None;
+                        }
+                    };
+                    let dropped = trace.dropped();
+                    let lines = trace.take_lines();
+                    match args.get(1) {
+                        Some(path) => {
+                            let mut content = lines.join("\n");
+                            content.push('\n');
+                            if let Err(err) = std::fs::write(path, content) {
+                                println!("could not write CPU trace `{path}` ({err})")
+                            }
+                        }
+                        None => lines.iter().for_each(|line| println!("{line}")),
+                    }
+                    if dropped > 0 {
+                        println!("{dropped} older instruction(s) didn't fit into the trace");
+                    }
+                }
+                Some("file") => match args.get(1).copied() {
+                    Some("off") => {
+                        self.write_trace(device);
+                        self.trace_file = None
+                    }
+                    Some(path) => match File::create(path) {
+                        Ok(file) => {
+                            self.write_trace(device);
+                            self.trace_file = Some(BufWriter::new(file));
+                            // the instructions traced so far are written, too
+                            device
+                                .cpu_trace
+                                .get_or_insert_with(|| CpuTrace::new(DEFAULT_TRACE_SIZE));
+                        }
+                        Err(err) => println!("could not create CPU trace `{path}` ({err})"),
+                    },
+                    None => println!("missing file argument"),
+                },
+                Some(arg) => println!("unknown argument `{arg}`"),
+            },
+            "patches" => {
+                if let Some(cart) = device.cartridge() {
+                    for &offset in &self.rom_patches {
+                        println!("  {offset:06x}  {:02x}", cart.rom()[offset]);
+                    }
+                }
+                println!("{} patched ROM byte(s)", self.rom_patches.len());
+            }
+            "hacks" => match args[..] {
+                [] if device.hacks().is_empty() => println!("the game needs no hacks"),
+                [] => {
+                    for active in device.hacks() {
+                        let state = if active.enabled { "on" } else { "off" };
+                        let hack = active.hack;
+                        println!("{:<20} {state:<3}  {}", hack.name, hack.description)
+                    }
+                }
+                [name, state @ ("on" | "off")] => {
+                    if !device.set_hack(name, state == "on") {
+                        println!("the game has no hack `{name}`")
+                    }
+                }
+                _ => println!("expected `hacks <name> on|off`"),
+            },
+            "cheat" => {
+                let changed = match args[..] {
+                    [] => {
+                        for (i, cheat) in device.cheats().iter().enumerate() {
+                            let state = if cheat.enabled { "on" } else { "off" };
+                            let code = cheat.code.to_string();
+                            println!("{i:>3}  {code:<9}  {state:<3}  {}", cheat.description)
+                        }
+                        println!("{} cheat(s)", device.cheats().len());
+                        Ok(false)
+                    }
+                    ["add", code, ..] => rsnes::cheat::Code::parse(code)
+                        .and_then(|code| device.add_cheat(code, args[2..].join(" "), true))
+                        .map(|index| {
+                            println!("added cheat {index}");
+                            true
+                        }),
+                    [state @ ("on" | "off"), _] => device
+                        .set_cheat(num_arg!(1, 0), state == "on")
+                        .map(|()| true),
+                    ["remove", _] => device.remove_cheat(num_arg!(1, 0)).map(|_| true),
+                    _ => {
+                        println!(
+                            "expected `cheat [add <code> [description]|on|off|remove <index>]`"
+                        );
+                        Ok(false)
+                    }
+                };
+                match (changed, &self.cheat_file) {
+                    (Ok(true), Some(path)) => {
+                        if let Err(err) = cheats::save(device, path) {
+                            println!("could not write cheat file `{}` ({err})", path.display())
+                        }
+                    }
+                    (Ok(_), _) => (),
+                    (Err(err), _) => println!("{err}"),
+                }
+            }
+            "ips" => match (args.first(), device.cartridge()) {
+                (Some(path), Some(cart)) => {
+                    // the offsets of the patch are relative to the original file
+                    let offsets: BTreeSet<usize> = self
+                        .rom_patches
+                        .iter()
+                        .filter_map(|&offset| cart.file_offset(offset))
+                        .collect();
+                    let patch = rsnes::patch::create_ips(&cart.file_bytes(), offsets);
+                    if let Err(err) = std::fs::write(path, patch) {
+                        println!("could not write IPS file `{path}` ({err})")
+                    }
+                }
+                (None, _) => println!("missing IPS file argument"),
+                (_, None) => println!("no cartridge loaded"),
+            },
+            "region" => {
+                let addr = addr_arg!(0);
+                let region = device.region(addr);
+                println!("{addr}  {region}");
+                if !matches!(region, MemoryRegion::Unmapped | MemoryRegion::Coprocessor) {
+                    let mirrors = device.mirrors(addr);
+                    let shown: Vec<String> = mirrors
+                        .iter()
+                        .take(MAX_SHOWN_MIRRORS)
+                        .map(ToString::to_string)
+                        .collect();
+                    print!("{} mirror(s): {}", mirrors.len(), shown.join(" "));
+                    if mirrors.len() > MAX_SHOWN_MIRRORS {
+                        print!(" ...");
+                    }
+                    println!();
+                }
+            }
+            "map" => {
+                let ranges = device.address_map();
+                match args.first() {
+                    Some(path) => {
+                        let content = if path.ends_with(".json") {
+                            address_map::to_json(&ranges)
+                        } else {
+                            address_map::to_text(&ranges)
+                        };
+                        if let Err(err) = std::fs::write(path, content) {
+                            println!("could not write address map `{path}` ({err})")
+                        }
+                    }
+                    None => print!("{}", address_map::to_text(&ranges)),
+                }
+            }
+            "ppu" => match args.first() {
+                Some(&"watch") => self.watch_ppu ^= true,
+                Some(arg) => match rsnes::ppu::Register::from_name(arg) {
+                    Some(reg) => {
+                        if let Some(&value) = args.get(1) {
+                            match Self::parse_number(value) {
+                                Some(value) if value <= 0xffff => {
+                                    if !device.ppu.poke_register(reg, value as u16) {
+                                        println!("{reg} is read-only");
+                                        return Action::None;
+                                    }
+                                }
+                                _ => {
+                                    println!("invalid value `{value}`");
+                                    return Action::None;
+                                }
+                            }
+                        }
+                        println!("{reg} = ${:04x}", device.ppu.peek_register(reg))
+                    }
+                    None => println!("unknown argument `{arg}`"),
+                },
+                None => println!("{}", device.ppu.registers()),
+            },
+            "tint" => {
+                for &arg in &args {
+                    match LAYER_NAMES.iter().position(|name| *name == arg) {
+                        Some(layer) => device.ppu.tinted_layers[layer] ^= true,
+                        None if arg == "off" => device.ppu.tinted_layers = [false; 6],
+                        None => println!("unknown layer `{arg}`"),
+                    }
+                }
+                let tinted: Vec<_> = LAYER_NAMES
+                    .iter()
+                    .zip(device.ppu.tinted_layers)
+                    .filter(|(_, tinted)| *tinted)
+                    .map(|(name, _)| *name)
+                    .collect();
+                if tinted.is_empty() {
+                    println!("no layer is tinted")
+                } else {
+                    println!("tinted layers: {}", tinted.join(", "))
+                }
+            }
+            "inspect" => match args.first().copied() {
+                None | Some("on" | "off") => {
+                    let on = match args.first() {
+                        Some(arg) => *arg == "on",
+                        None => device.ppu.pixel_sources.is_none(),
+                    };
+                    device.ppu.pixel_sources =
+                        on.then(|| vec![PixelSource::default(); FRAME_BUFFER_SIZE]);
+                    if on {
+                        println!("inspecting pixels from the next frame on, click a pixel")
+                    } else {
+                        println!("stopped inspecting pixels")
+                    }
+                }
+                Some(_) => match [num_arg!(0, 0), num_arg!(1, 0)] {
+                    [x @ 0..=0xff, y] if y < FRAME_BUFFER_SIZE / 256 => {
+                        self.inspect_pixel(device, [x as u16, y as u16])
+                    }
+                    _ => println!("expected a pixel from (0, 0) to (255, 238)"),
+                },
+            },
+            "palette" => match (args.first().copied(), args.get(1)) {
+                (None, _) => {
+                    for (row, colors) in device.ppu.palette().chunks(16).enumerate() {
+                        let colors: Vec<_> =
+                            colors.iter().map(|color| format!("{color:04x}")).collect();
+                        println!("{:02x}: {}", row * 16, colors.join(" "));
+                    }
+                }
+                (Some("save"), Some(path)) => {
+                    if let Err(err) = palette::save(Path::new(path), &device.ppu.palette()) {
+                        println!("could not write palette file `{path}` ({err})")
+                    }
+                }
+                (Some("load"), Some(path)) => {
+                    let first = match num_arg!(2, 0) {
+                        first @ 0..=0xff => first as u8,
+                        _ => {
+                            println!("expected a color from 0 to 255");
+                            return Action::None;
+                        }
+                    };
+                    match palette::load(Path::new(path)) {
+                        Ok(colors) => {
+                            device.ppu.set_palette(first, &colors);
+                            let count = colors.len().min(256 - usize::from(first));
+                            println!("replaced {count} colors, shown from the next frame on")
+                        }
+                        Err(err) => println!("could not read palette file `{path}` ({err})"),
+                    }
+                }
+                _ => println!("expected `palette`, `palette save <file>` or `palette load <file>`"),
+            },
+            "movie" => match args.first().copied() {
+                None => match &tas.movie {
+                    Some(movie) => {
+                        let (states, size) = tas.greenzone_usage();
+                        println!(
+                            "movie `{}`: {:?} at frame {}/{} from {}, {} lag frames, greenzone: {states} states ({} KiB)",
+                            movie.title,
+                            tas.mode,
+                            tas.frame,
+                            movie.frames.len(),
+                            if movie.anchor.is_some() { "its anchor" } else { "power-on" },
+                            tas.lag_frame_count(),
+                            size >> 10
+                        )
+                    }
+                    None => println!("no movie loaded"),
+                },
+                Some("new") => {
+                    let title = device
+                        .cartridge()
+                        .map(|cart| cart.title().to_owned())
+                        .unwrap_or_default();
+                    let anchor = match args.get(1).copied() {
+                        Some("here") => Some(state_files::save(device)),
+                        _ => None,
+                    };
+                    tas.movie_path = None;
+                    tas.project = Project::default();
+                    if let Err(err) = tas.start(device, Movie::new(title, anchor), Mode::Record) {
+                        println!("{err}")
+                    }
+                }
+                Some("play") => match tas.movie.take() {
+                    Some(movie) => {
+                        if let Err(err) = tas.start(device, movie, Mode::Playback) {
+                            println!("{err}")
+                        }
+                    }
+                    None => println!("no movie loaded"),
+                },
+                Some("record") => match tas.movie {
+                    Some(_) => tas.record_from_here(),
+                    None => println!("no movie loaded"),
+                },
+                Some("stop") => tas.stop(),
+                Some("load") => match args.get(1) {
+                    Some(path) => {
+                        if let Err(err) = tas.load_movie(device, Path::new(path)) {
+                            println!("{err}")
+                        }
+                    }
+                    None => println!("missing movie file argument"),
+                },
+                Some("save") => match (args.get(1).map(PathBuf::from), tas.movie_path.clone()) {
+                    _ if tas.movie.is_none() => println!("no movie loaded"),
+                    (Some(path), _) | (None, Some(path)) => {
+                        if let Err(err) = tas.save_movie(&path) {
+                            println!("could not write movie file `{}` ({err})", path.display())
+                        }
+                    }
+                    (None, None) => println!("missing movie file argument"),
+                },
+                Some(arg) => println!("unknown argument `{arg}`"),
+            },
+            "roll" => {
+                let start = num_arg!(0, tas.frame.saturating_sub(ROLL_CONTEXT));
+                let count = num_arg!(1, 2 * ROLL_CONTEXT);
+                let movie = match &tas.movie {
+                    Some(movie) => movie,
+                    None => {
+                        println!("no movie loaded");
+                        return Action::None;
+                    }
+                };
+                println!("     frame |UDLRSsYBXAlr|UDLRSsYBXAlr|");
+                for frame in start..(start + count).min(movie.frames.len()) {
+                    let [port1, port2] = movie.frames[frame].map(movie::format_buttons);
+                    println!(
+                        "{}{}{frame:>8} |{port1}|{port2}|",
+                        if frame == tas.frame { '>' } else { ' ' },
+                        if tas.has_greenzone_state(frame) {
+                            '*'
+                        } else {
+                            ' '
+                        },
+                    );
+                }
+            }
+            "input" | "toggle" => {
+                let frame = num_arg!(0, tas.frame);
+                let port = port_arg!(1);
+                let buttons = match args.get(2).map(|arg| movie::parse_buttons(arg)) {
+                    Some(Some(buttons)) => buttons,
+                    _ => {
+                        println!("expected buttons like `UY` or `.`");
+                        return Action::None;
+                    }
+                };
+                match &mut tas.movie {
+                    Some(movie) => {
+                        let input = &mut movie.input_mut(frame)[port];
+                        if cmd == "input" {
+                            *input = buttons
+                        } else {
+                            *input ^= buttons
+                        }
+                    }
+                    None => {
+                        println!("no movie loaded");
+                        return Action::None;
+                    }
+                }
+                if let Err(err) = tas.input_changed(device, frame) {
+                    println!("{err}")
+                }
+            }
+            "insert" | "delete" => {
+                let frame = num_arg!(0, tas.frame);
+                let count = num_arg!(1, 1);
+                match &mut tas.movie {
+                    Some(movie) if cmd == "insert" => movie.insert(frame, count),
+                    Some(movie) => movie.delete(frame, count),
+                    None => {
+                        println!("no movie loaded");
+                        return Action::None;
+                    }
+                }
+                if let Err(err) = tas.input_changed(device, frame) {
+                    println!("{err}")
+                }
+            }
+            "seek" => {
+                let frame = num_arg!(0, tas.frame);
+                if tas.movie.is_some() {
+                    if let Err(err) = tas.seek(device, frame) {
+                        println!("{err}")
+                    }
+                } else {
+                    println!("no movie loaded")
+                }
+            }
+            "branch" => match (
+                args.first().copied(),
+                args.get(1..).map(|name| name.join(" ")),
+            ) {
+                (None, _) => {
+                    for (name, branch) in &tas.project.branches {
+                        println!(
+                            "{name:<24} frame {}, {} frames",
+                            branch.frame,
+                            branch.frames.len()
+                        );
+                    }
+                }
+                _ if tas.movie.is_none() => println!("no movie loaded"),
+                (Some("save"), Some(name)) if !name.is_empty() => tas.save_branch(name),
+                (Some("load"), Some(name)) => match tas.load_branch(device, &name) {
+                    Ok(true) => (),
+                    Ok(false) => println!("unknown branch `{name}`"),
+                    Err(err) => println!("{err}"),
+                },
+                (Some("delete"), Some(name)) => {
+                    if tas.project.branches.remove(&name).is_none() {
+                        println!("unknown branch `{name}`")
+                    }
+                }
+                _ => println!("expected `branch save|load|delete <name>`"),
+            },
+            "bookmark" => match args.first() {
+                None => {
+                    for (slot, frame) in tas.project.bookmarks.iter().enumerate() {
+                        if let Some(frame) = frame {
+                            println!("{slot}: frame {frame}");
+                        }
+                    }
+                }
+                Some(_) => {
+                    let slot = num_arg!(0, 0);
+                    if slot >= BOOKMARK_COUNT {
+                        println!("bookmark slots range from 0 to {}", BOOKMARK_COUNT - 1);
+                    } else if tas.movie.is_none() {
+                        println!("no movie loaded");
+                    } else if args.get(1) == Some(&"goto") {
+                        match tas.jump_to_bookmark(device, slot) {
+                            Ok(true) => (),
+                            Ok(false) => println!("bookmark {slot} is not set"),
+                            Err(err) => println!("{err}"),
+                        }
+                    } else {
+                        tas.set_bookmark(slot)
+                    }
+                }
+            },
+            "corrupt" => {
+                let corruptor = match &mut self.corruptor {
+                    Some(corruptor) => corruptor,
+                    None => {
+                        println!(
+                            "the corruptor is disabled, start the emulator with `--corruptor`"
+                        );
+                        return Action::None;
+                    }
+                };
+                match args.first().copied() {
+                    None => {
+                        println!(
+                            "{}, {:?} {} byte(s) every {} frame(s), {} undo step(s)",
+                            if corruptor.active {
+                                "active"
+                            } else {
+                                "inactive"
+                            },
+                            corruptor.kind,
+                            corruptor.count,
+                            corruptor.interval,
+                            corruptor.undo_steps()
+                        );
+                        for (start, len) in &corruptor.ranges {
+                            println!("  {start} + ${len:x}");
+                        }
+                    }
+                    Some("start") => corruptor.active = true,
+                    Some("stop") => corruptor.active = false,
+                    Some("clear") => corruptor.ranges.clear(),
+                    Some("now") => {
+                        let changes = corruptor.step(device);
+                        println!("corrupted {} byte(s)", changes.len());
+                        self.track_rom_patches(device, &changes);
+                    }
+                    Some("range") => {
+                        let start = addr_arg!(1);
+                        let len = num_arg!(2, 0);
+                        if len == 0 || len > 0x1000000 {
+                            println!("expected a range length from 1 to $1000000");
+                        } else {
+                            self.corruptor
+                                .as_mut()
+                                .unwrap()
+                                .ranges
+                                .push((start, len as u32))
+                        }
+                    }
+                    Some("mode") => match args.get(1).copied() {
+                        Some("flip") => corruptor.kind = corruptor::Kind::Flip,
+                        Some("random") => corruptor.kind = corruptor::Kind::Random,
+                        _ => println!("expected `flip` or `random`"),
+                    },
+                    Some("every") => {
+                        let interval = num_arg!(1, 0);
+                        let count = num_arg!(2, corruptor.count);
+                        let corruptor = self.corruptor.as_mut().unwrap();
+                        corruptor.interval = interval.min(u32::MAX as usize) as u32;
+                        corruptor.count = count;
+                    }
+                    Some("undo") => {
+                        let steps = num_arg!(1, 1);
+                        let undone = self.corruptor.as_mut().unwrap().undo(device, steps);
+                        println!("undid {undone} step(s)");
+                    }
+                    Some("seed") => {
+                        let seed = num_arg!(1, 0);
+                        self.corruptor.as_mut().unwrap().seed(seed as u64)
+                    }
+                    Some(arg) => println!("unknown argument `{arg}`"),
+                }
+            }
+            "state" => {
+                macro_rules! slot_arg {
+                    ($i:expr) => {
+                        match num_arg!($i, SLOT_COUNT) {
+                            slot if slot < SLOT_COUNT => slot,
+                            _ => {
+                                println!("expected a slot from 0 to {}", SLOT_COUNT - 1);
+                                return Action::None;
+                            }
+                        }
+                    };
+                }
+                match args.first().copied() {
+                    None => {
+                        for (slot, state) in states.slots.iter().enumerate() {
+                            if let Some(state) = state {
+                                println!("{slot}: {} KiB", state.len() >> 10);
+                            } else if states.is_unread(slot) {
+                                println!("{slot}: in a slot file");
+                            }
+                        }
+                    }
+                    Some("save") => states.store(slot_arg!(1), device),
+                    Some("load") => {
+                        let slot = slot_arg!(1);
+                        match states.restore(slot, device) {
+                            Ok(true) => (),
+                            Ok(false) => println!("slot {slot} is empty"),
+                            Err(err) => println!("{err}"),
+                        }
+                    }
+                    Some("undo") => match states.undo(device) {
+                        Ok(true) => (),
+                        Ok(false) => println!("no slot was loaded"),
+                        Err(err) => println!("{err}"),
+                    },
+                    Some("thumbnail") => {
+                        let slot = slot_arg!(1);
+                        match (&states.thumbnails[slot], args.get(2)) {
+                            (Some(thumb), Some(path)) => {
+                                if let Err(err) =
+                                    png::write(path, thumb.width, thumb.height, &thumb.pixels)
+                                {
+                                    println!("could not write thumbnail ({err})")
+                                }
+                            }
+                            (None, _) => println!("slot {slot} has no thumbnail"),
+                            (_, None) => println!("missing PNG file argument"),
+                        }
+                    }
+                    Some("export") => {
+                        let slot = slot_arg!(1);
+                        match args.get(2).map(|path| states.export(slot, path)) {
+                            Some(Ok(true)) => (),
+                            Some(Ok(false)) => println!("slot {slot} is empty"),
+                            Some(Err(err)) => println!("could not write state file ({err})"),
+                            None => println!("missing state file argument"),
+                        }
+                    }
+                    Some("import") => {
+                        let path = match args.get(1) {
+                            Some(path) => *path,
+                            None => {
+                                println!("missing state file argument");
+                                return Action::None;
+                            }
+                        };
+                        let result = if args.len() > 2 {
+                            states.import(slot_arg!(2), path, device)
+                        } else {
+                            state_files::read_file(path)
+                                .and_then(|state| state_files::load_checked(device, &state))
+                        };
+                        if let Err(err) = result {
+                            println!("{err}")
+                        }
+                    }
+                    Some(arg) => println!("unknown argument `{arg}`"),
+                }
+            }
+            "label" => {
+                let name = match args.first() {
+                    Some(name) => name.to_string(),
+                    None => {
+                        println!("missing label name");
+                        return Action::None;
+                    }
+                };
+                let addr = addr_arg!(1);
+                self.symbols.lock().unwrap().insert(name, addr);
+            }
+            "labels" => {
+                for (name, addr) in self.symbols.lock().unwrap().iter() {
+                    println!("  {addr}  {name}");
+                }
+            }
+            "source" => match args.first() {
+                Some(path) => match self.run_script(Path::new(path), device, tas, states) {
+                    Ok(action) => return action,
+                    Err(err) => println!("could not run script `{path}` ({err})"),
+                },
+                None => println!("missing script file argument"),
+            },
+            "history" => {
+                for (i, line) in self.history.lock().unwrap().iter().enumerate() {
+                    println!("{i:>5}  {line}");
+                }
+            }
+            "quit" | "q" => return Action::Quit,
+            _ => println!("unknown command `{cmd}` (type `help` for a list of commands)"),
+        }
+        Action::None
+    }
+}
@@ -0,0 +1,78 @@
+//! Firmware dumps of the NEC DSP coprocessors (`firmware-dirs`)
+//!
+//! The firmware of the DSP-1 to DSP-4 is built into the emulator. A dump of
+//! the chip replaces it, if one is found in the directories of the profile
+//! option `firmware-dirs` or in `$HOME/.config/rsnes/firmware`, which is
+//! searched last. A dump is either one file of 8 KiB, e.g. `dsp1b.rom`, or a
+//! program ROM of 6 KiB and a data ROM of 2 KiB, e.g. `dsp1b.program.rom`
+//! and `dsp1b.data.rom`, as other emulators expect them. Without a dump the
+//! built-in firmware is used.
+
+use rsnes::cartridge::Cartridge;
+use rsnes::enhancement::Dsp;
+use std::path::{Path, PathBuf};
+
+/// The directory searched after the ones of the profile, relative to `$HOME`
+static FIRMWARE_DIR: &str = ".config/rsnes/firmware";
+
+/// The directories, which are searched for firmware files
+fn search_dirs(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let default = std::env::var_os("HOME").map(|home| Path::new(&home).join(FIRMWARE_DIR));
+    dirs.iter().cloned().chain(default).collect()
+}
+
+/// Read the firmware `name` from `dir`, `None` if it has no such files
+fn read(dir: &Path, name: &str) -> Option<std::io::Result<(PathBuf, Vec<u8>)>> {
+    let path = dir.join(format!("{name}.rom"));
+    if path.is_file() {
+        return Some(std::fs::read(&path).map(|firmware| (path, firmware)));
+    }
+    let program = dir.join(format!("{name}.program.rom"));
+    let data = dir.join(format!("{name}.data.rom"));
+    if program.is_file() && data.is_file() {
+        let firmware = std::fs::read(&program).and_then(|mut firmware| {
+            firmware.extend(std::fs::read(data)?);
+            Ok(firmware)
+        });
+        return Some(firmware.map(|firmware| (program, firmware)));
+    }
+    None
+}
+
+/// Load a dump of the firmware of the coprocessor of `cartridge` from the
+/// first directory of `dirs`, which contains one
+pub fn load(cartridge: &mut Cartridge, dirs: &[PathBuf], verbose: bool) {
+    let Some(dsp) = cartridge.dsp_mut() else {
+        return;
+    };
+    let ver = dsp.version();
+    for dir in search_dirs(dirs) {
+        match read(&dir, ver.firmware_name()) {
+            Some(Ok((path, firmware))) => {
+                if dsp.load_firmware(&firmware) {
+                    println!(
+                        "[info] loaded the {} firmware \"{}\"",
+                        ver.name(),
+                        path.display()
+                    );
+                    return;
+                }
+                eprintln!(
+                    "[warning] the firmware \"{}\" has {} bytes instead of {}",
+                    path.display(),
+                    firmware.len(),
+                    Dsp::FIRMWARE_SIZE
+                )
+            }
+            Some(Err(err)) => eprintln!(
+                "[warning] could not read the {} firmware in \"{}\" ({err})",
+                ver.name(),
+                dir.display()
+            ),
+            None => (),
+        }
+    }
+    if verbose {
+        println!("[info] using the built-in {} firmware", ver.name())
+    }
+}
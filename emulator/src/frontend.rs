@@ -0,0 +1,190 @@
+//! Integration into launcher frontends (`--frontend-mode`)
+//!
+//! In frontend mode, the emulator reads commands line by line from stdin and
+//! exits as soon as stdin is closed, e.g. because the parent process died.
+//! Status lines are written to stdout:
+//!
+//! - `loaded <title>` after the cartridge was loaded
+//! - `fps <frames per second>` once per second
+//! - `paused` and `resumed`
+//! - `error <message>` after an invalid command
+//! - `crashed <message>` if the emulator panics
+//!
+//! Commands are `pause`, `resume`, `save <slot>`, `load <slot>`, `undo`
+//! (undo loading a slot), `palette-save <file>` and `palette-load <file>`
+//! (export CGRAM and preview a palette, see [`crate::palette`]) and `quit`.
+
+use crate::palette;
+use crate::states::{SaveStates, SLOT_COUNT};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const FPS_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Print a status line and flush it immediately, because stdout is a pipe
+pub fn report(line: std::fmt::Arguments) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{line}");
+    let _ = stdout.flush();
+}
+
+/// Report panics as `crashed` status lines in addition to the default output
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .map(|msg| msg.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        report(format_args!("crashed {}", message.replace('\n', " ")));
+    }));
+}
+
+pub struct Frontend {
+    lines: mpsc::Receiver<Option<String>>,
+    frames: u32,
+    last_report: Instant,
+    pub paused: bool,
+}
+
+impl Frontend {
+    /// Spawn the thread reading commands from stdin
+    pub fn new() -> Self {
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(Some(line)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            // stdin was closed
+            let _ = sender.send(None);
+        });
+        Self {
+            lines,
+            frames: 0,
+            last_report: Instant::now(),
+            paused: false,
+        }
+    }
+
+    /// Execute all received commands. Returns false, if the emulator should exit.
+    pub fn poll<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        states: &mut SaveStates,
+    ) -> bool {
+        loop {
+            match self.lines.try_recv() {
+                Ok(Some(line)) => {
+                    if !self.execute(&line, device, states) {
+                        return false;
+                    }
+                }
+                Ok(None) | Err(mpsc::TryRecvError::Disconnected) => return false,
+                Err(mpsc::TryRecvError::Empty) => return true,
+            }
+        }
+    }
+
+    fn execute<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        line: &str,
+        device: &mut Device<B, FB>,
+        states: &mut SaveStates,
+    ) -> bool {
+        let mut args = line.split_whitespace();
+        let cmd = match args.next() {
+            Some(cmd) => cmd,
+            None => return true,
+        };
+        // the file may contain spaces
+        let file = line
+            .trim()
+            .split_once(char::is_whitespace)
+            .map(|(_, file)| file.trim());
+        match (cmd, file) {
+            ("palette-save", Some(file)) => {
+                if let Err(err) = palette::save(Path::new(file), &device.ppu.palette()) {
+                    report(format_args!("error {err}"))
+                }
+                return true;
+            }
+            ("palette-load", Some(file)) => {
+                match palette::load(Path::new(file)) {
+                    Ok(colors) => device.ppu.set_palette(0, &colors),
+                    Err(err) => report(format_args!("error {err}")),
+                }
+                return true;
+            }
+            ("palette-save" | "palette-load", None) => {
+                report(format_args!("error expected a file"));
+                return true;
+            }
+            _ => (),
+        }
+        let slot = args
+            .next()
+            .and_then(|slot| slot.parse::<usize>().ok())
+            .filter(|slot| *slot < SLOT_COUNT);
+        match (cmd, slot) {
+            ("quit", _) => return false,
+            ("pause", _) => {
+                self.paused = true;
+                report(format_args!("paused"))
+            }
+            ("resume", _) => {
+                self.paused = false;
+                // don't count the paused time into the next fps report
+                self.frames = 0;
+                self.last_report = Instant::now();
+                report(format_args!("resumed"))
+            }
+            ("save", Some(slot)) => states.store(slot, device),
+            ("load", Some(slot)) => match states.restore(slot, device) {
+                Ok(true) => (),
+                Ok(false) => report(format_args!("error slot {slot} is empty")),
+                Err(err) => report(format_args!("error {err}")),
+            },
+            ("undo", _) => match states.undo(device) {
+                Ok(true) => (),
+                Ok(false) => report(format_args!("error no slot was loaded")),
+                Err(err) => report(format_args!("error {err}")),
+            },
+            ("save" | "load", None) => report(format_args!(
+                "error expected a slot from 0 to {}",
+                SLOT_COUNT - 1
+            )),
+            _ => report(format_args!("error unknown command `{cmd}`")),
+        }
+        true
+    }
+
+    /// Called by the main loop after a frame has been emulated
+    pub fn on_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.last_report.elapsed();
+        if elapsed >= FPS_REPORT_INTERVAL {
+            report(format_args!(
+                "fps {:.1}",
+                f64::from(self.frames) / elapsed.as_secs_f64()
+            ));
+            self.frames = 0;
+            self.last_report = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,559 @@
+//! Gamepads (`gamepad-mappings`)
+//!
+//! Gamepads are read from the joystick devices of Linux (`/dev/input/js*`),
+//! which are scanned every second, so they can be plugged in at any time. The
+//! first gamepad presses the buttons of the standard controller in port 1,
+//! the second the ones of port 2, in addition to the keys mapped to them.
+//!
+//! The buttons and axes of a gamepad are mapped with the mappings of the SDL
+//! game controller database, which are identified by the GUID of the gamepad
+//! (its bus, vendor, product and version). Built-in mappings of common
+//! gamepads are in `gamecontrollerdb.txt`, the option `gamepad-mappings` of a
+//! profile adds mappings, which take precedence. A mapping of the same vendor
+//! and product is used, if the version differs, and gamepads without any
+//! mapping use the layout of the Xbox 360 controller.
+//!
+//! The face buttons are mapped by their position, not their label: the bottom
+//! one (`a` in the database) is B, the right one A, the left one Y and the top
+//! one X, like on the controller of the console. The shoulder buttons and the
+//! triggers are L and R, the left stick moves like the D-pad.
+
+use rsnes::controller::{buttons, Controller, ControllerPorts};
+use std::time::{Duration, Instant};
+
+static BUILTIN_MAPPINGS: &str = include_str!("../gamecontrollerdb.txt");
+
+/// The mapping of gamepads without a known one
+static DEFAULT_MAPPING: &str = "00000000000000000000000000000000,Gamepad,\
+    a:b0,b:b1,x:b2,y:b3,leftshoulder:b4,rightshoulder:b5,back:b6,start:b7,\
+    dpup:h0.1,dpright:h0.2,dpdown:h0.4,dpleft:h0.8,leftx:a0,lefty:a1,";
+
+/// The deflection of an axis, which presses a button (half of the range)
+const AXIS_THRESHOLD: i32 = 0x4000;
+
+/// The time between two scans for new gamepads
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A button, the position of a hat or an axis of a gamepad in the numbering
+/// of SDL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Input {
+    Button(u8),
+    /// A hat and the bits of its directions (1 up, 2 right, 4 down, 8 left)
+    Hat(u8, u8),
+    Axis {
+        index: u8,
+        /// Only the positive (`Some(true)`) or negative half of the axis
+        half: Option<bool>,
+        inverted: bool,
+    },
+}
+
+impl Input {
+    /// Parse an input like `b3`, `h0.4`, `a2`, `+a1` or `a5~`
+    fn parse(text: &str) -> Option<Self> {
+        let (half, text) = match text.as_bytes().first()? {
+            b'+' => (Some(true), &text[1..]),
+            b'-' => (Some(false), &text[1..]),
+            _ => (None, text),
+        };
+        let (inverted, text) = match text.strip_suffix('~') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let (kind, index) = text.split_at(text.find(|c: char| c.is_ascii_digit())?);
+        match (kind, half, inverted) {
+            ("b", None, false) => Some(Self::Button(index.parse().ok()?)),
+            ("h", None, false) => {
+                let (hat, mask) = index.split_once('.')?;
+                Some(Self::Hat(hat.parse().ok()?, mask.parse().ok()?))
+            }
+            ("a", ..) => Some(Self::Axis {
+                index: index.parse().ok()?,
+                half,
+                inverted,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// What an input of a gamepad presses on the standard controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Buttons(u16),
+    /// An axis of the left stick with the buttons of its negative and
+    /// positive direction
+    Stick(u16, u16),
+}
+
+impl Target {
+    /// The target of an element of the SDL database, e.g. `a` or `-leftx`
+    fn parse(element: &str) -> Option<Self> {
+        let (half, element) = match element.as_bytes().first()? {
+            b'+' => (Some(true), &element[1..]),
+            b'-' => (Some(false), &element[1..]),
+            _ => (None, element),
+        };
+        let target = match element {
+            // the face buttons by their position
+            "a" => Self::Buttons(buttons::B),
+            "b" => Self::Buttons(buttons::A),
+            "x" => Self::Buttons(buttons::Y),
+            "y" => Self::Buttons(buttons::X),
+            "leftshoulder" | "lefttrigger" => Self::Buttons(buttons::L),
+            "rightshoulder" | "righttrigger" => Self::Buttons(buttons::R),
+            "back" => Self::Buttons(buttons::SELECT),
+            "start" => Self::Buttons(buttons::START),
+            "dpup" => Self::Buttons(buttons::UP),
+            "dpdown" => Self::Buttons(buttons::DOWN),
+            "dpleft" => Self::Buttons(buttons::LEFT),
+            "dpright" => Self::Buttons(buttons::RIGHT),
+            "leftx" => Self::Stick(buttons::LEFT, buttons::RIGHT),
+            "lefty" => Self::Stick(buttons::UP, buttons::DOWN),
+            _ => return None,
+        };
+        Some(match (target, half) {
+            (Self::Stick(negative, positive), Some(half)) => {
+                Self::Buttons(if half { positive } else { negative })
+            }
+            _ => target,
+        })
+    }
+}
+
+/// A line of the SDL game controller database
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    guid: [u8; 16],
+    platform: Option<String>,
+    bindings: Vec<(Target, Input)>,
+}
+
+impl Mapping {
+    /// Parse a line like `<GUID>,<name>,a:b0,b:b1,...,platform:Linux,`.
+    /// Elements, which have no button on the standard controller, like the
+    /// right stick, are ignored.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim().split(',');
+        let guid = fields.next()?;
+        if guid.len() != 32 {
+            return None;
+        }
+        let mut bytes = [0; 16];
+        for (byte, i) in bytes.iter_mut().zip((0..32).step_by(2)) {
+            *byte = u8::from_str_radix(guid.get(i..i + 2)?, 16).ok()?;
+        }
+        // the name
+        fields.next()?;
+        let mut platform = None;
+        let mut bindings = vec![];
+        for field in fields.filter(|field| !field.is_empty()) {
+            let (element, input) = field.split_once(':')?;
+            if element == "platform" {
+                platform = Some(input.to_owned());
+            } else if let Some(target) = Target::parse(element) {
+                bindings.push((target, Input::parse(input)?));
+            }
+        }
+        Some(Self {
+            guid: bytes,
+            platform,
+            bindings,
+        })
+    }
+
+    /// The buttons of the standard controller held on a gamepad in `state`
+    fn held(&self, state: &State) -> u16 {
+        let mut held = 0;
+        for &(target, input) in &self.bindings {
+            held |= match input {
+                Input::Button(button) => match state.buttons.get(usize::from(button)) {
+                    Some(true) => target_buttons(target, 1),
+                    _ => 0,
+                },
+                Input::Hat(hat, mask) => {
+                    let bits = state
+                        .hats
+                        .get(usize::from(hat))
+                        .map_or(0, |&hat| hat_bits(hat));
+                    if bits & mask > 0 {
+                        target_buttons(target, 1)
+                    } else {
+                        0
+                    }
+                }
+                Input::Axis {
+                    index,
+                    half,
+                    inverted,
+                } => {
+                    let value = state.axes.get(usize::from(index)).copied().unwrap_or(0);
+                    let value = i32::from(value) * if inverted { -1 } else { 1 };
+                    match (target, half) {
+                        (Target::Stick(..), _) => target_buttons(target, value),
+                        (_, Some(true)) => target_buttons(target, value - AXIS_THRESHOLD),
+                        (_, Some(false)) => target_buttons(target, -value - AXIS_THRESHOLD),
+                        // a trigger, which rests at the negative end
+                        (_, None) => target_buttons(target, value),
+                    }
+                }
+            }
+        }
+        held
+    }
+}
+
+/// The vendor and product ID in a GUID
+fn vendor_product(guid: [u8; 16]) -> [u16; 2] {
+    let [_, _, _, _, v0, v1, _, _, p0, p1, ..] = guid;
+    [u16::from_le_bytes([v0, v1]), u16::from_le_bytes([p0, p1])]
+}
+
+/// The buttons pressed by an input with `value`: buttons are pressed by
+/// positive values, sticks by values beyond the threshold
+fn target_buttons(target: Target, value: i32) -> u16 {
+    match target {
+        Target::Buttons(buttons) if value > 0 => buttons,
+        Target::Stick(negative, _) if value < -AXIS_THRESHOLD => negative,
+        Target::Stick(_, positive) if value > AXIS_THRESHOLD => positive,
+        _ => 0,
+    }
+}
+
+/// The directions of a hat as the bits of the SDL database
+fn hat_bits([x, y]: [i16; 2]) -> u8 {
+    let x = match x {
+        ..=-1 => 8,
+        1.. => 2,
+        0 => 0,
+    };
+    let y = match y {
+        ..=-1 => 1,
+        1.. => 4,
+        0 => 0,
+    };
+    x | y
+}
+
+/// The state of a gamepad in the numbering of SDL
+#[derive(Debug, Default)]
+struct State {
+    buttons: Vec<bool>,
+    axes: Vec<i16>,
+    /// The horizontal and vertical axis of every hat
+    hats: Vec<[i16; 2]>,
+}
+
+/// Parse the mappings of a `gamecontrollerdb.txt` file, comments and
+/// mappings of other platforms are skipped
+pub fn parse_mappings(text: &str) -> Vec<Mapping> {
+    (text.lines())
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(Mapping::parse)
+        .filter(|mapping| mapping.platform.as_deref().is_none_or(|p| p == "Linux"))
+        .collect()
+}
+
+pub struct Gamepads {
+    /// The mappings of the profile followed by the built-in ones
+    mappings: Vec<Mapping>,
+    pads: Vec<joydev::Gamepad>,
+    /// The buttons pressed on the controllers of both ports by the last call
+    /// to [`Gamepads::apply`]
+    applied: [u16; 2],
+    last_scan: Option<Instant>,
+    verbose: bool,
+}
+
+impl Gamepads {
+    /// Use `mappings` (lines of the SDL database) before the built-in ones
+    pub fn new(mappings: &[String], verbose: bool) -> Self {
+        let mut all: Vec<Mapping> = mappings.iter().filter_map(|m| Mapping::parse(m)).collect();
+        all.extend(parse_mappings(BUILTIN_MAPPINGS));
+        Self {
+            mappings: all,
+            pads: vec![],
+            applied: [0; 2],
+            last_scan: None,
+            verbose,
+        }
+    }
+
+    /// The mapping of a gamepad with `guid`, or else of the same vendor
+    /// and product
+    fn mapping(&self, guid: [u8; 16]) -> Option<&Mapping> {
+        (self.mappings.iter())
+            .find(|mapping| mapping.guid == guid)
+            .or_else(|| {
+                (self.mappings.iter()).find(|mapping| {
+                    vendor_product(mapping.guid) != [0; 2]
+                        && vendor_product(mapping.guid) == vendor_product(guid)
+                })
+            })
+    }
+
+    fn scan(&mut self) {
+        for path in joydev::device_paths() {
+            if self.pads.iter().any(|pad| pad.path == path) {
+                continue;
+            }
+            let Ok(mut pad) = joydev::Gamepad::open(path) else {
+                // e.g. no permission, it's retried with the next scan
+                continue;
+            };
+            let mapping = match self.mapping(pad.guid) {
+                Some(mapping) => mapping.clone(),
+                None => {
+                    if self.verbose {
+                        println!(
+                            "[info] gamepad \"{}\" ({}) has no known mapping, the layout of the Xbox 360 controller is used",
+                            pad.name,
+                            pad.guid.iter().map(|byte| format!("{byte:02x}")).collect::<String>(),
+                        )
+                    }
+                    Mapping::parse(DEFAULT_MAPPING).unwrap()
+                }
+            };
+            pad.mapping = mapping;
+            if self.pads.len() < 2 {
+                println!(
+                    "[info] gamepad \"{}\" connected to port {}",
+                    pad.name,
+                    self.pads.len() + 1
+                )
+            }
+            self.pads.push(pad);
+        }
+    }
+
+    /// Read the gamepads and press their buttons on the standard controllers
+    /// of both ports. Buttons pressed by keys are kept.
+    pub fn apply(&mut self, controllers: &mut ControllerPorts) {
+        if self
+            .last_scan
+            .is_none_or(|last_scan| last_scan.elapsed() >= SCAN_INTERVAL)
+        {
+            self.last_scan = Some(Instant::now());
+            self.scan();
+        }
+        self.pads.retain_mut(|pad| {
+            let connected = pad.poll();
+            if !connected {
+                println!("[info] gamepad \"{}\" disconnected", pad.name)
+            }
+            connected
+        });
+        let ports = [&mut controllers.port1, &mut controllers.port2];
+        for ((port, applied), pad) in ports
+            .into_iter()
+            .zip(&mut self.applied)
+            .zip(self.pads.iter().map(Some).chain([None; 2]))
+        {
+            if let Controller::Standard(cntrl) = &mut port.controller {
+                let held = pad.map_or(0, |pad| pad.mapping.held(&pad.state));
+                cntrl.pressed_buttons = (cntrl.pressed_buttons & !*applied) | held;
+                *applied = held;
+            }
+        }
+    }
+}
+
+/// The joystick devices of Linux
+#[cfg(target_os = "linux")]
+mod joydev {
+    use super::{Mapping, State};
+    use std::fs::File;
+    use std::io::{ErrorKind, Read};
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+
+    /// `JSIOCGAXES`, the count of axes
+    const JSIOCGAXES: libc::c_ulong = 0x8001_6a11;
+    /// `JSIOCGAXMAP`, the codes of the axes (`ABS_*`)
+    const JSIOCGAXMAP: libc::c_ulong = 0x8040_6a32;
+    const ABS_CNT: usize = 0x40;
+    const ABS_HAT0X: u8 = 0x10;
+    const ABS_HAT3Y: u8 = 0x17;
+
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    /// Set for the events with the initial state of the device
+    const JS_EVENT_INIT: u8 = 0x80;
+
+    /// An axis of the device in the numbering of SDL, which numbers the
+    /// hats separately
+    #[derive(Debug, Clone, Copy)]
+    enum Axis {
+        Analog(usize),
+        HatX(usize),
+        HatY(usize),
+    }
+
+    pub struct Gamepad {
+        pub path: PathBuf,
+        pub name: String,
+        /// The GUID of SDL: bus, vendor, product and version as little endian
+        /// 16 bit numbers, each followed by two zero bytes
+        pub guid: [u8; 16],
+        pub mapping: Mapping,
+        pub state: State,
+        file: File,
+        axes: Vec<Axis>,
+    }
+
+    fn set<T: Default + Clone>(values: &mut Vec<T>, index: usize, value: T) {
+        if values.len() <= index {
+            values.resize(index + 1, T::default())
+        }
+        values[index] = value
+    }
+
+    /// The joystick devices, e.g. `/dev/input/js0`
+    pub fn device_paths() -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir("/dev/input")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("js"))
+            .map(|entry| entry.path())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    /// Read a file of the device in sysfs, e.g. `id/vendor`
+    fn sysfs(path: &Path, file: &str) -> Option<String> {
+        let name = path.file_name()?.to_str()?;
+        let path = Path::new("/sys/class/input").join(name).join("device");
+        Some(
+            std::fs::read_to_string(path.join(file))
+                .ok()?
+                .trim()
+                .to_owned(),
+        )
+    }
+
+    impl Gamepad {
+        pub fn open(path: PathBuf) -> std::io::Result<Self> {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(&path)?;
+            let mut count: u8 = 0;
+            let mut codes = [0u8; ABS_CNT];
+            // SAFETY: both requests write at most the size of the buffers
+            unsafe {
+                if libc::ioctl(file.as_raw_fd(), JSIOCGAXES, &mut count) < 0
+                    || libc::ioctl(file.as_raw_fd(), JSIOCGAXMAP, codes.as_mut_ptr()) < 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            // SDL numbers the hats separately from the other axes
+            let mut axis_count = 0;
+            let axes = (codes.iter().take(count.into()))
+                .map(|&code| match code {
+                    ABS_HAT0X..=ABS_HAT3Y => {
+                        let hat = usize::from(code - ABS_HAT0X) / 2;
+                        if code % 2 == 0 {
+                            Axis::HatX(hat)
+                        } else {
+                            Axis::HatY(hat)
+                        }
+                    }
+                    _ => {
+                        axis_count += 1;
+                        Axis::Analog(axis_count - 1)
+                    }
+                })
+                .collect();
+            let mut guid = [0; 16];
+            for (i, file) in ["bustype", "vendor", "product", "version"]
+                .into_iter()
+                .enumerate()
+            {
+                let id = sysfs(&path, &format!("id/{file}"))
+                    .and_then(|id| u16::from_str_radix(&id, 16).ok())
+                    .unwrap_or(0);
+                guid[i * 4..i * 4 + 2].copy_from_slice(&id.to_le_bytes());
+            }
+            let name = sysfs(&path, "name").unwrap_or_else(|| path.display().to_string());
+            Ok(Self {
+                path,
+                name,
+                guid,
+                mapping: Mapping::parse(super::DEFAULT_MAPPING).unwrap(),
+                state: State::default(),
+                file,
+                axes,
+            })
+        }
+
+        /// Read the queued events. Returns false, if the gamepad was
+        /// disconnected.
+        pub fn poll(&mut self) -> bool {
+            let mut buf = [0; 8 * 64];
+            loop {
+                let len = match self.file.read(&mut buf) {
+                    Ok(0) => return false,
+                    Ok(len) => len,
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                    Err(_) => return false,
+                };
+                // time (u32), value (i16), type (u8) and number (u8)
+                for event in buf[..len].chunks_exact(8) {
+                    let value = i16::from_ne_bytes([event[4], event[5]]);
+                    let number = usize::from(event[7]);
+                    match event[6] & !JS_EVENT_INIT {
+                        JS_EVENT_BUTTON => set(&mut self.state.buttons, number, value != 0),
+                        JS_EVENT_AXIS => match self.axes.get(number) {
+                            Some(&Axis::Analog(axis)) => set(&mut self.state.axes, axis, value),
+                            Some(&Axis::HatX(hat)) => {
+                                let y = self.state.hats.get(hat).map_or(0, |hat| hat[1]);
+                                set(&mut self.state.hats, hat, [value, y])
+                            }
+                            Some(&Axis::HatY(hat)) => {
+                                let x = self.state.hats.get(hat).map_or(0, |hat| hat[0]);
+                                set(&mut self.state.hats, hat, [x, value])
+                            }
+                            None => (),
+                        },
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Gamepads are only supported on Linux
+#[cfg(not(target_os = "linux"))]
+mod joydev {
+    use super::{Mapping, State};
+    use std::path::PathBuf;
+
+    pub struct Gamepad {
+        pub path: PathBuf,
+        pub name: String,
+        pub guid: [u8; 16],
+        pub mapping: Mapping,
+        pub state: State,
+    }
+
+    pub fn device_paths() -> Vec<PathBuf> {
+        vec![]
+    }
+
+    impl Gamepad {
+        pub fn open(_path: PathBuf) -> std::io::Result<Self> {
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+
+        pub fn poll(&mut self) -> bool {
+            false
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! The icon of the window
+//!
+//! By default, the icon is the logo of rsnes. With the profile option
+//! `game-icon = true`, it's replaced by a thumbnail of the picture of the
+//! game, as soon as the game shows something.
+
+use crate::{png, screenshot};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+use winit::window::Icon;
+
+/// The name, by which window managers group the windows of the emulator
+/// (`WM_CLASS` on X11, the app id on Wayland)
+pub static APP_ID: &str = "rsnes";
+
+/// The width and height of the thumbnails
+const SIZE: u32 = 32;
+
+/// The logo of the repository, embedded into the executable
+static LOGO: &[u8] = include_bytes!("../../logo.png");
+
+/// The logo of rsnes
+pub fn default_icon() -> Icon {
+    let logo = png::decode(LOGO).unwrap();
+    Icon::from_rgba(logo.pixels.concat(), logo.width, logo.height).unwrap()
+}
+
+/// A thumbnail of the center of the current picture. Returns `None`, while
+/// the picture is black, e.g. before the game has drawn its first frame.
+pub fn game_icon<B: AudioBackend, FB: FrameBuffer>(device: &Device<B, FB>) -> Option<Icon> {
+    let (width, height, pixels) = screenshot::picture(device);
+    if pixels.iter().all(|&[r, g, b, _]| r | g | b == 0) {
+        return None;
+    }
+    // a square of whole blocks of pixels averaged into one pixel each
+    let block = height / SIZE;
+    let [left, top] = [width, height].map(|len| (len - block * SIZE) / 2);
+    let mut rgba = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let mut sum = [0u32; 3];
+            for dy in 0..block {
+                for dx in 0..block {
+                    let pos = (top + y * block + dy) * width + left + x * block + dx;
+                    let pixel = pixels[pos as usize];
+                    for (sum, component) in sum.iter_mut().zip(pixel) {
+                        *sum += u32::from(component)
+                    }
+                }
+            }
+            let [r, g, b] = sum.map(|sum| (sum / (block * block)) as u8);
+            rgba.extend([r, g, b, 0xff]);
+        }
+    }
+    Icon::from_rgba(rgba, SIZE, SIZE).ok()
+}
@@ -0,0 +1,221 @@
+//! Decompression of deflate streams (RFC 1951)
+//!
+//! Used for the image data of PNG files and the entries of ZIP archives.
+//! Errors are short descriptions of what is wrong with the stream.
+
+/// The default limit of the decompressed size. Far larger than any ROM,
+/// movie or image, but it stops decompression bombs before they fill the
+/// memory.
+pub const MAX_SIZE: usize = 64 << 20;
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> Result<u32, &'static str> {
+        let mut val = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos).ok_or("truncated data")?;
+            val |= u32::from((byte >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(val)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code
+struct Huffman {
+    /// The count of codes of every length
+    counts: [u16; 16],
+    /// The symbols ordered by their codes
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len > 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, &'static str> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid huffman code")
+    }
+}
+
+static LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+static LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+static DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+static DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+static CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lengths: &Huffman,
+    dists: &Huffman,
+) -> Result<(), &'static str> {
+    loop {
+        if out.len() > limit {
+            return Err("decompressed data too large");
+        }
+        let symbol = usize::from(lengths.decode(reader)?);
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let i = symbol - 257;
+                let len = usize::from(LENGTH_BASE[i]) + reader.bits(LENGTH_EXTRA[i])? as usize;
+                let i = usize::from(dists.decode(reader)?);
+                if i >= 30 {
+                    return Err("invalid distance");
+                }
+                let dist = usize::from(DIST_BASE[i]) + reader.bits(DIST_EXTRA[i])? as usize;
+                if dist > out.len() {
+                    return Err("invalid distance");
+                }
+                for _ in 0..len {
+                    out.push(out[out.len() - dist]);
+                }
+            }
+            _ => return Err("invalid length"),
+        }
+    }
+}
+
+/// Decompress a raw deflate stream of at most `limit` decompressed bytes.
+/// Returns the data and the count of bytes of `data` the stream took, so
+/// the caller can read a trailer after it.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize), &'static str> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = vec![];
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader
+                    .data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or("truncated data")?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if u16::from_le_bytes([header[2], header[3]]) != !len {
+                    return Err("invalid stored block length");
+                }
+                let len = usize::from(len);
+                let block = reader
+                    .data
+                    .get(reader.pos + 4..reader.pos + 4 + len)
+                    .ok_or("truncated data")?;
+                out.extend_from_slice(block);
+                reader.pos += 4 + len;
+            }
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                // the distance codes 30 and 31 are part of the code, but invalid
+                let (lengths, dists) = (Huffman::new(&lengths), Huffman::new(&[5; 32]));
+                inflate_block(&mut reader, &mut out, limit, &lengths, &dists)?
+            }
+            2 => {
+                let literals = reader.bits(5)? as usize + 257;
+                let dists = reader.bits(5)? as usize + 1;
+                let codes = reader.bits(4)? as usize + 4;
+                let mut code_lengths = [0; 19];
+                for &i in &CODE_LENGTH_ORDER[..codes] {
+                    code_lengths[i] = reader.bits(3)? as u8;
+                }
+                let code_lengths = Huffman::new(&code_lengths);
+                let mut lengths = Vec::with_capacity(literals + dists);
+                while lengths.len() < literals + dists {
+                    let (len, repeat) = match code_lengths.decode(&mut reader)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => (
+                            *lengths.last().ok_or("invalid code lengths")?,
+                            3 + reader.bits(2)?,
+                        ),
+                        17 => (0, 3 + reader.bits(3)?),
+                        _ => (0, 11 + reader.bits(7)?),
+                    };
+                    lengths.extend((0..repeat).map(|_| len));
+                }
+                if lengths.len() > literals + dists {
+                    return Err("invalid code lengths");
+                }
+                let (lengths, dists) = lengths.split_at(literals);
+                let (lengths, dists) = (Huffman::new(lengths), Huffman::new(dists));
+                inflate_block(&mut reader, &mut out, limit, &lengths, &dists)?
+            }
+            _ => return Err("invalid block type"),
+        }
+        if out.len() > limit {
+            return Err("decompressed data too large");
+        }
+        if last {
+            break;
+        }
+    }
+    reader.align();
+    Ok((out, reader.pos))
+}
+
+#[cfg(test)]
+mod tests;
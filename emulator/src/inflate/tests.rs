@@ -0,0 +1,74 @@
+use super::*;
+
+/// The text of the dynamic block
+fn squares() -> Vec<u8> {
+    (0..30u32)
+        .flat_map(|i| format!("{},", i * i % 97).into_bytes())
+        .collect()
+}
+
+#[test]
+pub fn test_stored_block() {
+    let mut data = vec![0x01, 0x05, 0x00, 0xfa, 0xff];
+    data.extend_from_slice(b"rsnes");
+    // the trailer isn't part of the stream
+    data.extend_from_slice(&[0x12, 0x34]);
+    assert_eq!(inflate(&data, MAX_SIZE), Ok((b"rsnes".to_vec(), 10)));
+    data[3] = 0;
+    assert_eq!(inflate(&data, MAX_SIZE), Err("invalid stored block length"));
+}
+
+#[test]
+pub fn test_fixed_block() {
+    let data = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+    assert_eq!(inflate(&data, MAX_SIZE), Ok((b"abcabcabcabc".to_vec(), 7)));
+    // "a" repeated at the distance 1
+    let data = [0x4b, 0x04, 0x02, 0x00];
+    assert_eq!(inflate(&data, MAX_SIZE), Ok((b"aaaa".to_vec(), 4)));
+}
+
+#[test]
+pub fn test_dynamic_block() {
+    let data = [
+        0x0d, 0x8c, 0xb9, 0x01, 0x00, 0x30, 0x08, 0x84, 0x16, 0xa2, 0xf0, 0x39, 0x35, 0xee, 0xbf,
+        0x58, 0xec, 0x28, 0x00, 0xc3, 0x11, 0x8b, 0x37, 0x51, 0x64, 0xa3, 0xa5, 0xc5, 0x73, 0x92,
+        0x10, 0x1a, 0x26, 0x08, 0xd2, 0xe9, 0x60, 0xcf, 0x48, 0xc6, 0xf0, 0xa0, 0x92, 0x3d, 0xfb,
+        0xda, 0x1b, 0x1c, 0x8b, 0x32, 0x1e, 0x5d, 0x7c,
+    ];
+    assert_eq!(inflate(&data, MAX_SIZE), Ok((squares(), data.len())));
+}
+
+#[test]
+pub fn test_bad_distance() {
+    // "a" repeated at the distance 2
+    let data = [0x4b, 0x04, 0x42, 0x00];
+    assert_eq!(inflate(&data, MAX_SIZE), Err("invalid distance"));
+    // the distance codes 30 and 31 don't exist
+    let data = [0x4b, 0x04, 0x3e, 0x00];
+    assert_eq!(inflate(&data, MAX_SIZE), Err("invalid distance"));
+}
+
+#[test]
+pub fn test_truncated() {
+    let data = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+    for len in 0..data.len() - 1 {
+        assert_eq!(inflate(&data[..len], MAX_SIZE), Err("truncated data"));
+    }
+    let data = [0x01, 0x05, 0x00, 0xfa, 0xff, b'r', b's'];
+    assert_eq!(inflate(&data, MAX_SIZE), Err("truncated data"));
+}
+
+#[test]
+pub fn test_invalid_block_type() {
+    assert_eq!(inflate(&[0x07], MAX_SIZE), Err("invalid block type"));
+}
+
+#[test]
+pub fn test_limit() {
+    let data = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+    assert_eq!(inflate(&data, 12).map(|(out, _)| out.len()), Ok(12));
+    assert_eq!(inflate(&data, 8), Err("decompressed data too large"));
+    let mut data = vec![0x01, 0x05, 0x00, 0xfa, 0xff];
+    data.extend_from_slice(b"rsnes");
+    assert_eq!(inflate(&data, 4), Err("decompressed data too large"));
+}
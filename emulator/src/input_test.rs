@@ -0,0 +1,179 @@
+//! The controller test screen (input test key, or `--test-input`)
+//!
+//! The screen shows the controllers of both ports with the buttons currently
+//! held and the last raw events received from the keyboard and the mouse,
+//! with the scancodes and the buttons they are mapped to. So the mappings of
+//! a profile can be checked, including keys, which aren't mapped at all.
+//! While the screen is open, the emulation is paused.
+
+use crate::config::button_names;
+use crate::menu::{draw_text, CELL_SIZE, SELECTED_COLOR, TEXT_COLOR};
+use rsnes::controller::{buttons, Controller, ControllerPorts};
+use std::collections::VecDeque;
+
+/// The count of events shown
+const EVENT_COUNT: usize = 8;
+
+const RELEASED_COLOR: [u8; 3] = [0x40; 3];
+const LABEL_COLOR: [u8; 3] = [0x10; 3];
+
+/// The buttons of a standard controller, with their positions and sizes in
+/// pixels relative to the top left corner of the pad and their labels
+static PAD: [(u16, [usize; 4], &str); 12] = [
+    (buttons::L, [8, 0, 24, 9], "L"),
+    (buttons::R, [88, 0, 24, 9], "R"),
+    (buttons::UP, [18, 18, 10, 10], ""),
+    (buttons::LEFT, [8, 28, 10, 10], ""),
+    (buttons::RIGHT, [28, 28, 10, 10], ""),
+    (buttons::DOWN, [18, 38, 10, 10], ""),
+    (buttons::SELECT, [44, 34, 12, 6], ""),
+    (buttons::START, [60, 34, 12, 6], ""),
+    (buttons::X, [90, 16, 11, 11], "X"),
+    (buttons::Y, [78, 28, 11, 11], "Y"),
+    (buttons::A, [102, 28, 11, 11], "A"),
+    (buttons::B, [90, 40, 11, 11], "B"),
+];
+
+#[derive(Default)]
+pub struct InputTest {
+    /// The last events, the newest one last
+    events: VecDeque<String>,
+}
+
+impl InputTest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, event: String) {
+        if self.events.len() == EVENT_COUNT {
+            self.events.pop_front();
+        }
+        self.events.push_back(event)
+    }
+
+    /// Show a key event. `mapped` is the port and the button of a standard
+    /// controller, to which the key is mapped.
+    pub fn key(&mut self, scancode: u32, pressed: bool, mapped: Option<(usize, u16)>) {
+        let target = match mapped {
+            Some((port, button)) => {
+                let name = button_names(button).concat().to_uppercase();
+                format!("PORT {} {name}", port + 1)
+            }
+            None => "NOT MAPPED".to_owned(),
+        };
+        let state = if pressed { "DOWN" } else { "UP" };
+        self.push(format!("KEY 0x{scancode:02X} {state}: {target}"))
+    }
+
+    /// Show a mouse button event
+    pub fn mouse_button(&mut self, button: winit::event::MouseButton, pressed: bool) {
+        use winit::event::MouseButton;
+        let name = match button {
+            MouseButton::Left => "LEFT".to_owned(),
+            MouseButton::Right => "RIGHT".to_owned(),
+            MouseButton::Middle => "MIDDLE".to_owned(),
+            MouseButton::Other(id) => id.to_string(),
+        };
+        let state = if pressed { "DOWN" } else { "UP" };
+        self.push(format!("MOUSE {name} {state}"))
+    }
+
+    /// Draw the screen over a picture of RGBA pixels
+    pub fn draw(
+        &self,
+        picture: &[u8],
+        width: u32,
+        height: u32,
+        controllers: &ControllerPorts,
+    ) -> Vec<u8> {
+        let (width, height) = (width as usize, height as usize);
+        // the picture of HD Mode 7 is scaled up, the screen is scaled with it
+        let scale = (width / rsnes::ppu::SCREEN_WIDTH as usize).max(1);
+        // darken the picture, so the text is readable on every picture
+        let mut out = picture.to_vec();
+        for pixel in out.chunks_exact_mut(4) {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+            pixel.copy_from_slice(&[r / 4, g / 4, b / 4, 0xff]);
+        }
+        let text = |out: &mut [u8], [x, y]: [usize; 2], line: &str, color| {
+            draw_text(out, width, [x * scale, y * scale], scale, line, color)
+        };
+        let button = |out: &mut [u8], [x, y, w, h]: [usize; 4], pressed: bool| {
+            let color = if pressed {
+                SELECTED_COLOR
+            } else {
+                RELEASED_COLOR
+            };
+            fill(out, width, height, scale, [x, y, w, h], color);
+            // the label is centered in the button
+            let label_color = if pressed { LABEL_COLOR } else { TEXT_COLOR };
+            (
+                [x + w.saturating_sub(5) / 2, y + h.saturating_sub(7) / 2],
+                label_color,
+            )
+        };
+        text(&mut out, [8, 6], "INPUT TEST", SELECTED_COLOR);
+        for (i, port) in [&controllers.port1, &controllers.port2]
+            .into_iter()
+            .enumerate()
+        {
+            let [left, top] = [8 + i * 128, 40];
+            let kind = match &port.controller {
+                Controller::None => "NONE",
+                Controller::Standard(_) => "STANDARD",
+                Controller::Mouse(_) => "MOUSE",
+            };
+            text(
+                &mut out,
+                [left, 24],
+                &format!("PORT {}: {kind}", i + 1),
+                TEXT_COLOR,
+            );
+            match &port.controller {
+                Controller::None => (),
+                Controller::Standard(cntrl) => {
+                    for (mask, [x, y, w, h], label) in PAD {
+                        let pressed = cntrl.pressed_buttons & mask > 0;
+                        let (pos, color) = button(&mut out, [left + x, top + y, w, h], pressed);
+                        text(&mut out, pos, label, color);
+                    }
+                }
+                Controller::Mouse(mouse) => {
+                    for (pressed, x, label) in
+                        [(mouse.left_button, 8, "L"), (mouse.right_button, 56, "R")]
+                    {
+                        let (pos, color) = button(&mut out, [left + x, top, 40, 40], pressed);
+                        text(&mut out, pos, label, color);
+                    }
+                    let [x, y] = mouse.internal_offset;
+                    text(&mut out, [left, 94], &format!("X: {x} Y: {y}"), TEXT_COLOR);
+                }
+            }
+        }
+        text(&mut out, [8, 112], "EVENTS", SELECTED_COLOR);
+        for (row, event) in self.events.iter().enumerate() {
+            text(&mut out, [8, 126 + row * CELL_SIZE[1]], event, TEXT_COLOR);
+        }
+        out
+    }
+}
+
+/// Fill the rectangle `x`, `y`, `w`, `h` of a picture of RGBA pixels, given
+/// in pixels of the console, which are `scale` pixels of the picture wide
+fn fill(
+    out: &mut [u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+    [x, y, w, h]: [usize; 4],
+    color: [u8; 3],
+) {
+    let columns = (x * scale).min(width)..((x + w) * scale).min(width);
+    for py in (y * scale).min(height)..((y + h) * scale).min(height) {
+        let row = &mut out[(py * width + columns.start) * 4..(py * width + columns.end) * 4];
+        for pixel in row.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[color[0], color[1], color[2], 0xff]);
+        }
+    }
+}
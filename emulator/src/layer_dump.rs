@@ -0,0 +1,110 @@
+//! Frame dumps of the separated layers (`--dump-layers`)
+//!
+//! Every frame of the range selected with `--dump-frames` is written as one
+//! PNG file per layer of the PPU (BG1 to BG4, the sprites and the backdrop),
+//! e.g. `<DIR>/bg1/000120.png`, so each layer is a sequence of images, which
+//! video editors import as a clip. Pixels, which a layer doesn't cover, are
+//! transparent. The frames are counted from the start of the emulation after
+//! the frames skipped with `--skip-frames`. The pictures are as large as the
+//! picture of the PPU, 256×224 or 256×239 pixels with overscan.
+
+use crate::png;
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    ppu::{LayerFrames, LAYER_NAMES, MAX_SCREEN_HEIGHT, MAX_SCREEN_HEIGHT_OVERSCAN, SCREEN_WIDTH},
+};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+pub struct LayerDump {
+    dir: PathBuf,
+    frames: RangeInclusive<u64>,
+    /// The frame emulated next
+    frame: u64,
+    done: bool,
+}
+
+/// Parse a range of frames like `120-300` or a single frame like `120`
+pub fn parse_range(range: &str) -> Option<RangeInclusive<u64>> {
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let (first, last) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    (first <= last).then_some(first..=last)
+}
+
+impl LayerDump {
+    /// Dump the layers of `frames` into `dir`. Capturing starts with the
+    /// next frame of `device`, if it's the first frame of the range.
+    pub fn new<B: AudioBackend, FB: FrameBuffer>(
+        device: &mut Device<B, FB>,
+        dir: PathBuf,
+        frames: RangeInclusive<u64>,
+    ) -> std::io::Result<Self> {
+        for name in LAYER_NAMES {
+            std::fs::create_dir_all(dir.join(name))?;
+        }
+        let dump = Self {
+            dir,
+            frames,
+            frame: 0,
+            done: false,
+        };
+        dump.prepare(device);
+        Ok(dump)
+    }
+
+    /// Draw the layers of the next frame separately, if it's in the range
+    fn prepare<B: AudioBackend, FB: FrameBuffer>(&self, device: &mut Device<B, FB>) {
+        let ppu = &mut device.ppu;
+        if self.frames.contains(&self.frame) {
+            ppu.layer_frames.get_or_insert_with(Box::default);
+        } else {
+            ppu.layer_frames = None;
+        }
+    }
+
+    /// Write the layers of a captured frame. Must be called after every frame.
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        if self.done {
+            return;
+        }
+        if let Some(frames) = &device.ppu.layer_frames {
+            let height = if device.ppu.is_overscan() {
+                MAX_SCREEN_HEIGHT_OVERSCAN
+            } else {
+                MAX_SCREEN_HEIGHT
+            };
+            if let Err(err) = self.write(frames, height) {
+                eprintln!(
+                    "[warning] could not write the layers of frame {} into \"{}\" ({err})",
+                    self.frame,
+                    self.dir.display()
+                );
+                self.done = true;
+            }
+        }
+        self.frame += 1;
+        if self.frame > *self.frames.end() && !self.done {
+            println!(
+                "[info] dumped the layers of {} frames into \"{}\"",
+                self.frames.end() - self.frames.start() + 1,
+                self.dir.display()
+            );
+            self.done = true;
+        }
+        if self.done {
+            device.ppu.layer_frames = None;
+        } else {
+            self.prepare(device);
+        }
+    }
+
+    fn write(&self, frames: &LayerFrames, height: u32) -> std::io::Result<()> {
+        for (name, pixels) in LAYER_NAMES.iter().zip(&frames.layers) {
+            let path = self.dir.join(name).join(format!("{:06}.png", self.frame));
+            let pixels = &pixels[..(SCREEN_WIDTH * height) as usize];
+            png::write(path, SCREEN_WIDTH, height, pixels)?;
+        }
+        Ok(())
+    }
+}
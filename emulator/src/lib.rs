@@ -0,0 +1,2915 @@
+//! The emulator and the parts of it, which other applications can embed
+//!
+//! [`run`] is the whole emulator, shared by the binary and the Android app,
+//! which only differ in their entry points. [`renderer`] draws the picture of
+//! the console into any wgpu texture, so the emulator can be shown e.g. as a
+//! widget of an egui application.
+
+mod address_map;
+#[cfg(target_os = "android")]
+mod android;
+mod archive;
+mod autosave;
+mod bench;
+mod cheats;
+mod compare;
+mod config;
+mod corruptor;
+mod debugger;
+mod firmware;
+mod frontend;
+mod gamepad;
+mod icon;
+mod inflate;
+mod input_test;
+mod layer_dump;
+mod menu;
+mod movie;
+mod movie_import;
+mod music;
+mod netplay;
+mod notes;
+mod palette;
+mod png;
+mod practice;
+mod project;
+mod quick_resume;
+mod recorder;
+pub mod renderer;
+mod resampler;
+mod scope;
+mod screenshot;
+mod srm;
+mod state_container;
+mod state_version;
+mod state_worker;
+mod states;
+mod stats;
+mod stream;
+mod tas;
+mod terminal;
+mod texture_pack;
+mod timer;
+mod touch;
+mod zip;
+
+use clap::{ErrorKind, Parser};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Sample,
+};
+use netplay::Netplay;
+use pollster::FutureExt;
+use renderer::Renderer;
+use rsnes::{backend::ArrayFrameBuffer, device::Device, run_ahead::RunAhead, spc700::StereoSample};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use winit::{
+    event::{DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    window::{Fullscreen, Window, WindowBuilder},
+};
+
+const MASTER_CYCLES_PER_TICK: u16 = 2;
+/// The maximum count of frames skipped by `--skip-blank` (one minute of NTSC frames)
+const MAX_BLANK_FRAMES: u32 = 3600;
+
+#[derive(Parser, Clone)]
+#[clap(
+    version = clap::crate_version!(),
+)]
+struct Options {
+    /// Game cartridge file to load (e.g. *.sfc and *.smc files)
+    #[clap(
+        parse(from_os_str),
+        required_unless_present_any =
+            &["stats", "check-config", "write-default-config", "test-input"]
+    )]
+    input: Option<PathBuf>,
+
+    /// Render Mode 7 at SCALE times the resolution (1 to 4). This is also
+    /// the resolution of `--texture-pack`, where it defaults to 4
+    #[clap(long, value_name = "SCALE")]
+    hd_mode7: Option<u8>,
+
+    /// Replace tiles with the PNG images of a texture pack directory
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    texture_pack: Option<PathBuf>,
+
+    /// Export the original of every drawn tile as PNG image into DIR,
+    /// as a template for texture packs
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    texture_dump: Option<PathBuf>,
+
+    /// Replace the music with the tracks of a music pack directory,
+    /// overriding the game's configuration
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    music_pack: Option<PathBuf>,
+
+    /// Maximum memory in MiB used for the images of `--texture-pack`
+    #[clap(long, value_name = "MIB", default_value_t = 256)]
+    texture_cache_size: usize,
+
+    /// Experimental: render PIXELS additional pixels on both sides of the
+    /// picture (at most 128), overriding the game's configuration
+    #[clap(long, value_name = "PIXELS")]
+    widescreen: Option<u8>,
+
+    /// The backgrounds extended by `--widescreen`, e.g. `1,2`
+    #[clap(long, value_name = "BGS", use_value_delimiter = true)]
+    widescreen_bgs: Vec<u8>,
+
+    /// Keep the save data in a flash chip instead of SRAM, as battery-less
+    /// flash carts do, overriding the game's configuration
+    #[clap(long)]
+    flash_save: bool,
+
+    /// Disable the compatibility hacks of the game, which are applied
+    /// depending on the CRC-32 of its ROM
+    #[clap(long)]
+    no_hacks: bool,
+
+    /// Add a Game Genie (`XXXX-XXXX`) or Pro Action Replay (`AAAAAAVV`) code
+    /// to the cheats of the game, which are kept in a file per game. Can be
+    /// given multiple times.
+    #[clap(long, value_name = "CODE")]
+    cheat: Vec<String>,
+
+    /// Apply an IPS, BPS or UPS patch to the ROM in memory, the ROM file is
+    /// left untouched. By default, a patch next to the ROM with its name and
+    /// the extension `.bps`, `.ups` or `.ips` is applied.
+    #[clap(long, parse(from_os_str), value_name = "PATH")]
+    patch: Option<PathBuf>,
+
+    /// The file loaded from a ZIP archive, by default the first one with a
+    /// ROM extension
+    #[clap(long, value_name = "NAME")]
+    archive_entry: Option<String>,
+
+    /// Run at PERCENT of the original speed
+    #[clap(long, value_name = "PERCENT", default_value_t = 100.0)]
+    speed: f64,
+
+    /// Don't output any audio, e.g. on systems without a working sound device
+    #[clap(long)]
+    no_audio: bool,
+
+    /// Don't open a window and pace the emulation by the audio output.
+    /// Hotkeys are entered on the terminal, e.g. to listen to the music of
+    /// a game or to run it on a server.
+    #[clap(long)]
+    no_video: bool,
+
+    /// Run the emulation as fast as possible without window, audio output
+    /// and hotkeys, e.g. for automated tests or scripts
+    #[clap(long, conflicts_with = "no-video")]
+    headless: bool,
+
+    /// Exit after emulating N frames (with `--headless`)
+    #[clap(long, value_name = "N", requires = "headless")]
+    frames: Option<u64>,
+
+    /// Emulate FRAMES frames uncapped and report the emulated frames per
+    /// second, the time spent in the components and the host CPU usage
+    #[clap(long, value_name = "FRAMES", conflicts_with_all = &["no-video", "headless"])]
+    bench: Option<u64>,
+
+    /// Stream the frames to viewers connecting to ADDR, e.g. `0.0.0.0:8080`.
+    /// Browsers show the stream when opening `http://<host>:8080`.
+    #[clap(long, value_name = "ADDR")]
+    stream: Option<String>,
+
+    /// Stream the frames as raw RGBA pixels instead of over HTTP
+    #[clap(long, requires = "stream")]
+    raw_stream: bool,
+
+    /// Write the layers of the PPU as separate sequences of transparent PNG
+    /// images into DIR, e.g. to edit them in a video editor
+    #[clap(long, parse(from_os_str), value_name = "DIR", requires = "dump-frames")]
+    dump_layers: Option<PathBuf>,
+
+    /// The frames dumped by `--dump-layers`, e.g. `120-300` or `120`
+    #[clap(long, value_name = "FIRST-LAST", requires = "dump-layers")]
+    dump_frames: Option<String>,
+
+    /// Synchronize the emulation to the audio output instead of the system
+    /// clock; avoids crackling, but the frame rate follows the sound card.
+    /// Same as `sync-mode = "audio"` in the profile.
+    #[clap(long)]
+    audio_sync: bool,
+
+    /// Present only frames with a new picture and sleep until the next frame
+    /// is due, instead of redrawing the window at 120 Hz.
+    /// Same as `capped-present = true` in the profile.
+    #[clap(long)]
+    capped_present: bool,
+
+    /// Fast-forward the first N frames at maximum speed
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    skip_frames: u32,
+
+    /// Fast-forward until the first frame, that is not completely black
+    /// (after `--skip-frames`)
+    #[clap(long)]
+    skip_blank: bool,
+
+    /// Enable the practice mode: `Shift` + `F11` sets an anchor state,
+    /// `F11` reloads it
+    #[clap(long)]
+    practice: bool,
+
+    /// Load the practice anchor from a state file (implies `--practice`)
+    #[clap(long, parse(from_os_str))]
+    practice_state: Option<PathBuf>,
+
+    /// Run up to FRAMES random frames without input after reloading
+    /// the practice anchor
+    #[clap(long, value_name = "FRAMES", default_value_t = 0)]
+    practice_delay: u32,
+
+    /// Print the playtime statistics of all played games and exit
+    #[clap(long)]
+    stats: bool,
+
+    /// Print which features of the cartridge are emulated and exit
+    #[clap(long)]
+    compat_report: bool,
+
+    /// Check the configuration file for errors and unknown keys and exit
+    #[clap(long)]
+    check_config: bool,
+
+    /// Write a commented configuration file with all default options to
+    /// PATH (`$HOME/.config/rsnes/config.toml` by default) and exit. An
+    /// existing file is never overwritten.
+    #[clap(long, value_name = "PATH")]
+    write_default_config: Option<Option<PathBuf>>,
+
+    /// Open the controller test screen, which shows the buttons held on both
+    /// ports and the raw keyboard and mouse events, to check the mappings of
+    /// the profile. Without INPUT, no game is started.
+    #[clap(long, conflicts_with_all = &["no-video", "headless", "bench"])]
+    test_input: bool,
+
+    /// Run the ROM HACK in lockstep with INPUT, both with the same input, and
+    /// show their pictures side by side with the different pixels highlighted
+    #[clap(
+        long,
+        value_name = "HACK",
+        parse(from_os_str),
+        conflicts_with_all = &[
+            "no-video", "headless", "bench", "test-input", "play-movie",
+            "record-movie", "netplay-host", "netplay-join",
+        ]
+    )]
+    compare: Option<PathBuf>,
+
+    /// Print extra information that may spam your stdout
+    #[clap(short, long)]
+    verbose: bool,
+
+    /// Use a provided configuration file
+    #[clap(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Use a specified profile of your configuration instead of the default
+    /// or the automatically selected profile
+    #[clap(short, long)]
+    profile: Option<String>,
+
+    /// Open the interactive debugger console on stdin
+    #[clap(short, long)]
+    debug: bool,
+
+    /// Run debugger commands from a script file at startup (implies `--debug`)
+    #[clap(long, parse(from_os_str))]
+    debug_script: Option<PathBuf>,
+
+    /// Enable the memory corruptor for glitch hunting (implies `--debug`).
+    /// Corruptions may crash the game or damage its save data
+    #[clap(long)]
+    corruptor: bool,
+
+    /// Run embedded into a launcher: exit when stdin is closed, accept simple
+    /// commands on stdin and print status lines to stdout
+    #[clap(long)]
+    frontend_mode: bool,
+
+    /// Refuse memory writes, slowdown, run-ahead of more than two frames and
+    /// loading save states, e.g. for hardcore achievements or verified races
+    #[clap(long)]
+    integrity_mode: bool,
+
+    /// Emulate FRAMES frames ahead of the shown picture after every frame,
+    /// hiding the input lag of the game
+    #[clap(
+        long,
+        value_name = "FRAMES",
+        conflicts_with_all = &[
+            "debug", "debug-script", "corruptor", "play-movie", "record-movie",
+            "netplay-host", "netplay-join",
+        ]
+    )]
+    run_ahead: Option<u32>,
+
+    /// Keep the save state slots of every game in a container file in
+    /// `$HOME/.config/rsnes/states`, so they persist between sessions
+    #[clap(long)]
+    state_container: bool,
+
+    /// Keep every save state slot in a state file in DIR, e.g.
+    /// `game.state0` for `game.sfc`
+    #[clap(long, parse(from_os_str), value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Keep the battery backed save memory in FILE instead of
+    /// `$HOME/.config/rsnes/saves/<crc32 of the ROM>.srm`
+    #[clap(long, parse(from_os_str), value_name = "FILE")]
+    srm: Option<PathBuf>,
+
+    /// Don't load or write the battery backed save memory
+    #[clap(long, conflicts_with = "srm")]
+    no_srm: bool,
+
+    /// Write a state file every MINUTES minutes of play into
+    /// `$HOME/.config/rsnes/autosave`
+    #[clap(long, value_name = "MINUTES")]
+    autosave: Option<u32>,
+
+    /// The count of state files kept by `--autosave`
+    #[clap(long, value_name = "N", default_value_t = 5)]
+    autosave_count: usize,
+
+    /// Maximum memory in MiB used for the save states of movie editing
+    #[clap(long, default_value_t = 256)]
+    greenzone_size: usize,
+
+    /// Play back an input movie from power-on or its anchor state. Movies of
+    /// lsnes (.lsmv) and BizHawk (.bk2) are imported. Without `--frames`,
+    /// `--headless` exits at the end of the movie
+    #[clap(
+        long,
+        value_name = "PATH",
+        parse(from_os_str),
+        conflicts_with = "record-movie"
+    )]
+    play_movie: Option<PathBuf>,
+
+    /// Record an input movie from power-on, which is saved when the emulator
+    /// exits
+    #[clap(long, value_name = "PATH", parse(from_os_str))]
+    record_movie: Option<PathBuf>,
+
+    /// Start the recorded movie from a state file instead of power-on
+    #[clap(
+        long,
+        value_name = "STATE",
+        parse(from_os_str),
+        requires = "record-movie"
+    )]
+    movie_anchor: Option<PathBuf>,
+
+    /// Host a netplay session on ADDR, e.g. `0.0.0.0:7845`, and wait for a
+    /// guest. The host plays with the first controller port
+    #[clap(long, value_name = "ADDR", conflicts_with = "netplay-join")]
+    netplay_host: Option<String>,
+
+    /// Join the netplay session of the host at ADDR, e.g. `example.com:7845`.
+    /// The guest plays with the second controller port
+    #[clap(long, value_name = "ADDR")]
+    netplay_join: Option<String>,
+
+    /// The input delay of a hosted netplay session in frames. Without
+    /// rollback, it should be longer than the round trip time to the guest
+    #[clap(long, value_name = "FRAMES", default_value_t = 2)]
+    netplay_delay: u8,
+
+    /// Run up to FRAMES frames of a hosted netplay session ahead of the
+    /// input of the peer, predicting it, and roll back mispredicted frames.
+    /// 0 waits for the input of the peer instead
+    #[clap(long, value_name = "FRAMES", default_value_t = 0)]
+    netplay_rollback: u8,
+
+    /// Open the pause menu with an item to continue the game from its quick
+    /// resume file, if it has one. The emulator is restarted with this
+    /// option, when a ROM is dropped on the window.
+    #[clap(long)]
+    offer_resume: bool,
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {
+        clap::command!().error(ErrorKind::Io, format_args!($($arg)*)).exit()
+    };
+}
+
+/// The arguments to restart the emulator with `rom`, when it's dropped on the
+/// window. Only the options, which don't depend on the game, are kept.
+fn switch_args(options: &Options, rom: &Path) -> Vec<OsString> {
+    let mut args = vec![rom.as_os_str().to_owned(), "--offer-resume".into()];
+    let flags = [
+        (options.verbose, "--verbose"),
+        (options.no_audio, "--no-audio"),
+        (options.audio_sync, "--audio-sync"),
+        (options.capped_present, "--capped-present"),
+        (options.debug, "--debug"),
+        (options.integrity_mode, "--integrity-mode"),
+        (options.state_container, "--state-container"),
+        (options.no_srm, "--no-srm"),
+    ];
+    args.extend(
+        flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, flag)| flag.into()),
+    );
+    if let Some(config) = &options.config {
+        args.extend(["--config".into(), config.as_os_str().to_owned()]);
+    }
+    if let Some(profile) = &options.profile {
+        args.extend(["--profile".into(), profile.into()]);
+    }
+    if let Some(dir) = &options.state_dir {
+        args.extend(["--state-dir".into(), dir.as_os_str().to_owned()]);
+    }
+    if let Some(minutes) = options.autosave {
+        args.extend(["--autosave".into(), minutes.to_string().into()]);
+        args.extend([
+            "--autosave-count".into(),
+            options.autosave_count.to_string().into(),
+        ]);
+    }
+    if let Some(frames) = options.run_ahead {
+        args.extend(["--run-ahead".into(), frames.to_string().into()]);
+    }
+    args.extend(["--speed".into(), options.speed.to_string().into()]);
+    args
+}
+
+/// Emulate the device until the next frame begins.
+/// Returns the count of emulated master cycles.
+fn run_frame<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+) -> u64 {
+    let start = snes.stats().master_cycles;
+    snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    while !snes.new_frame {
+        snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    }
+    snes.stats().master_cycles - start
+}
+
+/// Like [`run_frame`], but stop early, when a breakpoint or watchpoint of the
+/// debugger is hit, so `snes.new_frame` isn't set
+fn run_frame_until_break<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+) -> u64 {
+    let start = snes.stats().master_cycles;
+    let stopped = |snes: &Device<B, FB>| {
+        snes.debugger
+            .as_ref()
+            .is_some_and(|debugger| debugger.break_reason().is_some())
+    };
+    snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    while !snes.new_frame && !stopped(snes) {
+        snes.run_cycle::<MASTER_CYCLES_PER_TICK>();
+    }
+    snes.stats().master_cycles - start
+}
+
+/// Open the pause menu. The buttons held on port 1 are released, because the
+/// menu takes them, until it's closed.
+fn open_menu<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+    slot: usize,
+) -> menu::Menu {
+    if let rsnes::controller::Controller::Standard(cntrl) = &mut snes.controllers.port1.controller {
+        cntrl.pressed_buttons = 0
+    }
+    menu::Menu::new(slot, snes.cheats().len())
+}
+
+/// Write the save memory and the statistics before the emulator exits
+fn save_on_exit<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+    srm_file: Option<&mut srm::SrmFile>,
+    stats: &mut stats::Stats,
+    rom_id: &str,
+    session_time: Duration,
+    print_playtime: bool,
+) {
+    if let (Some(srm_file), Some(cartridge)) = (srm_file, snes.cartridge_mut()) {
+        srm_file.flush(cartridge)
+    }
+    stats.add_playtime(rom_id, session_time);
+    if let Some(rom) = stats.roms.get(rom_id).filter(|_| print_playtime) {
+        println!(
+            "[info] played for {}, {} in total",
+            stats::format_duration(session_time),
+            stats::format_duration(rom.playtime)
+        );
+    }
+    if let Err(err) = stats.save() {
+        eprintln!("[warning] could not save the statistics file ({err})")
+    }
+}
+
+/// Save the movie recorded with `--record-movie`
+fn save_recorded_movie(tas: &mut tas::Tas, path: Option<&PathBuf>) {
+    let Some(path) = path else { return };
+    match tas.save_movie(path) {
+        Ok(()) => println!(
+            "[info] saved the movie \"{}\" ({} frames)",
+            path.display(),
+            tas.movie.as_ref().map_or(0, |movie| movie.frames.len())
+        ),
+        Err(err) => eprintln!(
+            "[warning] could not save the movie \"{}\" ({err})",
+            path.display()
+        ),
+    }
+}
+
+/// Mute a DSP voice (0 to 7) or unmute it, if it is muted
+fn toggle_voice_muted<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+    voice: u8,
+) {
+    let muted = snes.smp.user_muted_voices() & (1 << voice) == 0;
+    snes.smp.set_voice_muted(voice, muted);
+    println!(
+        "[info] {} voice {voice}",
+        if muted { "muted" } else { "unmuted" }
+    )
+}
+
+/// Output only a DSP voice (0 to 7), or all voices again, if it is soloed
+fn toggle_solo_voice<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+    voice: u8,
+) {
+    if snes.smp.solo_voice() == Some(voice) {
+        snes.smp.set_solo_voice(None);
+        println!("[info] ended solo of voice {voice}")
+    } else {
+        snes.smp.set_solo_voice(Some(voice));
+        println!("[info] solo voice {voice}")
+    }
+}
+
+/// Unmute all DSP voices and end a solo
+fn reset_voice_muting<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+) {
+    for voice in 0..8 {
+        snes.smp.set_voice_muted(voice, false)
+    }
+    snes.smp.set_solo_voice(None);
+    println!("[info] all voices unmuted")
+}
+
+/// Stop a recording and report where it was saved
+fn finish_recording(recorder: recorder::Recorder) {
+    println!("[info] finishing the recording");
+    match recorder.finish() {
+        Ok(path) => println!("[info] saved recording \"{}\"", path.display()),
+        Err(err) => eprintln!("[warning] unable to save the recording ({err})"),
+    }
+}
+
+/// Emulate frames at maximum speed, first `frames` frames and then, if
+/// `until_visible` is set, until a frame is not completely black.
+/// Returns the count of skipped frames.
+fn skip_frames<B: rsnes::backend::AudioBackend, FB: rsnes::backend::FrameBuffer>(
+    snes: &mut Device<B, FB>,
+    frames: u32,
+    until_visible: bool,
+) -> u32 {
+    for _ in 0..frames {
+        run_frame(snes);
+    }
+    let mut skipped = frames;
+    if until_visible {
+        let is_blank = |snes: &Device<B, FB>| {
+            snes.ppu
+                .frame_buffer
+                .pixels()
+                .iter()
+                .all(|pixel| pixel[..3] == [0; 3])
+        };
+        while is_blank(snes) && skipped < frames + MAX_BLANK_FRAMES {
+            run_frame(snes);
+            skipped += 1;
+        }
+    }
+    skipped
+}
+
+/// The extensions of the patches applied automatically, in the order they
+/// are looked for
+static PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+/// Read a ROM file, or the ROM in the ZIP or gzip archive `path`
+fn read_rom(path: &Path, archive_entry: Option<&str>) -> Vec<u8> {
+    let content = std::fs::read(path)
+        .unwrap_or_else(|err| error!("Could not read file \"{}\" ({})\n", path.display(), err));
+    if !archive::is_archive(path) {
+        return content;
+    }
+    archive::unpack(path, &content, archive_entry).unwrap_or_else(|err| {
+        error!(
+            "Could not unpack the archive \"{}\" ({})\n",
+            path.display(),
+            err
+        )
+    })
+}
+
+/// Read a cartridge file and apply the patch `patch`, or the one next to the
+/// file (e.g. `game.bps` for `game.sfc` or `game.sfc.gz`), in memory
+fn cartridge_from_file(
+    path: &Path,
+    archive_entry: Option<&str>,
+    patch: Option<&Path>,
+    verbose: bool,
+) -> rsnes::cartridge::Cartridge {
+    let mut content = read_rom(path, archive_entry);
+    let next_to_rom = PATCH_EXTENSIONS
+        .iter()
+        .map(|ext| archive::rom_path(path).with_extension(ext))
+        .find(|patch| patch.is_file());
+    if let Some(patch) = patch.or(next_to_rom.as_deref()) {
+        let stats = std::fs::read(patch)
+            .map_err(|err| err.to_string())
+            .and_then(|patch| {
+                rsnes::patch::apply(&mut content, &patch).map_err(|err| err.to_string())
+            })
+            .unwrap_or_else(|err| {
+                error!("Could not apply patch \"{}\" ({})\n", patch.display(), err)
+            });
+        println!("[info] applied patch \"{}\"", patch.display());
+        if verbose {
+            println!(
+                "[info] {} records, {} bytes replaced, {} to {} bytes",
+                stats.records, stats.bytes, stats.old_size, stats.new_size
+            )
+        }
+    }
+    rsnes::cartridge::Cartridge::from_bytes(&content).unwrap_or_else(|err| {
+        error!(
+            "Failure while reading cartridge file \"{}\" ({})\n",
+            path.display(),
+            err
+        )
+    })
+}
+
+/// Print the features required by a cartridge (`--compat-report`)
+fn print_compat_report(path: &std::path::Path, archive_entry: Option<&str>) {
+    use rsnes::cartridge::compat::{CompatReport, Support};
+    let content = read_rom(path, archive_entry);
+    let report = CompatReport::new(&content).unwrap_or_else(|err| {
+        error!(
+            "Failure while reading cartridge file \"{}\" ({})\n",
+            path.display(),
+            err
+        )
+    });
+    println!("{}", report.title);
+    for requirement in &report.requirements {
+        print!("  {:<10} {}", requirement.support, requirement.feature);
+        match requirement.note {
+            Some(note) => println!(" ({note})"),
+            None => println!(),
+        }
+    }
+    println!(
+        "{}",
+        match report.support() {
+            Support::Supported => "expected to work",
+            Support::Partial => "may not work correctly",
+            Support::Missing => "not expected to work",
+        }
+    );
+}
+
+struct AudioBackend {
+    /// `None` if samples are discarded (`--no-audio`)
+    producer: Option<ringbuf::Producer<i16>>,
+    music: Option<music::Mixer>,
+    /// The samples of a running recording (record key)
+    recording: recorder::SampleTap,
+    /// The outputs of the voices, while the scopes are shown (scope key)
+    voices: scope::VoiceTap,
+}
+
+/// The sample rate of the S-DSP, other rates of the device are resampled
+const SAMPLE_RATE: cpal::SampleRate = cpal::SampleRate(32000);
+const TIME_PER_GPU_FRAME: Duration = Duration::from_micros(8_333);
+/// The longest time the event loop sleeps with `capped-present`, while no
+/// frame is due, so the debugger and the audio queue are still polled
+const CAPPED_POLL_INTERVAL: Duration = Duration::from_millis(4);
+/// The queued audio targeted by the sync mode `audio`
+const AUDIO_SYNC_LATENCY: Duration = Duration::from_millis(40);
+/// The stereo samples kept queued by the resampler, the same latency as
+/// targeted by the sync mode `audio`, so both don't work against each other
+const AUDIO_QUEUE_TARGET: usize =
+    (AUDIO_SYNC_LATENCY.as_millis() as usize) * SAMPLE_RATE.0 as usize / 1000;
+
+impl AudioBackend {
+    fn write_data<T: Sample>(
+        data: &mut [T],
+        consumer: &mut ringbuf::Consumer<i16>,
+        resampler: &mut resampler::Resampler,
+        channels: u16,
+        queued: &AtomicUsize,
+    ) {
+        resampler.control_rate(consumer.len() / 2, AUDIO_QUEUE_TARGET);
+        for frame in data.chunks_exact_mut(channels.into()) {
+            let sample = resampler.next_sample(|| {
+                // a stereo sample is always pushed at once
+                let l = consumer.pop()?;
+                Some([l, consumer.pop().unwrap_or(0)])
+            });
+            Self::map_channels(sample, frame)
+        }
+        queued.store(consumer.len(), Ordering::Relaxed)
+    }
+
+    /// Write a stereo sample to a frame of the output device. Mono devices
+    /// get the average of both channels. On devices with more channels, the
+    /// first two are the front left and right speakers of the usual layouts
+    /// (WAVE, ALSA), so the sample is played there and the others stay
+    /// silent, instead of duplicating it to the center, the subwoofer and
+    /// the rear.
+    fn map_channels<T: Sample>([l, r]: [i16; 2], frame: &mut [T]) {
+        match frame {
+            [mono] => *mono = T::from(&(((i32::from(l) + i32::from(r)) / 2) as i16)),
+            [left, right, others @ ..] => {
+                *left = T::from(&l);
+                *right = T::from(&r);
+                others.fill(T::from(&0i16));
+            }
+            [] => (),
+        }
+    }
+
+    fn create_stream<T: Sample>(
+        device: &cpal::Device,
+        cfg: &cpal::StreamConfig,
+        queued: Arc<AtomicUsize>,
+        rate_control: bool,
+    ) -> Result<
+        (
+            <cpal::Device as DeviceTrait>::Stream,
+            ringbuf::Producer<i16>,
+        ),
+        cpal::BuildStreamError,
+    > {
+        let channels = cfg.channels;
+        let ringbuf_size = (match cfg.buffer_size {
+            cpal::BufferSize::Fixed(val) => val,
+            cpal::BufferSize::Default => 1024,
+        } + cfg.sample_rate.0 / 6)
+            * u32::from(channels);
+        let (producer, mut consumer) = ringbuf::RingBuffer::new(ringbuf_size as usize).split();
+        let mut resampler =
+            resampler::Resampler::new(SAMPLE_RATE.0, cfg.sample_rate.0, rate_control);
+        device
+            .build_output_stream(
+                cfg,
+                move |data: &mut [T], _| {
+                    Self::write_data::<T>(data, &mut consumer, &mut resampler, channels, &queued)
+                },
+                |_| (),
+            )
+            .map(|stream| (stream, producer))
+    }
+
+    /// Returns the backend, the output stream and the count of queued
+    /// samples (two per stereo sample). With `rate_control` the resampler
+    /// follows the fill level of the queue (sync mode `dynamic`).
+    fn new(rate_control: bool) -> Option<(Self, cpal::platform::Stream, Arc<AtomicUsize>)> {
+        let host = cpal::available_hosts()
+            .into_iter()
+            .find_map(|id| cpal::host_from_id(id).ok())
+            .unwrap_or_else(cpal::default_host);
+        let device = host.default_output_device()?;
+        let cfg_range = device.supported_output_configs().ok()?.min_by_key(|cfg| {
+            (
+                // avoid resampling
+                !(cfg.min_sample_rate()..=cfg.max_sample_rate()).contains(&SAMPLE_RATE),
+                match cfg.channels() {
+                    0 => u16::MAX,
+                    1 => 12,
+                    2 => 0,
+                    n => n,
+                },
+                match cfg.sample_format() {
+                    cpal::SampleFormat::I16 => 0u8,
+                    cpal::SampleFormat::U16 => 1,
+                    cpal::SampleFormat::F32 => 2,
+                },
+                match cfg.buffer_size() {
+                    cpal::SupportedBufferSize::Unknown => cpal::FrameCount::MAX,
+                    cpal::SupportedBufferSize::Range { min, .. } => *min,
+                },
+            )
+        })?;
+        let sample_type = cfg_range.sample_format();
+        let sample_rate =
+            SAMPLE_RATE.clamp(cfg_range.min_sample_rate(), cfg_range.max_sample_rate());
+        let cfg = cfg_range.with_sample_rate(sample_rate).config();
+
+        let create_stream = match sample_type {
+            cpal::SampleFormat::I16 => Self::create_stream::<i16>,
+            cpal::SampleFormat::U16 => Self::create_stream::<u16>,
+            cpal::SampleFormat::F32 => Self::create_stream::<f32>,
+        };
+        let queued = Arc::new(AtomicUsize::new(0));
+        let (stream, producer) = create_stream(&device, &cfg, queued.clone(), rate_control).ok()?;
+        stream.play().ok()?;
+        Some((
+            Self {
+                producer: Some(producer),
+                music: None,
+                recording: Default::default(),
+                voices: Default::default(),
+            },
+            stream,
+            queued,
+        ))
+    }
+
+    /// A backend, which discards all samples
+    fn null() -> Self {
+        Self {
+            producer: None,
+            music: None,
+            recording: Default::default(),
+            voices: Default::default(),
+        }
+    }
+}
+
+impl rsnes::backend::AudioBackend for AudioBackend {
+    fn push_sample(&mut self, sample: StereoSample) {
+        let mut recording = self.recording.lock().unwrap();
+        if self.producer.is_none() && recording.is_none() {
+            return;
+        }
+        let sample = match &mut self.music {
+            Some(music) => music.mix(sample),
+            None => sample,
+        };
+        if let Some(recording) = recording.as_mut() {
+            recording.extend([sample.l, sample.r])
+        }
+        if let Some(producer) = &mut self.producer {
+            let _ = producer
+                .push(sample.l)
+                .and_then(|()| producer.push(sample.r));
+        }
+    }
+
+    fn push_voice_outputs(&mut self, outputs: [i16; 8]) {
+        if let Some(voices) = self.voices.lock().unwrap().as_mut() {
+            voices.push(outputs)
+        }
+    }
+}
+
+fn write_default_config(path: Option<PathBuf>) {
+    let path = path
+        .or_else(config::Config::default_path)
+        .unwrap_or_else(|| error!("no path given and $HOME is not set"));
+    if path.exists() {
+        error!("\"{}\" exists already", path.display());
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|err| error!("could not create \"{}\" ({err})", dir.display()));
+    }
+    std::fs::write(&path, config::Config::default_file())
+        .unwrap_or_else(|err| error!("could not write \"{}\" ({err})", path.display()));
+    println!("Wrote the default configuration to \"{}\"", path.display());
+}
+
+/// Open the window of the emulator, initially four times the size of the
+/// picture
+fn create_window(event_loop: &EventLoop<()>, fullscreen: bool, title: &str) -> Window {
+    let size = winit::dpi::PhysicalSize::new(
+        rsnes::ppu::SCREEN_WIDTH * 4,
+        rsnes::ppu::MAX_SCREEN_HEIGHT * 4,
+    );
+    let window_builder = WindowBuilder::new().with_window_icon(Some(icon::default_icon()));
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd"
+    ))]
+    let window_builder = {
+        use winit::platform::unix::WindowBuilderExtUnix;
+        window_builder
+            .with_class(icon::APP_ID.to_owned(), icon::APP_ID.to_owned())
+            .with_app_id(icon::APP_ID.to_owned())
+    };
+    #[cfg(target_os = "windows")]
+    let window_builder = {
+        use winit::platform::windows::WindowBuilderExtWindows;
+        window_builder.with_taskbar_icon(Some(icon::default_icon()))
+    };
+    window_builder
+        .with_decorations(true)
+        .with_visible(true)
+        .with_fullscreen(fullscreen.then(|| Fullscreen::Borderless(None)))
+        .with_resizable(true)
+        .with_maximized(false)
+        .with_inner_size(size)
+        .with_title(title)
+        .build(event_loop)
+        .unwrap_or_else(|err| error!("Failure while creating window ({})", err))
+}
+
+/// The graphics APIs used, Android devices without Vulkan support OpenGL ES
+const BACKENDS: wgpu::Backends = if cfg!(target_os = "android") {
+    wgpu::Backends::VULKAN.union(wgpu::Backends::GL)
+} else {
+    wgpu::Backends::VULKAN
+};
+
+/// Create a configured surface of `window` and the GPU device drawing to it.
+/// The instance creates a new surface, when the old one was lost.
+fn create_surface(
+    window: &Window,
+) -> (
+    wgpu::Instance,
+    wgpu::Surface,
+    wgpu::Device,
+    wgpu::Queue,
+    wgpu::SurfaceConfiguration,
+) {
+    let inst = wgpu::Instance::new(BACKENDS);
+    let surf = unsafe { inst.create_surface(window) };
+    let adapter = inst
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            compatible_surface: Some(&surf),
+            force_fallback_adapter: false,
+        })
+        .block_on()
+        .unwrap_or_else(|| error!("Failure finding a graphics adapter"));
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        )
+        .block_on()
+        .unwrap_or_else(|err| error!("Failure requesting a GPU command queue ({})", err));
+    // the window may not have the requested size, e.g. in fullscreen mode
+    let size = window.inner_size();
+    let surf_config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format: surf.get_preferred_format(&adapter).unwrap(),
+        width: size.width.max(1),
+        height: size.height.max(1),
+        present_mode: wgpu::PresentMode::Fifo,
+    };
+    surf.configure(&device, &surf_config);
+    (inst, surf, device, queue, surf_config)
+}
+
+/// The controller connected to `port`, 0 or 1
+fn controller_mut(
+    controllers: &mut rsnes::controller::ControllerPorts,
+    port: usize,
+) -> &mut rsnes::controller::Controller {
+    match port {
+        0 => &mut controllers.port1.controller,
+        _ => &mut controllers.port2.controller,
+    }
+}
+
+/// Show the controller test screen without a game (`--test-input`) until
+/// the window is closed
+fn run_input_test(config: &config::Config, profile: &config::Profile) -> ! {
+    let [port1_profile, port2_profile] =
+        config.get_controller_profiles(profile).map(|p| p.cloned());
+    // the console only keeps the state of the controllers and is never run
+    let mut snes = Device::new(
+        rsnes::backend::AudioDummy,
+        ArrayFrameBuffer([[0; 4]; rsnes::backend::FRAME_BUFFER_SIZE], true),
+        false,
+        false,
+    );
+    snes.controllers.port1 = config::controller_profile_to_port(port1_profile.as_ref());
+    snes.controllers.port2 = config::controller_profile_to_port(port2_profile.as_ref());
+    let mut input_test = input_test::InputTest::new();
+    let filter = profile.filter.filter_mode();
+    let input_test_key = profile.input_test_key;
+
+    let event_loop = EventLoop::new();
+    let window = create_window(&event_loop, profile.fullscreen, "rsnes - input test");
+    let (_, surf, device, queue, mut surf_config) = create_surface(&window);
+    let renderer = Renderer::new(&device, surf_config.format, &snes);
+    let (texture_width, texture_height) = renderer.texture_size();
+    let black = vec![0; texture_width as usize * texture_height as usize * 4];
+    let mut update_screen_size = true;
+    let mut focused = true;
+    let ports = [port1_profile, port2_profile];
+    event_loop.run(move |ev, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match ev {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                    surf_config.width = size.width;
+                    surf_config.height = size.height;
+                    surf.configure(&device, &surf_config);
+                    update_screen_size = true;
+                }
+                WindowEvent::Focused(focus) => focused = focus,
+                WindowEvent::MouseInput { button, state, .. } if focused => {
+                    let pressed = matches!(state, ElementState::Pressed);
+                    input_test.mouse_button(button, pressed);
+                    for (port, port_cfg) in ports.iter().enumerate() {
+                        if let Some(port_cfg) = port_cfg {
+                            let controller = controller_mut(&mut snes.controllers, port);
+                            port_cfg.handle_mouse_button(button, pressed, controller);
+                        }
+                    }
+                    window.request_redraw()
+                }
+                _ => (),
+            },
+            Event::DeviceEvent { event, .. } => match event {
+                DeviceEvent::Key(KeyboardInput {
+                    scancode, state, ..
+                }) if focused => {
+                    let pressed = matches!(state, ElementState::Pressed);
+                    // like on the test screen during a game, its key closes it
+                    if scancode == input_test_key && pressed {
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                    let mapped = ports.iter().enumerate().find_map(|(port, port_cfg)| {
+                        Some((port, port_cfg.as_ref()?.button(scancode)?))
+                    });
+                    input_test.key(scancode, pressed, mapped);
+                    if let Some((port, _)) = mapped {
+                        let controller = controller_mut(&mut snes.controllers, port);
+                        let port_cfg = ports[port].as_ref().unwrap();
+                        port_cfg.handle_scancode(scancode, pressed, controller);
+                    }
+                    window.request_redraw()
+                }
+                DeviceEvent::MouseMotion { delta: (dx, dy) } if focused => {
+                    for (port, port_cfg) in ports.iter().enumerate() {
+                        if let Some(port_cfg) = port_cfg {
+                            let controller = controller_mut(&mut snes.controllers, port);
+                            port_cfg.handle_mouse_move(dx, dy, controller);
+                        }
+                    }
+                    window.request_redraw()
+                }
+                _ => (),
+            },
+            Event::RedrawRequested(_) => match surf.get_current_texture() {
+                Ok(surface_texture) => {
+                    let picture =
+                        input_test.draw(&black, texture_width, texture_height, &snes.controllers);
+                    renderer.upload(&queue, &picture);
+                    if core::mem::take(&mut update_screen_size) {
+                        renderer.set_target_size(
+                            &queue,
+                            [surf_config.width, surf_config.height],
+                            u32::from(snes.ppu.vend() - 1),
+                        );
+                    }
+                    let view = surface_texture
+                        .texture
+                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    let mut encoder = device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                    renderer.render(&mut encoder, &view, filter);
+                    queue.submit(Some(encoder.finish()));
+                    surface_texture.present();
+                }
+                Err(wgpu::SurfaceError::Timeout) => (),
+                Err(err) => error!("Failed to acquire next swap chain texture ({})", err),
+            },
+            _ => (),
+        }
+    })
+}
+
+/// Run a game and a hack of it in lockstep (`--compare`) and show their
+/// pictures side by side until the window is closed
+fn run_comparison(
+    config: &config::Config,
+    profile: &config::Profile,
+    cartridges: [rsnes::cartridge::Cartridge; 2],
+    is_pal: bool,
+) -> ! {
+    let ports = config.get_controller_profiles(profile).map(|p| p.cloned());
+    let mut consoles = cartridges.map(|mut cartridge| {
+        firmware::load(&mut cartridge, &profile.firmware_dirs, false);
+        let mut snes = Box::new(Device::new(
+            rsnes::backend::AudioDummy,
+            ArrayFrameBuffer([[0; 4]; rsnes::backend::FRAME_BUFFER_SIZE], true),
+            is_pal,
+            false,
+        ));
+        snes.load_cartridge(cartridge);
+        snes.controllers.port1 = config::controller_profile_to_port(ports[0].as_ref());
+        snes.controllers.port2 = config::controller_profile_to_port(ports[1].as_ref());
+        snes
+    });
+    let mut comparison = compare::Comparison::new();
+    let mut pictures: [Vec<u8>; 2] = Default::default();
+    let filter = profile.filter.filter_mode();
+    let mut governor = rsnes::runner::Governor::new(rsnes::runner::Sync::Timer);
+
+    let event_loop = EventLoop::new();
+    let window = create_window(&event_loop, profile.fullscreen, &comparison.title());
+    let (_, surf, device, queue, mut surf_config) = create_surface(&window);
+    let renderers = consoles
+        .each_ref()
+        .map(|snes| Renderer::new(&device, surf_config.format, snes));
+    let start_time = Instant::now();
+    let mut update_screen_size = true;
+    let mut focused = true;
+    event_loop.run(move |ev, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match ev {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                    surf_config.width = size.width;
+                    surf_config.height = size.height;
+                    surf.configure(&device, &surf_config);
+                    update_screen_size = true;
+                }
+                WindowEvent::Focused(focus) => focused = focus,
+                WindowEvent::MouseInput { button, state, .. } if focused => {
+                    let pressed = matches!(state, ElementState::Pressed);
+                    for snes in &mut consoles {
+                        for (port, port_cfg) in ports.iter().enumerate() {
+                            if let Some(port_cfg) = port_cfg {
+                                let controller = controller_mut(&mut snes.controllers, port);
+                                port_cfg.handle_mouse_button(button, pressed, controller);
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            },
+            Event::DeviceEvent { event, .. } => match event {
+                DeviceEvent::Key(KeyboardInput {
+                    scancode, state, ..
+                }) if focused => {
+                    let pressed = matches!(state, ElementState::Pressed);
+                    for snes in &mut consoles {
+                        for (port, port_cfg) in ports.iter().enumerate() {
+                            if let Some(port_cfg) = port_cfg {
+                                let controller = controller_mut(&mut snes.controllers, port);
+                                port_cfg.handle_scancode(scancode, pressed, controller);
+                            }
+                        }
+                    }
+                }
+                DeviceEvent::MouseMotion { delta: (dx, dy) } if focused => {
+                    for snes in &mut consoles {
+                        for (port, port_cfg) in ports.iter().enumerate() {
+                            if let Some(port_cfg) = port_cfg {
+                                let controller = controller_mut(&mut snes.controllers, port);
+                                port_cfg.handle_mouse_move(dx, dy, controller);
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            },
+            Event::MainEventsCleared if governor.should_run_frame(start_time.elapsed()) => {
+                let cycle_count = run_frame(&mut consoles[0]);
+                run_frame(&mut consoles[1]);
+                let [original, hack] = consoles
+                    .each_ref()
+                    .map(|snes| snes.ppu.frame_buffer.get_bytes());
+                pictures = comparison.on_frame(original, hack);
+                window.set_title(&comparison.title());
+                window.request_redraw();
+                governor.frame_done(cycle_count, consoles[0].is_pal(), start_time.elapsed());
+            }
+            Event::RedrawRequested(_) => match surf.get_current_texture() {
+                Ok(surface_texture) => {
+                    // the original is shown in the left half, the hack in the right half
+                    let half = surf_config.width / 2;
+                    let rects = [
+                        [0, 0, half, surf_config.height],
+                        [half, 0, surf_config.width - half, surf_config.height],
+                    ];
+                    let view = surface_texture
+                        .texture
+                        .create_view(&wgpu::TextureViewDescriptor::default());
+                    let mut encoder = device
+                        .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+                    let update_screen_size = core::mem::take(&mut update_screen_size);
+                    for ((renderer, picture), rect) in renderers.iter().zip(&pictures).zip(rects) {
+                        if picture.is_empty() {
+                            continue;
+                        }
+                        renderer.upload(&queue, picture);
+                        if update_screen_size {
+                            renderer.set_target_size(
+                                &queue,
+                                [rect[2], rect[3]],
+                                u32::from(consoles[0].ppu.vend() - 1),
+                            );
+                        }
+                        renderer.render_into(&mut encoder, &view, filter, Some(rect));
+                    }
+                    queue.submit(Some(encoder.finish()));
+                    surface_texture.present();
+                }
+                Err(wgpu::SurfaceError::Timeout) => (),
+                Err(err) => error!("Failed to acquire next swap chain texture ({})", err),
+            },
+            Event::LoopDestroyed => comparison.print_summary(),
+            _ => (),
+        }
+    })
+}
+
+/// Run the emulator with the command line of the process, or of the intent
+/// on Android
+pub fn run() {
+    #[cfg(target_os = "android")]
+    let options = Options::parse_from(android::args());
+    #[cfg(not(target_os = "android"))]
+    let options = Options::parse();
+
+    if let Some(path) = &options.write_default_config {
+        write_default_config(path.clone());
+        return;
+    }
+    let config = config::Config::load(options.config.clone(), options.verbose)
+        .unwrap_or_else(|err| error!("config: {err}"));
+    for warning in config.warnings() {
+        eprintln!("[warning] config: {warning}")
+    }
+    if options.check_config {
+        match config.path() {
+            Some(path) => println!("The config file \"{}\" is valid", path.display()),
+            None => println!("No config file found, the default configuration is used"),
+        }
+        return;
+    }
+
+    if options.stats {
+        stats::Stats::load().print();
+        return;
+    }
+    if options.test_input && options.input.is_none() {
+        let profile = match &options.profile {
+            Some(name) => config
+                .get_profile(name)
+                .unwrap_or_else(|| error!("profile `{name}` is not defined")),
+            None => config.get_default_profile(),
+        };
+        run_input_test(&config, profile)
+    }
+    if options.compat_report {
+        print_compat_report(
+            options.input.as_ref().unwrap(),
+            options.archive_entry.as_deref(),
+        );
+        return;
+    }
+    let mut cartridge = cartridge_from_file(
+        options.input.as_ref().unwrap(),
+        options.archive_entry.as_deref(),
+        options.patch.as_deref(),
+        options.verbose,
+    );
+    let rom_id = stats::rom_id(cartridge.rom());
+    let mut stats = stats::Stats::load();
+    stats.launch(&rom_id, cartridge.title());
+    let mut session_time = Duration::ZERO;
+    let title = cartridge.title().to_owned();
+    let (profile_name, profile) = if let Some(name) = &options.profile {
+        let profile = config
+            .get_profile(name)
+            .unwrap_or_else(|| error!("profile `{name}` is not defined"));
+        (name.as_str(), profile)
+    } else if let Some((name, profile)) = config.select_profile(&rom_id, &title) {
+        if options.verbose {
+            println!("[info] selected profile `{name}` for this game");
+        }
+        (name, profile)
+    } else {
+        (config.default_profile_name(), config.get_default_profile())
+    };
+    let [port1_profile, port2_profile] =
+        config.get_controller_profiles(profile).map(|p| p.cloned());
+    if options.verbose {
+        println!(
+            "[info] Cartridge header information: {:#?}",
+            cartridge.header()
+        );
+    }
+    let is_pal = match profile.region {
+        rsnes::cartridge::CountryFrameRate::Any => matches!(
+            cartridge.get_country_frame_rate(),
+            rsnes::cartridge::CountryFrameRate::Pal
+        ),
+        rsnes::cartridge::CountryFrameRate::Pal => true,
+        rsnes::cartridge::CountryFrameRate::Ntsc => false,
+    };
+    if options.verbose {
+        println!(
+            "[info] Selected {} region",
+            if is_pal { "PAL" } else { "NTSC" }
+        );
+    }
+    if let Some(hack) = &options.compare {
+        run_comparison(
+            &config,
+            profile,
+            [
+                cartridge,
+                cartridge_from_file(hack, None, None, options.verbose),
+            ],
+            is_pal,
+        )
+    }
+    let game_config = config.get_game(&rom_id).cloned().unwrap_or_default();
+    let sync_mode = if options.audio_sync || options.no_video {
+        config::SyncMode::Audio
+    } else {
+        profile.sync_mode
+    };
+    if options.verbose {
+        println!("[info] Selected {sync_mode} sync mode");
+    }
+    // the benchmark runs without any output like the headless mode
+    let headless = options.headless || options.bench.is_some();
+    let audio = if options.no_audio || headless || !profile.audio {
+        None
+    } else {
+        let audio = AudioBackend::new(sync_mode == config::SyncMode::Dynamic);
+        if audio.is_none() {
+            eprintln!("[warning] no audio output device found, continuing without audio")
+        }
+        audio
+    };
+    let (mut audio_backend, audio_stream, audio_queued) = match audio {
+        Some((backend, stream, queued)) => (backend, Some(stream), queued),
+        None => (AudioBackend::null(), None, Arc::new(AtomicUsize::new(0))),
+    };
+    let mut music = options
+        .music_pack
+        .as_ref()
+        .or(game_config.music_pack.as_ref())
+        .map(|dir| {
+            let pack = config::MusicPack::load_from_dir(dir).unwrap_or_else(|err| {
+                error!("Could not load music pack \"{}\" ({})", dir.display(), err)
+            });
+            let (music, mixer) = music::Music::new(pack);
+            audio_backend.music = Some(mixer);
+            music
+        });
+    // the backend may be moved to the thread of the SMP
+    let recording_samples = audio_backend.recording.clone();
+    let voice_outputs = audio_backend.voices.clone();
+    let mut snes = Device::new(
+        audio_backend,
+        ArrayFrameBuffer([[0; 4]; rsnes::backend::FRAME_BUFFER_SIZE], true),
+        is_pal,
+        profile.threaded,
+    );
+    let widescreen = options.widescreen.or(game_config.widescreen).unwrap_or(0);
+    let hd_mode7 = options.hd_mode7.or(profile.hd_mode7);
+    if hd_mode7.is_some() || widescreen > 0 || options.texture_pack.is_some() {
+        use rsnes::ppu::HdFrame;
+        let scale = hd_mode7.unwrap_or(if options.texture_pack.is_some() {
+            HdFrame::MAX_SCALE
+        } else {
+            1
+        });
+        if !(1..=HdFrame::MAX_SCALE).contains(&scale) {
+            error!(
+                "The HD Mode 7 scale must be in the range 1 to {}",
+                HdFrame::MAX_SCALE
+            )
+        }
+        if widescreen > HdFrame::MAX_WIDESCREEN {
+            error!(
+                "The widescreen width must be at most {} pixels",
+                HdFrame::MAX_WIDESCREEN
+            )
+        }
+        let mut hd_frame = HdFrame::new(scale, widescreen);
+        if !options.widescreen_bgs.is_empty() {
+            hd_frame.widescreen_bgs = [1, 2, 3, 4].map(|nr| options.widescreen_bgs.contains(&nr));
+        } else if let Some(bgs) = game_config.widescreen_bgs {
+            hd_frame.widescreen_bgs = bgs;
+        }
+        snes.ppu.hd_frame = Some(hd_frame);
+    }
+    let texture_loader =
+        (options.texture_pack.is_some() || options.texture_dump.is_some()).then(|| {
+            let loader = texture_pack::TextureLoader::new(
+                options.texture_pack.as_deref(),
+                options.texture_dump.clone(),
+                options.texture_cache_size << 20,
+            )
+            .unwrap_or_else(|err| error!("Could not open the texture pack ({})", err));
+            if options.verbose && options.texture_pack.is_some() {
+                println!("[info] Found {} replacement textures", loader.len())
+            }
+            let mut pack = rsnes::texture::TexturePack::new();
+            pack.dump = options.texture_dump.is_some();
+            snes.ppu.texture_pack = Some(pack);
+            loader
+        });
+    if options.integrity_mode {
+        if options.debug || options.debug_script.is_some() || options.corruptor {
+            error!("The integrity mode can't be combined with the debugger")
+        }
+        if options.practice || options.practice_state.is_some() {
+            error!("The integrity mode can't be combined with the practice mode")
+        }
+        if options.play_movie.is_some() || options.record_movie.is_some() {
+            error!("The integrity mode can't be combined with movies")
+        }
+        snes.enable_integrity_mode();
+    }
+    let run_ahead = options.run_ahead.map(|frames| {
+        RunAhead::new(frames, &snes)
+            .unwrap_or_else(|err| error!("Could not enable run-ahead ({})", err))
+    });
+    snes.controllers.port1 = config::controller_profile_to_port(port1_profile.as_ref());
+    snes.controllers.port2 = config::controller_profile_to_port(port2_profile.as_ref());
+    if options.flash_save || game_config.flash_save {
+        cartridge.set_save_memory(rsnes::cartridge::SaveMemory::Flash);
+    }
+    if game_config.detect_sram {
+        cartridge.enable_sram_detection();
+    }
+    firmware::load(&mut cartridge, &profile.firmware_dirs, options.verbose);
+    if game_config.region_patch {
+        match cartridge.patch_region_checks() {
+            0 => eprintln!("[warning] no known region check found to patch"),
+            count => {
+                if options.verbose {
+                    println!("[info] patched {count} region checks")
+                }
+            }
+        }
+    }
+    // SA-1 cartridges keep their save data in BW-RAM, which isn't persisted
+    let mut srm_file = (!options.no_srm && !cartridge.has_sa1())
+        .then(|| options.srm.clone().or_else(|| srm::srm_path(&rom_id)))
+        .flatten()
+        .map(srm::SrmFile::new);
+    if let Some(srm_file) = &srm_file {
+        if let Err(err) = srm_file.load(&mut cartridge) {
+            error!(
+                "Could not read the save memory file \"{}\" ({})",
+                srm_file.path().display(),
+                err
+            )
+        }
+    }
+    snes.load_cartridge(cartridge);
+    let hacks: Vec<_> = snes.hacks().iter().map(|hack| hack.hack).collect();
+    for hack in hacks {
+        if options.no_hacks {
+            snes.set_hack(hack.name, false);
+        } else {
+            println!(
+                "[info] compatibility hack `{}`: {}",
+                hack.name, hack.description
+            )
+        }
+    }
+    let cheat_file = cheats::cheat_path(&rom_id).filter(|_| !snes.integrity_mode());
+    if let Some(path) = &cheat_file {
+        cheats::load(&mut snes, path).unwrap_or_else(|err| {
+            error!(
+                "Could not read the cheat file \"{}\" ({})",
+                path.display(),
+                err
+            )
+        });
+    }
+    for code in &options.cheat {
+        rsnes::cheat::Code::parse(code)
+            .and_then(|code| snes.add_cheat(code, String::new(), true))
+            .unwrap_or_else(|err| error!("{err}"));
+    }
+    if let Some(path) = cheat_file.as_ref().filter(|_| !options.cheat.is_empty()) {
+        if let Err(err) = cheats::save(&snes, path) {
+            eprintln!(
+                "[warning] could not write the cheat file \"{}\" ({err})",
+                path.display()
+            )
+        }
+    }
+    let cheat_count = snes.cheats().iter().filter(|cheat| cheat.enabled).count();
+    if cheat_count > 0 {
+        println!("[info] {cheat_count} cheat(s) enabled")
+    }
+    if sync_mode == config::SyncMode::Audio
+        && audio_stream.is_none()
+        && !options.no_video
+        && !headless
+    {
+        eprintln!("[warning] the sync mode `audio` has no effect without audio output")
+    }
+    let audio_sync = sync_mode == config::SyncMode::Audio;
+    let mut governor = rsnes::runner::Governor::new(if audio_sync && audio_stream.is_some() {
+        rsnes::runner::Sync::Audio {
+            latency: AUDIO_SYNC_LATENCY,
+        }
+    } else {
+        rsnes::runner::Sync::Timer
+    });
+    if options.speed <= 0.0 {
+        error!("The speed must be positive")
+    }
+    governor
+        .set_speed(options.speed / 100.0, &snes)
+        .unwrap_or_else(|err| error!("{err}"));
+    let mut autosave = options
+        .autosave
+        .filter(|&minutes| minutes > 0)
+        .map(|minutes| {
+            let dir = autosave::autosave_dir(&rom_id)
+                .unwrap_or_else(|| error!("Could not find the autosave directory"));
+            let interval = Duration::from_secs(u64::from(minutes) * 60);
+            autosave::AutoSave::new(dir.clone(), interval, options.autosave_count).unwrap_or_else(
+                |err| {
+                    error!(
+                        "Could not create the autosave directory \"{}\" ({})",
+                        dir.display(),
+                        err
+                    )
+                },
+            )
+        });
+    let mut tas = tas::Tas::new(&snes, options.greenzone_size << 20);
+    if let Some(path) = &options.play_movie {
+        tas.load_movie(&mut snes, path)
+            .unwrap_or_else(|err| error!("Could not load movie \"{}\" ({})", path.display(), err));
+    } else if let Some(path) = &options.record_movie {
+        let anchor = options.movie_anchor.as_ref().map(|path| {
+            states::read_file(path)
+                .and_then(|state| states::load_checked(&mut snes, &state).map(|()| state))
+                .unwrap_or_else(|err| {
+                    error!(
+                        "Could not load anchor state \"{}\" ({})",
+                        path.display(),
+                        err
+                    )
+                })
+        });
+        let title = snes
+            .cartridge()
+            .map(|cart| cart.title().to_owned())
+            .unwrap_or_default();
+        tas.movie_path = Some(path.clone());
+        tas.start(
+            &mut snes,
+            movie::Movie::new(title, anchor),
+            tas::Mode::Record,
+        )
+        .unwrap_or_else(|err| error!("Could not record movie \"{}\" ({})", path.display(), err));
+    }
+    if options.state_container && options.state_dir.is_some() {
+        error!("`--state-container` can't be combined with `--state-dir`")
+    }
+    let mut save_states = if options.state_container {
+        let path = state_container::container_path(&rom_id)
+            .unwrap_or_else(|| error!("Could not find the state container directory"));
+        let container = state_container::Container::open(path.clone()).unwrap_or_else(|err| {
+            error!(
+                "Could not open the state container \"{}\" ({})",
+                path.display(),
+                err
+            )
+        });
+        states::SaveStates::with_container(container)
+    } else if let Some(dir) = options.state_dir.as_ref().or(profile.state_dir.as_ref()) {
+        let input = options.input.as_ref().unwrap();
+        let name = input
+            .file_stem()
+            .unwrap_or(input.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let files = states::SlotFiles::new(dir.clone(), name).unwrap_or_else(|err| {
+            error!(
+                "Could not create the state directory \"{}\" ({})",
+                dir.display(),
+                err
+            )
+        });
+        states::SaveStates::with_files(files)
+    } else {
+        states::SaveStates::new()
+    };
+    if options.skip_frames > 0 || options.skip_blank {
+        let skipped = skip_frames(&mut snes, options.skip_frames, options.skip_blank);
+        if options.verbose {
+            println!("[info] skipped {skipped} frames")
+        }
+    }
+
+    let practice = (options.practice || options.practice_state.is_some()).then(|| {
+        let mut practice = practice::Practice::new(options.practice_delay);
+        if let Some(path) = &options.practice_state {
+            let state = states::read_file(path)
+                .and_then(|state| states::load_checked(&mut snes, &state).map(|()| state))
+                .unwrap_or_else(|err| {
+                    error!(
+                        "Could not load practice state \"{}\" ({})",
+                        path.display(),
+                        err
+                    )
+                });
+            practice.set_anchor(state)
+        }
+        practice
+    });
+    let mut netplay = if options.netplay_host.is_some() || options.netplay_join.is_some() {
+        if options.debug || options.debug_script.is_some() || options.corruptor {
+            error!("Netplay can't be combined with the debugger")
+        }
+        if options.play_movie.is_some() || options.record_movie.is_some() {
+            error!("Netplay can't be combined with movies")
+        }
+        if practice.is_some() {
+            error!("Netplay can't be combined with the practice mode")
+        }
+        if options.no_video || options.frontend_mode || options.bench.is_some() {
+            error!("Netplay needs the window or `--headless`")
+        }
+        snes.enable_deterministic_mode();
+        let state_hash = netplay::state_hash(&snes);
+        Some(match (&options.netplay_host, &options.netplay_join) {
+            (Some(addr), _) => Netplay::host(
+                addr,
+                options.netplay_delay,
+                options.netplay_rollback,
+                &rom_id,
+                state_hash,
+            )
+            .unwrap_or_else(|err| error!("Could not host netplay on {} ({})", addr, err)),
+            (None, Some(addr)) => Netplay::join(addr, &rom_id, state_hash)
+                .unwrap_or_else(|err| error!("Could not join netplay at {} ({})", addr, err)),
+            (None, None) => unreachable!(),
+        })
+    } else {
+        None
+    };
+    let mut frame_stream = options.stream.as_ref().map(|addr| {
+        let frame_stream = stream::FrameStream::bind(addr, options.raw_stream)
+            .unwrap_or_else(|err| error!("Could not listen on \"{}\" ({})", addr, err));
+        if options.verbose {
+            println!("[info] streaming frames on {}", frame_stream.local_addr())
+        }
+        frame_stream
+    });
+    let mut layer_dump = options.dump_layers.as_ref().map(|dir| {
+        let frames = (options.dump_frames.as_deref())
+            .and_then(layer_dump::parse_range)
+            .unwrap_or_else(|| error!("Invalid frame range, expected e.g. `120-300`"));
+        layer_dump::LayerDump::new(&mut snes, dir.clone(), frames)
+            .unwrap_or_else(|err| error!("Could not create \"{}\" ({})", dir.display(), err))
+    });
+    if let Some(frames) = options.bench {
+        if options.debug || options.debug_script.is_some() || options.corruptor {
+            error!("`--bench` can't be combined with the debugger")
+        }
+        if options.frontend_mode {
+            error!("`--bench` can't be combined with the frontend mode")
+        }
+        print!("{}", bench::run(&mut snes, frames));
+        return;
+    }
+    if options.headless {
+        if options.debug || options.debug_script.is_some() || options.corruptor {
+            error!("`--headless` can't be combined with the debugger")
+        }
+        if options.frontend_mode {
+            error!("`--headless` can't be combined with the frontend mode")
+        }
+        let start_time = Instant::now();
+        let mut frames = 0;
+        while options.frames.is_none_or(|count| frames < count) {
+            tas.before_frame(&mut snes);
+            if options.frames.is_none()
+                && options.play_movie.is_some()
+                && tas.mode == tas::Mode::Inactive
+            {
+                break;
+            }
+            if let Some(netplay) = &mut netplay {
+                while !netplay.before_frame(&mut snes) {}
+            }
+            let cycle_count = run_frame(&mut snes);
+            if let Some(netplay) = &mut netplay {
+                netplay.after_frame(&mut snes)
+            }
+            frames += 1;
+            if let Some(music) = &mut music {
+                music.on_frame(&mut snes)
+            }
+            if let Some(frame_stream) = &mut frame_stream {
+                frame_stream.on_frame(&snes)
+            }
+            if let Some(layer_dump) = &mut layer_dump {
+                layer_dump.on_frame(&mut snes)
+            }
+            let frame_time = rsnes::runner::cycles_to_duration(cycle_count, snes.is_pal());
+            session_time += frame_time;
+            if let Some(autosave) = &mut autosave {
+                autosave.on_frame(&snes, frame_time)
+            }
+            if let (Some(srm_file), Some(cartridge)) = (&mut srm_file, snes.cartridge_mut()) {
+                srm_file.on_frame(cartridge, frame_time)
+            }
+        }
+        if options.verbose {
+            println!(
+                "[info] emulated {frames} frames in {:.2}s",
+                start_time.elapsed().as_secs_f64()
+            )
+        }
+        save_recorded_movie(&mut tas, options.record_movie.as_ref());
+        if let Some(netplay) = netplay {
+            netplay.leave()
+        }
+        save_on_exit(
+            &mut snes,
+            srm_file.as_mut(),
+            &mut stats,
+            &rom_id,
+            session_time,
+            options.verbose,
+        );
+        return;
+    }
+    if options.no_video {
+        if options.debug || options.debug_script.is_some() || options.corruptor {
+            error!("`--no-video` can't be combined with the debugger")
+        }
+        if options.frontend_mode {
+            error!("`--no-video` can't be combined with the frontend mode")
+        }
+        if audio_stream.is_none() {
+            eprintln!("[warning] without audio output, the emulation is paced by the system clock")
+        }
+        println!("[info] running without video, enter `h` for a list of hotkeys");
+        let mut terminal = terminal::Terminal::new();
+        let mut gamepads = gamepad::Gamepads::new(&profile.gamepad_mappings, options.verbose);
+        let start_time = Instant::now();
+        let mut audio_paused = false;
+        while terminal.poll(&mut snes, &mut save_states, &mut governor) {
+            governor.set_paused(terminal.paused);
+            if let Some(audio_stream) = audio_stream
+                .as_ref()
+                .filter(|_| audio_paused != terminal.paused)
+            {
+                audio_paused = terminal.paused;
+                let result = if audio_paused {
+                    audio_stream.pause().map_err(|err| err.to_string())
+                } else {
+                    audio_stream.play().map_err(|err| err.to_string())
+                };
+                if let Err(err) = result {
+                    if options.verbose {
+                        eprintln!("[warning] could not pause or resume audio ({err})")
+                    }
+                }
+            }
+            governor.set_audio_queued(Duration::from_micros(
+                audio_queued.load(Ordering::Relaxed) as u64 * 1_000_000
+                    / (2 * u64::from(SAMPLE_RATE.0)),
+            ));
+            if !governor.should_run_frame(start_time.elapsed()) {
+                std::thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            gamepads.apply(&mut snes.controllers);
+            tas.before_frame(&mut snes);
+            let cycle_count = run_frame(&mut snes);
+            if let Some(music) = &mut music {
+                music.on_frame(&mut snes)
+            }
+            if let Some(frame_stream) = &mut frame_stream {
+                frame_stream.on_frame(&snes)
+            }
+            if let Some(layer_dump) = &mut layer_dump {
+                layer_dump.on_frame(&mut snes)
+            }
+            let frame_time = governor.frame_done(cycle_count, snes.is_pal(), start_time.elapsed());
+            session_time += frame_time;
+            if let Some(autosave) = &mut autosave {
+                autosave.on_frame(&snes, frame_time)
+            }
+            if let (Some(srm_file), Some(cartridge)) = (&mut srm_file, snes.cartridge_mut()) {
+                srm_file.on_frame(cartridge, frame_time)
+            }
+        }
+        save_recorded_movie(&mut tas, options.record_movie.as_ref());
+        save_on_exit(
+            &mut snes,
+            srm_file.as_mut(),
+            &mut stats,
+            &rom_id,
+            session_time,
+            true,
+        );
+        return;
+    }
+
+    let frontend = options.frontend_mode.then(|| {
+        if options.debug || options.debug_script.is_some() || options.corruptor {
+            error!("The frontend mode can't be combined with the debugger")
+        }
+        frontend::install_panic_hook();
+        frontend::report(format_args!(
+            "loaded {}",
+            snes.cartridge().map_or("", |cart| cart.title())
+        ));
+        frontend::Frontend::new()
+    });
+
+    let debugger =
+        (options.debug || options.debug_script.is_some() || options.corruptor).then(|| {
+            let corruptor = options.corruptor.then(|| {
+                let seed = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map_or(0, |time| time.as_nanos() as u64);
+                corruptor::Corruptor::new(seed)
+            });
+            let mut debugger = debugger::Debugger::new(corruptor);
+            debugger.cheat_file = cheat_file.clone();
+            debugger.session_file = debugger::session_path(&rom_id);
+            if let Err(err) = debugger.load_session(&mut snes, &mut tas, &mut save_states) {
+                eprintln!("[warning] could not restore the debug session ({err})")
+            }
+            if let Some(path) = &options.debug_script {
+                let action = debugger
+                    .run_script(path, &mut snes, &mut tas, &mut save_states)
+                    .unwrap_or_else(|err| {
+                        error!(
+                            "Could not read debug script \"{}\" ({})",
+                            path.display(),
+                            err
+                        )
+                    });
+                if action == debugger::Action::Quit {
+                    std::process::exit(0)
+                }
+            }
+            debugger
+        });
+
+    let event_loop = EventLoop::new();
+    let window = create_window(
+        &event_loop,
+        profile.fullscreen,
+        &window_title(&title, practice.as_ref(), false),
+    );
+    let (instance, surf, device, queue, surf_config) = create_surface(&window);
+    let renderer = Renderer::new(&device, surf_config.format, &snes);
+
+    // the slot last chosen in the menu, saved and loaded by the combos
+    let state_slot = 1;
+    let mut menu = None;
+    // a game switched to by dropping its ROM can continue from its quick
+    // resume file
+    let resume_path = quick_resume::resume_path(&rom_id).filter(|path| path.exists());
+    if options.offer_resume && resume_path.is_some() && !snes.integrity_mode() {
+        let mut resume_menu = open_menu(&mut snes, state_slot);
+        resume_menu.offer_quick_resume();
+        menu = Some(resume_menu)
+    }
+    let has_mouse = [port1_profile.as_ref(), port2_profile.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|c| c.is_mouse());
+    let start_time = Instant::now();
+    let mut app = App {
+        gamepads: gamepad::Gamepads::new(&profile.gamepad_mappings, options.verbose),
+        input_test: options.test_input.then(input_test::InputTest::new),
+        touch_overlay: profile.touch_overlay.then(touch::TouchOverlay::new),
+        timer: profile
+            .timer
+            .then(|| timer::Timer::new(profile.igt, is_pal)),
+        screenshot_dir: profile
+            .screenshot_dir
+            .clone()
+            .or_else(screenshot::screenshot_dir),
+        recording_dir: profile
+            .recording_dir
+            .clone()
+            .or_else(recorder::recording_dir),
+        menu_combo: profile.menu_combo,
+        save_state_combo: profile.save_state_combo,
+        load_state_combo: profile.load_state_combo,
+        filter: profile.filter,
+        game_icon_pending: profile.game_icon,
+        capped_present: options.capped_present || profile.capped_present,
+        profile: profile.clone(),
+        profile_name: profile_name.to_owned(),
+        config_path: config.path().map(std::path::Path::to_path_buf),
+        ports: [port1_profile, port2_profile],
+        rom_id,
+        title,
+        snes,
+        is_pal,
+        window,
+        instance,
+        surf,
+        device,
+        queue,
+        surf_config,
+        renderer,
+        audio_stream,
+        audio_queued,
+        recording_samples,
+        voice_outputs,
+        governor,
+        run_ahead,
+        tas,
+        save_states,
+        practice,
+        netplay,
+        frontend,
+        debugger,
+        stats,
+        session_time,
+        autosave,
+        srm_file,
+        cheat_file,
+        music,
+        texture_loader,
+        frame_stream,
+        layer_dump,
+        scope: None,
+        notes: None,
+        menu,
+        recorder: None,
+        state_slot,
+        resume_path,
+        switch_to: None,
+        user_paused: false,
+        audio_paused: false,
+        mid_frame: false,
+        start_time,
+        next_graphics_update: start_time,
+        redraw_pending: false,
+        suspended: false,
+        focused: true,
+        update_screen_size: true,
+        cursor_pos: [0.0; 2],
+        has_mouse,
+        shift: [false; 2],
+        ctrl: [false; 2],
+        options,
+    };
+    app.grab_cursor();
+    event_loop.run(move |event, _, control_flow| app.handle_event(event, control_flow))
+}
+
+/// The title of the window with the status of the practice mode
+fn window_title(title: &str, practice: Option<&practice::Practice>, paused: bool) -> String {
+    let mut title = format!("{} - {}", env!("CARGO_PKG_NAME"), title);
+    if let Some(status) = practice.and_then(practice::Practice::status) {
+        title = format!("{title} ({status})")
+    }
+    if paused {
+        title.push_str(" [paused]")
+    }
+    title
+}
+
+/// The emulator with its window, which handles the events of the event loop
+struct App {
+    options: Options,
+    profile: config::Profile,
+    /// The combos assigned in the menu are written to this profile
+    profile_name: String,
+    config_path: Option<PathBuf>,
+    ports: [Option<config::ControllerProfile>; 2],
+    rom_id: String,
+    title: String,
+    snes: Device<AudioBackend, ArrayFrameBuffer>,
+    is_pal: bool,
+    window: Window,
+    instance: wgpu::Instance,
+    surf: wgpu::Surface,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    surf_config: wgpu::SurfaceConfiguration,
+    renderer: Renderer,
+    audio_stream: Option<cpal::Stream>,
+    audio_queued: Arc<AtomicUsize>,
+    recording_samples: recorder::SampleTap,
+    voice_outputs: scope::VoiceTap,
+    governor: rsnes::runner::Governor,
+    run_ahead: Option<RunAhead>,
+    tas: tas::Tas,
+    save_states: states::SaveStates,
+    practice: Option<practice::Practice>,
+    netplay: Option<Netplay>,
+    frontend: Option<frontend::Frontend>,
+    debugger: Option<debugger::Debugger>,
+    stats: stats::Stats,
+    session_time: Duration,
+    autosave: Option<autosave::AutoSave>,
+    srm_file: Option<srm::SrmFile>,
+    cheat_file: Option<PathBuf>,
+    music: Option<music::Music>,
+    texture_loader: Option<texture_pack::TextureLoader>,
+    frame_stream: Option<stream::FrameStream>,
+    layer_dump: Option<layer_dump::LayerDump>,
+    gamepads: gamepad::Gamepads,
+    touch_overlay: Option<touch::TouchOverlay>,
+    scope: Option<scope::Scope>,
+    notes: Option<notes::Notes>,
+    menu: Option<menu::Menu>,
+    input_test: Option<input_test::InputTest>,
+    timer: Option<timer::Timer>,
+    recorder: Option<recorder::Recorder>,
+    screenshot_dir: Option<PathBuf>,
+    recording_dir: Option<PathBuf>,
+    /// The slot last chosen in the menu, saved and loaded by the combos
+    state_slot: usize,
+    /// The quick resume file of this game, if it exists
+    resume_path: Option<PathBuf>,
+    /// The ROM dropped on the window, which the emulator is restarted with
+    switch_to: Option<PathBuf>,
+    menu_combo: u16,
+    save_state_combo: u16,
+    load_state_combo: u16,
+    filter: config::Filter,
+    /// Replace the icon with a thumbnail of the game (`game-icon = true`)
+    game_icon_pending: bool,
+    /// Paused by the pause key, not by the debugger or frontend
+    user_paused: bool,
+    audio_paused: bool,
+    /// The debugger stopped the emulation in the middle of a frame
+    mid_frame: bool,
+    start_time: Instant,
+    next_graphics_update: Instant,
+    /// With `capped-present`, the window is only redrawn after a frame or a
+    /// change of the overlays, instead of every `TIME_PER_GPU_FRAME`
+    capped_present: bool,
+    /// All redraws requested while handling the events of a loop iteration
+    /// are done at once
+    redraw_pending: bool,
+    /// The window must not be drawn while the application is suspended
+    suspended: bool,
+    focused: bool,
+    update_screen_size: bool,
+    /// The position of the cursor in the window, to inspect pixels
+    cursor_pos: [f64; 2],
+    has_mouse: bool,
+    /// The left and right shift and control keys are held
+    shift: [bool; 2],
+    ctrl: [bool; 2],
+}
+
+impl App {
+    fn handle_event(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
+        if !self.capped_present {
+            *control_flow = ControlFlow::Poll;
+        }
+        match event {
+            Event::WindowEvent { event, .. } => self.window_event(event, control_flow),
+            Event::DeviceEvent { event, .. } => match event {
+                DeviceEvent::Key(KeyboardInput {
+                    scancode, state, ..
+                }) if self.focused => {
+                    self.key(scancode, state == ElementState::Pressed, control_flow)
+                }
+                DeviceEvent::MouseMotion { delta: (dx, dy) } if self.focused => {
+                    for (port, port_cfg) in self.ports.iter().enumerate() {
+                        if let Some(port_cfg) = port_cfg {
+                            let controller = controller_mut(&mut self.snes.controllers, port);
+                            port_cfg.handle_mouse_move(dx, dy, controller);
+                        }
+                    }
+                }
+                _ => (),
+            },
+            Event::MainEventsCleared => self.update(control_flow),
+            Event::Suspended => self.suspended = true,
+            // winit sends `Resumed` at the start, too
+            Event::Resumed if self.suspended => {
+                self.suspended = false;
+                // the window, the surface was created for, is destroyed while
+                // suspended on Android
+                self.surf = unsafe { self.instance.create_surface(&self.window) };
+                self.surf.configure(&self.device, &self.surf_config);
+                self.redraw_pending = true
+            }
+            Event::RedrawRequested(_) if !self.suspended => self.redraw(),
+            Event::LoopDestroyed => self.exit(),
+            _ => (),
+        }
+    }
+
+    fn window_event(&mut self, event: WindowEvent, control_flow: &mut ControlFlow) {
+        match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            // the surface can't be configured with a size of zero,
+            // e.g. while the window is minimized
+            WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                if self.surf_config.width != size.width || self.surf_config.height != size.height {
+                    self.update_screen_size = true;
+                }
+                self.surf_config.width = size.width;
+                self.surf_config.height = size.height;
+                self.surf.configure(&self.device, &self.surf_config);
+                self.redraw_pending = true
+            }
+            WindowEvent::DroppedFile(path)
+                if path.extension() == Some(states::STATE_FILE_EXTENSION.as_ref()) =>
+            {
+                if self.netplay_active() {
+                    eprintln!("[warning] can't load states during netplay")
+                } else if let Err(err) = states::read_file(&path)
+                    .and_then(|state| states::load_checked(&mut self.snes, &state))
+                {
+                    eprintln!("[warning] could not load `{}` ({err})", path.display())
+                }
+            }
+            WindowEvent::DroppedFile(path)
+                if quick_resume::is_rom(&path) || archive::is_archive(&path) =>
+            {
+                self.switch_game(path, control_flow)
+            }
+            WindowEvent::Focused(focus) => {
+                self.grab_cursor();
+                self.focused = focus
+            }
+            WindowEvent::Touch(touch) => {
+                if let Some(touch_overlay) = &mut self.touch_overlay {
+                    let lines = u32::from(self.snes.ppu.vend() - 1);
+                    let mapping = self
+                        .renderer
+                        .screen_mapping([self.surf_config.width, self.surf_config.height], lines);
+                    touch_overlay.touch(&touch, &mapping);
+                    self.redraw_pending = true
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => self.cursor_pos = [position.x, position.y],
+            WindowEvent::MouseInput { button, state, .. } if self.focused => {
+                self.mouse_button(button, state == ElementState::Pressed)
+            }
+            _ => (),
+        }
+    }
+
+    /// Hide the cursor and keep it in the window, if a mouse is connected
+    fn grab_cursor(&self) {
+        if self.has_mouse {
+            self.window.set_cursor_grab(true).unwrap_or_else(|err| {
+                if self.options.verbose {
+                    eprintln!("[warning] cursor grab failed ({err})")
+                }
+            });
+            self.window.set_cursor_visible(false);
+        }
+    }
+
+    /// Loading states is refused while a netplay session is active
+    fn netplay_active(&self) -> bool {
+        self.netplay.as_ref().is_some_and(Netplay::is_active)
+    }
+
+    fn update_title(&self) {
+        self.window.set_title(&window_title(
+            &self.title,
+            self.practice.as_ref(),
+            self.user_paused,
+        ))
+    }
+
+    fn toggle_fullscreen(&self) {
+        self.window.set_fullscreen(match self.window.fullscreen() {
+            Some(_) => None,
+            None => Some(Fullscreen::Borderless(None)),
+        })
+    }
+
+    /// Exit and restart the emulator with the ROM `path` dropped on the
+    /// window. The current game is stashed in its quick resume file.
+    fn switch_game(&mut self, path: PathBuf, control_flow: &mut ControlFlow) {
+        if self.netplay.is_some()
+            || self.options.play_movie.is_some()
+            || self.options.record_movie.is_some()
+            || self.frontend.is_some()
+        {
+            eprintln!(
+                "[warning] the game can't be switched during netplay, movies or in frontend mode"
+            );
+            return;
+        }
+        if let Some(path) = quick_resume::resume_path(&self.rom_id) {
+            if let Err(err) = quick_resume::stash(&self.snes, &path) {
+                eprintln!(
+                    "[warning] could not write the quick resume file \"{}\" ({err})",
+                    path.display()
+                )
+            }
+        }
+        self.switch_to = Some(path);
+        *control_flow = ControlFlow::Exit
+    }
+
+    fn mouse_button(&mut self, button: MouseButton, pressed: bool) {
+        if let Some(input_test) = &mut self.input_test {
+            input_test.mouse_button(button, pressed);
+            self.redraw_pending = true
+        }
+        if let Some(debugger) = &self.debugger {
+            if pressed && button == MouseButton::Left && self.snes.ppu.pixel_sources.is_some() {
+                let lines = u32::from(self.snes.ppu.vend() - 1);
+                let mapping = self
+                    .renderer
+                    .screen_mapping([self.surf_config.width, self.surf_config.height], lines);
+                if let Some(pixel) = mapping.to_pixel(self.cursor_pos) {
+                    debugger.inspect_pixel(&self.snes, pixel)
+                }
+            }
+        }
+        for (port, port_cfg) in self.ports.iter().enumerate() {
+            if let Some(port_cfg) = port_cfg {
+                let controller = controller_mut(&mut self.snes.controllers, port);
+                port_cfg.handle_mouse_button(button, pressed, controller);
+            }
+        }
+    }
+
+    /// A key of the keyboard goes to the controller test, the pause menu,
+    /// the controllers and the hotkeys, in this order
+    fn key(&mut self, scancode: u32, pressed: bool, control_flow: &mut ControlFlow) {
+        if let Some(input_test) = &mut self.input_test {
+            let mapped =
+                self.ports.iter().enumerate().find_map(|(port, port_cfg)| {
+                    Some((port, port_cfg.as_ref()?.button(scancode)?))
+                });
+            input_test.key(scancode, pressed, mapped);
+            self.redraw_pending = true
+        }
+        // while the pause menu is open, it takes the buttons of port 1
+        let menu_button = self
+            .menu
+            .as_ref()
+            .and(self.ports[0].as_ref())
+            .and_then(|port_cfg| port_cfg.button(scancode));
+        if let Some(button) = menu_button {
+            let action = self
+                .menu
+                .as_mut()
+                .and_then(|menu| menu.button(button, pressed));
+            if let Some(action) = action {
+                self.menu_action(action, control_flow)
+            }
+            self.redraw_pending = true;
+            return;
+        }
+        let mut handled = false;
+        for (port, port_cfg) in self.ports.iter().enumerate() {
+            if let Some(port_cfg) = port_cfg {
+                let controller = controller_mut(&mut self.snes.controllers, port);
+                if port_cfg.handle_scancode(scancode, pressed, controller) {
+                    handled = true;
+                    break;
+                }
+            }
+        }
+        if handled {
+            if pressed {
+                self.check_combos()
+            }
+        } else {
+            self.hotkey(scancode, pressed)
+        }
+    }
+
+    /// The combos of port 1, the menu combo opens the pause menu. They
+    /// aren't checked on the test screen.
+    fn check_combos(&mut self) {
+        let held = match &self.snes.controllers.port1.controller {
+            rsnes::controller::Controller::Standard(cntrl) if self.input_test.is_none() => {
+                cntrl.pressed_buttons
+            }
+            _ => return,
+        };
+        let is_held = |combo: u16| combo > 0 && held & combo == combo;
+        if is_held(self.menu_combo) {
+            self.menu = Some(open_menu(&mut self.snes, self.state_slot));
+            self.redraw_pending = true
+        } else if is_held(self.save_state_combo) {
+            self.save_states.store(self.state_slot, &self.snes)
+        } else if is_held(self.load_state_combo) {
+            if self.netplay_active() {
+                eprintln!("[warning] can't load states during netplay")
+            } else if let Err(err) = self.save_states.restore(self.state_slot, &mut self.snes) {
+                eprintln!("[warning] {err}")
+            }
+        }
+    }
+
+    fn menu_action(&mut self, action: menu::Action, control_flow: &mut ControlFlow) {
+        match action {
+            menu::Action::Resume => self.menu = None,
+            menu::Action::QuickResume | menu::Action::Reset | menu::Action::LoadState(_)
+                if self.netplay_active() =>
+            {
+                eprintln!("[warning] can't load states or reset during netplay")
+            }
+            menu::Action::QuickResume => {
+                let resumed = self.resume_path.as_ref().map(|path| {
+                    states::read_file(path)
+                        .and_then(|state| states::load_checked(&mut self.snes, &state))
+                });
+                match resumed {
+                    Some(Ok(())) | None => self.menu = None,
+                    Some(Err(err)) => eprintln!("[warning] could not quick resume ({err})"),
+                }
+            }
+            menu::Action::Reset => {
+                self.snes.reset();
+                self.menu = None
+            }
+            menu::Action::SaveState(slot) => {
+                self.save_states.store(slot, &self.snes);
+                self.state_slot = slot;
+                self.menu = None
+            }
+            menu::Action::LoadState(slot) => {
+                self.state_slot = slot;
+                match self.save_states.restore(slot, &mut self.snes) {
+                    Ok(true) => self.menu = None,
+                    Ok(false) => eprintln!("[warning] save state slot {slot} is empty"),
+                    Err(err) => eprintln!("[warning] {err}"),
+                }
+            }
+            menu::Action::ToggleCheat(index) => {
+                let enabled = self.snes.cheats().get(index).map(|cheat| cheat.enabled);
+                match enabled.map(|enabled| self.snes.set_cheat(index, !enabled)) {
+                    Some(Ok(())) => {
+                        if let Some(path) = &self.cheat_file {
+                            if let Err(err) = cheats::save(&self.snes, path) {
+                                eprintln!(
+                                    "[warning] could not write the cheat file \"{}\" ({err})",
+                                    path.display()
+                                )
+                            }
+                        }
+                    }
+                    Some(Err(err)) => eprintln!("[warning] {err}"),
+                    None => (),
+                }
+            }
+            menu::Action::StepSpeed { faster } => {
+                let speed = menu::step_speed(self.governor.speed(), faster);
+                if let Err(err) = self.governor.set_speed(speed, &self.snes) {
+                    eprintln!("[warning] {err}")
+                }
+            }
+            menu::Action::ToggleTimer => {
+                self.timer = match self.timer {
+                    Some(_) => None,
+                    None => Some(timer::Timer::new(self.profile.igt, self.is_pal)),
+                }
+            }
+            menu::Action::TestInput => {
+                self.input_test = Some(input_test::InputTest::new());
+                self.menu = None
+            }
+            menu::Action::ToggleFilter => self.filter = self.filter.toggled(),
+            menu::Action::ToggleFullscreen => self.toggle_fullscreen(),
+            menu::Action::SetCombo(combo, buttons) => self.set_combo(combo, buttons),
+            menu::Action::Quit => *control_flow = ControlFlow::Exit,
+        }
+    }
+
+    /// Assign a combo in the menu and write it to the profile
+    fn set_combo(&mut self, combo: menu::Combo, buttons: u16) {
+        let key = match combo {
+            menu::Combo::Menu => {
+                self.menu_combo = buttons;
+                "menu-combo"
+            }
+            menu::Combo::SaveState => {
+                self.save_state_combo = buttons;
+                "save-state-combo"
+            }
+            menu::Combo::LoadState => {
+                self.load_state_combo = buttons;
+                "load-state-combo"
+            }
+        };
+        let value = config::format_combo(buttons);
+        match &self.config_path {
+            Some(path) => {
+                if let Err(err) =
+                    config::write_profile_option(path, &self.profile_name, key, &value)
+                {
+                    eprintln!("[warning] could not save the combo in the config file ({err})")
+                }
+            }
+            None => {
+                eprintln!("[warning] no config file, the combo is kept until the emulator exits")
+            }
+        }
+    }
+
+    /// The keys, which aren't mapped to a controller
+    fn hotkey(&mut self, scancode: u32, pressed: bool) {
+        let profile = &self.profile;
+        let (shift, ctrl) = (self.shift.contains(&true), self.ctrl.contains(&true));
+        match scancode {
+            key if key == profile.pause_key && pressed => {
+                self.user_paused ^= true;
+                self.update_title()
+            }
+            // pause first, then emulate a frame per press
+            key if key == profile.frame_advance_key && pressed => {
+                if !self.user_paused {
+                    self.user_paused = true;
+                    self.update_title()
+                } else if let Err(err) = self.governor.advance_frame(&self.snes) {
+                    eprintln!("[warning] {err}")
+                }
+            }
+            key if key == profile.screenshot_key && pressed => self.save_screenshot(),
+            key if key == profile.menu_key && pressed => {
+                self.menu = match self.menu {
+                    Some(_) => None,
+                    None => Some(open_menu(&mut self.snes, self.state_slot)),
+                };
+                self.redraw_pending = true
+            }
+            key if key == profile.input_test_key && pressed => {
+                self.input_test = match self.input_test {
+                    Some(_) => None,
+                    None => Some(input_test::InputTest::new()),
+                };
+                self.redraw_pending = true
+            }
+            key if key == profile.timer_key && pressed => {
+                if let Some(timer) = &mut self.timer {
+                    timer.toggle();
+                    self.redraw_pending = true
+                }
+            }
+            key if key == profile.timer_reset_key && pressed => {
+                if let Some(timer) = &mut self.timer {
+                    timer.reset();
+                    self.redraw_pending = true
+                }
+            }
+            key if key == profile.scope_key && pressed => {
+                self.scope = match self.scope {
+                    Some(_) => None,
+                    None => Some(scope::Scope::new(self.voice_outputs.clone())),
+                };
+                self.redraw_pending = true
+            }
+            key if key == profile.notes_key && pressed => {
+                self.notes = match self.notes {
+                    Some(_) => None,
+                    None => Some(notes::Notes::new(&self.snes)),
+                };
+                self.redraw_pending = true
+            }
+            key if key == profile.filter_key && pressed => {
+                self.filter = self.filter.toggled();
+                println!("[info] {} filtering", self.filter);
+                self.redraw_pending = true
+            }
+            key if key == profile.record_key && pressed => self.toggle_recording(),
+            key if key == profile.fullscreen_key && pressed => self.toggle_fullscreen(),
+            // Tab: turbo while held
+            0x0f => self.governor.set_turbo(pressed),
+            0x2a => self.shift[0] = pressed,
+            0x36 => self.shift[1] = pressed,
+            0x1d => self.ctrl[0] = pressed,
+            0x61 => self.ctrl[1] = pressed,
+            // Ctrl + 1-8: mute voice 0-7, Ctrl + Shift: solo
+            2..=9 if pressed && ctrl => {
+                let voice = (scancode - 2) as u8;
+                if shift {
+                    toggle_solo_voice(&mut self.snes, voice)
+                } else {
+                    toggle_voice_muted(&mut self.snes, voice)
+                }
+            }
+            // Ctrl + 0: unmute all voices
+            11 if pressed && ctrl => reset_voice_muting(&mut self.snes),
+            10 if ctrl => (),
+            // 1-0: save a slot, Shift: load it
+            2..=11 if pressed => {
+                let id = if scancode == 11 { 0 } else { scancode - 1 };
+                if !shift {
+                    self.save_states.store(id as usize, &self.snes)
+                } else if self.netplay_active() {
+                    eprintln!("[warning] can't load states during netplay")
+                } else if let Err(err) = self.save_states.restore(id as usize, &mut self.snes) {
+                    eprintln!("[warning] {err}")
+                }
+            }
+            // F1 - F10: movie bookmarks
+            0x3b..=0x44 if pressed && self.tas.movie.is_some() => {
+                let slot = (scancode - 0x3a) as usize % 10;
+                if shift {
+                    self.tas.set_bookmark(slot)
+                } else if let Err(err) = self.tas.jump_to_bookmark(&mut self.snes, slot) {
+                    eprintln!("[warning] {err}")
+                }
+            }
+            // F12: undo loading a slot
+            0x58 if pressed => {
+                if self.netplay_active() {
+                    eprintln!("[warning] can't load states during netplay")
+                } else if let Err(err) = self.save_states.undo(&mut self.snes) {
+                    eprintln!("[warning] {err}")
+                }
+            }
+            // F11: practice anchor
+            0x57 if pressed && self.practice.is_some() => {
+                let netplay_active = self.netplay_active();
+                let practice = self.practice.as_mut().unwrap();
+                if shift {
+                    practice.set_anchor(states::save(&self.snes))
+                } else if netplay_active {
+                    eprintln!("[warning] can't load states during netplay")
+                } else if let Err(err) = practice.reload(&mut self.snes) {
+                    eprintln!("[warning] {err}")
+                }
+                self.update_title()
+            }
+            _ => (),
+        }
+    }
+
+    fn save_screenshot(&self) {
+        match (self.screenshot_dir.as_ref()).map(|dir| screenshot::save(&self.snes, dir)) {
+            Some(Ok(path)) => println!("[info] saved screenshot \"{}\"", path.display()),
+            Some(Err(err)) => eprintln!("[warning] unable to save the screenshot ({err})"),
+            None => eprintln!("[warning] could not find the screenshot directory"),
+        }
+    }
+
+    /// Start a video recording or finish the running one
+    fn toggle_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            finish_recording(recorder)
+        } else if let Some(dir) = &self.recording_dir {
+            match recorder::Recorder::start(
+                recorder::recording_path(
+                    dir,
+                    self.snes.cartridge().map_or("", |cart| cart.title()),
+                ),
+                &self.snes,
+                self.is_pal,
+                self.recording_samples.clone(),
+                SAMPLE_RATE.0,
+            ) {
+                Ok(recorder) => {
+                    println!("[info] recording \"{}\"", recorder.path().display());
+                    self.recorder = Some(recorder)
+                }
+                Err(err) => eprintln!("[warning] unable to start recording ({err})"),
+            }
+        } else {
+            eprintln!("[warning] could not find the recording directory")
+        }
+    }
+
+    /// Poll the debugger and the frontend, emulate a frame if one is due and
+    /// request a redraw
+    fn update(&mut self, control_flow: &mut ControlFlow) {
+        let mut paused = false;
+        if let Some(debugger) = &mut self.debugger {
+            if debugger.poll(&mut self.snes, &mut self.tas, &mut self.save_states)
+                == debugger::Action::Quit
+            {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+            paused = debugger.paused;
+        }
+        if let Some(frontend) = &mut self.frontend {
+            if !frontend.poll(&mut self.snes, &mut self.save_states) {
+                *control_flow = ControlFlow::Exit;
+                return;
+            }
+            paused |= frontend.paused;
+        }
+        paused |=
+            self.user_paused || self.menu.is_some() || self.input_test.is_some() || self.suspended;
+        self.governor.set_paused(paused);
+        // stop playing the queued samples while paused
+        if let Some(audio_stream) =
+            (self.audio_stream.as_ref()).filter(|_| self.audio_paused != paused)
+        {
+            self.audio_paused = paused;
+            let result = if paused {
+                audio_stream.pause().map_err(|err| err.to_string())
+            } else {
+                audio_stream.play().map_err(|err| err.to_string())
+            };
+            if let Err(err) = result {
+                if self.options.verbose {
+                    eprintln!("[warning] could not pause or resume audio ({err})")
+                }
+            }
+        }
+        if let Some(debugger) = &mut self.debugger {
+            for _ in 0..core::mem::take(&mut debugger.advance) {
+                let _ = self.governor.advance_frame(&self.snes);
+            }
+        }
+        self.governor.set_audio_queued(Duration::from_micros(
+            self.audio_queued.load(Ordering::Relaxed) as u64 * 1_000_000
+                / (2 * u64::from(SAMPLE_RATE.0)),
+        ));
+        self.gamepads.apply(&mut self.snes.controllers);
+        if let Some(touch_overlay) = &mut self.touch_overlay {
+            touch_overlay.apply(controller_mut(&mut self.snes.controllers, 0))
+        }
+        if self.governor.should_run_frame(self.start_time.elapsed())
+            && (self.netplay.as_mut()).is_none_or(|netplay| netplay.before_frame(&mut self.snes))
+            && !self.emulate_frame()
+        {
+            return;
+        }
+        // the real time of the timer keeps running while paused
+        if paused && self.timer.as_ref().is_some_and(timer::Timer::is_running) {
+            self.redraw_pending |= self.capped_present
+        }
+        let now = Instant::now();
+        if self.capped_present {
+            *control_flow = match self.governor.sleep_until() {
+                Some(time) => ControlFlow::WaitUntil(self.start_time + time),
+                None if self.governor.turbo() => ControlFlow::Poll,
+                None => ControlFlow::WaitUntil(now + CAPPED_POLL_INTERVAL),
+            };
+        } else if now >= self.next_graphics_update {
+            self.redraw_pending = true;
+            self.next_graphics_update = now + TIME_PER_GPU_FRAME;
+        }
+        if core::mem::take(&mut self.redraw_pending) && !self.suspended {
+            self.window.request_redraw()
+        }
+    }
+
+    /// Emulate a frame and update everything, which follows the frames.
+    /// Returns `false`, if the debugger stopped the emulation in the middle
+    /// of the frame.
+    fn emulate_frame(&mut self) -> bool {
+        let snes = &mut self.snes;
+        // the frame was started before the debugger stopped it
+        if !core::mem::take(&mut self.mid_frame) {
+            self.tas.before_frame(snes);
+            if let Some(practice) = &mut self.practice {
+                practice.before_frame(snes)
+            }
+        }
+        let cycle_count = run_frame_until_break(snes);
+        if !snes.new_frame {
+            self.mid_frame = true;
+            if let Some(debugger) = &mut self.debugger {
+                debugger.on_break(snes)
+            }
+            return false;
+        }
+        if let Some(practice) = &mut self.practice {
+            practice.after_frame(snes)
+        }
+        if let Some(netplay) = &mut self.netplay {
+            netplay.after_frame(snes)
+        }
+        if let (Some(loader), Some(pack)) = (&mut self.texture_loader, &mut snes.ppu.texture_pack) {
+            loader.update(pack)
+        }
+        if let Some(music) = &mut self.music {
+            music.on_frame(snes)
+        }
+        if let Some(frame_stream) = &mut self.frame_stream {
+            frame_stream.on_frame(snes)
+        }
+        if let Some(layer_dump) = &mut self.layer_dump {
+            layer_dump.on_frame(snes)
+        }
+        if let Some(scope) = &mut self.scope {
+            scope.on_frame()
+        }
+        if let Some(notes) = &mut self.notes {
+            notes.update(snes)
+        }
+        if let Some(timer) = &mut self.timer {
+            timer.update(snes)
+        }
+        if let Some(active) = &mut self.recorder {
+            if let Err(err) = active.on_frame(snes, cycle_count) {
+                eprintln!("[warning] stopped recording ({err})");
+                finish_recording(self.recorder.take().unwrap())
+            }
+        }
+        if self.game_icon_pending {
+            if let Some(game_icon) = icon::game_icon(snes) {
+                #[cfg(target_os = "windows")]
+                {
+                    use winit::platform::windows::WindowExtWindows;
+                    self.window.set_taskbar_icon(Some(game_icon.clone()))
+                }
+                self.window.set_window_icon(Some(game_icon));
+                self.game_icon_pending = false
+            }
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.on_frame(snes);
+        }
+        if let Some(frontend) = &mut self.frontend {
+            frontend.on_frame();
+        }
+        if let Some(run_ahead) = &self.run_ahead {
+            if let Err(err) = run_ahead.run(snes, |snes| {
+                run_frame(snes);
+            }) {
+                eprintln!("[warning] {err}")
+            }
+        }
+        let frame_time =
+            self.governor
+                .frame_done(cycle_count, snes.is_pal(), self.start_time.elapsed());
+        self.session_time += frame_time;
+        if let Some(autosave) = &mut self.autosave {
+            autosave.on_frame(snes, frame_time)
+        }
+        if let (Some(srm_file), Some(cartridge)) = (&mut self.srm_file, snes.cartridge_mut()) {
+            srm_file.on_frame(cartridge, frame_time)
+        }
+        // present every new picture once
+        self.redraw_pending |= self.capped_present;
+        true
+    }
+
+    /// Draw the picture with the overlays in the window
+    fn redraw(&mut self) {
+        let surface_texture = match self.surf.get_current_texture() {
+            Ok(surface_texture) => surface_texture,
+            Err(wgpu::SurfaceError::Timeout) => {
+                if self.options.verbose {
+                    eprintln!("[warning] surface acquire timeout");
+                }
+                return;
+            }
+            Err(err) => error!("Failed to acquire next swap chain texture ({})", err),
+        };
+        if self.snes.ppu.frame_buffer.1 {
+            let (width, height) = self.renderer.texture_size();
+            let picture = match &self.snes.ppu.hd_frame {
+                Some(hd_frame) => hd_frame.get_bytes(),
+                None => self.snes.ppu.frame_buffer.get_bytes(),
+            };
+            let overlay;
+            let picture = match &self.scope {
+                Some(scope) => {
+                    overlay = scope.draw(picture, width, height);
+                    &overlay
+                }
+                None => picture,
+            };
+            let notes_overlay;
+            let picture = match &self.notes {
+                Some(notes) => {
+                    notes_overlay = notes.draw(picture, width, height);
+                    &notes_overlay
+                }
+                None => picture,
+            };
+            let timer_overlay;
+            let picture = match &self.timer {
+                Some(timer) => {
+                    timer_overlay = timer.draw(picture, width, height);
+                    &timer_overlay
+                }
+                None => picture,
+            };
+            let touch_picture;
+            let picture = match &self.touch_overlay {
+                Some(touch_overlay) => {
+                    touch_picture = touch_overlay.draw(picture, width, height);
+                    &touch_picture
+                }
+                None => picture,
+            };
+            let menu_overlay;
+            let picture = match &self.menu {
+                Some(menu) => {
+                    let settings = menu::Settings {
+                        filter: self.filter,
+                        fullscreen: self.window.fullscreen().is_some(),
+                        speed: self.governor.speed(),
+                        timer: self.timer.is_some(),
+                        cheats: self.snes.cheats(),
+                        menu_combo: self.menu_combo,
+                        save_state_combo: self.save_state_combo,
+                        load_state_combo: self.load_state_combo,
+                    };
+                    menu_overlay = menu.draw(picture, width, height, &settings);
+                    &menu_overlay
+                }
+                None => picture,
+            };
+            let input_test_overlay;
+            let picture = match &self.input_test {
+                Some(input_test) => {
+                    input_test_overlay =
+                        input_test.draw(picture, width, height, &self.snes.controllers);
+                    &input_test_overlay
+                }
+                None => picture,
+            };
+            self.renderer.upload(&self.queue, picture);
+            if core::mem::take(&mut self.update_screen_size) {
+                self.renderer.set_target_size(
+                    &self.queue,
+                    [self.surf_config.width, self.surf_config.height],
+                    u32::from(self.snes.ppu.vend() - 1),
+                );
+            }
+        }
+
+        let frame = &surface_texture.texture;
+        let view = frame.create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        self.renderer
+            .render(&mut encoder, &view, self.filter.filter_mode());
+        self.queue.submit(Some(encoder.finish()));
+        surface_texture.present();
+    }
+
+    /// Save everything before the emulator exits, or restarts with the ROM
+    /// dropped on the window
+    fn exit(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            finish_recording(recorder)
+        }
+        save_recorded_movie(&mut self.tas, self.options.record_movie.as_ref());
+        if let Some(netplay) = self.netplay.take() {
+            if self.options.verbose {
+                println!("[info] netplay hashes: {}", netplay.worker_stats())
+            }
+            netplay.leave()
+        }
+        // the pending automatic state files are written first
+        if let Some(autosave) = self.autosave.take() {
+            if self.options.verbose {
+                println!("[info] automatic state files: {}", autosave.worker_stats())
+            }
+        }
+        // stdout only contains status lines in frontend mode
+        save_on_exit(
+            &mut self.snes,
+            self.srm_file.as_mut(),
+            &mut self.stats,
+            &self.rom_id,
+            self.session_time,
+            self.frontend.is_none(),
+        );
+        if let Some(rom) = self.switch_to.take() {
+            let err = quick_resume::restart(switch_args(&self.options, &rom));
+            error!("Could not restart with \"{}\" ({})", rom.display(), err)
+        }
+    }
+}
@@ -0,0 +1,397 @@
+//! The pause menu (menu key, or the menu combo, Start and Select by default)
+//!
+//! While the menu is open, the emulation is paused and the buttons of the
+//! controller in port 1 navigate it instead of reaching the game: up and down
+//! select an item, left and right change the save state slot or the speed, A
+//! or Start activates the item and B closes the menu. So the common actions
+//! don't need the keyboard, e.g. on a couch in front of a TV. The menu is
+//! drawn over the picture with a built-in font.
+//!
+//! The combos, which open the menu and save and load states while the game
+//! runs, are assigned in the menu, too: after activating a combo item, the
+//! buttons held together until all are released again become the combo. A
+//! combo needs at least two buttons, so it doesn't take a single button from
+//! the game.
+
+use crate::config::{button_names, Filter};
+use rsnes::{cheat::Cheat, controller::buttons};
+
+/// An action selected in the menu, performed by the caller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Resume,
+    /// Continue the game from its quick resume file (see
+    /// [`crate::quick_resume`])
+    QuickResume,
+    Reset,
+    SaveState(usize),
+    LoadState(usize),
+    /// Enable or disable the cheat with the index
+    ToggleCheat(usize),
+    /// Switch to the next higher or lower speed of [`SPEEDS`]
+    StepSpeed {
+        faster: bool,
+    },
+    ToggleTimer,
+    /// Open the controller test screen
+    TestInput,
+    ToggleFilter,
+    ToggleFullscreen,
+    /// A combo was captured, the buttons are a mask of
+    /// [`rsnes::controller::buttons`]
+    SetCombo(Combo, u16),
+    Quit,
+}
+
+/// The actions, which are assigned to combos of buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combo {
+    Menu,
+    SaveState,
+    LoadState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Item {
+    Resume,
+    QuickResume,
+    Reset,
+    SaveState,
+    LoadState,
+    Cheat(usize),
+    Combo(Combo),
+    Speed,
+    Timer,
+    InputTest,
+    Filter,
+    Fullscreen,
+    Quit,
+}
+
+/// The items, the cheats of the game are inserted after loading states
+static ITEMS: [Item; 13] = [
+    Item::Resume,
+    Item::Reset,
+    Item::SaveState,
+    Item::LoadState,
+    Item::Combo(Combo::Menu),
+    Item::Combo(Combo::SaveState),
+    Item::Combo(Combo::LoadState),
+    Item::Speed,
+    Item::Timer,
+    Item::InputTest,
+    Item::Filter,
+    Item::Fullscreen,
+    Item::Quit,
+];
+
+/// The speeds selectable in the menu, as fractions of the original speed
+pub static SPEEDS: [f64; 8] = [0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 3.0, 4.0];
+
+/// The next higher or lower speed of [`SPEEDS`] after `speed`
+pub fn step_speed(speed: f64, faster: bool) -> f64 {
+    if faster {
+        SPEEDS
+            .into_iter()
+            .find(|&step| step > speed)
+            .unwrap_or(SPEEDS[7])
+    } else {
+        SPEEDS
+            .into_iter()
+            .rev()
+            .find(|&step| step < speed)
+            .unwrap_or(SPEEDS[0])
+    }
+}
+
+/// The longest description of a cheat shown in the menu
+const MAX_CHEAT_NAME: usize = 24;
+
+/// The settings shown with their current values
+pub struct Settings<'a> {
+    pub filter: Filter,
+    pub fullscreen: bool,
+    /// The speed as a fraction of the original speed
+    pub speed: f64,
+    pub timer: bool,
+    pub cheats: &'a [Cheat],
+    pub menu_combo: u16,
+    pub save_state_combo: u16,
+    pub load_state_combo: u16,
+}
+
+/// The height of a character in pixels without spacing, it's 5 pixels wide
+const GLYPH_HEIGHT: usize = 7;
+/// The width and height of a character in pixels with spacing
+pub const CELL_SIZE: [usize; 2] = [6, 12];
+
+pub const TEXT_COLOR: [u8; 3] = [0xb0; 3];
+pub const SELECTED_COLOR: [u8; 3] = [0xff, 0xe0, 0x60];
+
+/// The columns of a character, the lowest bit is the top row
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0x3e, 0x51, 0x49, 0x45, 0x3e],
+        '1' => [0x00, 0x42, 0x7f, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4b, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7f, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3c, 0x4a, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1e],
+        ':' => [0x00, 0x36, 0x36, 0x00, 0x00],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '+' => [0x08, 0x08, 0x3e, 0x08, 0x08],
+        '#' => [0x14, 0x7f, 0x14, 0x7f, 0x14],
+        '%' => [0x23, 0x13, 0x08, 0x64, 0x62],
+        '<' => [0x08, 0x14, 0x22, 0x41, 0x00],
+        '>' => [0x00, 0x41, 0x22, 0x14, 0x08],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        'x' => [0x44, 0x28, 0x10, 0x28, 0x44],
+        'A' => [0x7e, 0x11, 0x11, 0x11, 0x7e],
+        'B' => [0x7f, 0x49, 0x49, 0x49, 0x36],
+        'C' => [0x3e, 0x41, 0x41, 0x41, 0x22],
+        'D' => [0x7f, 0x41, 0x41, 0x22, 0x1c],
+        'E' => [0x7f, 0x49, 0x49, 0x49, 0x41],
+        'F' => [0x7f, 0x09, 0x09, 0x01, 0x01],
+        'G' => [0x3e, 0x41, 0x49, 0x49, 0x7a],
+        'H' => [0x7f, 0x08, 0x08, 0x08, 0x7f],
+        'I' => [0x00, 0x41, 0x7f, 0x41, 0x00],
+        'J' => [0x20, 0x40, 0x41, 0x3f, 0x01],
+        'K' => [0x7f, 0x08, 0x14, 0x22, 0x41],
+        'L' => [0x7f, 0x40, 0x40, 0x40, 0x40],
+        'M' => [0x7f, 0x02, 0x0c, 0x02, 0x7f],
+        'N' => [0x7f, 0x04, 0x08, 0x10, 0x7f],
+        'O' => [0x3e, 0x41, 0x41, 0x41, 0x3e],
+        'P' => [0x7f, 0x09, 0x09, 0x09, 0x06],
+        'Q' => [0x3e, 0x41, 0x51, 0x21, 0x5e],
+        'R' => [0x7f, 0x09, 0x19, 0x29, 0x46],
+        'S' => [0x46, 0x49, 0x49, 0x49, 0x31],
+        'T' => [0x01, 0x01, 0x7f, 0x01, 0x01],
+        'U' => [0x3f, 0x40, 0x40, 0x40, 0x3f],
+        'V' => [0x1f, 0x20, 0x40, 0x20, 0x1f],
+        'W' => [0x3f, 0x40, 0x38, 0x40, 0x3f],
+        'X' => [0x63, 0x14, 0x08, 0x14, 0x63],
+        'Y' => [0x07, 0x08, 0x70, 0x08, 0x07],
+        'Z' => [0x61, 0x51, 0x49, 0x45, 0x43],
+        _ => [0; 5],
+    }
+}
+
+/// A combo being captured
+struct Capture {
+    combo: Combo,
+    held: u16,
+    /// All buttons held since the capture started
+    buttons: u16,
+}
+
+pub struct Menu {
+    items: Vec<Item>,
+    selected: usize,
+    /// The save state slot of the save and load items
+    slot: usize,
+    capture: Option<Capture>,
+}
+
+impl Menu {
+    /// Open the menu with a save state slot selected and an item for each of
+    /// the `cheats` cheats of the game
+    pub fn new(slot: usize, cheats: usize) -> Self {
+        let mut items = ITEMS.to_vec();
+        let pos = ITEMS
+            .iter()
+            .position(|&item| item == Item::LoadState)
+            .unwrap()
+            + 1;
+        items.splice(pos..pos, (0..cheats).map(Item::Cheat));
+        Self {
+            items,
+            selected: 0,
+            slot,
+            capture: None,
+        }
+    }
+
+    /// Add an item to continue from the quick resume file and select it
+    pub fn offer_quick_resume(&mut self) {
+        self.items.insert(0, Item::QuickResume);
+        self.selected = 0;
+    }
+
+    /// Handle a pressed or released button of the controller. Returns the
+    /// action to perform, if an item was activated.
+    pub fn button(&mut self, button: u16, pressed: bool) -> Option<Action> {
+        if let Some(capture) = &mut self.capture {
+            if pressed {
+                capture.held |= button;
+                capture.buttons |= button;
+                return None;
+            }
+            capture.held &= !button;
+            if capture.held > 0 || capture.buttons == 0 {
+                return None;
+            }
+            // a single button starts the capture over
+            let buttons = core::mem::take(&mut capture.buttons);
+            if buttons.count_ones() < 2 {
+                return None;
+            }
+            let combo = capture.combo;
+            self.capture = None;
+            return Some(Action::SetCombo(combo, buttons));
+        } else if !pressed {
+            return None;
+        }
+        let item = self.items[self.selected];
+        let count = self.items.len();
+        let has_slot = matches!(item, Item::SaveState | Item::LoadState);
+        match button {
+            buttons::UP => self.selected = (self.selected + count - 1) % count,
+            buttons::DOWN => self.selected = (self.selected + 1) % count,
+            buttons::LEFT if has_slot => self.slot = (self.slot + 9) % 10,
+            buttons::RIGHT if has_slot => self.slot = (self.slot + 1) % 10,
+            buttons::LEFT | buttons::RIGHT if item == Item::Speed => {
+                return Some(Action::StepSpeed {
+                    faster: button == buttons::RIGHT,
+                })
+            }
+            buttons::A | buttons::START => {
+                return Some(match item {
+                    Item::Resume => Action::Resume,
+                    Item::QuickResume => Action::QuickResume,
+                    Item::Reset => Action::Reset,
+                    Item::SaveState => Action::SaveState(self.slot),
+                    Item::LoadState => Action::LoadState(self.slot),
+                    Item::Cheat(index) => Action::ToggleCheat(index),
+                    Item::Combo(combo) => {
+                        self.capture = Some(Capture {
+                            combo,
+                            held: 0,
+                            buttons: 0,
+                        });
+                        return None;
+                    }
+                    Item::Speed => return None,
+                    Item::Timer => Action::ToggleTimer,
+                    Item::InputTest => Action::TestInput,
+                    Item::Filter => Action::ToggleFilter,
+                    Item::Fullscreen => Action::ToggleFullscreen,
+                    Item::Quit => Action::Quit,
+                })
+            }
+            buttons::B => return Some(Action::Resume),
+            _ => (),
+        }
+        None
+    }
+
+    fn label(&self, item: Item, settings: &Settings) -> String {
+        match item {
+            Item::Resume => "RESUME".to_owned(),
+            Item::QuickResume => "QUICK RESUME".to_owned(),
+            Item::Reset => "RESET".to_owned(),
+            Item::SaveState => format!("SAVE STATE  < {} >", self.slot),
+            Item::LoadState => format!("LOAD STATE  < {} >", self.slot),
+            Item::Cheat(index) => match settings.cheats.get(index) {
+                Some(cheat) => {
+                    let name = match cheat.description.as_str() {
+                        "" => cheat.code.to_string(),
+                        description => description.chars().take(MAX_CHEAT_NAME).collect(),
+                    };
+                    let state = if cheat.enabled { "ON" } else { "OFF" };
+                    format!("CHEAT {name}: {state}").to_uppercase()
+                }
+                // removed with the debugger, while the menu is open
+                None => "CHEAT REMOVED".to_owned(),
+            },
+            Item::Combo(combo) => {
+                let (name, buttons) = match combo {
+                    Combo::Menu => ("MENU", settings.menu_combo),
+                    Combo::SaveState => ("SAVE", settings.save_state_combo),
+                    Combo::LoadState => ("LOAD", settings.load_state_combo),
+                };
+                let buttons = match &self.capture {
+                    Some(capture) if capture.combo == combo => "HOLD BUTTONS".to_owned(),
+                    _ if buttons == 0 => "NONE".to_owned(),
+                    _ => button_names(buttons).join("+").to_uppercase(),
+                };
+                format!("{name} COMBO: {buttons}")
+            }
+            Item::Speed => format!("SPEED  < {}% >", (settings.speed * 100.0).round()),
+            Item::Timer => format!("TIMER: {}", if settings.timer { "ON" } else { "OFF" }),
+            Item::InputTest => "TEST CONTROLLERS".to_owned(),
+            Item::Filter => format!("FILTER: {}", settings.filter).to_uppercase(),
+            Item::Fullscreen => {
+                format!(
+                    "FULLSCREEN: {}",
+                    if settings.fullscreen { "ON" } else { "OFF" }
+                )
+            }
+            Item::Quit => "QUIT".to_owned(),
+        }
+    }
+
+    /// Draw the menu over a picture of RGBA pixels
+    pub fn draw(&self, picture: &[u8], width: u32, height: u32, settings: &Settings) -> Vec<u8> {
+        let mut out = picture.to_vec();
+        let (width, height) = (width as usize, height as usize);
+        // the picture of HD Mode 7 is scaled up, the menu is scaled with it
+        let scale = (width / rsnes::ppu::SCREEN_WIDTH as usize).max(1);
+        let mut lines = vec![
+            ("PAUSED".to_owned(), TEXT_COLOR),
+            (String::new(), TEXT_COLOR),
+        ];
+        for (i, &item) in self.items.iter().enumerate() {
+            let (prefix, color) = if i == self.selected {
+                ("> ", SELECTED_COLOR)
+            } else {
+                ("  ", TEXT_COLOR)
+            };
+            lines.push((prefix.to_owned() + &self.label(item, settings), color));
+        }
+        let columns = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        let [cell_width, cell_height] = CELL_SIZE.map(|len| len * scale);
+        let left = width.saturating_sub(columns * cell_width) / 2;
+        let top = height.saturating_sub(lines.len() * cell_height) / 2;
+        // darken the picture, so the text is readable on every picture
+        for pixel in out.chunks_exact_mut(4) {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+            pixel.copy_from_slice(&[r / 4, g / 4, b / 4, 0xff]);
+        }
+        for (row, (line, color)) in lines.iter().enumerate() {
+            let pos = [left, top + row * cell_height];
+            draw_text(&mut out, width, pos, scale, line, *color);
+        }
+        out
+    }
+}
+
+/// Draw a line of `text` with the built-in font into a picture of RGBA
+/// pixels, `width` pixels wide. The text starts at `x`, `y` and every pixel
+/// of the font is drawn as a block of `scale` x `scale` pixels.
+pub fn draw_text(
+    out: &mut [u8],
+    width: usize,
+    [x, y]: [usize; 2],
+    scale: usize,
+    text: &str,
+    color: [u8; 3],
+) {
+    for (column, c) in text.chars().enumerate() {
+        let x = x + column * CELL_SIZE[0] * scale;
+        for (dx, bits) in glyph(c).into_iter().enumerate() {
+            for dy in (0..GLYPH_HEIGHT).filter(|dy| bits >> dy & 1 > 0) {
+                for py in y + dy * scale..y + (dy + 1) * scale {
+                    let start = (py * width + x + dx * scale) * 4;
+                    let end = (start + scale * 4).min(out.len());
+                    for pixel in out[start.min(end)..end].chunks_exact_mut(4) {
+                        pixel.copy_from_slice(&[color[0], color[1], color[2], 0xff]);
+                    }
+                }
+            }
+        }
+    }
+}
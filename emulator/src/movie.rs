@@ -0,0 +1,268 @@
+//! Input movies
+//!
+//! A movie is a list of controller inputs, one entry per frame and port,
+//! starting from power-on or from a save state, its anchor.
+//! Movies are stored as text files where each frame is a line like
+//! `|UDLRSsYBXAlr|............|` with a `.` in place of a released button:
+//!
+//! ```text
+//! # rsnes movie
+//! !version 2
+//! !title SUPER MARIOWORLD
+//! !start anchor
+//! |....S.......|............|
+//! ...
+//! ```
+//!
+//! The anchor is saved next to the movie file as a state file with the
+//! extension `.anchor`. Movies are played back in the deterministic mode of
+//! the core, so the input of a frame is exactly what the game latches in
+//! that frame (see [`rsnes::deterministic`]). Files without a version are
+//! version 1 movies, which always start from power-on.
+
+use crate::movie_import::ImportError;
+use crate::states::{self, StateFileError};
+use rsnes::controller::{buttons, Controller, ControllerPorts};
+use std::path::{Path, PathBuf};
+
+/// The order and characters of the buttons in a movie file
+pub static BUTTON_CHARS: [(u16, char); 12] = [
+    (buttons::UP, 'U'),
+    (buttons::DOWN, 'D'),
+    (buttons::LEFT, 'L'),
+    (buttons::RIGHT, 'R'),
+    (buttons::START, 'S'),
+    (buttons::SELECT, 's'),
+    (buttons::Y, 'Y'),
+    (buttons::B, 'B'),
+    (buttons::X, 'X'),
+    (buttons::A, 'A'),
+    (buttons::L, 'l'),
+    (buttons::R, 'r'),
+];
+
+static MOVIE_MAGIC: &str = "# rsnes movie";
+
+/// The version of the movie file format written by this emulator
+pub const MOVIE_VERSION: u32 = 2;
+
+/// The pressed buttons of both controller ports in one frame
+pub type FrameInput = [u16; 2];
+
+#[derive(Debug)]
+pub enum MovieLoadError {
+    Io(std::io::Error),
+    /// The file does not start with the magic line of the expected file type
+    Magic(&'static str),
+    Syntax {
+        line: usize,
+        content: String,
+    },
+    /// The movie file is newer than this emulator
+    Version(u32),
+    /// The anchor state of the movie could not be read
+    Anchor(StateFileError),
+    /// A movie of another emulator could not be converted
+    Import(ImportError),
+}
+
+impl From<std::io::Error> for MovieLoadError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl std::fmt::Display for MovieLoadError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(fmt, "unable to read file ({err})"),
+            Self::Magic(kind) => write!(fmt, "not a rsnes {kind} file"),
+            Self::Syntax { line, content } => {
+                write!(fmt, "invalid syntax in line {line}: \"{content}\"")
+            }
+            Self::Version(version) => write!(fmt, "unsupported movie version {version}"),
+            Self::Anchor(err) => write!(fmt, "unable to read the anchor state ({err})"),
+            Self::Import(err) => write!(fmt, "unable to import the movie ({err})"),
+        }
+    }
+}
+
+impl std::error::Error for MovieLoadError {}
+
+/// Format the pressed buttons of one controller as e.g. `U.....Y.....`
+pub fn format_buttons(pressed: u16) -> String {
+    BUTTON_CHARS
+        .iter()
+        .map(|&(button, c)| if pressed & button > 0 { c } else { '.' })
+        .collect()
+}
+
+/// Parse buttons either in the fixed movie file format (`U.....Y.....`)
+/// or as a list of button characters (`UY`). A single `.` means no buttons.
+pub fn parse_buttons(text: &str) -> Option<u16> {
+    let mut pressed = 0;
+    for c in text.chars() {
+        if c != '.' {
+            pressed |= button_from_char(c)?;
+        }
+    }
+    Some(pressed)
+}
+
+/// Format the input of a frame as a movie file line
+pub fn format_frame(input: &FrameInput) -> String {
+    let mut line = String::from("|");
+    for pressed in input {
+        line.push_str(&format_buttons(*pressed));
+        line.push('|');
+    }
+    line
+}
+
+/// Parse a movie file line like `|U.....Y.....|............|`
+pub fn parse_frame(line: &str) -> Option<FrameInput> {
+    let mut ports = line
+        .strip_prefix('|')?
+        .strip_suffix('|')?
+        .split('|')
+        .map(parse_buttons);
+    let mut input = FrameInput::default();
+    for pressed in input.iter_mut() {
+        *pressed = ports.next().flatten()?;
+    }
+    ports.next().is_none().then_some(input)
+}
+
+/// Get the character used for a button
+pub fn button_from_char(c: char) -> Option<u16> {
+    BUTTON_CHARS
+        .iter()
+        .find(|(_, bc)| *bc == c)
+        .map(|(button, _)| *button)
+}
+
+/// Get the currently pressed buttons of the standard controllers
+pub fn read_input(controllers: &ControllerPorts) -> FrameInput {
+    [&controllers.port1, &controllers.port2].map(|port| match &port.controller {
+        Controller::Standard(cntrl) => cntrl.pressed_buttons,
+        _ => 0,
+    })
+}
+
+/// Overwrite the pressed buttons of the standard controllers
+pub fn write_input(controllers: &mut ControllerPorts, input: FrameInput) {
+    for (port, pressed) in [&mut controllers.port1, &mut controllers.port2]
+        .into_iter()
+        .zip(input)
+    {
+        if let Controller::Standard(cntrl) = &mut port.controller {
+            cntrl.pressed_buttons = pressed
+        }
+    }
+}
+
+/// Get the path of the anchor state file belonging to a movie file
+pub fn anchor_path(movie_path: &Path) -> PathBuf {
+    movie_path.with_extension("anchor")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Movie {
+    pub title: String,
+    pub frames: Vec<FrameInput>,
+    /// The state the movie starts from, `None` for power-on
+    pub anchor: Option<Vec<u8>>,
+}
+
+impl Movie {
+    pub fn new(title: String, anchor: Option<Vec<u8>>) -> Self {
+        Self {
+            title,
+            frames: vec![],
+            anchor,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieLoadError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let mut lines = content.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim_end()) != Some(MOVIE_MAGIC) {
+            return Err(MovieLoadError::Magic("movie"));
+        }
+        let mut movie = Self::default();
+        for (i, line) in lines {
+            let line = line.trim();
+            let syntax_err = || MovieLoadError::Syntax {
+                line: i + 1,
+                content: line.to_owned(),
+            };
+            if let Some(title) = line.strip_prefix("!title ") {
+                movie.title = title.to_owned();
+            } else if let Some(version) = line.strip_prefix("!version ") {
+                let version: u32 = version.parse().map_err(|_| syntax_err())?;
+                if version > MOVIE_VERSION {
+                    return Err(MovieLoadError::Version(version));
+                }
+            } else if let Some(start) = line.strip_prefix("!start ") {
+                movie.anchor = match start {
+                    "power-on" => None,
+                    "anchor" => {
+                        Some(states::read_file(anchor_path(path)).map_err(MovieLoadError::Anchor)?)
+                    }
+                    _ => return Err(syntax_err()),
+                }
+            } else if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            } else {
+                let input = parse_frame(line).ok_or_else(syntax_err)?;
+                movie.frames.push(input);
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let start = match &self.anchor {
+            Some(anchor) => {
+                states::write_file(anchor_path(path), anchor)?;
+                "anchor"
+            }
+            None => "power-on",
+        };
+        let mut content = format!(
+            "{MOVIE_MAGIC}\n!version {MOVIE_VERSION}\n!title {}\n!start {start}\n",
+            self.title
+        );
+        for input in &self.frames {
+            content.push_str(&format_frame(input));
+            content.push('\n');
+        }
+        std::fs::write(path, content)
+    }
+
+    /// Insert `count` frames without any input in front of `frame`
+    pub fn insert(&mut self, frame: usize, count: usize) {
+        let frame = frame.min(self.frames.len());
+        self.frames.splice(
+            frame..frame,
+            std::iter::repeat_n(FrameInput::default(), count),
+        );
+    }
+
+    /// Remove `count` frames starting with `frame`
+    pub fn delete(&mut self, frame: usize, count: usize) {
+        let start = frame.min(self.frames.len());
+        let end = frame.saturating_add(count).min(self.frames.len());
+        self.frames.drain(start..end);
+    }
+
+    /// Get a mutable reference to the input of `frame`, extending the movie if necessary
+    pub fn input_mut(&mut self, frame: usize) -> &mut FrameInput {
+        if frame >= self.frames.len() {
+            self.frames.resize(frame + 1, FrameInput::default());
+        }
+        &mut self.frames[frame]
+    }
+}
@@ -0,0 +1,268 @@
+//! Import of the input movies of other emulators
+//!
+//! Existing SNES TASes can be played back to compare the accuracy of the
+//! emulation. Both supported formats are ZIP archives:
+//!
+//! - lsnes (`.lsmv`): the `input` file has a line per subframe. A line
+//!   starting with `F` starts a new frame and the system field is followed by
+//!   a field per controller, e.g. `F.|B...u.......|`, with the buttons in the
+//!   order `BYsSudlrAXLR`.
+//! - BizHawk (`.bk2`): `Input Log.txt` has a line per frame like
+//!   `|..|UDLRsSYBXAlr|............|`, whose columns are named by its
+//!   `LogKey` line.
+//!
+//! rsnes movies hold a single input per frame, which starts from power-on
+//! with empty save RAM. Resets, subframe inputs differing from the input of
+//! their frame, movies starting from a savestate or save RAM and controllers
+//! other than the standard controller can't be represented and are rejected.
+
+use crate::movie::{FrameInput, Movie};
+use crate::zip::{Archive, ZipError};
+use rsnes::controller::buttons;
+use std::path::Path;
+
+/// The order of the buttons in the controller fields of lsnes movies
+static LSNES_BUTTONS: [u16; 12] = [
+    buttons::B,
+    buttons::Y,
+    buttons::SELECT,
+    buttons::START,
+    buttons::UP,
+    buttons::DOWN,
+    buttons::LEFT,
+    buttons::RIGHT,
+    buttons::A,
+    buttons::X,
+    buttons::L,
+    buttons::R,
+];
+
+/// The names of the buttons in the `LogKey` of BizHawk movies
+static BIZHAWK_BUTTONS: [(&str, u16); 12] = [
+    ("Up", buttons::UP),
+    ("Down", buttons::DOWN),
+    ("Left", buttons::LEFT),
+    ("Right", buttons::RIGHT),
+    ("Select", buttons::SELECT),
+    ("Start", buttons::START),
+    ("Y", buttons::Y),
+    ("B", buttons::B),
+    ("X", buttons::X),
+    ("A", buttons::A),
+    ("L", buttons::L),
+    ("R", buttons::R),
+];
+
+#[derive(Debug)]
+pub enum ImportError {
+    Zip(ZipError),
+    /// The movie uses something, which rsnes movies can't represent
+    Unsupported(String),
+    Syntax {
+        file: &'static str,
+        line: usize,
+        content: String,
+    },
+}
+
+impl From<ZipError> for ImportError {
+    fn from(err: ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Zip(err) => write!(fmt, "{err}"),
+            Self::Unsupported(msg) => write!(fmt, "unsupported movie ({msg})"),
+            Self::Syntax {
+                file,
+                line,
+                content,
+            } => write!(fmt, "invalid syntax in {file}, line {line}: \"{content}\""),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Lsnes,
+    BizHawk,
+}
+
+impl Format {
+    /// Get the format of a movie file by its extension, `None` for rsnes
+    /// movies and unknown files
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "lsmv" => Some(Self::Lsnes),
+            "bk2" => Some(Self::BizHawk),
+            _ => None,
+        }
+    }
+
+    /// Convert the content of a movie file of this format
+    pub fn import(self, content: &[u8]) -> Result<Movie, ImportError> {
+        let archive = Archive::new(content)?;
+        match self {
+            Self::Lsnes => import_lsnes(&archive),
+            Self::BizHawk => import_bizhawk(&archive),
+        }
+    }
+}
+
+fn read_text(archive: &Archive, name: &str) -> Result<String, ImportError> {
+    Ok(String::from_utf8_lossy(&archive.read(name)?).into_owned())
+}
+
+fn unsupported(msg: impl Into<String>) -> ImportError {
+    ImportError::Unsupported(msg.into())
+}
+
+fn import_lsnes(archive: &Archive) -> Result<Movie, ImportError> {
+    if archive.names().any(|name| name == "savestate") {
+        return Err(unsupported("it starts from a savestate"));
+    }
+    if archive.names().any(|name| name.starts_with("moviesram.")) {
+        return Err(unsupported("it starts with save RAM"));
+    }
+    // a port without a file has the default controller
+    let mut gamepads = vec![];
+    for (port, file, default) in [(0, "port1", "gamepad"), (1, "port2", "none")] {
+        let kind = match archive.read(file) {
+            Ok(kind) => String::from_utf8_lossy(&kind).trim().to_owned(),
+            Err(ZipError::Missing(_)) => default.to_owned(),
+            Err(err) => return Err(err.into()),
+        };
+        match kind.as_str() {
+            "gamepad" | "gamepad16" => gamepads.push(port),
+            "none" => (),
+            _ => return Err(unsupported(format!("{kind} controller in {file}"))),
+        }
+    }
+    let title = read_text(archive, "gamename").unwrap_or_default();
+    let mut movie = Movie::new(title.trim().to_owned(), None);
+    for (i, line) in read_text(archive, "input")?.lines().enumerate() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let syntax_err = || ImportError::Syntax {
+            file: "input",
+            line: i + 1,
+            content: line.to_owned(),
+        };
+        let mut fields = line.split('|');
+        let system = fields.next().unwrap_or_default();
+        let mut input = FrameInput::default();
+        for &port in &gamepads {
+            let field = fields.next().ok_or_else(syntax_err)?;
+            for (index, c) in field.chars().filter(|c| !c.is_whitespace()).enumerate() {
+                match LSNES_BUTTONS.get(index) {
+                    _ if c == '.' => (),
+                    Some(button) => input[port] |= button,
+                    None => return Err(unsupported(format!("extra buttons in line {}", i + 1))),
+                }
+            }
+        }
+        let mut flags = system.chars();
+        let new_frame = match flags.next() {
+            Some('F') => true,
+            Some('.') => false,
+            _ => return Err(syntax_err()),
+        };
+        if flags.next() == Some('R') {
+            let frame = movie.frames.len();
+            return Err(unsupported(format!("reset in frame {frame}")));
+        }
+        if new_frame {
+            movie.frames.push(input);
+        } else if *movie.frames.last().ok_or_else(syntax_err)? != input {
+            let frame = movie.frames.len() - 1;
+            return Err(unsupported(format!("subframe input in frame {frame}")));
+        }
+    }
+    Ok(movie)
+}
+
+/// A column of the input log of a BizHawk movie
+enum Column {
+    Console,
+    Button { port: usize, button: u16 },
+}
+
+fn parse_log_key(key: &str) -> Result<Vec<Column>, ImportError> {
+    let mut columns = vec![];
+    for name in key.split(['#', '|']).filter(|name| !name.is_empty()) {
+        let (player, button) = match name.strip_prefix('P').and_then(|name| name.split_once(' ')) {
+            Some((player, button)) => (player.parse().ok(), button),
+            None => (Some(0), name),
+        };
+        columns.push(match (player, button) {
+            (Some(0), _) | (_, "Reset" | "Power") => Column::Console,
+            (Some(player @ 1..=2), _) => Column::Button {
+                port: player - 1,
+                button: BIZHAWK_BUTTONS
+                    .iter()
+                    .find(|(bname, _)| *bname == button)
+                    .ok_or_else(|| unsupported(format!("the input \"{name}\"")))?
+                    .1,
+            },
+            _ => return Err(unsupported(format!("the input \"{name}\""))),
+        });
+    }
+    Ok(columns)
+}
+
+fn import_bizhawk(archive: &Archive) -> Result<Movie, ImportError> {
+    let mut title = String::new();
+    for line in read_text(archive, "Header.txt")?.lines() {
+        let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        match (key, value) {
+            ("Platform", platform) if platform != "SNES" => {
+                return Err(unsupported(format!("platform {platform}")))
+            }
+            ("StartsFromSavestate", "True") => {
+                return Err(unsupported("it starts from a savestate"))
+            }
+            ("StartsFromSaveRam", "True") => return Err(unsupported("it starts with save RAM")),
+            ("GameName", name) => title = name.to_owned(),
+            _ => (),
+        }
+    }
+    let mut movie = Movie::new(title, None);
+    let mut columns = None;
+    for (i, line) in read_text(archive, "Input Log.txt")?.lines().enumerate() {
+        let line = line.trim();
+        let syntax_err = || ImportError::Syntax {
+            file: "Input Log.txt",
+            line: i + 1,
+            content: line.to_owned(),
+        };
+        if let Some(key) = line.strip_prefix("LogKey:") {
+            columns = Some(parse_log_key(key)?);
+        } else if let Some(fields) = line.strip_prefix('|') {
+            let columns = columns.as_ref().ok_or_else(syntax_err)?;
+            let states = fields.chars().filter(|c| *c != '|');
+            if states.clone().count() != columns.len() {
+                return Err(syntax_err());
+            }
+            let mut input = FrameInput::default();
+            for (column, state) in columns.iter().zip(states) {
+                match column {
+                    _ if state == '.' => (),
+                    Column::Console => {
+                        let frame = movie.frames.len();
+                        return Err(unsupported(format!("reset or power in frame {frame}")));
+                    }
+                    Column::Button { port, button } => input[*port] |= button,
+                }
+            }
+            movie.frames.push(input);
+        }
+    }
+    Ok(movie)
+}
@@ -0,0 +1,328 @@
+//! Music replacement (`--music-pack`)
+//!
+//! A music pack is a directory with FLAC, OGG Vorbis or WAV files and a `music.toml`
+//! file, which describes how the game's song id is detected (see
+//! `example-music.toml`). When the game starts a song with a replacement,
+//! the configured DSP voices are muted and the track is mixed into the audio
+//! output instead. Sound effects keep playing on the other voices.
+
+use crate::config::{MusicPack, SongDetection};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    spc700::StereoSample,
+};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+
+/// The sample rate of the DSP
+const OUTPUT_RATE: u64 = 32000;
+
+#[derive(Debug)]
+pub enum TrackLoadError {
+    Io(std::io::Error),
+    Format(&'static str),
+}
+
+impl From<std::io::Error> for TrackLoadError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl std::fmt::Display for TrackLoadError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(fmt, "unable to read track ({err})"),
+            Self::Format(msg) => write!(fmt, "invalid or unsupported track ({msg})"),
+        }
+    }
+}
+
+impl std::error::Error for TrackLoadError {}
+
+pub struct Track {
+    rate: u32,
+    samples: Vec<StereoSample>,
+    loop_start: Option<usize>,
+}
+
+/// Decode a 16 bit PCM WAV file
+fn decode_wav(data: &[u8]) -> Result<(u32, Vec<StereoSample>), &'static str> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("not a WAV file");
+    }
+    let (mut format, mut data) = (None, &data[12..]);
+    while data.len() >= 8 {
+        let len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let chunk = data.get(8..8 + len).ok_or("truncated chunk")?;
+        match &data[..4] {
+            b"fmt " if len >= 16 => format = Some(chunk),
+            b"data" => {
+                let format = format.ok_or("missing format")?;
+                let word = |i: usize| u16::from_le_bytes([format[i], format[i + 1]]);
+                let (channels, bits) = (usize::from(word(2)), word(14));
+                if word(0) != 1 || bits != 16 || channels == 0 {
+                    return Err("only 16 bit PCM is supported");
+                }
+                let rate = u32::from_le_bytes(format[4..8].try_into().unwrap());
+                let samples = chunk
+                    .chunks_exact(channels * 2)
+                    .map(|frame| {
+                        let channel =
+                            |i: usize| i16::from_le_bytes([frame[i * 2], frame[i * 2 + 1]]);
+                        StereoSample::<i16>::new(channel(0), channel(channels.min(2) - 1))
+                    })
+                    .collect();
+                return Ok((rate, samples));
+            }
+            _ => (),
+        }
+        // chunks are padded to an even length
+        data = data.get(8 + len + (len & 1)..).unwrap_or_default();
+    }
+    Err("missing data")
+}
+
+/// Decode a FLAC file. Samples are converted to 16 bit.
+fn decode_flac(data: &[u8]) -> Result<(u32, Vec<StereoSample>), &'static str> {
+    let mut reader = claxon::FlacReader::new(data).map_err(|_| "not a FLAC file")?;
+    let info = reader.streaminfo();
+    let (right, bits) = (info.channels.min(2) - 1, info.bits_per_sample);
+    let to16 = |sample: i32| {
+        if bits > 16 {
+            (sample >> (bits - 16)) as i16
+        } else {
+            (sample << (16 - bits)) as i16
+        }
+    };
+    let mut samples = vec![];
+    let mut blocks = reader.blocks();
+    let mut buffer = vec![];
+    while let Some(block) = blocks
+        .read_next_or_eof(buffer)
+        .map_err(|_| "invalid FLAC frame")?
+    {
+        samples.extend((0..block.duration()).map(|i| {
+            StereoSample::<i16>::new(to16(block.sample(0, i)), to16(block.sample(right, i)))
+        }));
+        buffer = block.into_buffer();
+    }
+    Ok((info.sample_rate, samples))
+}
+
+/// Decode an OGG Vorbis file
+fn decode_ogg(data: &[u8]) -> Result<(u32, Vec<StereoSample>), &'static str> {
+    let mut reader = lewton::inside_ogg::OggStreamReader::new(std::io::Cursor::new(data))
+        .map_err(|_| "not an OGG Vorbis file")?;
+    let channels = usize::from(reader.ident_hdr.audio_channels);
+    let mut samples = vec![];
+    while let Some(packet) = reader
+        .read_dec_packet_itl()
+        .map_err(|_| "invalid OGG Vorbis packet")?
+    {
+        samples.extend(
+            packet
+                .chunks_exact(channels)
+                .map(|frame| StereoSample::<i16>::new(frame[0], frame[channels.min(2) - 1])),
+        );
+    }
+    Ok((reader.ident_hdr.audio_sample_rate, samples))
+}
+
+impl Track {
+    pub fn load<P: AsRef<Path>>(path: P, loop_start: Option<u64>) -> Result<Self, TrackLoadError> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        let ext = path.extension().and_then(|ext| ext.to_str());
+        let decode = match ext.map(str::to_ascii_lowercase).as_deref() {
+            Some("wav") => decode_wav,
+            Some("ogg") => decode_ogg,
+            _ => decode_flac,
+        };
+        let (rate, samples) = decode(&data).map_err(TrackLoadError::Format)?;
+        if rate == 0 || samples.is_empty() {
+            return Err(TrackLoadError::Format("empty track"));
+        }
+        let loop_start = loop_start.map(|start| start as usize);
+        if loop_start.is_some_and(|start| start >= samples.len()) {
+            return Err(TrackLoadError::Format("the loop starts after the end"));
+        }
+        Ok(Self {
+            rate,
+            samples,
+            loop_start,
+        })
+    }
+}
+
+enum Command {
+    Play(Arc<Track>),
+    Stop,
+}
+
+/// Mixes a track into the DSP output, running in the audio backend
+pub struct Mixer {
+    commands: mpsc::Receiver<Command>,
+    track: Option<Arc<Track>>,
+    /// The position in the track in 1/65536 samples
+    pos: u64,
+    volume: i32,
+}
+
+impl Mixer {
+    pub fn mix(&mut self, sample: StereoSample) -> StereoSample {
+        while let Ok(command) = self.commands.try_recv() {
+            self.pos = 0;
+            self.track = match command {
+                Command::Play(track) => Some(track),
+                Command::Stop => None,
+            };
+        }
+        let track = match &self.track {
+            Some(track) => track,
+            None => return sample,
+        };
+        let index = (self.pos >> 16) as usize;
+        let next = match index + 1 {
+            next if next < track.samples.len() => next,
+            _ => track.loop_start.unwrap_or(index),
+        };
+        // linear interpolation between two samples
+        let fraction = (self.pos & 0xffff) as i32;
+        let music = track.samples[index].zip_with(track.samples[next], |a, b| {
+            let a = i32::from(a);
+            a + (((i32::from(b) - a) * fraction) >> 16)
+        });
+        self.pos += (u64::from(track.rate) << 16) / OUTPUT_RATE;
+        if (self.pos >> 16) as usize >= track.samples.len() {
+            match track.loop_start {
+                Some(start) => self.pos -= ((track.samples.len() - start) as u64) << 16,
+                None => self.track = None,
+            }
+        }
+        sample
+            .to32()
+            .zip_with(music, |s, m| s + ((m * self.volume) >> 8))
+            .clamp16()
+    }
+}
+
+/// Detects song changes and controls the [`Mixer`]
+pub struct Music {
+    pack: MusicPack,
+    commands: mpsc::Sender<Command>,
+    /// The song with a replacement, which is currently played
+    song: Option<u8>,
+    /// The last value of a song id in memory
+    memory_value: Option<u8>,
+    tracks: HashMap<u8, Arc<Track>>,
+    decoded: mpsc::Receiver<(u8, Result<Track, TrackLoadError>)>,
+    decoded_sender: mpsc::Sender<(u8, Result<Track, TrackLoadError>)>,
+}
+
+impl Music {
+    pub fn new(pack: MusicPack) -> (Self, Mixer) {
+        let (commands, receiver) = mpsc::channel();
+        let (decoded_sender, decoded) = mpsc::channel();
+        let mixer = Mixer {
+            commands: receiver,
+            track: None,
+            pos: 0,
+            volume: (pack.volume * 256.0) as i32,
+        };
+        let music = Self {
+            pack,
+            commands,
+            song: None,
+            memory_value: None,
+            tracks: HashMap::new(),
+            decoded,
+            decoded_sender,
+        };
+        (music, mixer)
+    }
+
+    fn start(&mut self, song: u8) {
+        self.song = Some(song);
+        if let Some(track) = self.tracks.get(&song) {
+            let _ = self.commands.send(Command::Play(track.clone()));
+            return;
+        }
+        let _ = self.commands.send(Command::Stop);
+        // decoding takes a while, the replaced music stays muted meanwhile
+        let config = self.pack.tracks[&song].clone();
+        let sender = self.decoded_sender.clone();
+        std::thread::spawn(move || {
+            let _ = sender.send((song, Track::load(config.path, config.loop_start)));
+        });
+    }
+
+    fn stop<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        if self.song.take().is_some() {
+            let _ = self.commands.send(Command::Stop);
+            device.smp.set_muted_voices(0);
+        }
+    }
+
+    fn song_id_written<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        id: u8,
+        device: &mut Device<B, FB>,
+    ) {
+        if self.pack.tracks.contains_key(&id) {
+            if self.song != Some(id) {
+                self.start(id)
+            }
+        } else if self.pack.stop.contains(&id) {
+            self.stop(device)
+        }
+    }
+
+    /// Must be called after every emulated frame
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        match self.pack.detection {
+            SongDetection::Port(port) => {
+                let writes = core::mem::take(device.smp.port_writes.get_or_insert_with(Vec::new));
+                for (write_port, id) in writes {
+                    if write_port == port {
+                        self.song_id_written(id, device)
+                    }
+                }
+            }
+            SongDetection::Memory(addr) => {
+                let value = device.peek(addr);
+                if value != self.memory_value {
+                    self.memory_value = value;
+                    if let Some(id) = value {
+                        self.song_id_written(id, device)
+                    }
+                }
+            }
+        }
+        while let Ok((song, track)) = self.decoded.try_recv() {
+            match track {
+                Ok(track) => {
+                    let track = Arc::new(track);
+                    if self.song == Some(song) {
+                        let _ = self.commands.send(Command::Play(track.clone()));
+                    }
+                    self.tracks.insert(song, track);
+                }
+                Err(err) => {
+                    let path = &self.pack.tracks[&song].path;
+                    eprintln!("[warning] ignoring track \"{}\": {err}", path.display());
+                    self.pack.tracks.remove(&song);
+                    if self.song == Some(song) {
+                        self.stop(device)
+                    }
+                }
+            }
+        }
+        if self.song.is_some() {
+            // save states don't include the muted voices
+            device.smp.set_muted_voices(self.pack.voices)
+        }
+    }
+}
@@ -0,0 +1,593 @@
+//! Netplay with input delay and rollback (`--netplay-host`, `--netplay-join`)
+//!
+//! Two emulators run the same game in lockstep over UDP, relying on the
+//! deterministic mode of the core (see [`rsnes::deterministic`]). The host
+//! plays with the first controller port, the guest with the second; both
+//! use the controller they have configured for the first port. The input of
+//! a frame is sent to the peer and used `delay` frames later on both sides.
+//!
+//! A frame, whose input of the peer didn't arrive yet, runs with a predicted
+//! input, the last one which arrived, for up to `rollback` frames ahead of
+//! the peer. The state before such a frame is kept as a snapshot. If the
+//! input arrives and differs from the prediction, the snapshot is loaded and
+//! the frames since are emulated again with the correct input, before the
+//! next frame is shown. So the delay only needs to cover a part of the round
+//! trip time, but saving and loading a state must be fast (see
+//! [`SNAPSHOT_BUDGET`]). Without rollback, a frame waits for the input of the
+//! peer, so a delay longer than the round trip time keeps the game running
+//! smoothly.
+//!
+//! The guest starts the session with a handshake: it sends the CRC-32 of its
+//! ROM and the hash of its initial state, so the host can reject guests with
+//! another ROM or other save data. Every [`HASH_INTERVAL`] frames both sides
+//! exchange the hash of their state, once the inputs leading to it are
+//! confirmed, and the session ends, when they differ. The states are hashed
+//! by a [`StateWorker`], a hash, which the worker couldn't keep up with, is
+//! never compared, like one of a lost packet.
+//!
+//! Every packet starts with its kind, all numbers are little endian:
+//!
+//! - `H` hello: `RSNP`, the protocol version (16 bit), the state hash (64 bit)
+//!   and the ROM id
+//! - `W` welcome: the input delay and the rollback window in frames (8 bit
+//!   each)
+//! - `R` reject: the reason as text
+//! - `I` input: the first frame, whose input the sender still waits for
+//!   (32 bit), the first frame of the packet (32 bit) and the input of
+//!   consecutive frames (16 bit each). All inputs, which the peer still waits
+//!   for, are repeated to cover lost packets.
+//! - `S` state hash: the frame (32 bit) and the hash (64 bit)
+//! - `Q` quit
+
+use crate::movie::{self, FrameInput};
+use crate::state_worker::{StateWorker, WorkerStats};
+use crate::states;
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+use std::collections::{btree_map::Entry, BTreeMap};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"RSNP";
+const PROTOCOL_VERSION: u16 = 2;
+
+const HELLO: u8 = b'H';
+const WELCOME: u8 = b'W';
+const REJECT: u8 = b'R';
+const INPUT: u8 = b'I';
+const STATE_HASH: u8 = b'S';
+const QUIT: u8 = b'Q';
+
+/// The count of frames between two comparisons of the state hashes
+pub const HASH_INTERVAL: u32 = 60;
+
+/// The peer is considered gone, if nothing arrived for this long
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// While waiting for the peer, the input is sent again in this interval
+const RESEND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The most inputs in a packet
+const MAX_PACKET_INPUTS: usize = 200;
+
+/// The time, which saving and loading a snapshot of the state may take
+/// together in rollback netplay, so the frames emulated again after a
+/// misprediction still fit into a frame (see `--bench`)
+pub const SNAPSHOT_BUDGET: Duration = Duration::from_millis(2);
+
+/// The count of snapshots, whose average time is compared to the budget
+const SNAPSHOT_SAMPLES: u32 = 60;
+
+/// The count of states, which may wait to be hashed
+const HASH_QUEUE_SIZE: usize = 4;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(std::io::Error),
+    /// The peer didn't answer the handshake
+    Timeout,
+    Rejected(String),
+}
+
+impl From<std::io::Error> for NetplayError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl std::fmt::Display for NetplayError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(fmt, "{err}"),
+            Self::Timeout => write!(fmt, "the host didn't answer"),
+            Self::Rejected(reason) => write!(fmt, "rejected by the host ({reason})"),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+/// The 64-bit FNV-1a hash of the state of `device`
+pub fn state_hash<B: AudioBackend, FB: FrameBuffer>(device: &Device<B, FB>) -> u64 {
+    hash(&states::save(device))
+}
+
+/// The 64-bit FNV-1a hash of a state
+fn hash(state: &[u8]) -> u64 {
+    state.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+fn hello_packet(rom_id: &str, state_hash: u64) -> Vec<u8> {
+    let mut packet = vec![HELLO];
+    packet.extend_from_slice(MAGIC);
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    packet.extend_from_slice(&state_hash.to_le_bytes());
+    packet.extend_from_slice(rom_id.as_bytes());
+    packet
+}
+
+/// Check the hello packet of a guest. Returns the reason to reject it.
+fn check_hello(packet: &[u8], rom_id: &str, state_hash: u64) -> Result<(), &'static str> {
+    if packet.len() < 15 || &packet[1..5] != MAGIC {
+        return Err("invalid handshake");
+    }
+    if u16::from_le_bytes([packet[5], packet[6]]) != PROTOCOL_VERSION {
+        return Err("other netplay protocol version");
+    }
+    if &packet[15..] != rom_id.as_bytes() {
+        return Err("other ROM");
+    }
+    if u64::from_le_bytes(packet[7..15].try_into().unwrap()) != state_hash {
+        return Err("other initial state, e.g. other save data");
+    }
+    Ok(())
+}
+
+pub struct Netplay {
+    socket: UdpSocket,
+    delay: u32,
+    /// The most frames, which run ahead of the last input of the peer
+    rollback: u32,
+    /// The index of the controller port of the local player
+    port: usize,
+    /// The frame which runs next
+    frame: u32,
+    /// The first frame, whose input of the peer didn't arrive yet
+    confirmed: u32,
+    /// The first frame, whose input the peer still waits for
+    peer_confirmed: u32,
+    local_inputs: BTreeMap<u32, u16>,
+    remote_inputs: BTreeMap<u32, u16>,
+    /// The inputs of the peer predicted for the frames, which ran before they
+    /// arrived
+    predicted: BTreeMap<u32, u16>,
+    /// The states before the frames, which ran with a predicted input
+    snapshots: BTreeMap<u32, Vec<u8>>,
+    /// The first frame, which must run again, because its prediction was
+    /// wrong
+    mispredicted: Option<u32>,
+    /// A state hash, which is sent once the inputs before it are confirmed
+    pending_hash: Option<(u32, u64)>,
+    local_hashes: BTreeMap<u32, u64>,
+    remote_hashes: BTreeMap<u32, u64>,
+    hasher: StateWorker,
+    /// The hashes done by `hasher` with the rollback count, when their
+    /// states were captured, and their frames
+    hashed: mpsc::Receiver<(u32, u32, u64)>,
+    hashed_sender: mpsc::Sender<(u32, u32, u64)>,
+    /// The count of rollbacks, the hashes of states captured before a
+    /// rollback may be wrong
+    rollbacks: u32,
+    /// The controllers of the local player, while a frame runs with the
+    /// input of the session
+    held_input: FrameInput,
+    last_sent: Instant,
+    last_received: Instant,
+    /// The time taken by the first [`SNAPSHOT_SAMPLES`] snapshots and their
+    /// count, to warn about slow snapshots once
+    snapshot_time: Duration,
+    snapshot_count: u32,
+    peer_left: bool,
+    active: bool,
+}
+
+impl Netplay {
+    fn new(socket: UdpSocket, delay: u8, rollback: u8, port: usize) -> std::io::Result<Self> {
+        socket.set_nonblocking(true)?;
+        let delay = u32::from(delay);
+        // nobody can press a button in the first frames
+        let inputs: BTreeMap<u32, u16> = (0..delay).map(|frame| (frame, 0)).collect();
+        let (hashed_sender, hashed) = mpsc::channel();
+        Ok(Self {
+            socket,
+            delay,
+            rollback: rollback.into(),
+            port,
+            frame: 0,
+            confirmed: delay,
+            peer_confirmed: delay,
+            local_inputs: inputs.clone(),
+            remote_inputs: inputs,
+            predicted: BTreeMap::new(),
+            snapshots: BTreeMap::new(),
+            mispredicted: None,
+            pending_hash: None,
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            hasher: StateWorker::new("netplay", HASH_QUEUE_SIZE),
+            hashed,
+            hashed_sender,
+            rollbacks: 0,
+            held_input: FrameInput::default(),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            snapshot_time: Duration::ZERO,
+            snapshot_count: 0,
+            peer_left: false,
+            active: true,
+        })
+    }
+
+    /// Wait on `addr`, e.g. `0.0.0.0:7845`, for a guest with the same ROM and
+    /// initial state and start a session with an input delay of `delay` frames,
+    /// which runs up to `rollback` frames ahead of the guest
+    pub fn host(
+        addr: &str,
+        delay: u8,
+        rollback: u8,
+        rom_id: &str,
+        state_hash: u64,
+    ) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        println!(
+            "[info] netplay: waiting for a guest on {}",
+            socket.local_addr()?
+        );
+        let mut packet = [0; 512];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut packet) {
+                Ok(received) => received,
+                // an ICMP error of an earlier attempt of a guest
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionReset => continue,
+                Err(err) => return Err(err),
+            };
+            if packet[..len].first() != Some(&HELLO) {
+                continue;
+            }
+            match check_hello(&packet[..len], rom_id, state_hash) {
+                Ok(()) => {
+                    socket.connect(peer)?;
+                    socket.send(&[WELCOME, delay, rollback])?;
+                    println!("[info] netplay: {peer} joined");
+                    return Self::new(socket, delay, rollback, 0);
+                }
+                Err(reason) => {
+                    eprintln!("[warning] netplay: rejected {peer} ({reason})");
+                    let mut reject = vec![REJECT];
+                    reject.extend_from_slice(reason.as_bytes());
+                    socket.send_to(&reject, peer)?;
+                }
+            }
+        }
+    }
+
+    /// Join the session of the host at `addr`, e.g. `example.com:7845`
+    pub fn join(addr: &str, rom_id: &str, state_hash: u64) -> Result<Self, NetplayError> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let peer = addr
+            .to_socket_addrs()?
+            .find(|addr| addr.is_ipv4())
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "no IPv4 address found")
+            })?;
+        socket.connect(peer)?;
+        socket.set_read_timeout(Some(Duration::from_millis(250)))?;
+        let hello = hello_packet(rom_id, state_hash);
+        let start = Instant::now();
+        let mut packet = [0; 512];
+        while start.elapsed() < TIMEOUT {
+            socket.send(&hello)?;
+            let len = match socket.recv(&mut packet) {
+                Ok(len) => len,
+                // no answer yet or the host isn't listening yet
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock
+                            | std::io::ErrorKind::TimedOut
+                            | std::io::ErrorKind::ConnectionRefused
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            match packet[..len] {
+                [WELCOME, delay, rollback] => {
+                    println!(
+                        "[info] netplay: joined {peer} with an input delay of {delay} frames \
+                         and a rollback window of {rollback} frames"
+                    );
+                    return Ok(Self::new(socket, delay, rollback, 1)?);
+                }
+                [REJECT, ..] => {
+                    let reason = String::from_utf8_lossy(&packet[1..len]).into_owned();
+                    return Err(NetplayError::Rejected(reason));
+                }
+                _ => (),
+            }
+        }
+        Err(NetplayError::Timeout)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        // lost packets are sent again, other errors show up as a timeout
+        let _ = self.socket.send(packet);
+    }
+
+    /// Send the local inputs, which the peer still waits for
+    fn send_inputs(&mut self) {
+        let inputs = self.local_inputs.range(self.peer_confirmed..);
+        let Some((&first, _)) = inputs.clone().next() else {
+            return;
+        };
+        let mut packet = vec![INPUT];
+        packet.extend_from_slice(&self.confirmed.to_le_bytes());
+        packet.extend_from_slice(&first.to_le_bytes());
+        for (_, input) in inputs.take(MAX_PACKET_INPUTS) {
+            packet.extend_from_slice(&input.to_le_bytes());
+        }
+        self.send(&packet);
+        self.last_sent = Instant::now();
+    }
+
+    fn end(&mut self, msg: &str) {
+        println!("[info] netplay: {msg}");
+        self.active = false;
+    }
+
+    fn send_hash(&mut self, frame: u32, hash: u64) {
+        let mut packet = vec![STATE_HASH];
+        packet.extend_from_slice(&frame.to_le_bytes());
+        packet.extend_from_slice(&hash.to_le_bytes());
+        self.send(&packet);
+        self.local_hashes.insert(frame, hash);
+        self.compare_hashes(frame);
+    }
+
+    /// Compare the state hashes of `frame`, once both sides sent theirs
+    fn compare_hashes(&mut self, frame: u32) {
+        let (Some(local), Some(remote)) = (
+            self.local_hashes.get(&frame),
+            self.remote_hashes.get(&frame),
+        ) else {
+            return;
+        };
+        if local != remote {
+            eprintln!("[warning] netplay: desync detected in frame {frame}");
+            self.send(&[QUIT]);
+            self.end("the session ended");
+        }
+        // hashes of lost packets are never compared
+        self.local_hashes.retain(|&other, _| other > frame);
+        self.remote_hashes.retain(|&other, _| other > frame);
+    }
+
+    fn receive(&mut self) {
+        let mut packet = [0; 512];
+        while let Ok(len) = self.socket.recv(&mut packet) {
+            self.last_received = Instant::now();
+            let packet = &packet[..len];
+            match packet.first().copied() {
+                Some(INPUT) if len >= 9 => {
+                    let peer_confirmed = u32::from_le_bytes(packet[1..5].try_into().unwrap());
+                    self.peer_confirmed = self.peer_confirmed.max(peer_confirmed);
+                    let first = u32::from_le_bytes(packet[5..9].try_into().unwrap());
+                    for (frame, input) in (first..).zip(packet[9..].chunks_exact(2)) {
+                        if frame >= self.confirmed {
+                            let input = u16::from_le_bytes([input[0], input[1]]);
+                            self.remote_inputs.insert(frame, input);
+                        }
+                    }
+                }
+                Some(STATE_HASH) if len == 13 => {
+                    let frame = u32::from_le_bytes(packet[1..5].try_into().unwrap());
+                    let hash = u64::from_le_bytes(packet[5..13].try_into().unwrap());
+                    self.remote_hashes.insert(frame, hash);
+                    self.compare_hashes(frame);
+                }
+                // the guest didn't get the welcome
+                Some(HELLO) if self.port == 0 => {
+                    self.send(&[WELCOME, self.delay as u8, self.rollback as u8])
+                }
+                Some(QUIT) => self.peer_left = true,
+                _ => (),
+            }
+        }
+    }
+
+    /// Confirm the frames, whose input of the peer arrived in order, and
+    /// check the predictions made for them
+    fn confirm_inputs(&mut self) {
+        while let Some(&input) = self.remote_inputs.get(&self.confirmed) {
+            if let Some(prediction) = self.predicted.remove(&self.confirmed) {
+                if prediction != input && self.mispredicted.is_none() {
+                    self.mispredicted = Some(self.confirmed);
+                }
+            }
+            self.confirmed += 1;
+        }
+    }
+
+    /// The input of the peer for `frame`, predicted to be the last one which
+    /// arrived, if it didn't arrive yet
+    fn remote_input(&self, frame: u32) -> u16 {
+        let known = frame.min(self.confirmed.saturating_sub(1));
+        self.remote_inputs.get(&known).copied().unwrap_or(0)
+    }
+
+    /// Write the input of the session for `frame` to the controllers and keep
+    /// a snapshot before it, if the input of the peer is a prediction
+    fn prepare_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        frame: u32,
+    ) {
+        let mut input = FrameInput::default();
+        input[self.port] = self.local_inputs[&frame];
+        input[1 - self.port] = self.remote_input(frame);
+        movie::write_input(&mut device.controllers, input);
+        if frame >= self.confirmed {
+            let start = Instant::now();
+            self.snapshots.insert(frame, states::save(device));
+            self.predicted.insert(frame, input[1 - self.port]);
+            if self.snapshot_count < SNAPSHOT_SAMPLES {
+                self.snapshot_time += start.elapsed();
+                self.snapshot_count += 1;
+                // loading takes about as long as saving
+                let average = self.snapshot_time / SNAPSHOT_SAMPLES;
+                if self.snapshot_count == SNAPSHOT_SAMPLES && 2 * average > SNAPSHOT_BUDGET {
+                    eprintln!(
+                        "[warning] netplay: snapshots take {:.1} ms, rollbacks may stutter",
+                        average.as_secs_f64() * 1000.0
+                    );
+                }
+            }
+        }
+    }
+
+    /// Hash the state after `frame` every [`HASH_INTERVAL`] frames. The hash
+    /// is sent, once it's done and the inputs of the frames before are
+    /// confirmed.
+    fn finish_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        frame: u32,
+    ) {
+        let next = frame + 1;
+        if next.is_multiple_of(HASH_INTERVAL) {
+            let state = states::save(device);
+            let (sender, rollbacks) = (self.hashed_sender.clone(), self.rollbacks);
+            self.hasher.submit(move || {
+                let _ = sender.send((rollbacks, next, hash(&state)));
+            });
+        }
+    }
+
+    /// Load the snapshot before the first mispredicted frame and emulate the
+    /// frames since again with the inputs, which arrived meanwhile
+    fn roll_back<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        first: u32,
+    ) {
+        let held_input = movie::read_input(&device.controllers);
+        let snapshot = self.snapshots.remove(&first).unwrap();
+        if let Err(err) = states::load(device, &snapshot) {
+            self.end(&format!("the session ended (could not roll back: {err})"));
+            return;
+        }
+        // the hashes of states after the misprediction are computed again
+        self.pending_hash = self.pending_hash.filter(|&(frame, _)| frame <= first);
+        self.rollbacks += 1;
+        self.predicted.retain(|&frame, _| frame < first);
+        for frame in first..self.frame {
+            self.prepare_frame(device, frame);
+            crate::run_frame(device);
+            self.finish_frame(device, frame);
+        }
+        movie::write_input(&mut device.controllers, held_input);
+    }
+
+    /// Must be called right before a frame is emulated. Returns false, if the
+    /// frame has to wait for the input of the peer, then it must not run.
+    pub fn before_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+    ) -> bool {
+        if !self.active {
+            return true;
+        }
+        let scheduled = self.frame + self.delay;
+        if let Entry::Vacant(entry) = self.local_inputs.entry(scheduled) {
+            entry.insert(movie::read_input(&device.controllers)[0]);
+            self.send_inputs();
+        }
+        self.receive();
+        if !self.active {
+            return true;
+        }
+        self.confirm_inputs();
+        if let Some(first) = self.mispredicted.take() {
+            self.roll_back(device, first);
+        }
+        while let Ok((rollbacks, frame, hash)) = self.hashed.try_recv() {
+            // a hash of a state captured before a rollback is dropped
+            if rollbacks == self.rollbacks {
+                self.pending_hash = Some((frame, hash))
+            }
+        }
+        if let Some((frame, hash)) = self.pending_hash {
+            if frame <= self.confirmed {
+                self.pending_hash = None;
+                self.send_hash(frame, hash);
+            }
+        }
+        // only the inputs, which may still be used, predict others or are
+        // requested by the peer, are kept
+        let oldest = self.frame.min(self.confirmed.saturating_sub(1));
+        self.remote_inputs.retain(|&frame, _| frame >= oldest);
+        self.snapshots.retain(|&frame, _| frame >= self.confirmed);
+        let oldest = self.frame.min(self.confirmed).min(self.peer_confirmed);
+        self.local_inputs.retain(|&frame, _| frame >= oldest);
+        if self.frame >= self.confirmed + self.rollback {
+            if self.peer_left {
+                self.end("the peer left the session");
+            } else if self.last_received.elapsed() > TIMEOUT {
+                self.end("the connection to the peer was lost");
+            } else {
+                if self.last_sent.elapsed() > RESEND_INTERVAL {
+                    self.send_inputs();
+                }
+                std::thread::sleep(Duration::from_millis(1));
+                return false;
+            }
+            return true;
+        }
+        self.held_input = movie::read_input(&device.controllers);
+        self.prepare_frame(device, self.frame);
+        true
+    }
+
+    /// Must be called right after a frame was emulated
+    pub fn after_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        if !self.active {
+            return;
+        }
+        self.finish_frame(device, self.frame);
+        self.frame += 1;
+        // the state includes the controllers, so the local player's are
+        // restored only after hashing it
+        movie::write_input(&mut device.controllers, self.held_input);
+    }
+
+    pub fn worker_stats(&self) -> WorkerStats {
+        self.hasher.stats()
+    }
+
+    /// Tell the peer, that the session ends
+    pub fn leave(mut self) {
+        if self.active {
+            self.send(&[QUIT]);
+        }
+    }
+}
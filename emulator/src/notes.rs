@@ -0,0 +1,127 @@
+//! The notes played by the DSP voices (notes key)
+//!
+//! While the notes are shown, the registers of the eight voices are read
+//! after every frame and listed in the top left corner of the picture: the
+//! sample (its source number, the index in the sample directory, and its
+//! address), the pitch register, the note and the envelope. Samples aren't
+//! recorded at a fixed note, so the note is relative to the sample: played
+//! at its original rate (pitch `$1000`), it's shown as C4. The intervals
+//! between the notes of an instrument are exact, its key may be off.
+
+use crate::menu::{draw_text, CELL_SIZE, SELECTED_COLOR, TEXT_COLOR};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    spc700::VoiceState,
+};
+
+/// The color of voices, which are silent
+const SILENT_COLOR: [u8; 3] = [0x60; 3];
+
+static NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+static HEADER: &str = "V SRC ADDR PITCH NOTE   ENV";
+
+/// The width of the envelope bar in pixels of the font
+const ENVELOPE_WIDTH: usize = 32;
+
+pub struct Notes {
+    voices: [VoiceState; 8],
+    /// The voices, whose envelope rose since the last frame, e.g. after a
+    /// key on (bit n for voice n)
+    rising: u8,
+}
+
+impl Notes {
+    pub fn new<B: AudioBackend, FB: FrameBuffer>(device: &Device<B, FB>) -> Self {
+        Self {
+            voices: device.smp.voices(),
+            rising: 0,
+        }
+    }
+
+    /// Read the voices from `device`. Must be called after every frame.
+    pub fn update<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &Device<B, FB>) {
+        let voices = device.smp.voices();
+        self.rising = (0..8)
+            .filter(|&i| voices[i].envelope > self.voices[i].envelope)
+            .fold(0, |bits, i| bits | 1 << i);
+        self.voices = voices;
+    }
+
+    /// Draw the notes over a picture of RGBA pixels
+    pub fn draw(&self, picture: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let (width, height) = (width as usize, height as usize);
+        // the picture of HD Mode 7 is scaled up, the notes are scaled with it
+        let scale = (width / rsnes::ppu::SCREEN_WIDTH as usize).max(1);
+        let [cell_width, cell_height] = CELL_SIZE.map(|len| len * scale);
+        // the envelope bars start below `ENV`
+        let bar_left = scale + (HEADER.len() - 3) * cell_width;
+        let right = (bar_left + (ENVELOPE_WIDTH + 1) * scale).min(width);
+        let bottom = (9 * cell_height).min(height);
+        let mut out = picture.to_vec();
+        // darken the background of the text, so it's readable on every picture
+        for row in out.chunks_exact_mut(width * 4).take(bottom) {
+            for pixel in row[..right * 4].chunks_exact_mut(4) {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+                pixel.copy_from_slice(&[r / 4, g / 4, b / 4, 0xff]);
+            }
+        }
+        draw_text(
+            &mut out,
+            width,
+            [scale, 2 * scale],
+            scale,
+            HEADER,
+            TEXT_COLOR,
+        );
+        for (i, voice) in self.voices.iter().enumerate() {
+            let color = if voice.envelope == 0 {
+                SILENT_COLOR
+            } else if self.rising & (1 << i) > 0 {
+                SELECTED_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            let note = if voice.noise {
+                "NOISE".to_owned()
+            } else {
+                note_name(voice.pitch)
+            };
+            let line = format!(
+                "{i} {:>3} {:04X} {:>5} {note:<6}",
+                format!("{:02X}", voice.source),
+                voice.sample_addr,
+                format!("{:04X}", voice.pitch),
+            );
+            let y = ((i + 1) * CELL_SIZE[1] + 2) * scale;
+            draw_text(&mut out, width, [scale, y], scale, &line, color);
+            // the envelope as a bar of up to `ENVELOPE_WIDTH` pixels
+            let bar = usize::from(voice.envelope) * ENVELOPE_WIDTH / 0x7f * scale;
+            for y in y + 2 * scale..(y + 6 * scale).min(height) {
+                let row = &mut out[y * width * 4..(y + 1) * width * 4];
+                let start = bar_left.min(width);
+                for pixel in row[start * 4..(start + bar).min(width) * 4].chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&[color[0], color[1], color[2], 0xff]);
+                }
+            }
+        }
+        out
+    }
+}
+
+/// The note played at `pitch` with its deviation in cents, e.g. `C#4-12`.
+/// The pitch `$1000` is C4.
+fn note_name(pitch: u16) -> String {
+    if pitch == 0 {
+        return "-".to_owned();
+    }
+    let semitones = 60.0 + 12.0 * (f64::from(pitch) / 4096.0).log2();
+    let note = semitones.round();
+    let cents = ((semitones - note) * 100.0).round() as i32;
+    let note = note as i32;
+    let name = NOTE_NAMES[note.rem_euclid(12) as usize];
+    format!("{name}{}{cents:+03}", note.div_euclid(12) - 1)
+}
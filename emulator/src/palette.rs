@@ -0,0 +1,110 @@
+//! Palette files (`palette` debugger command)
+//!
+//! CGRAM is exported to palette files of graphics tools and palettes
+//! modified there are written back to CGRAM, so their colors can be
+//! previewed in the game. `.act` files (Adobe Color Table) contain three
+//! bytes RGB per color, `.pal` files are written as JASC-PAL text files, but
+//! binary RIFF palettes and plain RGB palettes are read as well.
+//!
+//! The console has five bits per component, they're scaled to eight bits on
+//! export and the lowest three bits are dropped on import.
+
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// The size of an `.act` file without its optional color count
+const ACT_SIZE: usize = 256 * 3;
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn is_act(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("act"))
+}
+
+/// Convert a BGR555 color to RGB
+fn to_rgb(color: u16) -> [u8; 3] {
+    [0, 5, 10].map(|shift| {
+        let component = (color >> shift) as u8 & 0x1f;
+        component << 3 | component >> 2
+    })
+}
+
+/// Convert an RGB color to BGR555
+fn from_rgb([r, g, b]: [u8; 3]) -> u16 {
+    u16::from(r >> 3) | u16::from(g >> 3) << 5 | u16::from(b >> 3) << 10
+}
+
+/// Write the BGR555 colors `palette` to an `.act` file or a JASC-PAL file
+pub fn save(path: &Path, palette: &[u16]) -> std::io::Result<()> {
+    let content = if is_act(path) {
+        let mut content: Vec<u8> = palette.iter().flat_map(|&color| to_rgb(color)).collect();
+        content.resize(ACT_SIZE, 0);
+        content
+    } else {
+        let mut text = format!("JASC-PAL\r\n0100\r\n{}\r\n", palette.len());
+        for &color in palette {
+            let [r, g, b] = to_rgb(color);
+            text += &format!("{r} {g} {b}\r\n");
+        }
+        text.into_bytes()
+    };
+    std::fs::write(path, content)
+}
+
+/// Read the colors of an `.act` file or a JASC-PAL, RIFF or plain RGB
+/// `.pal` file as BGR555
+pub fn load(path: &Path) -> std::io::Result<Vec<u16>> {
+    let content = std::fs::read(path)?;
+    let colors: Vec<[u8; 3]> = if content.starts_with(b"JASC-PAL") {
+        parse_jasc(&String::from_utf8_lossy(&content))?
+    } else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"PAL ") {
+        // a `data` chunk: version, count and four bytes per color
+        let data = content
+            .get(20..)
+            .filter(|_| content.get(12..16) == Some(b"data"))
+            .ok_or_else(|| invalid("missing data chunk"))?;
+        let count = data.get(2..4).map_or(0, |count| {
+            usize::from(u16::from_le_bytes([count[0], count[1]]))
+        });
+        (data.get(4..).unwrap_or_default().chunks_exact(4))
+            .take(count)
+            .map(|color| [color[0], color[1], color[2]])
+            .collect()
+    } else {
+        // an `.act` file may end with the count of colors (big endian) and
+        // the index of the transparent color
+        let count = match content.get(ACT_SIZE..ACT_SIZE + 2) {
+            Some(&[high, low]) if is_act(path) && content.len() == ACT_SIZE + 4 => {
+                usize::from(u16::from_be_bytes([high, low]))
+            }
+            _ => 256,
+        };
+        (content.chunks_exact(3))
+            .take(count)
+            .map(|color| [color[0], color[1], color[2]])
+            .collect()
+    };
+    if colors.is_empty() {
+        return Err(invalid("no colors"));
+    }
+    Ok(colors.into_iter().take(256).map(from_rgb).collect())
+}
+
+fn parse_jasc(text: &str) -> std::io::Result<Vec<[u8; 3]>> {
+    let mut lines = text.lines().skip(2);
+    let count: usize = (lines.next())
+        .and_then(|count| count.trim().parse().ok())
+        .ok_or_else(|| invalid("invalid color count"))?;
+    (lines.take(count))
+        .map(|line| {
+            let mut components = line.split_whitespace().map(str::parse::<u8>);
+            match [(); 3].map(|_| components.next()) {
+                [Some(Ok(r)), Some(Ok(g)), Some(Ok(b))] => Ok([r, g, b]),
+                _ => Err(invalid(&format!("invalid color `{line}`"))),
+            }
+        })
+        .collect()
+}
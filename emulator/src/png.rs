@@ -0,0 +1,285 @@
+//! A minimal PNG reader and writer for texture packs
+//!
+//! Images with a bit depth of 8 are supported, in all color types, with and
+//! without interlacing. Written images are uncompressed RGBA images.
+
+use rsnes::patch::crc32;
+use std::path::Path;
+
+static PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug)]
+pub enum PngError {
+    Io(std::io::Error),
+    Format(&'static str),
+}
+
+impl From<std::io::Error> for PngError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl std::fmt::Display for PngError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(fmt, "unable to access image ({err})"),
+            Self::Format(msg) => write!(fmt, "invalid or unsupported PNG image ({msg})"),
+        }
+    }
+}
+
+impl std::error::Error for PngError {}
+
+/// A decoded RGBA image
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Decompress a zlib stream
+fn inflate(data: &[u8]) -> Result<Vec<u8>, PngError> {
+    if data.len() < 6 || data[0] & 0xf != 8 || data[1] & 0x20 > 0 {
+        return Err(PngError::Format("unsupported compression"));
+    }
+    let (out, len) =
+        crate::inflate::inflate(&data[2..], crate::inflate::MAX_SIZE).map_err(PngError::Format)?;
+    let checksum = data
+        .get(2 + len..2 + len + 4)
+        .ok_or(PngError::Format("truncated image data"))?;
+    if u32::from_be_bytes(checksum.try_into().unwrap()) != adler32(&out) {
+        return Err(PngError::Format("checksum mismatch"));
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = (
+        (p - i16::from(a)).abs(),
+        (p - i16::from(b)).abs(),
+        (p - i16::from(c)).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// The passes of the Adam7 interlacing, see `decode`
+static ADAM7_PASSES: [[usize; 4]; 7] = [
+    [0, 0, 8, 8],
+    [4, 0, 8, 8],
+    [0, 4, 4, 8],
+    [2, 0, 4, 4],
+    [0, 2, 2, 4],
+    [1, 0, 2, 2],
+    [0, 1, 1, 2],
+];
+
+/// Undo the filters of the rows of an image (or an interlacing pass) with
+/// `stride` bytes per row, the filter byte of every row is kept
+fn unfilter(data: &[u8], stride: usize, channels: usize) -> Result<Vec<u8>, PngError> {
+    let mut raw = data.to_vec();
+    for y in 0..raw.len() / (stride + 1) {
+        let row = y * (stride + 1);
+        let filter = raw[row];
+        for i in 1..=stride {
+            let a = if i > channels {
+                raw[row + i - channels]
+            } else {
+                0
+            };
+            let b = if y > 0 { raw[row + i - stride - 1] } else { 0 };
+            let c = if y > 0 && i > channels {
+                raw[row + i - channels - stride - 1]
+            } else {
+                0
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(PngError::Format("invalid filter")),
+            };
+            raw[row + i] = raw[row + i].wrapping_add(predictor);
+        }
+    }
+    Ok(raw)
+}
+
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Image, PngError> {
+    decode(&std::fs::read(path)?)
+}
+
+/// Decode the content of a PNG file
+pub fn decode(content: &[u8]) -> Result<Image, PngError> {
+    let mut data = content
+        .strip_prefix(PNG_MAGIC)
+        .ok_or(PngError::Format("missing signature"))?;
+    let (mut header, mut palette, mut transparency) = (None, vec![], vec![]);
+    let mut compressed = vec![];
+    loop {
+        if data.len() < 12 {
+            return Err(PngError::Format("truncated chunk"));
+        }
+        let len = u32::from_be_bytes(data[..4].try_into().unwrap()) as usize;
+        let chunk = data
+            .get(4..8 + len)
+            .ok_or(PngError::Format("truncated chunk"))?;
+        let checksum = data
+            .get(8 + len..12 + len)
+            .ok_or(PngError::Format("truncated chunk"))?;
+        if u32::from_be_bytes(checksum.try_into().unwrap()) != crc32(chunk) {
+            return Err(PngError::Format("checksum mismatch"));
+        }
+        let (kind, content) = chunk.split_at(4);
+        match kind {
+            b"IHDR" if content.len() == 13 => header = Some(content),
+            b"PLTE" => palette = content.to_vec(),
+            b"tRNS" => transparency = content.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(content),
+            b"IEND" => break,
+            _ => (),
+        }
+        data = &data[12 + len..];
+    }
+    let header = header.ok_or(PngError::Format("missing header"))?;
+    let width = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if depth != 8 {
+        return Err(PngError::Format("bit depth must be 8"));
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(PngError::Format("invalid color type")),
+    };
+    // the passes as the position of the first pixel and the distances of
+    // the pixels in both directions
+    let passes: &[[usize; 4]] = match interlace {
+        0 => &[[0, 0, 1, 1]],
+        1 => &ADAM7_PASSES,
+        _ => return Err(PngError::Format("invalid interlace method")),
+    };
+    let (width, height) = (width as usize, height as usize);
+    let raw = inflate(&compressed)?;
+    let mut rest = &raw[..];
+    let mut pixels = vec![[0; 4]; width * height];
+    for &[x0, y0, dx, dy] in passes {
+        let pass_width = (width + dx - 1 - x0.min(width)) / dx;
+        let pass_height = (height + dy - 1 - y0.min(height)) / dy;
+        // empty passes don't even have filter bytes
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let stride = pass_width * channels;
+        if rest.len() < (stride + 1) * pass_height {
+            return Err(PngError::Format("wrong image data size"));
+        }
+        let (pass, next) = rest.split_at((stride + 1) * pass_height);
+        rest = next;
+        let pass = unfilter(pass, stride, channels)?;
+        for (y, row) in pass.chunks(stride + 1).enumerate() {
+            for (x, pixel) in row[1..].chunks(channels).enumerate() {
+                pixels[(y0 + y * dy) * width + x0 + x * dx] = match *pixel {
+                    [v] if color_type == 0 => [v, v, v, 0xff],
+                    [i] => {
+                        let i = usize::from(i);
+                        let color = palette
+                            .get(i * 3..i * 3 + 3)
+                            .ok_or(PngError::Format("invalid palette index"))?;
+                        let alpha = transparency.get(i).copied().unwrap_or(0xff);
+                        [color[0], color[1], color[2], alpha]
+                    }
+                    [v, a] => [v, v, v, a],
+                    [r, g, b] => [r, g, b, 0xff],
+                    [r, g, b, a] => [r, g, b, a],
+                    _ => unreachable!(),
+                };
+            }
+        }
+    }
+    if !rest.is_empty() {
+        return Err(PngError::Format("wrong image data size"));
+    }
+    let (width, height) = (width as u32, height as u32);
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(content);
+    let checksum = crc32(&out[start..]);
+    out.extend_from_slice(&checksum.to_be_bytes());
+}
+
+/// Write an uncompressed RGBA image
+pub fn write<P: AsRef<Path>>(
+    path: P,
+    width: u32,
+    height: u32,
+    pixels: &[[u8; 4]],
+) -> std::io::Result<()> {
+    std::fs::write(path, encode(width, height, pixels))
+}
+
+/// Encode an uncompressed RGBA image
+pub fn encode(width: u32, height: u32, pixels: &[[u8; 4]]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((width as usize * 4 + 1) * height as usize);
+    for row in pixels.chunks(width as usize) {
+        raw.push(0);
+        raw.extend(row.iter().flatten());
+    }
+    let mut compressed = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        compressed.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        compressed.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        compressed.extend_from_slice(&len.to_le_bytes());
+        compressed.extend_from_slice(&(!len).to_le_bytes());
+        compressed.extend_from_slice(block);
+    }
+    compressed.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    let mut out = PNG_MAGIC.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &compressed);
+    write_chunk(&mut out, b"IEND", &[]);
+    out
+}
@@ -0,0 +1,95 @@
+//! Practice mode
+//!
+//! An anchor state can be reloaded with a single hotkey. Every anchor starts
+//! a new section, whose attempts are counted. Optionally, the game is run for
+//! a random count of frames without input after reloading the anchor, so the
+//! timing of the section can't be learned by heart.
+
+use crate::movie::{self, FrameInput};
+use crate::states;
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    integrity::IntegrityError,
+};
+use std::time::SystemTime;
+
+pub struct Practice {
+    anchor: Option<Vec<u8>>,
+    /// The count of attempts of every section
+    attempts: Vec<u32>,
+    /// The maximum count of frames without input after a reload
+    pub max_delay: u32,
+    /// The remaining frames without input
+    delay: u32,
+    /// The input of the player, while it is suppressed
+    held_input: Option<FrameInput>,
+}
+
+impl Practice {
+    pub fn new(max_delay: u32) -> Self {
+        Self {
+            anchor: None,
+            attempts: vec![],
+            max_delay,
+            delay: 0,
+            held_input: None,
+        }
+    }
+
+    /// Use a state as the anchor of a new section
+    pub fn set_anchor(&mut self, state: Vec<u8>) {
+        self.anchor = Some(state);
+        self.attempts.push(1);
+    }
+
+    /// Reload the anchor state. Returns false, if there is no anchor.
+    pub fn reload<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+    ) -> Result<bool, IntegrityError> {
+        let anchor = match &self.anchor {
+            Some(anchor) => anchor,
+            None => return Ok(false),
+        };
+        states::load(device, anchor)?;
+        if let Some(attempts) = self.attempts.last_mut() {
+            *attempts += 1
+        }
+        self.delay = if self.max_delay > 0 {
+            // the exact time of the key press is random enough
+            let nanos = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |time| time.subsec_nanos());
+            nanos % (self.max_delay + 1)
+        } else {
+            0
+        };
+        Ok(true)
+    }
+
+    /// Must be called right before a frame is emulated
+    pub fn before_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        if self.delay > 0 {
+            self.delay -= 1;
+            self.held_input = Some(movie::read_input(&device.controllers));
+            movie::write_input(&mut device.controllers, FrameInput::default());
+        }
+    }
+
+    /// Must be called right after a frame was emulated
+    pub fn after_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        if let Some(input) = self.held_input.take() {
+            movie::write_input(&mut device.controllers, input);
+        }
+    }
+
+    /// A short status text like `section 2, attempt 14`
+    pub fn status(&self) -> Option<String> {
+        let attempts = self.attempts.last()?;
+        Some(format!(
+            "section {}, attempt {attempts}",
+            self.attempts.len()
+        ))
+    }
+}
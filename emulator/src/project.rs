@@ -0,0 +1,105 @@
+//! TAS projects
+//!
+//! A project stores the named branches and frame bookmarks of a movie.
+//! It is saved next to the movie file with the extension `.rsproj`.
+//! Like movies, projects are text files:
+//!
+//! ```text
+//! # rsnes project
+//! !bookmark 1 1200
+//! !branch 3600 boss fight
+//! |....S.......|............|
+//! ...
+//! ```
+
+use crate::movie::{self, FrameInput, MovieLoadError};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+static PROJECT_MAGIC: &str = "# rsnes project";
+
+/// The count of bookmark slots
+pub const BOOKMARK_COUNT: usize = 10;
+
+/// A named copy of the movie input
+#[derive(Debug, Clone, Default)]
+pub struct Branch {
+    /// The frame that was current when the branch was saved
+    pub frame: usize,
+    pub frames: Vec<FrameInput>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Project {
+    pub bookmarks: [Option<usize>; BOOKMARK_COUNT],
+    pub branches: BTreeMap<String, Branch>,
+}
+
+/// Get the path of the project file belonging to a movie file
+pub fn project_path(movie_path: &Path) -> PathBuf {
+    movie_path.with_extension("rsproj")
+}
+
+impl Project {
+    pub fn is_empty(&self) -> bool {
+        self.bookmarks.iter().all(Option::is_none) && self.branches.is_empty()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MovieLoadError> {
+        let content = std::fs::read_to_string(path)?;
+        let mut lines = content.lines().enumerate();
+        if lines.next().map(|(_, line)| line.trim_end()) != Some(PROJECT_MAGIC) {
+            return Err(MovieLoadError::Magic("project"));
+        }
+        let mut project = Self::default();
+        let mut branch = None;
+        for (i, line) in lines {
+            let line = line.trim();
+            let syntax_err = || MovieLoadError::Syntax {
+                line: i + 1,
+                content: line.to_owned(),
+            };
+            if let Some(args) = line.strip_prefix("!bookmark ") {
+                let (slot, frame) = args.split_once(' ').ok_or_else(syntax_err)?;
+                let slot: usize = slot.parse().map_err(|_| syntax_err())?;
+                *project.bookmarks.get_mut(slot).ok_or_else(syntax_err)? =
+                    Some(frame.trim().parse().map_err(|_| syntax_err())?);
+            } else if let Some(args) = line.strip_prefix("!branch ") {
+                let (frame, name) = args.split_once(' ').ok_or_else(syntax_err)?;
+                let frame = frame.parse().map_err(|_| syntax_err())?;
+                branch = Some(
+                    project
+                        .branches
+                        .entry(name.trim().to_owned())
+                        .or_insert(Branch {
+                            frame,
+                            frames: vec![],
+                        }),
+                );
+            } else if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+                continue;
+            } else {
+                let input = movie::parse_frame(line).ok_or_else(syntax_err)?;
+                branch.as_mut().ok_or_else(syntax_err)?.frames.push(input);
+            }
+        }
+        Ok(project)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut content = format!("{PROJECT_MAGIC}\n");
+        for (slot, frame) in self.bookmarks.iter().enumerate() {
+            if let Some(frame) = frame {
+                content.push_str(&format!("!bookmark {slot} {frame}\n"));
+            }
+        }
+        for (name, branch) in &self.branches {
+            content.push_str(&format!("!branch {} {name}\n", branch.frame));
+            for input in &branch.frames {
+                content.push_str(&movie::format_frame(input));
+                content.push('\n');
+            }
+        }
+        std::fs::write(path, content)
+    }
+}
@@ -0,0 +1,74 @@
+//! Switching games with quick resume (drop a ROM file on the window)
+//!
+//! Like on the dashboard of a console, the game is switched without losing
+//! the progress: the state of the running game is stashed in its quick resume
+//! file, and the emulator is restarted with the dropped ROM, so everything
+//! depending on the game (profile, save memory, cheats, ...) is set up as on
+//! startup. If the new game has a quick resume file, too, the pause menu
+//! offers to continue from there (`--offer-resume`).
+
+use crate::states;
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// The directory of the quick resume files, relative to `$HOME`
+static RESUME_DIR: &str = ".config/rsnes/resume";
+
+/// The extensions of the files, which are loaded as ROMs when dropped
+pub static ROM_EXTENSIONS: &[&str] = &["sfc", "smc", "swc", "fig"];
+
+/// Whether `path` is a ROM file by its extension
+pub fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ROM_EXTENSIONS
+                .iter()
+                .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+        })
+}
+
+/// The quick resume file of the ROM with the id `rom_id`
+pub fn resume_path(rom_id: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(
+        Path::new(&home)
+            .join(RESUME_DIR)
+            .join(format!("{rom_id}.{}", states::STATE_FILE_EXTENSION)),
+    )
+}
+
+/// Write the state of `device` to the quick resume file `path`
+pub fn stash<B: AudioBackend, FB: FrameBuffer>(
+    device: &Device<B, FB>,
+    path: &Path,
+) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    states::write_file(path, &states::save(device))
+}
+
+/// Replace the emulator by a new instance with the arguments `args`.
+/// Returns only, if it couldn't be started.
+pub fn restart(args: Vec<OsString>) -> std::io::Error {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => return err,
+    };
+    let mut command = std::process::Command::new(exe);
+    command.args(args);
+    #[cfg(unix)]
+    {
+        std::os::unix::process::CommandExt::exec(&mut command)
+    }
+    #[cfg(not(unix))]
+    match command.spawn() {
+        Ok(_) => std::process::exit(0),
+        Err(err) => err,
+    }
+}
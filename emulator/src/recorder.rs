@@ -0,0 +1,217 @@
+//! Video recording with ffmpeg (record key)
+//!
+//! While recording, every frame is piped into an `ffmpeg` process, which
+//! encodes it into a temporary video file, and the audio samples are written
+//! to a temporary raw file. When the recording is stopped, both are muxed into
+//! the output file, whose container is chosen by its extension, e.g. `.mkv` or
+//! `.mp4`.
+//!
+//! The video has a constant frame rate, the rate of the original hardware
+//! showing frames of 262 (PAL: 312) lines. The emulated frames are placed by
+//! the master cycles emulated since the recording was started, so a frame is
+//! duplicated or dropped, whenever the emulated frames deviate from that rate,
+//! e.g. because of interlacing. As the samples are emulated in sync with the
+//! master cycles as well, audio and video stay in sync however long the
+//! recording is.
+
+use crate::{screenshot, stats};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    clock::{self, ClockDivider},
+    device::Device,
+};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+
+/// The default directory of the recordings, relative to `$HOME`
+static RECORDING_DIR: &str = ".config/rsnes/recordings";
+
+/// The container of the recordings, if the directory of a profile is used
+static RECORDING_EXTENSION: &str = "mkv";
+
+/// The dots of a frame of 262 (PAL: 312) lines with 341 dots each
+const fn dots_per_frame(is_pal: bool) -> u64 {
+    341 * if is_pal { 312 } else { 262 }
+}
+
+pub fn recording_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(RECORDING_DIR))
+}
+
+/// A new recording file in `dir`, named after the game with the title
+/// `title` and the current time (UTC)
+pub fn recording_path(dir: &Path, title: &str) -> PathBuf {
+    dir.join(format!(
+        "{}.{RECORDING_EXTENSION}",
+        stats::timestamped_file_name(title)
+    ))
+}
+
+/// The samples passed to the audio backend, while a recording is running
+pub type SampleTap = Arc<Mutex<Option<Vec<i16>>>>;
+
+fn ffmpeg() -> Command {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-loglevel", "error", "-y"]);
+    command
+}
+
+pub struct Recorder {
+    path: PathBuf,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    ffmpeg: Child,
+    /// The frames are written by a thread, so the emulation doesn't wait for
+    /// the encoder, unless it is far behind
+    frames: Option<mpsc::SyncSender<Vec<u8>>>,
+    writer: Option<std::thread::JoinHandle<std::io::Result<()>>>,
+    audio: BufWriter<File>,
+    samples: SampleTap,
+    /// Converts the emulated master cycles into dots
+    dot_clock: ClockDivider,
+    /// The dots emulated since the recording was started
+    dots: u64,
+    dots_per_frame: u64,
+    /// The count of frames written to the video
+    frame_count: u64,
+    /// The height of the video, frames of a different height are cropped or
+    /// padded with black lines
+    height: u32,
+    sample_rate: u32,
+}
+
+impl Recorder {
+    /// Start recording into `path`. The samples passed to `samples` while
+    /// recording are added as audio with the rate `sample_rate`.
+    pub fn start<B: AudioBackend, FB: FrameBuffer>(
+        path: PathBuf,
+        device: &Device<B, FB>,
+        is_pal: bool,
+        samples: SampleTap,
+        sample_rate: u32,
+    ) -> std::io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let (width, height, _) = screenshot::picture(device);
+        // the frame rate is the dot clock divided by the dots of a frame
+        let (num, den) = clock::dot_clock(is_pal).fraction();
+        let dots_per_frame = dots_per_frame(is_pal);
+        let video_path = path.with_extension("video.mkv");
+        let audio_path = path.with_extension("audio.raw");
+        let mut ffmpeg = ffmpeg()
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-s"])
+            .arg(format!("{width}x{height}"))
+            .arg("-framerate")
+            .arg(format!("{num}/{}", den * dots_per_frame))
+            .args(["-i", "pipe:0", "-pix_fmt", "yuv420p"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                std::io::Error::new(err.kind(), format!("could not start ffmpeg ({err})"))
+            })?;
+        let stdin = ffmpeg.stdin.take().unwrap();
+        let (sender, frames) = mpsc::sync_channel(16);
+        let writer = std::thread::spawn(move || write_frames(stdin, frames));
+        let audio = BufWriter::new(File::create(&audio_path)?);
+        *samples.lock().unwrap() = Some(Vec::new());
+        Ok(Self {
+            path,
+            video_path,
+            audio_path,
+            ffmpeg,
+            frames: Some(sender),
+            writer: Some(writer),
+            audio,
+            samples,
+            dot_clock: ClockDivider::new(clock::master(is_pal), clock::dot_clock(is_pal)),
+            dots: 0,
+            dots_per_frame,
+            frame_count: 0,
+            height,
+            sample_rate,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Must be called after every emulated frame of `cycles` master cycles
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &Device<B, FB>,
+        cycles: u64,
+    ) -> std::io::Result<()> {
+        if let Some(samples) = self.samples.lock().unwrap().as_mut() {
+            for sample in samples.drain(..) {
+                self.audio.write_all(&sample.to_le_bytes())?
+            }
+        }
+        self.dot_clock.tick(cycles as u32);
+        self.dots += u64::from(self.dot_clock.take());
+        // the count of frames, which should have been shown by now
+        let due = self.dots / self.dots_per_frame;
+        if due <= self.frame_count {
+            return Ok(());
+        }
+        let (width, _, pixels) = screenshot::picture(device);
+        let mut frame: Vec<u8> = pixels.into_iter().flatten().collect();
+        frame.resize((width * self.height * 4) as usize, 0);
+        let sender = self.frames.as_ref().unwrap();
+        while self.frame_count < due {
+            if sender.send(frame.clone()).is_err() {
+                // the writer failed, the error is returned by `finish`
+                return Ok(());
+            }
+            self.frame_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Stop recording and mux video and audio into the output file
+    pub fn finish(mut self) -> std::io::Result<PathBuf> {
+        *self.samples.lock().unwrap() = None;
+        self.audio.flush()?;
+        drop(self.frames.take());
+        let written = self.writer.take().unwrap().join().unwrap();
+        let status = self.ffmpeg.wait()?;
+        written?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "ffmpeg failed to encode the video ({status})"
+            )));
+        }
+        let status = ffmpeg()
+            .arg("-i")
+            .arg(&self.video_path)
+            .args(["-f", "s16le", "-ac", "2", "-ar"])
+            .arg(self.sample_rate.to_string())
+            .arg("-i")
+            .arg(&self.audio_path)
+            .args(["-c:v", "copy"])
+            .arg(&self.path)
+            .status()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!(
+                "ffmpeg failed to mux the recording ({status})"
+            )));
+        }
+        let _ = std::fs::remove_file(&self.video_path);
+        let _ = std::fs::remove_file(&self.audio_path);
+        Ok(self.path)
+    }
+}
+
+fn write_frames(mut stdin: ChildStdin, frames: mpsc::Receiver<Vec<u8>>) -> std::io::Result<()> {
+    for frame in frames {
+        stdin.write_all(&frame)?
+    }
+    // closing stdin ends the video
+    Ok(())
+}
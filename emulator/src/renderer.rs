@@ -0,0 +1,397 @@
+//! Rendering the picture of the console with wgpu
+//!
+//! The [`Renderer`] uploads the picture to a texture and draws it scaled to
+//! fit into a target view, keeping the aspect ratio. It doesn't own a surface,
+//! so the target can be a texture of another wgpu application, e.g. a texture
+//! shown in a widget of an egui application or a level editor:
+//!
+//! ```ignore
+//! let renderer = Renderer::new(&device, target_format, &snes);
+//! renderer.set_target_size(&queue, [width, height], u32::from(snes.ppu.vend() - 1));
+//! // for every frame, after it was emulated
+//! renderer.upload_frame(&queue, &snes);
+//! renderer.render(&mut encoder, &target_view, wgpu::FilterMode::Nearest);
+//! ```
+//!
+//! The target must have the format given to [`Renderer::new`] and the usage
+//! [`wgpu::TextureUsages::RENDER_ATTACHMENT`]. Its contents outside of the
+//! picture are kept.
+//!
+//! A [`ScreenMapping`] converts positions in the target, e.g. the cursor in a
+//! window, to pixels of the console, for light guns, crosshairs or debug
+//! tools, which point at the picture:
+//!
+//! ```ignore
+//! let mapping = renderer.screen_mapping([width, height], lines);
+//! if let Some([x, y]) = mapping.to_pixel([cursor.x, cursor.y]) {
+//!     // the cursor is at the pixel `x`, `y` of the picture
+//! }
+//! ```
+
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+
+mod shaders {
+    macro_rules! include_shader {
+        ($t:expr) => {
+            include_bytes!(concat!(env!("OUT_DIR"), "/", $t))
+        };
+    }
+
+    static VERTEX_SHADER: &[u8] = include_shader!("main.vertex.spirv");
+    static FRAGMENT_SHADER: &[u8] = include_shader!("main.fragment.spirv");
+
+    fn create_shader(device: &wgpu::Device, source: &[u8]) -> wgpu::ShaderModule {
+        device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: None, // TODO: label
+            source: wgpu::util::make_spirv(source),
+        })
+    }
+
+    static SHADER_ENTRY_POINT: &str = "main";
+
+    pub fn create_vs(device: &wgpu::Device) -> (&str, wgpu::ShaderModule) {
+        (SHADER_ENTRY_POINT, create_shader(device, VERTEX_SHADER))
+    }
+
+    pub fn create_fs(device: &wgpu::Device) -> (&str, wgpu::ShaderModule) {
+        (SHADER_ENTRY_POINT, create_shader(device, FRAGMENT_SHADER))
+    }
+}
+
+pub struct Renderer {
+    texture: wgpu::Texture,
+    texture_extent: wgpu::Extent3d,
+    /// The width of the picture in regular pixels
+    picture_width: u32,
+    screen_size_buffer: wgpu::Buffer,
+    /// One bind group for every filter, nearest and linear
+    bind_groups: [wgpu::BindGroup; 2],
+    render_pipeline: wgpu::RenderPipeline,
+}
+
+impl Renderer {
+    /// Create a renderer for the pictures of `snes`, which draws to targets
+    /// of `format`. The size of the picture depends on the HD and widescreen
+    /// rendering of the PPU, so they must be set up before.
+    pub fn new<B: AudioBackend, FB: FrameBuffer>(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        snes: &Device<B, FB>,
+    ) -> Self {
+        let (vs_entry, vs_shader) = shaders::create_vs(device);
+        let (fs_entry, fs_shader) = shaders::create_fs(device);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let (texture_scale, texture_width) = snes
+            .ppu
+            .hd_frame
+            .as_ref()
+            .map_or((1, rsnes::ppu::SCREEN_WIDTH), |hd_frame| {
+                (u32::from(hd_frame.scale()), hd_frame.width())
+            });
+        let texture_extent = wgpu::Extent3d {
+            width: texture_width,
+            height: rsnes::ppu::MAX_SCREEN_HEIGHT * texture_scale,
+            depth_or_array_layers: 1,
+        };
+        let texture_format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: texture_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture_format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        });
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: None,
+            format: Some(texture_format),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level: 0,
+            mip_level_count: None,
+            base_array_layer: 0,
+            array_layer_count: None,
+        });
+        let screen_size_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4 * 4,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_groups = [wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear].map(|filter| {
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                label: None,
+                address_mode_u: wgpu::AddressMode::MirrorRepeat,
+                address_mode_v: wgpu::AddressMode::MirrorRepeat,
+                address_mode_w: wgpu::AddressMode::MirrorRepeat,
+                mag_filter: filter,
+                min_filter: filter,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                lod_min_clamp: 100.0,
+                lod_max_clamp: 100.0,
+                compare: None,
+                anisotropy_clamp: Some(core::num::NonZeroU8::new(1).unwrap()),
+                border_color: None,
+            });
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&texture_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: screen_size_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_shader,
+                entry_point: vs_entry,
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_shader,
+                entry_point: fs_entry,
+                targets: &[format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        Self {
+            texture,
+            texture_extent,
+            picture_width: texture_width / texture_scale,
+            screen_size_buffer,
+            bind_groups,
+            render_pipeline,
+        }
+    }
+
+    /// The width and height of the pictures in pixels
+    pub fn texture_size(&self) -> (u32, u32) {
+        (self.texture_extent.width, self.texture_extent.height)
+    }
+
+    /// Upload a picture of [`Renderer::texture_size`] with four bytes (RGBA)
+    /// per pixel, e.g. the picture of the console with overlays
+    pub fn upload(&self, queue: &wgpu::Queue, picture: &[u8]) {
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            picture,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: core::num::NonZeroU32::new(4 * self.texture_extent.width),
+                rows_per_image: core::num::NonZeroU32::new(self.texture_extent.height),
+            },
+            self.texture_extent,
+        );
+    }
+
+    /// Upload the current picture of the console
+    pub fn upload_frame<B: AudioBackend, FB: FrameBuffer>(
+        &self,
+        queue: &wgpu::Queue,
+        snes: &Device<B, FB>,
+    ) {
+        match &snes.ppu.hd_frame {
+            Some(hd_frame) => self.upload(queue, hd_frame.get_bytes()),
+            None => self.upload(queue, snes.ppu.frame_buffer.pixels().as_flattened()),
+        }
+    }
+
+    /// Set the size of the target in pixels and the count of visible lines
+    /// of the picture. Must be called before rendering and whenever either
+    /// changes.
+    pub fn set_target_size(&self, queue: &wgpu::Queue, [width, height]: [u32; 2], lines: u32) {
+        let aspect_width = self.aspect_width();
+        for (i, val) in [width, height, aspect_width, lines].into_iter().enumerate() {
+            queue.write_buffer(&self.screen_size_buffer, 4 * i as u64, &val.to_ne_bytes());
+        }
+    }
+
+    /// The width of the picture relative to a height of `MAX_SCREEN_HEIGHT`,
+    /// which sets the proportions it is shown with
+    fn aspect_width(&self) -> u32 {
+        rsnes::ppu::MAX_SCREEN_HEIGHT * self.picture_width / rsnes::ppu::SCREEN_WIDTH
+    }
+
+    /// The rectangle `x`, `y`, `width`, `height` of a target of `size`, to
+    /// which a picture of `lines` lines is drawn with black bars around it
+    pub fn picture_rect(&self, [width, height]: [u32; 2], lines: u32) -> [f64; 4] {
+        let (width, height) = (f64::from(width), f64::from(height));
+        let proportion = f64::from(self.aspect_width()) / f64::from(lines);
+        if proportion > width / height {
+            let picture_height = width / proportion;
+            [0.0, (height - picture_height) / 2.0, width, picture_height]
+        } else {
+            let picture_width = height * proportion;
+            [(width - picture_width) / 2.0, 0.0, picture_width, height]
+        }
+    }
+
+    /// The mapping of positions in a target of `size` to the pixels of a
+    /// picture of `lines` lines, which is drawn to it
+    pub fn screen_mapping(&self, size: [u32; 2], lines: u32) -> ScreenMapping {
+        ScreenMapping {
+            size: size.map(f64::from),
+            rect: self.picture_rect(size, lines),
+            picture_width: self.picture_width,
+            lines,
+            rotation: Rotation::None,
+        }
+    }
+
+    /// Record drawing the last uploaded picture into `view`, scaled with
+    /// `filter`
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        filter: wgpu::FilterMode,
+    ) {
+        self.render_into(encoder, view, filter, None)
+    }
+
+    /// Like [`Renderer::render`], but only draw into the rectangle `x`, `y`,
+    /// `width`, `height` of `view` in pixels, e.g. to show two pictures side
+    /// by side. The target size is the size of the rectangle then.
+    pub fn render_into(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        filter: wgpu::FilterMode,
+        rect: Option<[u32; 4]>,
+    ) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        if let Some([x, y, width, height]) = rect {
+            rpass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        }
+        rpass.set_pipeline(&self.render_pipeline);
+        let bind_group = match filter {
+            wgpu::FilterMode::Nearest => &self.bind_groups[0],
+            wgpu::FilterMode::Linear => &self.bind_groups[1],
+        };
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..6, 0..1);
+    }
+}
+
+/// The rotation of a target, which an application shows rotated clockwise,
+/// e.g. for games made for a vertical screen
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Right,
+    UpsideDown,
+    Left,
+}
+
+/// Maps positions in a target to the pixels of the picture drawn to it (see
+/// [`Renderer::screen_mapping`]). It accounts for the scaling with black bars
+/// around the picture, the count of visible lines, which changes with
+/// overscan, the widescreen area of HD Mode 7 and the rotation of the target.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenMapping {
+    /// The size of the target before the rotation
+    size: [f64; 2],
+    /// The rectangle `x`, `y`, `width`, `height` of the picture in the target
+    rect: [f64; 4],
+    /// The width of the picture in regular pixels, including the widescreen
+    /// area on both sides
+    picture_width: u32,
+    lines: u32,
+    rotation: Rotation,
+}
+
+impl ScreenMapping {
+    /// The same mapping for positions in the target shown with `rotation`
+    pub fn rotated(self, rotation: Rotation) -> Self {
+        Self { rotation, ..self }
+    }
+
+    /// The pixel `x`, `y` of the console at the position `x`, `y` in pixels
+    /// of the target, or `None` if the position is outside of the picture or
+    /// in its widescreen area. The row 0 is the first visible line, which the
+    /// PPU draws in scanline 1.
+    pub fn to_pixel(&self, [x, y]: [f64; 2]) -> Option<[u16; 2]> {
+        let [width, height] = self.size;
+        let [x, y] = match self.rotation {
+            Rotation::None => [x, y],
+            Rotation::Right => [y, height - x],
+            Rotation::UpsideDown => [width - x, height - y],
+            Rotation::Left => [width - y, x],
+        };
+        let [rect_x, rect_y, rect_width, rect_height] = self.rect;
+        let widescreen = f64::from(self.picture_width - rsnes::ppu::SCREEN_WIDTH) / 2.0;
+        let x = ((x - rect_x) / rect_width * f64::from(self.picture_width) - widescreen).floor();
+        let y = ((y - rect_y) / rect_height * f64::from(self.lines)).floor();
+        let on_screen = (0.0..f64::from(rsnes::ppu::SCREEN_WIDTH)).contains(&x)
+            && (0.0..f64::from(self.lines)).contains(&y);
+        on_screen.then_some([x as u16, y as u16])
+    }
+}
@@ -0,0 +1,99 @@
+//! Conversion of the 32 kHz output of the S-DSP to the sample rate of the
+//! audio device
+//!
+//! The samples are interpolated linearly. The position between two input
+//! samples is kept as an exact fraction, so the ratio of the rates doesn't
+//! accumulate rounding errors, however long it runs.
+//!
+//! The clocks of the emulation and of the audio device are never exactly
+//! equal, so the queue of input samples would slowly run empty or full.
+//! [`Resampler::control_rate`] nudges the rate, at which input samples are
+//! consumed, by at most [`MAX_ADJUSTMENT`] to keep the queue at its target
+//! level, unless it's disabled (sync modes `video` and `audio`). If it runs
+//! empty anyway, e.g. while the emulation is paused, silence is output until
+//! it is filled up to the target again.
+
+/// The largest relative change of the consumption rate, a pitch change of
+/// this size isn't audible
+const MAX_ADJUSTMENT: f64 = 0.005;
+
+/// The weight of a new measurement in the average fill level of the queue.
+/// The level jumps by a frame of samples, whenever the emulation pushes them.
+const AVERAGE_WEIGHT: f64 = 1.0 / 16.0;
+
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    /// The input samples consumed per output sample in 1/`output_rate`
+    /// samples, `input_rate` with the adjustment of the rate control
+    step: u32,
+    /// The position between `prev` and `next` in 1/`output_rate` samples
+    pos: u32,
+    prev: [i16; 2],
+    next: [i16; 2],
+    /// The average count of queued input samples
+    average_queued: f64,
+    /// Output silence, until the queue is filled up to the target
+    buffering: bool,
+    /// Adjust `step` to the fill level of the queue
+    rate_control: bool,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, rate_control: bool) -> Self {
+        Self {
+            input_rate,
+            output_rate,
+            step: input_rate,
+            pos: 0,
+            prev: [0; 2],
+            next: [0; 2],
+            average_queued: 0.0,
+            buffering: true,
+            rate_control,
+        }
+    }
+
+    /// Adjust the consumption rate to the count of `queued` stereo input
+    /// samples, which should be about `target`. Called before every block of
+    /// output.
+    pub fn control_rate(&mut self, queued: usize, target: usize) {
+        if self.buffering {
+            if queued < target {
+                return;
+            }
+            self.buffering = false;
+            self.average_queued = queued as f64;
+        }
+        if !self.rate_control {
+            return;
+        }
+        self.average_queued += (queued as f64 - self.average_queued) * AVERAGE_WEIGHT;
+        let deviation = (self.average_queued / target as f64 - 1.0).clamp(-1.0, 1.0);
+        let adjustment = 1.0 + deviation * MAX_ADJUSTMENT;
+        self.step = (f64::from(self.input_rate) * adjustment).round() as u32;
+    }
+
+    /// The next stereo sample of the output. Input samples are taken from
+    /// `input` as needed; if none is available, silence is inserted.
+    pub fn next_sample(&mut self, mut input: impl FnMut() -> Option<[i16; 2]>) -> [i16; 2] {
+        if self.buffering {
+            return [0; 2];
+        }
+        while self.pos >= self.output_rate {
+            self.pos -= self.output_rate;
+            self.prev = self.next;
+            self.next = input().unwrap_or_else(|| {
+                self.buffering = true;
+                [0; 2]
+            });
+        }
+        let (pos, rate) = (i64::from(self.pos), i64::from(self.output_rate));
+        let sample = [0, 1].map(|i| {
+            let (prev, next) = (i64::from(self.prev[i]), i64::from(self.next[i]));
+            (prev + (next - prev) * pos / rate) as i16
+        });
+        self.pos += self.step;
+        sample
+    }
+}
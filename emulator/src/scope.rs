@@ -0,0 +1,120 @@
+//! Oscilloscopes of the DSP voices (scope key)
+//!
+//! While the scopes are shown, the outputs of the eight voices are tapped
+//! from the audio backend and drawn over the bottom third of the picture, in
+//! two rows of four. A voice is shown after its envelope, but before its
+//! volume, so muted voices and voices panned to one side are visible, too.
+//! Every scope starts at a rising zero crossing, so periodic waves stand
+//! still.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// The outputs of the voices after every sample, `None` while the scopes
+/// aren't shown
+pub type VoiceTap = Arc<Mutex<Option<Vec<[i16; 8]>>>>;
+
+/// The samples shown by a scope, 16 ms
+const WINDOW: usize = 512;
+/// The samples kept, the zero crossing is searched in the older half
+const HISTORY: usize = 2 * WINDOW;
+
+static COLORS: [[u8; 3]; 8] = [
+    [0xff, 0x60, 0x60],
+    [0xff, 0xb0, 0x40],
+    [0xf0, 0xf0, 0x50],
+    [0x70, 0xf0, 0x70],
+    [0x50, 0xe0, 0xe0],
+    [0x70, 0x90, 0xff],
+    [0xc0, 0x70, 0xff],
+    [0xff, 0x80, 0xd0],
+];
+
+pub struct Scope {
+    tap: VoiceTap,
+    history: VecDeque<[i16; 8]>,
+}
+
+impl Scope {
+    /// Start tapping the voices, until the scope is dropped
+    pub fn new(tap: VoiceTap) -> Self {
+        *tap.lock().unwrap() = Some(vec![]);
+        Self {
+            tap,
+            history: VecDeque::with_capacity(HISTORY),
+        }
+    }
+
+    /// Take the samples emulated since the last frame
+    pub fn on_frame(&mut self) {
+        if let Some(outputs) = self.tap.lock().unwrap().as_mut() {
+            self.history.extend(outputs.drain(..));
+        }
+        let excess = self.history.len().saturating_sub(HISTORY);
+        self.history.drain(..excess);
+    }
+
+    /// The first sample shown of `voice`
+    fn trigger(&self, voice: usize) -> usize {
+        let latest = self.history.len() - WINDOW;
+        (1..=latest)
+            .rev()
+            .find(|&i| self.history[i - 1][voice] < 0 && self.history[i][voice] >= 0)
+            .unwrap_or(latest)
+    }
+
+    /// Draw the scopes over a picture of RGBA pixels
+    pub fn draw(&self, picture: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let mut out = picture.to_vec();
+        let (width, height) = (width as usize, height as usize);
+        let (cell_width, cell_height) = (width / 4, height / 6);
+        let top = height - 2 * cell_height;
+        for (voice, color) in COLORS.iter().enumerate() {
+            let left = (voice % 4) * cell_width;
+            let cell_top = top + (voice / 4) * cell_height;
+            // darken the background, so the waves are visible on every picture
+            for y in cell_top..cell_top + cell_height {
+                let row = (y * width + left) * 4;
+                for pixel in out[row..row + cell_width * 4].chunks_exact_mut(4) {
+                    let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+                    pixel.copy_from_slice(&[r / 4, g / 4, b / 4, 0xff]);
+                }
+            }
+            let mut plot = |x: usize, y: usize, rgb: [u8; 3]| {
+                let i = ((cell_top + y) * width + left + x) * 4;
+                out[i..i + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 0xff]);
+            };
+            let mid = cell_height / 2;
+            for x in 0..cell_width {
+                plot(x, mid, [0x40; 3]);
+            }
+            if self.history.len() < WINDOW {
+                continue;
+            }
+            let start = self.trigger(voice);
+            let to_y = |sample: i16| {
+                let amplitude = (cell_height / 2).saturating_sub(1) as i32;
+                (mid as i32 - i32::from(sample) * amplitude / 0x8000) as usize
+            };
+            // the range of the samples of every column, as a vertical line
+            for x in 0..cell_width {
+                let begin = start + x * WINDOW / cell_width;
+                let end = (start + (x + 1) * WINDOW / cell_width).max(begin + 1);
+                let samples = self.history.range(begin..end).map(|outputs| outputs[voice]);
+                let (min, max) = samples.fold((i16::MAX, i16::MIN), |(min, max), sample| {
+                    (min.min(sample), max.max(sample))
+                });
+                for y in to_y(max)..=to_y(min) {
+                    plot(x, y, *color)
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        *self.tap.lock().unwrap() = None
+    }
+}
@@ -0,0 +1,61 @@
+//! Screenshots (screenshot key)
+//!
+//! The picture is taken from the frame buffer in the resolution the PPU
+//! outputs it, 256×224 or 256×239 pixels with overscan, regardless of the
+//! size of the window. It is written to a PNG file named after the game and
+//! the current time (UTC), e.g. `SUPER MARIOWORLD 2024-05-01-183000.png`.
+
+use crate::{png, stats};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    ppu::{MAX_SCREEN_HEIGHT, MAX_SCREEN_HEIGHT_OVERSCAN, SCREEN_WIDTH},
+};
+use std::path::{Path, PathBuf};
+
+/// The default directory of the screenshots, relative to `$HOME`
+static SCREENSHOT_DIR: &str = ".config/rsnes/screenshots";
+
+pub fn screenshot_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(SCREENSHOT_DIR))
+}
+
+/// The current picture of the PPU as opaque RGBA pixels.
+/// Returns the width, the height and the pixels.
+pub fn picture<B: AudioBackend, FB: FrameBuffer>(
+    device: &Device<B, FB>,
+) -> (u32, u32, Vec<[u8; 4]>) {
+    let height = if device.ppu.is_overscan() {
+        MAX_SCREEN_HEIGHT_OVERSCAN
+    } else {
+        MAX_SCREEN_HEIGHT
+    };
+    let pixels = device.ppu.frame_buffer.pixels()[..(SCREEN_WIDTH * height) as usize]
+        .iter()
+        // forced blank pixels are fully transparent
+        .map(|&[r, g, b, _]| [r, g, b, 0xff])
+        .collect();
+    (SCREEN_WIDTH, height, pixels)
+}
+
+/// Write the current picture into `dir`. Returns the path of the file.
+pub fn save<B: AudioBackend, FB: FrameBuffer>(
+    device: &Device<B, FB>,
+    dir: &Path,
+) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let title = device.cartridge().map_or("", |cart| cart.title());
+    let name = stats::timestamped_file_name(title);
+    let mut path = dir.join(format!("{name}.png"));
+    // several screenshots in the same second
+    for i in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = dir.join(format!("{name}-{i}.png"));
+    }
+    let (width, height, pixels) = picture(device);
+    png::write(&path, width, height, &pixels)?;
+    Ok(path)
+}
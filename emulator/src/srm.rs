@@ -0,0 +1,91 @@
+//! Battery backed save memory files (`.srm`)
+//!
+//! The SRAM or flash memory of a cartridge is loaded from a file named after
+//! the ROM id at startup. Changes are written back a few seconds after the
+//! game wrote them and when the emulator exits. The file is replaced
+//! atomically, so a crash while writing keeps the previous save.
+
+use rsnes::cartridge::Cartridge;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The directory of the save memory files, relative to `$HOME`
+static SRM_DIR: &str = ".config/rsnes/saves";
+
+/// Changed save memory is written at most this often
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The save memory file of the ROM with the id `rom_id`
+pub fn srm_path(rom_id: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join(SRM_DIR).join(format!("{rom_id}.srm")))
+}
+
+pub struct SrmFile {
+    path: PathBuf,
+    elapsed: Duration,
+}
+
+impl SrmFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Load the save memory of `cartridge` from the file, if it exists
+    pub fn load(&self, cartridge: &mut Cartridge) -> std::io::Result<()> {
+        match std::fs::read(&self.path) {
+            Ok(data) => {
+                if !cartridge.load_sram(&data) {
+                    eprintln!(
+                        "[warning] the size of \"{}\" ({} bytes) doesn't match the save memory ({} bytes)",
+                        self.path.display(),
+                        data.len(),
+                        cartridge.sram().len()
+                    )
+                }
+                Ok(())
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Must be called after every emulated frame
+    pub fn on_frame(&mut self, cartridge: &mut Cartridge, frame_time: Duration) {
+        self.elapsed += frame_time;
+        if self.elapsed >= FLUSH_INTERVAL {
+            self.elapsed = Duration::ZERO;
+            self.flush(cartridge)
+        }
+    }
+
+    /// Write the save memory, if it changed since it was last written
+    pub fn flush(&mut self, cartridge: &mut Cartridge) {
+        if !cartridge.is_sram_dirty() || cartridge.sram().is_empty() {
+            return;
+        }
+        match self.write(cartridge.sram()) {
+            Ok(()) => cartridge.clear_sram_dirty(),
+            Err(err) => eprintln!(
+                "[warning] unable to write the save memory file \"{}\" ({err})",
+                self.path.display()
+            ),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("srm.tmp");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &self.path)
+    }
+}
@@ -0,0 +1,218 @@
+//! Save state container files (`--state-container`)
+//!
+//! All save state slots of a ROM, their thumbnails and the undo state are kept
+//! in a single file. Changes are appended as records, so storing a state
+//! doesn't rewrite the whole file. A record replaces all earlier records of the
+//! same kind and slot. When the file mostly consists of replaced records, it is
+//! compacted by rewriting only the current ones.
+//!
+//! The file starts with a header with the magic `rsnes states` and the version of
+//! the state format. The states of a container of an older version are
+//! upgraded, when it is opened, and it is rewritten. States, which are too old to
+//! be upgraded, are dropped. Every record consists of its kind,
+//! its slot, the length (32 bit little endian) and the CRC-32 (32 bit little
+//! endian) of its data, followed by the data. An incomplete or corrupt record
+//! at the end of the file, e.g. after a crash, is ignored together with the
+//! records after it.
+
+use crate::state_version::{self, MigrationError, CONTAINER_MAGIC};
+use crate::states::StateFileError;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The directory of the container files, relative to `$HOME`
+static CONTAINER_DIR: &str = ".config/rsnes/states";
+
+const RECORD_HEADER_SIZE: usize = 10;
+
+/// Don't compact files smaller than this
+const MIN_COMPACTION_SIZE: u64 = 1 << 20;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RecordKind {
+    State = 0,
+    Thumbnail = 1,
+    Undo = 2,
+}
+
+impl RecordKind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::State),
+            1 => Some(Self::Thumbnail),
+            2 => Some(Self::Undo),
+            _ => None,
+        }
+    }
+}
+
+fn encode_record(kind: RecordKind, slot: u8, data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+    record.extend_from_slice(&[kind as u8, slot]);
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&rsnes::patch::crc32(data).to_le_bytes());
+    record.extend_from_slice(data);
+    record
+}
+
+/// The container file of the ROM with the id `rom_id`
+pub fn container_path(rom_id: &str) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(
+        Path::new(&home)
+            .join(CONTAINER_DIR)
+            .join(format!("{rom_id}.states")),
+    )
+}
+
+pub struct Container {
+    path: PathBuf,
+    file: File,
+    records: BTreeMap<(RecordKind, u8), Vec<u8>>,
+    /// The size of the file in bytes
+    file_size: u64,
+}
+
+impl Container {
+    /// Open a container file, or create it, if it doesn't exist
+    pub fn open(path: PathBuf) -> Result<Self, StateFileError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content = match std::fs::read(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let header = state_version::header(CONTAINER_MAGIC);
+                std::fs::write(&path, &header)?;
+                header
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let (version, mut data) =
+            state_version::parse_header(&content, CONTAINER_MAGIC).ok_or(StateFileError::Magic)?;
+        let mut records = BTreeMap::new();
+        let mut valid_size = content.len() - data.len();
+        while data.len() >= RECORD_HEADER_SIZE {
+            let word = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+            let (len, crc) = (word(2) as usize, word(6));
+            let record = match data.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + len) {
+                Some(record) if rsnes::patch::crc32(record) == crc => record,
+                _ => break,
+            };
+            // records of unknown kinds are skipped for compatibility
+            if let Some(kind) = RecordKind::from_u8(data[0]) {
+                records.insert((kind, data[1]), record.to_vec());
+            }
+            valid_size += RECORD_HEADER_SIZE + len;
+            data = &data[RECORD_HEADER_SIZE + len..];
+        }
+        if valid_size < content.len() {
+            eprintln!(
+                "[warning] ignoring {} corrupt bytes at the end of \"{}\"",
+                content.len() - valid_size,
+                path.display()
+            );
+        }
+        let outdated = version != state_version::STATE_VERSION;
+        if outdated {
+            records = migrate_records(records, version)?;
+        }
+        let file = OpenOptions::new().write(true).open(&path)?;
+        // cut off the corrupt end, so new records can be read
+        file.set_len(valid_size as u64)?;
+        let mut container = Self {
+            path,
+            file,
+            records,
+            file_size: valid_size as u64,
+        };
+        if outdated {
+            // the header and the states must be rewritten
+            container.compact()?;
+        } else {
+            container.compact_if_needed()?;
+        }
+        Ok(container)
+    }
+
+    pub fn get(&self, kind: RecordKind, slot: u8) -> Option<&[u8]> {
+        self.records.get(&(kind, slot)).map(Vec::as_slice)
+    }
+
+    /// Append a record, replacing the previous record of the slot
+    pub fn put(&mut self, kind: RecordKind, slot: u8, data: Vec<u8>) -> std::io::Result<()> {
+        let record = encode_record(kind, slot, &data);
+        self.records.insert((kind, slot), data);
+        self.file.seek(SeekFrom::Start(self.file_size))?;
+        self.file.write_all(&record)?;
+        self.file_size += record.len() as u64;
+        self.compact_if_needed()
+    }
+
+    /// The size of the file, if it only contained the current records
+    fn compacted_size(&self) -> u64 {
+        let records: usize = self
+            .records
+            .values()
+            .map(|data| RECORD_HEADER_SIZE + data.len())
+            .sum();
+        (state_version::header(CONTAINER_MAGIC).len() + records) as u64
+    }
+
+    fn compact_if_needed(&mut self) -> std::io::Result<()> {
+        if self.file_size >= MIN_COMPACTION_SIZE && self.file_size > 2 * self.compacted_size() {
+            self.compact()
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Rewrite the file with only the current records
+    pub fn compact(&mut self) -> std::io::Result<()> {
+        let mut content = state_version::header(CONTAINER_MAGIC);
+        for (&(kind, slot), data) in &self.records {
+            content.extend(encode_record(kind, slot, data));
+        }
+        // replace the file atomically, so a crash doesn't lose any states
+        let tmp_path = self.path.with_extension("states.tmp");
+        std::fs::write(&tmp_path, &content)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().write(true).open(&self.path)?;
+        self.file_size = content.len() as u64;
+        Ok(())
+    }
+}
+
+/// Upgrade the states among the records of a container of the format `version`
+fn migrate_records(
+    records: BTreeMap<(RecordKind, u8), Vec<u8>>,
+    version: u32,
+) -> Result<BTreeMap<(RecordKind, u8), Vec<u8>>, StateFileError> {
+    let mut migrated = BTreeMap::new();
+    for ((kind, slot), data) in records {
+        let data = match kind {
+            RecordKind::State | RecordKind::Undo => match state_version::migrate(data, version) {
+                Ok(data) => data,
+                Err(err @ MigrationError::Incompatible { .. }) => {
+                    // the other slots stay usable
+                    eprintln!("[warning] dropping the state of slot {slot}: {err}");
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            },
+            RecordKind::Thumbnail => data,
+        };
+        migrated.insert((kind, slot), data);
+    }
+    // thumbnails of dropped states
+    let states: Vec<u8> = migrated
+        .keys()
+        .filter(|(kind, _)| *kind == RecordKind::State)
+        .map(|(_, slot)| *slot)
+        .collect();
+    migrated.retain(|(kind, slot), _| *kind != RecordKind::Thumbnail || states.contains(slot));
+    Ok(migrated)
+}
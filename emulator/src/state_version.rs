@@ -0,0 +1,248 @@
+//! Versions of the save state format
+//!
+//! The layout of a serialized device changes, whenever a component gains or
+//! loses state. Files containing states therefore start with a header naming
+//! the version of the format, e.g. `rsnes state 2\n`. States of older versions
+//! are upgraded step by step by the functions in [`MIGRATIONS`], either when
+//! they are loaded or by the `rsnes-state-migrate` utility. Files written
+//! before the header contained a version, e.g. `rsnes state\n`, are version 1.
+//!
+//! Whenever the layout changes, [`STATE_VERSION`] must be increased and a
+//! function converting states of the previous version must be appended to
+//! [`MIGRATIONS`].
+//!
+//! Migrations can't use the types of `rsnes`, which always have the layout
+//! of the current version. Instead, [`layout`] walks a serialized device
+//! along the frozen layout of the old version. This module only depends on
+//! `std`, so it is shared with the utility.
+
+/// The version of the current format
+pub const STATE_VERSION: u32 = 4;
+
+/// The magic of state files
+pub static STATE_FILE_MAGIC: &str = "rsnes state";
+
+/// The magic of state container files (`--state-container`)
+pub static CONTAINER_MAGIC: &str = "rsnes states";
+
+type Migration = fn(Vec<u8>) -> Result<Vec<u8>, MigrationError>;
+
+/// `MIGRATIONS[i]` upgrades a state of version `i + 1` to version `i + 2`
+static MIGRATIONS: [Migration; STATE_VERSION as usize - 1] = [from_v1, from_v2, from_v3];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// The state was written by a newer version of the emulator
+    Newer(u32),
+    /// The state can't be converted into the next version
+    Incompatible { version: u32, reason: &'static str },
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Newer(version) => write!(
+                fmt,
+                "the state has the format version {version}, which is newer than \
+                 the supported version {STATE_VERSION}"
+            ),
+            Self::Incompatible { version, reason } => write!(
+                fmt,
+                "the state can't be upgraded from the format version {version} ({reason})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+/// States of version 1 were written by many releases with different
+/// layouts, which can't be told apart, so they are rejected
+fn from_v1(_state: Vec<u8>) -> Result<Vec<u8>, MigrationError> {
+    Err(MigrationError::Incompatible {
+        version: 1,
+        reason: "states without a version in their header are too old",
+    })
+}
+
+/// Version 3 added the line of the character conversion DMA of the SA-1,
+/// which starts at the first line
+fn from_v2(mut state: Vec<u8>) -> Result<Vec<u8>, MigrationError> {
+    let corrupt = MigrationError::Incompatible {
+        version: 2,
+        reason: "the state is corrupt",
+    };
+    if let Some(sa1) = layout::find_sa1(&state, layout::SA1_SIZE_V2).ok_or(corrupt)? {
+        state.insert(sa1 + layout::SA1_DMA_LINE, 0)
+    }
+    Ok(state)
+}
+
+/// Version 4 clocked the SA-1 by a divider of the master clock, so it counts
+/// the cycles, until it continues, in SA-1 cycles instead of master cycles
+fn from_v3(mut state: Vec<u8>) -> Result<Vec<u8>, MigrationError> {
+    let corrupt = MigrationError::Incompatible {
+        version: 3,
+        reason: "the state is corrupt",
+    };
+    if let Some(sa1) = layout::find_sa1(&state, layout::SA1_SIZE_V3).ok_or(corrupt)? {
+        let ahead = sa1 + layout::SA1_AHEAD_CYCLES;
+        let bytes = &mut state[ahead..ahead + 4];
+        let cycles = i32::from_le_bytes(bytes.try_into().unwrap()).div_euclid(2);
+        bytes.copy_from_slice(&cycles.to_le_bytes());
+        // two master cycles per SA-1 cycle and no pending master cycles
+        let clock = [2u64, 1, 0].into_iter().flat_map(u64::to_le_bytes);
+        state.splice(ahead + 4..ahead + 4, clock);
+    }
+    Ok(state)
+}
+
+/// The frozen layout of serialized devices of old versions
+mod layout {
+    /// The size of the CPU, SMP, PPU and DMA, which precede the controllers
+    const COMPONENTS_SIZE: usize = 133_986;
+    /// The size of a controller port without the controller
+    const PORT_SIZE: usize = 10;
+    /// The size of the rest of the device following the cartridge
+    const TAIL_SIZE: usize = 131_112;
+    const DSP_SIZE: usize = 10_809;
+    const MAPPING_ENTRY_SIZE: usize = 12;
+    const FLASH_SIZE: usize = 3;
+    /// The size of the SA-1 in version 2, before the DMA line was added
+    pub const SA1_SIZE_V2: usize = 264_322;
+    /// The size of the SA-1 in version 3, before its clock was added
+    pub const SA1_SIZE_V3: usize = SA1_SIZE_V2 + 1;
+    /// The offset of the cycles, until the SA-1 continues, within the SA-1
+    pub const SA1_AHEAD_CYCLES: usize = 264_222;
+    /// The offset of the DMA line within the SA-1 of version 3
+    pub const SA1_DMA_LINE: usize = 264_275;
+
+    struct Reader<'a> {
+        state: &'a [u8],
+        pos: usize,
+    }
+
+    impl Reader<'_> {
+        fn skip(&mut self, n: usize) -> Option<()> {
+            self.pos = self
+                .pos
+                .checked_add(n)
+                .filter(|&pos| pos <= self.state.len())?;
+            Some(())
+        }
+
+        fn u8(&mut self) -> Option<u8> {
+            let byte = *self.state.get(self.pos)?;
+            self.pos += 1;
+            Some(byte)
+        }
+
+        fn bool(&mut self) -> Option<bool> {
+            self.u8().map(|byte| byte.count_ones() >= 4)
+        }
+
+        fn usize(&mut self) -> Option<usize> {
+            const SIZE: usize = core::mem::size_of::<usize>();
+            let bytes = self.state.get(self.pos..self.pos.checked_add(SIZE)?)?;
+            self.pos += SIZE;
+            Some(usize::from_le_bytes(bytes.try_into().ok()?))
+        }
+
+        /// Skip a vector of `size` bytes large elements
+        fn vec(&mut self, size: usize) -> Option<()> {
+            let len = self.usize()?;
+            self.skip(len.checked_mul(size)?)
+        }
+
+        /// Skip an option of a `size` bytes large value
+        fn option(&mut self, size: usize) -> Option<bool> {
+            let is_some = self.bool()?;
+            if is_some {
+                self.skip(size)?
+            }
+            Some(is_some)
+        }
+    }
+
+    /// Find the start of the SA-1 of the cartridge, which is `sa1_size`
+    /// bytes large. Returns `Some(None)` for cartridges without an SA-1 and
+    /// `None`, if the state doesn't fit the layout.
+    pub fn find_sa1(state: &[u8], sa1_size: usize) -> Option<Option<usize>> {
+        let mut reader = Reader { state, pos: 0 };
+        reader.skip(COMPONENTS_SIZE)?;
+        for _ in 0..2 {
+            let controller_size = match reader.u8()? {
+                0 => 0,
+                1 => 2,
+                2 => 11,
+                _ => return None,
+            };
+            reader.skip(controller_size + PORT_SIZE)?
+        }
+        // the I/O port and the auto joypad timer
+        reader.skip(3)?;
+        let mut sa1 = None;
+        if reader.bool()? {
+            // the header
+            reader.vec(1)?;
+            reader.skip(2)?;
+            let extended_size = match reader.u8()? {
+                0 => 0,
+                1 => 1,
+                2 => 16,
+                _ => return None,
+            };
+            reader.skip(extended_size + 1)?;
+            reader.option(1)?;
+            reader.skip(13)?;
+            // ROM and RAM
+            reader.vec(1)?;
+            reader.vec(1)?;
+            reader.option(DSP_SIZE)?;
+            let pos = reader.pos;
+            if reader.option(sa1_size)? {
+                sa1 = Some(pos + 1)
+            }
+            reader.vec(MAPPING_ENTRY_SIZE)?;
+            // the SRAM detection
+            reader.skip(1)?;
+            reader.option(FLASH_SIZE)?;
+        }
+        reader.skip(TAIL_SIZE)?;
+        (reader.pos == state.len()).then_some(sa1)
+    }
+}
+
+/// The header of a file starting with `magic` in the current format
+pub fn header(magic: &str) -> Vec<u8> {
+    format!("{magic} {STATE_VERSION}\n").into_bytes()
+}
+
+/// Split a file into the version of its format and the content after the
+/// header. Returns `None`, if the file doesn't start with `magic`.
+pub fn parse_header<'a>(content: &'a [u8], magic: &str) -> Option<(u32, &'a [u8])> {
+    let rest = content.strip_prefix(magic.as_bytes())?;
+    if let Some(rest) = rest.strip_prefix(b"\n") {
+        return Some((1, rest));
+    }
+    let rest = rest.strip_prefix(b" ")?;
+    let end = rest.iter().position(|&byte| byte == b'\n')?;
+    let version = std::str::from_utf8(&rest[..end]).ok()?.parse().ok()?;
+    (version > 0).then(|| (version, &rest[end + 1..]))
+}
+
+/// Upgrade a state of the format `version` to the current format
+pub fn migrate(mut state: Vec<u8>, version: u32) -> Result<Vec<u8>, MigrationError> {
+    if version > STATE_VERSION {
+        return Err(MigrationError::Newer(version));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        state = migration(state)?
+    }
+    Ok(state)
+}
+
+// the module is also included by the utility, which resolves paths from here
+#[cfg(test)]
+#[path = "state_version/tests.rs"]
+mod tests;
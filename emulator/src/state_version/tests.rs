@@ -0,0 +1,74 @@
+use super::*;
+use rsnes::{
+    controller::{Controller, Mouse},
+    test_util::{create_device, create_rom, TestDevice, HIROM, LOROM, SA1},
+};
+use save_state::{InSaveState, SaveStateSerializer};
+
+/// The size of the SA-1 in version 4 with its clock
+const SA1_SIZE_V4: usize = layout::SA1_SIZE_V3 + 24;
+
+fn save(device: &TestDevice) -> Vec<u8> {
+    let mut state = SaveStateSerializer { data: vec![] };
+    device.serialize(&mut state);
+    state.data
+}
+
+/// A state of version 4 of a newly loaded SA-1 cartridge
+fn sa1_state() -> (Vec<u8>, usize) {
+    let state = save(&create_device(&create_rom(0x8000, SA1, 0x35, 0, |_| ())));
+    let sa1 = layout::find_sa1(&state, SA1_SIZE_V4).unwrap().unwrap();
+    (state, sa1)
+}
+
+/// Convert a state of version 4 without pending master cycles back into
+/// version 3
+fn downgrade_to_v3(mut state: Vec<u8>, sa1: usize) -> Vec<u8> {
+    let ahead = sa1 + layout::SA1_AHEAD_CYCLES;
+    state.drain(ahead + 4..ahead + 28);
+    let cycles = i32::from_le_bytes(state[ahead..ahead + 4].try_into().unwrap()) * 2;
+    state[ahead..ahead + 4].copy_from_slice(&cycles.to_le_bytes());
+    state
+}
+
+#[test]
+pub fn test_from_v2() {
+    let (state, sa1) = sa1_state();
+    // a state of version 2 lacks the DMA line
+    let mut old_state = downgrade_to_v3(state.clone(), sa1);
+    old_state.remove(sa1 + layout::SA1_DMA_LINE);
+    assert_eq!(migrate(old_state.clone(), 2).unwrap(), state);
+    old_state.pop();
+    assert!(matches!(
+        migrate(old_state, 2),
+        Err(MigrationError::Incompatible { version: 2, .. })
+    ));
+}
+
+#[test]
+pub fn test_from_v3() {
+    let (state, sa1) = sa1_state();
+    let mut old_state = downgrade_to_v3(state.clone(), sa1);
+    assert_eq!(migrate(old_state.clone(), 3).unwrap(), state);
+    old_state.push(0);
+    assert!(matches!(
+        migrate(old_state, 3),
+        Err(MigrationError::Incompatible { version: 3, .. })
+    ));
+}
+
+#[test]
+pub fn test_migrate_without_sa1() {
+    let mut devices = vec![
+        create_device(&create_rom(0x8000, LOROM, 0, 0, |_| ())),
+        create_device(&create_rom(0x10000, HIROM, 0x02, 0x03, |_| ())),
+        create_device(&create_rom(0x8000, LOROM, 0x03, 0, |_| ())),
+    ];
+    devices[0].controllers.port1.controller = Controller::Mouse(Mouse::default());
+    devices[0].controllers.port2.controller = Controller::None;
+    for device in devices {
+        let state = save(&device);
+        assert_eq!(layout::find_sa1(&state, SA1_SIZE_V4), Some(None));
+        assert_eq!(migrate(state.clone(), 2).unwrap(), state);
+    }
+}
@@ -0,0 +1,130 @@
+//! Background work on captured states
+//!
+//! Capturing a state is fast, but hashing it or writing it to a file isn't,
+//! so that work is done by a worker thread, instead of delaying the next
+//! frame. The queue of the worker is bounded: when it's full, because the
+//! worker can't keep up, the job is dropped instead of waiting for it, and
+//! the dropped jobs are counted. So a slow disk causes missing states, but
+//! never a stuttering game.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    peak: AtomicUsize,
+    done: AtomicU64,
+    dropped: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+/// The load of a [`StateWorker`]
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerStats {
+    /// The jobs waiting in the queue
+    pub queued: usize,
+    /// The most jobs, which waited in the queue at once
+    pub peak: usize,
+    pub done: u64,
+    /// The jobs dropped, because the queue was full
+    pub dropped: u64,
+    /// The time spent on jobs
+    pub busy: Duration,
+}
+
+impl std::fmt::Display for WorkerStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} jobs done in {:.1} ms, {} dropped, {} queued (at most {})",
+            self.done,
+            self.busy.as_secs_f64() * 1000.0,
+            self.dropped,
+            self.queued,
+            self.peak
+        )
+    }
+}
+
+pub struct StateWorker {
+    name: &'static str,
+    jobs: Option<SyncSender<Job>>,
+    thread: Option<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+impl StateWorker {
+    /// Spawn a worker, which queues up to `capacity` jobs. `name` is shown in
+    /// warnings.
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        let (jobs, queue) = mpsc::sync_channel::<Job>(capacity);
+        let counters = Arc::new(Counters::default());
+        let thread = std::thread::spawn({
+            let counters = counters.clone();
+            move || {
+                for job in queue {
+                    let start = Instant::now();
+                    job();
+                    counters.queued.fetch_sub(1, Ordering::Relaxed);
+                    counters.done.fetch_add(1, Ordering::Relaxed);
+                    (counters.busy_nanos)
+                        .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                }
+            }
+        });
+        Self {
+            name,
+            jobs: Some(jobs),
+            thread: Some(thread),
+            counters,
+        }
+    }
+
+    /// Queue `job`. Returns false, if it was dropped, because the queue is
+    /// full.
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) -> bool {
+        let counters = &self.counters;
+        let queued = counters.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        let sent = (self.jobs.as_ref()).is_some_and(|jobs| jobs.try_send(Box::new(job)).is_ok());
+        if sent {
+            counters.peak.fetch_max(queued, Ordering::Relaxed);
+        } else {
+            counters.queued.fetch_sub(1, Ordering::Relaxed);
+            // warn only once, a slow disk stays slow
+            if counters.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                eprintln!(
+                    "[warning] {}: the states are captured faster than they are processed, some are skipped",
+                    self.name
+                )
+            }
+        }
+        sent
+    }
+
+    pub fn stats(&self) -> WorkerStats {
+        let counters = &self.counters;
+        WorkerStats {
+            queued: counters.queued.load(Ordering::Relaxed),
+            peak: counters.peak.load(Ordering::Relaxed),
+            done: counters.done.load(Ordering::Relaxed),
+            dropped: counters.dropped.load(Ordering::Relaxed),
+            busy: Duration::from_nanos(counters.busy_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Drop for StateWorker {
+    /// Finish the queued jobs, so no state file is left half written
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
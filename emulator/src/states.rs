@@ -0,0 +1,393 @@
+//! Save state slots and state files
+//!
+//! State files consist of a header with the magic `rsnes state` and the version
+//! of the format followed by the serialized device, so they can be shared
+//! between users. States of older versions are upgraded when they are read.
+//!
+//! The slots are kept in memory, unless a [`Container`] or [`SlotFiles`] are
+//! used.
+
+use crate::state_container::{Container, RecordKind};
+use crate::state_version::{self, MigrationError, STATE_FILE_MAGIC};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    integrity::IntegrityError,
+};
+use save_state::InSaveState;
+use std::path::{Path, PathBuf};
+
+/// The count of save state slots, selected by the number keys
+pub const SLOT_COUNT: usize = 10;
+
+/// The file extension of state files
+pub const STATE_FILE_EXTENSION: &str = "state";
+
+/// Thumbnails are scaled down by this factor in both directions
+const THUMBNAIL_SCALE: usize = 4;
+
+#[derive(Debug)]
+pub enum StateFileError {
+    Io(std::io::Error),
+    Magic,
+    Migration(MigrationError),
+    Integrity(IntegrityError),
+    /// The state could not be deserialized; the previous state was restored
+    Corrupt,
+}
+
+impl From<std::io::Error> for StateFileError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<MigrationError> for StateFileError {
+    fn from(err: MigrationError) -> Self {
+        Self::Migration(err)
+    }
+}
+
+impl From<IntegrityError> for StateFileError {
+    fn from(err: IntegrityError) -> Self {
+        Self::Integrity(err)
+    }
+}
+
+impl std::fmt::Display for StateFileError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(fmt, "unable to access state file ({err})"),
+            Self::Magic => write!(fmt, "not a rsnes state file"),
+            Self::Migration(err) => write!(fmt, "{err}"),
+            Self::Integrity(err) => write!(fmt, "{err}"),
+            Self::Corrupt => write!(fmt, "the state file is corrupt or incompatible"),
+        }
+    }
+}
+
+impl std::error::Error for StateFileError {}
+
+pub fn save<B: AudioBackend, FB: FrameBuffer>(device: &Device<B, FB>) -> Vec<u8> {
+    let mut serializer = save_state::SaveStateSerializer { data: vec![] };
+    device.serialize(&mut serializer);
+    serializer.data
+}
+
+pub fn load<B: AudioBackend, FB: FrameBuffer>(
+    device: &mut Device<B, FB>,
+    state: &[u8],
+) -> Result<(), IntegrityError> {
+    let mut deserializer = save_state::SaveStateDeserializer { data: state.iter() };
+    device.load_state(&mut deserializer)
+}
+
+/// Load a state which may not have been created by this emulator instance.
+/// If deserialization fails, the previous state is restored.
+pub fn load_checked<B: AudioBackend, FB: FrameBuffer>(
+    device: &mut Device<B, FB>,
+    state: &[u8],
+) -> Result<(), StateFileError> {
+    let backup = save(device);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut deserializer = save_state::SaveStateDeserializer { data: state.iter() };
+        device
+            .load_state(&mut deserializer)
+            .map(|()| deserializer.data.len())
+    }));
+    match result {
+        Ok(Ok(0)) => Ok(()),
+        Ok(Err(err)) => Err(err.into()),
+        _ => {
+            load(device, &backup)?;
+            Err(StateFileError::Corrupt)
+        }
+    }
+}
+
+/// Load a state and return the previous one
+fn swap<B: AudioBackend, FB: FrameBuffer>(
+    device: &mut Device<B, FB>,
+    state: &[u8],
+) -> Result<Vec<u8>, IntegrityError> {
+    let previous = save(device);
+    load(device, state)?;
+    Ok(previous)
+}
+
+/// Read a state file, upgrading its state to the current format
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, StateFileError> {
+    let content = std::fs::read(path)?;
+    let (version, state) =
+        state_version::parse_header(&content, STATE_FILE_MAGIC).ok_or(StateFileError::Magic)?;
+    Ok(state_version::migrate(state.to_vec(), version)?)
+}
+
+pub fn write_file<P: AsRef<Path>>(path: P, state: &[u8]) -> std::io::Result<()> {
+    let mut content = state_version::header(STATE_FILE_MAGIC);
+    content.extend_from_slice(state);
+    std::fs::write(path, content)
+}
+
+/// A scaled down picture of the screen at the time a state was saved
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Thumbnail {
+    fn capture<B: AudioBackend, FB: FrameBuffer>(device: &Device<B, FB>) -> Self {
+        let screen = device.ppu.frame_buffer.pixels();
+        let width = rsnes::ppu::SCREEN_WIDTH as usize / THUMBNAIL_SCALE;
+        let height = screen.len() / rsnes::ppu::SCREEN_WIDTH as usize / THUMBNAIL_SCALE;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // average the scaled down pixels
+                let mut sum = [0u32; 3];
+                for sy in 0..THUMBNAIL_SCALE {
+                    let row = (y * THUMBNAIL_SCALE + sy) * rsnes::ppu::SCREEN_WIDTH as usize;
+                    for pixel in &screen[row + x * THUMBNAIL_SCALE..][..THUMBNAIL_SCALE] {
+                        for (sum, channel) in sum.iter_mut().zip(pixel) {
+                            *sum += u32::from(*channel)
+                        }
+                    }
+                }
+                let [r, g, b] =
+                    sum.map(|sum| (sum / (THUMBNAIL_SCALE * THUMBNAIL_SCALE) as u32) as u8);
+                pixels.push([r, g, b, 0xff]);
+            }
+        }
+        Self {
+            width: width as u32,
+            height: height as u32,
+            pixels,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(self.width as u16).to_le_bytes());
+        data.extend_from_slice(&(self.height as u16).to_le_bytes());
+        data.extend(self.pixels.iter().flatten());
+        data
+    }
+
+    /// Returns `None` for invalid data and empty thumbnails
+    fn decode(data: &[u8]) -> Option<Self> {
+        let header = data.get(..4)?;
+        let size = |i: usize| u32::from(u16::from_le_bytes([header[i], header[i + 1]]));
+        let (width, height) = (size(0), size(2));
+        let pixels: Vec<[u8; 4]> = data[4..]
+            .chunks_exact(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect();
+        (pixels.len() == (width * height) as usize && !pixels.is_empty()).then_some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+/// A state file for every slot in a directory (`--state-dir`), named after
+/// the ROM, e.g. `game.state3`
+pub struct SlotFiles {
+    dir: PathBuf,
+    name: String,
+}
+
+impl SlotFiles {
+    pub fn new(dir: PathBuf, name: String) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, name })
+    }
+
+    fn path(&self, slot: usize) -> PathBuf {
+        self.dir
+            .join(format!("{}.{STATE_FILE_EXTENSION}{slot}", self.name))
+    }
+
+    /// Returns `Ok(None)`, if there is no file for the slot
+    fn read(&self, slot: usize) -> Result<Option<Vec<u8>>, StateFileError> {
+        match read_file(self.path(slot)) {
+            Ok(state) => Ok(Some(state)),
+            Err(StateFileError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write(&self, slot: usize, state: &[u8]) -> std::io::Result<()> {
+        // replace the file atomically, so a crash doesn't corrupt the slot
+        let path = self.path(slot);
+        let tmp_path = path.with_extension(format!("{STATE_FILE_EXTENSION}{slot}.tmp"));
+        write_file(&tmp_path, state)?;
+        std::fs::rename(&tmp_path, &path)
+    }
+}
+
+pub struct SaveStates {
+    pub slots: [Option<Vec<u8>>; SLOT_COUNT],
+    pub thumbnails: [Option<Thumbnail>; SLOT_COUNT],
+    /// The state before a slot was loaded the last time
+    undo: Option<Vec<u8>>,
+    container: Option<Container>,
+    files: Option<SlotFiles>,
+    /// Slots with a file, which wasn't read yet
+    unread: [bool; SLOT_COUNT],
+}
+
+impl SaveStates {
+    pub fn new() -> Self {
+        Self {
+            slots: [(); SLOT_COUNT].map(|()| None),
+            thumbnails: [(); SLOT_COUNT].map(|()| None),
+            undo: None,
+            container: None,
+            files: None,
+            unread: [false; SLOT_COUNT],
+        }
+    }
+
+    /// Keep every slot in a state file. The files are read when their slot
+    /// is loaded the first time.
+    pub fn with_files(files: SlotFiles) -> Self {
+        let mut states = Self::new();
+        states.unread = core::array::from_fn(|slot| files.path(slot).is_file());
+        states.files = Some(files);
+        states
+    }
+
+    /// Whether the slot is stored in a file, which wasn't read yet
+    pub fn is_unread(&self, slot: usize) -> bool {
+        self.unread[slot]
+    }
+
+    fn read_slot_file(&mut self, slot: usize) {
+        if !core::mem::take(&mut self.unread[slot]) {
+            return;
+        }
+        if let Some(files) = &self.files {
+            match files.read(slot) {
+                Ok(state) => self.slots[slot] = state,
+                Err(err) => eprintln!(
+                    "[warning] unable to read slot file \"{}\" ({err})",
+                    files.path(slot).display()
+                ),
+            }
+        }
+    }
+
+    /// Keep the slots in a container file, starting with the slots stored
+    /// in it
+    pub fn with_container(container: Container) -> Self {
+        let mut states = Self::new();
+        for slot in 0..SLOT_COUNT {
+            states.slots[slot] = container
+                .get(RecordKind::State, slot as u8)
+                .map(<[u8]>::to_vec);
+            states.thumbnails[slot] = container
+                .get(RecordKind::Thumbnail, slot as u8)
+                .and_then(Thumbnail::decode);
+        }
+        states.undo = container.get(RecordKind::Undo, 0).map(<[u8]>::to_vec);
+        states.container = Some(container);
+        states
+    }
+
+    fn write_container(&mut self, kind: RecordKind, slot: usize, data: &[u8]) {
+        if let Some(container) = &mut self.container {
+            if let Err(err) = container.put(kind, slot as u8, data.to_vec()) {
+                eprintln!("[warning] unable to write the state container ({err})")
+            }
+        }
+    }
+
+    pub fn store<B: AudioBackend, FB: FrameBuffer>(&mut self, slot: usize, device: &Device<B, FB>) {
+        let state = save(device);
+        let thumbnail = Thumbnail::capture(device);
+        self.write_container(RecordKind::State, slot, &state);
+        self.write_container(RecordKind::Thumbnail, slot, &thumbnail.encode());
+        self.write_slot_file(slot, &state);
+        self.slots[slot] = Some(state);
+        self.thumbnails[slot] = Some(thumbnail);
+    }
+
+    fn write_slot_file(&mut self, slot: usize, state: &[u8]) {
+        self.unread[slot] = false;
+        if let Some(files) = &self.files {
+            if let Err(err) = files.write(slot, state) {
+                eprintln!(
+                    "[warning] unable to write slot file \"{}\" ({err})",
+                    files.path(slot).display()
+                )
+            }
+        }
+    }
+
+    /// Load a slot. Returns `Ok(false)`, if the slot is empty.
+    /// The previous state is kept for [`SaveStates::undo`].
+    pub fn restore<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        slot: usize,
+        device: &mut Device<B, FB>,
+    ) -> Result<bool, IntegrityError> {
+        self.read_slot_file(slot);
+        let previous = match &self.slots[slot] {
+            Some(state) => swap(device, state)?,
+            None => return Ok(false),
+        };
+        self.set_undo(previous);
+        Ok(true)
+    }
+
+    /// Return to the state before the last slot was loaded. Undoing again
+    /// returns to the loaded state. Returns `Ok(false)`, if no slot was loaded.
+    pub fn undo<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+    ) -> Result<bool, IntegrityError> {
+        let previous = match &self.undo {
+            Some(state) => swap(device, state)?,
+            None => return Ok(false),
+        };
+        self.set_undo(previous);
+        Ok(true)
+    }
+
+    fn set_undo(&mut self, state: Vec<u8>) {
+        self.write_container(RecordKind::Undo, 0, &state);
+        self.undo = Some(state);
+    }
+
+    /// Write a slot to a state file. Returns `Ok(false)`, if the slot is empty.
+    pub fn export<P: AsRef<Path>>(&mut self, slot: usize, path: P) -> std::io::Result<bool> {
+        self.read_slot_file(slot);
+        match &self.slots[slot] {
+            Some(state) => write_file(path, state).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Read a state file into a slot. The state is validated by loading it
+    /// once, the current device state is kept.
+    pub fn import<B: AudioBackend, FB: FrameBuffer, P: AsRef<Path>>(
+        &mut self,
+        slot: usize,
+        path: P,
+        device: &mut Device<B, FB>,
+    ) -> Result<(), StateFileError> {
+        let state = read_file(path)?;
+        let current = save(device);
+        load_checked(device, &state)?;
+        load(device, &current)?;
+        self.write_container(RecordKind::State, slot, &state);
+        // an empty record removes the old thumbnail
+        self.write_container(RecordKind::Thumbnail, slot, &[]);
+        self.write_slot_file(slot, &state);
+        self.slots[slot] = Some(state);
+        self.thumbnails[slot] = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Playtime statistics
+//!
+//! For every ROM, identified by its CRC-32, the count of launches, the total
+//! playtime and the time it was last played are stored in a TOML file.
+//! Only emulated time is counted, so paused sessions don't add playtime.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use toml::value::{Table, Value};
+
+static STATS_FILE_PATH: &str = ".config/rsnes/stats.toml";
+
+#[derive(Debug, Clone, Default)]
+pub struct RomStats {
+    pub title: String,
+    pub launches: u64,
+    pub playtime: Duration,
+    /// Seconds since the unix epoch
+    pub last_played: u64,
+}
+
+impl RomStats {
+    fn load(map: &Table) -> Option<Self> {
+        let int = |name| match map.get(name) {
+            Some(Value::Integer(i)) => Some(*i as u64),
+            _ => None,
+        };
+        Some(Self {
+            title: map.get("title")?.as_str()?.to_owned(),
+            launches: int("launches")?,
+            playtime: Duration::from_secs(int("playtime")?),
+            last_played: int("last-played")?,
+        })
+    }
+
+    fn to_table(&self) -> Table {
+        let mut map = Table::new();
+        map.insert("title".into(), Value::String(self.title.clone()));
+        map.insert("launches".into(), Value::Integer(self.launches as i64));
+        map.insert(
+            "playtime".into(),
+            Value::Integer(self.playtime.as_secs() as i64),
+        );
+        map.insert(
+            "last-played".into(),
+            Value::Integer(self.last_played as i64),
+        );
+        map
+    }
+}
+
+pub struct Stats {
+    /// The file to save the statistics to. This is `None`, if the existing
+    /// file could not be parsed, to avoid overwriting it.
+    path: Option<PathBuf>,
+    pub roms: BTreeMap<String, RomStats>,
+}
+
+/// Format a duration as e.g. `12h 03m`
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+/// Format seconds since the unix epoch as a UTC date (`YYYY-MM-DD`)
+pub fn format_date(timestamp: u64) -> String {
+    // see <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Format seconds since the unix epoch as a UTC date and time
+/// (`YYYY-MM-DD-HHMMSS`), e.g. for file names
+pub fn format_timestamp(timestamp: u64) -> String {
+    let secs = timestamp % 86400;
+    format!(
+        "{}-{:02}{:02}{:02}",
+        format_date(timestamp),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Make the title of a game usable in file names on every file system. Path
+/// separators, characters reserved by Windows and control characters are
+/// replaced by `_`, leading and trailing dots and spaces are removed.
+pub fn sanitize_file_name(title: &str) -> String {
+    title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim_matches(['.', ' '])
+        .to_string()
+}
+
+/// The name of a file of a game created now, e.g. a screenshot: the title of
+/// the game followed by the current time (UTC), or only the time, if the
+/// game has no title
+pub fn timestamped_file_name(title: &str) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    match sanitize_file_name(title) {
+        title if title.is_empty() => format_timestamp(timestamp),
+        title => format!("{title} {}", format_timestamp(timestamp)),
+    }
+}
+
+/// Get the key of a ROM in the statistics file
+pub fn rom_id(rom: &[u8]) -> String {
+    format!("{:08x}", rsnes::patch::crc32(rom))
+}
+
+impl Stats {
+    /// Load the statistics file, or start with empty statistics
+    pub fn load() -> Self {
+        let path = std::env::var_os("HOME").map(|home| Path::new(&home).join(STATS_FILE_PATH));
+        let mut stats = Self {
+            path: path.clone(),
+            roms: BTreeMap::new(),
+        };
+        let content = match path.and_then(|path| std::fs::read_to_string(path).ok()) {
+            Some(content) => content,
+            None => return stats,
+        };
+        let roms = match toml::from_str::<Table>(&content) {
+            Ok(mut table) => table.remove("roms"),
+            Err(err) => {
+                eprintln!("[warning] statistics file parsing error: {err}");
+                stats.path = None;
+                return stats;
+            }
+        };
+        if let Some(Value::Table(roms)) = roms {
+            for (id, rom) in roms {
+                match rom.as_table().and_then(RomStats::load) {
+                    Some(rom) => {
+                        stats.roms.insert(id, rom);
+                    }
+                    None => eprintln!("[warning] ignoring invalid statistics entry `{id}`"),
+                }
+            }
+        }
+        stats
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let roms = self
+            .roms
+            .iter()
+            .map(|(id, rom)| (id.clone(), Value::Table(rom.to_table())))
+            .collect();
+        let mut table = Table::new();
+        table.insert("roms".into(), Value::Table(roms));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, Value::Table(table).to_string())
+    }
+
+    /// Count a launch of a ROM
+    pub fn launch(&mut self, id: &str, title: &str) {
+        let rom = self.roms.entry(id.to_owned()).or_default();
+        rom.title = title.to_owned();
+        rom.launches += 1;
+        rom.last_played = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+    }
+
+    pub fn add_playtime(&mut self, id: &str, playtime: Duration) {
+        if let Some(rom) = self.roms.get_mut(id) {
+            rom.playtime += playtime
+        }
+    }
+
+    /// Print all ROMs, most played first
+    pub fn print(&self) {
+        let mut roms: Vec<_> = self.roms.iter().collect();
+        roms.sort_by_key(|(_, rom)| std::cmp::Reverse(rom.playtime));
+        println!(
+            "{:<8} {:<24} {:>10} {:>8} {:>11}",
+            "crc32", "title", "playtime", "launches", "last played"
+        );
+        for (id, rom) in roms {
+            println!(
+                "{id:<8} {:<24} {:>10} {:>8} {:>11}",
+                rom.title,
+                format_duration(rom.playtime),
+                rom.launches,
+                format_date(rom.last_played)
+            );
+        }
+    }
+}
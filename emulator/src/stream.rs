@@ -0,0 +1,117 @@
+//! Streaming of the emulated frames to remote viewers (`--stream`)
+//!
+//! The emulator listens on a TCP address and sends every emulated frame to
+//! all connected viewers. By default, a connection is answered like an HTTP
+//! request with a `multipart/x-mixed-replace` stream of PNG images, which
+//! browsers show as a moving picture, like MJPEG. With `--raw-stream`, every
+//! frame is sent as its width and height (16 bit little endian) followed by
+//! the RGBA pixels, row by row. The height is 224 or, with overscan, 239.
+//!
+//! Frames are only encoded while a viewer is connected. Viewers, which can't
+//! keep up, skip frames instead of slowing down the emulation.
+
+use crate::{png, screenshot};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+
+static BOUNDARY: &str = "frame";
+
+type Frame = Arc<Vec<u8>>;
+
+pub struct FrameStream {
+    raw: bool,
+    local_addr: SocketAddr,
+    viewers: Arc<Mutex<Vec<mpsc::SyncSender<Frame>>>>,
+}
+
+impl FrameStream {
+    /// Listen for viewers on `addr`, e.g. `0.0.0.0:8080`
+    pub fn bind(addr: &str, raw: bool) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let viewers: Arc<Mutex<Vec<_>>> = Arc::default();
+        let new_viewers = viewers.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // only the newest frame is kept for a slow viewer
+                let (sender, frames) = mpsc::sync_channel(1);
+                new_viewers.lock().unwrap().push(sender);
+                std::thread::spawn(move || {
+                    // the viewer disconnected
+                    let _ = serve(stream, raw, frames);
+                });
+            }
+        });
+        Ok(Self {
+            raw,
+            local_addr,
+            viewers,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Must be called after every emulated frame
+    pub fn on_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &Device<B, FB>) {
+        let mut viewers = self.viewers.lock().unwrap();
+        if viewers.is_empty() {
+            return;
+        }
+        let (width, height, pixels) = screenshot::picture(device);
+        let frame = Arc::new(if self.raw {
+            let mut frame = Vec::with_capacity(4 + pixels.len() * 4);
+            frame.extend_from_slice(&(width as u16).to_le_bytes());
+            frame.extend_from_slice(&(height as u16).to_le_bytes());
+            frame.extend(pixels.iter().flatten());
+            frame
+        } else {
+            png::encode(width, height, &pixels)
+        });
+        viewers.retain(|viewer| {
+            !matches!(
+                viewer.try_send(frame.clone()),
+                Err(mpsc::TrySendError::Disconnected(_))
+            )
+        });
+    }
+}
+
+fn serve(mut stream: TcpStream, raw: bool, frames: mpsc::Receiver<Frame>) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    if !raw {
+        // any request is answered with the stream
+        let mut request = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        while request.read_line(&mut line)? > 0 && !line.trim_end().is_empty() {
+            line.clear()
+        }
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: multipart/x-mixed-replace; boundary={BOUNDARY}\r\n\
+             Cache-Control: no-cache\r\n\
+             Connection: close\r\n\r\n"
+        )?;
+    }
+    for frame in frames {
+        if !raw {
+            write!(
+                stream,
+                "--{BOUNDARY}\r\nContent-Type: image/png\r\nContent-Length: {}\r\n\r\n",
+                frame.len()
+            )?;
+        }
+        stream.write_all(&frame)?;
+        if !raw {
+            stream.write_all(b"\r\n")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,380 @@
+//! Movie recording, playback and editing
+//!
+//! While a movie is active, the emulator state at the start of every
+//! [`GREENZONE_INTERVAL`]-th frame is saved into the "greenzone".
+//! This allows seeking to any earlier frame by loading the closest preceding
+//! state and replaying the movie from there.
+//! Editing the input of a frame invalidates all greenzone states after it.
+//!
+//! The greenzone is bounded in memory. When it grows too large, states are
+//! evicted such that the greenzone stays dense around the current frame and
+//! becomes sparser the further away a state is.
+//!
+//! Branches and bookmarks of the movie are kept in a [`Project`].
+//!
+//! Movies run in the deterministic mode of the core. The latch count of the
+//! controllers tells, in which frames the game didn't read them: these lag
+//! frames are counted while a movie is recorded or played back.
+
+use crate::movie::{self, Movie, MovieLoadError};
+use crate::movie_import;
+use crate::project::{self, Branch, Project};
+use crate::states::{self, save as save_state};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    integrity::IntegrityError,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// The count of frames between two greenzone states
+pub const GREENZONE_INTERVAL: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Inactive,
+    Playback,
+    Record,
+}
+
+pub struct Tas {
+    pub movie: Option<Movie>,
+    /// The file the movie was loaded from or last saved to
+    pub movie_path: Option<PathBuf>,
+    pub project: Project,
+    pub mode: Mode,
+    /// The index of the frame that will be emulated next
+    pub frame: usize,
+    greenzone: BTreeMap<usize, Vec<u8>>,
+    /// The total size of all greenzone states in bytes
+    greenzone_size: usize,
+    /// The maximum value for `greenzone_size`
+    pub max_greenzone_size: usize,
+    power_on_state: Vec<u8>,
+    /// The frames, in which the controllers weren't latched
+    lag_frames: BTreeSet<usize>,
+    /// The latch count of the controllers at the start of the last frame,
+    /// `None` after a state was loaded
+    latch_count: Option<u64>,
+}
+
+impl Tas {
+    /// Create the movie state for a device that was just powered on
+    pub fn new<B: AudioBackend, FB: FrameBuffer>(
+        device: &Device<B, FB>,
+        max_greenzone_size: usize,
+    ) -> Self {
+        Self {
+            movie: None,
+            movie_path: None,
+            project: Project::default(),
+            mode: Mode::Inactive,
+            frame: 0,
+            greenzone: BTreeMap::new(),
+            greenzone_size: 0,
+            max_greenzone_size,
+            power_on_state: save_state(device),
+            lag_frames: BTreeSet::new(),
+            latch_count: None,
+        }
+    }
+
+    /// The state the movie starts from, its anchor or power-on
+    fn start_state(&self) -> &[u8] {
+        self.movie
+            .as_ref()
+            .and_then(|movie| movie.anchor.as_deref())
+            .unwrap_or(&self.power_on_state)
+    }
+
+    /// Start a movie from its anchor or from power-on
+    pub fn start<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        movie: Movie,
+        mode: Mode,
+    ) -> Result<(), IntegrityError> {
+        self.movie = Some(movie);
+        states::load(device, self.start_state())?;
+        device.enable_deterministic_mode();
+        self.mode = mode;
+        self.frame = 0;
+        self.lag_frames.clear();
+        self.latch_count = None;
+        self.clear_greenzone();
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.mode = Mode::Inactive;
+        self.clear_greenzone();
+    }
+
+    /// Continue recording at the current frame, discarding all later input
+    pub fn record_from_here(&mut self) {
+        if let Some(movie) = &mut self.movie {
+            movie.frames.truncate(self.frame);
+            let frame = self.frame;
+            self.lag_frames.retain(|&lag| lag < frame);
+            self.mode = Mode::Record;
+        }
+    }
+
+    /// Load a movie and its project file, if there is one, and start playback.
+    /// Movies of lsnes and BizHawk are imported, they have no file to save
+    /// them to until a path is given.
+    pub fn load_movie<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        path: &Path,
+    ) -> Result<(), MovieLoadError> {
+        if let Some(format) = movie_import::Format::from_path(path) {
+            let movie = format
+                .import(&std::fs::read(path)?)
+                .map_err(MovieLoadError::Import)?;
+            self.project = Project::default();
+            self.movie_path = None;
+            return self
+                .start(device, movie, Mode::Playback)
+                .map_err(|err| MovieLoadError::Anchor(err.into()));
+        }
+        let movie = Movie::load(path)?;
+        if let Some(anchor) = &movie.anchor {
+            states::load_checked(device, anchor).map_err(MovieLoadError::Anchor)?
+        }
+        let project_path = project::project_path(path);
+        self.project = if project_path.exists() {
+            Project::load(project_path)?
+        } else {
+            Project::default()
+        };
+        self.movie_path = Some(path.to_owned());
+        self.start(device, movie, Mode::Playback)
+            .map_err(|err| MovieLoadError::Anchor(err.into()))
+    }
+
+    /// Save the movie and, if it has branches or bookmarks, its project file
+    pub fn save_movie(&mut self, path: &Path) -> std::io::Result<()> {
+        if let Some(movie) = &self.movie {
+            movie.save(path)?;
+            if !self.project.is_empty() {
+                self.project.save(project::project_path(path))?;
+            }
+            self.movie_path = Some(path.to_owned());
+        }
+        Ok(())
+    }
+
+    /// Store the current movie input as a branch
+    pub fn save_branch(&mut self, name: String) {
+        if let Some(movie) = &self.movie {
+            let branch = Branch {
+                frame: self.frame,
+                frames: movie.frames.clone(),
+            };
+            self.project.branches.insert(name, branch);
+        }
+    }
+
+    /// Replace the movie input with a branch and go to the frame it was saved at.
+    /// Returns false, if there is no such branch.
+    pub fn load_branch<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        name: &str,
+    ) -> Result<bool, IntegrityError> {
+        let (branch, movie) = match (self.project.branches.get(name), &mut self.movie) {
+            (Some(branch), Some(movie)) => (branch, movie),
+            _ => return Ok(false),
+        };
+        let common = movie
+            .frames
+            .iter()
+            .zip(&branch.frames)
+            .take_while(|(a, b)| a == b)
+            .count();
+        movie.frames.clone_from(&branch.frames);
+        let frame = branch.frame;
+        self.invalidate(common);
+        self.seek(device, frame)?;
+        Ok(true)
+    }
+
+    /// Set a bookmark to the current frame
+    pub fn set_bookmark(&mut self, slot: usize) {
+        if let Some(bookmark) = self.project.bookmarks.get_mut(slot) {
+            *bookmark = Some(self.frame);
+        }
+    }
+
+    /// Go to the frame of a bookmark. Returns false, if the bookmark is not set.
+    pub fn jump_to_bookmark<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        slot: usize,
+    ) -> Result<bool, IntegrityError> {
+        match self.project.bookmarks.get(slot).copied().flatten() {
+            Some(frame) if self.movie.is_some() => {
+                self.seek(device, frame)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Invalidate the greenzone after the input of `frame` changed. If the
+    /// frame was already emulated, the current frame is restored with the new input.
+    pub fn input_changed<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        frame: usize,
+    ) -> Result<(), IntegrityError> {
+        self.invalidate(frame);
+        if frame < self.frame {
+            let (current, mode) = (self.frame, self.mode);
+            self.seek(device, current)?;
+            if mode == Mode::Record {
+                self.mode = mode
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop all greenzone states after `frame`, because its input changed
+    pub fn invalidate(&mut self, frame: usize) {
+        let invalid = self.greenzone.split_off(&(frame + 1));
+        self.greenzone_size -= invalid.values().map(Vec::len).sum::<usize>();
+        self.lag_frames.retain(|&lag| lag < frame);
+    }
+
+    /// The count of lag frames found so far
+    pub fn lag_frame_count(&self) -> usize {
+        self.lag_frames.len()
+    }
+
+    pub fn has_greenzone_state(&self, frame: usize) -> bool {
+        self.greenzone.contains_key(&frame)
+    }
+
+    /// Get the count and total size in bytes of the greenzone states
+    pub fn greenzone_usage(&self) -> (usize, usize) {
+        (self.greenzone.len(), self.greenzone_size)
+    }
+
+    fn clear_greenzone(&mut self) {
+        self.greenzone.clear();
+        self.greenzone_size = 0;
+    }
+
+    fn insert_greenzone_state(&mut self, frame: usize, state: Vec<u8>) {
+        self.greenzone_size += state.len();
+        if let Some(old) = self.greenzone.insert(frame, state) {
+            self.greenzone_size -= old.len();
+        }
+        while self.greenzone_size > self.max_greenzone_size && self.evict_greenzone_state() {}
+    }
+
+    /// Remove the state, whose removal creates the smallest gap relative
+    /// to its distance from the current frame.
+    /// The state of the current frame is never evicted and the oldest and
+    /// newest states are only evicted if there are no others.
+    /// Returns false, if there was no state to evict.
+    fn evict_greenzone_state(&mut self) -> bool {
+        let frames: Vec<usize> = self.greenzone.keys().copied().collect();
+        let victim = frames
+            .windows(3)
+            .filter(|window| window[1] != self.frame)
+            .min_by_key(|window| {
+                let gap = (window[2] - window[0]) as u64;
+                let distance = window[1].abs_diff(self.frame) as u64 + 1;
+                // compare gap / distance without rounding
+                (gap << 32) / distance
+            })
+            .map(|window| window[1])
+            .or_else(|| {
+                // with less than three states, drop the oldest one except
+                // for the current frame
+                frames.iter().copied().find(|&frame| frame != self.frame)
+            });
+        match victim.and_then(|frame| self.greenzone.remove(&frame)) {
+            Some(state) => {
+                self.greenzone_size -= state.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Must be called right before a frame is emulated
+    pub fn before_frame<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &mut Device<B, FB>) {
+        let frame_count = match (&self.movie, self.mode) {
+            (Some(movie), Mode::Playback | Mode::Record) => movie.frames.len(),
+            _ => return,
+        };
+        let latch_count = device.controllers.latch_count();
+        if let Some(previous) = self.latch_count.replace(latch_count) {
+            if previous == latch_count {
+                self.lag_frames.insert(self.frame - 1);
+            } else {
+                self.lag_frames.remove(&(self.frame - 1));
+            }
+        }
+        if self.mode == Mode::Playback && self.frame >= frame_count {
+            println!(
+                "[info] movie playback finished after {} frames ({} lag frames)",
+                self.frame,
+                self.lag_frames.len()
+            );
+            self.mode = Mode::Inactive;
+            return;
+        }
+        if self.frame.is_multiple_of(GREENZONE_INTERVAL)
+            && !self.greenzone.contains_key(&self.frame)
+        {
+            let state = save_state(device);
+            self.insert_greenzone_state(self.frame, state);
+        }
+        let movie = self.movie.as_mut().unwrap();
+        match self.mode {
+            Mode::Playback => movie::write_input(&mut device.controllers, movie.frames[self.frame]),
+            Mode::Record => *movie.input_mut(self.frame) = movie::read_input(&device.controllers),
+            Mode::Inactive => unreachable!(),
+        }
+        self.frame += 1;
+    }
+
+    /// Restore the emulation state at the start of `frame` by loading the closest
+    /// greenzone state and replaying the movie from there.
+    /// Movie input is played back afterwards.
+    pub fn seek<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        frame: usize,
+    ) -> Result<(), IntegrityError> {
+        let frame = match &self.movie {
+            Some(movie) => frame.min(movie.frames.len()),
+            None => return Ok(()),
+        };
+        match self.greenzone.range(..=frame).next_back() {
+            Some((&start, state)) => {
+                states::load(device, state)?;
+                self.frame = start;
+            }
+            None => {
+                states::load(device, self.start_state())?;
+                self.frame = 0;
+            }
+        }
+        self.latch_count = None;
+        self.mode = Mode::Playback;
+        // the frames are replayed without stopping at breakpoints
+        let debugger = device.debugger.take();
+        while self.frame < frame {
+            self.before_frame(device);
+            crate::run_frame(device);
+        }
+        device.debugger = debugger;
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+//! Hotkeys entered on the terminal (`--no-video`)
+//!
+//! Without a window, there are no key events, so hotkeys are read line by line
+//! from stdin instead. Every line contains a single hotkey, optionally
+//! followed by a slot:
+//!
+//! - `p` pauses or resumes
+//! - `t` toggles turbo
+//! - `s <slot>` and `l <slot>` save and load a slot, `u` undoes loading it
+//! - `m <voice>` mutes or unmutes a DSP voice (0 to 7), `o <voice>` outputs
+//!   only that voice or all voices again, `m` alone unmutes all voices
+//! - `q` quits, as does closing stdin
+//! - `h` lists the hotkeys
+
+use crate::states::{SaveStates, SLOT_COUNT};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    runner::Governor,
+};
+use std::io::BufRead;
+use std::sync::mpsc;
+
+static HELP: &str = "\
+p           pause or resume
+t           toggle turbo
+s <slot>    save to a slot
+l <slot>    load a slot
+u           undo loading a slot
+m <voice>   mute or unmute a DSP voice
+o <voice>   solo a DSP voice or end the solo
+m           unmute all DSP voices
+q           quit";
+
+pub struct Terminal {
+    lines: mpsc::Receiver<Option<String>>,
+    pub paused: bool,
+}
+
+impl Terminal {
+    /// Spawn the thread reading hotkeys from stdin
+    pub fn new() -> Self {
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if sender.send(Some(line)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            // stdin was closed
+            let _ = sender.send(None);
+        });
+        Self {
+            lines,
+            paused: false,
+        }
+    }
+
+    /// Execute all entered hotkeys. Returns false, if the emulator should exit.
+    pub fn poll<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &mut Device<B, FB>,
+        states: &mut SaveStates,
+        governor: &mut Governor,
+    ) -> bool {
+        loop {
+            match self.lines.try_recv() {
+                Ok(Some(line)) => {
+                    if !self.execute(&line, device, states, governor) {
+                        return false;
+                    }
+                }
+                Ok(None) | Err(mpsc::TryRecvError::Disconnected) => return false,
+                Err(mpsc::TryRecvError::Empty) => return true,
+            }
+        }
+    }
+
+    fn execute<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        line: &str,
+        device: &mut Device<B, FB>,
+        states: &mut SaveStates,
+        governor: &mut Governor,
+    ) -> bool {
+        let mut args = line.split_whitespace();
+        let key = match args.next() {
+            Some(key) => key,
+            None => return true,
+        };
+        let arg = args.next().and_then(|arg| arg.parse::<usize>().ok());
+        let slot = arg.filter(|slot| *slot < SLOT_COUNT);
+        let voice = arg.filter(|voice| *voice < 8).map(|voice| voice as u8);
+        match (key, slot) {
+            ("q", _) => return false,
+            ("h", _) => println!("{HELP}"),
+            ("p", _) => {
+                self.paused = !self.paused;
+                println!("[info] {}", if self.paused { "paused" } else { "resumed" })
+            }
+            ("t", _) => {
+                governor.set_turbo(!governor.turbo());
+                println!(
+                    "[info] turbo {}",
+                    if governor.turbo() {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                )
+            }
+            ("s", Some(slot)) => {
+                states.store(slot, device);
+                println!("[info] saved slot {slot}")
+            }
+            ("l", Some(slot)) => match states.restore(slot, device) {
+                Ok(true) => println!("[info] loaded slot {slot}"),
+                Ok(false) => eprintln!("[warning] slot {slot} is empty"),
+                Err(err) => eprintln!("[warning] {err}"),
+            },
+            ("u", _) => match states.undo(device) {
+                Ok(true) => println!("[info] undid loading the slot"),
+                Ok(false) => eprintln!("[warning] no slot was loaded"),
+                Err(err) => eprintln!("[warning] {err}"),
+            },
+            ("m", _) if arg.is_none() => crate::reset_voice_muting(device),
+            ("m", _) | ("o", _) => match voice {
+                Some(voice) if key == "m" => crate::toggle_voice_muted(device, voice),
+                Some(voice) => crate::toggle_solo_voice(device, voice),
+                None => eprintln!("[warning] expected a voice from 0 to 7"),
+            },
+            ("s" | "l", None) => {
+                eprintln!("[warning] expected a slot from 0 to {}", SLOT_COUNT - 1)
+            }
+            _ => eprintln!("[warning] unknown hotkey `{key}`, enter `h` for a list"),
+        }
+        true
+    }
+}
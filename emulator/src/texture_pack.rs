@@ -0,0 +1,115 @@
+//! Texture packs (see [`rsnes::texture`])
+//!
+//! A texture pack is a directory of PNG images named after the hash of the
+//! replaced tile, e.g. `0123456789abcdef.png`. Subdirectories are searched as
+//! well, so the images can be sorted freely. A replacement must be a multiple
+//! of the tile's size in both directions, e.g. 32×32 pixels for an 8×8 tile.
+//! Images are loaded when their tile is drawn for the first time and are kept
+//! in a cache of limited size.
+//!
+//! In dump mode, the original of every drawn tile is written to the dump
+//! directory with the same naming scheme, to be used as template by artists.
+
+use crate::png;
+use rsnes::texture::{Texture, TexturePack};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub struct TextureLoader {
+    files: HashMap<u64, PathBuf>,
+    dump_dir: Option<PathBuf>,
+    /// The maximum memory used for replacements in bytes
+    cache_size: usize,
+}
+
+/// Get the tile hash from a file name like `0123456789abcdef.png`
+fn parse_file_name(path: &Path) -> Option<u64> {
+    if !path.extension()?.eq_ignore_ascii_case("png") {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    if stem.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(stem, 16).ok()
+}
+
+fn dump_path(dir: &Path, hash: u64) -> PathBuf {
+    dir.join(format!("{hash:016x}.png"))
+}
+
+impl TextureLoader {
+    pub fn new(
+        pack_dir: Option<&Path>,
+        dump_dir: Option<PathBuf>,
+        cache_size: usize,
+    ) -> std::io::Result<Self> {
+        let mut loader = Self {
+            files: HashMap::new(),
+            dump_dir,
+            cache_size,
+        };
+        if let Some(dir) = pack_dir {
+            loader.index(dir)?
+        }
+        if let Some(dir) = &loader.dump_dir {
+            std::fs::create_dir_all(dir)?
+        }
+        Ok(loader)
+    }
+
+    fn index(&mut self, dir: &Path) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                self.index(&path)?
+            } else if let Some(hash) = parse_file_name(&path) {
+                self.files.insert(hash, path);
+            }
+        }
+        Ok(())
+    }
+
+    /// The count of replacements in the texture pack
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Load the requested replacements and write dumped tiles.
+    /// Must be called after every emulated frame.
+    pub fn update(&mut self, pack: &mut TexturePack) {
+        if pack.memory_usage() > self.cache_size {
+            pack.clear()
+        }
+        for hash in pack.take_requests() {
+            let path = match self.files.get(&hash) {
+                Some(path) => path,
+                None => continue,
+            };
+            match png::read(path) {
+                Ok(image) => {
+                    pack.insert(hash, Texture::new(image.width, image.height, image.pixels))
+                }
+                Err(err) => {
+                    eprintln!("[warning] ignoring texture \"{}\": {err}", path.display());
+                    self.files.remove(&hash);
+                }
+            }
+        }
+        if let Some(dir) = &self.dump_dir {
+            for (hash, texture) in pack.take_dumped() {
+                let path = dump_path(dir, hash);
+                if path.exists() {
+                    continue;
+                }
+                let (width, height) = (texture.width(), texture.height());
+                if let Err(err) = png::write(&path, width, height, texture.pixels()) {
+                    eprintln!(
+                        "[warning] unable to dump tile \"{}\": {err}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+}
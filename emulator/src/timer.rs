@@ -0,0 +1,145 @@
+//! Speedrun timer (`timer = true`)
+//!
+//! The timer is drawn in the top right corner of the picture, so runs can be
+//! practiced without external timer software. The real time (RTA) is the
+//! time of the wall clock, which is started and stopped with the timer key
+//! and reset with the timer reset key. It keeps running while the emulation
+//! is paused, like it does on a console.
+//!
+//! Many games count the time played themselves. If the profile of the game
+//! has the addresses of this in-game time (`igt`), it's read from the memory
+//! of the console after every frame and shown below the real time.
+
+use crate::config::IgtAddresses;
+use crate::menu::{draw_text, CELL_SIZE, SELECTED_COLOR, TEXT_COLOR};
+use rsnes::{
+    backend::{AudioBackend, FrameBuffer},
+    device::{Addr24, Device},
+};
+use std::time::{Duration, Instant};
+
+/// The frames per second of an NTSC console
+const NTSC_FRAME_RATE: f64 = 21_477_272.0 / 357_366.0;
+/// The frames per second of a PAL console
+const PAL_FRAME_RATE: f64 = 21_281_370.0 / 425_568.0;
+
+pub struct Timer {
+    /// The real time before the last start
+    elapsed: Duration,
+    /// When the real time was started, if it's running
+    started: Option<Instant>,
+    igt: Option<IgtAddresses>,
+    frame_rate: f64,
+    /// The in-game time read after the last frame
+    last_igt: Option<Duration>,
+}
+
+impl Timer {
+    pub fn new(igt: Option<IgtAddresses>, is_pal: bool) -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            started: None,
+            igt,
+            frame_rate: if is_pal {
+                PAL_FRAME_RATE
+            } else {
+                NTSC_FRAME_RATE
+            },
+            last_igt: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// Start the real time, or stop it, if it's running
+    pub fn toggle(&mut self) {
+        match self.started.take() {
+            Some(started) => self.elapsed += started.elapsed(),
+            None => self.started = Some(Instant::now()),
+        }
+    }
+
+    /// Stop the real time and set it to zero
+    pub fn reset(&mut self) {
+        self.elapsed = Duration::ZERO;
+        self.started = None;
+    }
+
+    /// The real time
+    pub fn real_time(&self) -> Duration {
+        self.elapsed
+            + self
+                .started
+                .map_or(Duration::ZERO, |started| started.elapsed())
+    }
+
+    /// Read the in-game time from the memory of `device`. Must be called
+    /// after every frame.
+    pub fn update<B: AudioBackend, FB: FrameBuffer>(&mut self, device: &Device<B, FB>) {
+        let igt = match self.igt {
+            Some(igt) => igt,
+            None => return,
+        };
+        let read = |addr: Option<Addr24>| {
+            let value = addr.and_then(|addr| device.peek(addr)).unwrap_or(0);
+            if igt.bcd {
+                u64::from(value >> 4) * 10 + u64::from(value & 0xf)
+            } else {
+                u64::from(value)
+            }
+        };
+        let seconds = read(igt.hours) * 3600 + read(igt.minutes) * 60 + read(igt.seconds);
+        let frames = read(igt.frames) as f64 / self.frame_rate;
+        self.last_igt = Some(Duration::from_secs(seconds) + Duration::from_secs_f64(frames));
+    }
+
+    /// Draw the timer over a picture of RGBA pixels
+    pub fn draw(&self, picture: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let (width, height) = (width as usize, height as usize);
+        // the picture of HD Mode 7 is scaled up, the timer is scaled with it
+        let scale = (width / rsnes::ppu::SCREEN_WIDTH as usize).max(1);
+        let rta_color = if self.is_running() {
+            SELECTED_COLOR
+        } else {
+            TEXT_COLOR
+        };
+        let mut lines = vec![(format!("RTA {}", format_time(self.real_time())), rta_color)];
+        if let Some(igt) = self.last_igt {
+            lines.push((format!("IGT {}", format_time(igt)), TEXT_COLOR));
+        }
+        let columns = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+        let [cell_width, cell_height] = CELL_SIZE.map(|len| len * scale);
+        let left = width.saturating_sub(columns * cell_width + 3 * scale);
+        let bottom = (lines.len() * cell_height).min(height);
+        let mut out = picture.to_vec();
+        // darken the background of the text, so it's readable on every picture
+        for row in out.chunks_exact_mut(width * 4).take(bottom) {
+            for pixel in row[left * 4..].chunks_exact_mut(4) {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+                pixel.copy_from_slice(&[r / 4, g / 4, b / 4, 0xff]);
+            }
+        }
+        for (row, (line, color)) in lines.iter().enumerate() {
+            // the lines are right-aligned
+            let x = width.saturating_sub((line.len() * CELL_SIZE[0] + 1) * scale);
+            let pos = [x, (row * CELL_SIZE[1] + 2) * scale];
+            draw_text(&mut out, width, pos, scale, line, *color);
+        }
+        out
+    }
+}
+
+/// Format a time like `1:02:03.45`, the hours are left out while they're
+/// zero
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    let hundredths = time.subsec_millis() / 10;
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{secs:02}.{hundredths:02}")
+    } else {
+        format!("{minutes}:{secs:02}.{hundredths:02}")
+    }
+}
@@ -0,0 +1,138 @@
+//! Touch controls over the picture (`touch-overlay = true`)
+//!
+//! The buttons of a standard controller are drawn over the picture, the
+//! D-pad on the left, the face buttons on the right, the shoulder buttons at
+//! the top and Select and Start at the bottom. Every finger presses the
+//! button under it, a finger between two directions of the D-pad presses
+//! both. The buttons are pressed on the controller in port 1 in addition to
+//! the keys mapped to it, so both can be used at once.
+
+use crate::menu::{draw_text, CELL_SIZE, SELECTED_COLOR, TEXT_COLOR};
+use crate::renderer::ScreenMapping;
+use rsnes::controller::{buttons, Controller};
+use rsnes::ppu::SCREEN_WIDTH;
+use std::collections::HashMap;
+use winit::event::{Touch, TouchPhase};
+
+const RELEASED_COLOR: [u8; 3] = [0x80; 3];
+
+/// The buttons with their positions and sizes in pixels of the console and
+/// their labels
+static BUTTONS: [(u16, [usize; 4], &str); 12] = [
+    (buttons::L, [0, 0, 40, 16], "L"),
+    (buttons::R, [216, 0, 40, 16], "R"),
+    (buttons::UP, [24, 132, 20, 20], ""),
+    (buttons::LEFT, [4, 152, 20, 20], ""),
+    (buttons::RIGHT, [44, 152, 20, 20], ""),
+    (buttons::DOWN, [24, 172, 20, 20], ""),
+    (buttons::SELECT, [94, 204, 32, 12], "SEL"),
+    (buttons::START, [130, 204, 32, 12], "STA"),
+    (buttons::X, [212, 132, 20, 20], "X"),
+    (buttons::Y, [192, 152, 20, 20], "Y"),
+    (buttons::A, [232, 152, 20, 20], "A"),
+    (buttons::B, [212, 172, 20, 20], "B"),
+];
+
+/// The corners of the D-pad, which aren't drawn
+static DIAGONALS: [(u16, [usize; 4]); 4] = [
+    (buttons::UP | buttons::LEFT, [4, 132, 20, 20]),
+    (buttons::UP | buttons::RIGHT, [44, 132, 20, 20]),
+    (buttons::DOWN | buttons::LEFT, [4, 172, 20, 20]),
+    (buttons::DOWN | buttons::RIGHT, [44, 172, 20, 20]),
+];
+
+#[derive(Default)]
+pub struct TouchOverlay {
+    /// The positions of the fingers on the picture in pixels of the console
+    fingers: HashMap<u64, [u16; 2]>,
+    /// The buttons pressed on the controller by the last call to
+    /// [`TouchOverlay::apply`]
+    applied: u16,
+}
+
+impl TouchOverlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a finger on the window, which shows the picture with `mapping`.
+    /// Fingers outside of the picture don't press any button.
+    pub fn touch(&mut self, touch: &Touch, mapping: &ScreenMapping) {
+        let pos = match touch.phase {
+            TouchPhase::Started | TouchPhase::Moved => {
+                mapping.to_pixel([touch.location.x, touch.location.y])
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => None,
+        };
+        match pos {
+            Some(pos) => self.fingers.insert(touch.id, pos),
+            None => self.fingers.remove(&touch.id),
+        };
+    }
+
+    /// The buttons under the fingers
+    pub fn held(&self) -> u16 {
+        let hit = |[x, y, w, h]: [usize; 4], [fx, fy]: [u16; 2]| {
+            (x..x + w).contains(&usize::from(fx)) && (y..y + h).contains(&usize::from(fy))
+        };
+        let areas = BUTTONS
+            .iter()
+            .map(|&(mask, area, _)| (mask, area))
+            .chain(DIAGONALS);
+        areas
+            .filter(|&(_, area)| self.fingers.values().any(|&finger| hit(area, finger)))
+            .fold(0, |held, (mask, _)| held | mask)
+    }
+
+    /// Press the buttons under the fingers on `controller` and release the
+    /// ones, which aren't touched anymore. Buttons pressed by keys are kept.
+    pub fn apply(&mut self, controller: &mut Controller) {
+        if let Controller::Standard(cntrl) = controller {
+            let held = self.held();
+            cntrl.pressed_buttons = (cntrl.pressed_buttons & !self.applied) | held;
+            self.applied = held;
+        }
+    }
+
+    /// Draw the buttons over a picture of RGBA pixels
+    pub fn draw(&self, picture: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let (width, height) = (width as usize, height as usize);
+        // the picture of HD Mode 7 is scaled up, the buttons are scaled with it
+        let scale = (width / SCREEN_WIDTH as usize).max(1);
+        let held = self.held();
+        let mut out = picture.to_vec();
+        for (mask, [x, y, w, h], label) in BUTTONS {
+            let color = if held & mask > 0 {
+                SELECTED_COLOR
+            } else {
+                RELEASED_COLOR
+            };
+            // the buttons are translucent, so the game stays visible
+            let columns = (x * scale).min(width)..((x + w) * scale).min(width);
+            for py in (y * scale).min(height)..((y + h) * scale).min(height) {
+                let row =
+                    &mut out[(py * width + columns.start) * 4..(py * width + columns.end) * 4];
+                for pixel in row.chunks_exact_mut(4) {
+                    for (component, color) in pixel.iter_mut().zip(color) {
+                        *component = ((u16::from(*component) + u16::from(color)) / 2) as u8
+                    }
+                }
+            }
+            // the label is centered in the button
+            let text_width = (label.len() * CELL_SIZE[0]).saturating_sub(1);
+            let pos = [
+                x + w.saturating_sub(text_width) / 2,
+                y + h.saturating_sub(7) / 2,
+            ];
+            draw_text(
+                &mut out,
+                width,
+                [pos[0] * scale, pos[1] * scale],
+                scale,
+                label,
+                TEXT_COLOR,
+            );
+        }
+        out
+    }
+}
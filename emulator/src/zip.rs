@@ -0,0 +1,146 @@
+//! A minimal reader of ZIP archives
+//!
+//! Entries are found by the central directory at the end of the archive.
+//! Stored and deflate compressed entries are supported, encrypted entries and
+//! the ZIP64 extensions are not.
+
+use crate::inflate::{self, inflate};
+use rsnes::patch::crc32;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+
+#[derive(Debug)]
+pub enum ZipError {
+    Format(&'static str),
+    /// The archive has no entry with this name
+    Missing(String),
+}
+
+impl std::fmt::Display for ZipError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Format(msg) => write!(fmt, "invalid or unsupported ZIP archive ({msg})"),
+            Self::Missing(name) => write!(fmt, "the archive contains no file \"{name}\""),
+        }
+    }
+}
+
+impl std::error::Error for ZipError {}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ZipError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ZipError::Format("truncated archive"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ZipError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ZipError::Format("truncated archive"))
+}
+
+struct Entry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    header_offset: usize,
+}
+
+pub struct Archive<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+impl<'a> Archive<'a> {
+    /// Read the central directory of the archive `data`
+    pub fn new(data: &'a [u8]) -> Result<Self, ZipError> {
+        // the end record is followed by a comment of up to 64 KiB
+        let end = (0..data.len().saturating_sub(21))
+            .rev()
+            .take(0x10000)
+            .find(|&offset| u32_at(data, offset).ok() == Some(END_OF_DIRECTORY))
+            .ok_or(ZipError::Format("missing end of central directory"))?;
+        let count = u16_at(data, end + 10)?;
+        let mut offset = u32_at(data, end + 16)? as usize;
+        // ZIP64 archives keep the offset in a record of their own
+        if offset == u32::MAX as usize {
+            return Err(ZipError::Format("ZIP64 archive"));
+        }
+        let mut entries = Vec::with_capacity(count.into());
+        for _ in 0..count {
+            if u32_at(data, offset)? != CENTRAL_HEADER {
+                return Err(ZipError::Format("invalid central directory"));
+            }
+            let name_len = usize::from(u16_at(data, offset + 28)?);
+            let extra_len = usize::from(u16_at(data, offset + 30)?);
+            let comment_len = usize::from(u16_at(data, offset + 32)?);
+            let name = data
+                .get(offset + 46..offset + 46 + name_len)
+                .ok_or(ZipError::Format("truncated archive"))?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                flags: u16_at(data, offset + 8)?,
+                method: u16_at(data, offset + 10)?,
+                crc: u32_at(data, offset + 16)?,
+                compressed_size: u32_at(data, offset + 20)? as usize,
+                size: u32_at(data, offset + 24)? as usize,
+                header_offset: u32_at(data, offset + 42)? as usize,
+            });
+            offset += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Self { data, entries })
+    }
+
+    /// The names of all entries, including directories
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    /// Decompress the entry called `name`
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, ZipError> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| ZipError::Missing(name.to_owned()))?;
+        // ZIP64 keeps the sizes and offsets, which don't fit, in an extra field
+        if [entry.compressed_size, entry.size, entry.header_offset].contains(&(u32::MAX as usize)) {
+            return Err(ZipError::Format("ZIP64 entry"));
+        }
+        if entry.flags & 1 > 0 {
+            return Err(ZipError::Format("encrypted entry"));
+        }
+        if entry.size > inflate::MAX_SIZE {
+            return Err(ZipError::Format("entry too large"));
+        }
+        let offset = entry.header_offset;
+        if u32_at(self.data, offset)? != LOCAL_HEADER {
+            return Err(ZipError::Format("invalid local header"));
+        }
+        let start = offset
+            + 30
+            + usize::from(u16_at(self.data, offset + 26)?)
+            + usize::from(u16_at(self.data, offset + 28)?);
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size)
+            .ok_or(ZipError::Format("truncated archive"))?;
+        let content = match entry.method {
+            0 => compressed.to_vec(),
+            8 => inflate(compressed, entry.size).map_err(ZipError::Format)?.0,
+            _ => return Err(ZipError::Format("unsupported compression method")),
+        };
+        if content.len() != entry.size || crc32(&content) != entry.crc {
+            return Err(ZipError::Format("checksum mismatch"));
+        }
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,176 @@
+use super::*;
+
+/// "abcabcabcabc" as a deflate stream
+const DEFLATED: [u8; 7] = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00];
+
+/// The fields of an entry, which are the same in its local header and in
+/// the central directory
+struct TestEntry<'a> {
+    name: &'a str,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed: &'a [u8],
+    size: u32,
+}
+
+impl<'a> TestEntry<'a> {
+    fn stored(name: &'a str, content: &'a [u8]) -> Self {
+        Self {
+            name,
+            flags: 0,
+            method: 0,
+            crc: crc32(content),
+            compressed: content,
+            size: content.len() as u32,
+        }
+    }
+
+    fn deflated(name: &'a str) -> Self {
+        Self {
+            name,
+            flags: 0,
+            method: 8,
+            crc: crc32(b"abcabcabcabc"),
+            compressed: &DEFLATED,
+            size: 12,
+        }
+    }
+
+    /// The fields from the version needed on
+    fn fields(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&20u16.to_le_bytes());
+        data.extend_from_slice(&self.flags.to_le_bytes());
+        data.extend_from_slice(&self.method.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&self.crc.to_le_bytes());
+        data.extend_from_slice(&(self.compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&self.size.to_le_bytes());
+        data.extend_from_slice(&(self.name.len() as u16).to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+    }
+}
+
+/// An archive of `entries` with the archive comment `comment`
+fn create_archive(entries: &[TestEntry], comment: &[u8]) -> Vec<u8> {
+    let mut data = vec![];
+    let mut offsets = vec![];
+    for entry in entries {
+        offsets.push(data.len() as u32);
+        data.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        entry.fields(&mut data);
+        data.extend_from_slice(entry.name.as_bytes());
+        data.extend_from_slice(entry.compressed);
+    }
+    let directory = data.len();
+    for (entry, offset) in entries.iter().zip(offsets) {
+        data.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+        data.extend_from_slice(&20u16.to_le_bytes());
+        entry.fields(&mut data);
+        data.extend_from_slice(&[0; 10]);
+        data.extend_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(entry.name.as_bytes());
+    }
+    let directory_size = data.len() - directory;
+    data.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    data.extend_from_slice(&(directory_size as u32).to_le_bytes());
+    data.extend_from_slice(&(directory as u32).to_le_bytes());
+    data.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+    data.extend_from_slice(comment);
+    data
+}
+
+fn format_error<T>(result: Result<T, ZipError>) -> Option<&'static str> {
+    match result {
+        Err(ZipError::Format(msg)) => Some(msg),
+        _ => None,
+    }
+}
+
+#[test]
+pub fn test_read() {
+    let data = create_archive(
+        &[
+            TestEntry::stored("roms/", b""),
+            TestEntry::stored("roms/test.sfc", b"rsnes"),
+            TestEntry::deflated("roms/test.txt"),
+        ],
+        b"a comment",
+    );
+    let archive = Archive::new(&data).unwrap();
+    assert_eq!(
+        archive.names().collect::<Vec<_>>(),
+        ["roms/", "roms/test.sfc", "roms/test.txt"]
+    );
+    assert_eq!(archive.read("roms/test.sfc").unwrap(), b"rsnes");
+    assert_eq!(archive.read("roms/test.txt").unwrap(), b"abcabcabcabc");
+    assert!(matches!(
+        archive.read("test.sfc"),
+        Err(ZipError::Missing(name)) if name == "test.sfc"
+    ));
+}
+
+#[test]
+pub fn test_unsupported_entries() {
+    let mut encrypted = TestEntry::stored("encrypted", b"rsnes");
+    encrypted.flags = 1;
+    let mut zip64 = TestEntry::stored("zip64", b"rsnes");
+    zip64.size = u32::MAX;
+    let mut bzip2 = TestEntry::stored("bzip2", b"rsnes");
+    bzip2.method = 12;
+    let data = create_archive(&[encrypted, zip64, bzip2], b"");
+    let archive = Archive::new(&data).unwrap();
+    assert_eq!(
+        format_error(archive.read("encrypted")),
+        Some("encrypted entry")
+    );
+    assert_eq!(format_error(archive.read("zip64")), Some("ZIP64 entry"));
+    assert_eq!(
+        format_error(archive.read("bzip2")),
+        Some("unsupported compression method")
+    );
+
+    // the end record of a ZIP64 archive points to its own record
+    let mut data = create_archive(&[TestEntry::stored("test.sfc", b"rsnes")], b"");
+    let end = data.len() - 22;
+    data[end + 16..end + 20].fill(0xff);
+    assert_eq!(format_error(Archive::new(&data)), Some("ZIP64 archive"));
+}
+
+#[test]
+pub fn test_corrupt_entries() {
+    let mut wrong_crc = TestEntry::stored("crc", b"rsnes");
+    wrong_crc.crc ^= 1;
+    let mut wrong_size = TestEntry::deflated("size");
+    wrong_size.size = 11;
+    let mut bad_stream = TestEntry::deflated("stream");
+    bad_stream.compressed = &DEFLATED[..4];
+    let data = create_archive(&[wrong_crc, wrong_size, bad_stream], b"");
+    let archive = Archive::new(&data).unwrap();
+    assert_eq!(format_error(archive.read("crc")), Some("checksum mismatch"));
+    assert_eq!(
+        format_error(archive.read("size")),
+        Some("decompressed data too large")
+    );
+    assert_eq!(format_error(archive.read("stream")), Some("truncated data"));
+}
+
+#[test]
+pub fn test_truncated_archive() {
+    let data = create_archive(&[TestEntry::deflated("test.txt")], b"");
+    for len in 0..data.len() - 1 {
+        assert!(format_error(Archive::new(&data[..len])).is_some());
+    }
+    // the central directory is intact, the content is missing
+    let mut data = data;
+    let directory = 30 + "test.txt".len() + DEFLATED.len();
+    data[directory + 20..directory + 24].copy_from_slice(&0x1000u32.to_le_bytes());
+    let archive = Archive::new(&data).unwrap();
+    assert_eq!(
+        format_error(archive.read("test.txt")),
+        Some("truncated archive")
+    );
+}
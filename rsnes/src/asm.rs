@@ -0,0 +1,401 @@
+//! A tiny 65816 assembler
+//!
+//! Assembles one instruction at a time without any support for labels or directives.
+//! The accepted syntax is the usual WDC syntax, e.g. `lda ($12,x)`, `mvn $7e,$7f`
+//! or `jml [$fffc]`. Numbers are hexadecimal when prefixed with `$` or `0x`,
+//! binary when prefixed with `%` and decimal otherwise.
+//! The operand width can be forced with a `.b`, `.w` or `.l` suffix on the mnemonic.
+
+use crate::device::Addr24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddrMode {
+    /// no operand
+    Implied,
+    /// `A`
+    Accumulator,
+    /// `#const` whose size depends on the M flag
+    ImmediateM,
+    /// `#const` whose size depends on the X flag
+    ImmediateX,
+    /// `#const` that is always 8 bits wide
+    Immediate8,
+    /// `dp`
+    Direct,
+    /// `dp,X`
+    DirectX,
+    /// `dp,Y`
+    DirectY,
+    /// `(dp)`
+    DirectIndirect,
+    /// `[dp]`
+    DirectIndirectLong,
+    /// `(dp,X)`
+    DirectXIndirect,
+    /// `(dp),Y`
+    DirectIndirectY,
+    /// `[dp],Y`
+    DirectIndirectLongY,
+    /// `addr`
+    Absolute,
+    /// `addr,X`
+    AbsoluteX,
+    /// `addr,Y`
+    AbsoluteY,
+    /// `long`
+    Long,
+    /// `long,X`
+    LongX,
+    /// `(addr)`
+    AbsoluteIndirect,
+    /// `[addr]`
+    AbsoluteIndirectLong,
+    /// `(addr,X)`
+    AbsoluteXIndirect,
+    /// `sr,S`
+    StackRelative,
+    /// `(sr,S),Y`
+    StackRelativeIndirectY,
+    /// 8-bit relative branch target
+    Relative8,
+    /// 16-bit relative branch target
+    Relative16,
+    /// `srcbank,dstbank`
+    BlockMove,
+}
+
+impl AddrMode {
+    /// Get the number of operand bytes following the opcode
+    pub const fn operand_size(self, m8: bool, x8: bool) -> usize {
+        use AddrMode::*;
+        match self {
+            Implied | Accumulator => 0,
+            ImmediateM => 2 - m8 as usize,
+            ImmediateX => 2 - x8 as usize,
+            Immediate8
+            | Direct
+            | DirectX
+            | DirectY
+            | DirectIndirect
+            | DirectIndirectLong
+            | DirectXIndirect
+            | DirectIndirectY
+            | DirectIndirectLongY
+            | StackRelative
+            | StackRelativeIndirectY
+            | Relative8 => 1,
+            Absolute | AbsoluteX | AbsoluteY | AbsoluteIndirect | AbsoluteIndirectLong
+            | AbsoluteXIndirect | Relative16 | BlockMove => 2,
+            Long | LongX => 3,
+        }
+    }
+}
+
+macro_rules! opcodes {
+    ($($mnemonic:ident $mode:ident),* $(,)?) => {
+        [$((stringify!($mnemonic), AddrMode::$mode)),*]
+    };
+}
+
+/// The mnemonic and addressing mode for every opcode
+pub static OPCODES: [(&str, AddrMode); 256] = opcodes![
+    // 0x00
+    BRK Immediate8, ORA DirectXIndirect, COP Immediate8, ORA StackRelative,
+    TSB Direct, ORA Direct, ASL Direct, ORA DirectIndirectLong,
+    PHP Implied, ORA ImmediateM, ASL Accumulator, PHD Implied,
+    TSB Absolute, ORA Absolute, ASL Absolute, ORA Long,
+    // 0x10
+    BPL Relative8, ORA DirectIndirectY, ORA DirectIndirect, ORA StackRelativeIndirectY,
+    TRB Direct, ORA DirectX, ASL DirectX, ORA DirectIndirectLongY,
+    CLC Implied, ORA AbsoluteY, INC Accumulator, TCS Implied,
+    TRB Absolute, ORA AbsoluteX, ASL AbsoluteX, ORA LongX,
+    // 0x20
+    JSR Absolute, AND DirectXIndirect, JSL Long, AND StackRelative,
+    BIT Direct, AND Direct, ROL Direct, AND DirectIndirectLong,
+    PLP Implied, AND ImmediateM, ROL Accumulator, PLD Implied,
+    BIT Absolute, AND Absolute, ROL Absolute, AND Long,
+    // 0x30
+    BMI Relative8, AND DirectIndirectY, AND DirectIndirect, AND StackRelativeIndirectY,
+    BIT DirectX, AND DirectX, ROL DirectX, AND DirectIndirectLongY,
+    SEC Implied, AND AbsoluteY, DEC Accumulator, TSC Implied,
+    BIT AbsoluteX, AND AbsoluteX, ROL AbsoluteX, AND LongX,
+    // 0x40
+    RTI Implied, EOR DirectXIndirect, WDM Immediate8, EOR StackRelative,
+    MVP BlockMove, EOR Direct, LSR Direct, EOR DirectIndirectLong,
+    PHA Implied, EOR ImmediateM, LSR Accumulator, PHK Implied,
+    JMP Absolute, EOR Absolute, LSR Absolute, EOR Long,
+    // 0x50
+    BVC Relative8, EOR DirectIndirectY, EOR DirectIndirect, EOR StackRelativeIndirectY,
+    MVN BlockMove, EOR DirectX, LSR DirectX, EOR DirectIndirectLongY,
+    CLI Implied, EOR AbsoluteY, PHY Implied, TCD Implied,
+    JML Long, EOR AbsoluteX, LSR AbsoluteX, EOR LongX,
+    // 0x60
+    RTS Implied, ADC DirectXIndirect, PER Relative16, ADC StackRelative,
+    STZ Direct, ADC Direct, ROR Direct, ADC DirectIndirectLong,
+    PLA Implied, ADC ImmediateM, ROR Accumulator, RTL Implied,
+    JMP AbsoluteIndirect, ADC Absolute, ROR Absolute, ADC Long,
+    // 0x70
+    BVS Relative8, ADC DirectIndirectY, ADC DirectIndirect, ADC StackRelativeIndirectY,
+    STZ DirectX, ADC DirectX, ROR DirectX, ADC DirectIndirectLongY,
+    SEI Implied, ADC AbsoluteY, PLY Implied, TDC Implied,
+    JMP AbsoluteXIndirect, ADC AbsoluteX, ROR AbsoluteX, ADC LongX,
+    // 0x80
+    BRA Relative8, STA DirectXIndirect, BRL Relative16, STA StackRelative,
+    STY Direct, STA Direct, STX Direct, STA DirectIndirectLong,
+    DEY Implied, BIT ImmediateM, TXA Implied, PHB Implied,
+    STY Absolute, STA Absolute, STX Absolute, STA Long,
+    // 0x90
+    BCC Relative8, STA DirectIndirectY, STA DirectIndirect, STA StackRelativeIndirectY,
+    STY DirectX, STA DirectX, STX DirectY, STA DirectIndirectLongY,
+    TYA Implied, STA AbsoluteY, TXS Implied, TXY Implied,
+    STZ Absolute, STA AbsoluteX, STZ AbsoluteX, STA LongX,
+    // 0xa0
+    LDY ImmediateX, LDA DirectXIndirect, LDX ImmediateX, LDA StackRelative,
+    LDY Direct, LDA Direct, LDX Direct, LDA DirectIndirectLong,
+    TAY Implied, LDA ImmediateM, TAX Implied, PLB Implied,
+    LDY Absolute, LDA Absolute, LDX Absolute, LDA Long,
+    // 0xb0
+    BCS Relative8, LDA DirectIndirectY, LDA DirectIndirect, LDA StackRelativeIndirectY,
+    LDY DirectX, LDA DirectX, LDX DirectY, LDA DirectIndirectLongY,
+    CLV Implied, LDA AbsoluteY, TSX Implied, TYX Implied,
+    LDY AbsoluteX, LDA AbsoluteX, LDX AbsoluteY, LDA LongX,
+    // 0xc0
+    CPY ImmediateX, CMP DirectXIndirect, REP Immediate8, CMP StackRelative,
+    CPY Direct, CMP Direct, DEC Direct, CMP DirectIndirectLong,
+    INY Implied, CMP ImmediateM, DEX Implied, WAI Implied,
+    CPY Absolute, CMP Absolute, DEC Absolute, CMP Long,
+    // 0xd0
+    BNE Relative8, CMP DirectIndirectY, CMP DirectIndirect, CMP StackRelativeIndirectY,
+    PEI DirectIndirect, CMP DirectX, DEC DirectX, CMP DirectIndirectLongY,
+    CLD Implied, CMP AbsoluteY, PHX Implied, STP Implied,
+    JML AbsoluteIndirectLong, CMP AbsoluteX, DEC AbsoluteX, CMP LongX,
+    // 0xe0
+    CPX ImmediateX, SBC DirectXIndirect, SEP Immediate8, SBC StackRelative,
+    CPX Direct, SBC Direct, INC Direct, SBC DirectIndirectLong,
+    INX Implied, SBC ImmediateM, NOP Implied, XBA Implied,
+    CPX Absolute, SBC Absolute, INC Absolute, SBC Long,
+    // 0xf0
+    BEQ Relative8, SBC DirectIndirectY, SBC DirectIndirect, SBC StackRelativeIndirectY,
+    PEA Absolute, SBC DirectX, INC DirectX, SBC DirectIndirectLongY,
+    SED Implied, SBC AbsoluteY, PLX Implied, XCE Implied,
+    JSR AbsoluteXIndirect, SBC AbsoluteX, INC AbsoluteX, SBC LongX,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    Empty,
+    UnknownMnemonic(String),
+    InvalidOperand(String),
+    InvalidWidth(String),
+    UnsupportedMode { mnemonic: String, operand: String },
+    ValueTooLarge(u32),
+    BranchOutOfRange(i32),
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "no instruction given"),
+            Self::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic `{}`", mnemonic),
+            Self::InvalidOperand(operand) => write!(f, "invalid operand `{}`", operand),
+            Self::InvalidWidth(suffix) => write!(f, "invalid width suffix `.{}`", suffix),
+            Self::UnsupportedMode { mnemonic, operand } => write!(
+                f,
+                "`{}` does not support an operand like `{}`",
+                mnemonic, operand
+            ),
+            Self::ValueTooLarge(val) => write!(f, "value ${:x} is too large", val),
+            Self::BranchOutOfRange(distance) => {
+                write!(f, "branch distance {} is out of range", distance)
+            }
+        }
+    }
+}
+
+/// Find the opcode for a mnemonic/addressing mode combination
+pub fn find_opcode(mnemonic: &str, mode: AddrMode) -> Option<u8> {
+    OPCODES
+        .iter()
+        .position(|&(m, md)| md == mode && m.eq_ignore_ascii_case(mnemonic))
+        .map(|op| op as u8)
+}
+
+/// Parse a number and return it together with the minimal count of bytes
+/// implied by the way it was written (e.g. `$0012` implies two bytes)
+fn parse_value(text: &str) -> Option<(u32, usize)> {
+    let (digits, radix, digits_per_byte) =
+        if let Some(hex) = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+            (hex, 16, 2)
+        } else if let Some(bin) = text.strip_prefix('%') {
+            (bin, 2, 8)
+        } else {
+            (text, 10, 0)
+        };
+    if digits.is_empty() {
+        return None;
+    }
+    let value = u32::from_str_radix(digits, radix).ok()?;
+    let value_size = match value {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x10000..=0xffffff => 3,
+        _ => return None,
+    };
+    let written_size = if digits_per_byte > 0 {
+        digits.len().div_ceil(digits_per_byte)
+    } else {
+        1
+    };
+    Some((value, value_size.max(written_size.min(3))))
+}
+
+fn strip_suffix_ci<'a>(text: &'a str, suffix: &str) -> Option<&'a str> {
+    let split = text.len().checked_sub(suffix.len())?;
+    if text.is_char_boundary(split) && text[split..].eq_ignore_ascii_case(suffix) {
+        Some(&text[..split])
+    } else {
+        None
+    }
+}
+
+/// Assemble a single instruction located at `pc`.
+///
+/// `m8` and `x8` are the states of the M and X status flags,
+/// which determine the size of immediate operands.
+pub fn assemble(line: &str, pc: Addr24, m8: bool, x8: bool) -> Result<Vec<u8>, AsmError> {
+    use AddrMode::*;
+    let line = line.trim();
+    let (mnemonic, operand) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if mnemonic.is_empty() {
+        return Err(AsmError::Empty);
+    }
+    let operand: String = operand.chars().filter(|c| !c.is_whitespace()).collect();
+    let (mnemonic, width) = match mnemonic.split_once('.') {
+        Some((mnemonic, suffix)) => (
+            mnemonic,
+            Some(match suffix.to_ascii_lowercase().as_str() {
+                "b" => 1,
+                "w" => 2,
+                "l" => 3,
+                _ => return Err(AsmError::InvalidWidth(suffix.to_owned())),
+            }),
+        ),
+        None => (mnemonic, None),
+    };
+    if !OPCODES
+        .iter()
+        .any(|(m, _)| m.eq_ignore_ascii_case(mnemonic))
+    {
+        return Err(AsmError::UnknownMnemonic(mnemonic.to_owned()));
+    }
+    let invalid = || AsmError::InvalidOperand(operand.clone());
+    let value = |text: &str| parse_value(text).ok_or_else(invalid);
+
+    let (candidates, (val, size)): (&[AddrMode], _) =
+        if operand.is_empty() || operand.eq_ignore_ascii_case("a") {
+            if operand.is_empty() && find_opcode(mnemonic, Immediate8).is_some() {
+                // BRK, COP and WDM without a signature byte
+                (&[Immediate8], (0, 1))
+            } else {
+                (&[Implied, Accumulator], (0, 0))
+            }
+        } else if let Some(imm) = operand.strip_prefix('#') {
+            (&[ImmediateM, ImmediateX, Immediate8], value(imm)?)
+        } else if find_opcode(mnemonic, BlockMove).is_some() {
+            let (src, dst) = operand.split_once(',').ok_or_else(invalid)?;
+            let ((src, src_size), (dst, dst_size)) = (value(src)?, value(dst)?);
+            if src_size > 1 || dst_size > 1 {
+                return Err(invalid());
+            }
+            (&[BlockMove], (src << 8 | dst, 2))
+        } else {
+            let lower = operand.to_ascii_lowercase();
+            if let Some(inner) = lower.strip_prefix('(') {
+                if let Some(inner) = strip_suffix_ci(inner, ",s),y") {
+                    (&[StackRelativeIndirectY], value(inner)?)
+                } else if let Some(inner) = strip_suffix_ci(inner, "),y") {
+                    (&[DirectIndirectY], value(inner)?)
+                } else if let Some(inner) = strip_suffix_ci(inner, ",x)") {
+                    (&[DirectXIndirect, AbsoluteXIndirect], value(inner)?)
+                } else if let Some(inner) = inner.strip_suffix(')') {
+                    (&[DirectIndirect, AbsoluteIndirect], value(inner)?)
+                } else {
+                    return Err(invalid());
+                }
+            } else if let Some(inner) = lower.strip_prefix('[') {
+                if let Some(inner) = strip_suffix_ci(inner, "],y") {
+                    (&[DirectIndirectLongY], value(inner)?)
+                } else if let Some(inner) = inner.strip_suffix(']') {
+                    (&[DirectIndirectLong, AbsoluteIndirectLong], value(inner)?)
+                } else {
+                    return Err(invalid());
+                }
+            } else if let Some(inner) = strip_suffix_ci(&lower, ",s") {
+                (&[StackRelative], value(inner)?)
+            } else if let Some(inner) = strip_suffix_ci(&lower, ",x") {
+                (&[DirectX, AbsoluteX, LongX], value(inner)?)
+            } else if let Some(inner) = strip_suffix_ci(&lower, ",y") {
+                (&[DirectY, AbsoluteY], value(inner)?)
+            } else {
+                (
+                    &[Relative8, Relative16, Direct, Absolute, Long],
+                    value(&lower)?,
+                )
+            }
+        };
+
+    // `jmp`/`jsr` with a long operand are aliases for `jml`/`jsl`
+    let aliases: &[&str] = match mnemonic.to_ascii_lowercase().as_str() {
+        "jmp" => &["jmp", "jml"],
+        "jsr" => &["jsr", "jsl"],
+        _ => &[mnemonic],
+    };
+    for &mode in candidates {
+        let op = match aliases.iter().find_map(|m| find_opcode(m, mode)) {
+            Some(op) => op,
+            None => continue,
+        };
+        let mut bytes = vec![op];
+        match mode {
+            Relative8 | Relative16 => {
+                let operand_size = mode.operand_size(m8, x8);
+                let next = i32::from(pc.addr) + 1 + operand_size as i32;
+                let distance = (val as u16 as i32) - next;
+                // branches wrap around inside the bank of the program counter
+                let distance = (distance + 0x8000).rem_euclid(0x10000) - 0x8000;
+                if operand_size == 1 && !(-0x80..0x80).contains(&distance) {
+                    return Err(AsmError::BranchOutOfRange(distance));
+                }
+                bytes.extend_from_slice(&distance.to_le_bytes()[..operand_size]);
+            }
+            BlockMove => bytes.extend_from_slice(&[val as u8, (val >> 8) as u8]),
+            _ => {
+                let operand_size = match (mode, width) {
+                    (ImmediateM | ImmediateX, Some(width)) if width < 3 => width,
+                    _ => mode.operand_size(m8, x8),
+                };
+                if let Some(width) = width {
+                    if width != operand_size && operand_size > 0 {
+                        continue;
+                    }
+                }
+                if matches!(mode, ImmediateM | ImmediateX | Immediate8) {
+                    if u64::from(val) >> (8 * operand_size) != 0 {
+                        return Err(AsmError::ValueTooLarge(val));
+                    }
+                } else if operand_size < size {
+                    continue;
+                }
+                bytes.extend_from_slice(&val.to_le_bytes()[..operand_size]);
+            }
+        }
+        return Ok(bytes);
+    }
+    Err(AsmError::UnsupportedMode {
+        mnemonic: mnemonic.to_owned(),
+        operand,
+    })
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,87 @@
+use super::*;
+
+const PC: Addr24 = Addr24::new(0x80, 0x8000);
+
+/// Lines with their encoding, as assembled with the M and X flags
+static ENCODINGS: &[(&str, bool, bool, &[u8])] = &[
+    ("  nop  ", true, true, &[0xea]),
+    ("inc a", true, true, &[0x1a]),
+    ("inc", true, true, &[0x1a]),
+    ("brk", true, true, &[0x00, 0x00]),
+    ("LDA #$12", true, true, &[0xa9, 0x12]),
+    ("lda #$1234", false, true, &[0xa9, 0x34, 0x12]),
+    ("lda.w #$12", true, true, &[0xa9, 0x12, 0x00]),
+    ("ldx #$1234", true, false, &[0xa2, 0x34, 0x12]),
+    ("lda $12", true, true, &[0xa5, 0x12]),
+    ("lda $0012", true, true, &[0xad, 0x12, 0x00]),
+    ("lda 0x0012", true, true, &[0xad, 0x12, 0x00]),
+    ("lda.w $12", true, true, &[0xad, 0x12, 0x00]),
+    ("lda.l $12", true, true, &[0xaf, 0x12, 0x00, 0x00]),
+    ("lda %1010", true, true, &[0xa5, 0x0a]),
+    ("lda 300", true, true, &[0xad, 0x2c, 0x01]),
+    ("Lda ( $10 ),Y", true, true, &[0xb1, 0x10]),
+    ("lda $123456,x", true, true, &[0xbf, 0x56, 0x34, 0x12]),
+    ("jmp $123456", true, true, &[0x5c, 0x56, 0x34, 0x12]),
+    ("jsr $123456", true, true, &[0x22, 0x56, 0x34, 0x12]),
+    ("jsr $1234", true, true, &[0x20, 0x34, 0x12]),
+    ("mvn $7e,$7f", true, true, &[0x54, 0x7f, 0x7e]),
+    ("bra $7f82", true, true, &[0x80, 0x80]),
+    ("bra $8081", true, true, &[0x80, 0x7f]),
+];
+
+/// Lines, which can't be assembled with both flags set
+fn errors() -> Vec<(&'static str, AsmError)> {
+    let unsupported = |mnemonic: &str, operand: &str| AsmError::UnsupportedMode {
+        mnemonic: mnemonic.to_owned(),
+        operand: operand.to_owned(),
+    };
+    let invalid = |operand: &str| AsmError::InvalidOperand(operand.to_owned());
+    vec![
+        ("", AsmError::Empty),
+        ("   ", AsmError::Empty),
+        ("foo $12", AsmError::UnknownMnemonic("foo".to_owned())),
+        ("lda.q $12", AsmError::InvalidWidth("q".to_owned())),
+        ("lda $12,z", invalid("$12,z")),
+        ("lda ($12", invalid("($12")),
+        ("lda $", invalid("$")),
+        ("lda $1000000", invalid("$1000000")),
+        ("mvn $7e", invalid("$7e")),
+        ("mvn $1234,$7f", invalid("$1234,$7f")),
+        ("nop #$12", unsupported("nop", "#$12")),
+        ("lda ($1234),y", unsupported("lda", "($1234),y")),
+        ("lda.b $1234", unsupported("lda", "$1234")),
+        ("lda #$123", AsmError::ValueTooLarge(0x123)),
+        ("bra $8100", AsmError::BranchOutOfRange(0xfe)),
+        ("bra $7f81", AsmError::BranchOutOfRange(-0x81)),
+    ]
+}
+
+#[test]
+fn test_encodings() {
+    for &(line, m8, x8, bytes) in ENCODINGS {
+        assert_eq!(assemble(line, PC, m8, x8).as_deref(), Ok(bytes), "`{line}`");
+    }
+}
+
+#[test]
+fn test_errors() {
+    for (line, err) in errors() {
+        assert_eq!(assemble(line, PC, true, true), Err(err), "`{line}`");
+    }
+}
+
+#[test]
+fn test_branch_wrap() {
+    // branches stay in the bank of the program counter
+    let end = Addr24::new(0x80, 0xfffe);
+    assert_eq!(assemble("bra $0000", end, true, true), Ok(vec![0x80, 0x00]));
+    assert_eq!(
+        assemble("brl $8000", end, true, true),
+        Ok(vec![0x82, 0xff, 0x7f])
+    );
+    let start = Addr24::new(0x80, 0x0002);
+    assert_eq!(
+        assemble("bne $fff0", start, true, true),
+        Ok(vec![0xd0, 0xec])
+    );
+}
@@ -3,6 +3,11 @@ mod audio {
 
     pub trait AudioBackend: Send + 'static {
         fn push_sample(&mut self, sample: StereoSample);
+
+        /// The outputs of the eight DSP voices (see
+        /// [`Spc700::voice_outputs`](crate::spc700::Spc700::voice_outputs)),
+        /// pushed after every sample
+        fn push_voice_outputs(&mut self, _outputs: [i16; 8]) {}
     }
     pub struct Dummy;
 
@@ -0,0 +1,159 @@
+//! Logging of bus accesses
+//!
+//! While a [`BusLog`] is attached to [`Device::bus_log`](crate::device::Device::bus_log),
+//! every byte read or written on the A and B bus by the main CPU or by DMA
+//! is checked against its filters. Matching accesses are kept in a ring buffer,
+//! so only the most recent ones are stored, until they are dumped with
+//! [`BusLog::take`].
+
+use crate::device::Addr24;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// The component, which caused a bus access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    Cpu,
+    /// DMA and HDMA transfers and HDMA table reads
+    Dma,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub addr: Addr24,
+    pub value: u8,
+    pub kind: AccessKind,
+    pub origin: Origin,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (kind, origin) = match (self.kind, self.origin) {
+            (AccessKind::Read, Origin::Cpu) => ("R", "cpu"),
+            (AccessKind::Write, Origin::Cpu) => ("W", "cpu"),
+            (AccessKind::Read, Origin::Dma) => ("R", "dma"),
+            (AccessKind::Write, Origin::Dma) => ("W", "dma"),
+        };
+        write!(f, "{origin} {kind} {} {:02x}", self.addr, self.value)
+    }
+}
+
+/// Matches accesses to an inclusive range of addresses.
+/// `None` matches any kind or origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    pub start: Addr24,
+    pub end: Addr24,
+    pub kind: Option<AccessKind>,
+    pub origin: Option<Origin>,
+}
+
+impl Filter {
+    /// Match all accesses to the addresses `start..=end`
+    pub fn range(start: Addr24, end: Addr24) -> Self {
+        Self {
+            start,
+            end,
+            kind: None,
+            origin: None,
+        }
+    }
+
+    pub fn matches(&self, access: &Access) -> bool {
+        (self.start..=self.end).contains(&access.addr)
+            && self.kind.is_none_or(|kind| kind == access.kind)
+            && self.origin.is_none_or(|origin| origin == access.origin)
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)?;
+        match self.kind {
+            Some(AccessKind::Read) => write!(f, " r")?,
+            Some(AccessKind::Write) => write!(f, " w")?,
+            None => (),
+        }
+        match self.origin {
+            Some(Origin::Cpu) => write!(f, " cpu"),
+            Some(Origin::Dma) => write!(f, " dma"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BusLog {
+    /// If not empty, only accesses matching one of these filters are logged
+    pub include: Vec<Filter>,
+    /// Accesses matching one of these filters are never logged
+    pub exclude: Vec<Filter>,
+    entries: VecDeque<Access>,
+    capacity: usize,
+    /// The count of logged accesses, which were dropped from the buffer
+    dropped: u64,
+}
+
+impl BusLog {
+    /// Create a log, which keeps the last `capacity` accesses
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The count of accesses, which didn't fit into the buffer since the last
+    /// call to [`BusLog::take`]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn is_logged(&self, access: &Access) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|f| f.matches(access)))
+            && !self.exclude.iter().any(|f| f.matches(access))
+    }
+
+    pub fn record(&mut self, access: Access) {
+        if !self.is_logged(&access) {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(access)
+    }
+
+    /// The logged accesses, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &Access> {
+        self.entries.iter()
+    }
+
+    /// Remove and return all logged accesses, oldest first
+    pub fn take(&mut self) -> Vec<Access> {
+        self.dropped = 0;
+        self.entries.drain(..).collect()
+    }
+}
@@ -5,10 +5,15 @@
 //! - the [super famicom wiki page](https://wiki.superfamicom.org/memory-mapping)
 //! - <http://patrickjohnston.org/ASM/ROM data/snestek.htm>
 
+use std::borrow::Cow;
 use std::convert::TryInto;
+use std::ops::Range;
+
+pub mod compat;
+pub mod flash;
 
 use crate::{
-    device::{Addr24, Data},
+    device::{Addr24, Data, MemoryRegion},
     enhancement::{sa1::Sa1, Dsp, DspVersion},
     timing::Cycles,
 };
@@ -16,11 +21,89 @@ use save_state::{SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::*;
 
 const MINIMUM_SIZE: usize = 0x8000;
+/// The largest ROM, which fits into the address space of the SNES
+pub(crate) const MAXIMUM_SIZE: usize = 0x1000000;
+
+/// The largest SRAM, which can be addressed by the LoROM mapping
+const MAX_SRAM_SIZE: u32 = 0x80000;
+/// The largest SRAM, which can be addressed by the HiROM mapping: 32 banks
+/// of 8 KiB at `$20-$3f:$6000-$7fff`
+const MAX_HIROM_SRAM_SIZE: u32 = 0x40000;
+/// The size, by which detected SRAM grows at least
+const MIN_DETECTED_SRAM_SIZE: usize = 0x800;
+
+/// Changes of the save memory are tracked in pages of this size
+pub const SRAM_PAGE_SIZE: usize = 0x400;
+
+/// The size of the title in the header in bytes
+pub const TITLE_SIZE: usize = 21;
 
 fn split_byte(byte: u8) -> (u8, u8) {
     (byte >> 4, byte & 15)
 }
 
+/// Decode a title of the header, which is encoded in Shift-JIS.
+///
+/// Titles of Japanese games mostly use the half-width katakana of JIS X 0201.
+/// Of the double-byte characters, only the kana, the full-width letters and
+/// digits and a few marks are decoded, because every other one needs the
+/// table of JIS X 0208. They are replaced by U+FFFD, like invalid bytes.
+/// Control characters are left out, as well as the spaces around the title.
+pub fn decode_title(bytes: &[u8]) -> String {
+    let mut title = String::new();
+    let mut bytes = bytes.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        let c = match byte {
+            // the yen sign is in place of the backslash in JIS X 0201
+            b'\\' => '¥',
+            b' '..=b'~' => byte.into(),
+            0x00..=0x1f | 0x7f => continue,
+            // half-width katakana
+            0xa1..=0xdf => char::from_u32(0xff61 + u32::from(byte - 0xa1)).unwrap(),
+            0x81..=0x9f | 0xe0..=0xfc => {
+                match bytes.next_if(|b| matches!(b, 0x40..=0x7e | 0x80..=0xfc)) {
+                    Some(trail) => {
+                        decode_double_byte(byte, trail).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    }
+                    None => char::REPLACEMENT_CHARACTER,
+                }
+            }
+            _ => char::REPLACEMENT_CHARACTER,
+        };
+        title.push(c)
+    }
+    title.trim_matches(' ').to_string()
+}
+
+/// Decode the double-byte Shift-JIS characters, which don't need a table
+fn decode_double_byte(lead: u8, trail: u8) -> Option<char> {
+    let offset = |start: u8, first: u32| first + u32::from(trail - start);
+    let code = match (lead, trail) {
+        (0x81, 0x40) => 0x3000,
+        (0x81, 0x45) => 0x30fb,
+        (0x81, 0x5b) => 0x30fc,
+        // full-width digits and letters
+        (0x82, 0x4f..=0x58) => offset(0x4f, 0xff10),
+        (0x82, 0x60..=0x79) => offset(0x60, 0xff21),
+        (0x82, 0x81..=0x9a) => offset(0x81, 0xff41),
+        (0x82, 0x9f..=0xf1) => offset(0x9f, 0x3041),
+        // the trail bytes of the katakana skip 0x7f
+        (0x83, 0x40..=0x7e) => offset(0x40, 0x30a1),
+        (0x83, 0x80..=0x96) => offset(0x80, 0x30e0),
+        _ => return None,
+    };
+    char::from_u32(code)
+}
+
+/// The kind of memory, in which a cartridge keeps its save data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveMemory {
+    /// Battery backed SRAM
+    Sram,
+    /// A flash chip (see [`flash`])
+    Flash,
+}
+
 #[derive(Debug)]
 pub enum ReadRomError {
     TooSmall(usize),
@@ -66,6 +149,14 @@ impl RomType {
             _ => return None,
         })
     }
+
+    /// The largest SRAM, which can be addressed by the mapping
+    const fn max_sram_size(self) -> u32 {
+        match self {
+            Self::HiRom => MAX_HIROM_SRAM_SIZE,
+            _ => MAX_SRAM_SIZE,
+        }
+    }
 }
 
 impl save_state::InSaveState for RomType {
@@ -195,6 +286,9 @@ impl Default for Coprocessor {
 #[derive(Debug, Default, Clone, InSaveState)]
 pub struct Header {
     name: String,
+    /// The title as stored in the header, padded with spaces
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    raw_title: [u8; TITLE_SIZE],
     speed: u8,
     rom_type: RomType,
     extended: OptExtendedHeader,
@@ -218,17 +312,10 @@ impl Header {
 
         let bytes = &full_bytes[16..];
         let mut score = 0;
-        let mut name = bytes[..21]
-            .iter()
-            .filter(|c| (b' '..=b'~').contains(c))
-            .skip_while(|&&c| c == b' ')
-            .map(|&c| if c == b'\\' { '¥' } else { c.into() })
-            .collect::<String>()
-            .to_string();
-        while name.ends_with(' ') {
-            name.truncate(name.len() - 1)
-        }
-        score += name.len() as u16 * VALID_CHAR;
+        let raw_title: [u8; TITLE_SIZE] = bytes[..TITLE_SIZE].try_into().unwrap();
+        let name = decode_title(&raw_title);
+        let valid_chars = name.chars().filter(|&c| c != char::REPLACEMENT_CHARACTER);
+        score += valid_chars.count() as u16 * VALID_CHAR;
         let (speed, rom_type) = split_byte(bytes[21]);
         if speed & !1 == 1 {
             score += VALID_SPEED_INDICATION
@@ -237,7 +324,11 @@ impl Header {
         let rom_type = RomType::from_byte(rom_type)?;
         let (coprocessor, chips) = split_byte(bytes[22]);
         let rom_size = 0x400u32.wrapping_shl(bytes[23].into());
-        let ram_size = 0x400u32.wrapping_shl(bytes[24].into());
+        // sizes of 4 GiB and more don't fit, but are implausible anyway
+        let ram_size = 0x400u32
+            .checked_shl(bytes[24].into())
+            .filter(|&size| size >= 0x400)
+            .unwrap_or(u32::MAX);
         let ram_size = if ram_size == 0x400 { 0 } else { ram_size };
         let country = bytes[25];
         if country <= 20 {
@@ -296,6 +387,7 @@ impl Header {
         Some((
             Self {
                 name,
+                raw_title,
                 speed,
                 rom_type,
                 extended,
@@ -535,6 +627,62 @@ fn copy_rom(dst: &mut [u8], src: &[u8]) {
     }
 }
 
+/// The offsets of all copies of the byte `offset` of `src` made by
+/// [`copy_rom`] into a ROM of the size `dst_len`
+fn rom_copy_offsets(
+    offset: usize,
+    dst_len: usize,
+    src_len: usize,
+    base: usize,
+    out: &mut Vec<usize>,
+) {
+    if dst_len <= src_len {
+        if offset < dst_len {
+            out.push(base + offset)
+        }
+    } else if src_len.is_power_of_two() {
+        out.extend(
+            (offset..dst_len)
+                .step_by(src_len)
+                .map(|offset| base + offset),
+        )
+    } else {
+        let left_part = src_len.next_power_of_two() >> 1;
+        if offset < left_part {
+            out.push(base + offset);
+            return;
+        }
+        let src_rest = src_len - left_part;
+        let right_part = src_rest.next_power_of_two();
+        let count = (dst_len - left_part) / right_part;
+        for i in 0..count {
+            let base = base + left_part + i * right_part;
+            rom_copy_offsets(offset - left_part, right_part, src_rest, base, out)
+        }
+    }
+}
+
+/// The offset in `src` of the byte `offset` of a ROM of the size `dst_len`
+/// filled by [`copy_rom`], `None` if it's padding
+fn rom_source_offset(offset: usize, dst_len: usize, src_len: usize) -> Option<usize> {
+    if dst_len <= src_len || src_len.is_power_of_two() {
+        Some(offset % src_len)
+    } else {
+        let left_part = src_len.next_power_of_two() >> 1;
+        if offset < left_part {
+            return Some(offset);
+        }
+        let src_rest = src_len - left_part;
+        let right_part = src_rest.next_power_of_two();
+        let count = (dst_len - left_part) / right_part;
+        let offset = offset - left_part;
+        if offset >= count * right_part {
+            return None;
+        }
+        rom_source_offset(offset % right_part, right_part, src_rest).map(|o| o + left_part)
+    }
+}
+
 fn create_rom(content: &[u8], size: u32) -> Vec<u8> {
     let size = size as usize;
     let mut rom = if content.len() > size {
@@ -546,6 +694,160 @@ fn create_rom(content: &[u8], size: u32) -> Vec<u8> {
     rom
 }
 
+/// The layouts of interleaved HiROM files dumped by some copiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interleave {
+    /// The first half of the file contains the upper 32 KiB of every
+    /// 64 KiB bank, the second half the lower 32 KiB. This layout is used
+    /// by the Super Wild Card, the Super UFO and the Game Doctor.
+    Halves,
+    /// 24 Mbit files of the Game Doctor: like [`Self::Halves`], but the
+    /// second half holds the lower halves of the banks `$20-$2f` before
+    /// the ones of the banks `$00-$1f`
+    GameDoctor24,
+}
+
+impl Interleave {
+    /// The size of a file with the layout [`Self::GameDoctor24`]
+    const GAME_DOCTOR_24_SIZE: usize = 0x300000;
+
+    /// The offset in the file of the byte `offset` of a ROM of `size` bytes
+    fn file_offset(self, offset: usize, size: usize) -> usize {
+        let (bank, addr) = (offset >> 16, offset & 0xffff);
+        let offset = if addr >= 0x8000 {
+            bank * 0x8000 + addr - 0x8000
+        } else {
+            size / 2 + bank * 0x8000 + addr
+        };
+        match self {
+            Self::Halves => offset,
+            // the three 512 KiB blocks of the second half are rotated
+            Self::GameDoctor24 => match offset {
+                0x180000..=0x27ffff => offset + 0x80000,
+                0x280000.. => offset - 0x100000,
+                _ => offset,
+            },
+        }
+    }
+
+    /// Undo the interleaving of the file `bytes`
+    fn deinterleave(self, bytes: &[u8]) -> Vec<u8> {
+        (0..bytes.len())
+            .map(|offset| bytes[self.file_offset(offset, bytes.len())])
+            .collect()
+    }
+}
+
+/// The header, which some copiers put in front of the ROM in its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopierHeader {
+    /// The header of the Super Wild Card (and Super Magicom)
+    Swc { hirom: bool },
+    /// The header of the Super UFO
+    Ufo { hirom: bool },
+    /// The header of the Game Doctor SF 3, 6 and 7
+    GameDoctor,
+}
+
+impl CopierHeader {
+    pub const SIZE: usize = 512;
+
+    /// Identify the copier header `bytes`. Headers without any
+    /// identification (e.g. of the Pro Fighter) are not recognized.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..Self::SIZE)?;
+        if bytes.starts_with(b"GAME DOCTOR SF ") {
+            Some(Self::GameDoctor)
+        } else if &bytes[8..16] == b"SUPERUFO" {
+            // the bank type is 0 for HiROM and 1 for LoROM
+            Some(Self::Ufo {
+                hirom: bytes[17] == 0,
+            })
+        } else if bytes[8..11] == [0xaa, 0xbb, 0x04] {
+            // bit 4 of the emulation mode selects the DRAM mapping
+            Some(Self::Swc {
+                hirom: bytes[2] & 0x10 != 0,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The mapping the header selects, if any
+    pub fn hirom(self) -> Option<bool> {
+        match self {
+            Self::Swc { hirom } | Self::Ufo { hirom } => Some(hirom),
+            Self::GameDoctor => None,
+        }
+    }
+}
+
+/// A ROM file after its copier header was stripped and the most plausible
+/// header was found
+struct RomFile<'a> {
+    /// The ROM. It is only copied, if it had to be de-interleaved.
+    bytes: Cow<'a, [u8]>,
+    header: Header,
+    copier_header: Option<CopierHeader>,
+    interleave: Option<Interleave>,
+}
+
+/// Strip a copier header from a ROM file and find the most plausible header
+fn find_header(bytes: &[u8]) -> Result<RomFile<'_>, ReadRomError> {
+    if bytes.len() < MINIMUM_SIZE {
+        return Err(ReadRomError::TooSmall(bytes.len()));
+    }
+    if bytes.len() & 0x1ff != 0 {
+        return Err(ReadRomError::AlignError(bytes.len()));
+    }
+    let (bytes, copier_header) = if bytes.len() & 0x3ff == 0 {
+        (bytes, None)
+    } else {
+        let (copier_header, bytes) = bytes.split_at(CopierHeader::SIZE);
+        (bytes, CopierHeader::from_bytes(copier_header))
+    };
+
+    let mut header = None;
+    for addr in [0x7fb0, 0xffb0, 0x40ffb0] {
+        if bytes.len() >= addr + 80 {
+            if let Some((new, score)) = Header::from_bytes(&bytes[addr..addr + 80]) {
+                if header.as_ref().map(|(_, s, _)| score > *s).unwrap_or(true) {
+                    header = Some((new, score, addr));
+                }
+            }
+        }
+    }
+    let (header, _score, addr) = header.ok_or(ReadRomError::NoSuitableHeader)?;
+    // the header of an interleaved HiROM is in the place of a LoROM header.
+    // The mapping in a copier header overrides the one in the ROM header.
+    let hirom = copier_header
+        .and_then(CopierHeader::hirom)
+        .unwrap_or(matches!(header.rom_type, RomType::HiRom));
+    if addr == 0x7fb0 && hirom && bytes.len() & 0xffff == 0 {
+        let interleave = match copier_header {
+            Some(CopierHeader::GameDoctor) if bytes.len() == Interleave::GAME_DOCTOR_24_SIZE => {
+                Interleave::GameDoctor24
+            }
+            _ => Interleave::Halves,
+        };
+        let rom = interleave.deinterleave(bytes);
+        if let Some((header, _score)) = Header::from_bytes(&rom[0xffb0..0x10000]) {
+            return Ok(RomFile {
+                bytes: Cow::Owned(rom),
+                header,
+                copier_header,
+                interleave: Some(interleave),
+            });
+        }
+    }
+    Ok(RomFile {
+        bytes: Cow::Borrowed(bytes),
+        header,
+        copier_header,
+        interleave: None,
+    })
+}
+
 #[derive(Debug, Default, Clone, InSaveState)]
 pub struct Cartridge {
     header: Header,
@@ -554,35 +856,48 @@ pub struct Cartridge {
     dsp: Option<Dsp>,
     sa1: Option<Sa1>,
     mapping: MemoryMapping,
+    /// The SRAM size in the header is not trusted, instead SRAM grows to
+    /// the highest written address
+    sram_detection: bool,
+    /// Replaces SRAM, if the save data is kept in flash memory
+    flash: Option<flash::Flash>,
+    /// The pages of the save memory, which changed since the last call to
+    /// [`Cartridge::clear_sram_dirty`]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    sram_dirty: Vec<bool>,
+    /// The whole save memory has to be considered changed, e.g. after its
+    /// size changed or a state was loaded
+    #[except((|_v, _s| ()), (|v: &mut bool, _s| *v = true))]
+    sram_all_dirty: bool,
+    /// The CRC-32 of the ROM before any patches
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    crc32: u32,
+    /// The size of the ROM in the file, before it was padded to the size
+    /// in the header
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    rom_size: usize,
+    /// The size of the copier header in front of the ROM in the file
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    file_header_size: usize,
+    /// The copier header in front of the ROM in the file, if it was
+    /// recognized
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    copier_header: Option<CopierHeader>,
+    /// The layout of the ROM in the file, if it was de-interleaved
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    interleave: Option<Interleave>,
 }
 
 impl Cartridge {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReadRomError> {
-        if bytes.len() < MINIMUM_SIZE {
-            return Err(ReadRomError::TooSmall(bytes.len()));
-        }
-        if bytes.len() & 0x1ff != 0 {
-            return Err(ReadRomError::AlignError(bytes.len()));
-        }
-        let bytes = if bytes.len() & 0x3ff == 0 {
-            bytes
-        } else {
-            &bytes[512..]
-        };
-
-        let mut header = None;
-        for addr in [0x7fb0, 0xffb0, 0x40ffb0] {
-            if bytes.len() >= addr + 80 {
-                if let Some((new, score)) = Header::from_bytes(&bytes[addr..addr + 80]) {
-                    if header.as_ref().map(|(_, s)| score > *s).unwrap_or(true) {
-                        header = Some((new, score));
-                    }
-                }
-            }
-        }
-        let (header, _score) = header.ok_or(ReadRomError::NoSuitableHeader)?;
-
-        let rom = create_rom(bytes, header.rom_size);
+        let file_size = bytes.len();
+        let RomFile {
+            bytes,
+            header,
+            copier_header,
+            interleave,
+        } = find_header(bytes)?;
+        let rom = create_rom(&bytes, header.rom_size);
 
         use core::num::Wrapping;
         let Wrapping(checksum): Wrapping<u16> =
@@ -591,7 +906,22 @@ impl Cartridge {
             eprintln!("warning: checksum did not match! Checksum in ROM is {:04x}; Calculated checksum is {:04x}", header.checksum, checksum);
         }
 
-        let ram_size = header.ram_size;
+        // romhacks sometimes lie about the SRAM size, so the size of SRAM is
+        // detected at runtime for headers with implausible SRAM. Headers
+        // without SRAM need `enable_sram_detection`.
+        let max_sram_size = header.rom_type.max_sram_size();
+        let sram_detection = header.coprocessor.is_none()
+            && matches!(header.rom_type, RomType::LoRom | RomType::HiRom)
+            && header.ram_size > max_sram_size;
+        let ram_size = if header.ram_size > max_sram_size {
+            eprintln!(
+                "warning: implausible SRAM size of {} KiB in the header, detecting it at runtime",
+                header.ram_size >> 10
+            );
+            0
+        } else {
+            header.ram_size
+        };
 
         let dsp = if let Some(Coprocessor::Dsp) = header.coprocessor {
             let ver = header
@@ -609,12 +939,21 @@ impl Cartridge {
         };
 
         let mut slf = Self {
+            crc32: crate::patch::crc32(&rom),
+            rom_size: bytes.len(),
+            file_header_size: file_size - bytes.len(),
+            copier_header,
+            interleave,
             rom,
             ram: vec![0xff; ram_size as usize],
             mapping: MemoryMapping::default(),
             dsp,
             sa1,
             header,
+            sram_detection,
+            flash: None,
+            sram_dirty: vec![false; (ram_size as usize).div_ceil(SRAM_PAGE_SIZE)],
+            sram_all_dirty: false,
         };
 
         slf.setup_memory_mappings();
@@ -660,8 +999,10 @@ impl Cartridge {
                 map!(map @ 0x80:0x8000 .. 0xbf:0xffff => Rom | Ignore [0x3f<<16:0xffff]);
                 map!(map @ 0xc0:0x0000 .. 0xff:0xffff => Rom | Ignore [0x3f<<16:0xffff]);
                 if self.ram.len() > 0 {
-                    map!(map @ 0x20:0x6000 .. 0x3f:0x7fff => Sram | Sram [0x3f<<13:0x1fff]);
-                    map!(map @ 0xa0:0x6000 .. 0xbf:0x7fff => Sram | Sram [0x3f<<13:0x1fff]);
+                    // the low five bits of the bank select one of 32 pages
+                    // of 8 KiB, smaller SRAM is mirrored
+                    map!(map @ 0x20:0x6000 .. 0x3f:0x7fff => Sram | Sram [0x1f<<13:0x1fff]);
+                    map!(map @ 0xa0:0x6000 .. 0xbf:0x7fff => Sram | Sram [0x1f<<13:0x1fff]);
                 }
                 if let Some(dsp) = &self.dsp {
                     match dsp.version() {
@@ -702,6 +1043,9 @@ impl Cartridge {
     }
 
     pub fn write_byte(&mut self, addr: Addr24, val: u8) {
+        if self.sram_detection {
+            self.detect_sram(addr)
+        }
         if self.has_sa1() {
             self.sa1_write::<false>(addr, val)
         } else {
@@ -728,10 +1072,137 @@ impl Cartridge {
         &self.header
     }
 
+    /// The title of the game, decoded with [`decode_title`]
     pub fn title(&self) -> &str {
         &self.header.name
     }
 
+    /// The title as stored in the header, padded with spaces
+    pub fn raw_title(&self) -> &[u8; TITLE_SIZE] {
+        &self.header.raw_title
+    }
+
+    /// The CRC-32 of the ROM, as it was loaded, which identifies the game,
+    /// e.g. for [`crate::hacks`]
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// The SRAM offset of an address in the SRAM area of the mapping,
+    /// even if no SRAM is mapped there (yet)
+    fn sram_area_offset(&self, addr: Addr24) -> Option<usize> {
+        let offset = match self.header.rom_type {
+            RomType::LoRom if addr.bank & 0x7f >= 0x70 && addr.addr < 0x8000 => {
+                (usize::from(addr.bank & 0xf) << 15) | usize::from(addr.addr)
+            }
+            RomType::HiRom
+                if (0x20..0x40).contains(&(addr.bank & 0x7f))
+                    && (0x6000..0x8000).contains(&addr.addr) =>
+            {
+                (usize::from(addr.bank & 0x1f) << 13) | usize::from(addr.addr & 0x1fff)
+            }
+            _ => return None,
+        };
+        // banks $7e and $7f are WRAM
+        (addr.bank & 0xfe != 0x7e).then_some(offset)
+    }
+
+    /// Grow SRAM, so that a write to `addr` doesn't end up in a mirror
+    fn detect_sram(&mut self, addr: Addr24) {
+        let offset = match self.sram_area_offset(addr) {
+            Some(offset) if offset >= self.ram.len() => offset,
+            _ => return,
+        };
+        let was_empty = self.ram.is_empty();
+        let size = (offset + 1).next_power_of_two().max(MIN_DETECTED_SRAM_SIZE);
+        // the old content is mirrored to the new part, as it is visible there
+        let mut ram = vec![0xff; size];
+        if !was_empty {
+            for chunk in ram.chunks_mut(self.ram.len()) {
+                chunk.copy_from_slice(&self.ram)
+            }
+        }
+        self.ram = ram;
+        self.sram_all_dirty = true;
+        if was_empty {
+            // the SRAM area was mapped to ROM or nothing before
+            self.mapping = MemoryMapping::default();
+            self.setup_memory_mappings();
+        }
+    }
+
+    /// The content of the cartridge RAM. Its size may grow while the game is
+    /// running, if the header doesn't specify a plausible size.
+    pub fn sram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    /// Load the save memory, e.g. from a `.srm` file. If the SRAM size is
+    /// detected at runtime, SRAM grows to the size of `data`. Returns `false`,
+    /// if the size doesn't match, then only the common part is loaded.
+    pub fn load_sram(&mut self, data: &[u8]) -> bool {
+        if self.sram_detection
+            && data.len() > self.ram.len()
+            && data.len() <= self.header.rom_type.max_sram_size() as usize
+            && data.len().is_power_of_two()
+        {
+            let was_empty = self.ram.is_empty();
+            self.ram = vec![0xff; data.len()];
+            if was_empty {
+                self.mapping = MemoryMapping::default();
+                self.setup_memory_mappings();
+            }
+        }
+        let len = data.len().min(self.ram.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        self.clear_sram_dirty();
+        len == data.len() && len == self.ram.len()
+    }
+
+    /// Whether the save memory changed since the last call to
+    /// [`Cartridge::clear_sram_dirty`]
+    pub fn is_sram_dirty(&self) -> bool {
+        self.sram_all_dirty || self.sram_dirty.contains(&true)
+    }
+
+    /// The ranges of the save memory, which changed since the last call to
+    /// [`Cartridge::clear_sram_dirty`]. Changes are tracked in pages of
+    /// [`SRAM_PAGE_SIZE`] bytes.
+    pub fn dirty_sram(&self) -> Vec<Range<usize>> {
+        if self.sram_all_dirty {
+            return std::iter::once(0..self.ram.len())
+                .filter(|range| !range.is_empty())
+                .collect();
+        }
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for (page, _) in self.sram_dirty.iter().enumerate().filter(|(_, &d)| d) {
+            let start = page * SRAM_PAGE_SIZE;
+            let end = (start + SRAM_PAGE_SIZE).min(self.ram.len());
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// Mark the save memory as unchanged, e.g. after it was written to a file
+    pub fn clear_sram_dirty(&mut self) {
+        self.sram_dirty.clear();
+        self.sram_dirty
+            .resize(self.ram.len().div_ceil(SRAM_PAGE_SIZE), false);
+        self.sram_all_dirty = false;
+    }
+
+    fn mark_sram_dirty(&mut self, range: Range<usize>) {
+        if self.sram_all_dirty {
+            return;
+        }
+        for page in range.start / SRAM_PAGE_SIZE..range.end.div_ceil(SRAM_PAGE_SIZE) {
+            self.sram_dirty[page] = true
+        }
+    }
+
     fn get_sram_addr(&self, addr: u32) -> usize {
         addr as usize & (self.ram.len() - 1)
     }
@@ -741,12 +1212,70 @@ impl Cartridge {
     }
 
     fn read_sram(&mut self, addr: u32) -> u8 {
-        self.ram[self.get_sram_addr(addr)]
+        let addr = self.get_sram_addr(addr);
+        match &self.flash {
+            Some(flash) => flash.read(&self.ram, addr),
+            None => self.ram[addr],
+        }
     }
 
     fn write_sram(&mut self, addr: u32, val: u8) {
         let addr = self.get_sram_addr(addr);
-        self.ram[addr] = val
+        let changed = match &mut self.flash {
+            Some(flash) => flash.write(&mut self.ram, addr, val),
+            None if self.ram[addr] != val => {
+                self.ram[addr] = val;
+                Some(addr..addr + 1)
+            }
+            None => None,
+        };
+        if let Some(range) = changed {
+            self.mark_sram_dirty(range)
+        }
+    }
+
+    pub fn save_memory(&self) -> SaveMemory {
+        if self.flash.is_some() {
+            SaveMemory::Flash
+        } else {
+            SaveMemory::Sram
+        }
+    }
+
+    /// Detect the size of SRAM at runtime, although the header says there is
+    /// none. This is needed by romhacks, which added saving to a game without
+    /// updating the header. Only LoROM and HiROM cartridges without
+    /// coprocessor and flash memory support this.
+    pub fn enable_sram_detection(&mut self) {
+        if self.header.coprocessor.is_none()
+            && matches!(self.header.rom_type, RomType::LoRom | RomType::HiRom)
+            && self.flash.is_none()
+        {
+            self.sram_detection = true
+        }
+    }
+
+    /// Select the kind of save memory, e.g. for flash carts, which can't be
+    /// told apart from SRAM carts by the header. Selecting flash erases the
+    /// save data. SA-1 cartridges always use their BW-RAM.
+    pub fn set_save_memory(&mut self, kind: SaveMemory) {
+        if self.has_sa1() {
+            return;
+        }
+        match kind {
+            SaveMemory::Sram => self.flash = None,
+            SaveMemory::Flash => {
+                // the command addresses need at least the smallest chip
+                let max_size = self.header.rom_type.max_sram_size() as usize;
+                let size = self.ram.len().clamp(0x20000, max_size);
+                self.flash = Some(flash::Flash::new(size));
+                self.sram_detection = false;
+                self.ram = vec![0xff; size];
+                self.sram_all_dirty = true;
+                self.mapping = MemoryMapping::default();
+                self.setup_memory_mappings();
+            }
+        }
     }
 
     pub fn read_rom_mut(&mut self, addr: u32) -> u8 {
@@ -757,6 +1286,182 @@ impl Cartridge {
         self.rom[self.get_rom_addr(addr)]
     }
 
+    /// The offset in the ROM file of the byte `offset` of the ROM, taking
+    /// the copier header and interleaving into account. Returns `None` for
+    /// the padding of the ROM up to the size in its header.
+    pub fn file_offset(&self, offset: usize) -> Option<usize> {
+        let offset = rom_source_offset(offset, self.rom.len(), self.rom_size)?;
+        let offset = match self.interleave {
+            Some(interleave) => interleave.file_offset(offset, self.rom_size),
+            None => offset,
+        };
+        Some(self.file_header_size + offset)
+    }
+
+    /// The ROM as it was laid out in its file, see [`Self::file_offset`].
+    /// The content of a copier header is not kept and filled with zeros.
+    pub fn file_bytes(&self) -> Vec<u8> {
+        let rom = &self.rom[..self.rom_size.min(self.rom.len())];
+        let mut bytes = vec![0; self.file_header_size];
+        bytes.extend_from_slice(rom);
+        if let Some(interleave) = self.interleave {
+            let file = &mut bytes[self.file_header_size..];
+            for (offset, &byte) in rom.iter().enumerate() {
+                file[interleave.file_offset(offset, rom.len())] = byte;
+            }
+        }
+        bytes
+    }
+
+    /// The ROM content (after mirroring it up to the header's ROM size)
+    pub(crate) fn rom_mut(&mut self) -> &mut [u8] {
+        &mut self.rom
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// The copier header in front of the ROM in its file, if it was
+    /// recognized
+    pub fn copier_header(&self) -> Option<CopierHeader> {
+        self.copier_header
+    }
+
+    /// Make the known region checks of the game pass on consoles of every
+    /// region, see [`crate::patch::patch_region_checks`]. Returns the count
+    /// of patched checks.
+    pub fn patch_region_checks(&mut self) -> usize {
+        let pal = matches!(self.get_country_frame_rate(), CountryFrameRate::Pal);
+        let checks = crate::patch::REGION_CHECKS;
+        crate::patch::patch_region_checks(&mut self.rom, self.crc32, pal, checks).len()
+    }
+
+    /// Find out which memory a bus address of the cartridge is decoded to.
+    /// All mirrors of a byte resolve to the same region and offset.
+    pub fn region(&self, addr: Addr24) -> MemoryRegion {
+        if let Some(sa1) = &self.sa1 {
+            if addr.bank & 0xc0 == 0xc0 {
+                MemoryRegion::Rom(self.get_rom_addr(sa1.hirom_addr(addr)))
+            } else if addr.bank & 0x40 == 0 && addr.addr >= 0x8000 {
+                MemoryRegion::Rom(self.get_rom_addr(sa1.lorom_addr(addr)))
+            } else if addr.bank & 0x40 == 0 {
+                match addr.addr {
+                    // SA-1 registers, I-RAM and BW-RAM
+                    0x2200..=0x23ff | 0x3000..=0x37ff | 0x6000..=0x7fff => {
+                        MemoryRegion::Coprocessor
+                    }
+                    _ => MemoryRegion::Unmapped,
+                }
+            } else if addr.bank & 0x10 == 0 {
+                // BW-RAM
+                MemoryRegion::Coprocessor
+            } else {
+                MemoryRegion::Unmapped
+            }
+        } else {
+            match self.mapping.find(addr) {
+                Some((index, MappingEntry { read, .. })) => match read {
+                    ReadFunction::Rom => MemoryRegion::Rom(self.get_rom_addr(index)),
+                    ReadFunction::Sram => MemoryRegion::Sram(self.get_sram_addr(index)),
+                    ReadFunction::DspDr | ReadFunction::DspSr => MemoryRegion::Coprocessor,
+                },
+                None => MemoryRegion::Unmapped,
+            }
+        }
+    }
+
+    /// Translate a bus address into an offset into the ROM.
+    /// Returns `None` if the address is not mapped to ROM.
+    pub fn rom_offset(&self, addr: Addr24) -> Option<usize> {
+        match self.region(addr) {
+            MemoryRegion::Rom(offset) => Some(offset),
+            _ => None,
+        }
+    }
+
+    /// Overwrite the ROM byte visible at a bus address.
+    /// Returns the affected ROM offset or `None` if the address is not mapped to ROM.
+    pub fn patch_rom(&mut self, addr: Addr24, val: u8) -> Option<usize> {
+        let offset = self.rom_offset(addr)?;
+        self.rom[offset] = val;
+        Some(offset)
+    }
+
+    /// Read ROM or SRAM at a bus address without any side effects
+    pub fn peek(&self, addr: Addr24) -> Option<u8> {
+        match self.region(addr) {
+            MemoryRegion::Rom(offset) => Some(self.rom[offset]),
+            MemoryRegion::Sram(offset) => Some(self.ram[offset]),
+            _ => None,
+        }
+    }
+
+    /// The offsets of all copies of a byte of the ROM. A ROM smaller than
+    /// the size in its header is padded with copies of its parts.
+    pub fn rom_copies(&self, offset: usize) -> Vec<usize> {
+        let (dst_len, src_len) = (self.rom.len(), self.rom_size);
+        match rom_source_offset(offset, dst_len, src_len) {
+            Some(source) if src_len > 0 && src_len < dst_len => {
+                let mut copies = vec![];
+                rom_copy_offsets(source, dst_len, src_len, 0, &mut copies);
+                copies
+            }
+            _ => vec![offset],
+        }
+    }
+
+    /// The ranges of addresses, in which the offset into ROM or SRAM
+    /// increases with the address, as the first address and the length
+    pub fn linear_ranges(&self) -> Vec<(Addr24, u32)> {
+        let mut ranges = vec![];
+        if self.sa1.is_some() {
+            for bank in 0..=0xff {
+                if bank & 0xc0 == 0xc0 {
+                    ranges.push((Addr24::new(bank, 0), 0x10000))
+                } else if bank & 0x40 == 0 {
+                    ranges.push((Addr24::new(bank, 0x8000), 0x8000))
+                }
+            }
+            return ranges;
+        }
+        for entry in &self.mapping.areas {
+            if !matches!(entry.read, ReadFunction::Rom | ReadFunction::Sram) {
+                continue;
+            }
+            let Area { start, end } = entry.area;
+            // the masked address starts from zero again every `wrap` bytes
+            let wrap = u32::from(entry.map.addr_mask) + 1;
+            for bank in start.bank..=end.bank {
+                let mut addr = u32::from(start.addr);
+                while addr <= u32::from(end.addr) {
+                    let len = (u32::from(end.addr) + 1 - addr).min(wrap - (addr & (wrap - 1)));
+                    ranges.push((Addr24::new(bank, addr as u16), len));
+                    addr += len
+                }
+            }
+        }
+        ranges
+    }
+
+    /// Write ROM or SRAM at a bus address without any side effects.
+    /// Returns `false` if the address is not decoded to ROM or SRAM.
+    pub fn poke(&mut self, addr: Addr24, val: u8) -> bool {
+        match self.region(addr) {
+            MemoryRegion::Rom(offset) => {
+                for offset in self.rom_copies(offset) {
+                    self.rom[offset] = val
+                }
+            }
+            MemoryRegion::Sram(offset) => {
+                self.ram[offset] = val;
+                self.mark_sram_dirty(offset..offset + 1)
+            }
+            _ => return false,
+        }
+        true
+    }
+
     fn read_dsp_data(&mut self, _: u32) -> u8 {
         let dsp = self.dsp.as_mut().unwrap();
         dsp.refresh();
@@ -799,11 +1504,7 @@ impl Cartridge {
 
     pub fn set_region(&mut self, pal: bool) {
         if let Some(dsp) = &mut self.dsp {
-            dsp.set_timing_proportion(if pal {
-                crate::timing::NECDSP_CPU_TIMING_PROPORTION_PAL
-            } else {
-                crate::timing::NECDSP_CPU_TIMING_PROPORTION_NTSC
-            })
+            dsp.set_region(pal)
         }
         if let Some(sa1) = &mut self.sa1 {
             sa1.set_region(pal)
@@ -816,12 +1517,27 @@ impl Cartridge {
         }
     }
 
+    /// Reset the NEC DSP and the SA-1, if the cartridge has them
+    pub fn reset_coprocessors(&mut self) {
+        if let Some(dsp) = &mut self.dsp {
+            dsp.reset()
+        }
+        if let Some(sa1) = &mut self.sa1 {
+            sa1.reset()
+        }
+    }
+
     pub fn refresh_coprocessors(&mut self) {
         if let Some(dsp) = &mut self.dsp {
             dsp.refresh()
         }
     }
 
+    /// The NEC DSP of the cartridge, if it has one
+    pub fn dsp_mut(&mut self) -> Option<&mut Dsp> {
+        self.dsp.as_mut()
+    }
+
     pub fn has_sa1(&self) -> bool {
         self.sa1.is_some()
     }
@@ -838,3 +1554,6 @@ impl Cartridge {
             .expect("unexpectedly queried sa1-chip in a non-sa1 cartridge")
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,170 @@
+//! Compatibility of a cartridge with this emulator
+//!
+//! The header of a cartridge names its memory mapping, its coprocessor and
+//! its save memory. [`CompatReport::new`] lists these features together with
+//! how well they are emulated, without loading the cartridge, which would
+//! fail for unsupported boards.
+
+use super::{create_rom, find_header, Coprocessor, Interleave, ReadRomError, RomFile, RomType};
+use crate::enhancement::DspVersion;
+
+/// How well a feature is emulated, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Support {
+    Supported,
+    Partial,
+    Missing,
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Supported => "supported",
+            Self::Partial => "partial",
+            Self::Missing => "missing",
+        })
+    }
+}
+
+/// A feature required by a cartridge
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    pub feature: String,
+    pub support: Support,
+    /// What is missing or how the feature is emulated
+    pub note: Option<&'static str>,
+}
+
+impl Requirement {
+    fn new(feature: impl Into<String>, support: Support, note: Option<&'static str>) -> Self {
+        Self {
+            feature: feature.into(),
+            support,
+            note,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompatReport {
+    pub title: String,
+    pub requirements: Vec<Requirement>,
+}
+
+impl CompatReport {
+    /// Inspect the header of the ROM file `bytes`
+    pub fn new(bytes: &[u8]) -> Result<Self, ReadRomError> {
+        use Support::*;
+        let RomFile {
+            bytes,
+            header,
+            interleave,
+            ..
+        } = find_header(bytes)?;
+        let rom_size = create_rom(&bytes, header.rom_size).len();
+        let max_sram_size = header.rom_type.max_sram_size();
+        let ram_size = if header.ram_size > max_sram_size {
+            0
+        } else {
+            header.ram_size
+        };
+        let mut requirements = vec![];
+        requirements.push(match header.rom_type {
+            RomType::LoRom => Requirement::new("LoROM mapping", Supported, None),
+            RomType::HiRom => Requirement::new("HiROM mapping", Supported, None),
+            RomType::LoRomSA1 => Requirement::new("SA-1 mapping", Supported, None),
+            RomType::LoRomSDD1 => Requirement::new("S-DD1 mapping", Missing, None),
+            RomType::ExHiRom => Requirement::new("ExHiROM mapping", Missing, None),
+            RomType::HiRomSPC7110 => Requirement::new("SPC7110 mapping", Missing, None),
+        });
+        if let Some(interleave) = interleave {
+            requirements.push(Requirement::new(
+                "interleaved dump",
+                Supported,
+                Some(match interleave {
+                    Interleave::Halves => "de-interleaved when loaded",
+                    Interleave::GameDoctor24 => {
+                        "de-interleaved in the 24 Mbit layout of the Game Doctor"
+                    }
+                }),
+            ));
+        }
+        if let Some(coprocessor) = header.coprocessor {
+            requirements.push(match coprocessor {
+                Coprocessor::Dsp => match header.find_dsp_version(rom_size as u32, ram_size) {
+                    Some(ver) => {
+                        let name = ver.name();
+                        // the boards handled by `Cartridge::setup_memory_mappings`
+                        let mapped = match header.rom_type {
+                            RomType::LoRom => matches!(
+                                (ver, rom_size >> 20, ram_size >> 10),
+                                (
+                                    DspVersion::Dsp1 | DspVersion::Dsp1B | DspVersion::Dsp4,
+                                    _,
+                                    0
+                                ) | (DspVersion::Dsp2 | DspVersion::Dsp3, 1, 8 | 32)
+                                    | (DspVersion::Dsp1 | DspVersion::Dsp1B, 2, 8)
+                            ),
+                            RomType::HiRom => {
+                                matches!(ver, DspVersion::Dsp1 | DspVersion::Dsp1B)
+                            }
+                            _ => false,
+                        };
+                        if mapped {
+                            Requirement::new(name, Supported, Some("the firmware is built in"))
+                        } else {
+                            Requirement::new(name, Missing, Some("the board isn't mapped"))
+                        }
+                    }
+                    None => Requirement::new("NEC DSP", Missing, Some("unknown board")),
+                },
+                Coprocessor::Sa1 => {
+                    Requirement::new("SA-1", Supported, Some("the BW-RAM isn't saved yet"))
+                }
+                Coprocessor::Gsu => Requirement::new("SuperFX", Missing, None),
+                Coprocessor::Obc1 => Requirement::new("OBC-1", Missing, None),
+                Coprocessor::Sdd1 => Requirement::new("S-DD1", Missing, None),
+                Coprocessor::Srtc => Requirement::new("S-RTC", Missing, None),
+                Coprocessor::Spc7110 => Requirement::new("SPC7110", Missing, None),
+                Coprocessor::St01x => Requirement::new(
+                    "ST010/ST011",
+                    Missing,
+                    Some("requires firmware, which isn't included"),
+                ),
+                Coprocessor::St018 => Requirement::new(
+                    "ST018",
+                    Missing,
+                    Some("requires firmware, which isn't included"),
+                ),
+                Coprocessor::Cx4 => Requirement::new("Cx4", Missing, None),
+                Coprocessor::Unknown => Requirement::new("unknown coprocessor", Missing, None),
+            });
+        }
+        if header.ram_size > max_sram_size {
+            requirements.push(Requirement::new(
+                "SRAM",
+                Supported,
+                Some("implausible size in the header, detected at runtime"),
+            ));
+        } else if ram_size > 0 {
+            requirements.push(Requirement::new(
+                format!("{} KiB SRAM", ram_size >> 10),
+                Supported,
+                None,
+            ));
+        }
+        Ok(Self {
+            title: header.name,
+            requirements,
+        })
+    }
+
+    /// The support of the worst supported feature
+    pub fn support(&self) -> Support {
+        self.requirements
+            .iter()
+            .map(|requirement| requirement.support)
+            .max()
+            .unwrap_or(Support::Supported)
+    }
+}
@@ -0,0 +1,108 @@
+//! Flash memory in place of battery backed SRAM
+//!
+//! Battery-less reproduction and flash carts keep the save data in a JEDEC
+//! compatible flash chip of the SST39SF0x0 series, which is mapped to the
+//! SRAM area. Writes are interpreted as commands: every command starts with
+//! the unlock sequence `$aa` to `$5555` and `$55` to `$2aaa`. Programming can
+//! only clear bits, an erase sets all bits of a 4 KiB sector or of the whole
+//! chip again.
+//!
+//! # Literature
+//!
+//! - the SST39SF010A/020A/040 data sheet
+
+use save_state_macro::*;
+use std::ops::Range;
+
+/// The flash chips are erased and programmed in sectors of this size
+pub const SECTOR_SIZE: usize = 0x1000;
+
+const MANUFACTURER_ID: u8 = 0xbf;
+
+/// The position in a command sequence
+mod step {
+    pub const IDLE: u8 = 0;
+    pub const UNLOCKED1: u8 = 1;
+    pub const UNLOCKED2: u8 = 2;
+    pub const PROGRAM: u8 = 3;
+    pub const ERASE: u8 = 4;
+    pub const ERASE_UNLOCKED1: u8 = 5;
+    pub const ERASE_UNLOCKED2: u8 = 6;
+}
+
+#[derive(Debug, Default, Clone, InSaveState)]
+pub struct Flash {
+    step: u8,
+    /// Reads return the chip identification instead of the memory
+    id_mode: bool,
+    device_id: u8,
+}
+
+impl Flash {
+    /// The smallest chip is used for up to 128 KiB
+    pub fn new(size: usize) -> Self {
+        Self {
+            step: step::IDLE,
+            id_mode: false,
+            device_id: match size {
+                0..=0x20000 => 0xb5,
+                0x20001..=0x40000 => 0xb6,
+                _ => 0xb7,
+            },
+        }
+    }
+
+    pub fn read(&self, memory: &[u8], offset: usize) -> u8 {
+        if self.id_mode {
+            if offset & 1 == 0 {
+                MANUFACTURER_ID
+            } else {
+                self.device_id
+            }
+        } else {
+            memory[offset]
+        }
+    }
+
+    /// Returns the range of the memory, which was programmed or erased
+    pub fn write(&mut self, memory: &mut [u8], offset: usize, val: u8) -> Option<Range<usize>> {
+        // only the lower 15 address lines are decoded for commands
+        let command_addr = offset & 0x7fff;
+        let mut changed = None;
+        self.step = match (self.step, command_addr, val) {
+            (_, _, 0xf0) => {
+                self.id_mode = false;
+                step::IDLE
+            }
+            (step::IDLE, 0x5555, 0xaa) => step::UNLOCKED1,
+            (step::UNLOCKED1, 0x2aaa, 0x55) => step::UNLOCKED2,
+            (step::UNLOCKED2, 0x5555, 0xa0) => step::PROGRAM,
+            (step::UNLOCKED2, 0x5555, 0x80) => step::ERASE,
+            (step::UNLOCKED2, 0x5555, 0x90) => {
+                self.id_mode = true;
+                step::IDLE
+            }
+            (step::PROGRAM, _, _) => {
+                memory[offset] &= val;
+                changed = Some(offset..offset + 1);
+                step::IDLE
+            }
+            (step::ERASE, 0x5555, 0xaa) => step::ERASE_UNLOCKED1,
+            (step::ERASE_UNLOCKED1, 0x2aaa, 0x55) => step::ERASE_UNLOCKED2,
+            (step::ERASE_UNLOCKED2, 0x5555, 0x10) => {
+                memory.fill(0xff);
+                changed = Some(0..memory.len());
+                step::IDLE
+            }
+            (step::ERASE_UNLOCKED2, _, 0x30) => {
+                let start = offset & !(SECTOR_SIZE - 1);
+                let end = (start + SECTOR_SIZE).min(memory.len());
+                memory[start..end].fill(0xff);
+                changed = Some(start..end);
+                step::IDLE
+            }
+            _ => step::IDLE,
+        };
+        changed
+    }
+}
@@ -0,0 +1,475 @@
+use super::*;
+use crate::test_util::{create_rom, HIROM, LOROM};
+
+/// A LoROM or HiROM file with the chipset byte `chips` and the SRAM size
+/// byte `ram` in its header
+fn create_rom_file(hirom: bool, chips: u8, ram: u8) -> Vec<u8> {
+    create_rom(0x10000, LOROM | hirom as u8, chips, ram, |_| ())
+}
+
+/// A LoROM or HiROM cartridge with the SRAM size byte `ram` in its header
+fn create_cartridge(hirom: bool, ram: u8) -> Box<Cartridge> {
+    crate::test_util::create_cartridge(&create_rom_file(hirom, 0, ram))
+}
+
+#[test]
+pub fn test_sram_from_header() {
+    let mut cart = create_cartridge(false, 3);
+    assert_eq!(cart.sram().len(), 0x2000);
+    // writes beyond the header size end up in a mirror
+    cart.write_byte(Addr24::new(0x70, 0x2001), 0x42);
+    assert_eq!(cart.sram().len(), 0x2000);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x0001)), Some(0x42));
+}
+
+#[test]
+pub fn test_sram_detection() {
+    // a header without SRAM is trusted by default
+    let mut cart = create_cartridge(false, 0);
+    cart.write_byte(Addr24::new(0x70, 0x0123), 0x42);
+    assert!(cart.sram().is_empty());
+    assert!(!cart.load_sram(&[0x42; 0x800]));
+    assert!(cart.sram().is_empty());
+
+    let mut cart = create_cartridge(false, 0);
+    cart.enable_sram_detection();
+    assert!(cart.sram().is_empty());
+    assert_eq!(cart.region(Addr24::new(0x70, 0)), MemoryRegion::Rom(0));
+    cart.write_byte(Addr24::new(0x70, 0x0123), 0x42);
+    assert_eq!(cart.sram().len(), MIN_DETECTED_SRAM_SIZE);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x0123)), Some(0x42));
+    // the old content stays visible in its mirrors
+    cart.write_byte(Addr24::new(0x71, 0x0000), 0x43);
+    assert_eq!(cart.sram().len(), 0x10000);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x0923)), Some(0x42));
+    assert_eq!(cart.read_byte(Addr24::new(0xf1, 0x0000)), Some(0x43));
+
+    let mut cart = create_cartridge(true, 0);
+    cart.enable_sram_detection();
+    cart.write_byte(Addr24::new(0x3f, 0x7fff), 0x44);
+    assert_eq!(cart.sram().len(), 0x40000);
+    assert_eq!(cart.read_byte(Addr24::new(0xbf, 0x7fff)), Some(0x44));
+}
+
+#[test]
+pub fn test_implausible_sram_size() {
+    // would be 4 GiB
+    let mut cart = create_cartridge(false, 0x16);
+    assert!(cart.sram().is_empty());
+    cart.write_byte(Addr24::new(0x7d, 0x7fff), 0x42);
+    assert_eq!(cart.sram().len(), 0x80000);
+}
+
+#[test]
+pub fn test_hirom_sram_mirroring() {
+    // 2 KiB like Donkey Kong Country, mirrored every 2 KiB in all SRAM banks
+    let mut cart = create_cartridge(true, 1);
+    assert_eq!(cart.sram().len(), 0x800);
+    cart.write_byte(Addr24::new(0x20, 0x6001), 0x42);
+    for addr in [
+        Addr24::new(0x20, 0x6801),
+        Addr24::new(0x20, 0x7801),
+        Addr24::new(0x31, 0x6001),
+        Addr24::new(0xbf, 0x7801),
+    ] {
+        assert_eq!(cart.read_byte(addr), Some(0x42), "{addr:?}");
+        assert_eq!(cart.region(addr), MemoryRegion::Sram(1));
+    }
+
+    // 8 KiB like Chrono Trigger, every bank shows the whole SRAM
+    let mut cart = create_cartridge(true, 3);
+    assert_eq!(cart.sram().len(), 0x2000);
+    cart.write_byte(Addr24::new(0x20, 0x6000), 0x43);
+    cart.write_byte(Addr24::new(0x20, 0x7fff), 0x44);
+    assert_eq!(cart.read_byte(Addr24::new(0x3f, 0x6000)), Some(0x43));
+    assert_eq!(cart.read_byte(Addr24::new(0xa5, 0x7fff)), Some(0x44));
+    assert_eq!(cart.sram()[0x1fff], 0x44);
+
+    // the largest SRAM starts at bank $20 and ends at bank $3f
+    let mut cart = create_cartridge(true, 8);
+    assert_eq!(cart.sram().len(), 0x40000);
+    cart.write_byte(Addr24::new(0x20, 0x6000), 0x45);
+    cart.write_byte(Addr24::new(0x3f, 0x7fff), 0x46);
+    assert_eq!(cart.sram()[0], 0x45);
+    assert_eq!(cart.sram()[0x3ffff], 0x46);
+    assert_eq!(
+        cart.region(Addr24::new(0x21, 0x6000)),
+        MemoryRegion::Sram(0x2000)
+    );
+}
+
+#[test]
+pub fn test_lorom_sram_mirroring() {
+    // 2 KiB like Super Mario World, mirrored in every SRAM bank
+    let mut cart = create_cartridge(false, 1);
+    assert_eq!(cart.sram().len(), 0x800);
+    cart.write_byte(Addr24::new(0x70, 0x07ff), 0x42);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x7fff)), Some(0x42));
+    assert_eq!(cart.read_byte(Addr24::new(0x7d, 0x0fff)), Some(0x42));
+    assert_eq!(cart.read_byte(Addr24::new(0xff, 0x17ff)), Some(0x42));
+    assert_eq!(
+        cart.region(Addr24::new(0xf3, 0x47ff)),
+        MemoryRegion::Sram(0x7ff)
+    );
+}
+
+#[test]
+pub fn test_hirom_sram_size_limit() {
+    // HiROM can't address 512 KiB, so the size is detected at runtime
+    let mut cart = create_cartridge(true, 9);
+    assert!(cart.sram().is_empty());
+    cart.write_byte(Addr24::new(0x20, 0x6000), 0x42);
+    assert_eq!(cart.sram().len(), MIN_DETECTED_SRAM_SIZE);
+    // save files larger than the addressable size are rejected
+    let mut cart = create_cartridge(true, 0);
+    cart.enable_sram_detection();
+    assert!(!cart.load_sram(&[0x43; 0x80000]));
+    assert!(cart.sram().is_empty());
+    assert!(cart.load_sram(&[0x43; 0x40000]));
+    assert_eq!(cart.read_byte(Addr24::new(0xbf, 0x7fff)), Some(0x43));
+}
+
+#[test]
+pub fn test_flash() {
+    let mut cart = create_cartridge(false, 3);
+    cart.set_save_memory(SaveMemory::Flash);
+    assert_eq!(cart.sram().len(), 0x20000);
+    let command = |cart: &mut Cartridge, sequence: &[(u16, u8)]| {
+        for &(addr, val) in sequence {
+            cart.write_byte(Addr24::new(0x70, addr), val)
+        }
+    };
+    let unlock = [(0x5555, 0xaa), (0x2aaa, 0x55)];
+    // plain writes are ignored
+    command(&mut cart, &[(0x1234, 0x00)]);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x1234)), Some(0xff));
+    // programming can only clear bits
+    command(
+        &mut cart,
+        &[unlock[0], unlock[1], (0x5555, 0xa0), (0x1234, 0x0f)],
+    );
+    command(
+        &mut cart,
+        &[unlock[0], unlock[1], (0x5555, 0xa0), (0x1234, 0xf5)],
+    );
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x1234)), Some(0x05));
+    command(&mut cart, &[unlock[0], unlock[1], (0x5555, 0x90)]);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x0000)), Some(0xbf));
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x0001)), Some(0xb5));
+    command(&mut cart, &[(0x0000, 0xf0)]);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x1234)), Some(0x05));
+    // erase the sector $1000-$1fff
+    let erase = [unlock[0], unlock[1], (0x5555, 0x80), unlock[0], unlock[1]];
+    command(
+        &mut cart,
+        &[unlock[0], unlock[1], (0x5555, 0xa0), (0x2000, 0x00)],
+    );
+    command(&mut cart, &erase);
+    command(&mut cart, &[(0x1000, 0x30)]);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x1234)), Some(0xff));
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x2000)), Some(0x00));
+    command(&mut cart, &erase);
+    command(&mut cart, &[(0x5555, 0x10)]);
+    assert!(cart.sram().iter().all(|&b| b == 0xff));
+}
+
+#[test]
+pub fn test_dirty_sram() {
+    let mut cart = create_cartridge(false, 5);
+    assert!(!cart.is_sram_dirty());
+    // writing the same value doesn't change anything
+    cart.write_byte(Addr24::new(0x70, 0x0010), 0xff);
+    assert!(!cart.is_sram_dirty());
+    cart.write_byte(Addr24::new(0x70, 0x0010), 0x42);
+    cart.write_byte(Addr24::new(0x70, 0x07ff), 0x42);
+    cart.write_byte(Addr24::new(0x70, 0x4000), 0x42);
+    assert!(cart.is_sram_dirty());
+    assert_eq!(cart.dirty_sram(), vec![0..0x800, 0x4000..0x4400]);
+    cart.clear_sram_dirty();
+    assert!(!cart.is_sram_dirty());
+    assert!(cart.dirty_sram().is_empty());
+}
+
+#[test]
+pub fn test_load_sram() {
+    let mut cart = create_cartridge(false, 3);
+    assert!(cart.load_sram(&[0x42; 0x2000]));
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x1fff)), Some(0x42));
+    assert!(!cart.is_sram_dirty());
+    assert!(!cart.load_sram(&[0x43; 0x1000]));
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x0fff)), Some(0x43));
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x1000)), Some(0x42));
+
+    // a detected size is restored from the file
+    let mut cart = create_cartridge(false, 0);
+    cart.enable_sram_detection();
+    assert!(cart.load_sram(&[0x44; 0x8000]));
+    assert_eq!(cart.sram().len(), 0x8000);
+    assert_eq!(cart.read_byte(Addr24::new(0x70, 0x7fff)), Some(0x44));
+}
+
+#[test]
+pub fn test_compat_report() {
+    use compat::{CompatReport, Support};
+    let report = CompatReport::new(&create_rom_file(false, 0x02, 3)).unwrap();
+    assert_eq!(report.title, "TEST");
+    let features: Vec<_> = report
+        .requirements
+        .iter()
+        .map(|requirement| (requirement.feature.as_str(), requirement.support))
+        .collect();
+    assert_eq!(
+        features,
+        [
+            ("LoROM mapping", Support::Supported),
+            ("8 KiB SRAM", Support::Supported)
+        ]
+    );
+    assert_eq!(report.support(), Support::Supported);
+    // a DSP-1 in a HiROM board
+    let report = CompatReport::new(&create_rom_file(true, 0x03, 0)).unwrap();
+    assert_eq!(report.requirements[1].feature, "DSP-1B");
+    assert_eq!(report.support(), Support::Supported);
+    // the SA-1 is emulated, a SuperFX isn't
+    let report = CompatReport::new(&create_rom_file(false, 0x35, 0)).unwrap();
+    assert_eq!(report.support(), Support::Supported);
+    let report = CompatReport::new(&create_rom_file(false, 0x13, 0)).unwrap();
+    assert_eq!(report.requirements[1].feature, "SuperFX");
+    assert_eq!(report.support(), Support::Missing);
+}
+
+#[test]
+pub fn test_decode_title() {
+    assert_eq!(decode_title(b"  SUPER MARIOWORLD   "), "SUPER MARIOWORLD");
+    assert_eq!(decode_title(b"C:\\GAME\0\0"), "C:¥GAME");
+    // half-width katakana
+    assert_eq!(decode_title(&[0xbd, 0xb0, 0xca, 0xdf, 0xb0]), "ｽｰﾊﾟｰ");
+    // hiragana, katakana and full-width letters, but no kanji
+    let title = [0x82, 0xa0, 0x83, 0x8d, 0x82, 0x60, 0x88, 0x9f, 0x81, 0x5b];
+    assert_eq!(decode_title(&title), "あロＡ\u{fffd}ー");
+    // a lead byte without a trail byte
+    assert_eq!(decode_title(&[b'A', 0x82]), "A\u{fffd}");
+
+    let cart = create_cartridge(false, 0);
+    assert_eq!(&cart.raw_title()[..5], b"TEST\0");
+    assert_eq!(cart.title(), "TEST");
+}
+
+#[test]
+pub fn test_deinterleave() {
+    use compat::CompatReport;
+    let mut rom = create_rom_file(true, 0, 0);
+    rom.extend((0..0x10000).map(|i| (i >> 8) as u8));
+    // the upper halves of both banks come first
+    let interleaved = [
+        &rom[0x8000..0x10000],
+        &rom[0x18000..],
+        &rom[..0x8000],
+        &rom[0x10000..0x18000],
+    ]
+    .concat();
+    let report = CompatReport::new(&interleaved).unwrap();
+    assert_eq!(report.requirements[1].feature, "interleaved dump");
+    let report = CompatReport::new(&rom).unwrap();
+    assert_eq!(report.requirements.len(), 1);
+    let cart = crate::test_util::create_cartridge(&interleaved);
+    assert_eq!(cart.title(), "TEST");
+    assert!(cart.rom() == rom);
+}
+
+/// The copier headers of the Super Wild Card, the Super UFO and the Game
+/// Doctor for a LoROM or HiROM file
+fn copier_headers(hirom: bool) -> [(Vec<u8>, Option<CopierHeader>); 3] {
+    let mut swc = vec![0; 512];
+    swc[2] = if hirom { 0x30 } else { 0 };
+    swc[8..11].copy_from_slice(&[0xaa, 0xbb, 0x04]);
+    let mut ufo = vec![0; 512];
+    ufo[8..16].copy_from_slice(b"SUPERUFO");
+    ufo[17] = !hirom as u8;
+    let mut gd = vec![0; 512];
+    gd[..16].copy_from_slice(b"GAME DOCTOR SF 3");
+    [
+        (swc, Some(CopierHeader::Swc { hirom })),
+        (ufo, Some(CopierHeader::Ufo { hirom })),
+        (gd, Some(CopierHeader::GameDoctor)),
+    ]
+}
+
+#[test]
+pub fn test_copier_header() {
+    let mut rom = create_rom_file(true, 0, 0);
+    rom.extend((0..0x10000).map(|i| (i >> 8) as u8));
+    let interleaved = [
+        &rom[0x8000..0x10000],
+        &rom[0x18000..],
+        &rom[..0x8000],
+        &rom[0x10000..0x18000],
+    ]
+    .concat();
+    for (header, expected) in copier_headers(true) {
+        let file = [&header[..], &interleaved].concat();
+        let cart = crate::test_util::create_cartridge(&file);
+        assert_eq!(cart.copier_header(), expected);
+        assert!(cart.rom() == rom);
+        assert!(cart.file_bytes()[512..] == interleaved);
+    }
+    // a header without any identification is stripped as well
+    let file = [&[0x55; 512][..], &interleaved].concat();
+    let cart = crate::test_util::create_cartridge(&file);
+    assert_eq!(cart.copier_header(), None);
+    assert!(cart.rom() == rom);
+    // the mapping in a copier header is trusted over the one in the ROM
+    for (header, expected) in copier_headers(false) {
+        let file = [&header[..], &interleaved].concat();
+        let cart = crate::test_util::create_cartridge(&file);
+        assert_eq!(cart.copier_header(), expected);
+        if expected == Some(CopierHeader::GameDoctor) {
+            assert!(cart.rom() == rom);
+        } else {
+            assert!(cart.rom() == interleaved);
+        }
+    }
+}
+
+#[test]
+pub fn test_deinterleave_game_doctor_24() {
+    use compat::CompatReport;
+    // 48 banks, whose lower halves are numbered
+    let rom = create_rom(0x300000, HIROM, 0, 0, |rom| {
+        for (i, half) in rom.chunks_mut(0x8000).enumerate().skip(2).step_by(2) {
+            half.fill(i as u8)
+        }
+    });
+    let (upper, lower): (Vec<&[u8]>, Vec<&[u8]>) = rom
+        .chunks(0x10000)
+        .map(|bank| (&bank[0x8000..], &bank[..0x8000]))
+        .unzip();
+    // the lower halves of the banks $20-$2f come first
+    let file = [
+        &copier_headers(true)[2].0[..],
+        &upper.concat(),
+        &lower[0x20..].concat(),
+        &lower[..0x20].concat(),
+    ]
+    .concat();
+    let report = CompatReport::new(&file).unwrap();
+    assert_eq!(
+        report.requirements[1].note,
+        Some("de-interleaved in the 24 Mbit layout of the Game Doctor")
+    );
+    let cart = crate::test_util::create_cartridge(&file);
+    assert_eq!(cart.copier_header(), Some(CopierHeader::GameDoctor));
+    assert!(cart.rom()[..0x300000] == rom);
+    assert!(cart.file_bytes()[512..] == file[512..]);
+    assert_eq!(cart.file_offset(0x00123), Some(512 + 0x200123));
+    assert_eq!(cart.file_offset(0x2f0123), Some(512 + 0x1f8123));
+    assert_eq!(cart.file_offset(0x2f8123), Some(512 + 0x178123));
+
+    // without the header of the Game Doctor, the layout of the halves is
+    // assumed
+    let mut file = file;
+    file[..16].fill(0);
+    let cart = crate::test_util::create_cartridge(&file);
+    assert_eq!(cart.copier_header(), None);
+    assert!(cart.rom()[..0x300000] != rom);
+}
+
+#[test]
+pub fn test_dsp_firmware() {
+    use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+    let rom = create_rom_file(false, 0x03, 0);
+    let mut cart = crate::test_util::create_cartridge(&rom);
+    let dsp = cart.dsp_mut().unwrap();
+    assert_eq!(dsp.version().firmware_name(), "dsp1b");
+    // the program ROM alone isn't enough
+    assert!(!dsp.load_firmware(&[0; 0x1800]));
+    assert!(dsp.load_firmware(&[0; Dsp::FIRMWARE_SIZE]));
+
+    // the version survives a save state
+    for ver in [DspVersion::Dsp1, DspVersion::Dsp1B, DspVersion::Dsp4] {
+        let mut state = SaveStateSerializer { data: vec![] };
+        ver.serialize(&mut state);
+        let mut loaded = DspVersion::Dsp2;
+        loaded.deserialize(&mut SaveStateDeserializer {
+            data: state.data.iter(),
+        });
+        assert_eq!(loaded.name(), ver.name());
+    }
+}
+
+#[test]
+pub fn test_padded_rom_poke() {
+    // 96 KiB padded to the 128 KiB in the header, the last 32 KiB twice
+    let rom = create_rom(0x18000, LOROM, 0, 0, |_| ());
+    let mut cart = crate::test_util::create_cartridge(&rom);
+    assert_eq!(cart.rom().len(), 0x20000);
+    assert_eq!(cart.rom_copies(0x18123), [0x10123, 0x18123]);
+    assert_eq!(cart.rom_copies(0x8123), [0x8123]);
+    assert!(cart.poke(Addr24::new(0x03, 0x8123), 0x42));
+    assert_eq!(cart.read_byte(Addr24::new(0x02, 0x8123)), Some(0x42));
+    assert_eq!(cart.read_byte(Addr24::new(0x03, 0x8123)), Some(0x42));
+}
+
+#[test]
+pub fn test_mirrors() {
+    let rom = create_rom(0x18000, LOROM, 0, 3, |_| ());
+    let device = crate::test_util::create_device(&rom);
+    for addr in [
+        Addr24::new(0x00, 0x0123),
+        Addr24::new(0x80, 0x2118),
+        Addr24::new(0x00, 0x4200),
+        Addr24::new(0x03, 0x8123),
+        Addr24::new(0x70, 0x0456),
+    ] {
+        let targets: Vec<MemoryRegion> = match device.region(addr) {
+            MemoryRegion::Rom(offset) => {
+                let cart = device.cartridge().unwrap();
+                cart.rom_copies(offset)
+                    .into_iter()
+                    .map(MemoryRegion::Rom)
+                    .collect()
+            }
+            region => vec![region],
+        };
+        // all memories of this cartridge are mirrored in blocks of at least
+        // 256 bytes, so only addresses with the same lowest byte are searched
+        let expected: Vec<Addr24> = (0..=0xffffu32)
+            .map(|i| Addr24::new((i >> 8) as u8, (i << 8) as u16 | addr.addr & 0xff))
+            .filter(|&addr| targets.contains(&device.region(addr)))
+            .collect();
+        assert!(expected.len() > 1);
+        assert_eq!(device.mirrors(addr), expected);
+    }
+}
+
+#[test]
+pub fn test_file_offset() {
+    let mut rom = create_rom_file(true, 0, 0);
+    rom.extend((0..0x10000).map(|i| (i >> 8) as u8));
+    // an interleaved file with a copier header
+    let file = [
+        &[0; 512][..],
+        &rom[0x8000..0x10000],
+        &rom[0x18000..],
+        &rom[..0x8000],
+        &rom[0x10000..0x18000],
+    ]
+    .concat();
+    let mut cart = crate::test_util::create_cartridge(&file);
+    assert!(cart.file_bytes() == file);
+    assert_eq!(cart.file_offset(0x18123), Some(512 + 0x8123));
+    assert_eq!(cart.file_offset(0x00123), Some(512 + 0x10123));
+    // the patch changes the same byte in the file
+    assert!(cart.poke(Addr24::new(0xc1, 0x8123), 0x42));
+    let patch = crate::patch::create_ips(&cart.file_bytes(), cart.file_offset(0x18123));
+    let mut patched = file.clone();
+    crate::patch::apply_ips(&mut patched, &patch).unwrap();
+    assert_eq!(patched.len(), file.len());
+    assert_eq!(patched[512 + 0x8123], 0x42);
+
+    // the padding of a 96 KiB ROM has no place in the file
+    let cart = crate::test_util::create_cartridge(&create_rom(0x18000, LOROM, 0, 0, |_| ()));
+    assert_eq!(cart.file_bytes().len(), 0x18000);
+    assert_eq!(cart.file_offset(0x10123), Some(0x10123));
+    assert_eq!(cart.file_offset(0x18123), Some(0x10123));
+}
@@ -0,0 +1,299 @@
+//! Cheat codes
+//!
+//! Game Genie and Pro Action Replay codes both replace a single byte at an
+//! address of the main CPU (see [`Code`]). What a cheat does depends on the
+//! memory the address is decoded to:
+//!
+//! - a byte of the ROM is patched, like the Game Genie does
+//! - writes to a byte of the work RAM or of the save memory are replaced by
+//!   the value at the bus, like the Pro Action Replay does. The value is also
+//!   written, when the cheat is enabled and at the start of every frame, so
+//!   it holds after a save state was loaded, too.
+//!
+//! Cheats are added with [`Device::add_cheat`] and can be toggled at runtime
+//! with [`Device::set_cheat`]. They are refused in integrity mode and removed,
+//! when another cartridge is loaded.
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    device::{Addr24, Data, Device, MemoryRegion},
+    integrity::{IntegrityError, Restricted},
+};
+
+/// The letters of Game Genie codes in the order of the hexadecimal digits
+/// they stand for
+const GAME_GENIE_LETTERS: &[u8; 16] = b"DF4709156BC8A23E";
+
+/// How the bits of the address in a Game Genie code are moved to get the
+/// address, as the mask of the bits in the code and the count of bits they
+/// are shifted to the left
+static GAME_GENIE_ADDR_BITS: [(u32, i32); 7] = [
+    (0x003c00, 10),
+    (0x00003c, 14),
+    (0xf00000, -8),
+    (0x000003, 10),
+    (0x00c000, -6),
+    (0x0f0000, -12),
+    (0x0003c0, -6),
+];
+
+const fn shift_left(value: u32, count: i32) -> u32 {
+    if count >= 0 {
+        value << count
+    } else {
+        value >> -count
+    }
+}
+
+/// How a [`Code`] is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `XXXX-XXXX` with the letters `0-9`, `A-F` of the Game Genie, which
+    /// encode the value and a scrambled address
+    GameGenie,
+    /// `AAAAAAVV`, the address followed by the value in hexadecimal
+    ProActionReplay,
+    /// `AAAAAA:VV`, the address and the value in hexadecimal
+    Raw,
+}
+
+/// A cheat code, which replaces the byte at `addr` with `value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Code {
+    pub addr: Addr24,
+    pub value: u8,
+    /// The format the code was written in, which is kept when it's displayed
+    pub format: Format,
+}
+
+impl Code {
+    /// Parse a code in any [`Format`]. The letters are case-insensitive.
+    pub fn parse(text: &str) -> Result<Self, CheatError> {
+        let invalid = || CheatError::InvalidCode(text.to_string());
+        let hex = |digits: &str| {
+            (digits.bytes().all(|c| c.is_ascii_hexdigit()))
+                .then(|| u32::from_str_radix(digits, 16).ok())
+                .flatten()
+                .ok_or_else(invalid)
+        };
+        let text = text.trim();
+        let (raw, format) = if let Some((left, right)) = text.split_once('-') {
+            if left.len() != 4 || right.len() != 4 {
+                return Err(invalid());
+            }
+            // translate the letters to hexadecimal digits first
+            let mut raw = 0;
+            for letter in left.bytes().chain(right.bytes()) {
+                let letter = letter.to_ascii_uppercase();
+                let digit = GAME_GENIE_LETTERS
+                    .iter()
+                    .position(|&c| c == letter)
+                    .ok_or_else(invalid)?;
+                raw = (raw << 4) | digit as u32;
+            }
+            let addr = GAME_GENIE_ADDR_BITS.iter().fold(0, |addr, &(mask, count)| {
+                addr | shift_left(raw & mask, count)
+            });
+            ((addr << 8) | (raw >> 24), Format::GameGenie)
+        } else if let Some((addr, value)) = text.split_once(':') {
+            if addr.len() != 6 || value.len() != 2 {
+                return Err(invalid());
+            }
+            ((hex(addr)? << 8) | hex(value)?, Format::Raw)
+        } else if text.len() == 8 {
+            (hex(text)?, Format::ProActionReplay)
+        } else {
+            return Err(invalid());
+        };
+        Ok(Self {
+            addr: Addr24::new((raw >> 24) as u8, (raw >> 8) as u16),
+            value: raw as u8,
+            format,
+        })
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (bank, addr, value) = (self.addr.bank, self.addr.addr, self.value);
+        match self.format {
+            Format::GameGenie => {
+                let addr = (u32::from(bank) << 16) | u32::from(addr);
+                let scrambled = GAME_GENIE_ADDR_BITS.iter().fold(0, |code, &(mask, count)| {
+                    code | shift_left(addr & shift_left(mask, count), -count)
+                });
+                let raw = (u32::from(value) << 24) | scrambled;
+                let letters: String = (0..8)
+                    .rev()
+                    .map(|i| char::from(GAME_GENIE_LETTERS[((raw >> (i * 4)) & 0xf) as usize]))
+                    .collect();
+                write!(f, "{}-{}", &letters[..4], &letters[4..])
+            }
+            Format::ProActionReplay => write!(f, "{bank:02X}{addr:04X}{value:02X}"),
+            Format::Raw => write!(f, "{bank:02X}{addr:04X}:{value:02X}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    InvalidCode(String),
+    /// The address of the code is neither decoded to ROM nor to RAM
+    NotMemory(Addr24),
+    /// There's no cheat with this index
+    NoCheat(usize),
+    Integrity(IntegrityError),
+}
+
+impl std::fmt::Display for CheatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidCode(code) => write!(
+                f,
+                "invalid code `{}` (expected `XXXX-XXXX`, `AAAAAAVV` or `AAAAAA:VV`)",
+                code
+            ),
+            Self::NotMemory(addr) => write!(f, "{} is neither ROM nor RAM", addr),
+            Self::NoCheat(index) => write!(f, "there's no cheat {}", index),
+            Self::Integrity(err) => err.fmt(f),
+        }
+    }
+}
+
+impl From<IntegrityError> for CheatError {
+    fn from(err: IntegrityError) -> Self {
+        Self::Integrity(err)
+    }
+}
+
+/// A cheat of the loaded game
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    pub code: Code,
+    pub description: String,
+    pub enabled: bool,
+    /// The memory `code.addr` is decoded to
+    region: MemoryRegion,
+    /// The ROM byte replaced by the enabled cheat
+    original: u8,
+}
+
+/// The cheats of the loaded game
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+    /// The regions and values of the enabled cheats of RAM
+    ram: Vec<(MemoryRegion, u8)>,
+}
+
+impl Cheats {
+    /// Whether writes have to be passed through [`Device::intercept_write`]
+    pub(crate) fn intercepts_writes(&self) -> bool {
+        !self.ram.is_empty()
+    }
+}
+
+impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
+    /// The cheats of the loaded game
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats.list
+    }
+
+    /// Add a cheat and return its index
+    pub fn add_cheat(
+        &mut self,
+        code: Code,
+        description: String,
+        enabled: bool,
+    ) -> Result<usize, CheatError> {
+        self.check_integrity(Restricted::Cheats)?;
+        let region = match self.region(code.addr) {
+            region @ (MemoryRegion::Rom(_) | MemoryRegion::Wram(_) | MemoryRegion::Sram(_)) => {
+                region
+            }
+            _ => return Err(CheatError::NotMemory(code.addr)),
+        };
+        self.cheats.list.push(Cheat {
+            code,
+            description,
+            enabled: false,
+            region,
+            original: 0,
+        });
+        let index = self.cheats.list.len() - 1;
+        self.set_cheat(index, enabled)?;
+        Ok(index)
+    }
+
+    /// Enable or disable the cheat with the index `index`
+    pub fn set_cheat(&mut self, index: usize, enabled: bool) -> Result<(), CheatError> {
+        if enabled {
+            self.check_integrity(Restricted::Cheats)?;
+        }
+        let cheat = self
+            .cheats
+            .list
+            .get_mut(index)
+            .ok_or(CheatError::NoCheat(index))?;
+        if cheat.enabled == enabled {
+            return Ok(());
+        }
+        cheat.enabled = enabled;
+        if let MemoryRegion::Rom(offset) = cheat.region {
+            if let Some(cart) = &mut self.cartridge {
+                let rom = cart.rom_mut();
+                if enabled {
+                    cheat.original = rom[offset];
+                    rom[offset] = cheat.code.value;
+                } else {
+                    rom[offset] = cheat.original;
+                }
+            }
+        }
+        self.cheats.ram = (self.cheats.list.iter())
+            .filter(|cheat| cheat.enabled && !matches!(cheat.region, MemoryRegion::Rom(_)))
+            .map(|cheat| (cheat.region, cheat.code.value))
+            .collect();
+        self.apply_ram_cheats();
+        Ok(())
+    }
+
+    /// Disable and remove the cheat with the index `index`. The indices of
+    /// the following cheats decrease by one.
+    pub fn remove_cheat(&mut self, index: usize) -> Result<Cheat, CheatError> {
+        self.set_cheat(index, false)?;
+        Ok(self.cheats.list.remove(index))
+    }
+
+    /// Disable and remove all cheats
+    pub fn clear_cheats(&mut self) {
+        while self.remove_cheat(0).is_ok() {}
+    }
+
+    /// Write the values of the enabled cheats of RAM, which differ
+    pub(crate) fn apply_ram_cheats(&mut self) {
+        for i in 0..self.cheats.list.len() {
+            let Cheat { code, enabled, .. } = self.cheats.list[i];
+            let in_ram = !matches!(self.cheats.list[i].region, MemoryRegion::Rom(_));
+            if enabled && in_ram && self.peek(code.addr) != Some(code.value) {
+                self.poke(code.addr, code.value);
+            }
+        }
+    }
+
+    /// Replace the bytes of a write to the RAM of enabled cheats by their
+    /// values
+    pub(crate) fn intercept_write<D: Data>(&self, addr: Addr24, value: D) -> D {
+        let mut bytes = value.to_bytes();
+        for (i, byte) in bytes.as_mut().iter_mut().enumerate() {
+            let region = self.region(Addr24::new(addr.bank, addr.addr.wrapping_add(i as u16)));
+            if let Some(&(_, value)) = self.cheats.ram.iter().find(|(r, _)| *r == region) {
+                *byte = value
+            }
+        }
+        D::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,135 @@
+use super::*;
+use crate::test_util::{create_device, create_rom, TestDevice, LOROM};
+
+fn create_test_device() -> Box<TestDevice> {
+    create_device(&create_rom(0x8000, LOROM, 0, 0, |_| ()))
+}
+
+#[test]
+pub fn test_parse_codes() {
+    let code = Code::parse("7e0dbe05").unwrap();
+    assert_eq!(code.addr, Addr24::new(0x7e, 0x0dbe));
+    assert_eq!(code.value, 0x05);
+    assert_eq!(code.format, Format::ProActionReplay);
+    assert_eq!(code.to_string(), "7E0DBE05");
+
+    let code = Code::parse("80C123:EA").unwrap();
+    assert_eq!(code.addr, Addr24::new(0x80, 0xc123));
+    assert_eq!(code.format, Format::Raw);
+    assert_eq!(code.to_string(), "80C123:EA");
+
+    // the value is `AD`, the lowest two bits of the address in the code
+    // are bits 10 and 11 of the address
+    let code = Code::parse("c2dd-dddf").unwrap();
+    assert_eq!(code.addr, Addr24::new(0x00, 0x0400));
+    assert_eq!(code.value, 0xad);
+    assert_eq!(code.format, Format::GameGenie);
+    assert_eq!(code.to_string(), "C2DD-DDDF");
+
+    for text in [
+        "",
+        "7e0dbe0",
+        "7e0dbe0g",
+        "0dbe:05",
+        "C2DD-DDDG",
+        "C2DD-DDDDD",
+    ] {
+        assert_eq!(
+            Code::parse(text),
+            Err(CheatError::InvalidCode(text.to_string()))
+        );
+    }
+}
+
+#[test]
+pub fn test_game_genie_round_trip() {
+    for addr in [0x000000, 0x008000, 0x7e1234, 0xc0ffee, 0xffffff, 0x123456] {
+        let code = Code {
+            addr: Addr24::new((addr >> 16) as u8, addr as u16),
+            value: 0x5a,
+            format: Format::GameGenie,
+        };
+        assert_eq!(Code::parse(&code.to_string()), Ok(code));
+    }
+}
+
+#[test]
+pub fn test_rom_cheat() {
+    let mut device = create_test_device();
+    let code = Code::parse("808001:EA").unwrap();
+    assert_eq!(device.add_cheat(code, "nop".to_string(), true), Ok(0));
+    assert_eq!(device.cartridge().unwrap().rom()[..3], [0, 0xea, 0]);
+    assert_eq!(device.read::<u8>(Addr24::new(0x00, 0x8001)), 0xea);
+    device.set_cheat(0, false).unwrap();
+    assert_eq!(device.cartridge().unwrap().rom()[..3], [0; 3]);
+    assert_eq!(device.set_cheat(1, true), Err(CheatError::NoCheat(1)));
+    let code = Code::parse("002100:00").unwrap();
+    assert_eq!(
+        device.add_cheat(code, String::new(), true),
+        Err(CheatError::NotMemory(code.addr))
+    );
+}
+
+#[test]
+pub fn test_ram_cheat() {
+    let mut device = create_test_device();
+    let lives = Addr24::new(0x7e, 0x0dbe);
+    device.write::<u8>(lives, 1);
+    device
+        .add_cheat(Code::parse("7E0DBE09").unwrap(), String::new(), true)
+        .unwrap();
+    // the value is written at once and writes through any mirror are
+    // replaced by it, also those of the second byte of a word
+    assert_eq!(device.peek(lives), Some(9));
+    device.write::<u8>(Addr24::new(0x00, 0x0dbe), 2);
+    device.write::<u16>(Addr24::new(0x7e, 0x0dbd), 0x0303);
+    assert_eq!(device.peek(lives), Some(9));
+    assert_eq!(device.peek(Addr24::new(0x7e, 0x0dbd)), Some(3));
+    device.remove_cheat(0).unwrap();
+    assert!(device.cheats().is_empty());
+    device.write::<u8>(lives, 2);
+    assert_eq!(device.peek(lives), Some(2));
+}
+
+#[test]
+pub fn test_cheats_in_integrity_mode() {
+    let mut device = create_test_device();
+    device
+        .add_cheat(Code::parse("808000:EA").unwrap(), String::new(), true)
+        .unwrap();
+    device.enable_integrity_mode();
+    // the cheats are removed
+    assert!(device.cheats().is_empty());
+    assert_eq!(device.cartridge().unwrap().rom()[0], 0);
+    assert_eq!(
+        device.add_cheat(Code::parse("808000:EA").unwrap(), String::new(), true),
+        Err(CheatError::Integrity(IntegrityError(Restricted::Cheats)))
+    );
+    // memory writes from outside of the emulation are refused as well
+    let addr = Addr24::new(0x7e, 0x0100);
+    assert!(!device.poke(addr, 0x42));
+    assert!(!device.write_data::<u8>(addr, 0x42));
+    assert_eq!(device.peek(addr), Some(0));
+    use save_state::InSaveState;
+    let mut state = save_state::SaveStateSerializer { data: vec![] };
+    device.serialize(&mut state);
+    assert_eq!(
+        device.load_state(&mut save_state::SaveStateDeserializer {
+            data: state.data.iter()
+        }),
+        Err(IntegrityError(Restricted::LoadState))
+    );
+}
+
+#[test]
+#[should_panic(expected = "loading save states is disabled in integrity mode")]
+pub fn test_deserialize_in_integrity_mode() {
+    use save_state::InSaveState;
+    let mut device = create_test_device();
+    let mut state = save_state::SaveStateSerializer { data: vec![] };
+    device.serialize(&mut state);
+    device.enable_integrity_mode();
+    device.deserialize(&mut save_state::SaveStateDeserializer {
+        data: state.data.iter(),
+    });
+}
@@ -0,0 +1,142 @@
+//! The clocks of the components, derived from the master clock
+//!
+//! All frequencies are exact fractions, so the cycles of a component can be
+//! derived from the master cycles by a [`ClockDivider`] without any rounding
+//! errors. The remainder is carried over, so the components can't drift apart,
+//! no matter how long the emulation runs.
+//!
+//! # Literature
+//!
+//! - <https://wiki.superfamicom.org/timing>
+//! - <https://problemkaputt.de/fullsnes.htm#snestimings>
+
+use crate::timing::Cycles;
+use core::time::Duration;
+use save_state_macro::*;
+
+/// A frequency in Hz as the fraction `num / den`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frequency {
+    num: u64,
+    den: u64,
+}
+
+impl Frequency {
+    pub const fn new(num: u64, den: u64) -> Self {
+        assert!(num > 0 && den > 0, "frequencies must be positive");
+        Self { num, den }
+    }
+
+    pub const fn from_hz(hz: u64) -> Self {
+        Self::new(hz, 1)
+    }
+
+    /// The frequency divided by `divisor`
+    pub const fn divide(self, divisor: u64) -> Self {
+        Self::new(self.num, self.den * divisor)
+    }
+
+    /// The frequency in Hz as the fraction `(num, den)`
+    pub const fn fraction(self) -> (u64, u64) {
+        (self.num, self.den)
+    }
+
+    pub fn as_hz(self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// The duration of `cycles` cycles, rounded down to nanoseconds
+    pub fn cycles_to_duration(self, cycles: u64) -> Duration {
+        let nanos = u128::from(cycles) * u128::from(self.den) * 1_000_000_000;
+        Duration::from_nanos((nanos / u128::from(self.num)) as u64)
+    }
+}
+
+/// 945/44 MHz, six times the NTSC color subcarrier
+pub const MASTER_NTSC: Frequency = Frequency::new(236_250_000, 11);
+
+/// 21.28137 MHz
+pub const MASTER_PAL: Frequency = Frequency::from_hz(21_281_370);
+
+/// The SPC700 and S-DSP are driven by their own 24.576 MHz oscillator,
+/// which is divided by 24
+pub const APU: Frequency = Frequency::from_hz(24_576_000).divide(24);
+
+/// The NEC µPD7725 in DSP-1 to DSP-4 cartridges
+pub const NECDSP: Frequency = Frequency::from_hz(8_192_000);
+
+/// The master clock of the region
+pub const fn master(is_pal: bool) -> Frequency {
+    if is_pal {
+        MASTER_PAL
+    } else {
+        MASTER_NTSC
+    }
+}
+
+/// The SA-1 CPU runs at half the master clock, i.e. 10.74 MHz for NTSC
+pub const fn sa1(is_pal: bool) -> Frequency {
+    master(is_pal).divide(2)
+}
+
+/// The SuperFX runs at the master clock or at half of it, depending on
+/// its clock select register
+pub const fn superfx(is_pal: bool, fast: bool) -> Frequency {
+    if fast {
+        master(is_pal)
+    } else {
+        master(is_pal).divide(2)
+    }
+}
+
+/// The PPU outputs a dot every four master cycles
+pub const fn dot_clock(is_pal: bool) -> Frequency {
+    master(is_pal).divide(4)
+}
+
+const fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b)
+    }
+    a
+}
+
+/// Converts master cycles into the cycles of a slower clock
+#[derive(Debug, Clone, InSaveState)]
+pub struct ClockDivider {
+    /// `master_cycles` master cycles are as long as `cycles` cycles
+    master_cycles: u64,
+    cycles: u64,
+    /// The master cycles, which weren't converted yet, multiplied by `cycles`
+    pending: u64,
+}
+
+impl ClockDivider {
+    pub const fn new(master: Frequency, clock: Frequency) -> Self {
+        let master_cycles = master.num * clock.den;
+        let cycles = master.den * clock.num;
+        let div = gcd(master_cycles, cycles);
+        Self {
+            master_cycles: master_cycles / div,
+            cycles: cycles / div,
+            pending: 0,
+        }
+    }
+
+    /// The ratio of master cycles to cycles as a reduced fraction
+    pub const fn ratio(&self) -> (u64, u64) {
+        (self.master_cycles, self.cycles)
+    }
+
+    /// Advance by `n` master cycles
+    pub fn tick(&mut self, n: Cycles) {
+        self.pending += u64::from(n) * self.cycles
+    }
+
+    /// The count of cycles, which elapsed since the last call
+    pub fn take(&mut self) -> Cycles {
+        let cycles = self.pending / self.master_cycles;
+        self.pending %= self.master_cycles;
+        cycles as Cycles
+    }
+}
@@ -1,4 +1,4 @@
-use core::{cell::Cell, mem::replace};
+use core::mem::replace;
 use save_state_macro::*;
 
 pub mod buttons {
@@ -24,65 +24,49 @@ pub enum Controller {
 }
 
 impl Controller {
-    pub fn poll_bit_data1(&self) -> bool {
+    /// Latch the serial data, which is shifted out after a strobe, LSB first.
+    /// The bits after the last one read as 1, except without a controller.
+    fn latch(&mut self) -> u32 {
         match self {
-            Self::None => false,
-            Self::Standard(StandardController { shift_register, .. }) => {
-                shift_register.get() & 1 > 0
-            }
-            Self::Mouse(Mouse { shift_register, .. }) => shift_register.get() & 1 > 0,
-        }
-    }
-
-    pub fn poll_bit_data2(&self) -> bool {
-        match self {
-            Self::None | Self::Standard(_) | Self::Mouse(_) => false,
-        }
-    }
-
-    pub fn on_strobe(&mut self) {
-        match self {
-            Self::Standard(cntrl) => cntrl.shift_register.set(cntrl.pressed_buttons),
+            Self::None => 0,
+            Self::Standard(cntrl) => 0xffff_0000 | u32::from(cntrl.pressed_buttons),
             Self::Mouse(mouse) => {
                 let [dx, dy] = mouse.internal_offset.map(|i| i.clamp(-0x7f, 0x7f));
                 mouse.internal_offset[0] = mouse.internal_offset[0].wrapping_sub(dx);
                 mouse.internal_offset[1] = mouse.internal_offset[1].wrapping_sub(dy);
-                let [dx, dy] =
-                    [dx, dy].map(|v| ((v.abs() as u8).reverse_bits() << 1) | (v < 0) as u8);
-                mouse.shift_register.set(
-                    0x8000
-                        | ((mouse.right_button as u32) << 8)
-                        | ((mouse.left_button as u32) << 9)
-                        | ((mouse.speed as u32) << 10)
-                        | ((dy as u32) << 16)
-                        | ((dx as u32) << 24),
-                );
+                // the sign is followed by the distance, MSB first
+                let [dx, dy] = [dx, dy]
+                    .map(|v| (v.unsigned_abs().min(0x7f) as u8).reverse_bits() | (v < 0) as u8);
+                0x8000
+                    | ((mouse.right_button as u32) << 8)
+                    | ((mouse.left_button as u32) << 9)
+                    | ((mouse.speed as u32) << 10)
+                    | ((dy as u32) << 16)
+                    | ((dx as u32) << 24)
             }
-            Self::None => (),
         }
     }
 
-    pub fn on_clock(&self) {
+    /// Whether the controller lets pin 6 of its port float high. The pin is
+    /// the programmable I/O line of the port (WRIO/RDIO), an open collector
+    /// line, which is low if either the console or the controller pulls it
+    /// low. None of the emulated controllers drive it. A light gun, like the
+    /// Super Scope, pulls the line of port 2 low, when the beam passes the
+    /// position it points at (see
+    /// [`Device::pulse_io_line`](crate::device::Device::pulse_io_line)).
+    fn io_line_high(&self) -> bool {
         match self {
-            Self::None => (),
-            Self::Standard(StandardController { shift_register, .. }) => {
-                shift_register.set((shift_register.get() >> 1) | 0x8000)
-            }
-            Self::Mouse(Mouse { shift_register, .. }) => {
-                shift_register.set((shift_register.get() >> 1) | 0x8000_0000)
-            }
+            Self::None | Self::Standard(_) | Self::Mouse(_) => true,
         }
     }
 
-    pub fn on_strobe_clock(&mut self) {
-        match self {
-            Self::Mouse(mouse) => {
-                mouse.speed += 1;
-                if mouse.speed >= 3 {
-                    mouse.speed = 0;
-                }
+    /// Called for every clock while the strobe is set
+    fn on_strobe_clock(&mut self) {
+        if let Self::Mouse(mouse) = self {
+            mouse.speed += 1;
+            if mouse.speed >= 3 {
+                mouse.speed = 0;
             }
-            _ => (),
         }
     }
 }
@@ -124,7 +108,6 @@ impl save_state::InSaveState for Controller {
 
 #[derive(Debug, Clone, Default, InSaveState)]
 pub struct Mouse {
-    shift_register: Cell<u32>,
     speed: u8,
     pub left_button: bool,
     pub right_button: bool,
@@ -148,23 +131,30 @@ impl Mouse {
 /// L,R,Start,Select buttons
 #[derive(Debug, Default, Clone, InSaveState)]
 pub struct StandardController {
-    shift_register: Cell<u16>,
     pub pressed_buttons: u16,
 }
 
 impl StandardController {
     pub const fn new() -> Self {
-        Self {
-            shift_register: Cell::new(0),
-            pressed_buttons: 0,
-        }
+        Self { pressed_buttons: 0 }
     }
 }
 
 #[derive(Debug, Clone, InSaveState)]
 pub struct ControllerPort {
     pub controller: Controller,
+    /// The controller as seen by the console in deterministic mode, sampled
+    /// from `controller` at the start of every frame (see
+    /// [`crate::deterministic`]). It's sampled again after loading a state.
+    #[except((|_v, _s| ()), (|v: &mut Option<Controller>, _s| *v = None))]
+    sampled: Option<Controller>,
     strobe: bool,
+    /// The serial data latched by the last strobe, LSB first
+    latched: u32,
+    /// The count of bits read since the strobe was cleared.
+    /// Both are part of save states, so a state can be loaded in the middle
+    /// of reading the controller, independent of the currently pressed buttons.
+    position: u8,
     data1: u16,
     data2: u16,
 }
@@ -173,26 +163,93 @@ impl ControllerPort {
     pub const fn new(controller: Controller) -> Self {
         Self {
             controller,
+            sampled: None,
             strobe: false,
+            latched: 0,
+            position: 0,
             data1: 0,
             data2: 0,
         }
     }
 
+    /// The controller as seen by the console
+    fn console_controller(&mut self) -> &mut Controller {
+        self.sampled.as_mut().unwrap_or(&mut self.controller)
+    }
+
+    /// Let the console see the current state of `controller` from now on,
+    /// until it's sampled again
+    fn sample(&mut self) {
+        self.sampled = Some(match (self.sampled.take(), &mut self.controller) {
+            (Some(Controller::Mouse(mut sampled)), Controller::Mouse(mouse)) => {
+                // the movement accumulates until the console reads it
+                sampled.left_button = mouse.left_button;
+                sampled.right_button = mouse.right_button;
+                for (sampled, offset) in sampled
+                    .internal_offset
+                    .iter_mut()
+                    .zip(&mut mouse.internal_offset)
+                {
+                    *sampled = sampled.saturating_add(core::mem::take(offset))
+                }
+                Controller::Mouse(sampled)
+            }
+            (_, Controller::Mouse(mouse)) => {
+                let sampled = mouse.clone();
+                mouse.internal_offset = [0; 2];
+                Controller::Mouse(sampled)
+            }
+            (_, controller) => controller.clone(),
+        })
+    }
+
     pub fn set_strobe(&mut self, bit: bool) {
-        if !replace(&mut self.strobe, bit) && bit {
-            self.controller.on_strobe()
+        let was_set = replace(&mut self.strobe, bit);
+        if !was_set && bit {
+            self.latched = self.console_controller().latch();
+            self.position = 0
+        } else if was_set && !bit {
+            self.latch_buttons()
+        }
+    }
+
+    /// The buttons of a standard controller are latched as long as the
+    /// strobe is set
+    fn latch_buttons(&mut self) {
+        let controller = self.console_controller();
+        if let Controller::Standard(_) = controller {
+            let latched = controller.latch();
+            self.latched = latched
+        }
+    }
+
+    /// The serial data bit at `position` after the last strobe
+    pub fn serial_bit(&self, position: u8) -> bool {
+        match self.sampled.as_ref().unwrap_or(&self.controller) {
+            Controller::None => false,
+            _ => self
+                .latched
+                .checked_shr(position.into())
+                .is_none_or(|v| v & 1 > 0),
         }
     }
 
     pub fn read_port_data(&mut self) -> u8 {
-        let bit1 = self.controller.poll_bit_data1();
-        let bit2 = self.controller.poll_bit_data2();
-        if !self.strobe {
+        let bit = if self.strobe {
+            // while the strobe is set, the first bit is output on every clock
+            self.latch_buttons();
+            // the speed of a mouse is kept in both, as it's set by the console
             self.controller.on_strobe_clock();
-        }
-        self.controller.on_clock();
-        (bit1 as u8) | ((bit2 as u8) << 1)
+            if let Some(sampled) = &mut self.sampled {
+                sampled.on_strobe_clock()
+            }
+            self.serial_bit(0)
+        } else {
+            let bit = self.serial_bit(self.position);
+            self.position = self.position.saturating_add(1);
+            bit
+        };
+        bit as u8
     }
 }
 
@@ -200,8 +257,12 @@ impl ControllerPort {
 pub struct ControllerPorts {
     pub port1: ControllerPort,
     pub port2: ControllerPort,
+    /// The value written to WRIO, a cleared bit pulls its line low
     pio: u8,
     pub(crate) auto_joypad_timer: u16,
+    /// The count of latches since power-on, it isn't part of save states
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    latch_count: u64,
 }
 
 impl ControllerPorts {
@@ -209,30 +270,70 @@ impl ControllerPorts {
         Self {
             port1: ControllerPort::new(Controller::Standard(StandardController::new())),
             port2: ControllerPort::new(Controller::None),
-            pio: 0,
+            // the lines float high after a reset
+            pio: 0xff,
             auto_joypad_timer: 0,
+            latch_count: 0,
         }
     }
 
-    /// Write to the programmable I/O-port.
-    /// Returns if EXTLATCH shall be triggered.
+    /// Write to the programmable I/O-port (WRIO).
+    /// Returns if EXTLATCH shall be triggered, which is connected to the line
+    /// of port 2 and latches the PPU counters on its falling edge.
     pub fn set_pio(&mut self, val: u8) -> bool {
-        (replace(&mut self.pio, val) & !val) & 0x80 > 0
+        let lines = self.io_lines();
+        self.pio = val;
+        (lines & !self.io_lines()) & 0x80 > 0
     }
 
     pub const fn get_pio(&self) -> u8 {
         self.pio
     }
 
+    /// The levels of the programmable I/O lines as read from RDIO. Bit 6 is
+    /// pin 6 of port 1 and bit 7 pin 6 of port 2, they are low if WRIO or the
+    /// controller pulls them low. The other bits aren't connected and read
+    /// back as written, which some copy protection checks rely on.
+    pub fn io_lines(&self) -> u8 {
+        let mut lines = self.pio;
+        if !self.port1.controller.io_line_high() {
+            lines &= !0x40
+        }
+        if !self.port2.controller.io_line_high() {
+            lines &= !0x80
+        }
+        lines
+    }
+
     pub fn set_strobe(&mut self, bit: bool) {
+        if bit && !self.port1.strobe {
+            self.latch_count += 1
+        }
         self.port1.set_strobe(bit);
         self.port2.set_strobe(bit);
     }
 
+    /// Sample the controllers of both ports for the deterministic mode
+    pub(crate) fn sample(&mut self) {
+        self.port1.sample();
+        self.port2.sample();
+    }
+
+    /// How often the controllers were latched, by the strobe of `$4016` or
+    /// by the automatic joypad read. Frames, in which it doesn't change, are
+    /// lag frames: the game didn't read the controllers.
+    pub const fn latch_count(&self) -> u64 {
+        self.latch_count
+    }
+
     pub fn auto_joypad(&mut self) {
+        self.latch_count += 1;
         for port in [&mut self.port1, &mut self.port2] {
+            // the latch is pulsed, so the strobe is cleared while reading
+            let strobe = port.strobe;
             port.set_strobe(false);
             port.set_strobe(true);
+            port.set_strobe(false);
             port.data1 = 0;
             port.data2 = 0;
             for _ in 0..16 {
@@ -242,6 +343,7 @@ impl ControllerPorts {
                 port.data1 |= u16::from(data & 1);
                 port.data2 |= u16::from(data >> 1);
             }
+            port.set_strobe(strobe);
         }
     }
 
@@ -255,3 +357,6 @@ impl ControllerPorts {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
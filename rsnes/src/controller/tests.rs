@@ -0,0 +1,119 @@
+//! Conformance tests of the serial protocol of the controllers
+//!
+//! Every log in `logs/` is a sequence of strobe writes and reads of $4016,
+//! transcribed from the sequences documented in fullsnes
+//! (<https://problemkaputt.de/fullsnes.htm#snescontrollersmouse>), together
+//! with the input of the controller at that time. A line is one of
+//!
+//! - `pad <buttons>`: the buttons pressed on a standard controller
+//! - `mouse <dx> <dy> <left> <right>`: the motion since the last latch and
+//!   the buttons of a mouse
+//! - `w4016 <value>`: a write to $4016, bit 0 is the strobe
+//! - `r4016 <bits>`: consecutive reads of $4016, the expected bit 0 of each,
+//!   spaces are ignored
+//!
+//! Until the first input line, no controller is connected. `#` starts a
+//! comment.
+
+use super::*;
+
+fn replay(name: &str, log: &str) {
+    let mut port = ControllerPort::new(Controller::None);
+    for (i, line) in log.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        let (command, args) = match line.split_once(' ') {
+            Some(command) => command,
+            None if line.is_empty() => continue,
+            None => panic!("{name}:{}: expected arguments", i + 1),
+        };
+        let int = |arg: &str| {
+            let arg = arg.trim();
+            match arg.strip_prefix("0x") {
+                Some(hex) => i32::from_str_radix(hex, 16),
+                None => arg.parse(),
+            }
+            .unwrap_or_else(|_| panic!("{name}:{}: invalid number `{arg}`", i + 1))
+        };
+        match command {
+            "pad" => {
+                let buttons = int(args) as u16;
+                match &mut port.controller {
+                    Controller::Standard(cntrl) => cntrl.pressed_buttons = buttons,
+                    cntrl => {
+                        *cntrl = Controller::Standard(StandardController {
+                            pressed_buttons: buttons,
+                        })
+                    }
+                }
+            }
+            "mouse" => {
+                let args: Vec<i32> = args.split_whitespace().map(int).collect();
+                let (offset, buttons) = (&args[..2], &args[2..]);
+                if !matches!(port.controller, Controller::Mouse(_)) {
+                    port.controller = Controller::Mouse(Mouse::default())
+                }
+                if let Controller::Mouse(mouse) = &mut port.controller {
+                    mouse.internal_offset = [offset[0], offset[1]];
+                    mouse.left_button = buttons[0] > 0;
+                    mouse.right_button = buttons[1] > 0;
+                }
+            }
+            "w4016" => port.set_strobe(int(args) & 1 > 0),
+            "r4016" => {
+                let expected: String = args.split_whitespace().collect();
+                let read: String = expected
+                    .chars()
+                    .map(|_| char::from(b'0' + port.read_port_data()))
+                    .collect();
+                assert_eq!(read, expected, "{name}:{}", i + 1);
+            }
+            _ => panic!("{name}:{}: unknown command `{command}`", i + 1),
+        }
+    }
+}
+
+macro_rules! replay {
+    ($name:literal) => {
+        replay($name, include_str!(concat!("logs/", $name)))
+    };
+}
+
+#[test]
+pub fn test_standard_controller_log() {
+    replay!("pad.log")
+}
+
+#[test]
+pub fn test_mouse_log() {
+    replay!("mouse.log")
+}
+
+#[test]
+pub fn test_no_controller_log() {
+    replay!("none.log")
+}
+
+#[test]
+pub fn test_latch_count() {
+    let mut ports = ControllerPorts::new();
+    ports.set_strobe(true);
+    // only the rising edge of the strobe latches
+    ports.set_strobe(true);
+    ports.set_strobe(false);
+    assert_eq!(ports.latch_count(), 1);
+    ports.auto_joypad();
+    assert_eq!(ports.latch_count(), 2);
+}
+
+#[test]
+pub fn test_mouse_clamps_offset() {
+    let mut controller = Controller::Mouse(Mouse {
+        internal_offset: [i32::MIN, i32::MAX],
+        ..Mouse::default()
+    });
+    // the sign and a distance of 127, the rest is kept for the next latches
+    assert_eq!(controller.latch() >> 16, 0xfffe);
+    if let Controller::Mouse(mouse) = controller {
+        assert_eq!(mouse.internal_offset, [i32::MIN + 0x7f, i32::MAX - 0x7f]);
+    }
+}
@@ -237,6 +237,10 @@ impl Cpu {
     }
 
     pub fn update_status(&mut self) {
+        // the M and X flags can't be cleared in emulation mode
+        if self.regs.is_emulation {
+            self.regs.status |= Status::INDEX_REGISTER_SIZE | Status::ACCUMULATION;
+        }
         if self.is_idx8() {
             self.regs.x &= 0xff;
             self.regs.y &= 0xff;
@@ -0,0 +1,85 @@
+//! Breakpoints and watchpoints
+//!
+//! While a [`Debugger`] is attached to [`Device::debugger`](crate::device::Device::debugger),
+//! the main CPU stops in front of every instruction at a breakpoint, and
+//! every byte read or written by the main CPU or by DMA is checked against
+//! the watchpoints. Both set a [`BreakReason`], which the frontend polls after
+//! [`Device::run_cycle`](crate::device::Device::run_cycle) to pause its loop.
+//! A watchpoint is reported after the access, when the instruction or DMA
+//! transfer doing it completed.
+//!
+//! While a breakpoint is reported, the main CPU doesn't continue. It passes
+//! the breakpoint once after [`Debugger::resume`].
+
+use crate::bus_log::{Access, Filter};
+use crate::device::Addr24;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The main CPU is about to execute the instruction at this address
+    Breakpoint(Addr24),
+    /// An access matched a watchpoint
+    Watchpoint(Access),
+}
+
+impl std::fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Breakpoint(addr) => write!(f, "breakpoint at {addr}"),
+            Self::Watchpoint(access) => write!(f, "watchpoint ({access})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    /// The addresses of instructions, in front of which the main CPU stops
+    pub breakpoints: BTreeSet<Addr24>,
+    /// Accesses matching one of these filters are reported
+    pub watchpoints: Vec<Filter>,
+    reason: Option<BreakReason>,
+    /// The breakpoint, which is passed after the emulation was resumed
+    resumed_at: Option<Addr24>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Why the emulation has to be paused, if it has to
+    pub fn break_reason(&self) -> Option<&BreakReason> {
+        self.reason.as_ref()
+    }
+
+    /// Continue after a break. Returns the reason of the break.
+    pub fn resume(&mut self) -> Option<BreakReason> {
+        let reason = self.reason.take();
+        if let Some(BreakReason::Breakpoint(addr)) = reason {
+            self.resumed_at = Some(addr);
+        }
+        reason
+    }
+
+    /// Check, if the main CPU has to stop in front of the instruction at `pc`
+    pub(crate) fn check_instruction(&mut self, pc: Addr24) -> bool {
+        if self.resumed_at.take() == Some(pc) {
+            return false;
+        }
+        match self.reason {
+            Some(BreakReason::Breakpoint(addr)) => addr == pc,
+            _ if self.breakpoints.contains(&pc) => {
+                self.reason = Some(BreakReason::Breakpoint(pc));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn check_access(&mut self, access: &Access) {
+        if self.reason.is_none() && self.watchpoints.iter().any(|w| w.matches(access)) {
+            self.reason = Some(BreakReason::Watchpoint(*access));
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Deterministic mode
+//!
+//! Input movies, netplay and regression tests rely on the emulation producing
+//! exactly the same frames, whenever it gets the same input. The core
+//! guarantees this in general:
+//!
+//! - it never reads the clock of the host or random numbers
+//! - all memory starts with fixed contents: WRAM, VRAM, CGRAM, OAM and the
+//!   memory of the S-SMP are zeroed, SRAM is filled with `0xff` until save
+//!   data is loaded into it
+//! - the S-SMP thread is synchronized on every access of its ports, so
+//!   running it in a thread doesn't change the results
+//!
+//! # Input latch model
+//!
+//! The game sees the controllers, when it latches them, i.e. on the strobe
+//! of `$4016` or by the automatic joypad read at the start of vblank.
+//! Usually, a change of a controller by the frontend is visible to the game
+//! immediately, so the result depends on when exactly the frontend changes
+//! it. In deterministic mode, which is enabled with
+//! [`Device::enable_deterministic_mode`](crate::device::Device::enable_deterministic_mode),
+//! the game sees the controllers as they were at the start of the current
+//! frame: changes become visible in the first cycle after a frame was
+//! completed (see [`Device::new_frame`](crate::device::Device::new_frame)).
+//! Mouse movements accumulate until then. So a log of the controllers for
+//! every frame reproduces a run, no matter when the frontend applies them.
+//!
+//! [`frame_hash`] condenses a frame, e.g. to compare two runs.
+
+/// The 64-bit FNV-1a hash of the pixels of a frame
+pub fn frame_hash(pixels: &[[u8; 4]]) -> u64 {
+    pixels
+        .iter()
+        .flatten()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+        })
+}
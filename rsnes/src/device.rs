@@ -2,16 +2,25 @@
 
 use crate::{
     backend::{AudioBackend, FrameBuffer},
+    bus_log::{Access, AccessKind, BusLog, Origin},
     cartridge::Cartridge,
+    cheat::Cheats,
     controller::ControllerPorts,
     cpu::Cpu,
+    debugger::Debugger,
     dma::Dma,
+    hacks::{Hacks, HACKS},
+    integrity::{IntegrityError, Restricted},
     ppu::Ppu,
+    profile::{Clock, Component, Profiler},
     registers::MathRegisters,
     smp::Smp,
-    timing::Cycles,
+    stats::Stats,
+    timing::{CpuStepping, Cycles},
+    trace::CpuTrace,
 };
 use core::cell::Cell;
+use save_state::{InSaveState, SaveStateDeserializer};
 use save_state_macro::*;
 
 const RAM_SIZE: usize = 0x20000;
@@ -51,6 +60,76 @@ impl save_state::InSaveState for Addr24 {
     }
 }
 
+/// The memory an address on the A bus is decoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+    /// an offset into the 128 KiB work RAM
+    Wram(u32),
+    /// an offset into the cartridge ROM
+    Rom(usize),
+    /// an offset into the cartridge RAM
+    Sram(usize),
+    /// a B bus register `$21xx`
+    BusB(u8),
+    /// an internal CPU register `$4xxx`
+    CpuRegister(u16),
+    /// registers or memory of a cartridge coprocessor
+    Coprocessor,
+    /// nothing is mapped to this address (open bus)
+    Unmapped,
+}
+
+impl std::fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Wram(offset) => write!(f, "WRAM ${:05x}", offset),
+            Self::Rom(offset) => write!(f, "ROM ${:06x}", offset),
+            Self::Sram(offset) => write!(f, "SRAM ${:05x}", offset),
+            Self::BusB(reg @ 0x00..=0x3f) => write!(f, "PPU $21{:02x}", reg),
+            Self::BusB(reg @ 0x40..=0x7f) => write!(f, "APU $21{:02x}", reg),
+            Self::BusB(reg @ 0x80..=0x83) => write!(f, "WRAM port $21{:02x}", reg),
+            Self::BusB(reg) => write!(f, "B bus $21{:02x}", reg),
+            Self::CpuRegister(reg) => write!(f, "CPU ${:04x}", reg),
+            Self::Coprocessor => write!(f, "coprocessor"),
+            Self::Unmapped => write!(f, "open bus"),
+        }
+    }
+}
+
+impl MemoryRegion {
+    /// The region of the following address, if the memory continues there
+    fn successor(self) -> Self {
+        match self {
+            Self::Wram(offset) => Self::Wram(offset + 1),
+            Self::Rom(offset) => Self::Rom(offset + 1),
+            Self::Sram(offset) => Self::Sram(offset + 1),
+            Self::BusB(reg) => Self::BusB(reg.wrapping_add(1)),
+            Self::CpuRegister(reg) => Self::CpuRegister(reg.wrapping_add(1)),
+            Self::Coprocessor | Self::Unmapped => self,
+        }
+    }
+
+    /// The count of bytes from this region to `region` in the same memory
+    fn distance(self, region: Self) -> Option<usize> {
+        match (self, region) {
+            (Self::Wram(a), Self::Wram(b)) => b.checked_sub(a).map(|d| d as usize),
+            (Self::Rom(a), Self::Rom(b)) | (Self::Sram(a), Self::Sram(b)) => b.checked_sub(a),
+            (Self::BusB(a), Self::BusB(b)) => b.checked_sub(a).map(usize::from),
+            (Self::CpuRegister(a), Self::CpuRegister(b)) => b.checked_sub(a).map(usize::from),
+            _ => None,
+        }
+    }
+}
+
+/// Addresses in a bank, which are decoded to consecutive bytes of the same
+/// memory, starting with `region`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    pub start: Addr24,
+    pub end: Addr24,
+    pub region: MemoryRegion,
+}
+
 pub trait Data: std::fmt::Debug + Sized + Default + Clone + Copy {
     type Arr: AsRef<[u8]> + AsMut<[u8]> + Default + std::fmt::Debug + Clone + Copy;
     fn to_bytes(self) -> Self::Arr;
@@ -141,6 +220,14 @@ impl Data for Addr24 {
 
 #[derive(Debug, InSaveState)]
 pub struct Device<B: AudioBackend, FB: FrameBuffer> {
+    /// Save states are refused by [`Device::load_state`] in integrity mode.
+    /// It must be the first field, so a refused state is not loaded partially.
+    #[except((|_v, _s| ()), (|v: &mut bool, _s| assert!(
+        !*v,
+        "{}",
+        IntegrityError(Restricted::LoadState)
+    )))]
+    integrity_mode: bool,
     pub(crate) cpu: Cpu,
     pub smp: Smp<B>,
     pub ppu: Ppu<FB>,
@@ -153,6 +240,8 @@ pub struct Device<B: AudioBackend, FB: FrameBuffer> {
     wram_addr: Cell<u32>,
     pub(crate) memory_cycles: Cycles,
     pub(crate) cpu_ahead_cycles: i32,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) cpu_stepping: CpuStepping,
     pub(crate) new_scanline: bool,
     pub(crate) scanline_drawn: bool,
     pub new_frame: bool,
@@ -165,11 +254,42 @@ pub struct Device<B: AudioBackend, FB: FrameBuffer> {
     pub(crate) nmi_vblank_bit: Cell<bool>,
     pub(crate) math_registers: MathRegisters,
     pub(crate) is_pal: bool,
+    /// If enabled, bus accesses are logged (see [`crate::bus_log`])
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub bus_log: Option<BusLog>,
+    /// If attached, breakpoints and watchpoints are checked (see [`crate::debugger`])
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub debugger: Option<Debugger>,
+    /// If enabled, the instructions of the main CPU are traced (see [`crate::trace`])
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub cpu_trace: Option<CpuTrace>,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) bus_origin: Origin,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) stats: Stats,
+    /// The samples output, when the counters were reset
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    stats_apu_samples: u64,
+    /// The samples output until the end of the last frame
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) frame_end_samples: u64,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) samples_this_frame: u32,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    profiler: Option<Box<Profiler>>,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) deterministic: bool,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) hacks: Hacks,
+    /// The cheats of the loaded game (see [`crate::cheat`])
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) cheats: Cheats,
 }
 
 impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
     pub fn new(audio_backend: B, frame_buffer: FB, is_pal: bool, is_threaded: bool) -> Self {
         Self {
+            integrity_mode: false,
             cpu: Cpu::new(),
             smp: Smp::new(audio_backend, is_pal, is_threaded),
             ppu: Ppu::new(frame_buffer, is_pal),
@@ -181,6 +301,7 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
             wram_addr: Cell::new(0),
             memory_cycles: 0,
             cpu_ahead_cycles: 186,
+            cpu_stepping: CpuStepping::default(),
             new_scanline: true,
             new_frame: true,
             scanline_drawn: false,
@@ -192,6 +313,18 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
             nmi_vblank_bit: Cell::new(false),
             math_registers: MathRegisters::new(),
             is_pal,
+            bus_log: None,
+            debugger: None,
+            cpu_trace: None,
+            bus_origin: Origin::Cpu,
+            stats: Stats::default(),
+            stats_apu_samples: 0,
+            frame_end_samples: 0,
+            samples_this_frame: 0,
+            profiler: None,
+            deterministic: false,
+            hacks: Hacks::default(),
+            cheats: Cheats::default(),
         }
     }
 
@@ -214,13 +347,166 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
         }
     }
 
+    /// Insert a cartridge and enable its hacks (see [`crate::hacks`])
     pub fn load_cartridge(&mut self, mut cartridge: Cartridge) {
         cartridge.set_region(self.is_pal);
+        // disable the hacks of the last cartridge
+        self.load_hacks(&[]);
+        self.cheats = Cheats::default();
         self.cartridge = Some(cartridge);
+        self.load_hacks(HACKS);
         self.cpu = Cpu::new();
         self.reset_program_counter();
     }
 
+    /// Press the reset button of the console. The CPUs and the S-SMP restart
+    /// at their reset vectors, interrupts and DMA are disabled and the
+    /// coprocessors of the cartridge are reset, while the memory and the
+    /// SRAM are kept.
+    pub fn reset(&mut self) {
+        self.cpu = Cpu::new();
+        self.dma = Dma::new();
+        self.shall_nmi = false;
+        self.shall_irq = false;
+        self.cpu_ahead_cycles = 186;
+        self.cpu_stepping = CpuStepping::default();
+        self.controllers.set_pio(0xff);
+        self.smp.reset();
+        self.cartridge.as_mut().unwrap().reset_coprocessors();
+        self.reset_program_counter();
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    /// Pull the programmable I/O line of controller port 2 low for a moment,
+    /// as the Super Scope does, when the beam passes the position it points
+    /// at. This latches the PPU counters, unless WRIO holds the line low.
+    pub fn pulse_io_line(&mut self) {
+        if self.controllers.io_lines() & 0x80 > 0 {
+            self.ppu.latch()
+        }
+    }
+
+    /// The counters of the emulation (see [`crate::stats`])
+    pub fn stats(&self) -> Stats {
+        Stats {
+            apu_samples: self.smp.samples_run() - self.stats_apu_samples,
+            ..self.stats
+        }
+    }
+
+    /// Reset the counters of the emulation to zero. Returns the counters
+    /// before the reset.
+    pub fn reset_stats(&mut self) -> Stats {
+        let stats = self.stats();
+        self.stats = Stats::default();
+        self.stats_apu_samples = self.smp.samples_run();
+        stats
+    }
+
+    /// The count of stereo samples the S-DSP output in the last completed
+    /// frame. The S-SMP catches up with the main CPU at the end of every
+    /// frame, so the samples of the frame are exactly those emulated up to
+    /// its end and the fraction of a sample left over is part of the next
+    /// frame. The count only depends on the emulated cycles, e.g. a frontend
+    /// can pull exactly this many samples after every frame to keep audio and
+    /// video in sync. A threaded S-SMP may still push some of them to the
+    /// backend after the frame completed.
+    pub fn samples_this_frame(&self) -> u32 {
+        self.samples_this_frame
+    }
+
+    /// Measure the host time spent in every component with `clock` (see
+    /// [`crate::profile`])
+    pub fn enable_profiling(&mut self, clock: Clock) {
+        self.profiler = Some(Box::new(Profiler::new(clock)))
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /// Run `f` as `component`, if profiling is enabled
+    #[inline]
+    pub(crate) fn profiled<T>(
+        &mut self,
+        component: Component,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        match &mut self.profiler {
+            None => f(self),
+            Some(profiler) => {
+                let previous = profiler.switch(component);
+                let result = f(self);
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.switch(previous);
+                }
+                result
+            }
+        }
+    }
+
+    /// Whether the console is a PAL console, which runs at 50 Hz
+    pub fn is_pal(&self) -> bool {
+        self.is_pal
+    }
+
+    /// Enable the deterministic mode (see [`crate::deterministic`]). The
+    /// mode can't be disabled again.
+    pub fn enable_deterministic_mode(&mut self) {
+        self.deterministic = true;
+        self.controllers.sample()
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Enable the integrity mode (see [`crate::integrity`]). All cheats are
+    /// removed.
+    pub fn enable_integrity_mode(&mut self) {
+        self.clear_cheats();
+        self.integrity_mode = true
+    }
+
+    pub fn integrity_mode(&self) -> bool {
+        self.integrity_mode
+    }
+
+    /// Check whether a feature may be used
+    pub fn check_integrity(&self, feature: Restricted) -> Result<(), IntegrityError> {
+        if self.integrity_mode && !feature.is_allowed() {
+            Err(IntegrityError(feature))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Load a save state. This is refused in integrity mode.
+    ///
+    /// Calling [`InSaveState::deserialize`] directly panics in integrity mode
+    /// instead, before anything is loaded.
+    pub fn load_state(&mut self, state: &mut SaveStateDeserializer) -> Result<(), IntegrityError> {
+        self.check_integrity(Restricted::LoadState)?;
+        self.deserialize(state);
+        Ok(())
+    }
+
+    /// Restore a state the core saved itself to undo emulation, e.g. for
+    /// run-ahead. This is possible in integrity mode, so it must never be
+    /// given a state from outside of the core.
+    pub(crate) fn restore_state(&mut self, state: &[u8]) {
+        let integrity_mode = core::mem::replace(&mut self.integrity_mode, false);
+        self.deserialize(&mut SaveStateDeserializer { data: state.iter() });
+        self.integrity_mode = integrity_mode
+    }
+
     pub fn reset_program_counter(&mut self) {
         let addr = crate::cpu::RESET_VECTOR_ADDR;
         self.cpu.regs.pc = Addr24::new(0, self.read::<u16>(addr));
@@ -237,26 +523,192 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
         self.open_bus = value.to_open_bus();
         self.memory_cycles +=
             (self.get_memory_cycle(addr) - 6) * core::mem::size_of::<D::Arr>() as u32;
+        self.log_access(addr, value, AccessKind::Read);
         value
     }
 
     /// Write a value to the mapped memory at the specified address.
     /// This method also updates open bus.
-    pub fn write<D: Data>(&mut self, addr: Addr24, value: D) {
+    pub fn write<D: Data>(&mut self, addr: Addr24, mut value: D) {
+        if self.cheats.intercepts_writes() {
+            value = self.intercept_write(addr, value)
+        }
         self.open_bus = value.to_open_bus();
-        self.write_data(addr, value);
+        self.write_mapped(addr, value);
         self.memory_cycles +=
             (self.get_memory_cycle(addr) - 6) * core::mem::size_of::<D::Arr>() as u32;
+        self.log_access(addr, value, AccessKind::Write);
+    }
+
+    /// Record every byte of an access in the bus log, if it is enabled, and
+    /// check it against the watchpoints of the debugger
+    pub(crate) fn log_access<D: Data>(&mut self, addr: Addr24, value: D, kind: AccessKind) {
+        if self.bus_log.is_none() && self.debugger.is_none() {
+            return;
+        }
+        for (i, &value) in value.to_bytes().as_ref().iter().enumerate() {
+            let access = Access {
+                addr: Addr24::new(addr.bank, addr.addr.wrapping_add(i as u16)),
+                value,
+                kind,
+                origin: self.bus_origin,
+            };
+            if let Some(log) = &mut self.bus_log {
+                log.record(access)
+            }
+            if let Some(debugger) = &mut self.debugger {
+                debugger.check_access(&access)
+            }
+        }
     }
 }
 
 impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
+    /// Find out which memory an address is decoded to.
+    /// All mirrors of a byte resolve to the same region and offset.
+    pub fn region(&self, addr: Addr24) -> MemoryRegion {
+        if (0x7e..=0x7f).contains(&addr.bank) {
+            MemoryRegion::Wram(((addr.bank as u32 & 1) << 16) | addr.addr as u32)
+        } else if addr.bank & 0xc0 == 0 || addr.bank & 0xc0 == 0x80 {
+            match addr.addr {
+                0x0000..=0x1fff => MemoryRegion::Wram(addr.addr.into()),
+                0x2100..=0x21ff => MemoryRegion::BusB((addr.addr & 0xff) as u8),
+                0x4000..=0x43ff => MemoryRegion::CpuRegister(addr.addr),
+                _ => self.cartridge_region(addr),
+            }
+        } else {
+            self.cartridge_region(addr)
+        }
+    }
+
+    /// Resolve the whole address space into ranges (see [`MappedRange`]),
+    /// ordered by address
+    pub fn address_map(&self) -> Vec<MappedRange> {
+        let mut ranges: Vec<MappedRange> = Vec::new();
+        let mut expected = None;
+        for addr in 0..=0xffffffu32 {
+            let addr = Addr24::new((addr >> 16) as u8, addr as u16);
+            let region = self.region(addr);
+            match ranges.last_mut() {
+                Some(range) if addr.addr > 0 && expected == Some(region) => range.end = addr,
+                _ => ranges.push(MappedRange {
+                    start: addr,
+                    end: addr,
+                    region,
+                }),
+            }
+            expected = Some(region.successor());
+        }
+        ranges
+    }
+
+    /// Find all addresses, which are decoded to the same memory as `addr`,
+    /// ordered by address. Copies of a byte in a padded ROM (see
+    /// [`Cartridge::rom_copies`]) count as mirrors.
+    pub fn mirrors(&self, addr: Addr24) -> Vec<Addr24> {
+        let targets: Vec<MemoryRegion> = match self.region(addr) {
+            MemoryRegion::Rom(offset) => match &self.cartridge {
+                Some(cart) => cart
+                    .rom_copies(offset)
+                    .into_iter()
+                    .map(MemoryRegion::Rom)
+                    .collect(),
+                None => return vec![],
+            },
+            MemoryRegion::Coprocessor | MemoryRegion::Unmapped => return vec![],
+            region => vec![region],
+        };
+        let system_ranges = (0..=0xffu8)
+            .filter(|bank| bank & 0x40 == 0)
+            .flat_map(|bank| {
+                [(0x0000, 0x2000), (0x2100, 0x100), (0x4000, 0x400)]
+                    .map(|(addr, len)| (Addr24::new(bank, addr), len))
+            })
+            .chain([0x7e, 0x7f].map(|bank| (Addr24::new(bank, 0), 0x10000)));
+        let cartridge_ranges = self.cartridge.iter().flat_map(Cartridge::linear_ranges);
+        let mut mirrors = vec![];
+        for (start, len) in system_ranges.chain(cartridge_ranges) {
+            let (mut addr, end) = (u32::from(start.addr), u32::from(start.addr) + len);
+            while addr < end {
+                let first = self.region(Addr24::new(start.bank, addr as u16));
+                // the memory may be smaller than the range and repeat in it
+                let len = (end - addr).min(self.region_size(first));
+                for target in &targets {
+                    if let Some(distance) = first.distance(*target).filter(|&d| d < len as usize) {
+                        mirrors.push(Addr24::new(start.bank, (addr as usize + distance) as u16))
+                    }
+                }
+                addr += len
+            }
+        }
+        mirrors.sort();
+        mirrors.dedup();
+        mirrors.retain(|&addr| targets.contains(&self.region(addr)));
+        mirrors
+    }
+
+    /// The count of bytes of the memory from `region` to its end
+    fn region_size(&self, region: MemoryRegion) -> u32 {
+        let cart = self.cartridge.as_ref();
+        let size = match region {
+            MemoryRegion::Wram(offset) => 0x20000 - offset as usize,
+            MemoryRegion::Rom(offset) => cart.map_or(0, |cart| cart.rom().len()) - offset,
+            MemoryRegion::Sram(offset) => cart.map_or(0, |cart| cart.sram().len()) - offset,
+            MemoryRegion::BusB(reg) => 0x100 - usize::from(reg),
+            MemoryRegion::CpuRegister(reg) => 0x10000 - usize::from(reg),
+            MemoryRegion::Coprocessor | MemoryRegion::Unmapped => 1,
+        };
+        size as u32
+    }
+
+    fn cartridge_region(&self, addr: Addr24) -> MemoryRegion {
+        self.cartridge
+            .as_ref()
+            .map(|cart| cart.region(addr))
+            .unwrap_or(MemoryRegion::Unmapped)
+    }
+
+    /// Read WRAM, ROM or SRAM without any side effects.
+    /// Returns `None` for registers and unmapped addresses.
+    pub fn peek(&self, addr: Addr24) -> Option<u8> {
+        match self.region(addr) {
+            MemoryRegion::Wram(offset) => Some(self.ram[offset as usize]),
+            _ => self.cartridge.as_ref()?.peek(addr),
+        }
+    }
+
+    /// Write WRAM, ROM or SRAM without any side effects.
+    /// The change is visible through every mirror of the address.
+    /// Returns `false` for registers, unmapped addresses and in integrity
+    /// mode.
+    pub fn poke(&mut self, addr: Addr24, value: u8) -> bool {
+        if self.check_integrity(Restricted::Poke).is_err() {
+            return false;
+        }
+        match self.region(addr) {
+            MemoryRegion::Wram(offset) => {
+                self.ram[offset as usize] = value;
+                true
+            }
+            _ => match &mut self.cartridge {
+                Some(cart) => cart.poke(addr, value),
+                None => false,
+            },
+        }
+    }
+
     pub fn read_bus_b<D: Data>(&mut self, addr: u8) -> D {
         let mut data = <D::Arr as Default>::default();
 
         for (i, d) in data.as_mut().iter_mut().enumerate() {
             let addr = addr.wrapping_add(i as u8);
             *d = match addr {
+                // SLHV latches through EXTLATCH, like the line of controller
+                // port 2, so it has no effect while the line is held low
+                0x37 if self.controllers.io_lines() & 0x80 == 0 => {
+                    self.ppu.open_bus1 = self.open_bus;
+                    self.open_bus
+                }
                 0x34..=0x3f => {
                     let val = self.ppu.read_register(addr).unwrap_or(self.open_bus);
                     if addr < 0x3b || addr == 0x3e {
@@ -268,7 +720,7 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
                 }
                 0x40..=0x7f => {
                     // APU Ports 2140h-2143h are mirrored to 2144h..217Fh
-                    self.smp.read_output_port(addr)
+                    self.profiled(Component::Smp, |device| device.smp.read_output_port(addr))
                 }
                 0x80 => {
                     let res = self.ram[self.wram_addr.get() as usize];
@@ -348,7 +800,12 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
             let addr = addr.wrapping_add(i as u8);
             match addr {
                 0x00..=0x33 => self.ppu.write_register(addr, *d),
-                0x40..=0x7f => self.smp.write_input_port(addr, *d),
+                0x40..=0x7f => {
+                    let data = *d;
+                    self.profiled(Component::Smp, |device| {
+                        device.smp.write_input_port(addr, data)
+                    })
+                }
                 0x80 => {
                     self.ram[(self.wram_addr.get() & 0x1ffff) as usize] = *d;
                     self.increment_wram_addr();
@@ -367,13 +824,22 @@ impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
         }
     }
 
-    /// Write the mapped memory at the specified address
+    /// Write the mapped memory at the specified address, including
+    /// registers. Returns `false` in integrity mode, like [`Self::poke`].
     ///
     /// # Note
     ///
     /// This method does not modify open bus
     /// The master cycles aren't touched either.
-    pub fn write_data<D: Data>(&mut self, addr: Addr24, value: D) {
+    pub fn write_data<D: Data>(&mut self, addr: Addr24, value: D) -> bool {
+        if self.check_integrity(Restricted::Poke).is_err() {
+            return false;
+        }
+        self.write_mapped(addr, value);
+        true
+    }
+
+    fn write_mapped<D: Data>(&mut self, addr: Addr24, value: D) {
         if (0x7e..=0x7f).contains(&addr.bank) {
             // address bus A + /WRAM
             value.write_to(
@@ -0,0 +1,125 @@
+//! A 65816 disassembler
+//!
+//! Decodes one instruction at a time with the opcode table of the
+//! [assembler](crate::asm). The size of immediate operands depends on the M
+//! and X status flags, which have to be known by the caller, e.g. from the
+//! CPU registers or by following `rep` and `sep` with
+//! [`Instruction::flags_after`].
+//!
+//! The text uses the syntax accepted by [`assemble`](crate::asm::assemble),
+//! with the operand written with as many digits as it has bytes. So
+//! assembling it at the same address returns the same bytes.
+
+use crate::asm::{AddrMode, OPCODES};
+use crate::device::Addr24;
+
+/// The longest instruction in bytes
+pub const MAX_INSTRUCTION_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    /// The address of the opcode
+    pub addr: Addr24,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: AddrMode,
+    /// The operand bytes as a little endian value
+    pub operand: u32,
+    /// The size of the instruction in bytes including the opcode
+    pub size: usize,
+}
+
+impl Instruction {
+    /// The address of the following instruction
+    pub const fn next_addr(&self) -> Addr24 {
+        Addr24::new(
+            self.addr.bank,
+            self.addr.addr.wrapping_add(self.size as u16),
+        )
+    }
+
+    /// The destination of a branch or of `per`. Branches wrap around inside
+    /// the bank of the instruction.
+    pub const fn branch_target(&self) -> Option<Addr24> {
+        let distance = match self.mode {
+            AddrMode::Relative8 => self.operand as u8 as i8 as u16,
+            AddrMode::Relative16 => self.operand as u16,
+            _ => return None,
+        };
+        let next = self.next_addr();
+        Some(Addr24::new(next.bank, next.addr.wrapping_add(distance)))
+    }
+
+    /// The M and X flags after the instruction was executed, if they were
+    /// `m8` and `x8` before. Only `rep` and `sep` are taken into account.
+    pub const fn flags_after(&self, m8: bool, x8: bool) -> (bool, bool) {
+        let bits = self.operand as u8;
+        match self.opcode {
+            // REP
+            0xc2 => (m8 && bits & 0x20 == 0, x8 && bits & 0x10 == 0),
+            // SEP
+            0xe2 => (m8 || bits & 0x20 > 0, x8 || bits & 0x10 > 0),
+            _ => (m8, x8),
+        }
+    }
+}
+
+/// Decode the instruction at the start of `bytes`, which is located at
+/// `addr`. `m8` and `x8` are the states of the M and X status flags.
+/// Returns `None`, if `bytes` ends before the instruction.
+pub fn decode(bytes: &[u8], addr: Addr24, m8: bool, x8: bool) -> Option<Instruction> {
+    let opcode = *bytes.first()?;
+    let (mnemonic, mode) = OPCODES[usize::from(opcode)];
+    let size = 1 + mode.operand_size(m8, x8);
+    let operand = bytes
+        .get(1..size)?
+        .iter()
+        .rev()
+        .fold(0, |val, &byte| (val << 8) | u32::from(byte));
+    Some(Instruction {
+        addr,
+        opcode,
+        mnemonic,
+        mode,
+        operand,
+        size,
+    })
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use AddrMode::*;
+        let mnemonic = self.mnemonic.to_ascii_lowercase();
+        let digits = (self.size - 1) * 2;
+        let val = format!("${:0digits$x}", self.operand);
+        match self.mode {
+            Implied => write!(f, "{mnemonic}"),
+            Accumulator => write!(f, "{mnemonic} a"),
+            ImmediateM | ImmediateX | Immediate8 => write!(f, "{mnemonic} #{val}"),
+            Direct | Absolute | Long => write!(f, "{mnemonic} {val}"),
+            DirectX | AbsoluteX | LongX => write!(f, "{mnemonic} {val},x"),
+            DirectY | AbsoluteY => write!(f, "{mnemonic} {val},y"),
+            DirectIndirect | AbsoluteIndirect => write!(f, "{mnemonic} ({val})"),
+            DirectIndirectLong | AbsoluteIndirectLong => write!(f, "{mnemonic} [{val}]"),
+            DirectXIndirect | AbsoluteXIndirect => write!(f, "{mnemonic} ({val},x)"),
+            DirectIndirectY => write!(f, "{mnemonic} ({val}),y"),
+            DirectIndirectLongY => write!(f, "{mnemonic} [{val}],y"),
+            StackRelative => write!(f, "{mnemonic} {val},s"),
+            StackRelativeIndirectY => write!(f, "{mnemonic} ({val},s),y"),
+            Relative8 | Relative16 => {
+                let target = self.branch_target().map_or(0, |target| target.addr);
+                write!(f, "{mnemonic} ${target:04x}")
+            }
+            // the destination bank is encoded first
+            BlockMove => write!(
+                f,
+                "{mnemonic} ${:02x},${:02x}",
+                self.operand >> 8,
+                self.operand & 0xff
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,96 @@
+use super::*;
+use crate::asm::assemble;
+
+const PC: Addr24 = Addr24::new(0x80, 0x8000);
+
+/// Instructions with their text, as decoded with the M and X flags
+static INSTRUCTIONS: &[(&[u8], bool, bool, &str)] = &[
+    (&[0xea], true, true, "nop"),
+    (&[0x0a], true, true, "asl a"),
+    (&[0xa9, 0x34], true, true, "lda #$34"),
+    (&[0xa9, 0x34, 0x12], false, true, "lda #$1234"),
+    (&[0xa2, 0x34], false, true, "ldx #$34"),
+    (&[0xa2, 0x34, 0x12], true, false, "ldx #$1234"),
+    (&[0xc2, 0x30], false, false, "rep #$30"),
+    (&[0x00, 0x01], true, true, "brk #$01"),
+    (&[0xa5, 0x10], true, true, "lda $10"),
+    (&[0xad, 0x10, 0x00], true, true, "lda $0010"),
+    (&[0xaf, 0x56, 0x34, 0x12], true, true, "lda $123456"),
+    (&[0xbf, 0x56, 0x34, 0x7e], true, true, "lda $7e3456,x"),
+    (&[0x96, 0x10], true, true, "stx $10,y"),
+    (&[0xa1, 0x10], true, true, "lda ($10,x)"),
+    (&[0xb1, 0x10], true, true, "lda ($10),y"),
+    (&[0xa7, 0x10], true, true, "lda [$10]"),
+    (&[0xb7, 0x10], true, true, "lda [$10],y"),
+    (&[0xa3, 0x03], true, true, "lda $03,s"),
+    (&[0xb3, 0x03], true, true, "lda ($03,s),y"),
+    (&[0x6c, 0xfc, 0xff], true, true, "jmp ($fffc)"),
+    (&[0x7c, 0x00, 0x90], true, true, "jmp ($9000,x)"),
+    (&[0xdc, 0xfc, 0xff], true, true, "jml [$fffc]"),
+    (&[0x5c, 0x56, 0x34, 0x12], true, true, "jml $123456"),
+    (&[0xd4, 0x10], true, true, "pei ($10)"),
+    (&[0xf4, 0x34, 0x12], true, true, "pea $1234"),
+    (&[0x80, 0xfe], true, true, "bra $8000"),
+    (&[0xd0, 0x10], true, true, "bne $8012"),
+    (&[0x82, 0xfd, 0x7f], true, true, "brl $0000"),
+    (&[0x62, 0x00, 0x01], true, true, "per $8103"),
+    (&[0x54, 0x7f, 0x7e], true, true, "mvn $7e,$7f"),
+];
+
+#[test]
+fn test_known_instructions() {
+    for &(bytes, m8, x8, text) in INSTRUCTIONS {
+        let instr = decode(bytes, PC, m8, x8).unwrap();
+        assert_eq!(instr.to_string(), text);
+        assert_eq!(instr.size, bytes.len(), "size of `{text}`");
+        assert_eq!(assemble(text, PC, m8, x8).unwrap(), bytes, "`{text}`");
+    }
+}
+
+#[test]
+fn test_round_trip_all_opcodes() {
+    for opcode in 0..=0xff {
+        for (m8, x8) in [(true, true), (false, false), (true, false), (false, true)] {
+            let bytes = [opcode, 0x9a, 0x78, 0x56];
+            let instr = decode(&bytes, PC, m8, x8).unwrap();
+            assert_eq!(instr.mnemonic, OPCODES[usize::from(opcode)].0);
+            let text = instr.to_string();
+            let assembled = assemble(&text, PC, m8, x8)
+                .unwrap_or_else(|err| panic!("`{text}` ({opcode:02x}): {err}"));
+            assert_eq!(assembled, &bytes[..instr.size], "`{text}`");
+        }
+    }
+}
+
+#[test]
+fn test_truncated_instruction() {
+    assert_eq!(decode(&[], PC, true, true), None);
+    assert_eq!(decode(&[0xad, 0x10], PC, true, true), None);
+    assert_eq!(decode(&[0xa9, 0x10], PC, false, true), None);
+    assert!(decode(&[0xa9, 0x10], PC, true, true).is_some());
+}
+
+#[test]
+fn test_flags_after() {
+    let rep = decode(&[0xc2, 0x20], PC, true, true).unwrap();
+    assert_eq!(rep.flags_after(true, true), (false, true));
+    let sep = decode(&[0xe2, 0x30], PC, false, false).unwrap();
+    assert_eq!(sep.flags_after(false, false), (true, true));
+    let lda = decode(&[0xa9, 0x00, 0x00], PC, false, false).unwrap();
+    assert_eq!(lda.flags_after(false, false), (false, false));
+    // the operand of a following instruction uses the new flags
+    let code = [0xc2, 0x20, 0xa9, 0x34, 0x12, 0xe2, 0x20, 0xa9, 0x34];
+    let (mut pc, mut flags) = (PC, (true, true));
+    let mut lines = vec![];
+    while let Some(instr) = decode(
+        &code[usize::from(pc.addr - PC.addr)..],
+        pc,
+        flags.0,
+        flags.1,
+    ) {
+        lines.push(instr.to_string());
+        flags = instr.flags_after(flags.0, flags.1);
+        pc = instr.next_addr();
+    }
+    assert_eq!(lines, ["rep #$20", "lda #$1234", "sep #$20", "lda #$34"]);
+}
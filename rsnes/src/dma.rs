@@ -1,4 +1,7 @@
-use crate::device::{Addr24, Device};
+use crate::{
+    bus_log::AccessKind,
+    device::{Addr24, Device},
+};
 use save_state_macro::*;
 
 pub mod flags {
@@ -162,6 +165,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
         addr: Addr24,
         b_bus: u8,
     ) {
+        self.stats.dma_bytes += 1;
         let b_bus = b_bus.wrapping_add(b_bus_offset);
         let channel = self.dma.channels.get(channel_id).unwrap();
         if channel.control & flags::PPU_TO_CPU > 0 {
@@ -170,7 +174,13 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             {
                 self.open_bus
             } else {
-                self.read_bus_b::<u8>(b_bus)
+                let value = self.read_bus_b::<u8>(b_bus);
+                self.log_access(
+                    Addr24::new(0, 0x2100 | u16::from(b_bus)),
+                    value,
+                    AccessKind::Read,
+                );
+                value
             };
             match addr.addr {
                 0x2100..=0x21ff | 0x4300..=0x437f | 0x420b | 0x420c => (),
@@ -185,7 +195,12 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
                 ) => self.open_bus,
                 _ => self.read::<u8>(addr),
             };
-            self.write_bus_b(b_bus, value)
+            self.write_bus_b(b_bus, value);
+            self.log_access(
+                Addr24::new(0, 0x2100 | u16::from(b_bus)),
+                value,
+                AccessKind::Write,
+            )
         }
     }
 
@@ -6,7 +6,10 @@
 //! - https://datasheet.datasheetarchive.com/originals/scans/Scans-003/Scans-0079458.pdf
 //! - SNES book 2 - Section 3
 
-use crate::timing::Cycles;
+use crate::{
+    clock::{self, ClockDivider},
+    timing::Cycles,
+};
 use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::InSaveState;
 
@@ -114,8 +117,7 @@ pub struct Dsp {
     ram: [u16; 0x100],
     ver: DspVersion,
 
-    timing_proportion: (Cycles, Cycles),
-    master_cycles: Cycles,
+    clock: ClockDivider,
 }
 
 impl Default for Dsp {
@@ -142,27 +144,54 @@ impl Dsp {
             drom: *drom,
             ram: [0; 0x100],
             ver,
-            timing_proportion: (0, 0),
-            master_cycles: 0,
+            clock: ClockDivider::new(clock::MASTER_NTSC, clock::NECDSP),
         }
     }
 
+    /// The size of a firmware file, the program ROM followed by the data ROM
+    pub const FIRMWARE_SIZE: usize = ROM_SIZE;
+
     pub const fn version(&self) -> DspVersion {
         self.ver
     }
 
-    pub fn set_timing_proportion(&mut self, prop: (Cycles, Cycles)) {
-        self.timing_proportion = prop
+    /// Replace the built-in firmware with a dump of the chip. Returns `false`
+    /// if it isn't [`Dsp::FIRMWARE_SIZE`] bytes large.
+    pub fn load_firmware(&mut self, firmware: &[u8]) -> bool {
+        match firmware.try_into() {
+            Ok(firmware) => {
+                (self.irom, self.drom) = DspVersion::split_roms(firmware);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Reset the registers, like the reset pin of the chip does. The data
+    /// RAM keeps its content.
+    pub fn reset(&mut self) {
+        self.status = 0;
+        self.ramptr = 0;
+        self.romptr = 0;
+        self.pc = 0;
+        self.mult = [0; 2];
+        self.acc = [0; 2];
+        self.flag = [0; 2];
+        self.temp = [0; 2];
+        self.stack = Stack::new();
+        self.port = 0;
+    }
+
+    pub fn set_region(&mut self, is_pal: bool) {
+        self.clock = ClockDivider::new(clock::master(is_pal), clock::NECDSP)
     }
 
     pub fn tick(&mut self, n: Cycles) {
-        self.master_cycles += n * self.timing_proportion.1
+        self.clock.tick(n)
     }
 
     pub fn refresh(&mut self) {
-        let cycles = self.master_cycles / self.timing_proportion.0;
-        self.master_cycles %= self.timing_proportion.0;
-        for _ in 0..cycles {
+        for _ in 0..self.clock.take() {
             self.dispatch()
         }
     }
@@ -423,6 +452,28 @@ pub enum DspVersion {
 }
 
 impl DspVersion {
+    /// The name of the chip, e.g. `DSP-1B`
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Dsp1 => "DSP-1",
+            Self::Dsp1B => "DSP-1B",
+            Self::Dsp2 => "DSP-2",
+            Self::Dsp3 => "DSP-3",
+            Self::Dsp4 => "DSP-4",
+        }
+    }
+
+    /// The name of its firmware files, e.g. `dsp1b` for `dsp1b.rom`
+    pub const fn firmware_name(&self) -> &'static str {
+        match self {
+            Self::Dsp1 => "dsp1",
+            Self::Dsp1B => "dsp1b",
+            Self::Dsp2 => "dsp2",
+            Self::Dsp3 => "dsp3",
+            Self::Dsp4 => "dsp4",
+        }
+    }
+
     pub fn rom(&self) -> &'static Rom {
         match self {
             Self::Dsp1 => &DSP1_ROM,
@@ -462,8 +513,8 @@ impl InSaveState for DspVersion {
         let mut i: u8 = 0;
         i.deserialize(state);
         *self = match i {
-            0 => Self::Dsp1B,
-            1 => Self::Dsp1,
+            0 => Self::Dsp1,
+            1 => Self::Dsp1B,
             2 => Self::Dsp2,
             3 => Self::Dsp3,
             4 => Self::Dsp4,
@@ -9,6 +9,7 @@
 
 use crate::{
     cartridge::Cartridge,
+    clock::{self, ClockDivider},
     cpu::{Cpu, Status},
     device::{Addr24, Data, Device},
     instr::{AccessType, DeviceAccess},
@@ -33,6 +34,28 @@ mod dma_modes {
 
     /// character conversion type 2 (semi-automatic) mode running
     pub const TYPE2: u8 = 3;
+
+    /// character conversion type 1 waiting for the SNES to read the characters
+    pub const TYPE1_WAITING: u8 = 4;
+}
+
+const fn linear(addr: Addr24) -> u32 {
+    (addr.bank as u32) << 16 | addr.addr as u32
+}
+
+const fn from_linear(addr: u32) -> Addr24 {
+    Addr24::new((addr >> 16) as u8, addr as u16)
+}
+
+/// Convert eight pixels into the bitplanes of a row of a character
+fn to_bitplanes(pixels: [u8; 8]) -> [u8; 8] {
+    let mut planes = [0; 8];
+    for (x, pixel) in pixels.into_iter().enumerate() {
+        for (i, plane) in planes.iter_mut().enumerate() {
+            *plane |= ((pixel >> i) & 1) << (7 - x);
+        }
+    }
+    planes
 }
 
 #[derive(Debug, Default, Clone, Copy, InSaveState)]
@@ -151,6 +174,8 @@ pub struct DmaInfo {
     dst: Addr24,
     byte_counter: u16,
     bit_map_file: [[u8; 8]; 2],
+    /// the next line of the character conversion type 2
+    line: u8,
 }
 
 impl DmaInfo {
@@ -169,6 +194,7 @@ impl DmaInfo {
             dst: Addr24::new(0, 0),
             byte_counter: 0,
             bit_map_file: [[0; 8]; 2],
+            line: 0,
         }
     }
 }
@@ -370,7 +396,9 @@ pub struct Sa1 {
     bwram: [u8; BWRAM_SIZE],
     blocks: [Block; 4],
     cpu: Cpu,
+    /// The SA-1 cycles, until the CPU or DMA continues
     ahead_cycles: i32,
+    clock: ClockDivider,
     vectors: Vectors,
     snes_control_flags: u8,
     control_flags: u8,
@@ -413,7 +441,8 @@ impl Sa1 {
                 Block::new(3, 3), // Set Super MMC Bank F
             ],
             cpu: Cpu::new(),
-            ahead_cycles: 80,
+            ahead_cycles: 40,
+            clock: ClockDivider::new(clock::MASTER_NTSC, clock::sa1(false)),
             vectors: Vectors::new(),
             snes_control_flags: 0,
             control_flags: 0x20,
@@ -438,12 +467,18 @@ impl Sa1 {
     }
 
     pub fn set_region(&mut self, is_pal: bool) {
-        self.timer.set_region(is_pal)
+        self.timer.set_region(is_pal);
+        self.clock = ClockDivider::new(clock::master(is_pal), clock::sa1(is_pal))
     }
 
+    /// Reset the chip, like the reset button of the console does. The
+    /// battery backed BW-RAM and the I-RAM keep their content.
     pub fn reset(&mut self) {
-        // TODO: correctly implement resetting
-        *self = Self::new()
+        let old = core::mem::take(self);
+        self.iram = old.iram;
+        self.bwram = old.bwram;
+        self.timer.vend = old.timer.vend;
+        self.clock = old.clock;
     }
 
     pub fn cpu_mut(&mut self) -> &mut Cpu {
@@ -570,6 +605,62 @@ impl Sa1 {
         }
         self.bwram[(addr & 0x3_ffff) as usize] = val
     }
+
+    /// Write the bitplanes of a character row, which starts at `addr`, to I-RAM
+    fn write_bitplanes(&mut self, addr: u16, planes: &[u8]) {
+        for (i, &plane) in (0..).zip(planes) {
+            let addr = addr.wrapping_add(((i & 6) << 3) | (i & 1));
+            self.iram[usize::from(addr) & (IRAM_SIZE - 1)] = plane;
+        }
+    }
+
+    const fn is_converting_type1(&self) -> bool {
+        self.dma.running == dma_modes::TYPE1_WAITING
+    }
+
+    /// Read the byte `addr` of BW-RAM from the SNES during the character
+    /// conversion type 1. The SNES reads the converted characters from the
+    /// I-RAM buffer instead, each character is converted when the SNES reads
+    /// its first byte.
+    fn read_character_conversion(&mut self, addr: u32) -> u8 {
+        let bits = u32::from(self.dma.color_bits);
+        let char_size = bits * 8;
+        let dst = self.dma.dst.addr;
+        if addr & (char_size - 1) == 0 {
+            let src = linear(self.dma.src);
+            let width = u32::from(self.dma.vram_width);
+            let line_size = width * bits;
+            let tile = (addr.wrapping_sub(src) & (BWRAM_SIZE as u32 - 1)) / char_size;
+            let mut row_addr = src + (tile / width) * 8 * line_size + (tile % width) * bits;
+            for y in 0..8 {
+                let mut bytes = [0; 8];
+                for (i, byte) in (0..).zip(&mut bytes[..bits as usize]) {
+                    *byte = self.bwram[(row_addr + i) as usize & (BWRAM_SIZE - 1)];
+                }
+                row_addr += line_size;
+                let row = u64::from_le_bytes(bytes);
+                let mask = (1 << bits) - 1;
+                let pixels = core::array::from_fn(|x| ((row >> (x as u32 * bits)) & mask) as u8);
+                let planes = to_bitplanes(pixels);
+                self.write_bitplanes(dst.wrapping_add(y << 1), &planes[..bits as usize]);
+            }
+        }
+        let addr = u32::from(dst) + (addr & (char_size - 1));
+        self.iram[addr as usize & (IRAM_SIZE - 1)]
+    }
+
+    /// Convert the pixels of the next bit map register into a character row
+    /// in I-RAM (character conversion type 2)
+    fn convert_bit_map_line(&mut self) {
+        let bits = self.dma.color_bits;
+        let line = self.dma.line;
+        let planes = to_bitplanes(self.dma.bit_map_file[usize::from(line & 1)]);
+        // two characters of 8 lines each
+        let mut addr = self.dma.dst.addr & !((u16::from(bits) << 4) - 1);
+        addr += u16::from(line & 8) * u16::from(bits) + (u16::from(line & 7) << 1);
+        self.write_bitplanes(addr, &planes[..usize::from(bits)]);
+        self.dma.line = (line + 1) & 15;
+    }
 }
 
 pub struct AccessTypeSa1;
@@ -617,16 +708,27 @@ impl<'a, B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer>
         self.0.cartridge.as_mut().unwrap().sa1_mut()
     }
 
-    pub fn run_dma_normal(&mut self) {
-        todo!("SA-1 normal dma")
+    pub fn run_dma_normal(&mut self) -> u32 {
+        self.0.cartridge.as_mut().unwrap().run_sa1_dma_normal()
     }
 
-    pub fn run_dma_character_conversion_type1(&mut self) {
-        todo!("SA-1 character conversion type 1 dma")
+    pub fn run_dma_character_conversion_type1(&mut self) -> u32 {
+        // the SNES reads the characters from BW-RAM with its own DMA
+        let sa1 = self.sa1_mut();
+        sa1.dma.running = dma_modes::TYPE1_WAITING;
+        sa1.snes_interrupt_trigger |= 0x20;
+        if sa1.snes_interrupt_enable & 0x20 > 0 {
+            sa1.snes_interrupt_acknowledge &= !0x20;
+            sa1.snes_irq_pin = true;
+        }
+        0
     }
 
-    pub fn run_dma_character_conversion_type2(&mut self) {
-        todo!("SA-1 character conversion type 2 dma")
+    pub fn run_dma_character_conversion_type2(&mut self) -> u32 {
+        let sa1 = self.sa1_mut();
+        sa1.convert_bit_map_line();
+        sa1.dma.running = dma_modes::STOPPED;
+        u32::from(sa1.dma.color_bits) * 6
     }
 
     pub fn run_cpu<const N: u16>(&mut self) {
@@ -635,36 +737,37 @@ impl<'a, B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer>
             return;
         }
         let needs_refresh = sa1.ahead_cycles <= 0;
-        sa1.ahead_cycles -= i32::from(N);
+        sa1.clock.tick(N.into());
+        sa1.ahead_cycles -= sa1.clock.take() as i32;
         if needs_refresh {
             sa1.memory_cycles = 0;
-            let cycles = if sa1.dma.running != dma_modes::STOPPED {
-                match sa1.dma.running {
-                    dma_modes::NORMAL => self.run_dma_normal(),
-                    dma_modes::TYPE1 => self.run_dma_character_conversion_type1(),
-                    dma_modes::TYPE2 => self.run_dma_character_conversion_type2(),
-                    _ => panic!("unknown dma mode"),
-                }
-                0
-            } else {
-                // > WAI/HALT stops the CPU until an exception (usually an IRQ or NMI) request occurs
-                // > in case of IRQs this works even if IRQs are disabled (via I=1).
-                // source: FullSNES
-                if sa1.cpu.wait_mode || sa1.control_flags & 0x60 != 0 {
-                    sa1.cpu.wait_mode &= !sa1.shall_nmi() && !sa1.shall_irq();
-                    sa1.ahead_cycles += 1;
-                    return;
-                }
-                if sa1.shall_nmi() {
-                    self.nmi()
-                } else if sa1.shall_irq() {
-                    self.irq()
-                } else {
-                    self.dispatch_instruction() * 6
+            let cycles = match sa1.dma.running {
+                dma_modes::NORMAL => self.run_dma_normal(),
+                dma_modes::TYPE1 => self.run_dma_character_conversion_type1(),
+                dma_modes::TYPE2 => self.run_dma_character_conversion_type2(),
+                // the type 1 conversion waits for the SNES, not for the SA-1
+                dma_modes::STOPPED | dma_modes::TYPE1_WAITING => {
+                    // > WAI/HALT stops the CPU until an exception (usually an IRQ or NMI) request occurs
+                    // > in case of IRQs this works even if IRQs are disabled (via I=1).
+                    // source: FullSNES
+                    if sa1.cpu.wait_mode || sa1.control_flags & 0x60 != 0 {
+                        sa1.cpu.wait_mode &= !sa1.shall_nmi() && !sa1.shall_irq();
+                        sa1.ahead_cycles += 1;
+                        return;
+                    }
+                    if sa1.shall_nmi() {
+                        self.nmi()
+                    } else if sa1.shall_irq() {
+                        self.irq()
+                    } else {
+                        self.dispatch_instruction() * 6
+                    }
                 }
+                _ => panic!("unknown dma mode"),
             };
+            // the costs are counted in eighths of SA-1 cycles
             let sa1 = self.sa1_mut();
-            self.sa1_mut().ahead_cycles += ((cycles + sa1.memory_cycles) >> 2).max(1) as i32;
+            sa1.ahead_cycles += ((cycles + sa1.memory_cycles) >> 3).max(1) as i32;
         }
         let sa1 = self.sa1_mut();
         if sa1.timer.tick(N) {
@@ -675,7 +778,8 @@ impl<'a, B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer>
 }
 
 impl Cartridge {
-    fn read_varlen_part(&self, addr: Addr24) -> u8 {
+    /// Read a byte of the SA-1 bus without side effects
+    fn sa1_peek(&self, addr: Addr24) -> u8 {
         const FALLBACK: u8 = 0xff;
         if addr.bank & 0x40 == 0 {
             match addr.addr {
@@ -702,12 +806,12 @@ impl Cartridge {
         if is_high {
             addr.addr = addr.addr.wrapping_add(1);
         }
-        let val1 = self.read_varlen_part(addr);
+        let val1 = self.sa1_peek(addr);
         let val = if self.sa1_ref().varlen.bit_nr & 7 == 0 {
             val1
         } else {
             addr.addr = addr.addr.wrapping_add(1);
-            let val2 = self.read_varlen_part(addr);
+            let val2 = self.sa1_peek(addr);
             ((u16::from_le_bytes([val1, val2]) >> self.sa1_ref().varlen.bit_nr) & 0xff) as u8
         };
         if is_high && self.sa1_ref().varlen.auto_increment {
@@ -716,6 +820,45 @@ impl Cartridge {
         val
     }
 
+    /// Run a normal DMA transfer of the SA-1 at once and return its cycles
+    fn run_sa1_dma_normal(&mut self) -> u32 {
+        let dma = &self.sa1_ref().dma;
+        let (direction, count) = (dma.direction, dma.byte_counter);
+        let (mut src, mut dst) = (linear(dma.src), linear(dma.dst));
+        let mut cycles = 0;
+        for _ in 0..count {
+            let sa1 = self.sa1_ref();
+            let val = if direction.is_src_rom() {
+                cycles += 6;
+                self.sa1_peek(from_linear(src))
+            } else if direction.is_src_bwram() {
+                cycles += 12;
+                sa1.bwram[src as usize & (BWRAM_SIZE - 1)]
+            } else {
+                cycles += 6;
+                sa1.iram[src as usize & (IRAM_SIZE - 1)]
+            };
+            let sa1 = self.sa1_mut();
+            if direction.is_dst_bwram() {
+                cycles += 12;
+                sa1.bwram[dst as usize & (BWRAM_SIZE - 1)] = val;
+            } else {
+                cycles += 6;
+                sa1.iram[dst as usize & (IRAM_SIZE - 1)] = val;
+            }
+            src = (src + 1) & 0xff_ffff;
+            dst = (dst + 1) & 0xff_ffff;
+        }
+        let sa1 = self.sa1_mut();
+        sa1.dma.src = from_linear(src);
+        sa1.dma.dst = from_linear(dst);
+        sa1.dma.byte_counter = 0;
+        sa1.dma.running = dma_modes::STOPPED;
+        sa1.sa1_interrupt_trigger |= 0x20;
+        sa1.sa1_interrupt_acknowledge &= !(sa1.sa1_interrupt_enable & 0x20);
+        cycles
+    }
+
     pub fn sa1_read_io<const INTERNAL: bool>(&mut self, id: u16) -> Option<u8> {
         let sa1 = self.sa1_mut();
         const SA1: bool = true;
@@ -723,8 +866,6 @@ impl Cartridge {
         Some(match (id, INTERNAL) {
             (0x2300, SNES) => {
                 // SCNT - SNES Control flags
-                // TODO: IRQ from Character Conversion DMA
-                // TODO: IRQ from SA-1 to SNES
                 (sa1.snes_control_flags & 0x5f) | (sa1.snes_interrupt_trigger & 0xa0)
             }
             (0x2301, SA1) => {
@@ -869,14 +1010,26 @@ impl Cartridge {
                 sa1.dma.char_conversion = val & 0x20 > 0;
                 sa1.dma.priority = val & 0x40 > 0;
                 sa1.dma.enable = val & 0x80 > 0;
+                sa1.dma.line = 0;
             }
             (0x2231, _) => {
                 // CDMA - Character Conversion DMA Parameters
-                // TODO: what happens, when `color_bits = 1`?
                 // TODO: what happens, when `vram_width = 64 or 128`?
-                sa1.dma.color_bits = 1 << (!val & 3);
+                // the color depth 3 is reserved, it stops every character
+                // conversion and none can be started until it's changed
+                sa1.dma.color_bits = [8, 4, 2, 0][usize::from(val & 3)];
                 sa1.dma.vram_width = 1 << ((val >> 2) & 7);
                 sa1.dma.terminate = val & 0x80 > 0;
+                if (sa1.dma.terminate
+                    && matches!(sa1.dma.running, dma_modes::TYPE1 | dma_modes::TYPE1_WAITING))
+                    || (sa1.dma.color_bits == 0
+                        && matches!(
+                            sa1.dma.running,
+                            dma_modes::TYPE1 | dma_modes::TYPE1_WAITING | dma_modes::TYPE2
+                        ))
+                {
+                    sa1.dma.running = dma_modes::STOPPED
+                }
             }
             (0x2232..=0x2234, _) => {
                 // SDA - DMA source address
@@ -896,7 +1049,7 @@ impl Cartridge {
                         {
                             sa1.dma.running = dma_modes::NORMAL
                         }
-                    } else if id == 0x2236 && sa1.dma.is_automatic {
+                    } else if id == 0x2236 && sa1.dma.is_automatic && sa1.dma.color_bits > 0 {
                         sa1.dma.running = dma_modes::TYPE1
                     }
                 }
@@ -914,7 +1067,11 @@ impl Cartridge {
             (0x2240..=0x224f, SA1) => {
                 // BRF - Character Conversion DMA Bit Map
                 sa1.dma.bit_map_file[usize::from((id >> 3) & 1)][usize::from(id & 0x7)] = val;
-                if id & 7 == 7 && sa1.dma.enable && sa1.dma.char_conversion && !sa1.dma.is_automatic
+                if id & 7 == 7
+                    && sa1.dma.enable
+                    && sa1.dma.char_conversion
+                    && !sa1.dma.is_automatic
+                    && sa1.dma.color_bits > 0
                 {
                     sa1.dma.running = dma_modes::TYPE2
                 }
@@ -975,6 +1132,10 @@ impl Cartridge {
                     self.sa1_read_io::<INTERNAL>(addr.addr)
                 }
                 0x3000..=0x37ff => Some(sa1.iram[usize::from(addr.addr) & (IRAM_SIZE - 1)]),
+                0x6000..=0x7fff if !INTERNAL && sa1.is_converting_type1() => {
+                    let addr = sa1.get_bwram_small::<INTERNAL>(addr);
+                    Some(sa1.read_character_conversion(addr))
+                }
                 0x6000..=0x7fff => Some(sa1.read_bwram_small::<INTERNAL>(addr)),
                 0x8000..=0xffff => {
                     sa1.memory_cycles -= 6;
@@ -985,11 +1146,14 @@ impl Cartridge {
             }
         } else if addr.bank & 0x80 == 0 {
             match addr.bank & 0x30 {
+                0x00 if !INTERNAL && sa1.is_converting_type1() => {
+                    Some(sa1.read_character_conversion(linear(addr) & 0x3_ffff))
+                }
                 0x00 => {
                     Some(sa1.bwram[(usize::from(addr.bank & 3) << 16) | usize::from(addr.addr)])
                 }
                 0x20 => Some(
-                    sa1.read_bwram_bits((u32::from(addr.bank & 15) << 16) | u32::from(addr.bank)),
+                    sa1.read_bwram_bits((u32::from(addr.bank & 15) << 16) | u32::from(addr.addr)),
                 ),
                 _ => None,
             }
@@ -1022,7 +1186,7 @@ impl Cartridge {
                     sa1.bwram[(usize::from(addr.bank & 3) << 16) | usize::from(addr.addr)] = val
                 }
                 0x20 => sa1.write_bwram_bits(
-                    (u32::from(addr.bank & 15) << 16) | u32::from(addr.bank),
+                    (u32::from(addr.bank & 15) << 16) | u32::from(addr.addr),
                     val,
                 ),
                 _ => (),
@@ -1030,3 +1194,6 @@ impl Cartridge {
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,133 @@
+use super::*;
+use crate::test_util::{create_rom, TestDevice, SA1};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+
+/// A device with an SA-1 cartridge
+fn create_device() -> Box<TestDevice> {
+    crate::test_util::create_device(&create_rom(0x8000, SA1, 0x35, 0, |_| ()))
+}
+
+fn cartridge(device: &mut TestDevice) -> &mut Cartridge {
+    device.cartridge.as_mut().unwrap()
+}
+
+/// Write the registers from `id` on from the SA-1
+fn write_io(device: &mut TestDevice, id: u16, vals: &[u8]) {
+    for (id, &val) in (id..).zip(vals) {
+        cartridge(device).sa1_write::<true>(Addr24::new(0, id), val)
+    }
+}
+
+fn read_snes(device: &mut TestDevice, addr: Addr24) -> u8 {
+    cartridge(device).sa1_read::<false>(addr).unwrap()
+}
+
+#[test]
+pub fn test_dma_normal() {
+    let mut device = create_device();
+    for i in 0..4 {
+        cartridge(&mut device).sa1_write::<false>(Addr24::new(0, 0x3010 + i), i as u8 + 1);
+    }
+    // from I-RAM to BW-RAM
+    write_io(&mut device, 0x2230, &[0x86]);
+    write_io(&mut device, 0x2232, &[0x10, 0x00, 0x00]);
+    write_io(&mut device, 0x2238, &[4, 0]);
+    write_io(&mut device, 0x2235, &[0x00, 0x01, 0x40]);
+    assert_eq!(
+        cartridge(&mut device).sa1_ref().dma.running,
+        dma_modes::NORMAL
+    );
+    device.with_sa1_cpu().run_dma_normal();
+    let sa1 = cartridge(&mut device).sa1_ref();
+    assert_eq!(sa1.dma.running, dma_modes::STOPPED);
+    assert_eq!(sa1.sa1_interrupt_trigger & 0x20, 0x20);
+    assert_eq!(sa1.bwram[0x0ff..0x105], [0, 1, 2, 3, 4, 0]);
+}
+
+#[test]
+pub fn test_character_conversion_type1() {
+    let mut device = create_device();
+    // the first row of a 2 bpp character: pixels 0, 1, 2, 3, 0, 1, 2, 3
+    cartridge(&mut device).sa1_write::<false>(Addr24::new(0x40, 0x0000), 0xe4);
+    cartridge(&mut device).sa1_write::<false>(Addr24::new(0x40, 0x0001), 0xe4);
+    // enable the IRQ of the character conversion
+    cartridge(&mut device).sa1_write::<false>(Addr24::new(0, 0x2201), 0x20);
+    write_io(&mut device, 0x2230, &[0xb0]);
+    write_io(&mut device, 0x2231, &[0x02]);
+    write_io(&mut device, 0x2232, &[0x00, 0x00, 0x40]);
+    write_io(&mut device, 0x2235, &[0x00, 0x03]);
+    device.with_sa1_cpu().run_dma_character_conversion_type1();
+    assert!(device.get_irq_pin());
+    assert_eq!(read_snes(&mut device, Addr24::new(0x40, 0x0000)), 0x55);
+    assert_eq!(read_snes(&mut device, Addr24::new(0x40, 0x0001)), 0x33);
+    assert_eq!(read_snes(&mut device, Addr24::new(0x40, 0x0002)), 0x00);
+    // terminate the conversion
+    write_io(&mut device, 0x2231, &[0x82]);
+    assert_eq!(read_snes(&mut device, Addr24::new(0x40, 0x0000)), 0xe4);
+}
+
+#[test]
+pub fn test_character_conversion_type2() {
+    let mut device = create_device();
+    write_io(&mut device, 0x2230, &[0xa0]);
+    write_io(&mut device, 0x2231, &[0x02]);
+    write_io(&mut device, 0x2235, &[0x00, 0x02]);
+    for (id, pixels) in [(0x2240, [0, 1, 2, 3, 0, 1, 2, 3]), (0x2248, [3; 8])] {
+        write_io(&mut device, id, &pixels);
+        assert_eq!(
+            cartridge(&mut device).sa1_ref().dma.running,
+            dma_modes::TYPE2
+        );
+        device.with_sa1_cpu().run_dma_character_conversion_type2();
+    }
+    let sa1 = cartridge(&mut device).sa1_ref();
+    assert_eq!(sa1.iram[0x200..0x204], [0x55, 0x33, 0xff, 0xff]);
+}
+
+#[test]
+pub fn test_character_conversion_reserved_color_depth() {
+    let mut device = create_device();
+    let running = |device: &mut TestDevice| cartridge(device).sa1_ref().dma.running;
+    write_io(&mut device, 0x2230, &[0xa0]);
+    write_io(&mut device, 0x2231, &[0x03]);
+    write_io(&mut device, 0x2240, &[0; 8]);
+    assert_eq!(running(&mut device), dma_modes::STOPPED);
+    // a running conversion is stopped
+    write_io(&mut device, 0x2231, &[0x02]);
+    write_io(&mut device, 0x2240, &[0; 8]);
+    assert_eq!(running(&mut device), dma_modes::TYPE2);
+    write_io(&mut device, 0x2231, &[0x03]);
+    assert_eq!(running(&mut device), dma_modes::STOPPED);
+    // the automatic conversion isn't started either
+    write_io(&mut device, 0x2230, &[0xb0]);
+    write_io(&mut device, 0x2235, &[0x00, 0x03]);
+    assert_eq!(running(&mut device), dma_modes::STOPPED);
+}
+
+#[test]
+pub fn test_reset() {
+    let mut device = create_device();
+    cartridge(&mut device).sa1_write::<false>(Addr24::new(0, 0x3000), 0x12);
+    cartridge(&mut device).sa1_write::<false>(Addr24::new(0x40, 0x0000), 0x34);
+    write_io(&mut device, 0x2230, &[0x86]);
+    device.reset();
+    // the registers are reset, the memory is kept
+    assert!(!cartridge(&mut device).sa1_ref().dma.enable);
+    assert_eq!(read_snes(&mut device, Addr24::new(0, 0x3000)), 0x12);
+    assert_eq!(read_snes(&mut device, Addr24::new(0x40, 0x0000)), 0x34);
+}
+
+#[test]
+pub fn test_save_dma_line() {
+    let mut device = create_device();
+    cartridge(&mut device).sa1_mut().dma.line = 5;
+    let mut state = SaveStateSerializer { data: vec![] };
+    device.serialize(&mut state);
+    let mut loaded = create_device();
+    let mut deserializer = SaveStateDeserializer {
+        data: state.data.iter(),
+    };
+    loaded.deserialize(&mut deserializer);
+    assert_eq!(deserializer.data.len(), 0);
+    assert_eq!(cartridge(&mut loaded).sa1_mut().dma.line, 5);
+}
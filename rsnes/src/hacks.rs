@@ -0,0 +1,151 @@
+//! Compatibility hacks for single games
+//!
+//! Some games depend on details of the hardware, which aren't emulated
+//! exactly (yet). Instead of checks for these games spread over the
+//! emulation, every workaround is an entry of [`HACKS`], which names the
+//! games it is needed by with the CRC-32 of their ROM (see
+//! [`Cartridge::crc32`](crate::cartridge::Cartridge::crc32)).
+//!
+//! The hacks of a game are enabled, when its cartridge is loaded. They are
+//! listed by [`Device::hacks`] and can be toggled by [`Device::set_hack`],
+//! e.g. to check, whether a hack is still needed after the emulation was
+//! improved.
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+};
+
+/// The master cycles, for which the main CPU is paused by the DRAM refresh
+/// in every scanline
+pub const REFRESH_CYCLES: u16 = 40;
+
+/// What a hack changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Pause the main CPU for this count of master cycles for the DRAM
+    /// refresh of every scanline instead of [`REFRESH_CYCLES`]
+    RefreshCycles(u16),
+    /// Replace bytes of the ROM, starting at the ROM offset `offset`
+    RomPatch { offset: usize, bytes: &'static [u8] },
+}
+
+#[derive(Debug)]
+pub struct Hack {
+    /// A unique name in kebab-case
+    pub name: &'static str,
+    /// What the hack works around
+    pub description: &'static str,
+    /// The CRC-32s of the ROMs, which need the hack
+    pub crc32s: &'static [u32],
+    pub effect: Effect,
+}
+
+/// All known hacks
+pub static HACKS: &[Hack] = &[Hack {
+    name: "speedy-gonzales-refresh",
+    description: "Speedy Gonzales: Los Gatos Bandidos hangs in stage 6-1, unless the \
+                  main CPU runs a little longer in every scanline",
+    crc32s: &[0x4e9b_cc7b],
+    effect: Effect::RefreshCycles(36),
+}];
+
+/// A hack of the loaded game
+#[derive(Debug, Clone)]
+pub struct ActiveHack {
+    pub hack: &'static Hack,
+    pub enabled: bool,
+    /// The ROM bytes replaced by an enabled [`Effect::RomPatch`]
+    original: Vec<u8>,
+}
+
+/// The hacks of the loaded game
+#[derive(Debug, Clone, Default)]
+pub struct Hacks {
+    list: Vec<ActiveHack>,
+}
+
+impl Hacks {
+    /// Find the hacks for the ROM with the CRC-32 `crc32` in `registry`. The
+    /// hacks are disabled.
+    pub fn find(registry: &'static [Hack], crc32: u32) -> Self {
+        let list = registry
+            .iter()
+            .filter(|hack| hack.crc32s.contains(&crc32))
+            .map(|hack| ActiveHack {
+                hack,
+                enabled: false,
+                original: vec![],
+            })
+            .collect();
+        Self { list }
+    }
+}
+
+impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
+    /// Replace the hacks of the loaded game by the ones for it in `registry`
+    /// (usually [`HACKS`]) and enable them
+    pub fn load_hacks(&mut self, registry: &'static [Hack]) {
+        for i in 0..self.hacks.list.len() {
+            self.apply_hack(i, false)
+        }
+        let crc32 = self.cartridge.as_ref().map_or(0, |cart| cart.crc32());
+        self.hacks = Hacks::find(registry, crc32);
+        for i in 0..self.hacks.list.len() {
+            self.apply_hack(i, true)
+        }
+    }
+
+    /// The hacks of the loaded game
+    pub fn hacks(&self) -> &[ActiveHack] {
+        &self.hacks.list
+    }
+
+    /// Enable or disable the hack called `name`. Returns `false`, if the
+    /// loaded game has no hack with this name.
+    pub fn set_hack(&mut self, name: &str, enabled: bool) -> bool {
+        match self
+            .hacks
+            .list
+            .iter()
+            .position(|hack| hack.hack.name == name)
+        {
+            Some(i) => {
+                self.apply_hack(i, enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn apply_hack(&mut self, index: usize, enabled: bool) {
+        let active = &mut self.hacks.list[index];
+        if active.enabled == enabled {
+            return;
+        }
+        active.enabled = enabled;
+        match active.hack.effect {
+            Effect::RefreshCycles(cycles) => {
+                self.ppu.refresh_cycles = if enabled { cycles } else { REFRESH_CYCLES }
+            }
+            Effect::RomPatch { offset, bytes } => {
+                let rom = match &mut self.cartridge {
+                    Some(cart) => cart.rom_mut(),
+                    None => return,
+                };
+                // bytes beyond the end of the ROM are left out
+                let start = offset.min(rom.len());
+                let end = (offset + bytes.len()).min(rom.len());
+                if enabled {
+                    active.original = rom[start..end].to_vec();
+                    rom[start..end].copy_from_slice(&bytes[..end - start]);
+                } else {
+                    rom[start..end].copy_from_slice(&active.original);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,124 @@
+use super::*;
+use crate::device::Addr24;
+use crate::test_util::{create_cartridge, create_device, LOROM};
+
+fn create_rom() -> Vec<u8> {
+    crate::test_util::create_rom(0x8000, LOROM, 0, 0, |_| ())
+}
+
+/// A registry with a hack of each kind for `rom` and one for another game
+fn create_registry(rom: &[u8]) -> &'static [Hack] {
+    let crc32s = Box::leak(Box::new([crate::patch::crc32(rom)]));
+    Box::leak(Box::new([
+        Hack {
+            name: "refresh",
+            description: "a longer DRAM refresh",
+            crc32s,
+            effect: Effect::RefreshCycles(80),
+        },
+        Hack {
+            name: "patch",
+            description: "patch the first bytes of the ROM",
+            crc32s,
+            effect: Effect::RomPatch {
+                offset: 1,
+                bytes: &[0xea, 0xea],
+            },
+        },
+        Hack {
+            name: "other",
+            description: "a hack for another game",
+            crc32s: &[0],
+            effect: Effect::RefreshCycles(0),
+        },
+    ]))
+}
+
+/// Change the bytes `at..at + 4` of `rom`, so its CRC-32 becomes `crc32`.
+/// The CRC-32 is affine in the bits of the data, so the bits are found by
+/// solving a linear system.
+fn forge_crc32(rom: &mut [u8], at: usize, crc32: u32) {
+    use crate::patch::crc32 as crc;
+    rom[at..at + 4].fill(0);
+    let base = crc(rom);
+    // the change of the CRC-32 by every bit and the bits causing it
+    let mut rows: Vec<(u32, u32)> = (0..32)
+        .map(|bit| {
+            rom[at + bit / 8] ^= 1 << (bit % 8);
+            let change = crc(rom) ^ base;
+            rom[at + bit / 8] ^= 1 << (bit % 8);
+            (change, 1 << bit)
+        })
+        .collect();
+    for i in 0..32 {
+        let pivot = (i..32).find(|&j| rows[j].0 >> i & 1 > 0).unwrap();
+        rows.swap(i, pivot);
+        for j in 0..32 {
+            if j != i && rows[j].0 >> i & 1 > 0 {
+                rows[j] = (rows[j].0 ^ rows[i].0, rows[j].1 ^ rows[i].1)
+            }
+        }
+    }
+    let change = crc32 ^ base;
+    let bits = (0..32)
+        .filter(|&i| change >> i & 1 > 0)
+        .fold(0, |bits, i| bits ^ rows[i].1);
+    rom[at..at + 4].copy_from_slice(&bits.to_le_bytes());
+}
+
+#[test]
+pub fn test_known_hacks() {
+    for hack in HACKS {
+        let mut rom = create_rom();
+        forge_crc32(&mut rom, 0x100, hack.crc32s[0]);
+        // the hacks of the registry are enabled, when the cartridge is loaded
+        let device = create_device(&rom);
+        assert_eq!(device.cartridge().unwrap().crc32(), hack.crc32s[0]);
+        let active = device
+            .hacks()
+            .iter()
+            .find(|active| active.hack.name == hack.name);
+        assert!(active.unwrap().enabled, "{}", hack.name);
+        if let Effect::RefreshCycles(cycles) = hack.effect {
+            assert_eq!(device.ppu.refresh_cycles, cycles)
+        }
+    }
+}
+
+#[test]
+pub fn test_hacks_by_crc32() {
+    let rom = create_rom();
+    let mut device = create_device(&rom);
+    assert!(device.hacks().is_empty());
+    device.load_hacks(create_registry(&rom));
+    let names: Vec<_> = device.hacks().iter().map(|hack| hack.hack.name).collect();
+    assert_eq!(names, ["refresh", "patch"]);
+    assert!(device.hacks().iter().all(|hack| hack.enabled));
+    assert_eq!(device.ppu.refresh_cycles, 80);
+    let cart = device.cartridge().unwrap();
+    assert_eq!(cart.rom()[..4], [0, 0xea, 0xea, 0]);
+    assert_eq!(cart.crc32(), crate::patch::crc32(&rom));
+}
+
+#[test]
+pub fn test_toggle_hacks() {
+    let rom = create_rom();
+    let mut device = create_device(&rom);
+    device.load_hacks(create_registry(&rom));
+    assert!(device.set_hack("refresh", false));
+    assert!(device.set_hack("patch", false));
+    assert!(!device.set_hack("other", false));
+    assert_eq!(device.ppu.refresh_cycles, REFRESH_CYCLES);
+    assert_eq!(device.cartridge().unwrap().rom()[..4], [0; 4]);
+    assert!(device.set_hack("patch", true));
+    assert_eq!(
+        device
+            .cartridge_mut()
+            .unwrap()
+            .read_byte(Addr24::new(0, 0x8002)),
+        Some(0xea)
+    );
+    // loading another cartridge removes the hacks
+    device.load_cartridge(*create_cartridge(&rom));
+    assert!(device.hacks().is_empty());
+}
@@ -58,11 +58,28 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> AccessTyp
     for AccessTypeMain
 {
     fn read<D: Data>(device: &mut Device<B, FB>, addr: Addr24) -> D {
-        device.read::<D>(addr)
+        if !device.cpu_stepping.active {
+            return device.read::<D>(addr);
+        }
+        // every byte is read at its own cycle
+        let mut bytes = D::Arr::default();
+        for (i, byte) in bytes.as_mut().iter_mut().enumerate() {
+            let addr = Addr24::new(addr.bank, addr.addr.wrapping_add(i as u16));
+            device.step_cpu_access(addr);
+            *byte = device.read::<u8>(addr);
+        }
+        D::from_bytes(&bytes)
     }
 
     fn write<D: Data>(device: &mut Device<B, FB>, addr: Addr24, val: D) {
-        device.write::<D>(addr, val)
+        if !device.cpu_stepping.active {
+            return device.write::<D>(addr, val);
+        }
+        for (i, byte) in val.to_bytes().as_ref().iter().enumerate() {
+            let addr = Addr24::new(addr.bank, addr.addr.wrapping_add(i as u16));
+            device.step_cpu_access(addr);
+            device.write::<u8>(addr, *byte);
+        }
     }
 
     fn cpu(device: &Device<B, FB>) -> &Cpu {
@@ -151,6 +168,52 @@ impl<
         }
         D::from_bytes(&arr)
     }
+
+    /// Push data on the stack without wrapping the stack pointer to page 1 in
+    /// emulation mode. The instructions added by the 65816 push like this,
+    /// they have to call [`Self::end_native_stack`] afterwards.
+    pub fn push_native<D: Data>(&mut self, val: D) {
+        for d in val.to_bytes().as_ref().iter().rev() {
+            self.write(Addr24::new(0, self.cpu().regs.sp), *d);
+            self.cpu_mut().regs.sp = self.cpu().regs.sp.wrapping_sub(1);
+        }
+    }
+
+    /// Pull data from the stack like [`Self::push_native`] pushes it
+    pub fn pull_native<D: Data>(&mut self) -> D {
+        let mut arr = D::Arr::default();
+        for d in arr.as_mut() {
+            self.cpu_mut().regs.sp = self.cpu().regs.sp.wrapping_add(1);
+            *d = self.read(Addr24::new(0, self.cpu().regs.sp));
+        }
+        D::from_bytes(&arr)
+    }
+
+    /// Move the stack pointer back to page 1 in emulation mode
+    pub fn end_native_stack(&mut self) {
+        if self.cpu().regs.is_emulation {
+            self.cpu_mut().regs.sp = (self.cpu().regs.sp & 0xff) | 256
+        }
+    }
+
+    /// The address of an offset in the direct page. In emulation mode with a
+    /// page aligned direct page register, the address wraps inside of the page
+    /// like on the 6502.
+    pub fn direct_addr(&self, offset: u16) -> Addr24 {
+        let dp = self.cpu().regs.dp;
+        if self.cpu().regs.is_emulation && dp & 0xff == 0 {
+            Addr24::new(0, dp | (offset & 0xff))
+        } else {
+            Addr24::new(0, dp.wrapping_add(offset))
+        }
+    }
+
+    /// Read a 16-bit pointer from an offset in the direct page
+    fn read_direct_pointer(&mut self, offset: u16) -> u16 {
+        let low = self.read::<u8>(self.direct_addr(offset));
+        let high = self.read::<u8>(self.direct_addr(offset.wrapping_add(1)));
+        u16::from_le_bytes([low, high])
+    }
 }
 
 impl<
@@ -199,7 +262,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        let addr = self.read(Addr24::new(0, self.cpu().regs.dp.wrapping_add(addr.into())));
+        let addr = self.read_direct_pointer(addr.into());
         self.cpu().get_data_addr(addr)
     }
 
@@ -217,14 +280,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        Addr24::new(
-            0,
-            self.cpu()
-                .regs
-                .dp
-                .wrapping_add(addr.into())
-                .wrapping_add(val),
-        )
+        self.direct_addr(u16::from(addr).wrapping_add(val))
     }
 
     /// DP Indexed, X
@@ -272,7 +328,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        Addr24::new(0, self.cpu().regs.dp.wrapping_add(val.into()))
+        self.direct_addr(val.into())
     }
 
     /// DP Indexed Indirect, X
@@ -281,17 +337,12 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        let addr = self
-            .cpu()
-            .regs
-            .dp
-            .wrapping_add(if self.cpu().is_idx8() {
-                self.cpu().regs.x8().into()
-            } else {
-                self.cpu().regs.x
-            })
-            .wrapping_add(val.into());
-        let addr = self.read(Addr24::new(0, addr));
+        let x = if self.cpu().is_idx8() {
+            self.cpu().regs.x8().into()
+        } else {
+            self.cpu().regs.x
+        };
+        let addr = self.read_direct_pointer(x.wrapping_add(val.into()));
         self.cpu().get_data_addr(addr)
     }
 
@@ -322,8 +373,7 @@ impl<
         if self.cpu().regs.dp & 0xff > 0 {
             *cycles += 1
         }
-        let addr = addr.wrapping_add(self.cpu().regs.dp);
-        let addr = self.read::<u16>(Addr24::new(0, addr));
+        let addr = self.read_direct_pointer(addr);
         let y = if self.cpu().is_idx8() {
             self.cpu().regs.y & 0xff
         } else {
@@ -509,7 +559,8 @@ impl<
             }
             0x0b => {
                 // PHD - Push Direct Page
-                self.push(self.cpu().regs.dp)
+                self.push_native(self.cpu().regs.dp);
+                self.end_native_stack()
             }
             0x0c => {
                 // TSB - Test and set Bits from Absolute
@@ -740,8 +791,9 @@ impl<
             }
             0x22 => {
                 // JSR/JSL - Jump to Subroutine Long
-                self.push(start_addr.bank);
-                self.push(start_addr.addr.wrapping_add(3));
+                self.push_native(start_addr.bank);
+                self.push_native(start_addr.addr.wrapping_add(3));
+                self.end_native_stack();
                 let new_addr = self.load::<Addr24>();
                 self.cpu_mut().regs.pc = new_addr;
             }
@@ -810,7 +862,8 @@ impl<
             }
             0x2b => {
                 // PLD - Pull Direct Page Register
-                let dp = self.pull();
+                let dp = self.pull_native();
+                self.end_native_stack();
                 self.cpu_mut().regs.dp = dp;
                 self.cpu_mut().update_nz16(dp);
             }
@@ -1203,7 +1256,8 @@ impl<
                 // PER - Push PC + imm
                 let val = self.load::<u16>();
                 let val = self.cpu().regs.pc.addr.wrapping_add(val);
-                self.push(val)
+                self.push_native(val);
+                self.end_native_stack()
             }
             0x63 => {
                 // ADC - Stack Relative Add with Carry
@@ -1296,7 +1350,8 @@ impl<
             }
             0x6b => {
                 // RTL - Return from subroutine long
-                self.cpu_mut().regs.pc = self.pull();
+                self.cpu_mut().regs.pc = self.pull_native();
+                self.end_native_stack();
                 self.cpu_mut().regs.pc.addr = self.cpu().regs.pc.addr.wrapping_add(1);
             }
             0x6c => {
@@ -1917,7 +1972,8 @@ impl<
             }
             0xab => {
                 // PLB - Pull Data Bank
-                let db = self.pull();
+                let db = self.pull_native();
+                self.end_native_stack();
                 self.cpu_mut().regs.db = db;
                 self.cpu_mut().update_nz8(db)
             }
@@ -2330,9 +2386,15 @@ impl<
             }
             0xd4 => {
                 // PEI - Push 16-bit value from DP
-                let addr = self.load_direct(&mut cycles);
-                let val = self.read::<u16>(addr);
-                self.push(val);
+                let addr = self.load::<u8>();
+                if self.cpu().regs.dp & 0xff > 0 {
+                    cycles += 1
+                }
+                // the pointer doesn't wrap inside of the direct page
+                let addr = self.cpu().regs.dp.wrapping_add(addr.into());
+                let val = self.read::<u16>(Addr24::new(0, addr));
+                self.push_native(val);
+                self.end_native_stack()
             }
             0xd5 => {
                 // CMP - Compare A with DP Indexed, X
@@ -2550,7 +2612,8 @@ impl<
             0xf4 => {
                 // PEA - Push absolute value
                 let addr = self.load::<u16>();
-                self.push(addr)
+                self.push_native(addr);
+                self.end_native_stack()
             }
             0xf5 => {
                 // SBC - Subtract DP Indexed, X with carry
@@ -2608,7 +2671,8 @@ impl<
             0xfc => {
                 // JSR - Jump to Subroutine
                 let addr = self.load_indexed_indirect();
-                self.push(start_addr.addr.wrapping_add(2));
+                self.push_native(start_addr.addr.wrapping_add(2));
+                self.end_native_stack();
                 self.cpu_mut().regs.pc = addr;
             }
             0xfd => {
@@ -2728,149 +2792,65 @@ impl<
         }
     }
 
-    fn generic_add_carry8<const GT1: u8, const GT2: u16>(
-        &mut self,
-        op1: u8,
-        fu8: fn(u8, u8) -> u8,
-        gt8: fn(&u8, &u8) -> bool,
-        fu16: fn(u16, u16) -> u16,
-        gt16: fn(&u16, &u16) -> bool,
-    ) {
+    fn generic_add_carry8<const SUBTRACT: bool>(&mut self, op1: u8) {
         let op2 = self.cpu().regs.a8();
-        if self.cpu().regs.status.has(Status::DECIMAL) {
-            let res = (op1 & 0xf)
-                .wrapping_add(op2 & 0xf)
-                .wrapping_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let res = if gt8(&res, &GT1) { fu8(res, 6) } else { res };
-            let carry = (res > 0xf) as u16;
-            let res = u16::from(op1 & 0xf0)
-                .wrapping_add((op2 & 0xf0).into())
-                .wrapping_add(carry << 4)
-                .wrapping_add((res & 0xf).into());
-            self.cpu_mut().regs.status.set_if(
-                Status::OVERFLOW,
-                !(u16::from(op1) ^ u16::from(op2)) & (u16::from(op2) ^ res) & 0x80 > 0,
-            );
-            let res = if gt16(&res, &GT2) {
-                fu16(res, 0x60)
-            } else {
-                res
-            };
-            self.cpu_mut().regs.status.set_if(Status::CARRY, res > 0xff);
-            let res = (res & 0xff) as u8;
-            self.cpu_mut().update_nz8(res);
-            self.cpu_mut().regs.set_a8(res);
+        let carry = self.cpu().regs.status.has(Status::CARRY);
+        let (new, carry, overflow) = if self.cpu().regs.status.has(Status::DECIMAL) {
+            let (new, carry, overflow) = decimal_add::<SUBTRACT>(op1.into(), op2.into(), carry, 2);
+            ((new & 0xff) as u8, carry, overflow)
         } else {
             let (new, nc) = op1.overflowing_add(op2);
-            let (new, nc2) = new.overflowing_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let nc = nc ^ nc2;
-            self.cpu_mut().regs.status.set_if(Status::CARRY, nc);
+            let (new, nc2) = new.overflowing_add(carry as _);
             let op1v = op1 & 128;
-            let v = op1v == (op2 & 128) && op1v != (new & 128);
-            self.cpu_mut().regs.status.set_if(Status::OVERFLOW, v);
-            self.cpu_mut().update_nz8(new);
-            self.cpu_mut().regs.set_a8(new);
-        }
+            (new, nc ^ nc2, op1v == (op2 & 128) && op1v != (new & 128))
+        };
+        self.cpu_mut().regs.status.set_if(Status::CARRY, carry);
+        self.cpu_mut()
+            .regs
+            .status
+            .set_if(Status::OVERFLOW, overflow);
+        self.cpu_mut().update_nz8(new);
+        self.cpu_mut().regs.set_a8(new);
     }
 
     pub fn add_carry8(&mut self, op1: u8) {
-        self.generic_add_carry8::<9, 0x9f>(
-            op1,
-            u8::wrapping_add,
-            u8::gt,
-            u16::wrapping_add,
-            u16::gt,
-        )
+        self.generic_add_carry8::<false>(op1)
     }
 
     pub fn sub_carry8(&mut self, op1: u8) {
-        self.generic_add_carry8::<0xf, 0xff>(
-            !op1,
-            u8::wrapping_sub,
-            u8::le,
-            u16::wrapping_sub,
-            u16::le,
-        )
+        self.generic_add_carry8::<true>(!op1)
     }
 
-    fn generic_add_carry16<const GT1: u16, const GT2: u16, const GT3: u16, const GT4: u32>(
-        &mut self,
-        op1: u16,
-        f: fn(u16, u16) -> u16,
-        gt: fn(&u16, &u16) -> bool,
-        fu32: fn(u32, u32) -> u32,
-        gt32: fn(&u32, &u32) -> bool,
-    ) {
+    fn generic_add_carry16<const SUBTRACT: bool>(&mut self, op1: u16) {
         let op2 = self.cpu().regs.a;
-        if self.cpu().regs.status.has(Status::DECIMAL) {
-            let res = (op1 & 0xf)
-                .wrapping_add(op2 & 0xf)
-                .wrapping_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let res = if gt(&res, &GT1) { f(res, 6) } else { res };
-            let carry = (res > 0xf) as u16;
-            let res = (op1 & 0xf0)
-                .wrapping_add(op2 & 0xf0)
-                .wrapping_add(carry << 4)
-                .wrapping_add(res & 0xf);
-            let res = if gt(&res, &GT2) { f(res, 0x60) } else { res };
-            let carry = (res > 0xff) as u16;
-            let res = (op1 & 0xf00)
-                .wrapping_add(op2 & 0xf00)
-                .wrapping_add(carry << 8)
-                .wrapping_add(res & 0xff);
-            let res = if gt(&res, &GT3) { f(res, 0x600) } else { res };
-            let carry = (res > 0xfff) as u32;
-            let res = u32::from(op1 & 0xf000)
-                .wrapping_add((op2 & 0xf000).into())
-                .wrapping_add(carry << 12)
-                .wrapping_add((res & 0xfff).into());
-            self.cpu_mut().regs.status.set_if(
-                Status::OVERFLOW,
-                !(u32::from(op1) ^ u32::from(op2)) & (u32::from(op2) ^ res) & 0x8000 > 0,
-            );
-            let res = if gt32(&res, &GT4) {
-                fu32(res, 0x6000)
-            } else {
-                res
-            };
-            self.cpu_mut()
-                .regs
-                .status
-                .set_if(Status::CARRY, res > 0xffff);
-            let res = (res & 0xffff) as u16;
-            self.cpu_mut().update_nz16(res);
-            self.cpu_mut().regs.a = res
+        let carry = self.cpu().regs.status.has(Status::CARRY);
+        let (new, carry, overflow) = if self.cpu().regs.status.has(Status::DECIMAL) {
+            decimal_add::<SUBTRACT>(op1, op2, carry, 4)
         } else {
             let (new, nc) = op1.overflowing_add(op2);
-            let (new, nc2) = new.overflowing_add(self.cpu().regs.status.has(Status::CARRY) as _);
-            let nc = nc ^ nc2;
-            self.cpu_mut().regs.status.set_if(Status::CARRY, nc);
+            let (new, nc2) = new.overflowing_add(carry as _);
             let op1v = op1 & 0x8000;
-            let v = op1v == (op2 & 0x8000) && op1v != (new & 0x8000);
-            self.cpu_mut().regs.status.set_if(Status::OVERFLOW, v);
-            self.cpu_mut().update_nz16(new);
-            self.cpu_mut().regs.a = new;
-        }
+            (
+                new,
+                nc ^ nc2,
+                op1v == (op2 & 0x8000) && op1v != (new & 0x8000),
+            )
+        };
+        self.cpu_mut().regs.status.set_if(Status::CARRY, carry);
+        self.cpu_mut()
+            .regs
+            .status
+            .set_if(Status::OVERFLOW, overflow);
+        self.cpu_mut().update_nz16(new);
+        self.cpu_mut().regs.a = new;
     }
 
     pub fn add_carry16(&mut self, op1: u16) {
-        self.generic_add_carry16::<9, 0x9f, 0x9ff, 0x9fff>(
-            op1,
-            u16::wrapping_add,
-            u16::gt,
-            u32::wrapping_add,
-            u32::gt,
-        )
+        self.generic_add_carry16::<false>(op1)
     }
 
     pub fn sub_carry16(&mut self, op1: u16) {
-        self.generic_add_carry16::<0xf, 0xff, 0xfff, 0xffff>(
-            !op1,
-            u16::wrapping_sub,
-            u16::le,
-            u32::wrapping_sub,
-            u32::le,
-        )
+        self.generic_add_carry16::<true>(!op1)
     }
 
     pub fn branch_near(&mut self, cond: bool, cycles: &mut Cycles) {
@@ -3021,10 +3001,54 @@ impl<
         } else {
             self.push(self.cpu().regs.pc)
         }
-        self.push(self.cpu().regs.status.0);
+        // in emulation mode, the break flag tells BRK and interrupts apart
+        let status = if self.cpu().regs.is_emulation {
+            self.cpu().regs.status & !Status::BREAK
+        } else {
+            self.cpu().regs.status
+        };
+        self.push(status.0);
         self.cpu_mut().regs.status |= Status::IRQ_DISABLE;
         self.cpu_mut().regs.status &= !Status::DECIMAL;
         self.cpu_mut().regs.pc = Addr24::new(0, vector);
         48
     }
 }
+
+/// Add the lowest `digits` BCD digits of two values with the decimal flag set,
+/// where `op1` is already inverted for a subtraction.
+/// Returns the result, the carry and the overflow flag.
+///
+/// Invalid BCD digits are adjusted like by the 65816, which only corrects a
+/// digit by 6 if it carried (or didn't borrow for a subtraction). The overflow
+/// flag is calculated before the highest digit is corrected, the negative and
+/// zero flags are calculated from the corrected result.
+fn decimal_add<const SUBTRACT: bool>(
+    op1: u16,
+    op2: u16,
+    carry: bool,
+    digits: u32,
+) -> (u16, bool, bool) {
+    let (op1, op2) = (i32::from(op1), i32::from(op2));
+    let (mut res, mut carry, mut overflow) = (0i32, i32::from(carry), false);
+    for digit in 0..digits {
+        let shift = digit * 4;
+        let mask = 0xf << shift;
+        res = (op1 & mask) + (op2 & mask) + (carry << shift) + (res & ((1 << shift) - 1));
+        if digit + 1 == digits {
+            overflow = !(op1 ^ op2) & (op2 ^ res) & (8 << shift) != 0;
+        }
+        if SUBTRACT {
+            if res < 0x10 << shift {
+                res -= 6 << shift
+            }
+        } else if res >= 0xa << shift {
+            res += 6 << shift
+        }
+        carry = i32::from(res >= 0x10 << shift);
+    }
+    (res as u16, carry > 0, overflow)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,785 @@
+use super::*;
+use crate::test_util::{create_rom, TestDevice, LOROM};
+
+/// The tested instructions are executed from this address in WRAM
+const CODE: u16 = 0x1000;
+
+/// A LoROM cartridge, where every interrupt vector points to its own address
+fn create_device() -> Box<TestDevice> {
+    let rom = create_rom(0x8000, LOROM, 0, 0, |rom| {
+        for vector in (0xffe4..0x10000).step_by(2) {
+            let offset = vector - 0x8000;
+            rom[offset..offset + 2].copy_from_slice(&(vector as u16).to_le_bytes());
+        }
+    });
+    crate::test_util::create_device(&rom)
+}
+
+fn run(device: &mut TestDevice, code: &[u8]) {
+    for (i, byte) in code.iter().enumerate() {
+        device.write(Addr24::new(0, CODE + i as u16), *byte);
+    }
+    device.cpu.regs.pc = Addr24::new(0, CODE);
+    device.with_main_cpu().dispatch_instruction();
+}
+
+fn set_native(device: &mut TestDevice) {
+    device.cpu.set_emulation(false);
+    device.cpu.regs.status = Status(0);
+}
+
+/// An independent implementation of ADC and SBC, which calculates every digit
+/// separately. Returns the result and the status register.
+fn reference_add(a: u16, data: u16, status: Status, wide: bool, subtract: bool) -> (u16, Status) {
+    let (mask, sign, digits) = if wide {
+        (0xffff, 0x8000, 4)
+    } else {
+        (0xff, 0x80, 2)
+    };
+    let data = if subtract { !data & mask } else { data };
+    let mut carry = status.has(Status::CARRY) as i32;
+    let (result, overflow) = if status.has(Status::DECIMAL) {
+        let (mut result, mut overflow) = (0, false);
+        for digit in 0..digits {
+            let x = i32::from(a >> (4 * digit)) & 0xf;
+            let y = i32::from(data >> (4 * digit)) & 0xf;
+            let mut sum = x + y + carry;
+            if digit == digits - 1 {
+                overflow = (x ^ sum) & (y ^ sum) & 8 > 0;
+            }
+            if subtract && sum <= 0xf {
+                sum -= 6
+            } else if !subtract && sum > 9 {
+                sum += 6
+            }
+            carry = (sum > 0xf) as i32;
+            result |= ((sum & 0xf) as u16) << (4 * digit);
+        }
+        (result, overflow)
+    } else {
+        let sum = i32::from(a) + i32::from(data) + carry;
+        carry = (sum > mask as i32) as i32;
+        let result = sum as u16 & mask;
+        (result, (a ^ result) & (data ^ result) & sign > 0)
+    };
+    let mut status = status;
+    status.set_if(Status::CARRY, carry > 0);
+    status.set_if(Status::OVERFLOW, overflow);
+    status.set_if(Status::ZERO, result == 0);
+    status.set_if(Status::NEGATIVE, result & sign > 0);
+    (result, status)
+}
+
+#[test]
+pub fn test_add_carry8_exhaustive() {
+    let mut device = create_device();
+    set_native(&mut device);
+    device.cpu.regs.status = Status::ACCUMULATION;
+    for (opcode, subtract) in [(0x69, false), (0xe9, true)] {
+        for flags in [0, Status::CARRY.0, Status::DECIMAL.0, 0x09] {
+            let status = Status::ACCUMULATION | Status(flags);
+            for a in 0..=0xff {
+                for data in 0..=0xff {
+                    device.cpu.regs.status = status;
+                    device.cpu.regs.a = 0xab00 | a;
+                    run(&mut device, &[opcode, data as u8]);
+                    let (result, status) = reference_add(a, data, status, false, subtract);
+                    assert_eq!(
+                        (device.cpu.regs.a, device.cpu.regs.status.0),
+                        (0xab00 | result, status.0),
+                        "{opcode:02x} {data:02x} with A={a:02x}, P={flags:02x}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_add_carry16() {
+    // all patterns of valid, invalid and boundary digits
+    let digits = [0, 1, 5, 9, 0xa, 0xf];
+    let values: Vec<u16> = (0..digits.len().pow(4))
+        .map(|i| {
+            (0..4).fold(0, |value, digit| {
+                value | digits[i / digits.len().pow(digit) % digits.len()] << (4 * digit)
+            })
+        })
+        .collect();
+    let mut device = create_device();
+    set_native(&mut device);
+    for (opcode, subtract) in [(0x69, false), (0xe9, true)] {
+        for flags in [0, Status::CARRY.0, Status::DECIMAL.0, 0x09] {
+            for &a in &values {
+                for &data in &values {
+                    device.cpu.regs.status = Status(flags);
+                    device.cpu.regs.a = a;
+                    let [low, high] = data.to_le_bytes();
+                    run(&mut device, &[opcode, low, high]);
+                    let (result, status) = reference_add(a, data, Status(flags), true, subtract);
+                    assert_eq!(
+                        (device.cpu.regs.a, device.cpu.regs.status.0),
+                        (result, status.0),
+                        "{opcode:02x} {data:04x} with A={a:04x}, P={flags:02x}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+pub fn test_decimal_mode() {
+    const C: u8 = Status::CARRY.0;
+    const Z: u8 = Status::ZERO.0;
+    const V: u8 = Status::OVERFLOW.0;
+    const N: u8 = Status::NEGATIVE.0;
+    // (opcode, 16-bit, A, operand, carry in, result, flags)
+    let cases: &[(u8, bool, u16, u16, bool, u16, u8)] = &[
+        (0x69, false, 0x12, 0x34, false, 0x46, 0),
+        (0x69, false, 0x58, 0x46, true, 0x05, C | V),
+        (0x69, false, 0x99, 0x01, false, 0x00, C | Z),
+        (0x69, false, 0x81, 0x92, false, 0x73, C | V),
+        (0x69, false, 0x79, 0x00, true, 0x80, V | N),
+        (0x69, false, 0x0f, 0x01, false, 0x16, 0),
+        (0xe9, false, 0x46, 0x12, true, 0x34, C),
+        (0xe9, false, 0x40, 0x13, true, 0x27, C),
+        (0xe9, false, 0x32, 0x02, false, 0x29, C),
+        (0xe9, false, 0x12, 0x21, true, 0x91, N),
+        (0xe9, false, 0x00, 0x01, true, 0x99, N),
+        (0xe9, false, 0x80, 0x01, true, 0x79, C | V),
+        (0x69, true, 0x9999, 0x0001, false, 0x0000, C | Z),
+        (0x69, true, 0x1234, 0x8766, false, 0x0000, C | Z),
+        (0x69, true, 0x7999, 0x0001, false, 0x8000, V | N),
+        (0xe9, true, 0x0000, 0x0001, true, 0x9999, N),
+        (0xe9, true, 0x5000, 0x0001, true, 0x4999, C),
+    ];
+    let mut device = create_device();
+    set_native(&mut device);
+    for &(opcode, wide, a, data, carry, result, flags) in cases {
+        let size = if wide { 0 } else { Status::ACCUMULATION.0 };
+        device.cpu.regs.status = Status(size | Status::DECIMAL.0 | carry as u8);
+        device.cpu.regs.a = a;
+        let [low, high] = data.to_le_bytes();
+        run(&mut device, &[opcode, low, high]);
+        assert_eq!(
+            (device.cpu.regs.a, device.cpu.regs.status.0),
+            (result, size | Status::DECIMAL.0 | flags),
+            "{opcode:02x} {data:x} with A={a:x}, C={carry}"
+        );
+    }
+}
+
+#[test]
+pub fn test_stack_wrapping() {
+    // (code, S before, S after, addresses of the pushed bytes from the top)
+    let pushes: &[(&[u8], u16, u16, &[u16])] = &[
+        // PHA
+        (&[0x48], 0x100, 0x1ff, &[0x100]),
+        // PHP
+        (&[0x08], 0x100, 0x1ff, &[0x100]),
+        // JSR
+        (&[0x20, 0x00, 0x20], 0x100, 0x1fe, &[0x100, 0x1ff]),
+        // PHD
+        (&[0x0b], 0x100, 0x1fe, &[0x100, 0x0ff]),
+        // PEA
+        (&[0xf4, 0x34, 0x12], 0x100, 0x1fe, &[0x100, 0x0ff]),
+        // PER
+        (&[0x62, 0x00, 0x00], 0x100, 0x1fe, &[0x100, 0x0ff]),
+        // PEI
+        (&[0xd4, 0x00], 0x100, 0x1fe, &[0x100, 0x0ff]),
+        // JSL
+        (
+            &[0x22, 0x00, 0x20, 0x00],
+            0x101,
+            0x1fe,
+            &[0x101, 0x100, 0x0ff],
+        ),
+        // JSR (a,x)
+        (&[0xfc, 0x00, 0x20], 0x100, 0x1fe, &[0x100, 0x0ff]),
+    ];
+    for &(code, before, after, addresses) in pushes {
+        let mut device = create_device();
+        for addr in 0..0x300 {
+            device.write(Addr24::new(0, addr), 0xeeu8);
+        }
+        device.cpu.regs.sp = before;
+        // PEI pushes the pointer from outside of the filled memory
+        device.cpu.regs.dp = 0x0800;
+        run(&mut device, code);
+        assert_eq!(device.cpu.regs.sp, after, "{:02x}", code[0]);
+        let written: Vec<u16> = (0..0x300)
+            .filter(|&addr| device.read::<u8>(Addr24::new(0, addr)) != 0xee)
+            .collect();
+        let mut expected = addresses.to_vec();
+        expected.sort_unstable();
+        assert_eq!(written, expected, "{:02x}", code[0]);
+    }
+
+    // (code, S before, S after, addresses of the pulled bytes from the bottom)
+    let pulls: &[(&[u8], u16, u16, &[u16])] = &[
+        // PLA
+        (&[0x68], 0x1ff, 0x100, &[0x100]),
+        // RTS
+        (&[0x60], 0x1ff, 0x101, &[0x100, 0x101]),
+        // PLD
+        (&[0x2b], 0x1ff, 0x101, &[0x200, 0x201]),
+        // PLB
+        (&[0xab], 0x1ff, 0x100, &[0x200]),
+        // RTL
+        (&[0x6b], 0x1fe, 0x101, &[0x1ff, 0x200, 0x201]),
+    ];
+    for &(code, before, after, addresses) in pulls {
+        let mut device = create_device();
+        for (i, &addr) in addresses.iter().enumerate() {
+            device.write(Addr24::new(0, addr), 0x11u8 * (i as u8 + 1));
+        }
+        device.cpu.regs.sp = before;
+        run(&mut device, code);
+        assert_eq!(device.cpu.regs.sp, after, "{:02x}", code[0]);
+        let regs = &device.cpu.regs;
+        let pulled = match code[0] {
+            0x68 => u32::from(regs.a & 0xff),
+            0x60 => u32::from(regs.pc.addr.wrapping_sub(1)),
+            0x2b => u32::from(regs.dp),
+            0xab => u32::from(regs.db),
+            _ => (u32::from(regs.pc.bank) << 16) | u32::from(regs.pc.addr.wrapping_sub(1)),
+        };
+        let expected =
+            (0..addresses.len()).fold(0, |value, i| value | (0x11 * (i as u32 + 1)) << (8 * i));
+        assert_eq!(pulled, expected, "{:02x}", code[0]);
+    }
+}
+
+#[test]
+pub fn test_direct_page_wrapping() {
+    // (emulation, D, X, Y, code, address of the loaded byte)
+    type Case = (bool, u16, u16, u16, &'static [u8], u16);
+    let cases: &[Case] = &[
+        // LDA dp
+        (true, 0x0200, 0, 0, &[0xa5, 0xff], 0x02ff),
+        // LDA dp,X
+        (true, 0x0200, 2, 0, &[0xb5, 0xff], 0x0201),
+        (true, 0x0201, 2, 0, &[0xb5, 0xff], 0x0302),
+        (false, 0x0200, 2, 0, &[0xb5, 0xff], 0x0301),
+        // LDX dp,Y
+        (true, 0x0200, 0, 3, &[0xb6, 0xfe], 0x0201),
+        // ASL dp,X
+        (true, 0x0200, 1, 0, &[0x16, 0xff], 0x0200),
+        // LDA (dp), pointer at 0x02ff and 0x0200
+        (true, 0x0200, 0, 0, &[0xb2, 0xff], 0x0280),
+        (false, 0x0200, 0, 0, &[0xb2, 0xff], 0x0380),
+        // LDA (dp,X), pointer at 0x02ff and 0x0200
+        (true, 0x0200, 1, 0, &[0xa1, 0xfe], 0x0280),
+        (true, 0x0200, 2, 0, &[0xa1, 0xfe], 0x0202),
+        // LDA (dp),Y, pointer at 0x02ff and 0x0200
+        (true, 0x0200, 0, 1, &[0xb1, 0xff], 0x0281),
+        (true, 0x0201, 0, 1, &[0xb1, 0xfe], 0x0381),
+        // LDA [dp], pointer at 0x02ff, 0x0300 and 0x0301
+        (true, 0x0200, 0, 0, &[0xa7, 0xff], 0x0380),
+    ];
+    for &(emulation, dp, x, y, code, addr) in cases {
+        let mut device = create_device();
+        if !emulation {
+            set_native(&mut device);
+            device.cpu.regs.status = Status::ACCUMULATION | Status::INDEX_REGISTER_SIZE;
+        }
+        device.cpu.regs.dp = dp;
+        device.cpu.regs.x = x;
+        device.cpu.regs.y = y;
+        device.cpu.regs.db = 0;
+        // pointers
+        for (ptr, value) in [
+            (0x0200, 0x02),
+            (0x0201, 0x02),
+            (0x02ff, 0x80),
+            (0x0300, 0x03),
+        ] {
+            device.write(Addr24::new(0, ptr), value as u8);
+        }
+        device.write(Addr24::new(0, 0x0301), 0u8);
+        let old = device.read::<u8>(Addr24::new(0, addr));
+        device.write(Addr24::new(0, addr), 0x5au8.wrapping_add(old));
+        let expected = device.read::<u8>(Addr24::new(0, addr));
+        run(&mut device, code);
+        let loaded = match code[0] {
+            0xb6 => device.cpu.regs.x8(),
+            0x16 => device.read::<u8>(Addr24::new(0, addr)) >> 1,
+            _ => device.cpu.regs.a8(),
+        };
+        assert_eq!(loaded, expected, "{code:02x?} with D={dp:04x}");
+    }
+}
+
+#[test]
+pub fn test_interrupts() {
+    // (emulation, code or interrupt, vector, pushed bytes from the top)
+    enum Kind {
+        Code(&'static [u8]),
+        Irq,
+        Nmi,
+    }
+    let cases: &[(bool, Kind, u16, &[u8])] = &[
+        (true, Kind::Code(&[0x00, 0x00]), 0xfffe, &[0x10, 0x02, 0x30]),
+        (true, Kind::Code(&[0x02, 0x00]), 0xfff4, &[0x10, 0x02, 0x30]),
+        (true, Kind::Irq, 0xfffe, &[0x10, 0x00, 0x20]),
+        (true, Kind::Nmi, 0xfffa, &[0x10, 0x00, 0x20]),
+        (
+            false,
+            Kind::Code(&[0x00, 0x00]),
+            0xffe6,
+            &[0x00, 0x10, 0x02, 0x00],
+        ),
+        (
+            false,
+            Kind::Code(&[0x02, 0x00]),
+            0xffe4,
+            &[0x00, 0x10, 0x02, 0x00],
+        ),
+        (false, Kind::Irq, 0xffee, &[0x00, 0x10, 0x00, 0x00]),
+        (false, Kind::Nmi, 0xffea, &[0x00, 0x10, 0x00, 0x00]),
+    ];
+    for (emulation, kind, vector, pushed) in cases {
+        let mut device = create_device();
+        if !emulation {
+            set_native(&mut device);
+        } else {
+            device.cpu.regs.status = Status::RESET_DEFAULT & !Status::IRQ_DISABLE;
+        }
+        device.cpu.regs.sp = 0x1ff;
+        device.cpu.regs.pc = Addr24::new(0, CODE);
+        match kind {
+            Kind::Code(code) => run(&mut device, code),
+            Kind::Irq => drop(device.with_main_cpu().irq()),
+            Kind::Nmi => drop(device.with_main_cpu().nmi()),
+        }
+        assert_eq!(device.cpu.regs.pc, Addr24::new(0, *vector));
+        let stack: Vec<u8> = (0..pushed.len() as u16)
+            .map(|i| device.read::<u8>(Addr24::new(0, 0x1ff - i)))
+            .collect();
+        assert_eq!(&stack, pushed, "vector {vector:04x}");
+        let status = device.cpu.regs.status;
+        assert!(status.has(Status::IRQ_DISABLE) && !status.has(Status::DECIMAL));
+    }
+}
+
+#[test]
+pub fn test_emulation_status() {
+    let mut device = create_device();
+    // REP #$30
+    run(&mut device, &[0xc2, 0x30]);
+    assert_eq!(device.cpu.regs.status.0 & 0x30, 0x30);
+    // PLP
+    device.write(Addr24::new(0, 0x1ff), 0u8);
+    device.cpu.regs.sp = 0x1fe;
+    run(&mut device, &[0x28]);
+    assert_eq!(device.cpu.regs.status.0, 0x30);
+}
+
+#[test]
+pub fn test_memory_access_cycles() {
+    let mut device = create_device();
+    // LDA $2137 - latch the H/V counters
+    for (i, byte) in [0xad, 0x37, 0x21].into_iter().enumerate() {
+        device.write(Addr24::new(0, CODE + i as u16), byte as u8);
+    }
+    device.cpu.regs.pc = Addr24::new(0, CODE);
+    device.cpu_ahead_cycles = 0;
+    device.ppu.mut_pos().x = 100;
+    device.run_cycle::<2>();
+    let low = device.read::<u8>(Addr24::new(0, 0x213c));
+    let high = device.read::<u8>(Addr24::new(0, 0x213c));
+    let h = u16::from(low) | (u16::from(high & 1) << 8);
+    // with stepping, the register is read after three accesses to WRAM
+    let expected = if cfg!(feature = "cycle-accurate") {
+        100 + 3 * 8
+    } else {
+        100
+    };
+    assert_eq!(h, expected);
+}
+
+#[test]
+pub fn test_bus_log() {
+    use crate::bus_log::{Access, AccessKind, BusLog, Filter, Origin};
+    let mut device = create_device();
+    let buffer = Addr24::new(0, 0x300);
+    let mut log = BusLog::new(16);
+    log.include
+        .push(Filter::range(buffer, Addr24::new(0, 0x3ff)));
+    log.include.push(Filter::range(
+        Addr24::new(0, 0x2180),
+        Addr24::new(0, 0x2180),
+    ));
+    log.exclude.push(Filter {
+        kind: Some(AccessKind::Read),
+        origin: Some(Origin::Cpu),
+        ..Filter::range(buffer, buffer)
+    });
+    device.bus_log = Some(log);
+    // LDA #$42 ; STA $0300 ; LDA $0300
+    device.cpu.regs.a = 0x42;
+    run(&mut device, &[0x8d, 0x00, 0x03]);
+    run(&mut device, &[0xad, 0x00, 0x03]);
+    // DMA the byte from $0300 to WMDATA
+    for (addr, value) in [
+        (0x4300, 0),
+        (0x4301, 0x80),
+        (0x4302, 0),
+        (0x4303, 3),
+        (0x4304, 0),
+    ] {
+        device.write(Addr24::new(0, addr), value as u8);
+    }
+    device.write(Addr24::new(0, 0x4305), 1u16);
+    device.write(Addr24::new(0, 0x420b), 1u8);
+    device.cpu_ahead_cycles = 0;
+    for _ in 0..64 {
+        device.run_cycle::<2>();
+    }
+    let access = |addr, kind, origin| Access {
+        addr: Addr24::new(0, addr),
+        value: 0x42,
+        kind,
+        origin,
+    };
+    assert_eq!(
+        device.bus_log.as_mut().unwrap().take(),
+        [
+            access(0x300, AccessKind::Write, Origin::Cpu),
+            access(0x300, AccessKind::Read, Origin::Dma),
+            access(0x2180, AccessKind::Write, Origin::Dma),
+        ]
+    );
+}
+
+#[test]
+pub fn test_breakpoints_and_watchpoints() {
+    use crate::bus_log::{Access, AccessKind, Filter, Origin};
+    use crate::debugger::{BreakReason, Debugger};
+    let mut device = create_device();
+    // LDA #$42 ; STA $0300 ; BRA -2
+    for (i, byte) in [0xa9u8, 0x42, 0x8d, 0x00, 0x03, 0x80, 0xfe]
+        .iter()
+        .enumerate()
+    {
+        device.write(Addr24::new(0, CODE + i as u16), *byte);
+    }
+    device.cpu.regs.pc = Addr24::new(0, CODE);
+    device.cpu_ahead_cycles = 0;
+    let store = Addr24::new(0, CODE + 2);
+    let mut debugger = Debugger::new();
+    debugger.breakpoints.insert(store);
+    debugger.watchpoints.push(Filter {
+        kind: Some(AccessKind::Write),
+        ..Filter::range(Addr24::new(0, 0x300), Addr24::new(0, 0x300))
+    });
+    device.debugger = Some(debugger);
+    let run_until_break = |device: &mut TestDevice| {
+        for _ in 0..256 {
+            device.run_cycle::<2>();
+            if let Some(reason) = device.debugger.as_ref().unwrap().break_reason() {
+                return *reason;
+            }
+        }
+        panic!("the emulation didn't break");
+    };
+    assert_eq!(run_until_break(&mut device), BreakReason::Breakpoint(store));
+    // the CPU stays in front of the instruction
+    for _ in 0..64 {
+        device.run_cycle::<2>();
+    }
+    assert_eq!(device.cpu.regs.pc, store);
+    device.debugger.as_mut().unwrap().resume();
+    let access = Access {
+        addr: Addr24::new(0, 0x300),
+        value: 0x42,
+        kind: AccessKind::Write,
+        origin: Origin::Cpu,
+    };
+    assert_eq!(
+        run_until_break(&mut device),
+        BreakReason::Watchpoint(access)
+    );
+    assert_eq!(device.cpu.regs.pc, Addr24::new(0, CODE + 5));
+    // the branch loops without writing again
+    device.debugger.as_mut().unwrap().resume();
+    for _ in 0..256 {
+        device.run_cycle::<2>();
+    }
+    assert!(device.debugger.as_ref().unwrap().break_reason().is_none());
+}
+
+#[test]
+pub fn test_cpu_trace() {
+    use crate::trace::{CpuTrace, Format};
+    let mut device = create_device();
+    set_native(&mut device);
+    // SEP #$20 ; LDA #$42 ; BRA -4
+    for (i, byte) in [0xe2u8, 0x20, 0xa9, 0x42, 0x80, 0xfc].iter().enumerate() {
+        device.write(Addr24::new(0, CODE + i as u16), *byte);
+    }
+    device.cpu.regs.pc = Addr24::new(0, CODE);
+    device.cpu.regs.sp = 0x1ff;
+    device.cpu_ahead_cycles = 0;
+    device.cpu_trace = Some(CpuTrace::new(4));
+    for _ in 0..256 {
+        device.run_cycle::<2>();
+    }
+    let trace = device.cpu_trace.as_mut().unwrap();
+    assert_eq!(trace.len(), 4);
+    assert!(trace.dropped() > 0);
+    let entries = trace.take();
+    assert!(trace.is_empty() && trace.dropped() == 0);
+    // only the loop is left in the buffer
+    let pcs: Vec<u16> = entries.iter().map(|entry| entry.regs.pc.addr).collect();
+    assert!(pcs.windows(2).all(|pcs| pcs[0] != pcs[1]));
+    assert!(pcs.iter().all(|&pc| [CODE + 2, CODE + 4].contains(&pc)));
+    assert!(entries
+        .windows(2)
+        .all(|entries| entries[0].master_cycles < entries[1].master_cycles));
+    let mut entry = entries[0].clone();
+    entry.regs.pc = Addr24::new(0, CODE + 2);
+    entry.regs.a = 0x1242;
+    entry.master_cycles = 1234;
+    entry.pos = crate::ppu::RayPos { x: 186, y: 7 };
+    assert_eq!(
+        entry.to_line(Format::Rsnes),
+        "00:1002  a9 42        lda #$42         A:1242 X:0000 Y:0000 S:01ff D:0000 \
+         DB:00 P:nvMxdizc E:0 V:  7 H: 186 C:1234"
+    );
+    assert_eq!(
+        entry.to_line(Format::Bsnes),
+        "001002 lda #$42               A:1242 X:0000 Y:0000 S:01ff D:0000 B:00 \
+         nvMxdizc V:  7 H: 186"
+    );
+    assert_eq!(
+        entry.to_line(Format::Mesen),
+        "001002  LDA #$42               A:1242 X:0000 Y:0000 S:01FF D:0000 DB:00 \
+         P:nvMxdizc V:7 H:186 Cycle:1234"
+    );
+}
+
+#[test]
+pub fn test_controller_read_across_state_load() {
+    use crate::controller::{buttons, Controller};
+    use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+    let mut device = create_device();
+    let set_buttons = |device: &mut TestDevice, pressed: u16| {
+        if let Controller::Standard(cntrl) = &mut device.controllers.port1.controller {
+            cntrl.pressed_buttons = pressed
+        }
+    };
+    let read_bits = |device: &mut TestDevice, n: usize| -> Vec<u8> {
+        (0..n)
+            .map(|_| device.read::<u8>(Addr24::new(0, 0x4016)) & 1)
+            .collect()
+    };
+    set_buttons(&mut device, buttons::B | buttons::START);
+    device.write(Addr24::new(0, 0x4016), 1u8);
+    // while strobing, the current state of B is read
+    assert_eq!(read_bits(&mut device, 2), [1, 1]);
+    set_buttons(&mut device, buttons::Y | buttons::START);
+    assert_eq!(read_bits(&mut device, 1), [0]);
+    device.write(Addr24::new(0, 0x4016), 0u8);
+    assert_eq!(read_bits(&mut device, 2), [0, 1]);
+
+    let mut state = SaveStateSerializer { data: vec![] };
+    device.serialize(&mut state);
+    let expected = read_bits(&mut device, 18);
+    assert_eq!(expected[..2], [0, 1]);
+    assert_eq!(expected[14..], [1; 4]);
+
+    // the frontend sets new buttons after the state was loaded
+    set_buttons(&mut device, 0);
+    device.deserialize(&mut SaveStateDeserializer {
+        data: state.data.iter(),
+    });
+    set_buttons(&mut device, buttons::A);
+    assert_eq!(read_bits(&mut device, 18), expected);
+}
+
+#[test]
+pub fn test_stats() {
+    let mut device = create_device();
+    // DMA four bytes from $0300 to WMDATA and enable the NMI
+    for (addr, value) in [
+        (0x4300, 0),
+        (0x4301, 0x80),
+        (0x4302, 0),
+        (0x4303, 3),
+        (0x4304, 0),
+    ] {
+        device.write(Addr24::new(0, addr), value as u8);
+    }
+    device.write(Addr24::new(0, 0x4305), 4u16);
+    device.write(Addr24::new(0, 0x420b), 1u8);
+    device.write(Addr24::new(0, 0x4200), 0x80u8);
+    while device.stats().frames < 1 {
+        device.run_cycle::<2>();
+    }
+    let stats = device.reset_stats();
+    // a frame of 262 lines of 1364 master cycles, and the cycles the
+    // components were stepped into the next frame
+    assert_eq!(device.ppu.get_pos().y, 0);
+    let ahead = u64::from(device.ppu.get_pos().x);
+    assert_eq!(stats.master_cycles, 262 * 1364 + ahead);
+    assert_eq!(stats.dma_bytes, 4);
+    assert_eq!(stats.nmis, 1);
+    assert_eq!(stats.irqs, 0);
+    // the S-DSP outputs 32000 samples per second
+    assert!((500..=540).contains(&stats.apu_samples));
+    assert_eq!(device.stats(), Default::default());
+}
+
+#[test]
+pub fn test_samples_this_frame() {
+    let mut device = create_device();
+    let mut samples = 0;
+    for _ in 0..10 {
+        device.run_cycle::<2>();
+        while !device.new_frame {
+            device.run_cycle::<2>();
+        }
+        // about 32040 samples per second at 60.1 frames per second
+        assert!((532..=534).contains(&device.samples_this_frame()));
+        samples += u64::from(device.samples_this_frame());
+    }
+    assert_eq!(device.stats().apu_samples, samples);
+}
+
+#[test]
+pub fn test_reset() {
+    let mut device = create_device();
+    device.write(Addr24::new(0, 0x1234), 0x56u8);
+    device.write(Addr24::new(0, 0x4200), 0x81u8);
+    device.cpu.regs.pc = Addr24::new(0, CODE);
+    device.reset();
+    // the reset vector at $fffc points to its own address
+    assert_eq!(device.cpu.regs.pc, Addr24::new(0, 0xfffc));
+    assert_eq!(device.cpu.nmitimen, 0);
+    assert_eq!(device.read::<u8>(Addr24::new(0, 0x1234)), 0x56);
+}
+
+#[test]
+pub fn test_programmable_io() {
+    let mut device = create_device();
+    let [wrio, rdio, slhv, stat78] = [0x4201, 0x4213, 0x2137, 0x213f].map(|a| Addr24::new(0, a));
+    // STAT78 tells, whether the counters were latched since it was read
+    let latched = |device: &mut TestDevice| device.read::<u8>(stat78) & 0x40 > 0;
+    assert_eq!(device.read::<u8>(rdio), 0xff);
+    // the falling edge of the line of port 2 latches the counters
+    device.write(wrio, 0x7eu8);
+    assert!(latched(&mut device));
+    assert!(!latched(&mut device));
+    assert_eq!(device.read::<u8>(rdio), 0x7e);
+    device.write(wrio, 0x3eu8);
+    assert!(!latched(&mut device));
+    // neither the software latch nor a light gun latch, while it's held low
+    device.read::<u8>(slhv);
+    device.pulse_io_line();
+    assert!(!latched(&mut device));
+    device.write(wrio, 0xffu8);
+    device.read::<u8>(slhv);
+    assert!(latched(&mut device));
+    device.pulse_io_line();
+    assert!(latched(&mut device));
+    device.write(wrio, 0u8);
+    device.reset();
+    assert_eq!(device.read::<u8>(rdio), 0xff);
+}
+
+#[test]
+pub fn test_profiling() {
+    use crate::profile::Component;
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use core::time::Duration;
+    // a clock advancing by a microsecond on every reading
+    static TIME: AtomicU64 = AtomicU64::new(0);
+    fn clock() -> Duration {
+        Duration::from_micros(TIME.fetch_add(1, Ordering::Relaxed))
+    }
+    let mut device = create_device();
+    assert!(device.profiler().is_none());
+    device.enable_profiling(clock);
+    device.run_cycle::<2>();
+    while !device.new_frame {
+        device.run_cycle::<2>();
+    }
+    let profiler = device.profiler().unwrap();
+    for component in [
+        Component::Cpu,
+        Component::Dma,
+        Component::Ppu,
+        Component::Smp,
+    ] {
+        assert!(profiler.time(component) > Duration::ZERO, "{component:?}");
+    }
+    // the time until the last switch is accounted
+    let readings = TIME.load(Ordering::Relaxed);
+    assert_eq!(profiler.total(), Duration::from_micros(readings - 1));
+}
+
+#[test]
+pub fn test_deterministic_mode() {
+    use crate::backend::FrameBuffer;
+    use crate::controller::Controller;
+    use crate::deterministic::frame_hash;
+    // turn the screen on, enable the automatic joypad read and show the
+    // buttons of port 1 as backdrop color in an endless loop
+    #[rustfmt::skip]
+    let code = [
+        0xa9, 0x0f, 0x8d, 0x00, 0x21, // LDA #$0f; STA $2100
+        0xa9, 0x01, 0x8d, 0x00, 0x42, // LDA #$01; STA $4200
+        0x9c, 0x21, 0x21,             // STZ $2121
+        0xad, 0x18, 0x42, 0x8d, 0x22, 0x21, // LDA $4218; STA $2122
+        0xad, 0x19, 0x42, 0x8d, 0x22, 0x21, // LDA $4219; STA $2122
+        0x80, 0xf2,                   // BRA -14
+    ];
+    // pseudo-random buttons for every frame
+    let mut seed = 0x1234_5678u32;
+    let input: Vec<u16> = (0..40)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u16 & 0xfff
+        })
+        .collect();
+    let set_buttons = |device: &mut TestDevice, pressed: u16| {
+        if let Controller::Standard(cntrl) = &mut device.controllers.port1.controller {
+            cntrl.pressed_buttons = pressed
+        }
+    };
+    // the buttons of the next frame are set after `delay` cycles of the
+    // current frame, which the game doesn't see until the next frame starts
+    let record = |delay: usize| -> Vec<u64> {
+        let mut device = create_device();
+        device.enable_deterministic_mode();
+        assert!(device.is_deterministic());
+        run(&mut device, &code);
+        set_buttons(&mut device, input[0]);
+        let mut hashes = vec![];
+        for buttons in input.iter().skip(1).chain([&0]) {
+            let mut cycles = 0;
+            device.run_cycle::<2>();
+            while !device.new_frame {
+                cycles += 1;
+                if cycles == delay {
+                    set_buttons(&mut device, *buttons)
+                }
+                device.run_cycle::<2>();
+            }
+            if delay == 0 {
+                set_buttons(&mut device, *buttons)
+            }
+            hashes.push(frame_hash(device.ppu.frame_buffer.pixels()));
+        }
+        hashes
+    };
+    let hashes = record(0);
+    assert_eq!(hashes, record(0));
+    assert_eq!(hashes, record(50_000));
+    // the frames actually depend on the input
+    assert!(hashes[2..].iter().any(|hash| *hash != hashes[1]));
+}
@@ -0,0 +1,57 @@
+//! Integrity mode
+//!
+//! Achievement systems in hardcore mode and verified races require that the
+//! game is played without any advantage by the emulator. When the integrity
+//! mode of a [`Device`](crate::device::Device) is enabled with
+//! [`Device::enable_integrity_mode`](crate::device::Device::enable_integrity_mode),
+//! the core refuses all [`Restricted`] features. The mode can't be disabled
+//! again, a new device has to be created instead.
+
+/// The maximum count of frames [`RunAhead`](crate::run_ahead::RunAhead) may
+/// emulate ahead in integrity mode. Hiding the input lag of the original
+/// hardware doesn't give an advantage.
+pub const MAX_RUN_AHEAD_FRAMES: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restricted {
+    Cheats,
+    /// Writing memory with [`Device::poke`](crate::device::Device::poke)
+    Poke,
+    /// Running the emulation slower than the original hardware
+    Slowdown,
+    /// Running ahead the given count of frames
+    RunAhead(u32),
+    LoadState,
+}
+
+impl Restricted {
+    /// Whether the feature may be used in integrity mode
+    pub fn is_allowed(self) -> bool {
+        matches!(self, Self::RunAhead(frames) if frames <= MAX_RUN_AHEAD_FRAMES)
+    }
+}
+
+impl std::fmt::Display for Restricted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Cheats => write!(f, "cheats"),
+            Self::Poke => write!(f, "writing memory"),
+            Self::Slowdown => write!(f, "slowdown"),
+            Self::RunAhead(frames) => write!(
+                f,
+                "run-ahead of {frames} frames (at most {MAX_RUN_AHEAD_FRAMES} frames)"
+            ),
+            Self::LoadState => write!(f, "loading save states"),
+        }
+    }
+}
+
+/// A [`Restricted`] feature was used in integrity mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntegrityError(pub Restricted);
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} is disabled in integrity mode", self.0)
+    }
+}
@@ -1,14 +1,32 @@
+pub mod asm;
 pub mod backend;
+pub mod bus_log;
 pub mod cartridge;
+pub mod cheat;
+pub mod clock;
 pub mod controller;
 pub mod cpu;
+pub mod debugger;
+pub mod deterministic;
 pub mod device;
+pub mod disasm;
 pub mod dma;
 pub mod enhancement;
+pub mod hacks;
 mod instr;
+pub mod integrity;
 pub mod oam;
+pub mod patch;
 pub mod ppu;
+pub mod profile;
 mod registers;
+pub mod run_ahead;
+pub mod runner;
 pub mod smp;
 pub mod spc700;
+pub mod stats;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod texture;
 mod timing;
+pub mod trace;
@@ -161,7 +161,7 @@ impl Oam {
 pub struct CgRam {
     data: [u8; 512],
     // 9-bit value
-    addr: u16,
+    pub(crate) addr: u16,
     stashed_write: u8,
 }
 
@@ -193,6 +193,12 @@ impl CgRam {
         u16::from_le_bytes([self.data[addr], self.data[addr | 1]])
     }
 
+    /// Replace a color without changing the address of CGADD
+    pub fn write16(&mut self, addr: u8, color: u16) {
+        let addr = usize::from(addr) << 1;
+        self.data[addr..addr + 2].copy_from_slice(&(color & 0x7fff).to_le_bytes());
+    }
+
     pub fn read(&mut self, open_bus: u8) -> u8 {
         let mut val = self.data[usize::from(self.addr & 0x1ff)];
         if self.addr & 1 == 1 {
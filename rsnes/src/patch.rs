@@ -0,0 +1,454 @@
+//! ROM patch file formats and patches applied at load time
+//!
+//! # Literature
+//!
+//! - <https://zerosoft.zophar.net/ips.php>
+//! - <https://www.romhacking.net/documents/713/> (IPS truncation extension)
+//! - <https://www.romhacking.net/documents/746/> (BPS)
+//! - <https://www.romhacking.net/documents/392/> (UPS)
+
+use crate::cartridge::MAXIMUM_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const IPS_MAX_OFFSET: usize = 0xffffff;
+const IPS_MAX_RECORD_SIZE: usize = 0xffff;
+const BPS_MAGIC: &[u8] = b"BPS1";
+const UPS_MAGIC: &[u8] = b"UPS1";
+/// The CRC-32s of the source, the target and the patch at the end of BPS
+/// and UPS patches
+const FOOTER_SIZE: usize = 12;
+/// The size of the header, which some copiers put in front of the ROM
+const COPIER_HEADER_SIZE: usize = 512;
+
+/// Create an IPS patch containing the bytes of `rom` at the `changed` offsets.
+///
+/// The offsets must be sorted. Consecutive offsets are merged into a single record.
+/// Offsets beyond the 16 MiB IPS address space are ignored.
+pub fn create_ips<I: IntoIterator<Item = usize>>(rom: &[u8], changed: I) -> Vec<u8> {
+    let mut records: Vec<(usize, usize)> = vec![];
+    for offset in changed {
+        if offset > IPS_MAX_OFFSET || offset >= rom.len() {
+            continue;
+        }
+        match records.last_mut() {
+            Some((start, len)) if *start + *len == offset && *len < IPS_MAX_RECORD_SIZE - 1 => {
+                *len += 1
+            }
+            _ => records.push((offset, 1)),
+        }
+    }
+    let mut patch = IPS_MAGIC.to_vec();
+    for (mut start, mut len) in records {
+        if (start as u32).to_be_bytes()[1..] == *IPS_EOF {
+            // this offset would be read as the end-of-file marker,
+            // so include the byte in front of the record
+            start -= 1;
+            len += 1;
+        }
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&(len as u16).to_be_bytes());
+        patch.extend_from_slice(&rom[start..start + len]);
+    }
+    patch.extend_from_slice(IPS_EOF);
+    patch
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch doesn't start with the magic bytes of its format
+    BadMagic,
+    /// The patch ends in the middle of a record
+    UnexpectedEnd,
+    /// A record copies bytes from outside of the ROM or writes beyond the
+    /// end of the patched ROM
+    OutOfBounds,
+    /// The CRC-32 of the patch doesn't match its content
+    PatchChecksum,
+    /// The patch was made for a ROM with another size or CRC-32
+    WrongSource { expected: u32, actual: u32 },
+    /// The CRC-32 of the patched ROM differs from the one in the patch
+    TargetChecksum,
+    /// The patched ROM would be larger than any ROM of the SNES
+    TooLarge(usize),
+}
+
+impl std::fmt::Display for PatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an IPS, BPS or UPS patch"),
+            Self::UnexpectedEnd => write!(f, "unexpected end of the patch"),
+            Self::OutOfBounds => write!(f, "the patch accesses bytes outside of the ROM"),
+            Self::PatchChecksum => write!(f, "the patch is damaged (wrong CRC-32)"),
+            Self::WrongSource { expected, actual } => write!(
+                f,
+                "the patch was made for another ROM (CRC-32 {expected:08x}, the ROM has {actual:08x})"
+            ),
+            Self::TargetChecksum => write!(f, "the patched ROM has the wrong CRC-32"),
+            Self::TooLarge(size) => write!(
+                f,
+                "the patched ROM would be too large ({size} > {MAXIMUM_SIZE} bytes)"
+            ),
+        }
+    }
+}
+
+/// What an applied patch changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PatchStats {
+    pub records: usize,
+    /// The count of bytes replaced by the patch
+    pub bytes: usize,
+    /// The size of the ROM before it was patched
+    pub old_size: usize,
+    pub new_size: usize,
+}
+
+/// Apply an IPS patch to `rom`. The ROM grows, when records are written
+/// beyond its end, and is truncated by the extension after the end-of-file
+/// marker. If the patch is damaged, `rom` is left untouched.
+pub fn apply_ips(rom: &mut Vec<u8>, patch: &[u8]) -> Result<PatchStats, PatchError> {
+    let mut rest = patch.strip_prefix(IPS_MAGIC).ok_or(PatchError::BadMagic)?;
+    let mut take = |len: usize| {
+        if rest.len() < len {
+            return Err(PatchError::UnexpectedEnd);
+        }
+        let (bytes, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(bytes)
+    };
+    let be = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .fold(0, |n, &byte| (n << 8) | usize::from(byte))
+    };
+    let mut stats = PatchStats {
+        old_size: rom.len(),
+        ..Default::default()
+    };
+    let mut target = rom.clone();
+    loop {
+        let offset = take(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = be(offset);
+        let data = match be(take(2)?) {
+            // a run of a single byte
+            0 => {
+                let len = be(take(2)?);
+                vec![take(1)?[0]; len]
+            }
+            len => take(len)?.to_vec(),
+        };
+        let end = offset + data.len();
+        if end > target.len() {
+            check_target_size(end)?;
+            target.resize(end, 0)
+        }
+        target[offset..end].copy_from_slice(&data);
+        stats.records += 1;
+        stats.bytes += data.len();
+    }
+    if let Ok(size) = take(3) {
+        target.truncate(be(size))
+    }
+    *rom = target;
+    stats.new_size = rom.len();
+    Ok(stats)
+}
+
+/// Apply an IPS, BPS or UPS patch to `rom`, the format is detected by the
+/// magic bytes at the start of the patch
+pub fn apply(rom: &mut Vec<u8>, patch: &[u8]) -> Result<PatchStats, PatchError> {
+    if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else {
+        apply_ips(rom, patch)
+    }
+}
+
+/// The part of a BPS or UPS patch between the magic and the footer
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        if self.0.len() < len {
+            return Err(PatchError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A variable-length number. Every byte holds 7 bits, starting with the
+    /// lowest, and the highest bit is set in the last one. A longer encoding
+    /// always means a larger number, so each is unique.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let (mut number, mut shift) = (0usize, 1usize);
+        loop {
+            let byte = self.byte()?;
+            number = usize::from(byte & 0x7f)
+                .checked_mul(shift)
+                .and_then(|bits| number.checked_add(bits))
+                .ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 > 0 {
+                return Ok(number);
+            }
+            shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+            number = number.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    /// A relative offset, the lowest bit of the number is the sign
+    fn offset(&mut self) -> Result<isize, PatchError> {
+        let number = self.number()?;
+        let offset = (number >> 1) as isize;
+        Ok(if number & 1 > 0 { -offset } else { offset })
+    }
+}
+
+/// Check the footer of a BPS or UPS patch and return the body of the patch
+/// and the CRC-32s of the source and the target
+fn split_footer<'a>(patch: &'a [u8], magic: &[u8]) -> Result<(Reader<'a>, u32, u32), PatchError> {
+    let patch_crc =
+        |offset: usize| u32::from_le_bytes(patch[offset..offset + 4].try_into().unwrap());
+    if !patch.starts_with(magic) {
+        return Err(PatchError::BadMagic);
+    }
+    if patch.len() < magic.len() + FOOTER_SIZE {
+        return Err(PatchError::UnexpectedEnd);
+    }
+    let footer = patch.len() - FOOTER_SIZE;
+    if crc32(&patch[..footer + 8]) != patch_crc(footer + 8) {
+        return Err(PatchError::PatchChecksum);
+    }
+    Ok((
+        Reader(&patch[magic.len()..footer]),
+        patch_crc(footer),
+        patch_crc(footer + 4),
+    ))
+}
+
+/// Check the size of the ROM a patch creates, before memory is allocated
+/// for it
+fn check_target_size(size: usize) -> Result<(), PatchError> {
+    if size > MAXIMUM_SIZE {
+        return Err(PatchError::TooLarge(size));
+    }
+    Ok(())
+}
+
+/// Check that `rom` is the source of a patch. A copier header, which the
+/// source didn't have, is removed.
+fn check_source(rom: &mut Vec<u8>, size: usize, crc: u32) -> Result<(), PatchError> {
+    if rom.len() == size && crc32(rom) == crc {
+        return Ok(());
+    }
+    if rom.len() == size + COPIER_HEADER_SIZE && crc32(&rom[COPIER_HEADER_SIZE..]) == crc {
+        rom.drain(..COPIER_HEADER_SIZE);
+        return Ok(());
+    }
+    Err(PatchError::WrongSource {
+        expected: crc,
+        actual: crc32(rom),
+    })
+}
+
+/// Apply a BPS patch to `rom`. The CRC-32s of the ROM and of the patched
+/// ROM must match the ones in the patch, otherwise `rom` is left untouched.
+pub fn apply_bps(rom: &mut Vec<u8>, patch: &[u8]) -> Result<PatchStats, PatchError> {
+    let (mut reader, source_crc, target_crc) = split_footer(patch, BPS_MAGIC)?;
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_target_size(target_size)?;
+    let metadata_size = reader.number()?;
+    reader.take(metadata_size)?;
+    let mut source = rom.clone();
+    check_source(&mut source, source_size, source_crc)?;
+    let mut stats = PatchStats {
+        old_size: rom.len(),
+        ..Default::default()
+    };
+    let mut target = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    while !reader.0.is_empty() {
+        let action = reader.number()?;
+        let len = (action >> 2) + 1;
+        let start = target.len();
+        // no action writes beyond the end of the target
+        let end = start
+            .checked_add(len)
+            .filter(|&end| end <= target_size)
+            .ok_or(PatchError::OutOfBounds)?;
+        match action & 3 {
+            // source read: the bytes at the same offset of the source
+            0 => {
+                let bytes = source.get(start..end);
+                target.extend_from_slice(bytes.ok_or(PatchError::OutOfBounds)?);
+            }
+            // target read: the bytes in the patch
+            1 => target.extend_from_slice(reader.take(len)?),
+            // source copy: the bytes at a relative offset of the source
+            2 => {
+                source_offset = (source_offset.checked_add_signed(reader.offset()?))
+                    .ok_or(PatchError::OutOfBounds)?;
+                let source_end = source_offset
+                    .checked_add(len)
+                    .ok_or(PatchError::OutOfBounds)?;
+                let bytes = source.get(source_offset..source_end);
+                target.extend_from_slice(bytes.ok_or(PatchError::OutOfBounds)?);
+                source_offset = source_end;
+            }
+            // target copy: the bytes at a relative offset of the target, which
+            // may overlap with the copied bytes to repeat a pattern
+            _ => {
+                target_offset = (target_offset.checked_add_signed(reader.offset()?))
+                    .filter(|&offset| offset < start)
+                    .ok_or(PatchError::OutOfBounds)?;
+                for _ in 0..len {
+                    target.push(target[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+        stats.records += 1;
+        if action & 3 != 0 {
+            stats.bytes += len
+        }
+    }
+    if target.len() != target_size || crc32(&target) != target_crc {
+        return Err(PatchError::TargetChecksum);
+    }
+    *rom = target;
+    stats.new_size = rom.len();
+    Ok(stats)
+}
+
+/// Apply a UPS patch to `rom`. The CRC-32s of the ROM and of the patched
+/// ROM must match the ones in the patch, otherwise `rom` is left untouched.
+pub fn apply_ups(rom: &mut Vec<u8>, patch: &[u8]) -> Result<PatchStats, PatchError> {
+    let (mut reader, source_crc, target_crc) = split_footer(patch, UPS_MAGIC)?;
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    check_target_size(target_size)?;
+    let mut target = rom.clone();
+    check_source(&mut target, source_size, source_crc)?;
+    let mut stats = PatchStats {
+        old_size: rom.len(),
+        ..Default::default()
+    };
+    // the source is padded with zeros to the size of the target
+    target.resize(target_size, 0);
+    let mut offset = 0usize;
+    while !reader.0.is_empty() {
+        // a hunk skips unchanged bytes, then xors bytes until a zero
+        offset = offset
+            .checked_add(reader.number()?)
+            .ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                break;
+            }
+            if let Some(target) = target.get_mut(offset) {
+                *target ^= byte;
+                stats.bytes += 1;
+            }
+            offset += 1;
+        }
+        offset += 1;
+        stats.records += 1;
+    }
+    if crc32(&target) != target_crc {
+        return Err(PatchError::TargetChecksum);
+    }
+    *rom = target;
+    stats.new_size = rom.len();
+    Ok(stats)
+}
+
+/// Calculate the CRC-32 (as used by zip and the BPS/UPS formats) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    const POLYNOMIAL: u32 = 0xedb88320;
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+            (crc >> 1) ^ (POLYNOMIAL & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+/// A known region check of a game, which reads bit 4 of $213F (set on PAL
+/// consoles) into the zero flag and branches with `BEQ` or `BNE` on it
+#[derive(Debug)]
+pub struct RegionCheck {
+    pub game: &'static str,
+    /// The CRC-32 of the ROM (see
+    /// [`Cartridge::crc32`](crate::cartridge::Cartridge::crc32))
+    pub crc32: u32,
+    /// The ROM offset of the check
+    pub offset: usize,
+    /// The code of the check, ending with the branch and its operand
+    pub code: &'static [u8],
+}
+
+/// The region checks replaced by [`patch_region_checks`]
+pub static REGION_CHECKS: &[RegionCheck] = &[RegionCheck {
+    game: "Super Metroid (Japan, USA)",
+    crc32: 0xd63e_d5f8,
+    offset: 0x0_8a4f,
+    // LDA $213F; AND #$10; BEQ +5
+    code: &[0xad, 0x3f, 0x21, 0x29, 0x10, 0xf0, 0x05],
+}];
+
+const BEQ: u8 = 0xf0;
+const BNE: u8 = 0xd0;
+const BRA: u8 = 0x80;
+const NOP: u8 = 0xea;
+
+/// Replace the branches after the region checks in `table` of the ROM with
+/// the CRC-32 `crc32`, so they always behave as on a console of the region of
+/// the game (`pal`), which makes region lockouts pass on a console of the
+/// other region. The checks are only patched, if the ROM contains all of
+/// them, e.g. not in a ROM modified by a patch. Returns the offsets of the
+/// patched branches.
+pub fn patch_region_checks(
+    rom: &mut [u8],
+    crc32: u32,
+    pal: bool,
+    table: &[RegionCheck],
+) -> Vec<usize> {
+    let checks: Vec<&RegionCheck> = table.iter().filter(|check| check.crc32 == crc32).collect();
+    let found = |check: &&RegionCheck| {
+        rom.get(check.offset..)
+            .is_some_and(|code| code.starts_with(check.code))
+    };
+    if !checks.iter().all(found) {
+        return vec![];
+    }
+    let mut patched = vec![];
+    for check in checks {
+        let branch = check.offset + check.code.len() - 2;
+        let op = rom[branch];
+        if op != BEQ && op != BNE {
+            continue;
+        }
+        // the zero flag is cleared on PAL consoles
+        if (op == BNE) == pal {
+            rom[branch] = BRA;
+        } else {
+            rom[branch..branch + 2].copy_from_slice(&[NOP; 2]);
+        }
+        patched.push(branch);
+    }
+    patched
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,251 @@
+use super::*;
+
+/// A ROM with two region checks and the table of them
+fn region_checks() -> (Vec<u8>, [RegionCheck; 2]) {
+    let mut rom = vec![0; 0x20];
+    // LDA $213F; AND #$10; BEQ +2
+    let code1: &[u8] = &[0xad, 0x3f, 0x21, 0x29, 0x10, 0xf0, 0x02];
+    // LDA $213F; BIT #$10; BNE -4
+    let code2: &[u8] = &[0xad, 0x3f, 0x21, 0x89, 0x10, 0xd0, 0xfc];
+    rom[0x04..0x0b].copy_from_slice(code1);
+    rom[0x10..0x17].copy_from_slice(code2);
+    let check = |offset, code| RegionCheck {
+        game: "Test",
+        crc32: 0x1234,
+        offset,
+        code,
+    };
+    (rom, [check(0x04, code1), check(0x10, code2)])
+}
+
+#[test]
+pub fn test_patch_region_checks() {
+    let (mut rom, table) = region_checks();
+    assert_eq!(
+        patch_region_checks(&mut rom, 0x1234, true, &table),
+        [9, 0x15]
+    );
+    // the zero flag is cleared on PAL consoles, so `BEQ` is never taken
+    // and `BNE` always
+    assert_eq!(rom[0x09..0x0b], [NOP, NOP]);
+    assert_eq!(rom[0x15..0x17], [BRA, 0xfc]);
+
+    let (mut rom, table) = region_checks();
+    assert_eq!(
+        patch_region_checks(&mut rom, 0x1234, false, &table),
+        [9, 0x15]
+    );
+    assert_eq!(rom[0x09..0x0b], [BRA, 0x02]);
+    assert_eq!(rom[0x15..0x17], [NOP, NOP]);
+}
+
+#[test]
+pub fn test_patch_region_checks_verifies_code() {
+    let (mut rom, table) = region_checks();
+    // another game
+    let original = rom.clone();
+    assert!(patch_region_checks(&mut rom, 0x4321, false, &table).is_empty());
+    assert_eq!(rom, original);
+    // a modified ROM, which lacks one of the checks
+    rom[0x13] = 0x29;
+    let original = rom.clone();
+    assert!(patch_region_checks(&mut rom, 0x1234, false, &table).is_empty());
+    assert_eq!(rom, original);
+    // a check beyond the end of the ROM
+    let (mut rom, table) = region_checks();
+    rom.truncate(0x16);
+    assert!(patch_region_checks(&mut rom, 0x1234, false, &table).is_empty());
+}
+
+#[test]
+pub fn test_apply_ips() {
+    let mut rom = vec![0; 8];
+    rom[2] = 0x12;
+    let patch = create_ips(&rom, [1, 2]);
+    let mut patched = vec![0xff; 8];
+    let stats = apply_ips(&mut patched, &patch).unwrap();
+    assert_eq!(patched, [0xff, 0, 0x12, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!((stats.records, stats.bytes), (1, 2));
+
+    // a run of `AB` beyond the end grows the ROM, the extension after the
+    // end-of-file marker truncates it
+    let mut patch = b"PATCH\x00\x00\x06\x00\x00\x00\x04\xab".to_vec();
+    patch.extend_from_slice(b"EOF\x00\x00\x09");
+    let mut rom = vec![0; 8];
+    let stats = apply_ips(&mut rom, &patch).unwrap();
+    assert_eq!(rom, [0, 0, 0, 0, 0, 0, 0xab, 0xab, 0xab]);
+    assert_eq!(
+        stats,
+        PatchStats {
+            records: 1,
+            bytes: 4,
+            old_size: 8,
+            new_size: 9,
+        }
+    );
+
+    assert_eq!(apply_ips(&mut rom, b"PTCH"), Err(PatchError::BadMagic));
+    assert_eq!(
+        apply_ips(&mut rom, b"PATCH\x00\x00\x01\x00\x02\x00"),
+        Err(PatchError::UnexpectedEnd)
+    );
+}
+
+#[test]
+pub fn test_apply_ips_leaves_rom_on_error() {
+    // a complete record followed by a truncated one
+    let mut rom = vec![0; 8];
+    assert_eq!(
+        apply_ips(
+            &mut rom,
+            b"PATCH\x00\x00\x01\x00\x01\xab\x00\x00\x02\x00\x02\xcd"
+        ),
+        Err(PatchError::UnexpectedEnd)
+    );
+    assert_eq!(rom, [0; 8]);
+    // a run at the highest offset grows the ROM beyond the largest cartridge
+    let end = IPS_MAX_OFFSET + 0xffff;
+    assert_eq!(
+        apply_ips(&mut rom, b"PATCH\xff\xff\xff\x00\x00\xff\xff\xabEOF"),
+        Err(PatchError::TooLarge(end))
+    );
+    assert_eq!(rom, [0; 8]);
+}
+
+fn encode_number(mut number: usize, out: &mut Vec<u8>) {
+    loop {
+        let bits = (number & 0x7f) as u8;
+        number >>= 7;
+        if number == 0 {
+            out.push(0x80 | bits);
+            return;
+        }
+        out.push(bits);
+        number -= 1;
+    }
+}
+
+fn finish_patch(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+    patch
+}
+
+#[test]
+pub fn test_patch_numbers() {
+    for number in [0, 1, 0x7f, 0x80, 0x407f, 0x4080, 0x12345678] {
+        let mut bytes = vec![];
+        encode_number(number, &mut bytes);
+        assert_eq!(Reader(&bytes).number(), Ok(number));
+    }
+}
+
+#[test]
+pub fn test_apply_bps() {
+    let source: Vec<u8> = (0..16).collect();
+    let target = [
+        0, 1, 2, 3, 0xaa, 0xbb, 8, 9, 10, 11, 0xaa, 0xbb, 8, 9, 10, 11,
+    ];
+    let mut patch = BPS_MAGIC.to_vec();
+    for number in [16, 16, 0] {
+        encode_number(number, &mut patch);
+    }
+    // read 4 bytes of the source, 2 of the patch, copy 4 bytes from offset 8
+    // of the source and 6 from offset 4 of the target
+    encode_number(3 << 2, &mut patch);
+    encode_number((1 << 2) | 1, &mut patch);
+    patch.extend_from_slice(&[0xaa, 0xbb]);
+    encode_number((3 << 2) | 2, &mut patch);
+    encode_number(8 << 1, &mut patch);
+    encode_number((5 << 2) | 3, &mut patch);
+    encode_number(4 << 1, &mut patch);
+    let patch = finish_patch(patch, &source, &target);
+
+    let mut rom = source.clone();
+    let stats = apply(&mut rom, &patch).unwrap();
+    assert_eq!(rom, target);
+    assert_eq!((stats.records, stats.bytes), (4, 12));
+
+    // a copier header is removed
+    let mut rom = vec![0; COPIER_HEADER_SIZE];
+    rom.extend_from_slice(&source);
+    apply(&mut rom, &patch).unwrap();
+    assert_eq!(rom, target);
+
+    let mut rom = target.to_vec();
+    assert_eq!(
+        apply(&mut rom, &patch),
+        Err(PatchError::WrongSource {
+            expected: crc32(&source),
+            actual: crc32(&target),
+        })
+    );
+    assert_eq!(rom, target);
+    let mut damaged = patch.clone();
+    damaged[10] ^= 1;
+    assert_eq!(
+        apply(&mut source.clone(), &damaged),
+        Err(PatchError::PatchChecksum)
+    );
+}
+
+#[test]
+pub fn test_apply_ups() {
+    let source: Vec<u8> = (0..8).collect();
+    let target = [0, 1, 0xf2, 3, 4, 5, 6, 7, 0, 0x55];
+    let mut patch = UPS_MAGIC.to_vec();
+    for number in [8, 10] {
+        encode_number(number, &mut patch);
+    }
+    // skip 2 bytes and xor one, skip 5 bytes after the terminating zero and
+    // xor one beyond the source
+    encode_number(2, &mut patch);
+    patch.extend_from_slice(&[0xf0, 0]);
+    encode_number(5, &mut patch);
+    patch.extend_from_slice(&[0x55, 0]);
+    let patch = finish_patch(patch, &source, &target);
+
+    let mut rom = source.clone();
+    let stats = apply(&mut rom, &patch).unwrap();
+    assert_eq!(rom, target);
+    assert_eq!((stats.records, stats.bytes, stats.new_size), (2, 2, 10));
+
+    // the CRC-32 of the target doesn't match without the second hunk
+    let mut patch = UPS_MAGIC.to_vec();
+    for number in [8, 10, 2] {
+        encode_number(number, &mut patch);
+    }
+    patch.extend_from_slice(&[0xf0, 0]);
+    let patch = finish_patch(patch, &source, &target);
+    let mut rom = source.clone();
+    assert_eq!(apply(&mut rom, &patch), Err(PatchError::TargetChecksum));
+    assert_eq!(rom, source);
+}
+
+#[test]
+pub fn test_patch_bounds() {
+    let source: Vec<u8> = (0..16).collect();
+    // the target size is checked before memory is allocated for it
+    for magic in [BPS_MAGIC, UPS_MAGIC] {
+        let mut patch = magic.to_vec();
+        for number in [16, MAXIMUM_SIZE + 1, 0] {
+            encode_number(number, &mut patch);
+        }
+        let patch = finish_patch(patch, &source, &source);
+        assert_eq!(
+            apply(&mut source.clone(), &patch),
+            Err(PatchError::TooLarge(MAXIMUM_SIZE + 1))
+        );
+    }
+    // a source read far beyond the end of the target
+    let mut patch = BPS_MAGIC.to_vec();
+    for number in [16, 16, 0, 1 << 42] {
+        encode_number(number, &mut patch);
+    }
+    let patch = finish_patch(patch, &source, &source);
+    assert_eq!(
+        apply(&mut source.clone(), &patch),
+        Err(PatchError::OutOfBounds)
+    );
+}
@@ -1,4 +1,9 @@
+mod registers;
+
+pub use registers::Register;
+
 use crate::oam::{CgRam, Oam, Object};
+use crate::texture::{Texel, Texture, TexturePack, TileHasher};
 use core::mem::{replace, take};
 use save_state::{SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::*;
@@ -187,6 +192,31 @@ impl From<u16> for Color {
     }
 }
 
+/// The names of the layers in the order of [`Ppu::tinted_layers`]
+pub static LAYER_NAMES: [&str; 6] = ["bg1", "bg2", "bg3", "bg4", "obj", "backdrop"];
+const OBJ_LAYER: usize = 4;
+const BACKDROP_LAYER: usize = 5;
+
+/// The colors of the layers in the order of [`LAYER_NAMES`], when they are
+/// tinted
+const TINT_COLORS: [Color; 6] = [
+    Color::new(0x1f, 0x04, 0x04),
+    Color::new(0x04, 0x1f, 0x04),
+    Color::new(0x04, 0x0c, 0x1f),
+    Color::new(0x1f, 0x1f, 0x04),
+    Color::new(0x1f, 0x04, 0x1f),
+    Color::new(0x0c, 0x0c, 0x0c),
+];
+
+/// Replace the color of a pixel of the layer `layer` by the color of the
+/// layer. The tint is darker for lower priorities `prio` (0 to 3), the
+/// brightness of the real color only shades it, so tiles stay recognizable.
+fn tint(layer: usize, prio: u8, color: Color) -> Color {
+    let luma = u16::from(color.r.max(color.g).max(color.b).min(0x1f));
+    let shade = (u16::from(prio) + 2) * (0x10 + luma / 2);
+    TINT_COLORS[layer].map(|c| (u16::from(c) * shade / (5 * 0x1f)) as u8)
+}
+
 #[derive(Debug, Clone, Copy, InSaveState)]
 pub struct ColorMath {
     window: Window,
@@ -304,6 +334,141 @@ const fn sign_extend<const B: u16>(n: u16) -> u16 {
     }
 }
 
+/// The pixels of the main screen of every layer, drawn separately, e.g. to
+/// edit the layers of a frame in a video editor. Pixels, which a layer
+/// doesn't cover, are transparent. Color math isn't applied, the brightness
+/// is.
+#[derive(Debug, Clone)]
+pub struct LayerFrames {
+    /// The RGBA pixels of the layers in the order of [`LAYER_NAMES`], with
+    /// [`SCREEN_WIDTH`] pixels per scanline
+    pub layers: [Vec<[u8; 4]>; 6],
+}
+
+impl LayerFrames {
+    pub fn new() -> Self {
+        Self {
+            layers: core::array::from_fn(|_| vec![[0; 4]; crate::backend::FRAME_BUFFER_SIZE]),
+        }
+    }
+}
+
+impl Default for LayerFrames {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where a pixel of the main screen comes from, to inspect single pixels.
+/// The default is a pixel of the backdrop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelSource {
+    /// The index of the layer in [`LAYER_NAMES`]
+    pub layer: u8,
+    /// The priority bit of a BG tile or the priority (0 to 3) of a sprite
+    pub priority: u8,
+    /// The VRAM word address of the 8x8 character, `None` for the backdrop
+    pub tile_addr: Option<u16>,
+    /// The palette of the tile
+    pub palette: u8,
+    /// The index of the color in the palette
+    pub color: u8,
+    /// The CGRAM address of the color, `None` with direct color
+    pub cgram_addr: Option<u8>,
+    /// Whether color math was applied to the pixel
+    pub color_math: bool,
+}
+
+impl PixelSource {
+    const BACKDROP: Self = Self {
+        layer: BACKDROP_LAYER as u8,
+        priority: 0,
+        tile_addr: None,
+        palette: 0,
+        color: 0,
+        cgram_addr: Some(0),
+        color_math: false,
+    };
+
+    /// The source of a sprite pixel
+    fn sprite(entry: ObjCacheEntry, tile_addr: u16) -> Self {
+        Self {
+            layer: OBJ_LAYER as u8,
+            priority: entry.prio,
+            tile_addr: Some(tile_addr),
+            palette: (entry.palette_addr >> 4) & 7,
+            color: entry.palette_addr & 15,
+            cgram_addr: Some(entry.palette_addr),
+            color_math: false,
+        }
+    }
+}
+
+impl Default for PixelSource {
+    fn default() -> Self {
+        Self::BACKDROP
+    }
+}
+
+/// The picture rendered with "HD Mode 7" and widescreen.
+///
+/// Mode 7 scanlines are rendered at `scale` times the resolution in both
+/// directions, by evaluating the Mode 7 matrix at every subpixel.
+/// All other scanlines are copied from the regular frame buffer.
+///
+/// With widescreen (experimental), `widescreen` additional pixels are rendered
+/// on both sides of the picture. Only Mode 7 and the backgrounds enabled in
+/// `widescreen_bgs` are extended, sprites and all other layers are left out and
+/// color math is not applied.
+#[derive(Debug, Clone)]
+pub struct HdFrame {
+    scale: u8,
+    widescreen: u8,
+    /// The backgrounds, which are drawn in the widescreen area
+    pub widescreen_bgs: [bool; 4],
+    pixels: Vec<[u8; 4]>,
+}
+
+impl HdFrame {
+    pub const MAX_SCALE: u8 = 4;
+    pub const MAX_WIDESCREEN: u8 = 128;
+
+    /// Create a frame buffer with a scale from 1 to [`HdFrame::MAX_SCALE`]
+    /// and up to [`HdFrame::MAX_WIDESCREEN`] widescreen pixels on each side
+    pub fn new(scale: u8, widescreen: u8) -> Self {
+        assert!((1..=Self::MAX_SCALE).contains(&scale));
+        assert!(widescreen <= Self::MAX_WIDESCREEN);
+        let width = (SCREEN_WIDTH + 2 * u32::from(widescreen)) * u32::from(scale);
+        let height = MAX_SCREEN_HEIGHT_OVERSCAN * u32::from(scale);
+        Self {
+            scale,
+            widescreen,
+            widescreen_bgs: [true; 4],
+            pixels: vec![[0; 4]; (width * height) as usize],
+        }
+    }
+
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    pub fn widescreen(&self) -> u8 {
+        self.widescreen
+    }
+
+    pub fn width(&self) -> u32 {
+        (SCREEN_WIDTH + 2 * u32::from(self.widescreen)) * u32::from(self.scale)
+    }
+
+    pub fn pixels(&self) -> &[[u8; 4]] {
+        &self.pixels
+    }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.pixels.as_ptr() as _, self.pixels.len() << 2) }
+    }
+}
+
 /// Settings used to draw Mode 7's BG1.
 #[derive(Debug, Clone, InSaveState)]
 struct Mode7Settings {
@@ -463,6 +628,16 @@ enum DrawLayer {
     Sprite { prio: u8 },
 }
 
+impl DrawLayer {
+    /// The index of the layer in [`LAYER_NAMES`] and its priority (0 to 3)
+    const fn source(&self) -> (usize, u8) {
+        match *self {
+            Self::Bg { nr, prio, .. } => (nr as usize, if prio { 3 } else { 1 }),
+            Self::Sprite { prio } => (OBJ_LAYER, prio),
+        }
+    }
+}
+
 impl save_state::InSaveState for DrawLayer {
     fn serialize(&self, state: &mut SaveStateSerializer) {
         match self {
@@ -625,6 +800,51 @@ impl BgMode {
 pub struct Ppu<FB: crate::backend::FrameBuffer> {
     #[except((|_v, _s| ()), (|_v, _s| ()))]
     pub frame_buffer: FB,
+    /// The upscaled picture, if HD Mode 7 is enabled
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub hd_frame: Option<HdFrame>,
+    /// The subpixel offset in 1/256 pixels, while drawing HD Mode 7
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    mode7_subpixel: [i32; 2],
+    /// The active texture pack, see [`crate::texture`]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub texture_pack: Option<TexturePack>,
+    /// The hashed tile of every BG's `cached_tile`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    bg_texels: [Option<Texel>; 4],
+    /// The texel of the last pixel returned by `fetch_bg_tile`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    bg_texel: Option<Texel>,
+    /// The hashed sprite tiles of the current scanline, like `obj_cache`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    obj_texels: [Option<Texel>; 256],
+    /// The texels of the main screen in the current scanline
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    texels: [Option<Texel>; 256],
+    /// The layers in the order of [`LAYER_NAMES`], whose pixels of the main
+    /// screen show the color of the layer and their priority instead of
+    /// their real color, to debug priorities and windows
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub tinted_layers: [bool; 6],
+    /// The layers drawn separately, while they are captured
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub layer_frames: Option<Box<LayerFrames>>,
+    /// The source of every pixel of the main screen, with [`SCREEN_WIDTH`]
+    /// pixels per scanline, while pixels are inspected
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub pixel_sources: Option<Vec<PixelSource>>,
+    /// The source of the pixel drawn last
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pixel_source: PixelSource,
+    /// The source of the last pixel returned by `fetch_bg_tile`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    bg_source: PixelSource,
+    /// The character address of every BG's `cached_tile`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    bg_tile_addrs: [u16; 4],
+    /// The character addresses of the sprite pixels of the current scanline
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    obj_tile_addrs: [u16; 256],
     oam: Oam,
     cgram: CgRam,
     vram: Vram,
@@ -655,6 +875,9 @@ pub struct Ppu<FB: crate::backend::FrameBuffer> {
     field: bool,
     force_blank: bool,
     is_pal: bool,
+    /// See [`crate::hacks::Effect::RefreshCycles`]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) refresh_cycles: u16,
     pub(crate) open_bus1: u8,
     pub(crate) open_bus2: u8,
 }
@@ -664,6 +887,20 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         let bg_mode = BgMode::new(0, false, false);
         Self {
             frame_buffer,
+            hd_frame: None,
+            mode7_subpixel: [0; 2],
+            texture_pack: None,
+            bg_texels: [None; 4],
+            bg_texel: None,
+            obj_texels: [None; 256],
+            texels: [None; 256],
+            tinted_layers: [false; 6],
+            layer_frames: None,
+            pixel_sources: None,
+            pixel_source: PixelSource::BACKDROP,
+            bg_source: PixelSource::BACKDROP,
+            bg_tile_addrs: [0; 4],
+            obj_tile_addrs: [0; 256],
             oam: Oam::new(),
             cgram: CgRam::new(),
             vram: Vram::new(),
@@ -691,6 +928,7 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             field: false,
             force_blank: true,
             is_pal,
+            refresh_cycles: crate::hacks::REFRESH_CYCLES,
             open_bus1: 0,
             open_bus2: 0,
         }
@@ -700,12 +938,9 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
     pub fn read_register(&mut self, addr: u8) -> Option<u8> {
         assert!(addr >= 0x34 && addr <= 0x3f);
         match addr {
-            0x34..=0x36 => {
-                // MPYL/M/H
-                let x = self.mode7_settings.params[0] as i16 as i32;
-                let y = (self.mode7_settings.params[1] >> 8) as i8 as i32;
-                Some(((x * y) as u32).to_le_bytes()[usize::from(addr & 3)])
-            }
+            0x34 => Some(self.peek_register(Register::Mpyl) as u8), // MPYL
+            0x35 => Some(self.peek_register(Register::Mpym) as u8), // MPYM
+            0x36 => Some(self.peek_register(Register::Mpyh) as u8), // MPYH
             0x37 => {
                 // SLHV - Software Latch for H/V Counter
                 self.latch();
@@ -725,16 +960,12 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             0x3b => Some(self.cgram.read(self.open_bus2)), // RDCGRAM
             0x3c => Some(self.latched.get::<0>(self.open_bus2)), // OPHCT
             0x3d => Some(self.latched.get::<1>(self.open_bus2)), // OPHCT
-            0x3e => Some(self.overflow_flags | (self.open_bus1 & 0x10) | CHIP_5C77_VERSION), // STAT77
+            0x3e => Some(self.peek_register(Register::Stat77) as u8), // STAT77
             0x3f => {
                 // STAT78
-                self.latched.flip = [false; 2];
-                Some(
-                    ((take(&mut self.latched.latched) as u8) << 6)
-                        | (self.open_bus2 & 0x20)
-                        | CHIP_5C78_VERSION
-                        | ((self.is_pal as u8) << 4),
-                )
+                let val = self.peek_register(Register::Stat78) as u8;
+                self.latched.reset_flipflops();
+                Some(val)
             }
             _ => unreachable!(),
         }
@@ -1019,28 +1250,49 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         xflip: bool,
         planes: u8,
     ) -> u64 {
+        let tile_nr = Self::tile_nr(x, y, tile_w, tile_h, char_nr);
+        self.fetch_tile_by_nr(y, tile_base, tile_nr, xflip, planes)
+    }
+
+    /// The number of the 8x8 character at `x`, `y` in a tile of the size
+    /// `tile_w`, `tile_h`
+    fn tile_nr(x: u16, y: u16, tile_w: u8, tile_h: u8, char_nr: u16) -> u16 {
         let [tile_x, tile_y] = [
             (x & 0xff) as u8 & (tile_w - 1),
             (y & 0xff) as u8 & (tile_h - 1),
         ];
-        let tile_nr = char_nr
+        char_nr
             .wrapping_add(u16::from(tile_x >> 3))
-            .wrapping_add(u16::from(tile_y >> 3) << 4);
-        self.fetch_tile_by_nr(y, tile_base, tile_nr, xflip, planes)
+            .wrapping_add(u16::from(tile_y >> 3) << 4)
     }
 
     fn decode_tile(tile: u64, x: u16) -> u8 {
         tile.to_be_bytes()[usize::from(x & 7)]
     }
 
-    fn fetch_bg7_tile(&mut self, x: u8, nr: u8, prio: bool) -> Option<Color> {
-        let x = if self.mode7_settings.x_mirror { !x } else { x };
+    fn fetch_bg7_tile(&mut self, x: i16, nr: u8, prio: bool) -> Option<Color> {
+        let x = if self.mode7_settings.x_mirror {
+            255 - x
+        } else {
+            x
+        };
+        let [mut sx, mut sy] = self.mode7_subpixel;
+        if self.mode7_settings.x_mirror {
+            sx = -sx
+        }
+        if self.mode7_settings.y_mirror {
+            sy = -sy
+        }
 
+        let params = self.mode7_settings.params.map(|p| p as i16 as i32);
         let v = [
-            (self.mode7_settings.tmp4[0], self.mode7_settings.params[0]),
-            (self.mode7_settings.tmp4[1], self.mode7_settings.params[2]),
+            (self.mode7_settings.tmp4[0], params[0], params[1]),
+            (self.mode7_settings.tmp4[1], params[2], params[3]),
         ]
-        .map(|(c, p)| c.wrapping_add(p as i16 as i32 * i32::from(x)));
+        .map(|(c, p, q)| {
+            c.wrapping_add(p * i32::from(x))
+                .wrapping_add((p * sx + q * sy) >> 8)
+        });
 
         let v = v.map(|c| (((c as u32) >> 8) & 0xffff) as u16);
         let char_nr = if self.mode7_settings.wrap || !v.iter().any(|&c| c > 0x3ff) {
@@ -1060,6 +1312,17 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         if cgram_addr == 0 || (nr == 1 && (cgram_addr & 0x80 == 0) == prio) {
             None
         } else {
+            if self.pixel_sources.is_some() {
+                self.bg_source = PixelSource {
+                    layer: nr,
+                    priority: prio.into(),
+                    tile_addr: Some(char_addr),
+                    palette: 0,
+                    color: cgram_addr,
+                    cgram_addr: (!self.direct_color_mode).then_some(cgram_addr),
+                    color_math: false,
+                };
+            }
             Some(if self.direct_color_mode {
                 Color {
                     r: (cgram_addr & 7) << 2,
@@ -1072,13 +1335,13 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         }
     }
 
-    pub fn fetch_bg_tile(&mut self, x: u8, y: u16, nr: u8, bits: u8, prio: bool) -> Option<Color> {
+    pub fn fetch_bg_tile(&mut self, x: i16, y: u16, nr: u8, bits: u8, prio: bool) -> Option<Color> {
         if self.bg_mode.num == 7 {
             return self.fetch_bg7_tile(x, nr, prio);
         }
         // TODO: implement offset-per-tile
         let bg = &self.bgs[usize::from(nr)];
-        let x = (x as i16 + (((bg.scroll[0] << 6) as i16) >> 6)) as u16 & 0x3ff;
+        let x = x.wrapping_add(((bg.scroll[0] << 6) as i16) >> 6) as u16 & 0x3ff;
         let y = (y as i16 + (((bg.scroll[1] << 6) as i16) >> 6)) as u16 & 0x3ff;
         let (x, y) = if let Some(start) = bg.mosaic_start {
             let sz = self.mosaic_size as u16;
@@ -1119,6 +1382,11 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             let y = if yflip { !y } else { y };
             let (base, tw, th) = (bg.tile_base_addr, bg.tile_size[0], bg.tile_size[1]);
             let tile = self.fetch_tile(x, y, base, tw, th, char_nr, xflip, bits);
+            if self.pixel_sources.is_some() {
+                let tile_nr = Self::tile_nr(x, y, tw, th, char_nr);
+                self.bg_tile_addrs[usize::from(nr)] =
+                    base.wrapping_add(tile_nr << (2 + bits.trailing_zeros()));
+            }
             let tile = CachedTile {
                 x: cache_x,
                 prio: sel_prio,
@@ -1126,6 +1394,11 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 palette_nr,
             };
             self.bgs[usize::from(nr)].cached_tile = Some(tile);
+            self.bg_texels[usize::from(nr)] = if self.texture_pack.is_some() {
+                self.hash_bg_tile(nr, bits, char_nr, palette_nr, [xflip, yflip], y)
+            } else {
+                None
+            };
             tile
         };
         if tile.prio ^ prio {
@@ -1135,23 +1408,152 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         if palette_idx == 0 {
             return None;
         }
-        let color = if self.direct_color_mode && bits == 8 {
+        if self.texture_pack.is_some() {
+            self.bg_texel = self.bg_texels[usize::from(nr)].map(|mut texel| {
+                let x = if texel.flip[0] { !x } else { x };
+                texel.pos[0] = (x & u16::from(texel.size[0] - 1)) as u8;
+                texel
+            });
+        }
+        if self.pixel_sources.is_some() {
+            let direct_color = self.direct_color_mode && bits == 8;
+            self.bg_source = PixelSource {
+                layer: nr,
+                priority: tile.prio.into(),
+                tile_addr: Some(self.bg_tile_addrs[usize::from(nr)]),
+                palette: tile.palette_nr,
+                color: palette_idx,
+                cgram_addr: (!direct_color)
+                    .then(|| self.bg_palette_addr(nr, bits, tile.palette_nr) | palette_idx),
+                color_math: false,
+            };
+        }
+        Some(self.bg_color(nr, bits, tile.palette_nr, palette_idx))
+    }
+
+    fn bg_color(&self, nr: u8, bits: u8, palette_nr: u8, palette_idx: u8) -> Color {
+        if self.direct_color_mode && bits == 8 {
             Color {
-                r: ((palette_idx & 7) << 2) | ((tile.palette_nr & 1) << 1),
-                g: ((palette_idx & 0x38) >> 1) | (tile.palette_nr & 2),
-                b: ((palette_idx & 0xc0) >> 3) | (tile.palette_nr & 4),
+                r: ((palette_idx & 7) << 2) | ((palette_nr & 1) << 1),
+                g: ((palette_idx & 0x38) >> 1) | (palette_nr & 2),
+                b: ((palette_idx & 0xc0) >> 3) | (palette_nr & 4),
             }
         } else {
-            let cg_addr = if self.bg_mode.num == 0 {
-                (tile.palette_nr << 2) | palette_idx | (nr << 5) as u8
-            } else if bits == 8 {
-                palette_idx
-            } else {
-                (tile.palette_nr << bits) | palette_idx
-            };
-            self.cgram.read16(cg_addr).into()
+            self.cgram
+                .read16(self.bg_palette_addr(nr, bits, palette_nr) | palette_idx)
+                .into()
+        }
+    }
+
+    fn bg_palette_addr(&self, nr: u8, bits: u8, palette_nr: u8) -> u8 {
+        if self.bg_mode.num == 0 {
+            (palette_nr << 2) | (nr << 5)
+        } else if bits == 8 {
+            0
+        } else {
+            palette_nr << bits
+        }
+    }
+
+    /// Hash a BG tile for the texture pack. `y` is the (flipped) y position.
+    fn hash_bg_tile(
+        &mut self,
+        nr: u8,
+        bits: u8,
+        char_nr: u16,
+        palette_nr: u8,
+        flip: [bool; 2],
+        y: u16,
+    ) -> Option<Texel> {
+        let bg = &self.bgs[usize::from(nr)];
+        let (base, size) = (bg.tile_base_addr, bg.tile_size);
+        let char_addr = |x: u8, y: u8| {
+            let char_nr = char_nr
+                .wrapping_add(u16::from(x >> 3))
+                .wrapping_add(u16::from(y >> 3) << 4);
+            base.wrapping_add(char_nr << (2 + bits.trailing_zeros()))
         };
-        Some(color)
+        let mut hasher = TileHasher::new();
+        for cy in (0..size[1]).step_by(8) {
+            for cx in (0..size[0]).step_by(8) {
+                let addr = char_addr(cx, cy);
+                for i in 0..u16::from(bits) << 2 {
+                    hasher.write_u16(self.vram.read(addr.wrapping_add(i)));
+                }
+            }
+        }
+        if self.direct_color_mode && bits == 8 {
+            hasher.write_u16(palette_nr.into())
+        } else {
+            let palette_addr = self.bg_palette_addr(nr, bits, palette_nr);
+            for i in 1..=((1u16 << bits) - 1) as u8 {
+                hasher.write_u16(self.cgram.read16(palette_addr | i));
+            }
+        }
+        let hash = hasher.finish();
+        if self.texture_pack.as_mut()?.visit(hash) {
+            let mut pixels = Vec::with_capacity(usize::from(size[0]) * usize::from(size[1]));
+            for py in 0..size[1] {
+                for px in 0..size[0] {
+                    let row = self.fetch_tile_by_nr(py.into(), char_addr(px, py), 0, false, bits);
+                    pixels.push(match Self::decode_tile(row, px.into()) {
+                        0 => [0; 4],
+                        idx => self
+                            .bg_color(nr, bits, palette_nr, idx)
+                            .to_rgba8_with_brightness(15),
+                    });
+                }
+            }
+            let texture = Texture::new(size[0].into(), size[1].into(), pixels);
+            self.texture_pack.as_mut()?.add_dumped(hash, texture);
+        }
+        Some(Texel {
+            hash,
+            pos: [0, (y & u16::from(size[1] - 1)) as u8],
+            size,
+            flip,
+        })
+    }
+
+    /// Hash a sprite tile for the texture pack
+    fn hash_obj_tile(
+        &mut self,
+        tile_addr: u16,
+        palette_nr: u8,
+        row: u8,
+        flip: [bool; 2],
+    ) -> Option<Texel> {
+        let palette_addr = 0x80 | (palette_nr << 4);
+        let mut hasher = TileHasher::new();
+        for i in 0..16 {
+            hasher.write_u16(self.vram.read(tile_addr.wrapping_add(i)));
+        }
+        for i in 1..16 {
+            hasher.write_u16(self.cgram.read16(palette_addr | i));
+        }
+        let hash = hasher.finish();
+        if self.texture_pack.as_mut()?.visit(hash) {
+            let mut pixels = Vec::with_capacity(64);
+            for py in 0..8 {
+                let tile = self.fetch_tile_by_nr(py, tile_addr, 0, false, 4);
+                for px in 0..8 {
+                    pixels.push(match Self::decode_tile(tile, px) {
+                        0 => [0; 4],
+                        idx => Color::from(self.cgram.read16(palette_addr | idx))
+                            .to_rgba8_with_brightness(15),
+                    });
+                }
+            }
+            self.texture_pack
+                .as_mut()?
+                .add_dumped(hash, Texture::new(8, 8, pixels));
+        }
+        Some(Texel {
+            hash,
+            pos: [0, row & 7],
+            size: [8, 8],
+            flip,
+        })
     }
 
     pub fn fetch_screen(
@@ -1160,10 +1562,13 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         y: u16,
         mainscreen: bool,
         subscreen: bool,
-    ) -> (Color, Option<Color>, bool) {
+    ) -> (Color, Option<Color>, bool, bool) {
         let [mut main_found, mut sub_found] = [false; 2];
         let (mut main, mut sub) = (Color::new(0, 0, 0), None);
         let mut layer_color_math = None;
+        let mut main_texel = None;
+        // the layer and priority of the main screen pixel
+        let mut main_source = (BACKDROP_LAYER, 3);
         for draw_ly_idx in 0..self.draw_layers.size {
             let draw_ly = &self.draw_layers.arr[usize::from(draw_ly_idx)];
             let ly = self.get_layer_from_draw_layer(&draw_ly);
@@ -1182,12 +1587,20 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 continue;
             }
             let mut layer_color_math_ = ly.color_math;
+            let mut texel = None;
+            let source = draw_ly.source();
             if let Some(color) = match draw_ly {
-                &DrawLayer::Bg { nr, bits, prio } => self.fetch_bg_tile(x, y, nr, bits, prio),
+                &DrawLayer::Bg { nr, bits, prio } => {
+                    self.bg_texel = None;
+                    let color = self.fetch_bg_tile(x.into(), y, nr, bits, prio);
+                    texel = self.bg_texel;
+                    color
+                }
                 &DrawLayer::Sprite { prio } => {
                     let entry = self.obj_cache[usize::from(x)];
                     if prio == entry.prio && entry.palette_addr != 0 {
                         layer_color_math_ &= entry.palette_addr & 0x40 > 0;
+                        texel = self.obj_texels[usize::from(x)];
                         Some(self.cgram.read16(entry.palette_addr).into())
                     } else {
                         None
@@ -1197,6 +1610,16 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 if is_main {
                     main_found = true;
                     main = color;
+                    main_texel = texel;
+                    main_source = source;
+                    if self.pixel_sources.is_some() {
+                        self.pixel_source = if source.0 == OBJ_LAYER {
+                            let x = usize::from(x);
+                            PixelSource::sprite(self.obj_cache[x], self.obj_tile_addrs[x])
+                        } else {
+                            self.bg_source
+                        };
+                    }
                     layer_color_math = Some(layer_color_math_);
                     if sub_found || !subscreen {
                         break;
@@ -1211,16 +1634,61 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 }
             }
         }
-        if !main_found && mainscreen {
-            main = self.cgram.main_screen_backdrop().into()
+        if !main_found {
+            if mainscreen {
+                main = self.cgram.main_screen_backdrop().into()
+            }
+            self.pixel_source = PixelSource::BACKDROP;
+        }
+        let (layer, prio) = main_source;
+        let tinted = mainscreen && self.tinted_layers[layer];
+        if tinted {
+            main = tint(layer, prio, main);
+            main_texel = None;
+        }
+        if self.texture_pack.is_some() {
+            self.texels[usize::from(x)] = main_texel;
         }
         (
             main,
             sub,
             layer_color_math.unwrap_or_else(|| self.color_math.backdrop),
+            tinted,
         )
     }
 
+    /// The pixels of every layer at `x`, `y`, see [`LayerFrames`]
+    fn layer_pixels(&mut self, x: u8, y: u16) -> [[u8; 4]; 6] {
+        let mut colors: [Option<Color>; 6] = [None; 6];
+        colors[BACKDROP_LAYER] = Some(self.cgram.main_screen_backdrop().into());
+        for draw_ly_idx in 0..self.draw_layers.size {
+            let draw_ly = self.draw_layers.arr[usize::from(draw_ly_idx)];
+            let (layer, _) = draw_ly.source();
+            let ly = self.get_layer_from_draw_layer(&draw_ly);
+            if colors[layer].is_some()
+                || !ly.main_screen
+                || (ly.window_area_main_screen && self.is_in_window(x, &ly.window))
+            {
+                continue;
+            }
+            colors[layer] = match draw_ly {
+                DrawLayer::Bg { nr, bits, prio } => self.fetch_bg_tile(x.into(), y, nr, bits, prio),
+                DrawLayer::Sprite { prio } => {
+                    let entry = self.obj_cache[usize::from(x)];
+                    (prio == entry.prio && entry.palette_addr != 0)
+                        .then(|| self.cgram.read16(entry.palette_addr).into())
+                }
+            };
+        }
+        colors.map(|color| match color {
+            Some(color) => {
+                let [r, g, b, _] = color.to_rgba8_with_brightness(self.brightness);
+                [r, g, b, 0xff]
+            }
+            None => [0; 4],
+        })
+    }
+
     pub fn draw_pixel(&mut self, x: u8, y: u16) -> [u8; 4] {
         let mut lazy_in_window = None;
         let mut in_window = || {
@@ -1240,12 +1708,17 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             0 | 3 => i == 0,
             _ => (i == 2) ^ in_window(),
         });
-        let (main, sub, color_math) = self.fetch_screen(
+        let (main, sub, color_math, tinted) = self.fetch_screen(
             x,
             y,
             main_enable,
             color_enable && self.color_math.add_subscreen,
         );
+        self.pixel_source.color_math = color_math && color_enable && !tinted;
+        if tinted {
+            // neither color math nor the brightness hide the tint
+            return main.to_rgba8_with_brightness(15);
+        }
         let color = if color_math && color_enable {
             let sub_or_backdrop = sub.unwrap_or(self.color_math.color);
             let mut color = if self.color_math.subtract_color {
@@ -1256,6 +1729,9 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             if self.color_math.half_color && main_enable && sub.is_some() {
                 color = color.half();
             }
+            if self.texture_pack.is_some() {
+                self.texels[usize::from(x)] = None;
+            }
             color.map(|c| c.clamp(0, 0x1f))
         } else {
             main
@@ -1271,6 +1747,11 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         let prio = obj.get_priority();
         let tile_addr = obj.get_tile_addr(base, tile_x, tile_y);
         let tile = self.fetch_tile_by_nr(row.into(), tile_addr, 0, false, 4);
+        let texel = if self.texture_pack.is_some() {
+            self.hash_obj_tile(tile_addr, palette_nr, row, [xflip, obj.is_yflip()])
+        } else {
+            None
+        };
         for x in 0u8..8 {
             let off = i16::from(x).wrapping_add(i16::from(tile_x) << 3);
             let gx = (if xflip {
@@ -1287,6 +1768,13 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                         palette_addr: 0x80 | (palette_nr << 4) | palette_idx,
                         prio,
                     };
+                    self.obj_tile_addrs[gx as usize] = tile_addr;
+                    if texel.is_some() {
+                        self.obj_texels[gx as usize] = texel.map(|mut texel| {
+                            texel.pos[0] = x;
+                            texel
+                        });
+                    }
                 };
             }
         }
@@ -1349,6 +1837,15 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
                 bg.mosaic_start = Some(y);
             }
         }
+        let mut layer_frames = self.layer_frames.take();
+        if let Some(frames) = &mut layer_frames {
+            for layer in &mut frames.layers {
+                layer[n..n + 256].fill([0; 4])
+            }
+        }
+        if let Some(sources) = &mut self.pixel_sources {
+            sources[n..n + 256].fill(PixelSource::BACKDROP)
+        }
         if self.force_blank {
             self.frame_buffer.mut_pixels()[n..n + 256].fill([0; 4])
         } else {
@@ -1361,9 +1858,107 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
             self.mode7_settings.update_tmp3::<1>();
             for x in 0u8..=255 {
                 self.frame_buffer.mut_pixels()[n] = self.draw_pixel(x, y);
+                let source = self.pixel_source;
+                if let Some(sources) = &mut self.pixel_sources {
+                    sources[n] = source
+                }
+                if let Some(frames) = &mut layer_frames {
+                    for (layer, pixel) in frames.layers.iter_mut().zip(self.layer_pixels(x, y)) {
+                        layer[n] = pixel
+                    }
+                }
                 n += 1;
             }
         }
+        self.layer_frames = layer_frames;
+        if self.hd_frame.is_some() {
+            self.draw_hd_scanline(y)
+        }
+    }
+
+    /// Draw a pixel of the widescreen area, see [`HdFrame`]
+    fn draw_widescreen_pixel(&mut self, x: i16, y: u16, bgs: [bool; 4]) -> [u8; 4] {
+        let mut color = None;
+        for draw_ly_idx in 0..self.draw_layers.size {
+            let draw_ly = self.draw_layers.arr[usize::from(draw_ly_idx)];
+            if !self.get_layer_from_draw_layer(&draw_ly).main_screen {
+                continue;
+            }
+            if let DrawLayer::Bg { nr, bits, prio } = draw_ly {
+                if bgs[usize::from(nr)] || self.bg_mode.num == 7 {
+                    color = self
+                        .fetch_bg_tile(x, y, nr, bits, prio)
+                        .map(|color| (color, draw_ly.source()));
+                    if color.is_some() {
+                        break;
+                    }
+                }
+            }
+        }
+        let (color, (layer, prio)) = color.unwrap_or_else(|| {
+            let backdrop = self.cgram.main_screen_backdrop().into();
+            (backdrop, (BACKDROP_LAYER, 3))
+        });
+        if self.tinted_layers[layer] {
+            tint(layer, prio, color).to_rgba8_with_brightness(15)
+        } else {
+            color.to_rgba8_with_brightness(self.brightness)
+        }
+    }
+
+    /// Draw the current scanline into `hd_frame`
+    fn draw_hd_scanline(&mut self, y: u16) {
+        let mut hd_frame = match self.hd_frame.take() {
+            Some(hd_frame) => hd_frame,
+            None => return,
+        };
+        let scale = usize::from(hd_frame.scale);
+        let width = hd_frame.width() as usize;
+        let widescreen = i16::from(hd_frame.widescreen);
+        let row = usize::from(self.pos.y) * 256;
+        let hd_row = usize::from(self.pos.y) * scale * width;
+        let upscale = self.bg_mode.num == 7 && !self.force_blank;
+        let texture_pack = self.texture_pack.take();
+        let brightness = u16::from(self.brightness.clamp(0, 15));
+        for x in -widescreen..256 + widescreen {
+            let pixel = if self.force_blank {
+                [0; 4]
+            } else if (0..256).contains(&x) {
+                self.frame_buffer.pixels()[row + x as usize]
+            } else {
+                self.draw_widescreen_pixel(x, y, hd_frame.widescreen_bgs)
+            };
+            let replacement = texture_pack
+                .as_ref()
+                .filter(|_| !self.force_blank && (0..256).contains(&x))
+                .and_then(|pack| {
+                    let texel = self.texels[x as usize]?;
+                    Some((texel, pack.get(texel.hash)?))
+                });
+            let column = (x + widescreen) as usize * scale;
+            for sy in 0..scale {
+                for sx in 0..scale {
+                    let replaced = replacement
+                        .and_then(|(texel, texture)| texel.sample(texture, [sx, sy], scale));
+                    hd_frame.pixels[hd_row + sy * width + column + sx] =
+                        if let Some(pixel) = replaced {
+                            pixel.map(|c| (u16::from(c) * brightness / 15) as u8)
+                        } else if upscale && (sx | sy) > 0 {
+                            self.mode7_subpixel = [sx, sy].map(|s| ((s << 8) / scale) as i32);
+                            if (0..256).contains(&x) {
+                                self.draw_pixel(x as u8, y)
+                            } else {
+                                self.draw_widescreen_pixel(x, y, hd_frame.widescreen_bgs)
+                            }
+                        } else {
+                            pixel
+                        };
+                }
+            }
+        }
+        self.mode7_subpixel = [0; 2];
+        self.texture_pack = texture_pack;
+        self.hd_frame = Some(hd_frame);
     }
 
     pub fn is_in_window(&self, x: u8, window: &Window) -> bool {
@@ -1384,6 +1979,26 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
         }
     }
 
+    /// The 256 colors of CGRAM as BGR555
+    pub fn palette(&self) -> [u16; 256] {
+        core::array::from_fn(|i| self.cgram.read16(i as u8))
+    }
+
+    /// Replace the colors of CGRAM starting with the color `first`, e.g. to
+    /// preview a modified palette. The game may overwrite them at any time.
+    pub fn set_palette(&mut self, first: u8, colors: &[u16]) {
+        for (addr, &color) in (first..=255).zip(colors) {
+            self.cgram.write16(addr, color)
+        }
+    }
+
+    pub fn layers(&self) -> impl Iterator<Item = &Layer> {
+        self.bgs
+            .iter()
+            .map(|bg| &bg.layer)
+            .chain(core::iter::once(&self.obj_layer))
+    }
+
     pub fn layers_mut(&mut self) -> impl Iterator<Item = &mut Layer> {
         self.bgs
             .iter_mut()
@@ -1443,7 +2058,7 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
     }
 
     pub fn is_cpu_active(&self) -> bool {
-        !(536..536 + 40).contains(&self.pos.x)
+        !(536..536 + self.refresh_cycles).contains(&self.pos.x)
     }
 
     pub fn end_vblank(&mut self) {
@@ -1469,6 +2084,159 @@ impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
     }
 }
 
+impl<FB: crate::backend::FrameBuffer> Ppu<FB> {
+    /// Get a human-readable view of the current PPU register state.
+    /// It is printed with the [`Display`](std::fmt::Display) trait.
+    pub fn registers(&self) -> RegisterView<'_, FB> {
+        RegisterView(self)
+    }
+}
+
+/// The PPU registers decoded into readable fields
+pub struct RegisterView<'a, FB: crate::backend::FrameBuffer>(&'a Ppu<FB>);
+
+impl std::fmt::Display for Window {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for i in 0..2 {
+            let state = match (self.windows[i], self.window_inversion[i]) {
+                (false, _) => "off",
+                (true, false) => "in",
+                (true, true) => "out",
+            };
+            write!(f, "W{}={:<3} ", i + 1, state)?;
+        }
+        write!(f, "logic={:?}", self.mask_logic)
+    }
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let flag = |b: bool, c: char| if b { c } else { '-' };
+        write!(
+            f,
+            "[{}{}{}{}{}] {}",
+            flag(self.main_screen, 'M'),
+            flag(self.sub_screen, 'S'),
+            flag(self.window_area_main_screen, 'm'),
+            flag(self.window_area_sub_screen, 's'),
+            flag(self.color_math, 'C'),
+            self.window
+        )
+    }
+}
+
+impl<'a, FB: crate::backend::FrameBuffer> std::fmt::Display for RegisterView<'a, FB> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ppu = self.0;
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(
+            f,
+            "INIDISP  force blank: {}, brightness: {}",
+            yes_no(ppu.force_blank),
+            ppu.brightness
+        )?;
+        writeln!(
+            f,
+            "BGMODE   mode {}, BG3 priority: {}, EXTBG: {}",
+            ppu.bg_mode.num,
+            yes_no(ppu.bg_mode.bg3_prio),
+            yes_no(ppu.bg_mode.extbg)
+        )?;
+        let [[sw, sh], [lw, lh]] = ppu.obj_size;
+        writeln!(
+            f,
+            "OBSEL    sizes: {}x{} / {}x{}, tiles: ${:04x} / ${:04x}",
+            sw, sh, lw, lh, ppu.obj_tile_addr[0], ppu.obj_tile_addr[1]
+        )?;
+        writeln!(
+            f,
+            "SETINI   interlace: {}, OBJ interlace: {}, overscan: {}, pseudo-hires: {}",
+            yes_no(ppu.interlace_active),
+            yes_no(ppu.object_interlace),
+            yes_no(ppu.overscan),
+            yes_no(ppu.pseudo512)
+        )?;
+        writeln!(f, "MOSAIC   size: {}", ppu.mosaic_size)?;
+        writeln!(
+            f,
+            "WINDOWS  W1: {}..{}, W2: {}..{}  (layer flags: Main/Sub screen, \
+             main/sub window, Color math)",
+            ppu.window_positions[0][0],
+            ppu.window_positions[0][1],
+            ppu.window_positions[1][0],
+            ppu.window_positions[1][1]
+        )?;
+        for (i, bg) in ppu.bgs.iter().enumerate() {
+            writeln!(
+                f,
+                "BG{}      tiles: {}x{}, map: ${:04x} ({}x{}), chr: ${:04x}, \
+                 scroll: ({}, {}), mosaic: {}",
+                i + 1,
+                bg.tile_size[0],
+                bg.tile_size[1],
+                bg.map_base_addr,
+                bg.size[0],
+                bg.size[1],
+                bg.tile_base_addr,
+                bg.scroll[0] & 0x3ff,
+                bg.scroll[1] & 0x3ff,
+                yes_no(bg.mosaic)
+            )?;
+            writeln!(f, "         {}", bg.layer)?;
+        }
+        writeln!(f, "OBJ      {}", ppu.obj_layer)?;
+        let m7 = &ppu.mode7_settings;
+        let signed13 = |n: u16| sign_extend::<13>(n) as i16;
+        writeln!(
+            f,
+            "MODE7    A={:04x} B={:04x} C={:04x} D={:04x}, center: ({}, {}), \
+             offset: ({}, {}), flip: {}{}, outside: {}",
+            m7.params[0],
+            m7.params[1],
+            m7.params[2],
+            m7.params[3],
+            signed13(m7.center[0]),
+            signed13(m7.center[1]),
+            signed13(m7.offset[0]),
+            signed13(m7.offset[1]),
+            if m7.x_mirror { "x" } else { "-" },
+            if m7.y_mirror { "y" } else { "-" },
+            match (m7.wrap, m7.fill) {
+                (true, _) => "wrap",
+                (false, false) => "transparent",
+                (false, true) => "tile 0",
+            }
+        )?;
+        let cm = &ppu.color_math;
+        const REGIONS: [&str; 4] = ["never", "outside window", "inside window", "always"];
+        writeln!(
+            f,
+            "CGWSEL   clip to black: {}, prevent math: {}, add: {}, direct color: {}",
+            REGIONS[usize::from((cm.behaviour >> 2) & 3)],
+            REGIONS[usize::from(cm.behaviour & 3)],
+            if cm.add_subscreen {
+                "sub screen"
+            } else {
+                "fixed color"
+            },
+            yes_no(ppu.direct_color_mode)
+        )?;
+        writeln!(
+            f,
+            "CGADSUB  {}{}, backdrop: {}, {}",
+            if cm.subtract_color { "subtract" } else { "add" },
+            if cm.half_color { " (half)" } else { "" },
+            yes_no(cm.backdrop),
+            cm.window
+        )?;
+        write!(
+            f,
+            "COLDATA  r={} g={} b={}",
+            cm.color.r, cm.color.g, cm.color.b
+        )
+    }
+}
+
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, InSaveState)]
 pub struct RemapMode {
     mask: u16,
@@ -1491,3 +2259,6 @@ impl RemapMode {
         (((rest_part >> self.shift) | (rest_part << 3)) & self.mask) | addr_part
     }
 }
+
+#[cfg(test)]
+mod tests;
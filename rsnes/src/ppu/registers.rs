@@ -0,0 +1,333 @@
+//! Typed access to single PPU registers
+//!
+//! Most PPU registers are write-only, and reading the others has side
+//! effects (flip-flops, latches and address increments). [`Ppu::peek_register`]
+//! returns the value of a register, as it was last written or as it would be
+//! read, without changing anything. [`Ppu::poke_register`] has the effect of
+//! a write to the register.
+//!
+//! Registers written twice (scrolling, Mode 7 parameters, VRAM and OAM
+//! address) are a single 16-bit value here, the write-twice latches are left
+//! alone. The data ports of OAM, VRAM and CGRAM aren't registers here.
+
+use super::{sign_extend, Ppu, Window, CHIP_5C77_VERSION, CHIP_5C78_VERSION, OBJ_SIZES};
+use crate::backend::FrameBuffer;
+
+macro_rules! registers {
+    ($($reg:ident = $addr:literal, $name:literal,)*) => {
+        /// A PPU register, or a pair of registers holding a 16-bit value
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Register {
+            $($reg,)*
+        }
+
+        impl Register {
+            /// All registers ordered by their address
+            pub const ALL: &'static [Self] = &[$(Self::$reg,)*];
+
+            /// The address of the register (of its low byte) in `$21xx`
+            pub const fn address(self) -> u8 {
+                match self {
+                    $(Self::$reg => $addr,)*
+                }
+            }
+
+            /// The common name of the register, e.g. `BG1HOFS`
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Self::$reg => $name,)*
+                }
+            }
+        }
+    };
+}
+
+registers! {
+    Inidisp = 0x00, "INIDISP",
+    Obsel = 0x01, "OBSEL",
+    Oamadd = 0x02, "OAMADD",
+    Bgmode = 0x05, "BGMODE",
+    Mosaic = 0x06, "MOSAIC",
+    Bg1sc = 0x07, "BG1SC",
+    Bg2sc = 0x08, "BG2SC",
+    Bg3sc = 0x09, "BG3SC",
+    Bg4sc = 0x0a, "BG4SC",
+    Bg12nba = 0x0b, "BG12NBA",
+    Bg34nba = 0x0c, "BG34NBA",
+    Bg1hofs = 0x0d, "BG1HOFS",
+    M7hofs = 0x0d, "M7HOFS",
+    Bg1vofs = 0x0e, "BG1VOFS",
+    M7vofs = 0x0e, "M7VOFS",
+    Bg2hofs = 0x0f, "BG2HOFS",
+    Bg2vofs = 0x10, "BG2VOFS",
+    Bg3hofs = 0x11, "BG3HOFS",
+    Bg3vofs = 0x12, "BG3VOFS",
+    Bg4hofs = 0x13, "BG4HOFS",
+    Bg4vofs = 0x14, "BG4VOFS",
+    Vmain = 0x15, "VMAIN",
+    Vmadd = 0x16, "VMADD",
+    M7sel = 0x1a, "M7SEL",
+    M7a = 0x1b, "M7A",
+    M7b = 0x1c, "M7B",
+    M7c = 0x1d, "M7C",
+    M7d = 0x1e, "M7D",
+    M7x = 0x1f, "M7X",
+    M7y = 0x20, "M7Y",
+    Cgadd = 0x21, "CGADD",
+    W12sel = 0x23, "W12SEL",
+    W34sel = 0x24, "W34SEL",
+    Wobjsel = 0x25, "WOBJSEL",
+    Wh0 = 0x26, "WH0",
+    Wh1 = 0x27, "WH1",
+    Wh2 = 0x28, "WH2",
+    Wh3 = 0x29, "WH3",
+    Wbglog = 0x2a, "WBGLOG",
+    Wobjlog = 0x2b, "WOBJLOG",
+    Tm = 0x2c, "TM",
+    Ts = 0x2d, "TS",
+    Tmw = 0x2e, "TMW",
+    Tsw = 0x2f, "TSW",
+    Cgwsel = 0x30, "CGWSEL",
+    Cgadsub = 0x31, "CGADSUB",
+    Coldata = 0x32, "COLDATA",
+    Setini = 0x33, "SETINI",
+    Mpyl = 0x34, "MPYL",
+    Mpym = 0x35, "MPYM",
+    Mpyh = 0x36, "MPYH",
+    Rdvram = 0x39, "RDVRAM",
+    Ophct = 0x3c, "OPHCT",
+    Opvct = 0x3d, "OPVCT",
+    Stat77 = 0x3e, "STAT77",
+    Stat78 = 0x3f, "STAT78",
+}
+
+impl Register {
+    /// Find a register by its name, ignoring the case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|reg| reg.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the register can be written, see [`Ppu::poke_register`]
+    pub const fn is_writable(self) -> bool {
+        self.address() <= 0x33
+    }
+}
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (${:04x})",
+            self.name(),
+            0x2100 | u16::from(self.address())
+        )
+    }
+}
+
+impl Window {
+    /// The bits of the window in WnnSEL, the inverse of [`Window::select`]
+    fn selection(&self) -> u8 {
+        u8::from(self.window_inversion[0])
+            | (u8::from(self.windows[0]) << 1)
+            | (u8::from(self.window_inversion[1]) << 2)
+            | (u8::from(self.windows[1]) << 3)
+    }
+}
+
+/// Combine flags to a value, the first flag is bit 0
+fn bits(flags: impl Iterator<Item = bool>) -> u16 {
+    flags
+        .enumerate()
+        .fold(0, |val, (i, flag)| val | (u16::from(flag) << i))
+}
+
+impl<FB: FrameBuffer> Ppu<FB> {
+    /// Get the value of a register without any side effects.
+    ///
+    /// Write-only registers return the value decoded by the PPU. Bits,
+    /// which aren't stored by it, are 0. So are the tile sizes of BGMODE in
+    /// modes 6 and 7, which are fixed in these modes. COLDATA is the fixed
+    /// color as BGR555.
+    pub fn peek_register(&self, reg: Register) -> u16 {
+        use Register::*;
+        let m7 = &self.mode7_settings;
+        match reg {
+            Inidisp => (u16::from(self.force_blank) << 7) | u16::from(self.brightness),
+            Obsel => {
+                let size = OBJ_SIZES.iter().position(|s| *s == self.obj_size);
+                let [addr, gap_addr] = self.obj_tile_addr;
+                let gap = gap_addr.wrapping_sub(addr).wrapping_sub(0x1000) >> 9;
+                ((size.unwrap_or(0) as u16) << 5) | (gap & 0x18) | (addr >> 13)
+            }
+            Oamadd => (self.oam.addr >> 1) | (u16::from(self.oam.priority) << 15),
+            Bgmode => {
+                let tile_sizes = match self.bg_mode.num {
+                    6 | 7 => 0,
+                    _ => bits(self.bgs.iter().map(|bg| bg.tile_size[1] == 16)),
+                };
+                u16::from(self.bg_mode.num)
+                    | (u16::from(self.bg_mode.bg3_prio) << 3)
+                    | (tile_sizes << 4)
+            }
+            Mosaic => {
+                (u16::from(self.mosaic_size.saturating_sub(1)) << 4)
+                    | bits(self.bgs.iter().map(|bg| bg.mosaic))
+            }
+            Bg1sc | Bg2sc | Bg3sc | Bg4sc => {
+                let bg = &self.bgs[usize::from(reg.address() - 0x07)];
+                ((bg.map_base_addr >> 8) & 0xfc)
+                    | u16::from(bg.size[0] > 32)
+                    | (u16::from(bg.size[1] > 32) << 1)
+            }
+            Bg12nba | Bg34nba => {
+                let nr = usize::from(reg.address() - 0x0b) << 1;
+                (self.bgs[nr].tile_base_addr >> 12)
+                    | ((self.bgs[nr | 1].tile_base_addr >> 8) & 0xf0)
+            }
+            Bg1hofs | Bg1vofs | Bg2hofs | Bg2vofs | Bg3hofs | Bg3vofs | Bg4hofs | Bg4vofs => {
+                let id = usize::from(reg.address() - 0x0d);
+                self.bgs[id >> 1].scroll[id & 1] & 0x3ff
+            }
+            M7hofs => m7.offset[0] & 0x1fff,
+            M7vofs => m7.offset[1] & 0x1fff,
+            Vmain => {
+                let steps = match self.vram.steps {
+                    1 => 0,
+                    32 => 1,
+                    _ => 2,
+                };
+                let remap = if self.vram.remap_mode.mask > 0 {
+                    u16::from(self.vram.remap_mode.shift & 3)
+                } else {
+                    0
+                };
+                (u16::from(!self.vram.increment_first) << 7) | (remap << 2) | steps
+            }
+            Vmadd => self.vram.unmapped_addr,
+            M7sel => {
+                u16::from(m7.x_mirror)
+                    | (u16::from(m7.y_mirror) << 1)
+                    | (u16::from(m7.fill) << 6)
+                    | (u16::from(!m7.wrap) << 7)
+            }
+            M7a | M7b | M7c | M7d => m7.params[usize::from(reg.address() - 0x1b)],
+            M7x => m7.center[0] & 0x1fff,
+            M7y => m7.center[1] & 0x1fff,
+            Cgadd => self.cgram.addr >> 1,
+            W12sel | W34sel | Wobjsel => {
+                let [w1, w2] = match reg {
+                    W12sel => [&self.bgs[0].layer.window, &self.bgs[1].layer.window],
+                    W34sel => [&self.bgs[2].layer.window, &self.bgs[3].layer.window],
+                    _ => [&self.obj_layer.window, &self.color_math.window],
+                };
+                u16::from(w1.selection() | (w2.selection() << 4))
+            }
+            Wh0 | Wh1 | Wh2 | Wh3 => {
+                let id = usize::from(reg.address() - 0x26);
+                u16::from(self.window_positions[id >> 1][id & 1])
+            }
+            Wbglog => self.bgs.iter().enumerate().fold(0, |val, (i, bg)| {
+                val | (u16::from(bg.layer.window.mask_logic.to_byte()) << (i * 2))
+            }),
+            Wobjlog => {
+                u16::from(self.obj_layer.window.mask_logic.to_byte())
+                    | (u16::from(self.color_math.window.mask_logic.to_byte()) << 2)
+            }
+            Tm => bits(self.layers().map(|layer| layer.main_screen)),
+            Ts => bits(self.layers().map(|layer| layer.sub_screen)),
+            Tmw => bits(self.layers().map(|layer| layer.window_area_main_screen)),
+            Tsw => bits(self.layers().map(|layer| layer.window_area_sub_screen)),
+            Cgwsel => {
+                u16::from(self.direct_color_mode)
+                    | (u16::from(self.color_math.add_subscreen) << 1)
+                    | (u16::from(self.color_math.behaviour) << 4)
+            }
+            Cgadsub => {
+                let cm = &self.color_math;
+                bits(self.layers().map(|layer| layer.color_math))
+                    | (u16::from(cm.backdrop) << 5)
+                    | (u16::from(cm.half_color) << 6)
+                    | (u16::from(cm.subtract_color) << 7)
+            }
+            Coldata => {
+                let color = &self.color_math.color;
+                u16::from(color.r) | (u16::from(color.g) << 5) | (u16::from(color.b) << 10)
+            }
+            Setini => {
+                u16::from(self.interlace_active)
+                    | (u16::from(self.object_interlace) << 1)
+                    | (u16::from(self.overscan) << 2)
+                    | (u16::from(self.pseudo512) << 3)
+                    | (u16::from(self.bg_mode.extbg) << 6)
+            }
+            Mpyl | Mpym | Mpyh => {
+                let x = m7.params[0] as i16 as i32;
+                let y = (m7.params[1] >> 8) as i8 as i32;
+                u16::from(((x * y) as u32).to_le_bytes()[usize::from(reg.address() & 3)])
+            }
+            Rdvram => self.vram.buffered,
+            Ophct => self.latched.pos.x,
+            Opvct => self.latched.pos.y,
+            Stat77 => u16::from(self.overflow_flags | (self.open_bus1 & 0x10) | CHIP_5C77_VERSION),
+            Stat78 => u16::from(
+                (u8::from(self.latched.latched) << 6)
+                    | (self.open_bus2 & 0x20)
+                    | CHIP_5C78_VERSION
+                    | (u8::from(self.is_pal) << 4),
+            ),
+        }
+    }
+
+    /// Set a register, as if `value` was written to it. Registers written
+    /// twice are set at once, COLDATA takes a BGR555 color. Returns `false`,
+    /// if the register is read-only.
+    pub fn poke_register(&mut self, reg: Register, value: u16) -> bool {
+        use Register::*;
+        let m7 = &mut self.mode7_settings;
+        match reg {
+            Oamadd => {
+                let [low, high] = value.to_le_bytes();
+                self.oam.set_addr_low(low);
+                self.oam.set_addr_high(high);
+            }
+            Bg1hofs | Bg1vofs | Bg2hofs | Bg2vofs | Bg3hofs | Bg3vofs | Bg4hofs | Bg4vofs => {
+                let id = usize::from(reg.address() - 0x0d);
+                self.bgs[id >> 1].scroll[id & 1] = value & 0x3ff;
+            }
+            M7hofs => {
+                m7.offset[0] = sign_extend::<13>(value);
+                m7.update_tmp1::<0>();
+            }
+            M7vofs => {
+                m7.offset[1] = sign_extend::<13>(value);
+                m7.update_tmp1::<1>();
+            }
+            Vmadd => {
+                self.vram.unmapped_addr = value;
+                self.vram.update_mapped();
+                self.vram.prefetch();
+            }
+            M7a | M7b | M7c | M7d => m7.update_param(reg.address() - 0x1b, value),
+            M7x => {
+                m7.center[0] = sign_extend::<13>(value);
+                m7.update_tmp1::<0>();
+            }
+            M7y => {
+                m7.center[1] = sign_extend::<13>(value);
+                m7.update_tmp1::<1>();
+            }
+            Coldata => {
+                let component = |shift: u16| ((value >> shift) & 0x1f) as u8;
+                self.color_math.color.r = component(0);
+                self.color_math.color.g = component(5);
+                self.color_math.color.b = component(10);
+            }
+            _ if reg.is_writable() => self.write_register(reg.address(), value as u8),
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,734 @@
+//! Tests of the PPU in isolation
+//!
+//! A test is a TOML script, which sets up the PPU by register writes and
+//! uploads, renders one frame and compares pixels of the frame buffer:
+//!
+//! ```toml
+//! [[write]]          # write a register ($2100 to $2133)
+//! reg = 0x2100
+//! value = 0x0f
+//! line = 100         # optional: the vertical counter of the write,
+//! dot = 0            # and its dot (0 to 340), otherwise before the frame
+//!
+//! [[vram]]           # upload words through VMAIN, VMADD and VMDATA
+//! addr = 0x1000      # word address
+//! words = [0x00ff]
+//!
+//! [[cgram]]          # upload colors through CGADD and CGDATA
+//! addr = 0
+//! colors = [0x7fff]
+//!
+//! [[oam]]            # upload bytes through OAMADD and OAMDATA
+//! addr = 0
+//! bytes = [0, 0, 0, 0]
+//!
+//! [[expect]]         # pixels of the frame buffer
+//! x = 0
+//! y = 0
+//! width = 256        # optional: a rectangle of pixels
+//! height = 1
+//! color = 0x001f     # a BGR555 color at full brightness, or
+//! rgba = [0, 0, 0, 0]
+//! layer = "bg1"      # optional: the pixels of a single layer, see
+//!                    # `Ppu::layer_frames`
+//!
+//! [[peek]]           # a register after the frame, see `Ppu::peek_register`
+//! reg = "BG1HOFS"
+//! value = 0x0123
+//!
+//! tint = ["bg1"]     # optional: layers tinted, see `Ppu::tinted_layers`
+//! inspect = true     # optional: fill `Ppu::pixel_sources`
+//! ```
+//!
+//! Uploads accept `line` and `dot` as well. Like on hardware, the row `y` of
+//! the frame buffer is drawn near the end of the line `y`, at dot
+//! [`DRAW_DOT`], so writes in line `y` before that dot change the row `y`.
+//! Writes at the same time are executed in the order of the sections above,
+//! each in the order of the script.
+
+use super::*;
+use crate::backend::{ArrayFrameBuffer, FrameBuffer, FRAME_BUFFER_SIZE};
+
+type TestPpu = Ppu<ArrayFrameBuffer>;
+
+/// The dot, at which the PPU draws a line of the frame buffer
+const DRAW_DOT: u16 = (1364 - RAY_AHEAD_CYCLES) / 4;
+
+/// Register writes at the same time
+struct Event {
+    /// `None` before the frame
+    time: Option<(u16, u16)>,
+    writes: Vec<(u8, u8)>,
+}
+
+struct Expect {
+    pos: [u16; 2],
+    size: [u16; 2],
+    rgba: [u8; 4],
+    /// The index of the layer in [`LAYER_NAMES`]
+    layer: Option<usize>,
+}
+
+struct Script {
+    events: Vec<Event>,
+    expects: Vec<Expect>,
+    peeks: Vec<(Register, u16)>,
+    tinted_layers: [bool; 6],
+    inspect: bool,
+}
+
+fn int(table: &toml::Value, key: &str) -> Option<i64> {
+    table.get(key).map(|value| {
+        value
+            .as_integer()
+            .unwrap_or_else(|| panic!("`{key}` must be an integer"))
+    })
+}
+
+fn ints(table: &toml::Value, key: &str) -> Vec<i64> {
+    table
+        .get(key)
+        .and_then(toml::Value::as_array)
+        .unwrap_or_else(|| panic!("`{key}` must be an array"))
+        .iter()
+        .map(|value| value.as_integer().expect("expected an integer"))
+        .collect()
+}
+
+fn entries<'a>(script: &'a toml::Value, key: &str) -> &'a [toml::Value] {
+    script
+        .get(key)
+        .and_then(toml::Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+fn parse(script: &str) -> Script {
+    let script: toml::Value = script.parse().expect("invalid test script");
+    let mut events = vec![];
+    let mut push = |entry: &toml::Value, writes: Vec<(u8, u8)>| {
+        let time =
+            int(entry, "line").map(|line| (line as u16, int(entry, "dot").unwrap_or(0) as u16));
+        events.push(Event { time, writes })
+    };
+    for entry in entries(&script, "write") {
+        let reg = int(entry, "reg").expect("`reg` is missing");
+        assert!((0x2100..=0x2133).contains(&reg), "not a PPU write register");
+        let value = int(entry, "value").expect("`value` is missing");
+        push(entry, vec![(reg as u8, value as u8)]);
+    }
+    for entry in entries(&script, "vram") {
+        let [lo, hi] = (int(entry, "addr").unwrap_or(0) as u16).to_le_bytes();
+        let mut writes = vec![(0x15, 0x80), (0x16, lo), (0x17, hi)];
+        for word in ints(entry, "words") {
+            writes.extend([(0x18, word as u8), (0x19, (word >> 8) as u8)])
+        }
+        push(entry, writes);
+    }
+    for entry in entries(&script, "cgram") {
+        let mut writes = vec![(0x21, int(entry, "addr").unwrap_or(0) as u8)];
+        for color in ints(entry, "colors") {
+            writes.extend([(0x22, color as u8), (0x22, (color >> 8) as u8)])
+        }
+        push(entry, writes);
+    }
+    for entry in entries(&script, "oam") {
+        let [lo, hi] = (int(entry, "addr").unwrap_or(0) as u16 >> 1).to_le_bytes();
+        let mut writes = vec![(0x02, lo), (0x03, hi)];
+        writes.extend(
+            ints(entry, "bytes")
+                .into_iter()
+                .map(|byte| (0x04, byte as u8)),
+        );
+        push(entry, writes);
+    }
+    // the sort is stable, see the module documentation
+    events.sort_by_key(|event| event.time);
+    let expects = entries(&script, "expect")
+        .iter()
+        .map(|entry| {
+            let rgba = match (int(entry, "color"), entry.get("rgba")) {
+                (Some(color), None) => Color::from(color as u16).to_rgba8_with_brightness(15),
+                (None, Some(_)) => {
+                    let rgba: Vec<u8> = ints(entry, "rgba").iter().map(|&c| c as u8).collect();
+                    rgba.try_into().expect("`rgba` must have four components")
+                }
+                _ => panic!("expected either `color` or `rgba`"),
+            };
+            let get = |key| int(entry, key).unwrap_or(0) as u16;
+            let size = |key| int(entry, key).unwrap_or(1) as u16;
+            let layer = entry.get("layer").map(|name| {
+                (LAYER_NAMES.iter())
+                    .position(|layer| Some(*layer) == name.as_str())
+                    .expect("unknown `layer`")
+            });
+            Expect {
+                pos: [get("x"), get("y")],
+                size: [size("width"), size("height")],
+                rgba,
+                layer,
+            }
+        })
+        .collect();
+    let peeks = entries(&script, "peek")
+        .iter()
+        .map(|entry| {
+            let name = entry.get("reg").and_then(toml::Value::as_str);
+            let reg = name.and_then(Register::from_name).expect("unknown `reg`");
+            (reg, int(entry, "value").expect("`value` is missing") as u16)
+        })
+        .collect();
+    let mut tinted_layers = [false; 6];
+    for name in entries(&script, "tint") {
+        let index = LAYER_NAMES
+            .iter()
+            .position(|layer| Some(*layer) == name.as_str())
+            .expect("unknown layer in `tint`");
+        tinted_layers[index] = true;
+    }
+    let inspect = script.get("inspect").and_then(toml::Value::as_bool);
+    Script {
+        events,
+        expects,
+        peeks,
+        tinted_layers,
+        inspect: inspect.unwrap_or(false),
+    }
+}
+
+/// Execute the writes of a script and render one frame
+fn render(script: &Script) -> Box<TestPpu> {
+    let mut ppu = crate::test_util::with_stack(|| {
+        let frame_buffer = ArrayFrameBuffer([[0; 4]; FRAME_BUFFER_SIZE], false);
+        Box::new(Ppu::new(frame_buffer, false))
+    });
+    ppu.tinted_layers = script.tinted_layers;
+    if script.expects.iter().any(|expect| expect.layer.is_some()) {
+        ppu.layer_frames = Some(Box::default());
+    }
+    if script.inspect {
+        ppu.pixel_sources = Some(vec![PixelSource::default(); FRAME_BUFFER_SIZE]);
+    }
+    let mut events = script.events.iter().peekable();
+    let mut write_until = |ppu: &mut TestPpu, time: Option<(u16, u16)>| {
+        while let Some(event) = events.next_if(|event| event.time < time) {
+            for &(reg, value) in &event.writes {
+                ppu.write_register(reg, value)
+            }
+        }
+    };
+    write_until(&mut ppu, Some((0, 0)));
+    ppu.end_vblank();
+    for line in 0..ppu.vend() {
+        ppu.mut_pos().y = line;
+        write_until(&mut ppu, Some((line, DRAW_DOT)));
+        if line + 1 < ppu.vend() {
+            ppu.mut_pos().x = DRAW_DOT * 4;
+            ppu.draw_scanline();
+        }
+        write_until(&mut ppu, Some((line + 1, 0)));
+    }
+    ppu
+}
+
+/// Run a test script and panic, if a pixel or a register is different from
+/// its expected value
+fn check(script: &str) {
+    let script = parse(script);
+    let ppu = render(&script);
+    for expect in &script.expects {
+        for y in expect.pos[1]..expect.pos[1] + expect.size[1] {
+            for x in expect.pos[0]..expect.pos[0] + expect.size[0] {
+                let i = usize::from(y) * 256 + usize::from(x);
+                let pixel = match (expect.layer, &ppu.layer_frames) {
+                    (Some(layer), Some(frames)) => frames.layers[layer][i],
+                    _ => ppu.frame_buffer.pixels()[i],
+                };
+                assert_eq!(pixel, expect.rgba, "pixel ({x}, {y})");
+            }
+        }
+    }
+    for &(reg, value) in &script.peeks {
+        assert_eq!(ppu.peek_register(reg), value, "register {reg}");
+    }
+}
+
+#[test]
+pub fn test_backdrop() {
+    check(
+        r#"
+        [[cgram]]
+        colors = [0x001f]
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+
+        [[expect]]
+        width = 256
+        height = 224
+        color = 0x001f
+        "#,
+    )
+}
+
+#[test]
+pub fn test_mode0_bg1_tile() {
+    // tile 1 is filled with color 1, the tile map at word 0x400 shows it in
+    // the top left corner
+    check(
+        r#"
+        [[vram]]
+        addr = 0x0008
+        words = [0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff]
+
+        [[vram]]
+        addr = 0x0400
+        words = [0x0001]
+
+        [[cgram]]
+        colors = [0x0000, 0x03e0]
+
+        [[write]]
+        reg = 0x2107
+        value = 0x04
+
+        [[write]]
+        reg = 0x212c
+        value = 0x01
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+
+        # the first row shows the second line of the background
+        [[expect]]
+        width = 8
+        height = 7
+        color = 0x03e0
+
+        [[expect]]
+        x = 8
+        width = 8
+        height = 7
+        color = 0x0000
+
+        [[expect]]
+        y = 7
+        width = 8
+        color = 0x0000
+        "#,
+    )
+}
+
+#[test]
+pub fn test_raster_writes() {
+    // the brightness is changed before line 100 is drawn, the color after
+    // line 150 was drawn
+    check(
+        r#"
+        [[cgram]]
+        colors = [0x7fff]
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+
+        [[write]]
+        reg = 0x2100
+        value = 0x07
+        line = 100
+
+        [[cgram]]
+        colors = [0x7c00]
+        line = 150
+        dot = 330
+
+        [[expect]]
+        y = 99
+        width = 256
+        color = 0x7fff
+
+        [[expect]]
+        y = 100
+        width = 256
+        rgba = [119, 119, 119, 255]
+
+        [[expect]]
+        y = 150
+        width = 256
+        rgba = [119, 119, 119, 255]
+
+        [[expect]]
+        y = 151
+        width = 256
+        rgba = [0, 0, 119, 255]
+        "#,
+    )
+}
+
+#[test]
+pub fn test_forced_blank_lines() {
+    check(
+        r#"
+        [[cgram]]
+        colors = [0x7fff]
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+
+        [[write]]
+        reg = 0x2100
+        value = 0x80
+        line = 50
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+        line = 60
+
+        [[expect]]
+        y = 49
+        width = 256
+        color = 0x7fff
+
+        [[expect]]
+        y = 50
+        width = 256
+        height = 10
+        rgba = [0, 0, 0, 0]
+
+        [[expect]]
+        y = 60
+        width = 256
+        color = 0x7fff
+        "#,
+    )
+}
+
+#[test]
+pub fn test_tinted_layers() {
+    // BG1 is tinted by its color and its low priority, the backdrop isn't
+    check(
+        r#"
+        tint = ["bg1"]
+
+        [[vram]]
+        addr = 0x0008
+        words = [0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff]
+
+        [[vram]]
+        addr = 0x0400
+        words = [0x0001]
+
+        [[cgram]]
+        colors = [0x0000, 0x03e0]
+
+        [[write]]
+        reg = 0x2107
+        value = 0x04
+
+        [[write]]
+        reg = 0x212c
+        value = 0x01
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+
+        [[expect]]
+        width = 8
+        height = 7
+        rgba = [148, 16, 16, 255]
+
+        [[expect]]
+        x = 8
+        width = 8
+        height = 7
+        color = 0x0000
+        "#,
+    )
+}
+
+#[test]
+pub fn test_tinted_backdrop() {
+    // the tint ignores the brightness
+    check(
+        r#"
+        tint = ["backdrop"]
+
+        [[cgram]]
+        colors = [0x001f]
+
+        [[write]]
+        reg = 0x2100
+        value = 0x07
+
+        [[expect]]
+        width = 256
+        height = 224
+        color = 0x318c
+        "#,
+    )
+}
+
+/// Writes of every kind of register, which are checked by their decoded
+/// value and written back by [`Ppu::poke_register`]
+const REGISTER_SCRIPT: &str = r#"
+    write = [
+        { reg = 0x2101, value = 0x6b },
+        { reg = 0x2105, value = 0x19 },
+        { reg = 0x2106, value = 0x35 },
+        { reg = 0x2108, value = 0x7d },
+        { reg = 0x210c, value = 0x52 },
+        { reg = 0x210f, value = 0x23 },
+        { reg = 0x210f, value = 0x01 },
+        { reg = 0x210d, value = 0x00 },
+        { reg = 0x210d, value = 0x1f },
+        { reg = 0x2115, value = 0x85 },
+        { reg = 0x211a, value = 0x41 },
+        { reg = 0x211b, value = 0x00 },
+        { reg = 0x211b, value = 0x01 },
+        { reg = 0x211c, value = 0x34 },
+        { reg = 0x211c, value = 0xfe },
+        { reg = 0x2123, value = 0x3a },
+        { reg = 0x2128, value = 0x80 },
+        { reg = 0x212a, value = 0x1b },
+        { reg = 0x212c, value = 0x13 },
+        { reg = 0x2131, value = 0xa2 },
+        { reg = 0x2132, value = 0x3f },
+        { reg = 0x2132, value = 0x84 },
+    ]
+    peek = [
+        { reg = "OBSEL", value = 0x6b },
+        { reg = "BGMODE", value = 0x19 },
+        { reg = "MOSAIC", value = 0x35 },
+        { reg = "BG2SC", value = 0x7d },
+        { reg = "BG34NBA", value = 0x52 },
+        { reg = "BG2HOFS", value = 0x0123 },
+        { reg = "M7HOFS", value = 0x1f00 },
+        { reg = "VMAIN", value = 0x85 },
+        { reg = "M7SEL", value = 0x41 },
+        { reg = "M7A", value = 0x0100 },
+        { reg = "M7B", value = 0xfe34 },
+        { reg = "MPYL", value = 0x00 },
+        { reg = "MPYM", value = 0xfe },
+        { reg = "MPYH", value = 0xff },
+        { reg = "W12SEL", value = 0x3a },
+        { reg = "WH2", value = 0x80 },
+        { reg = "WBGLOG", value = 0x1b },
+        { reg = "TM", value = 0x13 },
+        { reg = "CGADSUB", value = 0xa2 },
+        { reg = "COLDATA", value = 0x101f },
+    ]
+"#;
+
+#[test]
+pub fn test_peek_registers() {
+    check(REGISTER_SCRIPT)
+}
+
+#[test]
+pub fn test_poke_registers() {
+    let written = render(&parse(REGISTER_SCRIPT));
+    let mut poked = render(&parse(""));
+    for &reg in Register::ALL.iter().filter(|reg| reg.is_writable()) {
+        assert!(poked.poke_register(reg, written.peek_register(reg)));
+    }
+    for &reg in Register::ALL.iter().filter(|reg| reg.is_writable()) {
+        assert_eq!(
+            poked.peek_register(reg),
+            written.peek_register(reg),
+            "register {reg}"
+        );
+    }
+    assert!(!poked.poke_register(Register::Stat78, 0));
+}
+
+#[test]
+pub fn test_peek_has_no_side_effects() {
+    let mut ppu = render(&parse(""));
+    ppu.latch();
+    let stat78 = ppu.peek_register(Register::Stat78);
+    assert_eq!(stat78 & 0x40, 0x40);
+    assert_eq!(ppu.peek_register(Register::Stat78), stat78);
+    assert_eq!(ppu.read_register(0x3f), Some(stat78 as u8));
+    assert_eq!(ppu.peek_register(Register::Stat78) & 0x40, 0);
+}
+
+#[test]
+pub fn test_palette() {
+    let script = parse(
+        r#"
+        [[cgram]]
+        addr = 1
+        colors = [0x7fff, 0x1234]
+        "#,
+    );
+    let mut ppu = render(&script);
+    assert_eq!(ppu.palette()[..3], [0, 0x7fff, 0x1234]);
+    // the colors don't wrap around and the unused bit is cleared
+    ppu.set_palette(2, &[0x03e0, 0xffff]);
+    assert_eq!(ppu.palette()[1..4], [0x7fff, 0x03e0, 0x7fff]);
+    ppu.set_palette(255, &[0x001f, 0x001f]);
+    assert_eq!(ppu.palette()[0], 0);
+    assert_eq!(ppu.palette()[255], 0x001f);
+}
+
+#[test]
+pub fn test_layer_frames() {
+    // a tile of BG1 in the top left corner and a sprite of the same color
+    // next to it, the pixels of each layer are drawn separately
+    check(
+        r#"
+        [[vram]]
+        addr = 0x0008
+        words = [0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff]
+
+        [[vram]]
+        addr = 0x0400
+        words = [0x0001]
+
+        [[vram]]
+        addr = 0x4010
+        words = [0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff]
+
+        [[cgram]]
+        colors = [0x7c00, 0x03e0]
+
+        [[cgram]]
+        addr = 0x81
+        colors = [0x001f]
+
+        [[oam]]
+        bytes = [16, 100, 1, 0]
+
+        [[write]]
+        reg = 0x2101
+        value = 0x02
+
+        [[write]]
+        reg = 0x2107
+        value = 0x04
+
+        [[write]]
+        reg = 0x212c
+        value = 0x11
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+
+        [[expect]]
+        width = 8
+        height = 7
+        layer = "bg1"
+        color = 0x03e0
+
+        [[expect]]
+        x = 8
+        width = 248
+        height = 7
+        layer = "bg1"
+        rgba = [0, 0, 0, 0]
+
+        [[expect]]
+        x = 16
+        y = 100
+        width = 8
+        height = 8
+        layer = "obj"
+        color = 0x001f
+
+        [[expect]]
+        width = 256
+        height = 100
+        layer = "obj"
+        rgba = [0, 0, 0, 0]
+
+        [[expect]]
+        width = 256
+        height = 224
+        layer = "backdrop"
+        color = 0x7c00
+
+        [[expect]]
+        width = 256
+        height = 224
+        layer = "bg2"
+        rgba = [0, 0, 0, 0]
+
+        [[expect]]
+        x = 16
+        y = 100
+        color = 0x001f
+        "#,
+    )
+}
+
+#[test]
+pub fn test_pixel_sources() {
+    // the scene of `test_layer_frames` with color math on BG1
+    let ppu = render(&parse(
+        r#"
+        inspect = true
+
+        [[vram]]
+        addr = 0x0008
+        words = [0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff]
+
+        [[vram]]
+        addr = 0x0400
+        words = [0x0001]
+
+        [[vram]]
+        addr = 0x4010
+        words = [0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff, 0x00ff]
+
+        [[oam]]
+        bytes = [16, 100, 1, 0]
+
+        [[write]]
+        reg = 0x2101
+        value = 0x02
+
+        [[write]]
+        reg = 0x2107
+        value = 0x04
+
+        [[write]]
+        reg = 0x212c
+        value = 0x11
+
+        [[write]]
+        reg = 0x2131
+        value = 0x01
+
+        [[write]]
+        reg = 0x2100
+        value = 0x0f
+        "#,
+    ));
+    let sources = ppu.pixel_sources.as_ref().unwrap();
+    let bg1 = PixelSource {
+        layer: 0,
+        priority: 0,
+        tile_addr: Some(0x0008),
+        palette: 0,
+        color: 1,
+        cgram_addr: Some(1),
+        color_math: true,
+    };
+    let obj = PixelSource {
+        layer: OBJ_LAYER as u8,
+        priority: 0,
+        tile_addr: Some(0x4010),
+        palette: 0,
+        color: 1,
+        cgram_addr: Some(0x81),
+        color_math: false,
+    };
+    assert_eq!(sources[0], bg1);
+    assert_eq!(sources[100 * 256 + 16], obj);
+    assert_eq!(sources[50 * 256 + 100], PixelSource::default());
+}
@@ -0,0 +1,96 @@
+//! Host time spent in the components of the emulation
+//!
+//! Profiling is enabled with
+//! [`Device::enable_profiling`](crate::device::Device::enable_profiling),
+//! which takes the clock of the host, so the core doesn't depend on a system
+//! clock. Whenever another component starts running, the time since the last
+//! switch is added to the component, which ran until then. So nested work is
+//! accounted to the component doing it, e.g. catching up the S-SMP on an
+//! access of its ports counts towards the S-SMP, not the main CPU. Other
+//! accesses count towards the accessing component, e.g. the memory of a
+//! coprocessor read by the main CPU.
+//!
+//! Reading the clock takes time itself, so the emulation runs slower while
+//! profiling.
+
+use core::time::Duration;
+
+/// The time since an arbitrary, fixed point in time
+pub type Clock = fn() -> Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    /// The main CPU, including the memory accesses of its instructions
+    Cpu,
+    /// DMA and HDMA transfers
+    Dma,
+    /// Rendering scanlines
+    Ppu,
+    /// The S-SMP and the S-DSP, if they don't run in their own thread
+    Smp,
+    /// The chips of the cartridge, which run alongside the console
+    Coprocessors,
+    /// The scheduling of the components, timers and interrupts
+    Other,
+}
+
+impl Component {
+    pub const ALL: [Self; 6] = [
+        Self::Cpu,
+        Self::Dma,
+        Self::Ppu,
+        Self::Smp,
+        Self::Coprocessors,
+        Self::Other,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Cpu => "CPU",
+            Self::Dma => "DMA",
+            Self::Ppu => "PPU",
+            Self::Smp => "SPC700",
+            Self::Coprocessors => "coprocessors",
+            Self::Other => "other",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    clock: Clock,
+    current: Component,
+    /// The time of the last switch
+    since: Duration,
+    times: [Duration; Component::ALL.len()],
+}
+
+impl Profiler {
+    pub(crate) fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            current: Component::Other,
+            since: clock(),
+            times: [Duration::ZERO; Component::ALL.len()],
+        }
+    }
+
+    /// Let `component` run from now on, returns the component, which ran
+    /// until now
+    pub(crate) fn switch(&mut self, component: Component) -> Component {
+        let now = (self.clock)();
+        self.times[self.current as usize] += now.saturating_sub(self.since);
+        self.since = now;
+        core::mem::replace(&mut self.current, component)
+    }
+
+    /// The time spent in `component` until the last switch
+    pub fn time(&self, component: Component) -> Duration {
+        self.times[component as usize]
+    }
+
+    /// The time spent in all components until the last switch
+    pub fn total(&self) -> Duration {
+        self.times.iter().sum()
+    }
+}
@@ -104,8 +104,8 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
                 )
             }
             0x4213 => {
-                // RDIO - Road Programmable IO Line
-                Some(self.controllers.get_pio())
+                // RDIO - Read Programmable IO Line
+                Some(self.controllers.io_lines())
             }
             0x4214..=0x4217 => {
                 // Math result registers
@@ -0,0 +1,73 @@
+//! Run-ahead
+//!
+//! Most games react to input one or more frames late. Run-ahead hides this
+//! lag: after every frame, the state is kept, the following frames are
+//! emulated with the same input, and the state is restored again, so the
+//! frame buffer shows the picture of the last frame emulated ahead. Only the
+//! audio of the frames, which aren't undone, is output, and the frames run
+//! ahead aren't counted in the [`Stats`](crate::stats::Stats).
+//!
+//! In integrity mode, run-ahead is limited to
+//! [`MAX_RUN_AHEAD_FRAMES`](crate::integrity::MAX_RUN_AHEAD_FRAMES) frames.
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    integrity::{IntegrityError, Restricted},
+};
+use save_state::{InSaveState, SaveStateSerializer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunAhead {
+    frames: u32,
+}
+
+impl RunAhead {
+    /// Run `frames` frames ahead. More than
+    /// [`MAX_RUN_AHEAD_FRAMES`](crate::integrity::MAX_RUN_AHEAD_FRAMES)
+    /// frames are refused in integrity mode.
+    pub fn new<B: AudioBackend, FB: FrameBuffer>(
+        frames: u32,
+        device: &Device<B, FB>,
+    ) -> Result<Self, IntegrityError> {
+        device.check_integrity(Restricted::RunAhead(frames))?;
+        Ok(Self { frames })
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Must be called after a frame was completed. The frames ahead are
+    /// emulated by `run_frame`, which emulates the device until the next
+    /// frame begins, and undone afterwards.
+    pub fn run<B: AudioBackend, FB: FrameBuffer>(
+        &self,
+        device: &mut Device<B, FB>,
+        mut run_frame: impl FnMut(&mut Device<B, FB>),
+    ) -> Result<(), IntegrityError> {
+        // the integrity mode may have been enabled after the creation
+        device.check_integrity(Restricted::RunAhead(self.frames))?;
+        if self.frames == 0 {
+            return Ok(());
+        }
+        let mut state = SaveStateSerializer { data: vec![] };
+        device.serialize(&mut state);
+        let stats = device.stats;
+        let samples = (device.frame_end_samples, device.samples_this_frame);
+        let smp_counters = (device.smp.cycles_run, device.smp.samples_run);
+        device.smp.set_output_muted(true);
+        for _ in 0..self.frames {
+            run_frame(device)
+        }
+        device.smp.set_output_muted(false);
+        device.restore_state(&state.data);
+        device.stats = stats;
+        (device.frame_end_samples, device.samples_this_frame) = samples;
+        (device.smp.cycles_run, device.smp.samples_run) = smp_counters;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
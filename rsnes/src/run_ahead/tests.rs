@@ -0,0 +1,89 @@
+use super::*;
+use crate::integrity::MAX_RUN_AHEAD_FRAMES;
+use crate::test_util::{create_device, create_rom, TestDevice, LOROM};
+
+/// A ROM, which increments the backdrop color in every NMI, so every frame
+/// has a different picture
+fn create_test_device() -> Box<TestDevice> {
+    create_device(&create_rom(0x8000, LOROM, 0, 0, |rom| {
+        rom[..17].copy_from_slice(&[
+            0x78, // sei
+            0x18, // clc
+            0xfb, // xce
+            0xe2, 0x20, // sep #$20
+            0xa9, 0x0f, // lda #$0f
+            0x8d, 0x00, 0x21, // sta $2100
+            0xa9, 0x80, // lda #$80
+            0x8d, 0x00, 0x42, // sta $4200
+            0x80, 0xfe, // bra $800f
+        ]);
+        rom[0x100..0x10f].copy_from_slice(&[
+            0x9c, 0x21, 0x21, // stz $2121
+            0xee, 0x00, 0x00, // inc $0000
+            0xad, 0x00, 0x00, // lda $0000
+            0x8d, 0x22, 0x21, // sta $2122
+            0x9c, 0x22, 0x21, // stz $2122
+        ]);
+        rom[0x10f] = 0x40; // rti
+                           // the NMI vector of the native mode and the reset vector
+        rom[0x7fea..0x7fec].copy_from_slice(&0x8100u16.to_le_bytes());
+        rom[0x7ffc..0x7ffe].copy_from_slice(&0x8000u16.to_le_bytes());
+    }))
+}
+
+fn run_frame(device: &mut TestDevice) {
+    device.run_cycle::<2>();
+    while !device.new_frame {
+        device.run_cycle::<2>();
+    }
+}
+
+fn save(device: &TestDevice) -> Vec<u8> {
+    let mut state = SaveStateSerializer { data: vec![] };
+    device.serialize(&mut state);
+    state.data
+}
+
+#[test]
+pub fn test_run_ahead() {
+    let mut device = create_test_device();
+    let mut reference = create_test_device();
+    for _ in 0..4 {
+        run_frame(&mut device);
+        run_frame(&mut reference);
+    }
+    let state = save(&device);
+    let stats = device.stats();
+    let pixels = device.ppu.frame_buffer.0;
+    RunAhead::new(2, &device)
+        .unwrap()
+        .run(&mut device, run_frame)
+        .unwrap();
+    // the picture is the one of two frames later, but the state is unchanged
+    run_frame(&mut reference);
+    run_frame(&mut reference);
+    assert_ne!(device.ppu.frame_buffer.0, pixels);
+    assert_eq!(device.ppu.frame_buffer.0, reference.ppu.frame_buffer.0);
+    assert_eq!(save(&device), state);
+    assert_eq!(device.stats(), stats);
+}
+
+#[test]
+pub fn test_run_ahead_in_integrity_mode() {
+    let mut device = create_test_device();
+    device.enable_integrity_mode();
+    assert_eq!(
+        RunAhead::new(MAX_RUN_AHEAD_FRAMES + 1, &device),
+        Err(IntegrityError(Restricted::RunAhead(
+            MAX_RUN_AHEAD_FRAMES + 1
+        )))
+    );
+    run_frame(&mut device);
+    let state = save(&device);
+    // the state is restored, although loading states is refused
+    RunAhead::new(MAX_RUN_AHEAD_FRAMES, &device)
+        .unwrap()
+        .run(&mut device, run_frame)
+        .unwrap();
+    assert_eq!(save(&device), state);
+}
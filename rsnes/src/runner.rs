@@ -0,0 +1,176 @@
+//! Frame pacing for frontends
+//!
+//! A [`Governor`] decides when the next frame has to be emulated. The time is
+//! passed in by the frontend as the [`Duration`] since an arbitrary, fixed
+//! point in time, so the governor works on platforms without a system clock
+//! and can be driven by emulated time as well.
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    device::Device,
+    integrity::{IntegrityError, Restricted},
+};
+use core::time::Duration;
+
+/// The timer is reset, if the emulation fell behind this far
+pub const TIME_UNTIL_TIMER_RESET: Duration = Duration::from_millis(500);
+
+/// The time the original hardware of the region needs for `cycles` master
+/// cycles
+pub fn cycles_to_duration(cycles: u64, is_pal: bool) -> Duration {
+    crate::clock::master(is_pal).cycles_to_duration(cycles)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sync {
+    /// Emulate frames at the rate of the original hardware
+    Timer,
+    /// Emulate frames while less than `latency` of audio is queued for
+    /// output (see [`Governor::set_audio_queued`]). This avoids crackling
+    /// caused by the clocks of the audio device and the system drifting
+    /// apart. The timer is used instead, if the speed is not 100%.
+    Audio { latency: Duration },
+}
+
+#[derive(Debug, Clone)]
+pub struct Governor {
+    sync: Sync,
+    /// The target speed relative to the original hardware
+    speed: f64,
+    turbo: bool,
+    paused: bool,
+    /// The count of frames to emulate while paused
+    advance: u32,
+    next_frame: Option<Duration>,
+    audio_queued: Duration,
+}
+
+impl Governor {
+    pub fn new(sync: Sync) -> Self {
+        Self {
+            sync,
+            speed: 1.0,
+            turbo: false,
+            paused: false,
+            advance: 0,
+            next_frame: None,
+            audio_queued: Duration::ZERO,
+        }
+    }
+
+    pub fn sync(&self) -> Sync {
+        self.sync
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Set the target speed, e.g. `0.5` for half speed.
+    /// Speeds below 100% are refused in integrity mode.
+    pub fn set_speed<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        speed: f64,
+        device: &Device<B, FB>,
+    ) -> Result<(), IntegrityError> {
+        assert!(speed > 0.0, "the speed must be positive");
+        if speed < 1.0 {
+            device.check_integrity(Restricted::Slowdown)?;
+        }
+        self.speed = speed;
+        Ok(())
+    }
+
+    pub fn turbo(&self) -> bool {
+        self.turbo
+    }
+
+    /// Emulate frames as fast as possible while turbo is enabled
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        if !turbo {
+            self.next_frame = None
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused != paused {
+            self.paused = paused;
+            self.advance = 0;
+            // don't catch up the paused time
+            self.next_frame = None
+        }
+    }
+
+    /// Emulate a single frame while paused.
+    /// This is refused in integrity mode.
+    pub fn advance_frame<B: AudioBackend, FB: FrameBuffer>(
+        &mut self,
+        device: &Device<B, FB>,
+    ) -> Result<(), IntegrityError> {
+        device.check_integrity(Restricted::Slowdown)?;
+        if self.paused {
+            self.advance += 1
+        }
+        Ok(())
+    }
+
+    /// Report the duration of audio, which is queued for output
+    pub fn set_audio_queued(&mut self, queued: Duration) {
+        self.audio_queued = queued
+    }
+
+    /// The time, at which the next frame is due, if it is known
+    pub fn next_frame(&self) -> Option<Duration> {
+        self.next_frame
+    }
+
+    /// The time, until which [`Governor::should_run_frame`] stays `false`,
+    /// if it only depends on the time. A frontend can sleep until then
+    /// instead of polling.
+    pub fn sleep_until(&self) -> Option<Duration> {
+        if self.paused || self.turbo {
+            return None;
+        }
+        match self.sync {
+            Sync::Audio { .. } if self.speed == 1.0 => None,
+            _ => Some(self.next_frame.unwrap_or_default()),
+        }
+    }
+
+    /// Whether a frame should be emulated at time `now`
+    pub fn should_run_frame(&self, now: Duration) -> bool {
+        if self.paused {
+            return self.advance > 0;
+        }
+        if self.turbo {
+            return true;
+        }
+        match self.sync {
+            Sync::Audio { latency } if self.speed == 1.0 => self.audio_queued < latency,
+            _ => self.next_frame.is_none_or(|next| now >= next),
+        }
+    }
+
+    /// Must be called after a frame of `cycles` master cycles was emulated
+    /// by a console of the region `is_pal`. Returns the emulated time.
+    pub fn frame_done(&mut self, cycles: u64, is_pal: bool, now: Duration) -> Duration {
+        let frame_time = cycles_to_duration(cycles, is_pal);
+        if self.paused {
+            self.advance = self.advance.saturating_sub(1);
+        } else if !self.turbo {
+            let next = self.next_frame.unwrap_or(now) + frame_time.div_f64(self.speed);
+            // reset the timer if it fell to far behind
+            self.next_frame = Some(if now > next + TIME_UNTIL_TIMER_RESET {
+                now
+            } else {
+                next
+            });
+        }
+        frame_time
+    }
+}
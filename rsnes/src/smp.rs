@@ -1,7 +1,8 @@
 use crate::{
     backend::AudioBackend as Backend,
-    spc700::Spc700,
-    timing::{Cycles, APU_CPU_TIMING_PROPORTION_NTSC, APU_CPU_TIMING_PROPORTION_PAL},
+    clock::{self, ClockDivider},
+    spc700::{Spc700, VoiceState},
+    timing::Cycles,
 };
 use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
 use save_state_macro::InSaveState;
@@ -21,6 +22,12 @@ enum ThreadCommand {
     },
     SaveState(Box<Spc700>),
     GetSaveState,
+    GetVoices,
+    MuteVoices(u8),
+    MuteVoice(u8, bool),
+    SoloVoice(Option<u8>),
+    MuteOutput(bool),
+    Reset,
     KillMe,
 }
 
@@ -28,10 +35,15 @@ enum ThreadCommand {
 enum MainCommand {
     Data(u8),
     SaveState(Box<Spc700>),
+    Voices([VoiceState; 8]),
 }
 
 type ReturnType = Result<(), RecvError>;
 
+/// Browsers run WebAssembly on a single thread, where spawning one fails,
+/// so the S-SMP always runs on the calling thread there
+const THREADS_AVAILABLE: bool = cfg!(not(target_family = "wasm"));
+
 #[derive(Debug)]
 struct Thread {
     join_handle: Option<std::thread::JoinHandle<ReturnType>>,
@@ -41,13 +53,34 @@ struct Thread {
 
 #[derive(Debug, InSaveState)]
 pub struct Smp<B: Backend> {
+    #[except(Self::serialize_spc, Self::deserialize_spc)]
     pub spc: Option<Spc700>,
     #[except((|_v, _s| ()), (|_v, _s| ()))]
     pub backend: Option<B>,
     #[except(Self::serialize_save_state, Self::deserialize_save_state)]
     thread: Option<Thread>,
-    timing_proportion: (Cycles, Cycles),
-    master_cycles: Cycles,
+    clock: ClockDivider,
+    /// The APU cycles run since the creation
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) cycles_run: u64,
+    /// The stereo samples output since the creation
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub(crate) samples_run: u64,
+    /// The APU cycles until the next sample modulo 32, it's read from the
+    /// S-SMP again after loading a state
+    #[except((|_v, _s| ()), (|v: &mut Option<u8>, _s| *v = None))]
+    sample_phase: Option<u8>,
+    /// If enabled, every write to the input ports (port, value) is recorded
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    pub port_writes: Option<Vec<(u8, u8)>>,
+    /// The voices muted with [`Self::set_voice_muted`]
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    user_muted_voices: u8,
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    solo_voice: Option<u8>,
+    /// Whether the samples are dropped instead of pushed to the backend
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    output_muted: bool,
 }
 
 fn threaded_spc<B: Backend>(
@@ -56,13 +89,18 @@ fn threaded_spc<B: Backend>(
     send: Sender<MainCommand>,
     recv: Receiver<ThreadCommand>,
 ) -> ReturnType {
+    let mut output_muted = false;
     loop {
         match recv.recv()? {
             ThreadCommand::RunCycles { cycles, action } => {
                 // synchronize
                 for _ in 0..cycles {
-                    if let Some(sample) = spc.run_cycle() {
-                        backend.push_sample(sample)
+                    match spc.run_cycle() {
+                        Some(sample) if !output_muted => {
+                            backend.push_sample(sample);
+                            backend.push_voice_outputs(spc.voice_outputs())
+                        }
+                        _ => (),
                     }
                 }
                 // run action
@@ -76,10 +114,21 @@ fn threaded_spc<B: Backend>(
                     None => (),
                 }
             }
-            ThreadCommand::SaveState(new_spc) => spc = *new_spc,
+            ThreadCommand::SaveState(mut new_spc) => {
+                new_spc.copy_voice_muting(&spc);
+                spc = *new_spc
+            }
             ThreadCommand::GetSaveState => {
                 let _ = send.send(MainCommand::SaveState(Box::new(spc.clone())));
             }
+            ThreadCommand::GetVoices => {
+                let _ = send.send(MainCommand::Voices(spc.voices()));
+            }
+            ThreadCommand::MuteVoices(voices) => spc.set_muted_voices(voices),
+            ThreadCommand::MuteVoice(voice, muted) => spc.set_voice_muted(voice, muted),
+            ThreadCommand::SoloVoice(voice) => spc.set_solo_voice(voice),
+            ThreadCommand::MuteOutput(muted) => output_muted = muted,
+            ThreadCommand::Reset => spc.reset(),
             ThreadCommand::KillMe => break Ok(()),
         }
     }
@@ -88,12 +137,8 @@ fn threaded_spc<B: Backend>(
 impl<B: Backend> Smp<B> {
     pub fn new(backend: B, is_pal: bool, is_threaded: bool) -> Self {
         let spc = Spc700::default();
-        let timing_proportion = if is_pal {
-            APU_CPU_TIMING_PROPORTION_PAL
-        } else {
-            APU_CPU_TIMING_PROPORTION_NTSC
-        };
-        if is_threaded {
+        let clock = ClockDivider::new(clock::master(is_pal), clock::APU);
+        if is_threaded && THREADS_AVAILABLE {
             let ((m_send, m_recv), (t_send, t_recv)) = (channel(), channel());
             let handle = std::thread::spawn(move || threaded_spc(spc, backend, m_send, t_recv));
             let thread = Some(Thread {
@@ -105,35 +150,85 @@ impl<B: Backend> Smp<B> {
                 spc: None,
                 backend: None,
                 thread,
-                timing_proportion,
-                master_cycles: 0,
+                clock,
+                cycles_run: 0,
+                samples_run: 0,
+                sample_phase: Some(0),
+                port_writes: None,
+                user_muted_voices: 0,
+                solo_voice: None,
+                output_muted: false,
             }
         } else {
             Self {
                 spc: Some(spc),
                 backend: Some(backend),
                 thread: None,
-                timing_proportion,
-                master_cycles: 0,
+                clock,
+                cycles_run: 0,
+                samples_run: 0,
+                sample_phase: Some(0),
+                port_writes: None,
+                user_muted_voices: 0,
+                solo_voice: None,
+                output_muted: false,
             }
         }
     }
 
     /// Tick in main CPU master cycles
     pub fn tick(&mut self, n: u16) {
-        self.master_cycles += Cycles::from(n) * self.timing_proportion.1;
+        self.clock.tick(n.into())
     }
 
     fn refresh_counters(&mut self) -> Cycles {
-        let cycles = self.master_cycles / self.timing_proportion.0;
-        self.master_cycles %= self.timing_proportion.0;
+        let cycles = self.clock.take();
+        self.cycles_run += u64::from(cycles);
+        // the S-DSP outputs a sample every 32 cycles, whenever the dispatch
+        // counter of the S-SMP is a multiple of 32
+        let phase = self.sample_phase();
+        self.samples_run += u64::from((phase + cycles).div_ceil(32) - phase.div_ceil(32));
+        self.sample_phase = Some(((phase + cycles) % 32) as u8);
         cycles
     }
 
-    fn refresh_no_thread(spc: &mut Spc700, backend: &mut B, cycles: Cycles) {
+    fn sample_phase(&mut self) -> Cycles {
+        let phase = self.sample_phase.map(u16::from).unwrap_or_else(|| {
+            if let Some(spc) = &self.spc {
+                spc.dispatch_counter()
+            } else if let Some(thread) = &self.thread {
+                thread.send.send(ThreadCommand::GetSaveState).unwrap();
+                match thread.recv.recv().unwrap() {
+                    MainCommand::SaveState(spc) => spc.dispatch_counter(),
+                    _ => panic!(),
+                }
+            } else {
+                unreachable!()
+            }
+        });
+        Cycles::from(phase % 32)
+    }
+
+    /// The APU cycles run since the creation
+    pub fn cycles_run(&self) -> u64 {
+        self.cycles_run
+    }
+
+    /// The stereo samples output since the creation. They are counted, when
+    /// the S-SMP catches up with the main CPU, even if a threaded S-SMP
+    /// didn't push them to the backend yet.
+    pub fn samples_run(&self) -> u64 {
+        self.samples_run
+    }
+
+    fn refresh_no_thread(spc: &mut Spc700, backend: &mut B, cycles: Cycles, muted: bool) {
         for _ in 0..cycles {
-            if let Some(sample) = spc.run_cycle() {
-                backend.push_sample(sample)
+            match spc.run_cycle() {
+                Some(sample) if !muted => {
+                    backend.push_sample(sample);
+                    backend.push_voice_outputs(spc.voice_outputs())
+                }
+                _ => (),
             }
         }
     }
@@ -141,7 +236,7 @@ impl<B: Backend> Smp<B> {
     pub fn refresh(&mut self) {
         let cycles = self.refresh_counters();
         if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
-            Self::refresh_no_thread(spc, backend, cycles)
+            Self::refresh_no_thread(spc, backend, cycles, self.output_muted)
         } else if let Some(thread) = &mut self.thread {
             let _ = thread.send.send(ThreadCommand::RunCycles {
                 cycles,
@@ -155,7 +250,7 @@ impl<B: Backend> Smp<B> {
     pub fn read_output_port(&mut self, addr: u8) -> u8 {
         let cycles = self.refresh_counters();
         if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
-            Self::refresh_no_thread(spc, backend, cycles);
+            Self::refresh_no_thread(spc, backend, cycles, self.output_muted);
             spc.output[usize::from(addr & 3)]
         } else if let Some(thread) = &mut self.thread {
             let _ = thread.send.send(ThreadCommand::RunCycles {
@@ -173,9 +268,12 @@ impl<B: Backend> Smp<B> {
     }
 
     pub fn write_input_port(&mut self, addr: u8, data: u8) {
+        if let Some(writes) = &mut self.port_writes {
+            writes.push((addr & 3, data))
+        }
         let cycles = self.refresh_counters();
         if let (Some(spc), Some(backend)) = (&mut self.spc, &mut self.backend) {
-            Self::refresh_no_thread(spc, backend, cycles);
+            Self::refresh_no_thread(spc, backend, cycles, self.output_muted);
             spc.input[usize::from(addr & 3)] = data
         } else if let Some(thread) = &mut self.thread {
             let _ = thread.send.send(ThreadCommand::RunCycles {
@@ -187,10 +285,109 @@ impl<B: Backend> Smp<B> {
         }
     }
 
+    /// Reset the S-SMP, like the reset button of the console does. It
+    /// restarts in the IPL ROM, while its memory is kept.
+    pub fn reset(&mut self) {
+        self.refresh();
+        if let Some(spc) = &mut self.spc {
+            spc.reset()
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::Reset);
+        }
+    }
+
+    /// Leave DSP voices out of the audio output (bit n for voice n).
+    /// This isn't part of save states.
+    pub fn set_muted_voices(&mut self, voices: u8) {
+        if let Some(spc) = &mut self.spc {
+            spc.set_muted_voices(voices)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::MuteVoices(voices));
+        }
+    }
+
+    /// Mute or unmute a single DSP voice (0 to 7), e.g. to listen to the
+    /// other voices. This combines with [`Self::set_muted_voices`] and isn't
+    /// part of save states.
+    pub fn set_voice_muted(&mut self, voice: u8, muted: bool) {
+        let bit = 1 << (voice & 7);
+        if muted {
+            self.user_muted_voices |= bit
+        } else {
+            self.user_muted_voices &= !bit
+        }
+        if let Some(spc) = &mut self.spc {
+            spc.set_voice_muted(voice, muted)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::MuteVoice(voice, muted));
+        }
+    }
+
+    /// The voices muted with [`Self::set_voice_muted`] (bit n for voice n)
+    pub fn user_muted_voices(&self) -> u8 {
+        self.user_muted_voices
+    }
+
+    /// Output only a single DSP voice (0 to 7), regardless of muting, or
+    /// all voices, which aren't muted, with `None`. This isn't part of save
+    /// states.
+    pub fn set_solo_voice(&mut self, voice: Option<u8>) {
+        let voice = voice.map(|voice| voice & 7);
+        self.solo_voice = voice;
+        if let Some(spc) = &mut self.spc {
+            spc.set_solo_voice(voice)
+        } else if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::SoloVoice(voice));
+        }
+    }
+
+    pub fn solo_voice(&self) -> Option<u8> {
+        self.solo_voice
+    }
+
+    /// Drop the samples instead of pushing them to the backend, e.g. while
+    /// running ahead. The S-SMP catches up with the main CPU first, so only
+    /// the samples of later cycles are affected.
+    pub(crate) fn set_output_muted(&mut self, muted: bool) {
+        self.refresh();
+        self.output_muted = muted;
+        if let Some(thread) = &mut self.thread {
+            let _ = thread.send.send(ThreadCommand::MuteOutput(muted));
+        }
+    }
+
+    /// The state of every DSP voice, e.g. to show the notes played
+    pub fn voices(&self) -> [VoiceState; 8] {
+        if let Some(spc) = &self.spc {
+            spc.voices()
+        } else if let Some(thread) = &self.thread {
+            thread.send.send(ThreadCommand::GetVoices).unwrap();
+            match thread.recv.recv().unwrap() {
+                MainCommand::Voices(voices) => voices,
+                _ => panic!(),
+            }
+        } else {
+            unreachable!()
+        }
+    }
+
     pub fn is_threaded(&self) -> bool {
         self.thread.is_some()
     }
 
+    fn serialize_spc(spc: &Option<Spc700>, ser: &mut SaveStateSerializer) {
+        spc.serialize(ser)
+    }
+
+    /// Like deserializing an option, but the muted voices are kept
+    fn deserialize_spc(spc: &mut Option<Spc700>, deser: &mut SaveStateDeserializer) {
+        let old_spc = spc.take();
+        spc.deserialize(deser);
+        if let (Some(spc), Some(old_spc)) = (spc, &old_spc) {
+            spc.copy_voice_muting(old_spc)
+        }
+    }
+
     fn serialize_save_state(thread: &Option<Thread>, ser: &mut SaveStateSerializer) {
         // TODO: do not unwrap
         if let Some(thread) = thread {
@@ -98,7 +98,20 @@ static CYCLES: [Cycles; 256] = [
        2, 8, 4, 5, 4, 5, 5, 6,   3, 4, 5, 4, 2, 2, 4, 2,  // f^
 ];
 
-const F0_RESET: u8 = 0x80;
+/// Bits of the TEST register ($F0)
+mod test {
+    /// Halt all timers
+    pub const TIMERS_DISABLE: u8 = 0x01;
+    pub const RAM_WRITABLE: u8 = 0x02;
+    /// Reads of RAM return [`DISABLED_RAM_VALUE`](super::DISABLED_RAM_VALUE)
+    /// and writes are ignored
+    pub const RAM_DISABLE: u8 = 0x04;
+    pub const TIMERS_ENABLE: u8 = 0x08;
+    // the upper four bits add wait states, which are not emulated
+}
+
+const TEST_RESET: u8 = test::RAM_WRITABLE | test::TIMERS_ENABLE;
+const DISABLED_RAM_VALUE: u8 = 0x5a;
 
 /// Flags
 pub mod flags {
@@ -350,6 +363,19 @@ pub struct Dsp {
     echo_sample: StereoSample,
 
     global_output: StereoSample,
+
+    /// Voices, which are left out of the output (bit n for voice n)
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    muted_voices: u8,
+    /// Voices muted by the user, in addition to `muted_voices`
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    user_muted_voices: u8,
+    /// The only voice, which is output, if any
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    solo_voice: Option<u8>,
+    /// The last output of every voice before its volume is applied
+    #[except((|_v, _s| ()), (|_v, _s| ()))]
+    voice_outputs: [i16; 8],
 }
 
 impl Dsp {
@@ -395,6 +421,11 @@ impl Dsp {
             echo_sample: StereoSample::<i16>::new2(0),
 
             global_output: StereoSample::<i16>::new2(0),
+
+            muted_voices: 0,
+            user_muted_voices: 0,
+            solo_voice: None,
+            voice_outputs: [0; 8],
         }
     }
 
@@ -426,6 +457,14 @@ impl Dsp {
         self.mem[(adr & 0x7f) as usize]
     }
 
+    fn is_voice_audible(&self, voice: u8) -> bool {
+        let muted = self.muted_voices | self.user_muted_voices;
+        match self.solo_voice {
+            Some(solo) => voice == solo,
+            None => (muted >> voice) & 1 == 0,
+        }
+    }
+
     pub fn run_step<const STEP: u8>(&mut self, voice: u8, ram: &[u8; MEMORY_SIZE]) {
         macro_rules! vx {
             ($id:ident) => {
@@ -461,9 +500,11 @@ impl Dsp {
                 let sample =
                     ((i32::from(self.output) * i32::from(vx!(VOLL | $channel) as i8)) >> 7).clamp(-0x8000, 0x7fff) as i16;
                 let amp = |s: &mut i16| *s = s.saturating_add(sample);
-                amp(&mut self.main_sample.$i);
-                if (self.echo_enabled >> voice) & 1 > 0 {
-                    amp(&mut self.echo_sample.$i)
+                if self.is_voice_audible(voice) {
+                    amp(&mut self.main_sample.$i);
+                    if (self.echo_enabled >> voice) & 1 > 0 {
+                        amp(&mut self.echo_sample.$i)
+                    }
                 }
             }};
         }
@@ -625,6 +666,7 @@ impl Dsp {
                     (self.noise << 1) as i16
                 };
                 self.output = ((i32::from(out) * i32::from(voice!().gain)) >> 11) as i16;
+                self.voice_outputs[usize::from(voice)] = self.output;
                 voice!().envx_buf = ((voice!().gain >> 4) & 0xff) as u8;
                 if reg!(FLG) & 0x80 > 0 || self.brr_head & 3 == 1 {
                     voice!().period = AdsrPeriod::Release;
@@ -885,6 +927,22 @@ impl Dsp {
     }
 }
 
+/// The state of a DSP voice, as shown by the registers of the DSP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceState {
+    /// The pitch register, `$1000` plays the sample at 32 kHz
+    pub pitch: u16,
+    /// The source number, the index of the sample in the sample directory
+    pub source: u8,
+    /// The address of the first BRR block of the sample, read from the
+    /// sample directory
+    pub sample_addr: u16,
+    /// The current value of the envelope (ENVX, 0 to 127)
+    pub envelope: u8,
+    /// Whether the voice outputs noise instead of its sample
+    pub noise: bool,
+}
+
 #[derive(Debug, Clone, InSaveState)]
 pub struct Spc700 {
     mem: [u8; MEMORY_SIZE],
@@ -901,7 +959,18 @@ pub struct Spc700 {
     status: u8,
     pc: u16,
 
+    /// The TEST register ($F0)
+    test: u8,
+    ipl_rom_enabled: bool,
+    dsp_addr: u8,
+
     timer_max: [u8; 3],
+    /// The output of the first timer stage, which toggles at twice the
+    /// timer's rate
+    timer_stage1: [bool; 3],
+    /// The stage 1 output gated by the TEST register; its falling edge
+    /// clocks the timer
+    timer_lines: [bool; 3],
     // internal timer ticks ALL in 64kHz
     timers: [u8; 3],
     timer_enable: u8,
@@ -913,14 +982,8 @@ pub struct Spc700 {
 
 impl Default for Spc700 {
     fn default() -> Self {
-        const fn generate_power_up_memory() -> [u8; MEMORY_SIZE] {
-            let mut mem = [0; MEMORY_SIZE];
-            mem[0xf0] = F0_RESET;
-            mem
-        }
-        const POWER_UP_MEMORY: [u8; MEMORY_SIZE] = generate_power_up_memory();
         Self {
-            mem: POWER_UP_MEMORY,
+            mem: [0; MEMORY_SIZE],
             input: [0; 4],
             output: [0; 4],
             dsp: Dsp::new(),
@@ -931,7 +994,13 @@ impl Default for Spc700 {
             pc: 0xffc0,
             status: 2,
 
+            test: TEST_RESET,
+            ipl_rom_enabled: true,
+            dsp_addr: 0,
+
             timer_max: [0; 3],
+            timer_stage1: [false; 3],
+            timer_lines: [false; 3],
             timers: [0; 3],
             timer_enable: 0,
             counters: [Cell::new(0), Cell::new(0), Cell::new(0)],
@@ -943,8 +1012,16 @@ impl Default for Spc700 {
 }
 
 impl Spc700 {
+    /// The count of cycles run modulo 2^16, a sample is output, whenever
+    /// it's a multiple of 32
+    pub(crate) fn dispatch_counter(&self) -> u16 {
+        self.dispatch_counter
+    }
+
     pub fn reset(&mut self) {
-        self.mem[0xf0] = F0_RESET;
+        self.test = TEST_RESET;
+        self.ipl_rom_enabled = true;
+        self.timer_enable = 0;
         self.input = [0; 4];
         self.output = [0; 4];
         self.a = 0;
@@ -952,15 +1029,69 @@ impl Spc700 {
         self.y = 0;
         self.sp = 0;
         // actually self.read16(0xfffe), but this will
-        // always result in 0xffc0, because mem[0xf0] = 0x80
+        // always result in 0xffc0, because the IPL ROM is mapped
         self.pc = 0xffc0;
         self.status = 0;
         self.halt = false;
         // TODO: reset dsp
     }
 
+    /// Leave voices out of the output (bit n for voice n), without changing
+    /// the emulation. This isn't part of save states.
+    pub fn set_muted_voices(&mut self, voices: u8) {
+        self.dsp.muted_voices = voices
+    }
+
+    /// Mute or unmute a single voice (0 to 7) for the user, independently of
+    /// [`Self::set_muted_voices`]. This isn't part of save states.
+    pub fn set_voice_muted(&mut self, voice: u8, muted: bool) {
+        let bit = 1 << (voice & 7);
+        if muted {
+            self.dsp.user_muted_voices |= bit
+        } else {
+            self.dsp.user_muted_voices &= !bit
+        }
+    }
+
+    /// Output only `voice` (0 to 7), regardless of muting, or all voices,
+    /// which aren't muted, with `None`. This isn't part of save states.
+    pub fn set_solo_voice(&mut self, voice: Option<u8>) {
+        self.dsp.solo_voice = voice.map(|voice| voice & 7)
+    }
+
+    /// Copy the muted voices and the solo voice from another SPC700, e.g.
+    /// after loading a save state
+    pub fn copy_voice_muting(&mut self, other: &Self) {
+        self.dsp.muted_voices = other.dsp.muted_voices;
+        self.dsp.user_muted_voices = other.dsp.user_muted_voices;
+        self.dsp.solo_voice = other.dsp.solo_voice;
+    }
+
+    /// The last output of every DSP voice, after its envelope, but before
+    /// its volume and muting are applied
+    pub fn voice_outputs(&self) -> [i16; 8] {
+        self.dsp.voice_outputs
+    }
+
+    /// The state of every DSP voice
+    pub fn voices(&self) -> [VoiceState; 8] {
+        let regs = &self.dsp.mem;
+        let dir = u16::from(regs[usize::from(regs::DIR)]) << 8;
+        core::array::from_fn(|voice| {
+            let reg = |reg: u8| regs[voice << 4 | usize::from(reg)];
+            let source = reg(regs::SRCN);
+            VoiceState {
+                pitch: u16::from_le_bytes([reg(regs::PITCHL), reg(regs::PITCHH) & 0x3f]),
+                source,
+                sample_addr: load16(&self.mem, dir.wrapping_add(u16::from(source) << 2)),
+                envelope: reg(regs::ENVX),
+                noise: (regs[usize::from(regs::NON)] >> voice) & 1 > 0,
+            }
+        })
+    }
+
     pub fn is_rom_mapped(&self) -> bool {
-        self.mem[0xf0] & 0x80 > 0
+        self.ipl_rom_enabled
     }
 
     pub fn read16(&self, addr: u16) -> u16 {
@@ -969,18 +1100,28 @@ impl Spc700 {
 
     pub fn read(&self, addr: u16) -> u8 {
         match addr {
-            0xf3 => self.dsp.read(self.mem[0xf2]),
+            0xf2 => self.dsp_addr,
+            // DSP registers are mirrored to $80-$ff
+            0xf3 => self.dsp.read(self.dsp_addr),
             0xf4..=0xf7 => self.input[usize::from(addr - 0xf4)],
             0xfd..=0xff => self.counters[usize::from(addr - 0xfd)].take(),
             0xf0..=0xf1 | 0xfa..=0xfc => 0,
             0xffc0..=0xffff if self.is_rom_mapped() => ROM[(addr & 0x3f) as usize],
+            _ if self.test & test::RAM_DISABLE > 0 => DISABLED_RAM_VALUE,
             addr => self.mem[addr as usize],
         }
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0xf0 => todo!("undocumented SPC register TEST(f0) written"),
+            // TEST can only be written with the P flag cleared
+            0xf0 if self.status & flags::ZERO_PAGE == 0 => {
+                self.test = val;
+                // changing the gate of the timers may clock them
+                for i in 0..3 {
+                    self.sync_timer_stage1(i)
+                }
+            }
             0xf1 => {
                 if val & 0x10 > 0 {
                     self.input[0..2].fill(0)
@@ -996,11 +1137,19 @@ impl Spc700 {
                         self.timers[i] = 0;
                     }
                 }
+                self.ipl_rom_enabled = val & 0x80 > 0;
             }
-            0xf3 => self.dsp.write(self.mem[0xf2], val),
+            0xf2 => self.dsp_addr = val,
+            // writes to the mirrors of the DSP registers are ignored
+            0xf3 => self.dsp.write(self.dsp_addr, val),
             0xf4..=0xf7 => self.output[(addr - 0xf4) as usize] = val,
             0xfa..=0xfc => self.timer_max[usize::from(addr & 3) ^ 2] = val,
-            addr => self.mem[addr as usize] = val,
+            _ => (),
+        }
+        // all writes, including writes to registers and the IPL ROM,
+        // reach the RAM
+        if self.test & (test::RAM_WRITABLE | test::RAM_DISABLE) == test::RAM_WRITABLE {
+            self.mem[addr as usize] = val
         }
     }
 
@@ -2450,8 +2599,20 @@ impl Spc700 {
         res
     }
 
-    pub fn update_timer(&mut self, i: usize) {
-        if self.timer_enable & (1 << i) > 0 {
+    /// Toggle the first stage of timer `i`
+    fn toggle_timer_stage1(&mut self, i: usize) {
+        self.timer_stage1[i] = !self.timer_stage1[i];
+        self.sync_timer_stage1(i)
+    }
+
+    /// Update the gated stage 1 output of timer `i` and clock the timer on
+    /// its falling edge. Timers stand still, unless TEST enables them.
+    fn sync_timer_stage1(&mut self, i: usize) {
+        let line = self.timer_stage1[i]
+            && self.test & (test::TIMERS_ENABLE | test::TIMERS_DISABLE) == test::TIMERS_ENABLE;
+        let falling_edge = self.timer_lines[i] && !line;
+        self.timer_lines[i] = line;
+        if falling_edge && self.timer_enable & (1 << i) > 0 {
             self.timers[i] = self.timers[i].wrapping_add(1);
             if self.timers[i] == self.timer_max[i] {
                 self.timers[i] = 0;
@@ -2467,15 +2628,16 @@ impl Spc700 {
         self.cycles_ahead = self.cycles_ahead.saturating_sub(1);
         self.dsp.run_one_step(&mut self.mem);
         let mut output = None;
-        if self.dispatch_counter & 0xf == 0 {
+        // the first stages toggle at twice the rate of the timers
+        if self.dispatch_counter & 7 == 0 {
             if self.dispatch_counter & 0x1f == 0 {
                 output = Some(self.dsp.global_output);
-                if self.dispatch_counter & 0x7f == 0 {
-                    self.update_timer(0);
-                    self.update_timer(1);
+                if self.dispatch_counter & 0x3f == 0 {
+                    self.toggle_timer_stage1(0);
+                    self.toggle_timer_stage1(1);
                 }
             }
-            self.update_timer(2);
+            self.toggle_timer_stage1(2);
         }
         self.dispatch_counter = self.dispatch_counter.wrapping_add(1);
         output
@@ -0,0 +1,26 @@
+//! Counters of the emulation
+//!
+//! The [`Device`](crate::device::Device) counts a few events while it runs,
+//! so frontends don't have to keep their own counters for them. The counters
+//! start at zero, when the device is created, and can be reset with
+//! [`Device::reset_stats`](crate::device::Device::reset_stats), e.g. once per
+//! second for a rate. They aren't part of save states.
+
+/// The events counted since the device was created or the counters were
+/// reset (see [`Device::stats`](crate::device::Device::stats))
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The emulated master cycles
+    pub master_cycles: u64,
+    /// The completed frames
+    pub frames: u64,
+    /// The NMIs taken by the main CPU
+    pub nmis: u64,
+    /// The IRQs taken by the main CPU
+    pub irqs: u64,
+    /// The bytes transferred by DMA and HDMA
+    pub dma_bytes: u64,
+    /// The stereo samples output by the S-DSP. The S-SMP is emulated lazily,
+    /// so this may lag behind by a frame.
+    pub apu_samples: u64,
+}
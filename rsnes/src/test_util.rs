@@ -0,0 +1,85 @@
+//! Helpers for the tests of rsnes and of the crates embedding it
+//!
+//! The module is only compiled for the tests of rsnes and with the feature
+//! `test-util`, which the other crates enable for their tests.
+
+use crate::backend::{ArrayFrameBuffer, AudioDummy, FRAME_BUFFER_SIZE};
+use crate::cartridge::Cartridge;
+use crate::device::Device;
+
+pub type TestDevice = Device<AudioDummy, ArrayFrameBuffer>;
+
+/// The map mode of a LoROM cartridge in the header
+pub const LOROM: u8 = 0x20;
+/// The map mode of a HiROM cartridge in the header
+pub const HIROM: u8 = 0x21;
+/// The map mode of an SA-1 cartridge in the header
+pub const SA1: u8 = 0x23;
+
+/// A ROM file of `size` bytes titled `TEST`, with the map mode `map_mode`,
+/// the chipset byte `chips` and the SRAM size byte `ram` in its header.
+/// `edit` changes the ROM, before its checksum is written into the header.
+pub fn create_rom(
+    size: usize,
+    map_mode: u8,
+    chips: u8,
+    ram: u8,
+    edit: impl FnOnce(&mut [u8]),
+) -> Vec<u8> {
+    let mut rom = vec![0; size];
+    let header = if map_mode & 0x0f == 0x01 {
+        0xffc0
+    } else {
+        0x7fc0
+    };
+    rom[header..header + 4].copy_from_slice(b"TEST");
+    rom[header + 0x15] = map_mode;
+    rom[header + 0x16] = chips;
+    rom[header + 0x17] = (size.next_power_of_two() >> 10).trailing_zeros() as u8;
+    rom[header + 0x18] = ram;
+    edit(&mut rom);
+    // the checksum and its complement always add 0x1fe to the checksum
+    let checksum = rom
+        .iter()
+        .fold(0x1feu16, |sum, &b| sum.wrapping_add(b.into()));
+    rom[header + 0x1c..header + 0x1e].copy_from_slice(&(!checksum).to_le_bytes());
+    rom[header + 0x1e..header + 0x20].copy_from_slice(&checksum.to_le_bytes());
+    rom
+}
+
+/// A LoROM cartridge, which loops at its reset vector
+pub fn create_looping_rom() -> Vec<u8> {
+    create_rom(0x8000, LOROM, 0, 0, |rom| {
+        // BRA -2 at $8000
+        rom[..2].copy_from_slice(&[0x80, 0xfe]);
+        rom[0x7ffc..0x7ffe].copy_from_slice(&0x8000u16.to_le_bytes());
+    })
+}
+
+/// Run `f` in a thread with a larger stack. Devices and cartridges are too
+/// large for the stack of the test threads.
+pub fn with_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// Load `rom` into a cartridge
+pub fn create_cartridge(rom: &[u8]) -> Box<Cartridge> {
+    let rom = rom.to_vec();
+    with_stack(move || Box::new(Cartridge::from_bytes(&rom).unwrap()))
+}
+
+/// A NTSC device with `rom` loaded
+pub fn create_device(rom: &[u8]) -> Box<TestDevice> {
+    let rom = rom.to_vec();
+    with_stack(move || {
+        let frame_buffer = ArrayFrameBuffer([[0; 4]; FRAME_BUFFER_SIZE], false);
+        let mut device = Box::new(Device::new(AudioDummy, frame_buffer, false, false));
+        device.load_cartridge(Cartridge::from_bytes(&rom).unwrap());
+        device
+    })
+}
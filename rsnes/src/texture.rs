@@ -0,0 +1,171 @@
+//! Texture replacement
+//!
+//! While a [`TexturePack`] is active, every drawn background tile (8×8 or
+//! 16×16 pixels, depending on the BG's tile size) and every 8×8 sprite tile
+//! is hashed together with its palette. If a replacement texture has been
+//! inserted for the hash, the tile is drawn with it into the
+//! [`HdFrame`](crate::ppu::HdFrame).
+//!
+//! Loading the replacements is left to the frontend: the hashes of tiles,
+//! which are drawn for the first time, can be taken with
+//! [`TexturePack::take_requests`]. If [`TexturePack::dump`] is enabled, the
+//! original pixels of these tiles are recorded as well.
+//!
+//! Replacements are only drawn where the original tile is opaque, pixels
+//! affected by color math keep their original colors.
+
+use std::collections::{HashMap, HashSet};
+
+/// An RGBA texture
+#[derive(Debug, Clone)]
+pub struct Texture {
+    width: u32,
+    height: u32,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl Texture {
+    pub fn new(width: u32, height: u32, pixels: Vec<[u8; 4]>) -> Self {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[u8; 4]] {
+        &self.pixels
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.pixels.len() * 4
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TexturePack {
+    textures: HashMap<u64, Texture>,
+    /// The hashes of all tiles drawn since the last [`TexturePack::clear`]
+    known: HashSet<u64>,
+    requests: Vec<u64>,
+    /// Record the original pixels of newly drawn tiles
+    pub dump: bool,
+    dumped: Vec<(u64, Texture)>,
+    memory_usage: usize,
+}
+
+impl TexturePack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the tile with the hash `hash`. The texture's size must be
+    /// a multiple of the tile size.
+    pub fn insert(&mut self, hash: u64, texture: Texture) {
+        self.memory_usage += texture.memory_usage();
+        if let Some(old) = self.textures.insert(hash, texture) {
+            self.memory_usage -= old.memory_usage();
+        }
+    }
+
+    /// Take the hashes of the tiles, which were drawn for the first time
+    pub fn take_requests(&mut self) -> Vec<u64> {
+        core::mem::take(&mut self.requests)
+    }
+
+    /// Take the original pixels of the tiles, which were drawn for the first
+    /// time while [`TexturePack::dump`] was enabled
+    pub fn take_dumped(&mut self) -> Vec<(u64, Texture)> {
+        core::mem::take(&mut self.dumped)
+    }
+
+    /// Remove all replacements. All tiles will be requested again.
+    pub fn clear(&mut self) {
+        self.textures.clear();
+        self.known.clear();
+        self.memory_usage = 0;
+    }
+
+    /// The size of all replacements in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    pub(crate) fn get(&self, hash: u64) -> Option<&Texture> {
+        self.textures.get(&hash)
+    }
+
+    /// Register a drawn tile.
+    /// Returns true, if the tile should be dumped.
+    pub(crate) fn visit(&mut self, hash: u64) -> bool {
+        if self.known.insert(hash) {
+            self.requests.push(hash);
+            self.dump
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn add_dumped(&mut self, hash: u64, texture: Texture) {
+        self.dumped.push((hash, texture))
+    }
+}
+
+/// A pixel of a hashed tile
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Texel {
+    pub hash: u64,
+    /// The position in the unflipped tile
+    pub pos: [u8; 2],
+    pub size: [u8; 2],
+    pub flip: [bool; 2],
+}
+
+impl Texel {
+    /// Sample the replacement at subpixel `sub` of `scale` subpixels
+    pub fn sample(&self, texture: &Texture, sub: [usize; 2], scale: usize) -> Option<[u8; 4]> {
+        let factor = [
+            texture.width as usize / usize::from(self.size[0]),
+            texture.height as usize / usize::from(self.size[1]),
+        ];
+        if factor.contains(&0) {
+            return None;
+        }
+        let [x, y] = [0, 1].map(|i| {
+            let s = sub[i] * factor[i] / scale;
+            let s = if self.flip[i] { factor[i] - 1 - s } else { s };
+            usize::from(self.pos[i]) * factor[i] + s
+        });
+        let pixel = texture.pixels[y * texture.width as usize + x];
+        // transparent pixels show the original tile
+        (pixel[3] >= 0x80).then_some(pixel)
+    }
+}
+
+/// 64-bit FNV-1a
+pub(crate) struct TileHasher(u64);
+
+impl TileHasher {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        for byte in val.to_le_bytes() {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -5,57 +5,87 @@
 //! - <https://wiki.superfamicom.org/timing>
 
 use crate::{
+    bus_log::Origin,
     cpu::Status,
     device::{Addr24, Device},
+    profile::Component,
 };
 
+/// Master cycles (see [`crate::clock`] for the other clocks)
 pub type Cycles = u32;
 
-// The SNES master clock runs at ca. (945/44) MHz which is ca. 21_477kHz;
-// The APU runs at 1024kHz
-
-/// This is a fractional proportion between the cpu and apu clock speed
-pub(crate) const APU_CPU_TIMING_PROPORTION_NTSC: (Cycles, Cycles) = (118125, 5632);
-pub(crate) const APU_CPU_TIMING_PROPORTION_PAL: (Cycles, Cycles) = (665, 32);
-
-pub(crate) const NECDSP_CPU_TIMING_PROPORTION_NTSC: (Cycles, Cycles) = (118125, 45056);
-pub(crate) const NECDSP_CPU_TIMING_PROPORTION_PAL: (Cycles, Cycles) = (40591, 15625);
+/// The progress of the main CPU through its current instruction.
+///
+/// With the `cycle-accurate` feature, the other components are advanced up to
+/// the cycle of every memory access of the main CPU, before the access is
+/// done. Otherwise the whole instruction is executed at its first cycle.
+/// Internal operations are still accounted at the end of the instruction.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct CpuStepping {
+    pub active: bool,
+    /// The master cycles the other components were already advanced by
+    pub stepped: Cycles,
+    /// The master cycles of the last memory access, which the other
+    /// components still have to be advanced by
+    pub pending: Cycles,
+    /// The master cycles the other components are ahead of the main CPU,
+    /// after it was stepped
+    pub ahead: Cycles,
+    /// Whether a frame was completed while the other components were advanced
+    pub new_frame: bool,
+}
 
 impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B, FB> {
     pub fn run_cycle<const N: u16>(&mut self) {
+        if self.new_frame && self.deterministic {
+            // the input latch model of the deterministic mode
+            self.controllers.sample()
+        }
+        if self.cpu_stepping.ahead >= Cycles::from(N) {
+            // the other components already ran while the CPU was stepped
+            self.cpu_stepping.ahead -= Cycles::from(N);
+            self.new_frame = false;
+            self.run_cpu_or_dma::<N>();
+        } else {
+            self.cpu_stepping.ahead = 0;
+            self.run_components::<N, true>();
+        }
+        self.new_frame |= core::mem::take(&mut self.cpu_stepping.new_frame);
+    }
+
+    /// Run all components for `N` master cycles. The main CPU and DMA are
+    /// left out, if `RUN_CPU` is false.
+    fn run_components<const N: u16, const RUN_CPU: bool>(&mut self) {
+        // also counts the cycles the components are stepped ahead of the CPU
+        self.stats.master_cycles += u64::from(N);
         self.smp.tick(N);
-        self.cartridge.as_mut().unwrap().tick(N.into());
+        self.profiled(Component::Coprocessors, |device| {
+            device.cartridge.as_mut().unwrap().tick(N.into())
+        });
         let vend = self.ppu.vend();
         if self.is_auto_joypad() && self.new_scanline && self.ppu.get_pos().y == vend + 2 {
             self.controllers.auto_joypad_timer = 4224;
             self.controllers.auto_joypad()
         }
         self.controllers.auto_joypad_timer -= self.controllers.auto_joypad_timer.min(N);
-        // > The CPU is paused for 40 cycles beginning about 536 cycles
-        // > after the start of each scanline
-        // source: <https://wiki.superfamicom.org/timing>
-        if self.ppu.is_cpu_active() && self.cpu.active {
-            if self.dma.hdma_ahead_cycles > 0 {
-                self.dma.hdma_ahead_cycles -= i32::from(N);
-            } else if self.dma.is_dma_running() {
-                if self.dma.ahead_cycles > 0 {
-                    self.dma.ahead_cycles -= i32::from(N)
-                } else {
-                    self.do_dma_first_channel()
-                }
-            } else {
-                self.run_cpu::<N>();
-            }
+        if RUN_CPU {
+            self.run_cpu_or_dma::<N>();
         }
         if self.cartridge.as_ref().unwrap().has_sa1() {
-            self.with_sa1_cpu().run_cpu::<N>();
+            self.profiled(Component::Coprocessors, |device| {
+                device.with_sa1_cpu().run_cpu::<N>()
+            });
         }
         if self.new_frame {
-            self.dma.hdma_ahead_cycles = self.reset_hdma();
+            self.bus_origin = Origin::Dma;
+            self.dma.hdma_ahead_cycles = self.profiled(Component::Dma, Self::reset_hdma);
+            self.bus_origin = Origin::Cpu;
         }
         if self.do_hdma && !self.ppu.is_in_vblank() && self.ppu.get_pos().x >= 1024 {
             self.do_hdma = false;
-            self.dma.hdma_ahead_cycles = self.do_hdma();
+            self.bus_origin = Origin::Dma;
+            self.dma.hdma_ahead_cycles = self.profiled(Component::Dma, Self::do_hdma);
+            self.bus_origin = Origin::Cpu;
         }
         let vblanked = self.new_scanline && self.ppu.get_pos().y == vend;
         if vblanked {
@@ -66,7 +96,7 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             && !self.scanline_drawn
         {
             self.scanline_drawn = true;
-            self.ppu.draw_scanline();
+            self.profiled(Component::Ppu, |device| device.ppu.draw_scanline());
         }
         let h_irq_enabled = self.cpu.nmitimen & 0x10 > 0;
         let v_irq_enabled = self.cpu.nmitimen & 0x20 > 0;
@@ -83,6 +113,27 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
         self.update_counters::<N>();
     }
 
+    fn run_cpu_or_dma<const N: u16>(&mut self) {
+        // > The CPU is paused for 40 cycles beginning about 536 cycles
+        // > after the start of each scanline
+        // source: <https://wiki.superfamicom.org/timing>
+        if self.ppu.is_cpu_active() && self.cpu.active {
+            if self.dma.hdma_ahead_cycles > 0 {
+                self.dma.hdma_ahead_cycles -= i32::from(N);
+            } else if self.dma.is_dma_running() {
+                if self.dma.ahead_cycles > 0 {
+                    self.dma.ahead_cycles -= i32::from(N)
+                } else {
+                    self.bus_origin = Origin::Dma;
+                    self.profiled(Component::Dma, Self::do_dma_first_channel);
+                    self.bus_origin = Origin::Cpu;
+                }
+            } else {
+                self.run_cpu::<N>();
+            }
+        }
+    }
+
     pub fn update_counters<const N: u16>(&mut self) {
         self.ppu.mut_pos().x += N;
         self.math_registers.tick(N);
@@ -103,13 +154,22 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
             if self.ppu.get_pos().y >= scanline_count {
                 self.ppu.mut_pos().y -= scanline_count;
                 self.new_frame = true;
+                self.stats.frames += 1;
+                if self.cheats.intercepts_writes() {
+                    self.apply_ram_cheats()
+                }
                 self.nmi_vblank_bit.set(false);
                 self.ppu.end_vblank();
-                self.smp.refresh();
-                self.cartridge.as_mut().unwrap().refresh_coprocessors();
+                self.profiled(Component::Smp, |device| device.smp.refresh());
+                let samples = self.smp.samples_run();
+                self.samples_this_frame = (samples - self.frame_end_samples) as u32;
+                self.frame_end_samples = samples;
+                self.profiled(Component::Coprocessors, |device| {
+                    device.cartridge.as_mut().unwrap().refresh_coprocessors()
+                });
             } else if self.smp.is_threaded() {
                 // if the S-SMP is threaded, refresh it every scanline
-                self.smp.refresh();
+                self.profiled(Component::Smp, |device| device.smp.refresh());
             }
         }
     }
@@ -126,24 +186,85 @@ impl<B: crate::backend::AudioBackend, FB: crate::backend::FrameBuffer> Device<B,
                 self.cpu_ahead_cycles += 1;
                 return;
             }
+            if self.debugger.is_some() && self.is_at_breakpoint() {
+                // stay in front of the instruction, until resumed
+                self.cpu_ahead_cycles += i32::from(N);
+                return;
+            }
+            if self.cpu_trace.is_some() && !self.takes_interrupt() {
+                self.trace_instruction()
+            }
             self.memory_cycles = 0;
-            let cycles = (if self.shall_nmi {
-                self.shall_nmi = false;
-                self.with_main_cpu().nmi()
-            } else if (self.shall_irq || self.get_irq_pin())
-                && !self.cpu.regs.status.has(Status::IRQ_DISABLE)
-            {
-                self.shall_irq = false;
-                self.with_main_cpu().irq()
-            } else {
-                // > Internal operation CPU cycles always take 6 master cycles
-                // source: <https://wiki.superfamicom.org/memory-mapping>
-                self.with_main_cpu().dispatch_instruction() * 6
+            self.cpu_stepping.active = cfg!(feature = "cycle-accurate");
+            self.cpu_stepping.stepped = 0;
+            self.cpu_stepping.pending = 0;
+            let cycles = self.profiled(Component::Cpu, |device| {
+                if device.shall_nmi {
+                    device.shall_nmi = false;
+                    device.stats.nmis += 1;
+                    device.with_main_cpu().nmi()
+                } else if (device.shall_irq || device.get_irq_pin())
+                    && !device.cpu.regs.status.has(Status::IRQ_DISABLE)
+                {
+                    device.shall_irq = false;
+                    device.stats.irqs += 1;
+                    device.with_main_cpu().irq()
+                } else {
+                    // > Internal operation CPU cycles always take 6 master cycles
+                    // source: <https://wiki.superfamicom.org/memory-mapping>
+                    device.with_main_cpu().dispatch_instruction() * 6
+                }
             }) + self.memory_cycles;
+            self.cpu_stepping.active = false;
+            let stepping = &mut self.cpu_stepping;
+            stepping.ahead += stepping.stepped;
+            // the instruction lasts at least until its last memory access ends
+            let cycles = cycles.max(stepping.stepped + stepping.pending);
             self.cpu_ahead_cycles += cycles as i32;
         }
     }
 
+    /// Check, if an interrupt is taken instead of the next instruction
+    fn takes_interrupt(&self) -> bool {
+        self.shall_nmi
+            || ((self.shall_irq || self.get_irq_pin())
+                && !self.cpu.regs.status.has(Status::IRQ_DISABLE))
+    }
+
+    /// Check the breakpoints of the debugger, unless an interrupt is taken
+    /// instead of the next instruction
+    fn is_at_breakpoint(&mut self) -> bool {
+        let interrupt = self.takes_interrupt();
+        let pc = self.cpu.regs.pc;
+        match &mut self.debugger {
+            Some(debugger) if !interrupt => debugger.check_instruction(pc),
+            _ => false,
+        }
+    }
+
+    /// Must be called before every memory access of the main CPU. If the
+    /// current instruction is stepped, the other components are advanced to
+    /// the cycle of the access.
+    pub(crate) fn step_cpu_access(&mut self, addr: Addr24) {
+        if !self.cpu_stepping.active {
+            return;
+        }
+        let mut cycles = core::mem::take(&mut self.cpu_stepping.pending);
+        self.cpu_stepping.stepped += cycles;
+        // every memory access takes 6, 8 or 12 master cycles
+        while cycles >= 2 {
+            if cycles >= 6 {
+                self.run_components::<6, false>();
+                cycles -= 6
+            } else {
+                self.run_components::<2, false>();
+                cycles -= 2
+            }
+            self.cpu_stepping.new_frame |= self.new_frame;
+        }
+        self.cpu_stepping.pending = self.get_memory_cycle(addr);
+    }
+
     pub fn get_memory_cycle(&self, addr: Addr24) -> Cycles {
         #[repr(u8)]
         enum Speed {
@@ -0,0 +1,239 @@
+//! Tracing of the instructions executed by the main CPU
+//!
+//! While a [`CpuTrace`] is attached to [`Device::cpu_trace`](crate::device::Device::cpu_trace),
+//! the state of the main CPU is recorded in front of every instruction it
+//! executes. Like the [bus log](crate::bus_log), the entries are kept in a ring
+//! buffer, until they are taken with [`CpuTrace::take`]. Interrupts aren't
+//! traced themselves, only the instructions of their handlers.
+//!
+//! An entry can be written in the style of the trace logs of bsnes and Mesen
+//! (see [`Format`]), so the logs can be compared line by line to find the
+//! first instruction, at which the emulators diverge.
+
+use crate::{
+    backend::{AudioBackend, FrameBuffer},
+    cpu::{Regs, Status},
+    device::{Addr24, Device},
+    disasm::{self, Instruction, MAX_INSTRUCTION_SIZE},
+    ppu::RayPos,
+};
+use std::collections::VecDeque;
+
+/// How an [`Entry`] is written as a line of text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Everything recorded, including the instruction bytes and the master
+    /// cycle
+    #[default]
+    Rsnes,
+    /// The columns of the CPU trace of bsnes
+    Bsnes,
+    /// The columns of the default CPU trace of Mesen, with upper case
+    /// hexadecimal digits
+    Mesen,
+}
+
+impl Format {
+    pub const ALL: [Self; 3] = [Self::Rsnes, Self::Bsnes, Self::Mesen];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Rsnes => "rsnes",
+            Self::Bsnes => "bsnes",
+            Self::Mesen => "mesen",
+        }
+    }
+
+    /// Find a format by its case-insensitive name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The state of the main CPU in front of an instruction
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// The registers, where `regs.pc` is the address of the instruction
+    pub regs: Regs,
+    /// The bytes at the program counter. Only the first
+    /// [`Instruction::size`] bytes belong to the instruction.
+    pub bytes: [u8; MAX_INSTRUCTION_SIZE],
+    /// The master cycles counted by [`Device::stats`]
+    pub master_cycles: u64,
+    /// The position of the PPU, where `x` is counted in master cycles
+    pub pos: RayPos,
+}
+
+impl Entry {
+    pub fn instruction(&self) -> Instruction {
+        let m8 = self.regs.status.has(Status::ACCUMULATION) || self.regs.is_emulation;
+        let x8 = self.regs.status.has(Status::INDEX_REGISTER_SIZE) || self.regs.is_emulation;
+        // there are always enough bytes for the longest instruction
+        disasm::decode(&self.bytes, self.regs.pc, m8, x8).unwrap()
+    }
+
+    /// The status flags as letters, which are upper case if set
+    pub fn flags(&self) -> String {
+        "nvmxdizc"
+            .chars()
+            .enumerate()
+            .map(|(i, letter)| match self.regs.status.0 & (0x80 >> i) {
+                0 => letter,
+                _ => letter.to_ascii_uppercase(),
+            })
+            .collect()
+    }
+
+    /// Write the entry as a line of text without line break
+    pub fn to_line(&self, format: Format) -> String {
+        let instr = self.instruction();
+        let Regs {
+            a,
+            x,
+            y,
+            sp,
+            dp,
+            db,
+            ..
+        } = self.regs;
+        let (pc, flags, pos) = (self.regs.pc, self.flags(), self.pos);
+        match format {
+            Format::Rsnes => {
+                let bytes: Vec<String> = self.bytes[..instr.size]
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect();
+                format!(
+                    "{pc}  {:<11}  {:<16} A:{a:04x} X:{x:04x} Y:{y:04x} S:{sp:04x} D:{dp:04x} \
+                     DB:{db:02x} P:{flags} E:{} V:{:3} H:{:4} C:{}",
+                    bytes.join(" "),
+                    instr.to_string(),
+                    u8::from(self.regs.is_emulation),
+                    pos.y,
+                    pos.x,
+                    self.master_cycles,
+                )
+            }
+            Format::Bsnes => format!(
+                "{:02x}{:04x} {:<22} A:{a:04x} X:{x:04x} Y:{y:04x} S:{sp:04x} D:{dp:04x} B:{db:02x} \
+                 {flags} V:{:3} H:{:4}",
+                pc.bank,
+                pc.addr,
+                instr.to_string(),
+                pos.y,
+                pos.x,
+            ),
+            Format::Mesen => format!(
+                "{:02X}{:04X}  {:<22} A:{a:04X} X:{x:04X} Y:{y:04X} S:{sp:04X} D:{dp:04X} DB:{db:02X} \
+                 P:{flags} V:{} H:{} Cycle:{}",
+                pc.bank,
+                pc.addr,
+                instr.to_string().to_ascii_uppercase(),
+                pos.y,
+                pos.x,
+                self.master_cycles,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CpuTrace {
+    /// The format of the lines returned by [`CpuTrace::take_lines`]
+    pub format: Format,
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    /// The count of traced instructions, which were dropped from the buffer
+    dropped: u64,
+}
+
+impl CpuTrace {
+    /// Create a trace, which keeps the last `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            format: Format::default(),
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The count of instructions, which didn't fit into the buffer since the
+    /// last call to [`CpuTrace::take`]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn record(&mut self, entry: Entry) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+        self.entries.push_back(entry)
+    }
+
+    /// The traced instructions, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    /// Remove and return all traced instructions, oldest first
+    pub fn take(&mut self) -> Vec<Entry> {
+        self.dropped = 0;
+        self.entries.drain(..).collect()
+    }
+
+    /// Remove all traced instructions and return them as lines in
+    /// [`CpuTrace::format`]
+    pub fn take_lines(&mut self) -> Vec<String> {
+        let format = self.format;
+        self.take()
+            .iter()
+            .map(|entry| entry.to_line(format))
+            .collect()
+    }
+}
+
+impl<B: AudioBackend, FB: FrameBuffer> Device<B, FB> {
+    /// Record the instruction at the program counter of the main CPU
+    pub(crate) fn trace_instruction(&mut self) {
+        let pc = self.cpu.regs.pc;
+        let mut bytes = [0; MAX_INSTRUCTION_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            // the bytes of I/O registers cannot be read without side effects
+            *byte = self
+                .peek(Addr24::new(pc.bank, pc.addr.wrapping_add(i as u16)))
+                .unwrap_or(self.open_bus);
+        }
+        let entry = Entry {
+            regs: self.cpu.regs.clone(),
+            bytes,
+            master_cycles: self.stats.master_cycles,
+            pos: *self.ppu.get_pos(),
+        };
+        if let Some(trace) = &mut self.cpu_trace {
+            trace.record(entry)
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! WebAssembly bindings of rsnes for the browser frontend in `www/`
+//!
+//! An [`Emulator`] is created from the bytes of a ROM. The frontend calls
+//! [`Emulator::run`] on every animation frame, which emulates the frames due
+//! by then, and afterwards reads the picture with [`Emulator::frame`] and the
+//! audio with [`Emulator::take_audio`]. The time is passed in by the
+//! frontend (`performance.now()`), as WebAssembly has no system clock.
+//!
+//! Browsers don't run WebAssembly on several threads, so the S-SMP always
+//! runs on the thread of the emulator.
+
+use rsnes::{
+    backend::{ArrayFrameBuffer, AudioBackend, FRAME_BUFFER_SIZE},
+    cartridge::{Cartridge, CountryFrameRate},
+    controller::{Controller, StandardController},
+    device::Device,
+    ppu::{MAX_SCREEN_HEIGHT, MAX_SCREEN_HEIGHT_OVERSCAN, SCREEN_WIDTH},
+    runner::{Governor, Sync},
+    spc700::StereoSample,
+};
+use save_state::{InSaveState, SaveStateDeserializer, SaveStateSerializer};
+use std::time::Duration;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// The samples, which weren't taken, are dropped beyond one second
+const MAX_QUEUED_SAMPLES: usize = 32000;
+
+/// The master cycles emulated at once, like the emulator does
+const MASTER_CYCLES_PER_TICK: u16 = 2;
+
+/// The frames emulated by a single call of [`Emulator::run`], if the
+/// emulation fell behind
+const MAX_FRAMES_PER_RUN: u32 = 4;
+
+/// Keeps the samples of the S-DSP, until they are taken
+#[derive(Default)]
+struct SampleQueue(Vec<StereoSample>);
+
+impl AudioBackend for SampleQueue {
+    fn push_sample(&mut self, sample: StereoSample) {
+        if self.0.len() >= MAX_QUEUED_SAMPLES {
+            self.0.remove(0);
+        }
+        self.0.push(sample)
+    }
+}
+
+type WebDevice = Device<SampleQueue, ArrayFrameBuffer>;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    device: Box<WebDevice>,
+    governor: Governor,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Load the ROM of a cartridge into a console of its region
+    #[wasm_bindgen(constructor)]
+    pub fn new(rom: &[u8]) -> Result<Emulator, String> {
+        let cartridge = Cartridge::from_bytes(rom).map_err(|err| err.to_string())?;
+        let is_pal = matches!(cartridge.get_country_frame_rate(), CountryFrameRate::Pal);
+        let frame_buffer = ArrayFrameBuffer([[0; 4]; FRAME_BUFFER_SIZE], false);
+        let mut device = Box::new(Device::new(
+            SampleQueue::default(),
+            frame_buffer,
+            is_pal,
+            false,
+        ));
+        device.load_cartridge(cartridge);
+        Ok(Self {
+            device,
+            governor: Governor::new(Sync::Timer),
+        })
+    }
+
+    /// Whether the console runs at 50 Hz, the rate of PAL consoles
+    pub fn is_pal(&self) -> bool {
+        self.device.is_pal()
+    }
+
+    /// Emulate the frames due at `now`, the milliseconds since an arbitrary,
+    /// fixed point in time. Returns whether a frame was emulated.
+    pub fn run(&mut self, now: f64) -> bool {
+        let now = Duration::from_secs_f64(now.max(0.0) / 1000.0);
+        let mut frames = 0;
+        while frames < MAX_FRAMES_PER_RUN && self.governor.should_run_frame(now) {
+            let cycles = self.run_frame();
+            self.governor.frame_done(cycles, self.device.is_pal(), now);
+            frames += 1
+        }
+        frames > 0
+    }
+
+    /// Emulate the console until the next frame begins. Returns the master
+    /// cycles emulated.
+    fn run_frame(&mut self) -> u64 {
+        let start = self.device.stats().master_cycles;
+        self.device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+        while !self.device.new_frame {
+            self.device.run_cycle::<MASTER_CYCLES_PER_TICK>();
+        }
+        self.device.stats().master_cycles - start
+    }
+
+    pub fn width(&self) -> u32 {
+        SCREEN_WIDTH
+    }
+
+    /// The height of the picture, it changes with the overscan mode
+    pub fn height(&self) -> u32 {
+        if self.device.ppu.is_overscan() {
+            MAX_SCREEN_HEIGHT_OVERSCAN
+        } else {
+            MAX_SCREEN_HEIGHT
+        }
+    }
+
+    /// The picture of the last frame as rows of RGBA pixels, ready for an
+    /// `ImageData`
+    pub fn frame(&self) -> Vec<u8> {
+        let len = (self.width() * self.height()) as usize * 4;
+        let mut pixels = self.device.ppu.frame_buffer.get_bytes()[..len].to_vec();
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel[3] = 0xff
+        }
+        pixels
+    }
+
+    /// Take the stereo samples at 32 kHz emulated since the last call, left
+    /// and right interleaved in the range of -1 to 1, as used by Web Audio
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        let queue = match self.device.smp.backend.as_mut() {
+            Some(queue) => queue,
+            None => return vec![],
+        };
+        queue
+            .0
+            .drain(..)
+            .flat_map(|sample| [sample.l, sample.r])
+            .map(|sample| f32::from(sample) / 32768.0)
+            .collect()
+    }
+
+    /// Set the pressed buttons of a standard controller in port 1 or 2, the
+    /// controller is connected, if it isn't yet. The bits are the buttons of
+    /// `rsnes::controller::buttons`.
+    pub fn set_buttons(&mut self, port: u32, buttons: u16) -> Result<(), String> {
+        let controller = match port {
+            1 => &mut self.device.controllers.port1.controller,
+            2 => &mut self.device.controllers.port2.controller,
+            _ => return Err(format!("there is no port {port}")),
+        };
+        match controller {
+            Controller::Standard(cntrl) => cntrl.pressed_buttons = buttons,
+            _ => {
+                *controller = Controller::Standard(StandardController {
+                    pressed_buttons: buttons,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.device.reset()
+    }
+
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut serializer = SaveStateSerializer { data: vec![] };
+        self.device.serialize(&mut serializer);
+        serializer.data
+    }
+
+    /// Load a state of [`Self::save_state`] of the same version of rsnes. If
+    /// it can't be loaded, the console keeps its state.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let backup = self.save_state();
+        let device = &mut self.device;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut deserializer = SaveStateDeserializer { data: state.iter() };
+            device
+                .load_state(&mut deserializer)
+                .map(|()| deserializer.data.len())
+        }));
+        match result {
+            Ok(Ok(0)) => Ok(()),
+            Ok(Err(err)) => Err(err.to_string()),
+            _ => {
+                let _ = self.device.load_state(&mut SaveStateDeserializer {
+                    data: backup.iter(),
+                });
+                Err("the state is corrupt or of another version".to_owned())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,51 @@
+use super::*;
+use rsnes::test_util::{create_looping_rom, with_stack};
+
+/// Run `f` with an emulator of the test ROM
+fn with_emulator(f: impl FnOnce(&mut Emulator) + Send + 'static) {
+    with_stack(move || f(&mut Emulator::new(&create_looping_rom()).unwrap()))
+}
+
+#[test]
+pub fn test_invalid_rom() {
+    with_stack(|| assert!(Emulator::new(&[0; 0x100]).is_err()))
+}
+
+#[test]
+pub fn test_run() {
+    with_emulator(|emulator| {
+        // the first frame is due at once, the next one after a frame time
+        assert!(emulator.run(0.0));
+        assert!(!emulator.run(10.0));
+        assert!(emulator.run(20.0));
+        // frames, which fell behind, are caught up with a limited count at once
+        assert!(emulator.run(100.0));
+        assert_eq!(emulator.frame().len(), 256 * 224 * 4);
+        assert!(emulator.frame().chunks(4).all(|pixel| pixel[3] == 0xff));
+        assert!(!emulator.is_pal());
+        // about 533 samples per frame at 60 Hz
+        let samples = emulator.take_audio();
+        assert!(
+            (2 * 2500..=2 * 3300).contains(&samples.len()),
+            "{} samples",
+            samples.len()
+        );
+        assert!(samples.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+        assert!(emulator.take_audio().is_empty());
+        assert!(emulator.set_buttons(2, 0x100).is_ok());
+        assert!(emulator.set_buttons(3, 0x100).is_err());
+    })
+}
+
+#[test]
+pub fn test_states() {
+    with_emulator(|emulator| {
+        emulator.run(0.0);
+        let state = emulator.save_state();
+        emulator.run(20.0);
+        assert!(emulator.load_state(&[0; 16]).is_err());
+        assert!(emulator.load_state(&state).is_ok());
+        // the state loaded is saved again
+        assert_eq!(emulator.save_state(), state);
+    })
+}
@@ -0,0 +1,180 @@
+// Browser frontend of rsnes, see the README for building `pkg/`
+import init, { Emulator } from "./pkg/rsnes_web.js";
+
+// the sample rate of the S-DSP
+const SAMPLE_RATE = 32000;
+// the audio scheduled ahead of the playback position in seconds
+const AUDIO_LATENCY = 0.06;
+
+// the bits of the buttons of a standard controller
+const BUTTONS = {
+    B: 0x001, Y: 0x002, Select: 0x004, Start: 0x008,
+    Up: 0x010, Down: 0x020, Left: 0x040, Right: 0x080,
+    A: 0x100, X: 0x200, L: 0x400, R: 0x800,
+};
+
+// the keys of the controller in port 1, the same keys as the default
+// controller profile of the emulator, but Start and Select aren't on Alt,
+// which opens the menu of some browsers
+const KEYS = {
+    KeyJ: BUTTONS.A, KeyK: BUTTONS.B, KeyL: BUTTONS.X, Semicolon: BUTTONS.Y,
+    KeyW: BUTTONS.Up, KeyA: BUTTONS.Left, KeyS: BUTTONS.Down, KeyD: BUTTONS.Right,
+    KeyQ: BUTTONS.L, KeyE: BUTTONS.R,
+    Enter: BUTTONS.Start, ShiftRight: BUTTONS.Select,
+};
+
+// the buttons of a gamepad with the "standard" mapping of the Gamepad API,
+// laid out like the SNES controller
+const GAMEPAD_BUTTONS = [
+    BUTTONS.B, BUTTONS.A, BUTTONS.Y, BUTTONS.X, BUTTONS.L, BUTTONS.R,
+    0, 0, BUTTONS.Select, BUTTONS.Start, 0, 0,
+    BUTTONS.Up, BUTTONS.Down, BUTTONS.Left, BUTTONS.Right,
+];
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const status = document.getElementById("status");
+const controls = ["reset", "save", "load"].map((id) => document.getElementById(id));
+
+let emulator = null;
+let keyButtons = 0;
+let savedState = null;
+let audio = null;
+let audioTime = 0;
+
+function setStatus(text) {
+    status.textContent = text;
+}
+
+function gamepadButtons(index) {
+    const gamepad = navigator.getGamepads ? navigator.getGamepads()[index] : null;
+    if (!gamepad || gamepad.mapping !== "standard") {
+        return 0;
+    }
+    return gamepad.buttons.reduce(
+        (buttons, button, i) => (button.pressed ? buttons | (GAMEPAD_BUTTONS[i] || 0) : buttons),
+        0,
+    );
+}
+
+// Schedule the samples emulated since the last frame after the ones
+// scheduled before, so they play without gaps
+function playAudio(samples) {
+    const frames = samples.length / 2;
+    if (!audio || frames === 0) {
+        return;
+    }
+    const buffer = audio.createBuffer(2, frames, SAMPLE_RATE);
+    const [left, right] = [buffer.getChannelData(0), buffer.getChannelData(1)];
+    for (let i = 0; i < frames; i++) {
+        left[i] = samples[2 * i];
+        right[i] = samples[2 * i + 1];
+    }
+    const source = audio.createBufferSource();
+    source.buffer = buffer;
+    source.connect(audio.destination);
+    // start over after falling behind, e.g. in a background tab
+    if (audioTime < audio.currentTime) {
+        audioTime = audio.currentTime + AUDIO_LATENCY;
+    }
+    source.start(audioTime);
+    audioTime += buffer.duration;
+}
+
+function drawFrame() {
+    const [width, height] = [emulator.width(), emulator.height()];
+    if (canvas.width !== width || canvas.height !== height) {
+        [canvas.width, canvas.height] = [width, height];
+    }
+    const pixels = new Uint8ClampedArray(emulator.frame());
+    context.putImageData(new ImageData(pixels, width, height), 0, 0);
+}
+
+function onAnimationFrame(now) {
+    requestAnimationFrame(onAnimationFrame);
+    if (!emulator) {
+        return;
+    }
+    emulator.set_buttons(1, keyButtons | gamepadButtons(0));
+    emulator.set_buttons(2, gamepadButtons(1));
+    if (emulator.run(now)) {
+        playAudio(emulator.take_audio());
+        drawFrame();
+    }
+}
+
+function saveState() {
+    savedState = emulator.save_state();
+    setStatus("Saved the state");
+}
+
+function loadState() {
+    if (!savedState) {
+        setStatus("No state was saved yet");
+        return;
+    }
+    try {
+        emulator.load_state(savedState);
+        setStatus("Loaded the state");
+    } catch (err) {
+        setStatus(`Could not load the state (${err})`);
+    }
+}
+
+async function loadRom(file) {
+    const rom = new Uint8Array(await file.arrayBuffer());
+    try {
+        if (emulator) {
+            emulator.free();
+        }
+        emulator = new Emulator(rom);
+    } catch (err) {
+        emulator = null;
+        controls.forEach((control) => (control.disabled = true));
+        setStatus(`Could not load "${file.name}" (${err})`);
+        return;
+    }
+    savedState = null;
+    // browsers only allow starting audio after an interaction of the user,
+    // like selecting the file
+    if (!audio) {
+        audio = new AudioContext({ sampleRate: SAMPLE_RATE });
+    }
+    await audio.resume();
+    audioTime = 0;
+    controls.forEach((control) => (control.disabled = false));
+    setStatus(`Running "${file.name}" (${emulator.is_pal() ? "PAL" : "NTSC"})`);
+}
+
+document.getElementById("rom").addEventListener("change", (event) => {
+    if (event.target.files.length > 0) {
+        loadRom(event.target.files[0]);
+    }
+});
+controls[0].addEventListener("click", () => emulator.reset());
+controls[1].addEventListener("click", saveState);
+controls[2].addEventListener("click", loadState);
+
+window.addEventListener("keydown", (event) => {
+    if (!emulator) {
+        return;
+    }
+    if (event.code in KEYS) {
+        keyButtons |= KEYS[event.code];
+    } else if (event.code === "F2") {
+        saveState();
+    } else if (event.code === "F4") {
+        loadState();
+    } else {
+        return;
+    }
+    event.preventDefault();
+});
+window.addEventListener("keyup", (event) => {
+    if (event.code in KEYS) {
+        keyButtons &= ~KEYS[event.code];
+    }
+});
+
+await init();
+requestAnimationFrame(onAnimationFrame);